@@ -1,7 +1,7 @@
-INSERT INTO sc_projects VALUES (1, "project #1", NULL, 1);
-INSERT INTO sc_projects VALUES (2, "project #2", "This is the second project", 1);
-INSERT INTO sc_projects VALUES (3, "project #3", "This is a project from a different user", 2);
-INSERT INTO sc_project_samples VALUES(1, 1, 1);
-INSERT INTO sc_project_samples VALUES(2, 1, 2);
-INSERT INTO sc_project_samples VALUES(3, 1, 3);
-INSERT INTO sc_project_samples VALUES(4, 3, 4);
+INSERT INTO sc_projects VALUES (1, "project #1", NULL, 1, 1, NULL, NULL);
+INSERT INTO sc_projects VALUES (2, "project #2", "This is the second project", 1, 1, NULL, NULL);
+INSERT INTO sc_projects VALUES (3, "project #3", "This is a project from a different user", 2, 1, NULL, NULL);
+INSERT INTO sc_project_samples VALUES(1, 1, 1, NULL, 1);
+INSERT INTO sc_project_samples VALUES(2, 1, 2, NULL, 1);
+INSERT INTO sc_project_samples VALUES(3, 1, 3, NULL, 1);
+INSERT INTO sc_project_samples VALUES(4, 3, 4, NULL, 1);
@@ -1,4 +1,4 @@
-INSERT INTO sc_samples VALUES (1, 43254, 1, 12, 2022, 1, "some notes", NULL, 1);
-INSERT INTO sc_samples VALUES (2, 40683, 1, 10, 2023, 2, "some notes", 100, 1);
-INSERT INTO sc_samples VALUES (3, 40683, 1, 11, 2023, 1, NULL, NULL, 1);
-INSERT INTO sc_samples VALUES (4, 40683, 1, 11, 2023, 1, NULL, NULL, 2);
+INSERT INTO sc_samples VALUES (1, 43254, 1, 12, 2022, 1, "some notes", NULL, 1, 1, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL);
+INSERT INTO sc_samples VALUES (2, 40683, 1, 10, 2023, 2, "some notes", 100, 1, 1, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL);
+INSERT INTO sc_samples VALUES (3, 40683, 1, 11, 2023, 1, NULL, NULL, 1, 1, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL);
+INSERT INTO sc_samples VALUES (4, 40683, 1, 11, 2023, 1, NULL, NULL, 2, 1, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL, NULL);
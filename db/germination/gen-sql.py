@@ -4,6 +4,7 @@ import logging
 import os
 import csv
 import argparse
+import sys
 
 debuglevel = os.environ.get("DEBUG")
 if debuglevel == "1":
@@ -109,7 +110,7 @@ def find_possibilities(cursor, name1, name2, name3, rank):
             logging.debug("   - {}: {}".format(row['tsn'], row['complete_name']))
         return True
     else:
-        return false
+        return False
 
 
 def get_germ_code_id(cursor, germcode):
@@ -157,9 +158,44 @@ def get_taxon(cursor, name1, name2, name3, rank):
     return None
 
 
-def handle_taxa_list(cursor, reader):
+# problems that prevent the list from being applied
+ERROR_UNKNOWN_CODE = "unknown-code"
+ERROR_UNMATCHED_TAXON = "unmatched-taxon"
+ERROR_DUPLICATE = "duplicate"
+# problems that are only reported
+WARNING_HYBRID = "hybrid-skipped"
+ERRORS = (ERROR_UNKNOWN_CODE, ERROR_UNMATCHED_TAXON, ERROR_DUPLICATE)
+
+REPORT_FIELDS = ["line", "taxon", "germcode", "problem", "detail"]
+
+
+def lookup_taxon(cursor, name1, name2, name3, rank):
+    tsn = get_taxon(cursor, name1, name2, name3, rank)
+    if tsn is not None:
+        return tsn
+
+    new_genus = find_genus_synonym(cursor, name1)
+    if new_genus:
+        logging.info("genus {} is a synonym for {}, using new name {} {}"
+                     .format(name1, new_genus, new_genus, name2))
+        return get_taxon(cursor, new_genus, name2, name3, rank)
+    return None
+
+
+def validate_taxa_list(cursor, reader):
+    """Check every row of the list without changing anything. Returns the (tsn, germid) pairs of
+    the valid rows and a list of the problems that were found, one dict per problem."""
     taxa = []
-    for row in reader:
+    problems = []
+    seen = {}
+
+    def problem(line, name, germcode, kind, detail):
+        logging.warning("line {}: {}: {}".format(line, kind, detail))
+        problems.append({"line": line, "taxon": name, "germcode": germcode,
+                         "problem": kind, "detail": detail})
+
+    # the header is line 1
+    for line, row in enumerate(reader, start=2):
         ind1 = row[CSV_FIELDS[0]].strip()
         name1 = row[CSV_FIELDS[1]].strip()
         ind2 = row[CSV_FIELDS[2]].strip()
@@ -167,44 +203,57 @@ def handle_taxa_list(cursor, reader):
         ind3 = row[CSV_FIELDS[4]].strip()
         name3 = row[CSV_FIELDS[5]].strip()
         germcode = str(row[CSV_FIELDS[6]].strip())
+        dname = displayname(name1, name2, name3)
 
         # skip hybrids for now
         if ind1 == "X" or ind2 == "X":
-            logging.info("skipping hybrid for now")
+            problem(line, dname, germcode, WARNING_HYBRID, "hybrids are not supported yet")
             continue
 
-        # lookup germ code id
+        valid = True
         code = get_germ_code_id(cursor, germcode)
+        if code is None:
+            problem(line, dname, germcode, ERROR_UNKNOWN_CODE,
+                    "germination code '{}' does not exist".format(germcode))
+            valid = False
 
         rank = RANK_SPECIES
         if ind3 == "var.":
             rank = RANK_VARIETY
         elif ind3 == "subsp.":
-            rank == RANK_SUBSPECIES
-        tsn = get_taxon(cursor, name1, name2, name3, rank)
-        if tsn is not None:
-            taxa.append((tsn, code))
+            rank = RANK_SUBSPECIES
+        tsn = lookup_taxon(cursor, name1, name2, name3, rank)
+        if tsn is None:
+            if find_possibilities(cursor, name1, name2, name3, rank):
+                detail = "no exact match, but there are similar names"
+            else:
+                detail = "no matching taxon"
+            problem(line, dname, germcode, ERROR_UNMATCHED_TAXON, detail)
             continue
 
-        new_genus = find_genus_synonym(cursor, name1)
-        if new_genus:
-            logging.info("genus {} is a synonym for {}, using new name {} {}"
-                         .format(name1, new_genus, new_genus, name2))
-            tsn = get_taxon(cursor, new_genus, name2, name3, rank)
-            if tsn is not None:
-                taxa.append((tsn, code))
-                continue
+        # the same taxon may be listed twice, e.g. under a synonym
+        if (tsn, germcode) in seen:
+            problem(line, dname, germcode, ERROR_DUPLICATE,
+                    "taxon {} already has this code on line {}"
+                    .format(tsn, seen[(tsn, germcode)]))
+            continue
+        seen[(tsn, germcode)] = line
+        if valid:
+            taxa.append((tsn, code))
+    return taxa, problems
+
 
-        if not find_possibilities(cursor, name1, name2, name3, rank):
-            logging.warning("unable to find species {} {}"
-                            .format(name1, name2))
-    return taxa
+def write_report(path, problems):
+    with open(path, "w", newline="") as reportfile:
+        writer = csv.DictWriter(reportfile, fieldnames=REPORT_FIELDS)
+        writer.writeheader()
+        writer.writerows(problems)
 
 
 def check_fieldnames(fieldnames):
-    if (len(reader.fieldnames)) != len(CSV_FIELDS):
-        raise RuntimeError("Expected 9 fields, found {}"
-                           .format(len(reader.fieldnames)))
+    if (len(fieldnames)) != len(CSV_FIELDS):
+        raise RuntimeError("Expected {} fields, found {}"
+                           .format(len(CSV_FIELDS), len(fieldnames)))
     for i in range(len(fieldnames)):
         if CSV_FIELDS[i] != fieldnames[i]:
             raise RuntimeError("Field name mismatch. expected field named '{}' in column {}, found '{}'"
@@ -215,6 +264,10 @@ if __name__ == "__main__":
     parser = argparse.ArgumentParser()
     parser.add_argument("specieslist")
     parser.add_argument('-d', '--db', default="ITIS.sqlite")
+    parser.add_argument('-r', '--report', default="germination-report.csv",
+                        help="where to write the validation report")
+    parser.add_argument('--force', action="store_true",
+                        help="output the valid rows even if other rows have errors")
     args = parser.parse_args()
 
     dburl = "file:{}?mode=ro".format(args.db)
@@ -228,10 +281,20 @@ if __name__ == "__main__":
         print("Failed to parse input file: {}".format(e))
         exit(1)
 
+    # validate the whole list before writing anything, so that all of the problems can be reviewed
+    # and fixed at once
     cursor = dbconn.cursor()
-    taxa = handle_taxa_list(cursor, reader)
+    taxa, problems = validate_taxa_list(cursor, reader)
     cursor = None
     dbconn = None
+    write_report(args.report, problems)
+    errors = [p for p in problems if p["problem"] in ERRORS]
+    logging.warning("{} problems found, {} of them errors. See {} for details"
+                    .format(len(problems), len(errors), args.report))
+    if errors and not args.force:
+        print("Not generating SQL because the list has {} errors. See {} or use --force to skip them"
+              .format(len(errors), args.report), file=sys.stderr)
+        exit(1)
     if taxa:
         outdb = sqlite3.connect(":memory:")
         logging.info("Adding {} items to the database".format(len(taxa)))
@@ -148,14 +148,16 @@ def combine_status(old, new):
     return STATUS_INTRODUCED
 
 
-def add_taxa(taxa, tsn, status):
+def add_taxa(taxa, tsn, status, invasive):
     newstatus = status
+    newinvasive = invasive or None
     try:
-        oldstatus = taxa[tsn]
+        (oldstatus, oldinvasive) = taxa[tsn]
         newstatus = combine_status(oldstatus, status)
+        newinvasive = oldinvasive or newinvasive
     except:
         pass
-    taxa[tsn] = newstatus
+    taxa[tsn] = (newstatus, newinvasive)
 
 
 def handle_taxa_list(cursor, reader):
@@ -183,7 +185,7 @@ def handle_taxa_list(cursor, reader):
             rank == RANK_SUBSPECIES
         tsn = get_taxon(cursor, name1, name2, name3, rank)
         if tsn is not None:
-            add_taxa(taxa, tsn, native_status)
+            add_taxa(taxa, tsn, native_status, invasive_status)
             continue
 
         new_genus = find_genus_synonym(cursor, name1)
@@ -191,7 +193,7 @@ def handle_taxa_list(cursor, reader):
             logging.info("genus {} is a synonym for {}, using new name {} {}".format(name1, new_genus, new_genus, name2))
             tsn = get_taxon(cursor, new_genus, name2, name3, rank)
             if tsn is not None:
-                add_taxa(taxa, tsn, native_status)
+                add_taxa(taxa, tsn, native_status, invasive_status)
                 continue
 
         if not find_possibilities(cursor, name1, name2, name3, rank):
@@ -229,6 +231,7 @@ if __name__ == "__main__":
     if taxa and args.updatedb:
         logging.info("Adding {} items to the database".format(len(taxa)))
         cursor.execute('DROP TABLE "mntaxa"')
-        cursor.execute(' CREATE TABLE "mntaxa" ( "id" INTEGER, "tsn" INTEGER, "native_status" INTEGER, PRIMARY KEY("id" AUTOINCREMENT), FOREIGN KEY("tsn") REFERENCES "taxonomic_units"("tsn"))')
-        cursor.executemany("INSERT INTO mntaxa ('tsn', 'native_status') VALUES (?, ?)", taxa.items())
+        cursor.execute(' CREATE TABLE "mntaxa" ( "id" INTEGER, "tsn" INTEGER, "native_status" INTEGER, "invasive_status" TEXT, PRIMARY KEY("id" AUTOINCREMENT), FOREIGN KEY("tsn") REFERENCES "taxonomic_units"("tsn"))')
+        cursor.executemany("INSERT INTO mntaxa ('tsn', 'native_status', 'invasive_status') VALUES (?, ?, ?)",
+                           [(tsn, native, invasive) for (tsn, (native, invasive)) in taxa.items()])
         dbconn.commit()
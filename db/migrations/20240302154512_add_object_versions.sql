@@ -0,0 +1,37 @@
+ALTER TABLE sc_samples ADD COLUMN sampleversion INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE sc_sources ADD COLUMN srcversion INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE sc_projects ADD COLUMN projversion INTEGER NOT NULL DEFAULT 1;
+
+DROP VIEW IF EXISTS vsamples;
+CREATE VIEW vsamples (sampleid, tsn, parentid, srcid, srcname, srcdesc, srcversion, complete_name, unit_name1, unit_name2, unit_name3, seq, quantity, month, year, notes, certainty, cnames, userid, sampleversion) AS
+SELECT S.sampleid,
+       T.tsn,
+       T.parent_tsn,
+       L.srcid,
+       L.srcname,
+       L.srcdesc,
+       L.srcversion,
+       T.complete_name,
+       T.unit_name1,
+       T.unit_name2,
+       T.unit_name3,
+       T.phylo_sort_seq,
+       quantity,
+       MONTH,
+       YEAR,
+       notes,
+       certainty,
+       GROUP_CONCAT(V.vernacular_name, "@"),
+       U.userid,
+       S.sampleversion
+FROM sc_samples S
+INNER JOIN taxonomic_units T ON T.tsn=S.tsn
+INNER JOIN sc_sources L ON L.srcid=S.srcid
+INNER JOIN sc_users U ON U.userid=S.userid
+LEFT JOIN
+  (SELECT *
+   FROM vernaculars
+   WHERE (LANGUAGE="English"
+          OR LANGUAGE="unspecified") ) V ON V.tsn=T.tsn
+GROUP BY S.sampleid,
+         T.tsn
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS "sc_user_tokens" (
+	"tokenid"	INTEGER NOT NULL UNIQUE,
+	"userid"	INTEGER NOT NULL,
+	"tokenname"	TEXT NOT NULL,
+	"tokenhash"	TEXT NOT NULL,
+	"tokencreated"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	"tokenlastused"	TEXT,
+	PRIMARY KEY("tokenid" AUTOINCREMENT),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE
+);
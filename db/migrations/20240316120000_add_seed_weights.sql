@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS "sc_taxon_seed_weights" (
+	"tsn"	INTEGER NOT NULL UNIQUE,
+	"seedspergram"	REAL NOT NULL CHECK("seedspergram" > 0),
+	"weightsource"	TEXT,
+	PRIMARY KEY("tsn"),
+	FOREIGN KEY("tsn") REFERENCES "taxonomic_units"("tsn")
+);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS "sc_sample_flags" (
+	"flagid"	INTEGER NOT NULL UNIQUE,
+	"sampleid"	INTEGER NOT NULL,
+	"flagreason"	TEXT NOT NULL,
+	"flagcreated"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY("flagid" AUTOINCREMENT),
+	FOREIGN KEY("sampleid") REFERENCES "sc_samples"("sampleid") ON DELETE CASCADE,
+	UNIQUE("sampleid","flagreason")
+);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS "sc_user_prefs" (
+	"userid"	INTEGER NOT NULL UNIQUE,
+	"defaultsource"	INTEGER,
+	"defaultcertainty"	INTEGER NOT NULL DEFAULT 1,
+	"defaultdatecurrent"	INTEGER NOT NULL DEFAULT 0,
+	PRIMARY KEY("userid"),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE,
+	FOREIGN KEY("defaultsource") REFERENCES "sc_sources"("srcid") ON DELETE SET NULL
+);
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS "sc_mail_queue" (
+	"mailid"	INTEGER NOT NULL UNIQUE,
+	"mailsender"	TEXT NOT NULL,
+	"mailrecipient"	TEXT NOT NULL,
+	"mailsubject"	TEXT NOT NULL,
+	"mailmessage"	BLOB NOT NULL,
+	"mailstatus"	INTEGER NOT NULL DEFAULT 0,
+	"mailattempts"	INTEGER NOT NULL DEFAULT 0,
+	"maillasterror"	TEXT,
+	"mailcreated"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	"mailnextattempt"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	"mailsent"	TEXT,
+	PRIMARY KEY("mailid" AUTOINCREMENT)
+);
+CREATE INDEX IF NOT EXISTS "sc_mail_queue_pending" ON "sc_mail_queue" ("mailstatus", "mailnextattempt");
@@ -0,0 +1,54 @@
+CREATE TABLE IF NOT EXISTS "sc_organizations" (
+	"orgid"	INTEGER NOT NULL UNIQUE,
+	"orgname"	TEXT NOT NULL UNIQUE,
+	"orgdescription"	TEXT,
+	"orgcreated"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY("orgid" AUTOINCREMENT)
+);
+CREATE TABLE IF NOT EXISTS "sc_org_members" (
+	"orgid"	INTEGER NOT NULL,
+	"userid"	INTEGER NOT NULL,
+	"memberrole"	INTEGER NOT NULL,
+	PRIMARY KEY("orgid", "userid"),
+	FOREIGN KEY("orgid") REFERENCES "sc_organizations"("orgid") ON DELETE CASCADE,
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "sc_org_members_user" ON "sc_org_members" ("userid");
+ALTER TABLE sc_samples ADD COLUMN sampleorgid INTEGER REFERENCES sc_organizations(orgid) ON DELETE SET NULL;
+ALTER TABLE sc_sources ADD COLUMN srcorgid INTEGER REFERENCES sc_organizations(orgid) ON DELETE SET NULL;
+ALTER TABLE sc_projects ADD COLUMN projorgid INTEGER REFERENCES sc_organizations(orgid) ON DELETE SET NULL;
+DROP VIEW IF EXISTS vsamples;
+CREATE VIEW vsamples (sampleid, tsn, parentid, srcid, srcname, srcdesc, srcversion, srcorgid, complete_name, unit_name1, unit_name2, unit_name3, seq, quantity, month, year, notes, certainty, cnames, userid, sampleversion, sampleorgid) AS
+SELECT S.sampleid,
+       T.tsn,
+       T.parent_tsn,
+       L.srcid,
+       L.srcname,
+       L.srcdesc,
+       L.srcversion,
+       L.srcorgid,
+       T.complete_name,
+       T.unit_name1,
+       T.unit_name2,
+       T.unit_name3,
+       T.phylo_sort_seq,
+       quantity,
+       MONTH,
+       YEAR,
+       notes,
+       certainty,
+       GROUP_CONCAT(V.vernacular_name, "@"),
+       U.userid,
+       S.sampleversion,
+       S.sampleorgid
+FROM sc_samples S
+INNER JOIN taxonomic_units T ON T.tsn=S.tsn
+INNER JOIN sc_sources L ON L.srcid=S.srcid
+INNER JOIN sc_users U ON U.userid=S.userid
+LEFT JOIN
+  (SELECT *
+   FROM vernaculars
+   WHERE (LANGUAGE="English"
+          OR LANGUAGE="unspecified") ) V ON V.tsn=T.tsn
+GROUP BY S.sampleid,
+         T.tsn;
@@ -0,0 +1,14 @@
+-- Timestamps are stored in UTC without an offset. Convert any values that were stored with an
+-- offset (e.g. '2024-04-01 10:00:00+02:00') so that they compare and sort correctly.
+UPDATE sc_users SET usersince=datetime(usersince) WHERE datetime(usersince) IS NOT NULL;
+UPDATE sc_user_verification SET uvrequested=datetime(uvrequested) WHERE datetime(uvrequested) IS NOT NULL;
+UPDATE sc_user_tokens SET tokencreated=datetime(tokencreated) WHERE datetime(tokencreated) IS NOT NULL;
+UPDATE sc_user_tokens SET tokenlastused=datetime(tokenlastused) WHERE datetime(tokenlastused) IS NOT NULL;
+UPDATE sc_sample_flags SET flagcreated=datetime(flagcreated) WHERE datetime(flagcreated) IS NOT NULL;
+UPDATE sc_mail_queue SET mailcreated=datetime(mailcreated) WHERE datetime(mailcreated) IS NOT NULL;
+UPDATE sc_mail_queue SET mailnextattempt=datetime(mailnextattempt) WHERE datetime(mailnextattempt) IS NOT NULL;
+UPDATE sc_mail_queue SET mailsent=datetime(mailsent) WHERE datetime(mailsent) IS NOT NULL;
+UPDATE sc_organizations SET orgcreated=datetime(orgcreated) WHERE datetime(orgcreated) IS NOT NULL;
+
+-- the IANA name of the time zone that dates and times are displayed in for this user
+ALTER TABLE sc_users ADD COLUMN usertimezone TEXT;
@@ -0,0 +1,47 @@
+-- Samples that were bought rather than collected record where and how they were purchased. A sample
+-- is considered purchased when it has a vendor.
+ALTER TABLE sc_samples ADD COLUMN purchasevendor TEXT;
+ALTER TABLE sc_samples ADD COLUMN purchaselot TEXT;
+ALTER TABLE sc_samples ADD COLUMN purchasedate TEXT;
+ALTER TABLE sc_samples ADD COLUMN purchaseprice REAL CHECK("purchaseprice" >= 0);
+ALTER TABLE sc_samples ADD COLUMN purchaseorigin TEXT;
+DROP VIEW IF EXISTS vsamples;
+CREATE VIEW vsamples (sampleid, tsn, parentid, srcid, srcname, srcdesc, srcversion, srcorgid, complete_name, unit_name1, unit_name2, unit_name3, seq, quantity, month, year, notes, certainty, cnames, userid, sampleversion, sampleorgid, purchasevendor, purchaselot, purchasedate, purchaseprice, purchaseorigin) AS
+SELECT S.sampleid,
+       T.tsn,
+       T.parent_tsn,
+       L.srcid,
+       L.srcname,
+       L.srcdesc,
+       L.srcversion,
+       L.srcorgid,
+       T.complete_name,
+       T.unit_name1,
+       T.unit_name2,
+       T.unit_name3,
+       T.phylo_sort_seq,
+       quantity,
+       MONTH,
+       YEAR,
+       notes,
+       certainty,
+       GROUP_CONCAT(V.vernacular_name, "@"),
+       U.userid,
+       S.sampleversion,
+       S.sampleorgid,
+       S.purchasevendor,
+       S.purchaselot,
+       S.purchasedate,
+       S.purchaseprice,
+       S.purchaseorigin
+FROM sc_samples S
+INNER JOIN taxonomic_units T ON T.tsn=S.tsn
+INNER JOIN sc_sources L ON L.srcid=S.srcid
+INNER JOIN sc_users U ON U.userid=S.userid
+LEFT JOIN
+  (SELECT *
+   FROM vernaculars
+   WHERE (LANGUAGE="English"
+          OR LANGUAGE="unspecified") ) V ON V.tsn=T.tsn
+GROUP BY S.sampleid,
+         T.tsn;
@@ -0,0 +1,2 @@
+-- the month that the user's collection year starts in, used to group samples by year in reports
+ALTER TABLE sc_user_prefs ADD COLUMN yearstartmonth INTEGER NOT NULL DEFAULT 1 CHECK("yearstartmonth" BETWEEN 1 AND 12);
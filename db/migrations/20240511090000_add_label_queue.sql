@@ -0,0 +1,10 @@
+-- samples whose packet labels still need to be printed. A sample is queued again whenever it is
+-- modified, so that its label can be reprinted with the new details.
+CREATE TABLE IF NOT EXISTS "sc_label_queue" (
+	"sampleid"	INTEGER NOT NULL UNIQUE,
+	"labelqueued"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	"labelprinted"	TEXT,
+	PRIMARY KEY("sampleid"),
+	FOREIGN KEY("sampleid") REFERENCES "sc_samples"("sampleid") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "sc_label_queue_pending" ON "sc_label_queue" ("labelprinted");
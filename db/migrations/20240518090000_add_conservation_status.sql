@@ -0,0 +1,25 @@
+-- taxa that are listed as endangered, threatened or of special concern in a state or other region
+CREATE TABLE IF NOT EXISTS "sc_conservation_status" (
+	"tsn"	INTEGER NOT NULL,
+	"region"	TEXT NOT NULL,
+	"consstatus"	INTEGER NOT NULL,
+	PRIMARY KEY("tsn","region"),
+	FOREIGN KEY("tsn") REFERENCES "taxonomic_units"("tsn")
+);
+-- permits that allow a user to collect a listed taxon in a region
+CREATE TABLE IF NOT EXISTS "sc_permits" (
+	"permitid"	INTEGER NOT NULL UNIQUE,
+	"userid"	INTEGER NOT NULL,
+	"tsn"	INTEGER NOT NULL,
+	"region"	TEXT NOT NULL,
+	"permitnumber"	TEXT NOT NULL,
+	"permitexpires"	TEXT,
+	"permitnotes"	TEXT,
+	PRIMARY KEY("permitid" AUTOINCREMENT),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE,
+	FOREIGN KEY("tsn") REFERENCES "taxonomic_units"("tsn")
+);
+-- the region whose conservation listings apply to the user's new samples, and whether a permit is
+-- required (2), only warned about (1) or not checked at all (0)
+ALTER TABLE sc_user_prefs ADD COLUMN region TEXT;
+ALTER TABLE sc_user_prefs ADD COLUMN permitpolicy INTEGER NOT NULL DEFAULT 2 CHECK("permitpolicy" BETWEEN 0 AND 2);
@@ -0,0 +1,3 @@
+-- the invasive status from the Minnesota taxa list (e.g. "SN" for a state prohibited noxious
+-- weed), which was previously dropped when the list was imported
+ALTER TABLE "mntaxa" ADD COLUMN "invasive_status" TEXT;
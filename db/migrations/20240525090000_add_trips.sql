@@ -0,0 +1,74 @@
+-- a planned field outing. Trips list the people taking part and the sources and taxa that they
+-- intend to visit and collect, and samples record the trip that they were collected on.
+CREATE TABLE IF NOT EXISTS "sc_trips" (
+	"tripid"	INTEGER NOT NULL UNIQUE,
+	"tripname"	TEXT NOT NULL,
+	"tripdate"	TEXT NOT NULL,
+	"tripnotes"	TEXT,
+	"userid"	INTEGER NOT NULL,
+	"tripversion"	INTEGER NOT NULL DEFAULT 1,
+	PRIMARY KEY("tripid" AUTOINCREMENT),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE
+);
+CREATE TABLE IF NOT EXISTS "sc_trip_participants" (
+	"tripid"	INTEGER NOT NULL,
+	"participant"	TEXT NOT NULL,
+	PRIMARY KEY("tripid", "participant"),
+	FOREIGN KEY("tripid") REFERENCES "sc_trips"("tripid") ON DELETE CASCADE
+);
+CREATE TABLE IF NOT EXISTS "sc_trip_sources" (
+	"tripid"	INTEGER NOT NULL,
+	"srcid"	INTEGER NOT NULL,
+	PRIMARY KEY("tripid", "srcid"),
+	FOREIGN KEY("tripid") REFERENCES "sc_trips"("tripid") ON DELETE CASCADE,
+	FOREIGN KEY("srcid") REFERENCES "sc_sources"("srcid") ON DELETE CASCADE
+);
+CREATE TABLE IF NOT EXISTS "sc_trip_taxa" (
+	"tripid"	INTEGER NOT NULL,
+	"tsn"	INTEGER NOT NULL,
+	PRIMARY KEY("tripid", "tsn"),
+	FOREIGN KEY("tripid") REFERENCES "sc_trips"("tripid") ON DELETE CASCADE,
+	FOREIGN KEY("tsn") REFERENCES "taxonomic_units"("tsn")
+);
+ALTER TABLE sc_samples ADD COLUMN "tripid" INTEGER REFERENCES "sc_trips"("tripid") ON DELETE SET NULL;
+DROP VIEW IF EXISTS vsamples;
+CREATE VIEW vsamples (sampleid, tsn, parentid, srcid, srcname, srcdesc, srcversion, srcorgid, complete_name, unit_name1, unit_name2, unit_name3, seq, quantity, month, year, notes, certainty, cnames, userid, sampleversion, sampleorgid, purchasevendor, purchaselot, purchasedate, purchaseprice, purchaseorigin, tripid) AS
+SELECT S.sampleid,
+       T.tsn,
+       T.parent_tsn,
+       L.srcid,
+       L.srcname,
+       L.srcdesc,
+       L.srcversion,
+       L.srcorgid,
+       T.complete_name,
+       T.unit_name1,
+       T.unit_name2,
+       T.unit_name3,
+       T.phylo_sort_seq,
+       quantity,
+       MONTH,
+       YEAR,
+       notes,
+       certainty,
+       GROUP_CONCAT(V.vernacular_name, "@"),
+       U.userid,
+       S.sampleversion,
+       S.sampleorgid,
+       S.purchasevendor,
+       S.purchaselot,
+       S.purchasedate,
+       S.purchaseprice,
+       S.purchaseorigin,
+       S.tripid
+FROM sc_samples S
+INNER JOIN taxonomic_units T ON T.tsn=S.tsn
+INNER JOIN sc_sources L ON L.srcid=S.srcid
+INNER JOIN sc_users U ON U.userid=S.userid
+LEFT JOIN
+  (SELECT *
+   FROM vernaculars
+   WHERE (LANGUAGE="English"
+          OR LANGUAGE="unspecified") ) V ON V.tsn=T.tsn
+GROUP BY S.sampleid,
+         T.tsn;
@@ -0,0 +1,8 @@
+-- stable identifiers for records that were exported to or imported from a JSON dump, so that the
+-- same record keeps its identity across instances even though its numeric ID differs
+CREATE TABLE IF NOT EXISTS "sc_uuids" (
+	"tablename"	TEXT NOT NULL,
+	"localid"	INTEGER NOT NULL,
+	"uuid"	TEXT NOT NULL UNIQUE,
+	PRIMARY KEY("tablename", "localid")
+);
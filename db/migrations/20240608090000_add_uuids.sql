@@ -0,0 +1,82 @@
+-- Random identifiers that are used in public URLs and the API instead of the sequential numeric
+-- IDs, which are easy to guess and differ between databases. The numeric IDs are still used as keys
+-- within the database. Records that are inserted without a UUID are assigned a random (version 4)
+-- UUID.
+ALTER TABLE sc_samples ADD COLUMN "sampleuuid" TEXT;
+UPDATE sc_samples SET sampleuuid=lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)));
+CREATE UNIQUE INDEX IF NOT EXISTS "sc_samples_uuid" ON "sc_samples" ("sampleuuid");
+CREATE TRIGGER IF NOT EXISTS "sc_samples_assign_uuid" AFTER INSERT ON "sc_samples" WHEN NEW.sampleuuid IS NULL
+BEGIN
+  UPDATE sc_samples SET sampleuuid=lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))) WHERE sampleid=NEW.sampleid;
+END;
+ALTER TABLE sc_sources ADD COLUMN "srcuuid" TEXT;
+UPDATE sc_sources SET srcuuid=lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)));
+CREATE UNIQUE INDEX IF NOT EXISTS "sc_sources_uuid" ON "sc_sources" ("srcuuid");
+CREATE TRIGGER IF NOT EXISTS "sc_sources_assign_uuid" AFTER INSERT ON "sc_sources" WHEN NEW.srcuuid IS NULL
+BEGIN
+  UPDATE sc_sources SET srcuuid=lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))) WHERE srcid=NEW.srcid;
+END;
+ALTER TABLE sc_projects ADD COLUMN "projuuid" TEXT;
+UPDATE sc_projects SET projuuid=lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)));
+CREATE UNIQUE INDEX IF NOT EXISTS "sc_projects_uuid" ON "sc_projects" ("projuuid");
+CREATE TRIGGER IF NOT EXISTS "sc_projects_assign_uuid" AFTER INSERT ON "sc_projects" WHEN NEW.projuuid IS NULL
+BEGIN
+  UPDATE sc_projects SET projuuid=lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))) WHERE projectid=NEW.projectid;
+END;
+ALTER TABLE sc_project_samples ADD COLUMN "psuuid" TEXT;
+UPDATE sc_project_samples SET psuuid=lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)));
+CREATE UNIQUE INDEX IF NOT EXISTS "sc_project_samples_uuid" ON "sc_project_samples" ("psuuid");
+CREATE TRIGGER IF NOT EXISTS "sc_project_samples_assign_uuid" AFTER INSERT ON "sc_project_samples" WHEN NEW.psuuid IS NULL
+BEGIN
+  UPDATE sc_project_samples SET psuuid=lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))) WHERE psid=NEW.psid;
+END;
+ALTER TABLE sc_project_notes ADD COLUMN "pnoteuuid" TEXT;
+UPDATE sc_project_notes SET pnoteuuid=lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6)));
+CREATE UNIQUE INDEX IF NOT EXISTS "sc_project_notes_uuid" ON "sc_project_notes" ("pnoteuuid");
+CREATE TRIGGER IF NOT EXISTS "sc_project_notes_assign_uuid" AFTER INSERT ON "sc_project_notes" WHEN NEW.pnoteuuid IS NULL
+BEGIN
+  UPDATE sc_project_notes SET pnoteuuid=lower(hex(randomblob(4)) || '-' || hex(randomblob(2)) || '-4' || substr(hex(randomblob(2)), 2) || '-' || substr('89ab', 1 + (abs(random()) % 4), 1) || substr(hex(randomblob(2)), 2) || '-' || hex(randomblob(6))) WHERE pnoteid=NEW.pnoteid;
+END;
+DROP VIEW IF EXISTS vsamples;
+CREATE VIEW vsamples (sampleid, tsn, parentid, srcid, srcname, srcdesc, srcversion, srcorgid, complete_name, unit_name1, unit_name2, unit_name3, seq, quantity, month, year, notes, certainty, cnames, userid, sampleversion, sampleorgid, purchasevendor, purchaselot, purchasedate, purchaseprice, purchaseorigin, tripid, sampleuuid, srcuuid) AS
+SELECT S.sampleid,
+       T.tsn,
+       T.parent_tsn,
+       L.srcid,
+       L.srcname,
+       L.srcdesc,
+       L.srcversion,
+       L.srcorgid,
+       T.complete_name,
+       T.unit_name1,
+       T.unit_name2,
+       T.unit_name3,
+       T.phylo_sort_seq,
+       quantity,
+       MONTH,
+       YEAR,
+       notes,
+       certainty,
+       GROUP_CONCAT(V.vernacular_name, "@"),
+       U.userid,
+       S.sampleversion,
+       S.sampleorgid,
+       S.purchasevendor,
+       S.purchaselot,
+       S.purchasedate,
+       S.purchaseprice,
+       S.purchaseorigin,
+       S.tripid,
+       S.sampleuuid,
+       L.srcuuid
+FROM sc_samples S
+INNER JOIN taxonomic_units T ON T.tsn=S.tsn
+INNER JOIN sc_sources L ON L.srcid=S.srcid
+INNER JOIN sc_users U ON U.userid=S.userid
+LEFT JOIN
+  (SELECT *
+   FROM vernaculars
+   WHERE (LANGUAGE="English"
+          OR LANGUAGE="unspecified") ) V ON V.tsn=T.tsn
+GROUP BY S.sampleid,
+         T.tsn;
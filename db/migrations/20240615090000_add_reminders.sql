@@ -0,0 +1,17 @@
+-- reminders that are shown to the user once they are due, e.g. when the cold stratification that
+-- was started along with a project note is complete
+CREATE TABLE IF NOT EXISTS "sc_reminders" (
+	"reminderid"	INTEGER NOT NULL UNIQUE,
+	"userid"	INTEGER NOT NULL,
+	"pnoteid"	INTEGER,
+	"reminderdue"	TEXT NOT NULL,
+	"remindertitle"	TEXT NOT NULL,
+	"reminderemailed"	INTEGER NOT NULL DEFAULT 0,
+	"reminderdismissed"	INTEGER NOT NULL DEFAULT 0,
+	PRIMARY KEY("reminderid" AUTOINCREMENT),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE,
+	FOREIGN KEY("pnoteid") REFERENCES "sc_project_notes"("pnoteid") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "sc_reminders_due" ON "sc_reminders" ("reminderdismissed", "reminderdue");
+-- whether reminders are also sent by email once they are due
+ALTER TABLE sc_user_prefs ADD COLUMN emailreminders INTEGER NOT NULL DEFAULT 0;
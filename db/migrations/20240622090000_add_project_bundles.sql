@@ -0,0 +1,15 @@
+-- Export bundles of a project's data that are generated in the background and can be downloaded
+-- once they are ready. The generated zip file is stored in the database until it expires.
+CREATE TABLE IF NOT EXISTS "sc_project_bundles" (
+	"bundleid"	INTEGER NOT NULL UNIQUE,
+	"bundleuuid"	TEXT NOT NULL UNIQUE,
+	"projectid"	INTEGER NOT NULL,
+	"userid"	INTEGER NOT NULL,
+	"bundlestatus"	INTEGER NOT NULL DEFAULT 0,
+	"bundledata"	BLOB,
+	"bundleerror"	TEXT,
+	"bundlecreated"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY("bundleid" AUTOINCREMENT),
+	FOREIGN KEY("projectid") REFERENCES "sc_projects"("projectid") ON DELETE CASCADE,
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE
+);
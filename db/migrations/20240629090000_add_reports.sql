@@ -0,0 +1,13 @@
+-- Custom reports that are defined by users: a template that is rendered with the samples that
+-- match a saved filter
+CREATE TABLE IF NOT EXISTS "sc_reports" (
+	"reportid"	INTEGER NOT NULL UNIQUE,
+	"userid"	INTEGER NOT NULL,
+	"reportname"	TEXT NOT NULL,
+	"reportformat"	INTEGER NOT NULL DEFAULT 0,
+	"reportfilter"	TEXT,
+	"reporttemplate"	TEXT NOT NULL,
+	PRIMARY KEY("reportid" AUTOINCREMENT),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE,
+	UNIQUE("userid", "reportname")
+);
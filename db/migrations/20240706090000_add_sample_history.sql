@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS "sc_sample_history" (
+	"historyid"	INTEGER NOT NULL UNIQUE,
+	"sampleid"	INTEGER NOT NULL,
+	"userid"	INTEGER,
+	"historyfield"	TEXT NOT NULL,
+	"historyold"	TEXT,
+	"historynew"	TEXT,
+	"historydate"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY("historyid" AUTOINCREMENT),
+	FOREIGN KEY("sampleid") REFERENCES "sc_samples"("sampleid") ON DELETE CASCADE,
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE SET NULL
+);
+CREATE INDEX IF NOT EXISTS "sc_sample_history_sampleid" ON "sc_sample_history"("sampleid");
@@ -0,0 +1,8 @@
+-- secret keys that identify a user in the address that they send notes to by email
+CREATE TABLE IF NOT EXISTS "sc_mailin_keys" (
+	"userid"	INTEGER NOT NULL UNIQUE,
+	"mailinkey"	TEXT NOT NULL UNIQUE,
+	"mailincreated"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY("userid"),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE
+);
@@ -0,0 +1,33 @@
+-- cultivation notes for a taxon, either the personal notes of a user or the shared notes of an
+-- organization
+CREATE TABLE IF NOT EXISTS "sc_taxon_notes" (
+	"tnoteid"	INTEGER NOT NULL UNIQUE,
+	"tsn"	INTEGER NOT NULL,
+	"userid"	INTEGER,
+	"orgid"	INTEGER,
+	"tnotetext"	TEXT NOT NULL,
+	"tnoteupdated"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	"tnoteeditor"	INTEGER,
+	PRIMARY KEY("tnoteid" AUTOINCREMENT),
+	FOREIGN KEY("tsn") REFERENCES "taxonomic_units"("tsn"),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE,
+	FOREIGN KEY("orgid") REFERENCES "sc_organizations"("orgid") ON DELETE CASCADE,
+	FOREIGN KEY("tnoteeditor") REFERENCES "sc_users"("userid") ON DELETE SET NULL,
+	CHECK(("userid" IS NULL) != ("orgid" IS NULL))
+);
+CREATE UNIQUE INDEX IF NOT EXISTS "sc_taxon_notes_user" ON "sc_taxon_notes"("tsn", "userid") WHERE "userid" IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS "sc_taxon_notes_org" ON "sc_taxon_notes"("tsn", "orgid") WHERE "orgid" IS NOT NULL;
+-- the cultivation notes that each user can see: the notes of the organizations that they are a
+-- member of, layered over their personal notes
+DROP VIEW IF EXISTS vtaxonnotes;
+CREATE VIEW vtaxonnotes (tnoteid, tsn, viewerid, userid, orgid, orgname, tnotetext, tnoteupdated, editorname, tnotelayer) AS
+SELECT N.tnoteid, N.tsn, M.userid, NULL, N.orgid, O.orgname, N.tnotetext, N.tnoteupdated, E.username, 0
+FROM sc_taxon_notes N
+INNER JOIN sc_org_members M ON M.orgid=N.orgid
+INNER JOIN sc_organizations O ON O.orgid=N.orgid
+LEFT JOIN sc_users E ON E.userid=N.tnoteeditor
+UNION ALL
+SELECT N.tnoteid, N.tsn, N.userid, N.userid, NULL, NULL, N.tnotetext, N.tnoteupdated, E.username, 1
+FROM sc_taxon_notes N
+LEFT JOIN sc_users E ON E.userid=N.tnoteeditor
+WHERE N.userid IS NOT NULL;
@@ -0,0 +1,4 @@
+-- the conditions at a source, each from a controlled vocabulary, see libseed::source
+ALTER TABLE sc_sources ADD COLUMN srchabitat INTEGER;
+ALTER TABLE sc_sources ADD COLUMN srcmoisture INTEGER;
+ALTER TABLE sc_sources ADD COLUMN srclight INTEGER;
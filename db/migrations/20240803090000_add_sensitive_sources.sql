@@ -0,0 +1,2 @@
+-- the exact coordinates of a sensitive source are only shown to users that may edit it
+ALTER TABLE sc_sources ADD COLUMN srcsensitive INTEGER NOT NULL DEFAULT 0;
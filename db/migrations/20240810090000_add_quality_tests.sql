@@ -0,0 +1,16 @@
+-- purity and fill tests of samples. The fill is the percentage of the seeds that were found to be
+-- filled by a cut test or an x-ray, and the purity is the percentage by weight of the sample that
+-- is seed rather than chaff or other debris.
+CREATE TABLE IF NOT EXISTS "sc_quality_tests" (
+	"qtestid"	INTEGER NOT NULL UNIQUE,
+	"sampleid"	INTEGER NOT NULL,
+	"qtestdate"	TEXT NOT NULL,
+	"qtestfill"	REAL,
+	"qtestmethod"	INTEGER,
+	"qtestpurity"	REAL,
+	"qtesttester"	TEXT,
+	PRIMARY KEY("qtestid" AUTOINCREMENT),
+	FOREIGN KEY("sampleid") REFERENCES "sc_samples"("sampleid") ON DELETE CASCADE,
+	CHECK("qtestfill" IS NOT NULL OR "qtestpurity" IS NOT NULL)
+);
+CREATE INDEX IF NOT EXISTS "sc_quality_tests_sample" ON "sc_quality_tests"("sampleid", "qtestdate");
@@ -0,0 +1,25 @@
+-- CSV files of samples that were uploaded to be imported through the web interface. The file is
+-- kept until the import has run so that the columns can be mapped and the result previewed first.
+-- The import itself runs in the background and records its progress here.
+CREATE TABLE IF NOT EXISTS "sc_sample_imports" (
+	"importid"	INTEGER NOT NULL UNIQUE,
+	"userid"	INTEGER NOT NULL,
+	"importfilename"	TEXT NOT NULL,
+	"importdata"	TEXT NOT NULL,
+	"importstatus"	INTEGER NOT NULL DEFAULT 0,
+	"importtotal"	INTEGER NOT NULL DEFAULT 0,
+	"importprocessed"	INTEGER NOT NULL DEFAULT 0,
+	"importadded"	INTEGER NOT NULL DEFAULT 0,
+	"importerror"	TEXT,
+	"importcreated"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY("importid" AUTOINCREMENT),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE
+);
+-- the rows of an import that could not be imported, for the error report
+CREATE TABLE IF NOT EXISTS "sc_sample_import_errors" (
+	"importid"	INTEGER NOT NULL,
+	"importrow"	INTEGER NOT NULL,
+	"importmessage"	TEXT NOT NULL,
+	FOREIGN KEY("importid") REFERENCES "sc_sample_imports"("importid") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "sc_sample_import_errors_import" ON "sc_sample_import_errors"("importid", "importrow");
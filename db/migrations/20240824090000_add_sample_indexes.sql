@@ -0,0 +1,54 @@
+-- Indexes for the columns that the sample and project lists are filtered and joined by. Without
+-- them, listing the samples of a single user scans every sample in the database.
+CREATE INDEX IF NOT EXISTS "sc_samples_user" ON "sc_samples" ("userid");
+CREATE INDEX IF NOT EXISTS "sc_samples_org" ON "sc_samples" ("sampleorgid");
+CREATE INDEX IF NOT EXISTS "sc_samples_taxon" ON "sc_samples" ("tsn");
+CREATE INDEX IF NOT EXISTS "sc_samples_source" ON "sc_samples" ("srcid");
+CREATE INDEX IF NOT EXISTS "sc_samples_trip" ON "sc_samples" ("tripid");
+CREATE INDEX IF NOT EXISTS "sc_sources_user" ON "sc_sources" ("userid");
+CREATE INDEX IF NOT EXISTS "sc_project_samples_sample" ON "sc_project_samples" ("sampleid");
+CREATE INDEX IF NOT EXISTS "sc_project_notes_allocation" ON "sc_project_notes" ("psid");
+CREATE INDEX IF NOT EXISTS "hierarchy_tsn" ON "hierarchy" ("TSN");
+CREATE INDEX IF NOT EXISTS "taxonomic_units_rank_name" ON "taxonomic_units" ("rank_id", "unit_name1");
+-- The common names used to be joined and grouped by sample, which made sqlite build the whole view
+-- before applying any filter. Looking them up for each row keeps the view a plain join, so that
+-- filters on the view can use the indexes of the underlying tables.
+DROP VIEW IF EXISTS vsamples;
+CREATE VIEW vsamples (sampleid, tsn, parentid, srcid, srcname, srcdesc, srcversion, srcorgid, complete_name, unit_name1, unit_name2, unit_name3, seq, quantity, month, year, notes, certainty, cnames, userid, sampleversion, sampleorgid, purchasevendor, purchaselot, purchasedate, purchaseprice, purchaseorigin, tripid, sampleuuid, srcuuid) AS
+SELECT S.sampleid,
+       T.tsn,
+       T.parent_tsn,
+       L.srcid,
+       L.srcname,
+       L.srcdesc,
+       L.srcversion,
+       L.srcorgid,
+       T.complete_name,
+       T.unit_name1,
+       T.unit_name2,
+       T.unit_name3,
+       T.phylo_sort_seq,
+       S.quantity,
+       S.month,
+       S.year,
+       S.notes,
+       S.certainty,
+  (SELECT GROUP_CONCAT(V.vernacular_name, '@')
+   FROM vernaculars V
+   WHERE V.tsn=T.tsn
+     AND (V.language='English'
+          OR V.language='unspecified')),
+       S.userid,
+       S.sampleversion,
+       S.sampleorgid,
+       S.purchasevendor,
+       S.purchaselot,
+       S.purchasedate,
+       S.purchaseprice,
+       S.purchaseorigin,
+       S.tripid,
+       S.sampleuuid,
+       L.srcuuid
+FROM sc_samples S
+INNER JOIN taxonomic_units T ON T.tsn=S.tsn
+INNER JOIN sc_sources L ON L.srcid=S.srcid;
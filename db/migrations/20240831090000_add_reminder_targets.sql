@@ -0,0 +1,8 @@
+-- reminders can also be about a sample, a project or a source, e.g. to re-test the viability of a
+-- sample or to return to a source when its seeds are ripe
+ALTER TABLE sc_reminders ADD COLUMN "sampleid" INTEGER REFERENCES "sc_samples"("sampleid") ON DELETE CASCADE;
+ALTER TABLE sc_reminders ADD COLUMN "projectid" INTEGER REFERENCES "sc_projects"("projectid") ON DELETE CASCADE;
+ALTER TABLE sc_reminders ADD COLUMN "srcid" INTEGER REFERENCES "sc_sources"("srcid") ON DELETE CASCADE;
+CREATE INDEX IF NOT EXISTS "sc_reminders_sample" ON "sc_reminders" ("sampleid");
+CREATE INDEX IF NOT EXISTS "sc_reminders_project" ON "sc_reminders" ("projectid");
+CREATE INDEX IF NOT EXISTS "sc_reminders_source" ON "sc_reminders" ("srcid");
@@ -0,0 +1,28 @@
+-- local names that are shown instead of the ITIS name of a taxon, either the personal names of a
+-- user or the shared names of an organization
+CREATE TABLE IF NOT EXISTS "sc_taxon_names" (
+	"tnameid"	INTEGER NOT NULL UNIQUE,
+	"tsn"	INTEGER NOT NULL,
+	"userid"	INTEGER,
+	"orgid"	INTEGER,
+	"tname"	TEXT NOT NULL,
+	PRIMARY KEY("tnameid" AUTOINCREMENT),
+	FOREIGN KEY("tsn") REFERENCES "taxonomic_units"("tsn"),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE,
+	FOREIGN KEY("orgid") REFERENCES "sc_organizations"("orgid") ON DELETE CASCADE,
+	CHECK(("userid" IS NULL) != ("orgid" IS NULL))
+);
+CREATE UNIQUE INDEX IF NOT EXISTS "sc_taxon_names_user" ON "sc_taxon_names"("tsn", "userid") WHERE "userid" IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS "sc_taxon_names_org" ON "sc_taxon_names"("tsn", "orgid") WHERE "orgid" IS NOT NULL;
+-- the display names that each user can see. A personal name takes precedence over the names of
+-- the user's organizations.
+DROP VIEW IF EXISTS vtaxonnames;
+CREATE VIEW vtaxonnames (tnameid, tsn, viewerid, userid, orgid, orgname, tname, tnamelayer) AS
+SELECT N.tnameid, N.tsn, N.userid, N.userid, NULL, NULL, N.tname, 0
+FROM sc_taxon_names N
+WHERE N.userid IS NOT NULL
+UNION ALL
+SELECT N.tnameid, N.tsn, M.userid, NULL, N.orgid, O.orgname, N.tname, 1
+FROM sc_taxon_names N
+INNER JOIN sc_org_members M ON M.orgid=N.orgid
+INNER JOIN sc_organizations O ON O.orgid=N.orgid;
@@ -0,0 +1,16 @@
+-- the cultivation status of each allocated sample. Changes to the status are recorded as events so
+-- that the progress of a project can be followed over time.
+ALTER TABLE sc_project_samples ADD COLUMN "psstatus" INTEGER NOT NULL DEFAULT 1;
+CREATE TABLE IF NOT EXISTS "sc_allocation_events" (
+	"evid"	INTEGER NOT NULL UNIQUE,
+	"psid"	INTEGER NOT NULL,
+	"userid"	INTEGER,
+	"evfrom"	INTEGER,
+	"evto"	INTEGER NOT NULL,
+	"evtime"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY("evid" AUTOINCREMENT),
+	FOREIGN KEY("psid") REFERENCES "sc_project_samples"("psid") ON DELETE CASCADE,
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE SET NULL
+);
+CREATE INDEX IF NOT EXISTS "sc_allocation_events_psid" ON "sc_allocation_events"("psid");
+CREATE INDEX IF NOT EXISTS "sc_allocation_events_time" ON "sc_allocation_events"("evtime");
@@ -0,0 +1,26 @@
+-- the minimum quantity that a user wants to keep of a single sample or of each sample of a taxon
+CREATE TABLE IF NOT EXISTS "sc_stock_thresholds" (
+	"thresholdid"	INTEGER NOT NULL UNIQUE,
+	"userid"	INTEGER NOT NULL,
+	"sampleid"	INTEGER,
+	"tsn"	INTEGER,
+	"minquantity"	INTEGER NOT NULL CHECK("minquantity" >= 0),
+	PRIMARY KEY("thresholdid" AUTOINCREMENT),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE,
+	FOREIGN KEY("sampleid") REFERENCES "sc_samples"("sampleid") ON DELETE CASCADE,
+	FOREIGN KEY("tsn") REFERENCES "taxonomic_units"("tsn"),
+	CHECK(("sampleid" IS NULL) != ("tsn" IS NULL))
+);
+CREATE UNIQUE INDEX IF NOT EXISTS "sc_stock_thresholds_sample" ON "sc_stock_thresholds"("userid", "sampleid") WHERE "sampleid" IS NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS "sc_stock_thresholds_taxon" ON "sc_stock_thresholds"("userid", "tsn") WHERE "tsn" IS NOT NULL;
+-- the samples that a user has already been sent a low stock alert about. The alert is removed once
+-- the sample is no longer low on stock, so that the user is alerted again when it runs low again.
+CREATE TABLE IF NOT EXISTS "sc_stock_alerts" (
+	"userid"	INTEGER NOT NULL,
+	"sampleid"	INTEGER NOT NULL,
+	"alertsent"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY("userid", "sampleid"),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE,
+	FOREIGN KEY("sampleid") REFERENCES "sc_samples"("sampleid") ON DELETE CASCADE
+);
+ALTER TABLE sc_user_prefs ADD COLUMN emaillowstock INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,30 @@
+-- how often a user wants to visit a source that they look after during the months of its season.
+-- The season wraps around the end of the year if it ends in an earlier month than it starts.
+CREATE TABLE IF NOT EXISTS "sc_monitoring_schedules" (
+	"scheduleid"	INTEGER NOT NULL UNIQUE,
+	"userid"	INTEGER NOT NULL,
+	"srcid"	INTEGER NOT NULL,
+	"intervalweeks"	INTEGER NOT NULL CHECK("intervalweeks" BETWEEN 1 AND 52),
+	"seasonstart"	INTEGER NOT NULL CHECK("seasonstart" BETWEEN 1 AND 12),
+	"seasonend"	INTEGER NOT NULL CHECK("seasonend" BETWEEN 1 AND 12),
+	PRIMARY KEY("scheduleid" AUTOINCREMENT),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE,
+	FOREIGN KEY("srcid") REFERENCES "sc_sources"("srcid") ON DELETE CASCADE
+);
+CREATE UNIQUE INDEX IF NOT EXISTS "sc_monitoring_schedules_source" ON "sc_monitoring_schedules"("userid", "srcid");
+-- what was observed on a visit to a source, optionally about the population of a single taxon
+CREATE TABLE IF NOT EXISTS "sc_source_visits" (
+	"visitid"	INTEGER NOT NULL UNIQUE,
+	"userid"	INTEGER NOT NULL,
+	"srcid"	INTEGER NOT NULL,
+	"visitdate"	TEXT NOT NULL,
+	"tsn"	INTEGER,
+	"population"	INTEGER CHECK("population" >= 0),
+	"phenophase"	INTEGER,
+	"visitnotes"	TEXT,
+	PRIMARY KEY("visitid" AUTOINCREMENT),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE,
+	FOREIGN KEY("srcid") REFERENCES "sc_sources"("srcid") ON DELETE CASCADE,
+	FOREIGN KEY("tsn") REFERENCES "taxonomic_units"("tsn")
+);
+CREATE INDEX IF NOT EXISTS "sc_source_visits_source" ON "sc_source_visits"("srcid", "visitdate");
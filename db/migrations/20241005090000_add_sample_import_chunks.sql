@@ -0,0 +1,12 @@
+-- Uploaded CSV files are stored in chunks so that large files can be uploaded in several requests
+-- and read back a piece at a time instead of loading the whole file into memory
+CREATE TABLE IF NOT EXISTS "sc_sample_import_chunks" (
+	"importid"	INTEGER NOT NULL,
+	"chunkno"	INTEGER NOT NULL,
+	"chunkdata"	BLOB NOT NULL,
+	PRIMARY KEY("importid", "chunkno"),
+	FOREIGN KEY("importid") REFERENCES "sc_sample_imports"("importid") ON DELETE CASCADE
+);
+INSERT INTO sc_sample_import_chunks (importid, chunkno, chunkdata)
+	SELECT importid, 0, CAST(importdata AS BLOB) FROM sc_sample_imports;
+ALTER TABLE sc_sample_imports DROP COLUMN importdata;
@@ -0,0 +1,58 @@
+-- the grades of a user's quality grading scale, e.g. A, B and C. Grades with a lower rank are
+-- better. The criteria describe what a sample needs to be given the grade.
+CREATE TABLE IF NOT EXISTS "sc_quality_grades" (
+	"gradeid"	INTEGER NOT NULL UNIQUE,
+	"userid"	INTEGER NOT NULL,
+	"gradelabel"	TEXT NOT NULL,
+	"graderank"	INTEGER NOT NULL,
+	"gradecriteria"	TEXT,
+	PRIMARY KEY("gradeid" AUTOINCREMENT),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE,
+	UNIQUE("userid", "gradelabel")
+);
+ALTER TABLE sc_samples ADD COLUMN gradeid INTEGER REFERENCES sc_quality_grades(gradeid) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS "sc_samples_grade" ON "sc_samples" ("gradeid");
+DROP VIEW IF EXISTS vsamples;
+CREATE VIEW vsamples (sampleid, tsn, parentid, srcid, srcname, srcdesc, srcversion, srcorgid, complete_name, unit_name1, unit_name2, unit_name3, seq, quantity, month, year, notes, certainty, cnames, userid, sampleversion, sampleorgid, purchasevendor, purchaselot, purchasedate, purchaseprice, purchaseorigin, tripid, sampleuuid, srcuuid, gradeid, gradelabel, graderank, gradecriteria) AS
+SELECT S.sampleid,
+       T.tsn,
+       T.parent_tsn,
+       L.srcid,
+       L.srcname,
+       L.srcdesc,
+       L.srcversion,
+       L.srcorgid,
+       T.complete_name,
+       T.unit_name1,
+       T.unit_name2,
+       T.unit_name3,
+       T.phylo_sort_seq,
+       S.quantity,
+       S.month,
+       S.year,
+       S.notes,
+       S.certainty,
+  (SELECT GROUP_CONCAT(V.vernacular_name, '@')
+   FROM vernaculars V
+   WHERE V.tsn=T.tsn
+     AND (V.language='English'
+          OR V.language='unspecified')),
+       S.userid,
+       S.sampleversion,
+       S.sampleorgid,
+       S.purchasevendor,
+       S.purchaselot,
+       S.purchasedate,
+       S.purchaseprice,
+       S.purchaseorigin,
+       S.tripid,
+       S.sampleuuid,
+       L.srcuuid,
+       S.gradeid,
+       G.gradelabel,
+       G.graderank,
+       G.gradecriteria
+FROM sc_samples S
+INNER JOIN taxonomic_units T ON T.tsn=S.tsn
+INNER JOIN sc_sources L ON L.srcid=S.srcid
+LEFT JOIN sc_quality_grades G ON G.gradeid=S.gradeid;
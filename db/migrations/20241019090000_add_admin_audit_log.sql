@@ -0,0 +1,16 @@
+-- site administrators can impersonate other users to debug problems that they reported
+ALTER TABLE sc_users ADD COLUMN useradmin INTEGER NOT NULL DEFAULT 0;
+-- everything an administrator does while impersonating another user is recorded here. The
+-- acting user is the administrator, the impersonated user is the one whose account was used.
+CREATE TABLE IF NOT EXISTS "sc_audit_log" (
+	"auditid"	INTEGER NOT NULL UNIQUE,
+	"auditdate"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	"actorid"	INTEGER,
+	"impersonatedid"	INTEGER,
+	"auditaction"	TEXT NOT NULL,
+	"auditdetails"	TEXT,
+	PRIMARY KEY("auditid" AUTOINCREMENT),
+	FOREIGN KEY("actorid") REFERENCES "sc_users"("userid") ON DELETE SET NULL,
+	FOREIGN KEY("impersonatedid") REFERENCES "sc_users"("userid") ON DELETE SET NULL
+);
+CREATE INDEX IF NOT EXISTS "sc_audit_log_date" ON "sc_audit_log" ("auditdate");
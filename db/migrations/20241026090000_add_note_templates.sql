@@ -0,0 +1,13 @@
+-- templates for the notes that are added to the samples of a project again and again, e.g. weekly
+-- germination checks. The summary is a skeleton to fill in and the details often hold a checklist.
+CREATE TABLE IF NOT EXISTS "sc_note_templates" (
+	"templateid"	INTEGER NOT NULL UNIQUE,
+	"projectid"	INTEGER NOT NULL,
+	"templatename"	TEXT NOT NULL,
+	"notetype"	INTEGER NOT NULL,
+	"notesummary"	TEXT NOT NULL,
+	"notedetails"	TEXT,
+	PRIMARY KEY("templateid" AUTOINCREMENT),
+	FOREIGN KEY("projectid") REFERENCES "sc_projects"("projectid") ON DELETE CASCADE,
+	UNIQUE("projectid", "templatename")
+);
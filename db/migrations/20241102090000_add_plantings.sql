@@ -0,0 +1,25 @@
+-- the outcome of growing out a sample that was allocated to a project: how many plugs were
+-- produced and how many of them were planted where
+CREATE TABLE IF NOT EXISTS "sc_plantings" (
+	"plantingid"	INTEGER NOT NULL UNIQUE,
+	"psid"	INTEGER NOT NULL,
+	"plantingdate"	TEXT NOT NULL,
+	"plantingplugs"	INTEGER,
+	"plantingcount"	INTEGER NOT NULL,
+	"plantinglocation"	TEXT,
+	"plantingnotes"	TEXT,
+	PRIMARY KEY("plantingid" AUTOINCREMENT),
+	FOREIGN KEY("psid") REFERENCES "sc_project_samples"("psid") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "sc_plantings_allocation" ON "sc_plantings" ("psid");
+-- counts of the plants of a planting that were still alive some months after it was planted
+CREATE TABLE IF NOT EXISTS "sc_survival_checks" (
+	"checkid"	INTEGER NOT NULL UNIQUE,
+	"plantingid"	INTEGER NOT NULL,
+	"checkdate"	TEXT NOT NULL,
+	"checkmonths"	INTEGER NOT NULL,
+	"checkalive"	INTEGER NOT NULL,
+	PRIMARY KEY("checkid" AUTOINCREMENT),
+	FOREIGN KEY("plantingid") REFERENCES "sc_plantings"("plantingid") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "sc_survival_checks_planting" ON "sc_survival_checks" ("plantingid");
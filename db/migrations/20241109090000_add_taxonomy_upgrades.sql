@@ -0,0 +1,33 @@
+-- the accepted plant taxa as they were before a taxonomy upgrade. It is filled right before the
+-- ITIS tables are replaced and emptied again once the upgrade has been recorded.
+CREATE TABLE IF NOT EXISTS "sc_taxonomy_snapshot" (
+	"tsn"	INTEGER NOT NULL,
+	"parent_tsn"	INTEGER,
+	"complete_name"	TEXT,
+	"rank_id"	INTEGER NOT NULL,
+	PRIMARY KEY("tsn")
+);
+-- every recorded taxonomy upgrade along with the taxa that it changed
+CREATE TABLE IF NOT EXISTS "sc_taxonomy_upgrades" (
+	"upgradeid"	INTEGER NOT NULL UNIQUE,
+	"upgradedate"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	"upgradelabel"	TEXT,
+	PRIMARY KEY("upgradeid" AUTOINCREMENT)
+);
+-- a taxon that was added (no old name), removed (no new name), renamed or moved to another
+-- parent by an upgrade. Names are copied so that the report stays readable after later upgrades.
+CREATE TABLE IF NOT EXISTS "sc_taxonomy_changes" (
+	"changeid"	INTEGER NOT NULL UNIQUE,
+	"upgradeid"	INTEGER NOT NULL,
+	"tsn"	INTEGER NOT NULL,
+	"rank_id"	INTEGER NOT NULL,
+	"oldname"	TEXT,
+	"newname"	TEXT,
+	"oldparenttsn"	INTEGER,
+	"oldparentname"	TEXT,
+	"newparenttsn"	INTEGER,
+	"newparentname"	TEXT,
+	PRIMARY KEY("changeid" AUTOINCREMENT),
+	FOREIGN KEY("upgradeid") REFERENCES "sc_taxonomy_upgrades"("upgradeid") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "sc_taxonomy_changes_upgrade" ON "sc_taxonomy_changes" ("upgradeid");
@@ -0,0 +1,4 @@
+-- how numbers are displayed to the user: 0 = 1,234.5, 1 = 1.234,5, 2 = 1 234,5
+ALTER TABLE sc_users ADD COLUMN usernumberstyle INTEGER NOT NULL DEFAULT 0;
+-- the unit that seed weights are displayed in: 0 = grams, 1 = ounces
+ALTER TABLE sc_users ADD COLUMN userweightunit INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,14 @@
+-- the size of a user's collection on a particular day, recorded periodically so that its growth
+-- can be shown over time
+CREATE TABLE IF NOT EXISTS "sc_stats_snapshots" (
+	"snapshotid"	INTEGER NOT NULL UNIQUE,
+	"userid"	INTEGER NOT NULL,
+	"snapdate"	TEXT NOT NULL,
+	"nsamples"	INTEGER NOT NULL,
+	"ntaxa"	INTEGER NOT NULL,
+	"nsources"	INTEGER NOT NULL,
+	"quantity"	INTEGER NOT NULL,
+	PRIMARY KEY("snapshotid" AUTOINCREMENT),
+	UNIQUE("userid", "snapdate"),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE
+);
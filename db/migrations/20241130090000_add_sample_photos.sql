@@ -0,0 +1,13 @@
+-- Photos of samples, e.g. of the seeds for identification. The image is stored in the database
+-- along with its content type so that it can be served as it was uploaded.
+CREATE TABLE IF NOT EXISTS "sc_sample_photos" (
+	"photoid"	INTEGER NOT NULL UNIQUE,
+	"sampleid"	INTEGER NOT NULL,
+	"phototype"	TEXT NOT NULL,
+	"photodata"	BLOB NOT NULL,
+	"photocaption"	TEXT,
+	"photoadded"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY("photoid" AUTOINCREMENT),
+	FOREIGN KEY("sampleid") REFERENCES "sc_samples"("sampleid") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "sc_sample_photos_sample" ON "sc_sample_photos" ("sampleid");
@@ -0,0 +1,36 @@
+-- ITIS releases that were downloaded or uploaded to upgrade the taxonomy from the web interface.
+-- A release is checked and previewed in the background, and it is only installed once an
+-- administrator has reviewed the changes that it would make.
+CREATE TABLE IF NOT EXISTS "sc_taxonomy_releases" (
+	"releaseid"	INTEGER NOT NULL UNIQUE,
+	"userid"	INTEGER NOT NULL,
+	"releasestatus"	INTEGER NOT NULL DEFAULT 0,
+	"releasesource"	TEXT NOT NULL,
+	"releasepath"	TEXT,
+	"releaseupdated"	TEXT,
+	"releasetaxa"	INTEGER,
+	"releaseprogress"	TEXT,
+	"releaseerror"	TEXT,
+	"upgradeid"	INTEGER,
+	"releasecreated"	TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	PRIMARY KEY("releaseid" AUTOINCREMENT),
+	FOREIGN KEY("userid") REFERENCES "sc_users"("userid") ON DELETE CASCADE,
+	FOREIGN KEY("upgradeid") REFERENCES "sc_taxonomy_upgrades"("upgradeid") ON DELETE SET NULL
+);
+-- the taxa that installing a staged release would change, in the same form as the changes of a
+-- recorded upgrade
+CREATE TABLE IF NOT EXISTS "sc_taxonomy_release_changes" (
+	"changeid"	INTEGER NOT NULL UNIQUE,
+	"releaseid"	INTEGER NOT NULL,
+	"tsn"	INTEGER NOT NULL,
+	"rank_id"	INTEGER NOT NULL,
+	"oldname"	TEXT,
+	"newname"	TEXT,
+	"oldparenttsn"	INTEGER,
+	"oldparentname"	TEXT,
+	"newparenttsn"	INTEGER,
+	"newparentname"	TEXT,
+	PRIMARY KEY("changeid" AUTOINCREMENT),
+	FOREIGN KEY("releaseid") REFERENCES "sc_taxonomy_releases"("releaseid") ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS "sc_taxonomy_release_changes_release" ON "sc_taxonomy_release_changes" ("releaseid");
@@ -0,0 +1,184 @@
+//! Benchmarks for the queries behind the pages that are used the most: the sample list and the
+//! list of samples in a project.
+//!
+//! By default the benchmarks run against a new temporary database that is filled with generated
+//! data, using only the few taxa from the test fixtures. The size of the generated dataset can be
+//! set with the environment variable `SEEDCOLLECTION_BENCH_SAMPLES`. To benchmark with a complete
+//! taxonomy, set `SEEDCOLLECTION_BENCH_DB` to the path of an existing database instead, e.g. a
+//! copy of a real database or one that was filled with `seedctl admin database generate-load`.
+//! That database is not modified.
+use criterion::{criterion_group, criterion_main, Criterion};
+use libseed::{
+    filter::{Cmp, CompoundFilter, Op, SortOrder, SortSpec},
+    loadable::Loadable,
+    project::{allocation, Project},
+    sample::{self, Sample},
+    testing::load::{self, LoadSize},
+};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    Executor, Pool, Sqlite,
+};
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tokio::runtime::Runtime;
+
+const DEFAULT_SAMPLES: usize = 50_000;
+
+/// The text that the lists are filtered by, which matches some of the generated sources
+const FILTER: &str = "prairie";
+
+struct Dataset {
+    pool: Pool<Sqlite>,
+    /// the temporary database that was generated for the benchmarks, if any
+    generated: Option<PathBuf>,
+    /// the user with the most samples
+    userid: i64,
+    /// the project with the most samples
+    projectid: i64,
+}
+
+async fn open(path: &Path, create: bool) -> Pool<Sqlite> {
+    let options = SqliteConnectOptions::from_str(&format!("sqlite://{}", path.display()))
+        .expect("Invalid database path")
+        .create_if_missing(create)
+        .read_only(!create);
+    SqlitePoolOptions::new()
+        .connect_with(options)
+        .await
+        .expect("Failed to open the database")
+}
+
+async fn dataset() -> Dataset {
+    let (pool, generated) = match std::env::var_os("SEEDCOLLECTION_BENCH_DB") {
+        Some(path) => (open(Path::new(&path), false).await, None),
+        None => {
+            let samples = std::env::var("SEEDCOLLECTION_BENCH_SAMPLES")
+                .ok()
+                .map(|n| n.parse().expect("Invalid number of samples"))
+                .unwrap_or(DEFAULT_SAMPLES);
+            let path = std::env::temp_dir().join(format!(
+                "seedcollection-bench-{}.sqlite",
+                std::process::id()
+            ));
+            let pool = open(&path, true).await;
+            sqlx::migrate!("../db/migrations")
+                .run(&pool)
+                .await
+                .expect("Failed to run migrations");
+            pool.execute(include_str!("../../db/fixtures/taxa.sql"))
+                .await
+                .expect("Failed to add taxa");
+            load::generate(&LoadSize::samples(samples), 1, &pool)
+                .await
+                .expect("Failed to generate the dataset");
+            (pool, Some(path))
+        }
+    };
+    let userid = sqlx::query_scalar(
+        "SELECT userid FROM sc_samples GROUP BY userid ORDER BY COUNT(*) DESC LIMIT 1",
+    )
+    .fetch_one(&pool)
+    .await
+    .expect("The database has no samples");
+    let projectid = sqlx::query_scalar(
+        "SELECT projectid FROM sc_project_samples GROUP BY projectid ORDER BY COUNT(*) DESC LIMIT 1",
+    )
+    .fetch_one(&pool)
+    .await
+    .expect("The database has no projects with samples");
+    Dataset {
+        pool,
+        generated,
+        userid,
+        projectid,
+    }
+}
+
+fn sample_list(c: &mut Criterion, rt: &Runtime, data: &Dataset) {
+    let mut group = c.benchmark_group("sample list");
+    group.sample_size(10);
+    group.bench_function("all", |b| {
+        b.to_async(rt).iter(|| async {
+            Sample::load_all_user(data.userid, None, None, &data.pool)
+                .await
+                .unwrap()
+        })
+    });
+    group.bench_function("text filter", |b| {
+        b.to_async(rt).iter(|| async {
+            // the same filter as the filter field of the sample list
+            let filter = CompoundFilter::builder(Op::Or)
+                .push(sample::Filter::TaxonNameLike(FILTER.to_string()))
+                .push(sample::Filter::Notes(Cmp::Like, FILTER.to_string()))
+                .push(sample::Filter::SourceNameLike(FILTER.to_string()))
+                .build();
+            Sample::load_all_user(data.userid, Some(filter), None, &data.pool)
+                .await
+                .unwrap()
+        })
+    });
+    group.bench_function("family", |b| {
+        b.to_async(rt).iter(|| async {
+            Sample::load_all_user(
+                data.userid,
+                Some(sample::Filter::Family("Poaceae".to_string()).into()),
+                None,
+                &data.pool,
+            )
+            .await
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn project_allocations(c: &mut Criterion, rt: &Runtime, data: &Dataset) {
+    // the project is loaded in every iteration, like on the project page
+    let mut group = c.benchmark_group("project allocations");
+    group.sample_size(10);
+    group.bench_function("all", |b| {
+        b.to_async(rt).iter(|| async {
+            let mut project = Project::load(data.projectid, &data.pool).await.unwrap();
+            project.load_samples(None, None, &data.pool).await.unwrap();
+            project
+        })
+    });
+    group.bench_function("text filter sorted by activity", |b| {
+        b.to_async(rt).iter(|| async {
+            let mut project = Project::load(data.projectid, &data.pool).await.unwrap();
+            // the same filter as the filter field of the project page
+            let filter = CompoundFilter::builder(Op::Or)
+                .push(allocation::Filter::TaxonNameLike(FILTER.to_string()))
+                .push(allocation::Filter::SourceName(
+                    Cmp::Like,
+                    FILTER.to_string(),
+                ))
+                .push(allocation::Filter::Notes(Cmp::Like, FILTER.to_string()))
+                .build();
+            let sort = SortSpec::new(allocation::SortField::Activity, SortOrder::Descending);
+            project
+                .load_samples(Some(filter), Some(sort.into()), &data.pool)
+                .await
+                .unwrap();
+            project
+        })
+    });
+    group.finish();
+}
+
+fn queries(c: &mut Criterion) {
+    let rt = Runtime::new().expect("Failed to start runtime");
+    let data = rt.block_on(dataset());
+    sample_list(c, &rt, &data);
+    project_allocations(c, &rt, &data);
+    rt.block_on(data.pool.close());
+    if let Some(path) = data.generated {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+criterion_group!(benches, queries);
+criterion_main!(benches);
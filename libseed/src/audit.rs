@@ -0,0 +1,120 @@
+//! The audit trail of administrative actions. When a site administrator impersonates another user,
+//! the start and end of the impersonation and every change made in the meantime are recorded
+//! along with both identities, so that it is always possible to tell who really made a change.
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Sqlite};
+use time::OffsetDateTime;
+
+const SELECT: &str = r#"SELECT A.*, U.username AS actorname, I.username AS impersonatedname
+    FROM sc_audit_log A
+    LEFT JOIN sc_users U ON U.userid=A.actorid
+    LEFT JOIN sc_users I ON I.userid=A.impersonatedid"#;
+
+/// A single entry in the audit trail
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct AuditEntry {
+    #[sqlx(rename = "auditid")]
+    pub id: i64,
+    #[sqlx(rename = "auditdate")]
+    pub date: OffsetDateTime,
+    /// the user who performed the action. `None` if the user has since been deleted.
+    pub actorid: Option<i64>,
+    pub actorname: Option<String>,
+    /// the user that the actor was impersonating at the time, if any
+    pub impersonatedid: Option<i64>,
+    pub impersonatedname: Option<String>,
+    /// a short description of the action, e.g. `impersonate` or `POST /app/sample/new`
+    #[sqlx(rename = "auditaction")]
+    pub action: String,
+    #[sqlx(rename = "auditdetails")]
+    pub details: Option<String>,
+}
+
+impl AuditEntry {
+    /// Add an entry to the audit trail
+    pub async fn record(
+        actorid: i64,
+        impersonatedid: Option<i64>,
+        action: &str,
+        details: Option<&str>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<i64> {
+        if action.trim().is_empty() {
+            return Err(Error::InvalidValue(
+                "an audit entry needs an action".to_string(),
+            ));
+        }
+        let res = sqlx::query(
+            r#"INSERT INTO sc_audit_log (actorid, impersonatedid, auditaction, auditdetails)
+            VALUES (?, ?, ?, ?)"#,
+        )
+        .bind(actorid)
+        .bind(impersonatedid)
+        .bind(action)
+        .bind(details)
+        .execute(pool)
+        .await?;
+        Ok(res.last_insert_rowid())
+    }
+
+    /// Load the most recent entries of the audit trail, newest first. If `userid` is given, only
+    /// the entries where that user was either the actor or the impersonated user are returned.
+    pub async fn load_recent(
+        userid: Option<i64>,
+        limit: i64,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let mut sql = SELECT.to_string();
+        if userid.is_some() {
+            sql.push_str(" WHERE A.actorid=?1 OR A.impersonatedid=?1");
+        }
+        sql.push_str(" ORDER BY A.auditdate DESC, A.auditid DESC LIMIT ?2");
+        sqlx::query_as(&sql)
+            .bind(userid)
+            .bind(limit)
+            .fetch_all(pool)
+            .await
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users"))
+    ))]
+    async fn test_audit(pool: Pool<Sqlite>) {
+        AuditEntry::record(1, Some(2), "impersonate", None, &pool)
+            .await
+            .unwrap();
+        AuditEntry::record(1, Some(2), "POST /app/sample/new", Some("form"), &pool)
+            .await
+            .unwrap();
+        AuditEntry::record(1, None, "stop-impersonating", None, &pool)
+            .await
+            .unwrap();
+        assert!(AuditEntry::record(1, None, " ", None, &pool).await.is_err());
+
+        let entries = AuditEntry::load_recent(None, 10, &pool).await.unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].action, "stop-impersonating");
+        assert_eq!(entries[1].impersonatedid, Some(2));
+        assert!(entries[1].actorname.is_some());
+        assert!(entries[1].impersonatedname.is_some());
+        assert_eq!(entries[1].details.as_deref(), Some("form"));
+
+        let entries = AuditEntry::load_recent(Some(2), 10, &pool).await.unwrap();
+        assert_eq!(entries.len(), 2);
+        let entries = AuditEntry::load_recent(Some(1), 1, &pool).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert!(AuditEntry::load_recent(Some(3), 10, &pool)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+//! Operations that apply to many items at once, like allocating several samples to a project or
+//! importing the rows of a spreadsheet. An item that fails doesn't stop the operation: the outcome
+//! of every item is collected in a [BulkResult], so that the caller can report which items
+//! succeeded and why the others failed.
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// The outcome of a single item of a bulk operation
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct ItemResult<K> {
+    /// identifies the item, e.g. the id of a sample or the line of a file
+    pub item: K,
+    /// why the item failed, or `None` if it succeeded
+    pub error: Option<String>,
+}
+
+impl<K> ItemResult<K> {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// The outcomes of all items of a bulk operation, in the order that they were processed
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct BulkResult<K> {
+    pub items: Vec<ItemResult<K>>,
+}
+
+impl<K> Default for BulkResult<K> {
+    fn default() -> Self {
+        Self { items: Vec::new() }
+    }
+}
+
+impl<K> BulkResult<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that the item succeeded
+    pub fn succeed(&mut self, item: K) {
+        self.items.push(ItemResult { item, error: None });
+    }
+
+    /// Record that the item failed for the given reason
+    pub fn fail(&mut self, item: K, error: impl Into<String>) {
+        self.items.push(ItemResult {
+            item,
+            error: Some(error.into()),
+        });
+    }
+
+    /// Record the outcome of the item from the result of processing it, returning the value if it
+    /// succeeded
+    pub fn record<T, E: Display>(&mut self, item: K, result: Result<T, E>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                self.succeed(item);
+                Some(value)
+            }
+            Err(e) => {
+                self.fail(item, e.to_string());
+                None
+            }
+        }
+    }
+
+    /// The items that succeeded
+    pub fn succeeded(&self) -> impl Iterator<Item = &K> {
+        self.items.iter().filter(|r| r.succeeded()).map(|r| &r.item)
+    }
+
+    /// The items that failed, along with the reason
+    pub fn failed(&self) -> impl Iterator<Item = (&K, &str)> {
+        self.items
+            .iter()
+            .filter_map(|r| r.error.as_deref().map(|e| (&r.item, e)))
+    }
+
+    pub fn n_succeeded(&self) -> usize {
+        self.succeeded().count()
+    }
+
+    pub fn n_failed(&self) -> usize {
+        self.failed().count()
+    }
+
+    /// Whether any item failed
+    pub fn has_failures(&self) -> bool {
+        self.items.iter().any(|r| !r.succeeded())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn bulk_result() {
+        let mut result = BulkResult::new();
+        assert!(!result.has_failures());
+        assert_eq!(result.record(1, Ok::<_, Error>("one")), Some("one"));
+        assert_eq!(
+            result.record(
+                2,
+                Err::<(), _>(Error::InvalidValue("bad value".to_string()))
+            ),
+            None
+        );
+        result.succeed(3);
+        result.fail(4, "missing");
+
+        assert!(result.has_failures());
+        assert_eq!(result.n_succeeded(), 2);
+        assert_eq!(result.n_failed(), 2);
+        assert_eq!(result.succeeded().collect::<Vec<_>>(), vec![&1, &3]);
+        assert_eq!(
+            result.failed().collect::<Vec<_>>(),
+            vec![(&2, "invalid value: bad value"), (&4, "missing")]
+        );
+        assert_eq!(
+            result.items.iter().map(|r| r.item).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4]
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! Conservation listings of rare species and the permits that are needed to collect them. States
+//! publish lists of endangered, threatened and special concern species, which can be imported for
+//! each region. Users are warned about samples of listed taxa and, depending on their
+//! [PermitPolicy], need a permit before they can add new samples of a taxon that is listed in
+//! their region.
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteQueryResult, FromRow, Pool, Sqlite};
+use strum_macros::{Display, EnumString};
+use time::Date;
+
+#[derive(
+    Clone, Copy, Deserialize, Serialize, Debug, sqlx::Type, PartialEq, Display, EnumString,
+)]
+#[repr(i32)]
+#[strum(ascii_case_insensitive)]
+pub enum ListingStatus {
+    #[strum(serialize = "Endangered", serialize = "E", serialize = "END")]
+    Endangered = 1,
+    #[strum(serialize = "Threatened", serialize = "T", serialize = "THR")]
+    Threatened = 2,
+    #[strum(
+        to_string = "Special Concern",
+        serialize = "SpecialConcern",
+        serialize = "SC",
+        serialize = "SPC"
+    )]
+    SpecialConcern = 3,
+}
+
+/// Normalize the name of a region so that e.g. "mn" and "MN " refer to the same region
+pub fn normalize_region(region: &str) -> String {
+    region.trim().to_uppercase()
+}
+
+/// The conservation status of a taxon in a single region
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Listing {
+    pub tsn: i64,
+    pub region: String,
+    #[sqlx(rename = "consstatus")]
+    pub status: ListingStatus,
+}
+
+impl Listing {
+    /// Load the listings of a taxon in all regions
+    pub async fn load_taxon(tsn: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM sc_conservation_status WHERE tsn=? ORDER BY region")
+                .bind(tsn)
+                .fetch_all(pool)
+                .await?,
+        )
+    }
+
+    /// Load all listings, optionally only those of a single region
+    pub async fn load_all(region: Option<&str>, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        let region = region.map(normalize_region);
+        Ok(sqlx::query_as(
+            r#"SELECT * FROM sc_conservation_status WHERE ? IS NULL OR region=?
+            ORDER BY region, tsn"#,
+        )
+        .bind(&region)
+        .bind(&region)
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Replace all listings of a region with the given list of taxa, e.g. when a new version of
+    /// a state's list is published. Returns the number of listings that were saved.
+    pub async fn replace_region(
+        region: &str,
+        listings: &[(i64, ListingStatus)],
+        pool: &Pool<Sqlite>,
+    ) -> Result<u64> {
+        let region = normalize_region(region);
+        if region.is_empty() {
+            return Err(Error::InvalidValue("the region is empty".to_string()));
+        }
+        let mut tx = pool.begin().await?;
+        sqlx::query("DELETE FROM sc_conservation_status WHERE region=?")
+            .bind(&region)
+            .execute(&mut *tx)
+            .await?;
+        let mut saved = 0;
+        for (tsn, status) in listings {
+            saved += sqlx::query(
+                r#"INSERT INTO sc_conservation_status (tsn, region, consstatus) VALUES (?, ?, ?)
+                ON CONFLICT(tsn, region) DO UPDATE SET consstatus=excluded.consstatus"#,
+            )
+            .bind(tsn)
+            .bind(&region)
+            .bind(status)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+        tx.commit().await?;
+        Ok(saved)
+    }
+}
+
+/// A permit that allows a user to collect a listed taxon in a region
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Permit {
+    #[sqlx(rename = "permitid")]
+    pub id: i64,
+    pub userid: i64,
+    pub tsn: i64,
+    pub region: String,
+    /// the number or other identifier of the permit issued by the permitting agency
+    #[sqlx(rename = "permitnumber")]
+    pub number: String,
+    /// the last day that the permit is valid, if it expires
+    #[sqlx(rename = "permitexpires")]
+    pub expires: Option<Date>,
+    #[sqlx(rename = "permitnotes")]
+    pub notes: Option<String>,
+}
+
+impl Permit {
+    pub fn new(userid: i64, tsn: i64, region: &str, number: String) -> Self {
+        Self {
+            id: -1,
+            userid,
+            tsn,
+            region: normalize_region(region),
+            number,
+            expires: None,
+            notes: None,
+        }
+    }
+
+    pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        if self.id != -1 {
+            return Err(Error::InvalidOperationObjectAlreadyExists(self.id));
+        }
+        self.region = normalize_region(&self.region);
+        if self.region.is_empty() {
+            return Err(Error::InvalidValue("the region is empty".to_string()));
+        }
+        if self.number.trim().is_empty() {
+            return Err(Error::InvalidValue(
+                "the permit number is empty".to_string(),
+            ));
+        }
+        let res = sqlx::query(
+            r#"INSERT INTO sc_permits (userid, tsn, region, permitnumber, permitexpires, permitnotes)
+            VALUES (?, ?, ?, ?, ?, ?)"#,
+        )
+        .bind(self.userid)
+        .bind(self.tsn)
+        .bind(&self.region)
+        .bind(self.number.trim())
+        .bind(self.expires)
+        .bind(&self.notes)
+        .execute(pool)
+        .await?;
+        self.id = res.last_insert_rowid();
+        Ok(res)
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        Ok(sqlx::query_as("SELECT * FROM sc_permits WHERE permitid=?")
+            .bind(id)
+            .fetch_one(pool)
+            .await?)
+    }
+
+    /// Load all of the user's permits, including expired ones
+    pub async fn load_all_user(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as(
+            "SELECT * FROM sc_permits WHERE userid=? ORDER BY region, tsn, permitexpires",
+        )
+        .bind(userid)
+        .fetch_all(pool)
+        .await?)
+    }
+
+    pub async fn delete(&self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        sqlx::query("DELETE FROM sc_permits WHERE permitid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+}
+
+/// What happens when a user adds a new sample of a taxon that is listed in their region without
+/// having a valid permit for it
+#[derive(
+    Clone, Copy, Default, Deserialize, Serialize, Debug, sqlx::Type, PartialEq, Display, EnumString,
+)]
+#[repr(i32)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+#[serde(rename_all = "lowercase")]
+pub enum PermitPolicy {
+    /// listings are not checked
+    Ignore = 0,
+    /// the sample is saved, but the user is warned about the missing permit
+    Warn = 1,
+    /// the sample can't be saved without a permit
+    #[default]
+    Require = 2,
+}
+
+impl PermitPolicy {
+    /// Check whether a new sample may be saved, given the listings that the user has no permit
+    /// for
+    pub fn check(&self, unpermitted: &[Listing]) -> Result<()> {
+        match (self, unpermitted.first()) {
+            (Self::Require, Some(listing)) => Err(Error::PermitRequired(format!(
+                "the taxon is listed as {} in {}",
+                listing.status.to_string().to_lowercase(),
+                listing.region
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The listings of a taxon that apply to a user and that the user doesn't have a valid permit for
+/// on the given date. If `region` is given, only listings in that region apply, otherwise the
+/// listings of all regions do.
+pub async fn unpermitted_listings(
+    userid: i64,
+    tsn: i64,
+    region: Option<&str>,
+    date: Date,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<Listing>> {
+    let region = region.map(normalize_region).filter(|r| !r.is_empty());
+    Ok(sqlx::query_as(
+        r#"SELECT L.* FROM sc_conservation_status L
+        WHERE L.tsn=? AND (? IS NULL OR L.region=?)
+        AND NOT EXISTS (SELECT 1 FROM sc_permits P WHERE P.userid=? AND P.tsn=L.tsn
+            AND P.region=L.region AND (P.permitexpires IS NULL OR P.permitexpires >= ?))
+        ORDER BY L.region"#,
+    )
+    .bind(tsn)
+    .bind(&region)
+    .bind(&region)
+    .bind(userid)
+    .bind(date)
+    .fetch_all(pool)
+    .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use test_log::test;
+    use time::macros::date;
+
+    #[test]
+    fn parse_status() {
+        assert_eq!(
+            ListingStatus::from_str("E").unwrap(),
+            ListingStatus::Endangered
+        );
+        assert_eq!(
+            ListingStatus::from_str("threatened").unwrap(),
+            ListingStatus::Threatened
+        );
+        assert_eq!(
+            ListingStatus::from_str("sc").unwrap(),
+            ListingStatus::SpecialConcern
+        );
+        assert_eq!(ListingStatus::SpecialConcern.to_string(), "Special Concern");
+        assert!(ListingStatus::from_str("rare").is_err());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users", "taxa"))
+    ))]
+    async fn permits_for_listed_taxa(pool: Pool<Sqlite>) {
+        Listing::replace_region(
+            "mn",
+            &[
+                (43254, ListingStatus::Threatened),
+                (40683, ListingStatus::SpecialConcern),
+            ],
+            &pool,
+        )
+        .await
+        .expect("Failed to import listings");
+        Listing::replace_region("WI", &[(43254, ListingStatus::Endangered)], &pool)
+            .await
+            .expect("Failed to import listings");
+        assert_eq!(Listing::load_taxon(43254, &pool).await.unwrap().len(), 2);
+        // importing a new list replaces the old one
+        Listing::replace_region("MN", &[(43254, ListingStatus::Endangered)], &pool)
+            .await
+            .expect("Failed to import listings");
+        let mn = Listing::load_all(Some("MN"), &pool).await.unwrap();
+        assert_eq!(mn.len(), 1);
+        assert_eq!(mn[0].status, ListingStatus::Endangered);
+
+        let today = date!(2024 - 05 - 01);
+        let unpermitted = |region: Option<&'static str>| {
+            let pool = pool.clone();
+            async move {
+                unpermitted_listings(1, 43254, region, today, &pool)
+                    .await
+                    .expect("Failed to check listings")
+                    .iter()
+                    .map(|l| l.region.clone())
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(unpermitted(None).await, ["MN", "WI"]);
+        assert_eq!(unpermitted(Some("mn")).await, ["MN"]);
+        assert_eq!(
+            PermitPolicy::Require
+                .check(&Listing::load_all(Some("MN"), &pool).await.unwrap())
+                .unwrap_err()
+                .to_string(),
+            "the taxon is listed as endangered in MN, which requires a collecting permit"
+        );
+        assert!(PermitPolicy::Warn
+            .check(&Listing::load_all(Some("MN"), &pool).await.unwrap())
+            .is_ok());
+
+        // an expired permit doesn't count
+        let mut expired = Permit::new(1, 43254, "MN", "2023-17".to_string());
+        expired.expires = Some(date!(2023 - 12 - 31));
+        expired.insert(&pool).await.expect("Failed to add permit");
+        assert_eq!(unpermitted(Some("MN")).await, ["MN"]);
+        let mut permit = Permit::new(1, 43254, "mn", "2024-03".to_string());
+        permit.expires = Some(date!(2024 - 12 - 31));
+        permit.insert(&pool).await.expect("Failed to add permit");
+        assert!(unpermitted(Some("MN")).await.is_empty());
+        assert_eq!(unpermitted(None).await, ["WI"]);
+        // permits are personal
+        assert_eq!(
+            unpermitted_listings(2, 43254, Some("MN"), today, &pool)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(Permit::load_all_user(1, &pool).await.unwrap().len(), 2);
+        assert!(Permit::new(1, 43254, "MN", " ".to_string())
+            .insert(&pool)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,217 @@
+//! Cultivation notes: free-form instructions for sowing and growing a taxon, written in markdown.
+//! Unlike the notes of a sample, they apply to every sample of the taxon. Each user can keep
+//! personal notes for a taxon, and each organization can keep notes that are shared by all of its
+//! members. When a user looks at a taxon, the notes of their organizations are shown first,
+//! followed by their personal notes.
+use crate::{
+    error::{Error, Result},
+    organization::{Organization, Permission},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Sqlite};
+use time::OffsetDateTime;
+
+/// Who a set of cultivation notes (or a [display name](crate::taxonomy::names::DisplayName) of a
+/// taxon) belongs to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    /// the personal notes of a user
+    Personal(i64),
+    /// the notes that are shared by the members of an organization
+    Organization(i64),
+}
+
+/// The cultivation notes of a taxon as seen by a particular user
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct CultivationNotes {
+    #[sqlx(rename = "tnoteid")]
+    pub id: i64,
+    pub tsn: i64,
+    /// the user that the notes belong to, for personal notes
+    pub userid: Option<i64>,
+    /// the organization that the notes belong to, for shared notes
+    pub orgid: Option<i64>,
+    pub orgname: Option<String>,
+    #[sqlx(rename = "tnotetext")]
+    pub text: String,
+    #[sqlx(rename = "tnoteupdated")]
+    pub updated: OffsetDateTime,
+    /// the user that made the most recent change, if they still exist
+    #[sqlx(rename = "editorname")]
+    pub editor: Option<String>,
+}
+
+impl CultivationNotes {
+    /// Load all cultivation notes of the taxon that the user can see: the notes of the
+    /// organizations that the user is a member of (in the order of their names), followed by the
+    /// user's personal notes
+    pub async fn load_taxon(tsn: i64, userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            r#"SELECT * FROM vtaxonnotes WHERE tsn=? AND viewerid=?
+            ORDER BY tnotelayer, orgname"#,
+        )
+        .bind(tsn)
+        .bind(userid)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Load the notes of the taxon with the given scope, if there are any
+    pub async fn load_scope(tsn: i64, scope: Scope, pool: &Pool<Sqlite>) -> Result<Option<Self>> {
+        let query = match scope {
+            Scope::Personal(userid) => sqlx::query_as(
+                "SELECT * FROM vtaxonnotes WHERE tsn=? AND userid=? AND viewerid=userid",
+            )
+            .bind(tsn)
+            .bind(userid),
+            Scope::Organization(orgid) => {
+                sqlx::query_as("SELECT * FROM vtaxonnotes WHERE tsn=? AND orgid=? LIMIT 1")
+                    .bind(tsn)
+                    .bind(orgid)
+            }
+        };
+        query.fetch_optional(pool).await.map_err(Into::into)
+    }
+
+    /// Save the notes of the taxon with the given scope on behalf of the given user. The user must
+    /// be allowed to edit the objects of the organization to change its notes. Saving empty notes
+    /// removes them.
+    pub async fn save(
+        tsn: i64,
+        scope: Scope,
+        text: &str,
+        editor: i64,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Option<Self>> {
+        let (userid, orgid) = match scope {
+            Scope::Personal(userid) if userid == editor => (Some(userid), None),
+            Scope::Personal(_) => {
+                return Err(Error::InvalidOperation(
+                    "the personal notes of other users can't be changed".to_string(),
+                ))
+            }
+            Scope::Organization(orgid) => {
+                if !Organization::role(orgid, editor, pool)
+                    .await?
+                    .is_some_and(|role| role.permits(Permission::Edit))
+                {
+                    return Err(Error::InvalidOperation(
+                        "not allowed to change the notes of this organization".to_string(),
+                    ));
+                }
+                (None, Some(orgid))
+            }
+        };
+        let text = text.trim();
+        if text.is_empty() {
+            sqlx::query("DELETE FROM sc_taxon_notes WHERE tsn=? AND (userid=? OR orgid=?)")
+                .bind(tsn)
+                .bind(userid)
+                .bind(orgid)
+                .execute(pool)
+                .await?;
+            return Ok(None);
+        }
+        let conflict = match scope {
+            Scope::Personal(_) => "(tsn, userid) WHERE userid IS NOT NULL",
+            Scope::Organization(_) => "(tsn, orgid) WHERE orgid IS NOT NULL",
+        };
+        sqlx::query(&format!(
+            r#"INSERT INTO sc_taxon_notes (tsn, userid, orgid, tnotetext, tnoteeditor)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT{conflict} DO UPDATE SET tnotetext=excluded.tnotetext,
+                tnoteeditor=excluded.tnoteeditor, tnoteupdated=CURRENT_TIMESTAMP"#
+        ))
+        .bind(tsn)
+        .bind(userid)
+        .bind(orgid)
+        .bind(text)
+        .bind(editor)
+        .execute(pool)
+        .await?;
+        Self::load_scope(tsn, scope, pool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organization::OrgRole;
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users", "taxa"))
+    ))]
+    async fn cultivation_notes(pool: Pool<Sqlite>) {
+        let tsn = 40683;
+        assert!(CultivationNotes::load_taxon(tsn, 1, &pool)
+            .await
+            .unwrap()
+            .is_empty());
+        let personal = CultivationNotes::save(tsn, Scope::Personal(1), " Sow in fall ", 1, &pool)
+            .await
+            .expect("Failed to save notes")
+            .expect("Notes were not saved");
+        assert_eq!(personal.text, "Sow in fall");
+        assert_eq!(personal.editor.as_deref(), Some("testuser"));
+        // saving again replaces the notes
+        CultivationNotes::save(tsn, Scope::Personal(1), "Sow in late fall", 1, &pool)
+            .await
+            .unwrap();
+        // other users can't see or change them
+        assert!(CultivationNotes::load_taxon(tsn, 2, &pool)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(
+            CultivationNotes::save(tsn, Scope::Personal(1), "Mine now", 2, &pool)
+                .await
+                .is_err()
+        );
+
+        // organization notes are shared with its members and come first
+        let mut org = Organization::new("Prairie Group".to_string(), None);
+        org.insert(1, &pool).await.unwrap();
+        org.set_member(2, OrgRole::Viewer, &pool).await.unwrap();
+        assert!(CultivationNotes::save(
+            tsn,
+            Scope::Organization(org.id),
+            "Needs 60 days of cold stratification",
+            2,
+            &pool
+        )
+        .await
+        .is_err());
+        CultivationNotes::save(
+            tsn,
+            Scope::Organization(org.id),
+            "Needs 60 days of cold stratification",
+            1,
+            &pool,
+        )
+        .await
+        .unwrap();
+        let notes = CultivationNotes::load_taxon(tsn, 1, &pool).await.unwrap();
+        assert_eq!(
+            notes.iter().map(|n| n.text.as_str()).collect::<Vec<_>>(),
+            vec!["Needs 60 days of cold stratification", "Sow in late fall"]
+        );
+        assert_eq!(notes[0].orgname.as_deref(), Some("Prairie Group"));
+        let notes = CultivationNotes::load_taxon(tsn, 2, &pool).await.unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].orgid, Some(org.id));
+
+        // empty notes are removed
+        assert_eq!(
+            CultivationNotes::save(tsn, Scope::Personal(1), "  ", 1, &pool)
+                .await
+                .unwrap(),
+            None
+        );
+        let notes = CultivationNotes::load_taxon(tsn, 1, &pool).await.unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(notes[0].orgid, Some(org.id));
+    }
+}
@@ -0,0 +1,287 @@
+//! Data quality checks for a user's collection. Each check counts the objects that have a
+//! particular problem, e.g. samples whose quantity was never recorded, using a dedicated query. The
+//! objects themselves can be listed with the filter of the [`Issue`], so that they can be fixed.
+use crate::{
+    error::Result,
+    filter::{CompoundFilter, Op},
+    project::allocation::{self, Allocation},
+    sample::{self, Certainty, Sample},
+    source::{self, Source},
+    try_get_uuid,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, FromRow, Pool, QueryBuilder, Row, Sqlite};
+use strum::IntoEnumIterator;
+use strum_macros::{Display, EnumIter, EnumString};
+use time::{Date, Duration};
+use uuid::Uuid;
+
+/// The number of days after which an allocation without any new notes is considered inactive
+pub const INACTIVE_DAYS: i64 = 90;
+
+/// The date that an allocation must have a note on or after to be considered active as of `today`
+pub fn inactive_since(today: Date) -> Date {
+    today - Duration::days(INACTIVE_DAYS)
+}
+
+/// A problem with the data of a sample, source or allocation
+#[derive(Debug, Clone, Copy, PartialEq, Display, EnumString, EnumIter, Serialize, Deserialize)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum Issue {
+    /// samples whose quantity has not been recorded
+    MissingQuantity,
+    /// samples whose identification is uncertain
+    UncertainIdentification,
+    /// sources without coordinates
+    MissingLocation,
+    /// allocations without a note in the last [`INACTIVE_DAYS`] days
+    InactiveAllocation,
+}
+
+impl Issue {
+    /// A short description of the objects that have this issue
+    pub fn description(&self) -> &'static str {
+        match self {
+            Issue::MissingQuantity => "Samples without a quantity",
+            Issue::UncertainIdentification => "Samples with an uncertain identification",
+            Issue::MissingLocation => "Sources without coordinates",
+            Issue::InactiveAllocation => "Allocated samples without recent activity",
+        }
+    }
+
+    /// The filter for the samples that have this issue, if it is an issue of samples
+    pub fn sample_filter(&self) -> Option<sample::Filter> {
+        match self {
+            Issue::MissingQuantity => Some(sample::Filter::QuantityMissing),
+            Issue::UncertainIdentification => Some(sample::Filter::Certainty(Certainty::Uncertain)),
+            _ => None,
+        }
+    }
+
+    /// The filter for the sources that have this issue, if it is an issue of sources
+    pub fn source_filter(&self) -> Option<source::Filter> {
+        match self {
+            Issue::MissingLocation => Some(source::Filter::LocationMissing),
+            _ => None,
+        }
+    }
+
+    /// The filter for the allocations that have this issue as of `today`, if it is an issue of
+    /// allocations
+    pub fn allocation_filter(&self, today: Date) -> Option<allocation::Filter> {
+        match self {
+            Issue::InactiveAllocation => {
+                Some(allocation::Filter::InactiveSince(inactive_since(today)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Count the objects that the user has access to: the ones that have this issue and all of
+    /// the objects that were checked
+    async fn count(&self, userid: i64, today: Date, pool: &Pool<Sqlite>) -> Result<(i64, i64)> {
+        if let Some(filter) = self.sample_filter() {
+            let all = sample::Filter::Accessible(userid);
+            let count = Sample::count(
+                Some(
+                    CompoundFilter::builder(Op::And)
+                        .push(all.clone())
+                        .push(filter)
+                        .build(),
+                ),
+                pool,
+            )
+            .await?;
+            return Ok((count, Sample::count(Some(all.into()), pool).await?));
+        }
+        if let Some(filter) = self.source_filter() {
+            let all = source::Filter::Accessible(userid);
+            let count = Source::count(
+                Some(
+                    CompoundFilter::builder(Op::And)
+                        .push(all.clone())
+                        .push(filter)
+                        .build(),
+                ),
+                pool,
+            )
+            .await?;
+            return Ok((count, Source::count(Some(all.into()), pool).await?));
+        }
+        if let Some(filter) = self.allocation_filter(today) {
+            let all = allocation::Filter::Accessible(userid);
+            let count = Allocation::count(
+                Some(
+                    CompoundFilter::builder(Op::And)
+                        .push(all.clone())
+                        .push(filter)
+                        .build(),
+                ),
+                pool,
+            )
+            .await?;
+            return Ok((count, Allocation::count(Some(all.into()), pool).await?));
+        }
+        Ok((0, 0))
+    }
+}
+
+/// The result of a single check
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct IssueCount {
+    pub issue: Issue,
+    pub description: String,
+    /// the number of objects that have the issue
+    pub count: i64,
+    /// the number of objects that were checked
+    pub total: i64,
+}
+
+/// The number of inactive allocations in a single project
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct InactiveProject {
+    pub id: i64,
+    pub uuid: Uuid,
+    pub name: String,
+    pub count: i64,
+}
+
+impl FromRow<'_, SqliteRow> for InactiveProject {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            id: row.try_get("projectid")?,
+            uuid: try_get_uuid(row, "projuuid")?,
+            name: row.try_get("projname")?,
+            count: row.try_get("count")?,
+        })
+    }
+}
+
+/// The results of all data quality checks of a user's collection
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct QualityReport {
+    pub issues: Vec<IssueCount>,
+    /// the projects that have inactive allocations, in the order of their names
+    pub inactive_projects: Vec<InactiveProject>,
+}
+
+impl QualityReport {
+    /// Check the samples, sources and allocations that the user has access to. Allocations are
+    /// checked for activity as of `today`.
+    pub async fn load(userid: i64, today: Date, pool: &Pool<Sqlite>) -> Result<Self> {
+        let mut issues = Vec::new();
+        for issue in Issue::iter() {
+            let (count, total) = issue.count(userid, today, pool).await?;
+            issues.push(IssueCount {
+                issue,
+                description: issue.description().to_string(),
+                count,
+                total,
+            });
+        }
+        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            r#"SELECT P.projectid, P.projuuid, P.projname, COUNT(PS.psid) AS count
+            FROM sc_project_samples PS
+            INNER JOIN sc_projects P ON P.projectid=PS.projectid
+            LEFT JOIN sc_project_notes N ON N.pnoteid = (SELECT pnoteid FROM sc_project_notes
+                WHERE psid = PS.psid ORDER BY DATE(notedate) DESC, pnoteid DESC LIMIT 1)
+            WHERE "#,
+        );
+        CompoundFilter::builder(Op::And)
+            .push(allocation::Filter::Accessible(userid))
+            .push(allocation::Filter::InactiveSince(inactive_since(today)))
+            .build()
+            .add_to_query(&mut builder);
+        builder.push(" GROUP BY P.projectid ORDER BY P.projname");
+        let inactive_projects = builder.build_query_as().fetch_all(pool).await?;
+        Ok(Self {
+            issues,
+            inactive_projects,
+        })
+    }
+
+    /// An overall score from 0 to 100: the percentage of checked objects without any issues. A
+    /// collection without any objects has a perfect score.
+    pub fn score(&self) -> u32 {
+        let count: i64 = self.issues.iter().map(|i| i.count).sum();
+        let total: i64 = self.issues.iter().map(|i| i.total).sum();
+        if total == 0 {
+            return 100;
+        }
+        (100.0 * (total - count) as f64 / total as f64).round() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+    use time::macros::date;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn quality_report(pool: Pool<Sqlite>) {
+        let today = date!(2024 - 06 - 01);
+        let report = QualityReport::load(1, today, &pool).await.unwrap();
+        assert_eq!(report.issues.len(), Issue::iter().count());
+        for issue in &report.issues {
+            assert!(issue.count <= issue.total);
+        }
+
+        // every listed object has the issue and is counted
+        let samples = Sample::load_all_user(
+            1,
+            Issue::MissingQuantity.sample_filter().map(Into::into),
+            None,
+            &pool,
+        )
+        .await
+        .unwrap();
+        assert!(samples.iter().all(|s| s.quantity.is_none()));
+        assert_eq!(
+            report.issues[0],
+            IssueCount {
+                issue: Issue::MissingQuantity,
+                description: Issue::MissingQuantity.description().to_string(),
+                count: samples.len() as i64,
+                total: Sample::count(Some(sample::Filter::Accessible(1).into()), &pool)
+                    .await
+                    .unwrap(),
+            }
+        );
+
+        // fixing the issue improves the score
+        let before = report.score();
+        sqlx::query("UPDATE sc_samples SET quantity=10 WHERE quantity IS NULL AND userid=1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let report = QualityReport::load(1, today, &pool).await.unwrap();
+        assert_eq!(report.issues[0].count, 0);
+        assert!(report.score() >= before);
+
+        // allocations are inactive until they get a recent note
+        let inactive: i64 = report.inactive_projects.iter().map(|p| p.count).sum();
+        let allocations = report
+            .issues
+            .iter()
+            .find(|i| i.issue == Issue::InactiveAllocation)
+            .unwrap();
+        assert_eq!(inactive, allocations.count);
+        let later = QualityReport::load(1, date!(2100 - 01 - 01), &pool)
+            .await
+            .unwrap();
+        let all = later
+            .issues
+            .iter()
+            .find(|i| i.issue == Issue::InactiveAllocation)
+            .unwrap();
+        assert_eq!(all.count, all.total);
+    }
+}
@@ -0,0 +1,312 @@
+//! Finding and merging duplicate sources. Over the years it is easy to end up with several sources
+//! for the same site, e.g. "Smith Prairie" and "Smith prairie west". Two sources are considered
+//! possible duplicates when their names are similar and they are close to each other, or when they
+//! are at practically the same location. Merging moves everything that refers to the removed
+//! sources to the source that is kept.
+use crate::{
+    error::{Error, Result},
+    filter::DynFilterPart,
+    loadable::Loadable,
+    source::{distance_km, Source},
+};
+use serde::Serialize;
+use sqlx::{Pool, Sqlite};
+use std::collections::{HashMap, HashSet};
+
+/// The similarity from 0 to 1 above which two names are considered to be similar
+const NAME_THRESHOLD: f64 = 0.5;
+
+/// Sources with similar names are only considered duplicates if they are at most this far apart
+const NEARBY_KM: f64 = 5.0;
+
+/// Sources that are at most this far apart are considered duplicates regardless of their names
+const SAME_SITE_KM: f64 = 0.1;
+
+/// A pair of sources that may be duplicates of each other
+#[derive(Clone, Debug, Serialize)]
+pub struct Candidate {
+    pub first: Source,
+    pub second: Source,
+    /// the similarity of the names of the sources, from 0 to 1
+    pub name_similarity: f64,
+    /// the distance between the sources, if both have coordinates
+    pub distance_km: Option<f64>,
+}
+
+/// What a merge would change
+#[derive(Clone, Debug, Serialize)]
+pub struct MergePreview {
+    pub keep: Source,
+    pub remove: Vec<Source>,
+    /// the number of samples that would be moved to the source that is kept
+    pub samples: i64,
+    /// the number of trips that would visit the source that is kept instead
+    pub trips: i64,
+}
+
+/// The similarity of two names as the Dice coefficient of their character bigrams, ignoring case
+/// and punctuation
+pub fn name_similarity(a: &str, b: &str) -> f64 {
+    fn bigrams(s: &str) -> Vec<(char, char)> {
+        let normalized: Vec<char> = s
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .collect();
+        normalized.windows(2).map(|w| (w[0], w[1])).collect()
+    }
+    let (a, b) = (bigrams(a), bigrams(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    let mut counts: HashMap<(char, char), usize> = HashMap::new();
+    for bigram in &a {
+        *counts.entry(*bigram).or_default() += 1;
+    }
+    let mut shared = 0;
+    for bigram in &b {
+        if let Some(n) = counts.get_mut(bigram) {
+            if *n > 0 {
+                *n -= 1;
+                shared += 1;
+            }
+        }
+    }
+    2.0 * shared as f64 / (a.len() + b.len()) as f64
+}
+
+/// Find the pairs of sources matching `filter` that may be duplicates, with the most similar names
+/// first
+pub async fn find_candidates(
+    filter: Option<DynFilterPart>,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<Candidate>> {
+    let sources = Source::load_all(filter, pool).await?;
+    let mut candidates = Vec::new();
+    for (i, first) in sources.iter().enumerate() {
+        for second in &sources[i + 1..] {
+            let distance = match (
+                first.latitude,
+                first.longitude,
+                second.latitude,
+                second.longitude,
+            ) {
+                (Some(lat1), Some(lon1), Some(lat2), Some(lon2)) => {
+                    Some(distance_km(lat1, lon1, lat2, lon2))
+                }
+                _ => None,
+            };
+            let similarity = name_similarity(&first.name, &second.name);
+            let similar = similarity >= NAME_THRESHOLD && distance.is_none_or(|d| d <= NEARBY_KM);
+            if similar || distance.is_some_and(|d| d <= SAME_SITE_KM) {
+                candidates.push(Candidate {
+                    first: first.clone(),
+                    second: second.clone(),
+                    name_similarity: similarity,
+                    distance_km: distance,
+                });
+            }
+        }
+    }
+    candidates.sort_by(|a, b| b.name_similarity.total_cmp(&a.name_similarity));
+    Ok(candidates)
+}
+
+fn check_merge(keep: i64, remove: &[i64]) -> Result<()> {
+    if remove.is_empty() {
+        return Err(Error::InvalidValue(
+            "no sources were given to merge".to_string(),
+        ));
+    }
+    if remove.contains(&keep) {
+        return Err(Error::InvalidValue(
+            "a source can't be merged into itself".to_string(),
+        ));
+    }
+    if remove.iter().collect::<HashSet<_>>().len() != remove.len() {
+        return Err(Error::InvalidValue(
+            "a source was given more than once".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn id_list(ids: &[i64]) -> String {
+    ids.iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Preview merging the sources in `remove` into the source `keep`
+pub async fn preview_merge(keep: i64, remove: &[i64], pool: &Pool<Sqlite>) -> Result<MergePreview> {
+    check_merge(keep, remove)?;
+    let keep = Source::load(keep, pool).await?;
+    let mut sources = Vec::new();
+    for id in remove {
+        sources.push(Source::load(*id, pool).await?);
+    }
+    let ids = id_list(remove);
+    let samples = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM sc_samples WHERE srcid IN ({ids})"
+    ))
+    .fetch_one(pool)
+    .await?;
+    let trips = sqlx::query_scalar(&format!(
+        r#"SELECT COUNT(DISTINCT tripid) FROM sc_trip_sources WHERE srcid IN ({ids})
+        AND tripid NOT IN (SELECT tripid FROM sc_trip_sources WHERE srcid=?)"#
+    ))
+    .bind(keep.id)
+    .fetch_one(pool)
+    .await?;
+    Ok(MergePreview {
+        keep,
+        remove: sources,
+        samples,
+        trips,
+    })
+}
+
+/// Merge the sources in `remove` into the source `keep` in a single transaction: their samples,
+/// trip visits and the default sources of users are moved to `keep`, and then they are deleted.
+/// Returns the number of samples that were moved.
+pub async fn merge(keep: i64, remove: &[i64], pool: &Pool<Sqlite>) -> Result<i64> {
+    check_merge(keep, remove)?;
+    let mut tx = pool.begin().await?;
+    let exists: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM sc_sources WHERE srcid IN ({},{keep})",
+        id_list(remove)
+    ))
+    .fetch_one(&mut *tx)
+    .await?;
+    if exists != remove.len() as i64 + 1 {
+        return Err(Error::InvalidValue(
+            "some of the sources to merge don't exist".to_string(),
+        ));
+    }
+    let mut moved = 0;
+    for id in remove {
+        moved += sqlx::query("UPDATE sc_samples SET srcid=? WHERE srcid=?")
+            .bind(keep)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected() as i64;
+        sqlx::query(
+            r#"INSERT OR IGNORE INTO sc_trip_sources (tripid, srcid)
+            SELECT tripid, ? FROM sc_trip_sources WHERE srcid=?"#,
+        )
+        .bind(keep)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("UPDATE sc_user_prefs SET defaultsource=? WHERE defaultsource=?")
+            .bind(keep)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM sc_sources WHERE srcid=?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    #[test]
+    fn test_name_similarity() {
+        assert_eq!(name_similarity("Smith Prairie", "smith prairie"), 1.0);
+        assert_eq!(name_similarity("Smith Prairie", "Smith-Prairie!"), 1.0);
+        assert!(name_similarity("Smith Prairie", "Smith prairie west") > 0.7);
+        assert!(name_similarity("Smith Prairie", "Jones Woods") < 0.2);
+        assert_eq!(name_similarity("", "Jones Woods"), 0.0);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn test_merge(pool: Pool<Sqlite>) {
+        let mut west = Source::new(
+            "Test source 1 west".to_string(),
+            None,
+            Some(40.13),
+            Some(-90.13),
+            1,
+        );
+        west.insert(&pool).await.unwrap();
+        // the same location, but a different name
+        let mut same = Source::new(
+            "Parking lot".to_string(),
+            None,
+            Some(40.123),
+            Some(-90.123),
+            1,
+        );
+        same.insert(&pool).await.unwrap();
+        // a similar name, but far away
+        let mut far = Source::new(
+            "Test source 1 east".to_string(),
+            None,
+            Some(0.0),
+            Some(0.0),
+            1,
+        );
+        far.insert(&pool).await.unwrap();
+
+        let candidates = find_candidates(None, &pool).await.unwrap();
+        let pairs: Vec<(i64, i64)> = candidates
+            .iter()
+            .map(|c| (c.first.id.min(c.second.id), c.first.id.max(c.second.id)))
+            .collect();
+        assert!(pairs.contains(&(1, west.id)));
+        assert!(pairs.contains(&(1, same.id)));
+        assert!(!pairs.contains(&(1, far.id)));
+        assert!(!pairs.contains(&(1, 2)));
+
+        sqlx::query("UPDATE sc_samples SET srcid=? WHERE sampleid=1")
+            .bind(west.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let preview = preview_merge(1, &[west.id, same.id], &pool).await.unwrap();
+        assert_eq!(preview.keep.id, 1);
+        assert_eq!(preview.remove.len(), 2);
+        assert_eq!(preview.samples, 1);
+
+        assert!(merge(1, &[1], &pool).await.is_err());
+        assert!(merge(1, &[], &pool).await.is_err());
+        assert!(merge(1, &[west.id, 999], &pool).await.is_err());
+        // nothing was changed by the failed merge
+        assert!(Source::load(west.id, &pool).await.is_ok());
+
+        let before = Source::load(1, &pool)
+            .await
+            .unwrap()
+            .count_samples(&pool)
+            .await
+            .unwrap();
+        assert_eq!(merge(1, &[west.id, same.id], &pool).await.unwrap(), 1);
+        assert!(Source::load(west.id, &pool).await.is_err());
+        assert!(Source::load(same.id, &pool).await.is_err());
+        let after = Source::load(1, &pool)
+            .await
+            .unwrap()
+            .count_samples(&pool)
+            .await
+            .unwrap();
+        assert_eq!(after, before + 1);
+    }
+}
@@ -0,0 +1,228 @@
+//! Deleting several samples or sources at once. Before anything is deleted, the user can be shown
+//! a [DeleteImpact] that summarizes what else would be removed along with the selected objects.
+//! The objects are then deleted in a single transaction, so that either every object that could
+//! be deleted is removed or nothing is.
+use crate::{
+    error::{Error, Result},
+    source::{self, OnDelete},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, QueryBuilder, Sqlite, SqliteConnection};
+
+/// What would be removed or left behind when a set of samples and sources is deleted
+#[derive(FromRow, Debug, Default, Deserialize, Serialize, PartialEq, Clone)]
+pub struct DeleteImpact {
+    pub sources: i64,
+    /// the selected samples along with the samples that were collected at the selected sources
+    pub samples: i64,
+    /// the allocations of those samples to projects, which are removed with them
+    pub allocations: i64,
+    /// the notes of the removed allocations
+    pub notes: i64,
+    /// the projects that would have no samples left
+    pub emptied_projects: i64,
+}
+
+fn push_ids(builder: &mut QueryBuilder<'_, Sqlite>, ids: &[i64]) {
+    builder.push("(");
+    let mut separated = builder.separated(", ");
+    for id in ids {
+        separated.push_bind(*id);
+    }
+    builder.push(")");
+}
+
+impl DeleteImpact {
+    /// Summarize what would be removed when the given samples and sources are deleted, assuming
+    /// that the samples of the sources are deleted with them
+    pub async fn load(sampleids: &[i64], sourceids: &[i64], pool: &Pool<Sqlite>) -> Result<Self> {
+        let mut builder =
+            QueryBuilder::new("WITH D AS (SELECT sampleid FROM sc_samples WHERE sampleid IN ");
+        push_ids(&mut builder, sampleids);
+        builder.push(" OR srcid IN ");
+        push_ids(&mut builder, sourceids);
+        builder.push(
+            r#"), A AS (SELECT psid, projectid FROM sc_project_samples
+                WHERE sampleid IN (SELECT sampleid FROM D))
+            SELECT (SELECT COUNT(*) FROM sc_sources WHERE srcid IN "#,
+        );
+        push_ids(&mut builder, sourceids);
+        builder.push(
+            r#") AS sources,
+            (SELECT COUNT(*) FROM D) AS samples,
+            (SELECT COUNT(*) FROM A) AS allocations,
+            (SELECT COUNT(*) FROM sc_project_notes WHERE psid IN (SELECT psid FROM A)) AS notes,
+            (SELECT COUNT(DISTINCT projectid) FROM A WHERE NOT EXISTS
+                (SELECT 1 FROM sc_project_samples PS WHERE PS.projectid=A.projectid
+                AND PS.sampleid NOT IN (SELECT sampleid FROM D))) AS emptied_projects"#,
+        );
+        builder
+            .build_query_as()
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// The outcome of deleting a single object of a bulk deletion
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct DeleteResult {
+    pub id: i64,
+    pub deleted: bool,
+    /// why the object was not deleted
+    pub reason: Option<String>,
+}
+
+impl DeleteResult {
+    fn deleted(id: i64) -> Self {
+        Self {
+            id,
+            deleted: true,
+            reason: None,
+        }
+    }
+
+    /// A result for an object that was skipped for the given reason
+    pub fn skipped(id: i64, reason: impl Into<String>) -> Self {
+        Self {
+            id,
+            deleted: false,
+            reason: Some(reason.into()),
+        }
+    }
+}
+
+/// Delete a sample along with its allocations to projects and their notes
+async fn delete_sample_in(id: i64, tx: &mut SqliteConnection) -> Result<u64> {
+    sqlx::query(
+        r#"DELETE FROM sc_project_notes WHERE psid IN
+            (SELECT psid FROM sc_project_samples WHERE sampleid=?1);
+        DELETE FROM sc_project_samples WHERE sampleid=?1"#,
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+    Ok(sqlx::query("DELETE FROM sc_samples WHERE sampleid=?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected())
+}
+
+/// Delete the given samples in a single transaction, removing them from all projects. Samples that
+/// don't exist are reported as skipped.
+pub async fn delete_samples(ids: &[i64], pool: &Pool<Sqlite>) -> Result<Vec<DeleteResult>> {
+    let mut tx = pool.begin().await?;
+    let mut results = Vec::new();
+    for id in ids {
+        results.push(match delete_sample_in(*id, &mut tx).await? {
+            0 => DeleteResult::skipped(*id, "The sample does not exist"),
+            _ => DeleteResult::deleted(*id),
+        });
+    }
+    tx.commit().await?;
+    Ok(results)
+}
+
+/// Delete the given sources in a single transaction. Unless their samples are deleted with them,
+/// the sources that still have samples are skipped.
+pub async fn delete_sources(
+    ids: &[i64],
+    delete_samples: bool,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<DeleteResult>> {
+    let on_delete = match delete_samples {
+        true => OnDelete::Cascade,
+        false => OnDelete::Restrict,
+    };
+    let mut tx = pool.begin().await?;
+    let mut results = Vec::new();
+    for id in ids {
+        results.push(
+            match source::delete_source_in(*id, on_delete, &mut tx).await {
+                Ok(res) if res.rows_affected() == 0 => {
+                    DeleteResult::skipped(*id, "The source does not exist")
+                }
+                Ok(_) => DeleteResult::deleted(*id),
+                Err(Error::InvalidOperationObjectInUse(n)) => DeleteResult::skipped(
+                    *id,
+                    format!(
+                        "The source still has {n} sample{}",
+                        if n == 1 { "" } else { "s" }
+                    ),
+                ),
+                Err(e) => return Err(e),
+            },
+        );
+    }
+    tx.commit().await?;
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{loadable::Loadable, sample::Sample, source::Source};
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "assigned-samples")
+        )
+    ))]
+    async fn bulk_delete(pool: Pool<Sqlite>) {
+        let allocated: Vec<i64> =
+            sqlx::query_scalar("SELECT DISTINCT sampleid FROM sc_project_samples ORDER BY 1")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        let sample = allocated[0];
+        let nallocs: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM sc_project_samples WHERE sampleid=?")
+                .bind(sample)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let impact = DeleteImpact::load(&[sample], &[], &pool).await.unwrap();
+        assert_eq!(impact.sources, 0);
+        assert_eq!(impact.samples, 1);
+        assert_eq!(impact.allocations, nallocs);
+        assert_eq!(
+            DeleteImpact::load(&[], &[], &pool).await.unwrap(),
+            DeleteImpact::default()
+        );
+
+        let results = delete_samples(&[sample, 9999], &pool).await.unwrap();
+        assert!(results[0].deleted);
+        assert!(!results[1].deleted);
+        assert!(Sample::load(sample, &pool).await.is_err());
+        let remaining: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM sc_project_samples WHERE sampleid=?")
+                .bind(sample)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(remaining, 0);
+
+        // sources with samples are only deleted along with their samples
+        let srcid = Sample::load(allocated[1], &pool).await.unwrap().source.id();
+        let impact = DeleteImpact::load(&[], &[srcid], &pool).await.unwrap();
+        assert_eq!(impact.sources, 1);
+        assert!(impact.samples > 0);
+        let results = delete_sources(&[srcid], false, &pool).await.unwrap();
+        assert!(!results[0].deleted);
+        assert!(results[0].reason.as_deref().unwrap().contains("still has"));
+        assert!(Source::load(srcid, &pool).await.is_ok());
+        let results = delete_sources(&[srcid], true, &pool).await.unwrap();
+        assert!(results[0].deleted);
+        assert!(Source::load(srcid, &pool).await.is_err());
+        let nsamples: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sc_samples WHERE srcid=?")
+            .bind(srcid)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(nsamples, 0);
+    }
+}
@@ -0,0 +1,249 @@
+//! An example dataset for a public demo account. The demo account is read-only, but its data is
+//! replaced with the example data from time to time anyway, in case it was modified by other means.
+use crate::{
+    error::{Error, Result},
+    loadable::ExternalRef,
+    project::Project,
+    sample::{Certainty, Sample},
+    source::Source,
+    trip::Trip,
+    user::{User, UserStatus},
+};
+use sqlx::{Pool, Sqlite};
+use time::macros::date;
+use tracing::debug;
+
+/// (name, description, latitude, longitude)
+const SOURCES: &[(&str, &str, f64, f64)] = &[
+    (
+        "Oak savanna remnant",
+        "A small savanna remnant on a sandy ridge",
+        45.402,
+        -93.215,
+    ),
+    (
+        "Roadside prairie",
+        "Railroad right-of-way with a diverse mesic prairie",
+        44.213,
+        -95.902,
+    ),
+    (
+        "Riverside woods",
+        "Floodplain forest and woodland edge along the river",
+        44.917,
+        -92.784,
+    ),
+];
+
+/// (taxon name, index into [SOURCES], month, year, quantity)
+const SAMPLES: &[(&str, usize, u32, u32, i64)] = &[
+    ("Elymus canadensis", 2, 9, 2023, 400),
+    ("Sisyrinchium campestre", 1, 7, 2023, 150),
+    ("Andropogon gerardii", 1, 10, 2023, 2000),
+    ("Schizachyrium scoparium", 0, 10, 2023, 1500),
+    ("Echinacea angustifolia", 1, 9, 2022, 300),
+    ("Asclepias tuberosa", 0, 9, 2023, 120),
+    ("Monarda fistulosa", 1, 9, 2022, 800),
+    ("Zizia aurea", 2, 8, 2023, 250),
+    ("Rudbeckia hirta", 0, 8, 2022, 1000),
+    ("Liatris aspera", 0, 9, 2023, 200),
+    ("Dalea purpurea", 1, 9, 2023, 600),
+    ("Solidago speciosa", 0, 10, 2022, 900),
+];
+
+/// Make sure that the demo account exists. The account has no usable password, so it is only
+/// possible to log in to it without a password through the guest login.
+pub async fn ensure_user(username: &str, pool: &Pool<Sqlite>) -> Result<User> {
+    if let Some(user) = User::load_by_username(username, pool).await? {
+        return Ok(user);
+    }
+    let mut user = User::new(
+        username.to_string(),
+        format!("{username}@demo.invalid"),
+        "!".to_string(),
+        UserStatus::Verified,
+        None,
+        Some("Demo Account".to_string()),
+        None,
+    );
+    user.insert(pool).await?;
+    Ok(user)
+}
+
+/// Remove all of the demo user's data
+async fn clear(userid: i64, pool: &Pool<Sqlite>) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    let allocations = r#"SELECT psid FROM sc_project_samples
+        WHERE projectid IN (SELECT projectid FROM sc_projects WHERE userid=?)
+        OR sampleid IN (SELECT sampleid FROM sc_samples WHERE userid=?)"#;
+    sqlx::query(&format!(
+        "DELETE FROM sc_project_notes WHERE psid IN ({allocations})"
+    ))
+    .bind(userid)
+    .bind(userid)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(&format!(
+        "DELETE FROM sc_project_samples WHERE psid IN ({allocations})"
+    ))
+    .bind(userid)
+    .bind(userid)
+    .execute(&mut *tx)
+    .await?;
+    for table in [
+        "sc_mailin_keys",
+        "sc_monitoring_schedules",
+        "sc_reminders",
+        "sc_reports",
+        "sc_source_visits",
+        "sc_projects",
+        "sc_trips",
+        "sc_user_prefs",
+        "sc_permits",
+        "sc_taxon_notes",
+        "sc_taxon_names",
+        "sc_stock_alerts",
+        "sc_stock_thresholds",
+        "sc_user_tokens",
+        "sc_samples",
+        "sc_sources",
+    ] {
+        sqlx::query(&format!("DELETE FROM {table} WHERE userid=?"))
+            .bind(userid)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Replace all of the demo user's data with the example dataset. Example samples of taxa that are
+/// not in the taxonomy database are skipped. Returns the number of samples that were added.
+pub async fn reset(user: &User, pool: &Pool<Sqlite>) -> Result<usize> {
+    if user.id < 0 {
+        return Err(Error::InvalidStateMissingAttribute("id".to_string()));
+    }
+    clear(user.id, pool).await?;
+
+    let mut sources = Vec::new();
+    for (name, description, latitude, longitude) in SOURCES {
+        let mut source = Source::new(
+            name.to_string(),
+            Some(description.to_string()),
+            Some(*latitude),
+            Some(*longitude),
+            user.id,
+        );
+        source.insert(pool).await?;
+        sources.push(source.id);
+    }
+
+    let mut project = Project::new(
+        "Pollinator garden".to_string(),
+        Some("Seeds set aside for a backyard pollinator planting".to_string()),
+        user.id,
+    );
+    project.insert(pool).await?;
+    let mut trip = Trip::new(
+        "Fall prairie collection".to_string(),
+        date!(2023 - 09 - 16),
+        user.id,
+    );
+    trip.participants = vec!["Alex".to_string(), "Sam".to_string()];
+    trip.insert(pool).await?;
+    trip.add_target_source(sources[1], pool).await?;
+
+    let mut added = 0;
+    for (name, source, month, year, quantity) in SAMPLES {
+        let tsn: Option<i64> = sqlx::query_scalar(
+            "SELECT tsn FROM taxonomic_units WHERE complete_name=? AND name_usage='accepted'",
+        )
+        .bind(name)
+        .fetch_optional(pool)
+        .await?;
+        let Some(tsn) = tsn else {
+            debug!(name, "Skipping demo sample of unknown taxon");
+            continue;
+        };
+        let mut sample = Sample::new(
+            tsn,
+            user.id,
+            sources[*source],
+            Some(*month),
+            Some(*year),
+            Some(*quantity),
+            None,
+            Certainty::Certain,
+        );
+        if *year == 2023 && *month >= 9 && *source == 1 {
+            sample.trip = Some(trip.id);
+        }
+        sample.insert(pool).await?;
+        if *source == 1 {
+            trip.add_target_taxon(tsn, pool).await?;
+        }
+        if added % 3 == 0 {
+            project
+                .allocate_sample(ExternalRef::Stub(sample.id), pool)
+                .await?;
+        }
+        added += 1;
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filter::Cmp, loadable::Loadable, sample::Filter};
+    use std::sync::Arc;
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users", "taxa"))
+    ))]
+    async fn test_reset(pool: Pool<Sqlite>) {
+        let user = ensure_user("demo", &pool)
+            .await
+            .expect("Failed to create demo user");
+        assert_eq!(
+            ensure_user("demo", &pool).await.unwrap().id,
+            user.id,
+            "the user is only created once"
+        );
+        assert!(user.verify_password("").is_err());
+
+        // only the taxa that are in the fixtures can be added
+        assert_eq!(reset(&user, &pool).await.expect("Failed to reset"), 2);
+        let mut sample = Sample::load_all_user(user.id, None, None, &pool)
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        sample.notes = Some("modified".to_string());
+        sample.update(&pool).await.unwrap();
+
+        // resetting again replaces the modified data instead of adding to it
+        assert_eq!(reset(&user, &pool).await.expect("Failed to reset"), 2);
+        let samples = Sample::load_all_user(user.id, None, None, &pool)
+            .await
+            .unwrap();
+        assert_eq!(samples.len(), 2);
+        assert!(Sample::load_all_user(
+            user.id,
+            Some(Arc::new(Filter::Notes(Cmp::Like, "modified".to_string()))),
+            None,
+            &pool
+        )
+        .await
+        .unwrap()
+        .is_empty());
+        assert_eq!(
+            Source::load_all_user(user.id, &pool).await.unwrap().len(),
+            SOURCES.len()
+        );
+        // other users' data is untouched
+        assert!(User::load(1, &pool).await.is_ok());
+    }
+}
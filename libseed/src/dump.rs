@@ -0,0 +1,1237 @@
+//! A complete dump of the user data in a database, which can be restored into a different database.
+//! Taxonomic data is not part of the dump, since it is the same for every database.
+//!
+//! The numeric IDs of records differ between databases, so records in a dump are identified by
+//! UUIDs and refer to each other by UUID. Samples, sources, projects, allocations and notes have a
+//! UUID of their own. Other records are assigned a UUID when they are first exported or imported,
+//! which is remembered so that exporting a record again produces the same UUID. Either way,
+//! importing the same dump twice doesn't duplicate anything.
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Row, Sqlite, SqliteConnection};
+use uuid::Uuid;
+
+/// The version of the dump format written by [Dump::export]
+pub const FORMAT_VERSION: u32 = 1;
+
+/// A table whose records are identified by UUID, as (table name, ID column, UUID column). The
+/// UUIDs of tables without a UUID column are kept in a separate table.
+type Table = (&'static str, &'static str, Option<&'static str>);
+
+const USERS: Table = ("sc_users", "userid", None);
+const ORGANIZATIONS: Table = ("sc_organizations", "orgid", None);
+const SOURCES: Table = ("sc_sources", "srcid", Some("srcuuid"));
+const TRIPS: Table = ("sc_trips", "tripid", None);
+const SAMPLES: Table = ("sc_samples", "sampleid", Some("sampleuuid"));
+const PROJECTS: Table = ("sc_projects", "projectid", Some("projuuid"));
+const ALLOCATIONS: Table = ("sc_project_samples", "psid", Some("psuuid"));
+const NOTES: Table = ("sc_project_notes", "pnoteid", Some("pnoteuuid"));
+const PERMITS: Table = ("sc_permits", "permitid", None);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dump {
+    pub format: u32,
+    pub users: Vec<UserRecord>,
+    pub organizations: Vec<OrganizationRecord>,
+    pub sources: Vec<SourceRecord>,
+    pub trips: Vec<TripRecord>,
+    pub samples: Vec<SampleRecord>,
+    pub projects: Vec<ProjectRecord>,
+    pub permits: Vec<PermitRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserRecord {
+    pub uuid: Uuid,
+    pub username: String,
+    pub email: String,
+    pub pwhash: String,
+    pub status: i64,
+    pub since: Option<String>,
+    pub display_name: Option<String>,
+    pub profile: Option<String>,
+    pub timezone: Option<String>,
+    pub preferences: Option<PreferencesRecord>,
+    /// the user's quality grading scale
+    #[serde(default)]
+    pub grades: Vec<GradeRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PreferencesRecord {
+    pub default_source: Option<Uuid>,
+    pub default_certainty: i64,
+    pub default_date_current: bool,
+    pub year_start_month: i64,
+    pub region: Option<String>,
+    pub permit_policy: i64,
+}
+
+/// A grade of a user's grading scale, which samples refer to by its label
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GradeRecord {
+    pub label: String,
+    pub rank: i64,
+    pub criteria: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrganizationRecord {
+    pub uuid: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub created: String,
+    pub members: Vec<MemberRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemberRecord {
+    pub user: Uuid,
+    pub role: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceRecord {
+    pub uuid: Uuid,
+    pub user: Uuid,
+    pub organization: Option<Uuid>,
+    pub name: String,
+    pub description: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub version: i64,
+    /// the habitat attributes were added after the first version of the format, so they may be
+    /// missing from older dumps
+    #[serde(default)]
+    pub habitat: Option<i64>,
+    #[serde(default)]
+    pub moisture: Option<i64>,
+    #[serde(default)]
+    pub light: Option<i64>,
+    /// a dump is a complete backup, so it contains the exact coordinates of sensitive sources
+    #[serde(default)]
+    pub sensitive: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TripRecord {
+    pub uuid: Uuid,
+    pub user: Uuid,
+    pub name: String,
+    pub date: String,
+    pub notes: Option<String>,
+    pub version: i64,
+    pub participants: Vec<String>,
+    pub target_sources: Vec<Uuid>,
+    pub target_taxa: Vec<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SampleRecord {
+    pub uuid: Uuid,
+    pub user: Uuid,
+    pub source: Uuid,
+    pub organization: Option<Uuid>,
+    pub trip: Option<Uuid>,
+    pub tsn: i64,
+    pub certainty: Option<i64>,
+    pub month: Option<i64>,
+    pub year: Option<i64>,
+    pub quantity: Option<i64>,
+    pub notes: Option<String>,
+    pub version: i64,
+    pub purchase_vendor: Option<String>,
+    pub purchase_lot: Option<String>,
+    pub purchase_date: Option<String>,
+    pub purchase_price: Option<f64>,
+    pub purchase_origin: Option<String>,
+    pub flags: Vec<FlagRecord>,
+    #[serde(default)]
+    pub quality_tests: Vec<QualityTestRecord>,
+    pub label: Option<LabelRecord>,
+    /// the label of the sample's grade in the grading scale of its owner
+    #[serde(default)]
+    pub grade: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlagRecord {
+    pub reason: String,
+    pub created: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QualityTestRecord {
+    pub date: String,
+    pub fill: Option<f64>,
+    pub method: Option<i64>,
+    pub purity: Option<f64>,
+    pub tester: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LabelRecord {
+    pub queued: String,
+    pub printed: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectRecord {
+    pub uuid: Uuid,
+    pub user: Uuid,
+    pub organization: Option<Uuid>,
+    pub name: String,
+    pub description: Option<String>,
+    pub version: i64,
+    pub allocations: Vec<AllocationRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AllocationRecord {
+    pub uuid: Uuid,
+    pub sample: Uuid,
+    pub notes: Vec<NoteRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NoteRecord {
+    pub uuid: Uuid,
+    pub date: String,
+    pub kind: i64,
+    pub summary: String,
+    pub details: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PermitRecord {
+    pub uuid: Uuid,
+    pub user: Uuid,
+    pub tsn: i64,
+    pub region: String,
+    pub number: String,
+    pub expires: Option<String>,
+    pub notes: Option<String>,
+}
+
+/// The number of records handled by [Dump::import]. Only records that are identified by a UUID are
+/// counted, not e.g. sample flags or trip participants.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct ImportStats {
+    /// records that were added to the database
+    pub inserted: usize,
+    /// records that already existed in the database and were left unchanged
+    pub existing: usize,
+}
+
+/// Get the UUID of a record, assigning a new one if the record doesn't have one yet
+async fn uuid_of(table: Table, id: i64, conn: &mut SqliteConnection) -> Result<Uuid> {
+    let existing: Option<String> = match table {
+        (name, idcol, Some(uuidcol)) => {
+            sqlx::query_scalar(&format!("SELECT {uuidcol} FROM {name} WHERE {idcol}=?"))
+                .bind(id)
+                .fetch_optional(&mut *conn)
+                .await?
+        }
+        (name, _, None) => {
+            sqlx::query_scalar("SELECT uuid FROM sc_uuids WHERE tablename=? AND localid=?")
+                .bind(name)
+                .bind(id)
+                .fetch_optional(&mut *conn)
+                .await?
+        }
+    };
+    match existing {
+        Some(uuid) => Uuid::parse_str(&uuid)
+            .map_err(|_| Error::InvalidValue(format!("'{uuid}' is not a valid UUID"))),
+        None => {
+            let uuid = Uuid::new_v4();
+            remember(table, id, uuid, conn).await?;
+            Ok(uuid)
+        }
+    }
+}
+
+async fn optional_uuid_of(
+    table: Table,
+    id: Option<i64>,
+    conn: &mut SqliteConnection,
+) -> Result<Option<Uuid>> {
+    match id {
+        Some(id) => Ok(Some(uuid_of(table, id, conn).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Record the UUID of a record of a table without a UUID column. This replaces any stale mapping of
+/// the same UUID to a record that has since been removed.
+async fn remember(table: Table, id: i64, uuid: Uuid, conn: &mut SqliteConnection) -> Result<()> {
+    sqlx::query("INSERT OR REPLACE INTO sc_uuids (tablename, localid, uuid) VALUES (?, ?, ?)")
+        .bind(table.0)
+        .bind(id)
+        .bind(uuid.to_string())
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+/// Find the ID of the record with the given UUID, if it exists in this database
+async fn local_id(table: Table, uuid: Uuid, conn: &mut SqliteConnection) -> Result<Option<i64>> {
+    let sql = match table {
+        (name, idcol, Some(uuidcol)) => format!("SELECT {idcol} FROM {name} WHERE {uuidcol}=?"),
+        (name, idcol, None) => format!(
+            "SELECT U.localid FROM sc_uuids U INNER JOIN {name} T ON T.{idcol}=U.localid
+            WHERE U.tablename='{name}' AND U.uuid=?"
+        ),
+    };
+    sqlx::query_scalar(&sql)
+        .bind(uuid.to_string())
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Find the ID of a record that is referenced by another record in the dump
+async fn require_id(table: Table, uuid: Uuid, conn: &mut SqliteConnection) -> Result<i64> {
+    local_id(table, uuid, conn).await?.ok_or_else(|| {
+        Error::InvalidValue(format!("reference to unknown record {uuid} in {}", table.0))
+    })
+}
+
+async fn optional_id(
+    table: Table,
+    uuid: Option<Uuid>,
+    conn: &mut SqliteConnection,
+) -> Result<Option<i64>> {
+    match uuid {
+        Some(uuid) => Ok(Some(require_id(table, uuid, conn).await?)),
+        None => Ok(None),
+    }
+}
+
+impl Dump {
+    /// Dump all of the user data in the database
+    pub async fn export(pool: &Pool<Sqlite>) -> Result<Self> {
+        // assigning UUIDs to records writes to the database
+        let mut tx = pool.begin().await?;
+        let conn = &mut *tx;
+        let dump = Self {
+            format: FORMAT_VERSION,
+            users: export_users(conn).await?,
+            organizations: export_organizations(conn).await?,
+            sources: export_sources(conn).await?,
+            trips: export_trips(conn).await?,
+            samples: export_samples(conn).await?,
+            projects: export_projects(conn).await?,
+            permits: export_permits(conn).await?,
+        };
+        tx.commit().await?;
+        Ok(dump)
+    }
+
+    /// Restore the records of the dump into the database. Records that already exist in the
+    /// database are left unchanged. Users and organizations that don't have a UUID yet are matched
+    /// by their unique name. Either the whole dump is imported or nothing is.
+    pub async fn import(&self, pool: &Pool<Sqlite>) -> Result<ImportStats> {
+        if self.format != FORMAT_VERSION {
+            return Err(Error::InvalidValue(format!(
+                "unsupported dump format version {}",
+                self.format
+            )));
+        }
+        let mut stats = ImportStats::default();
+        let mut tx = pool.begin().await?;
+        let conn = &mut *tx;
+        self.import_users(&mut stats, conn).await?;
+        self.import_organizations(&mut stats, conn).await?;
+        self.import_sources(&mut stats, conn).await?;
+        // the preferences may refer to a source
+        self.import_preferences(conn).await?;
+        self.import_grades(conn).await?;
+        self.import_trips(&mut stats, conn).await?;
+        self.import_samples(&mut stats, conn).await?;
+        self.import_projects(&mut stats, conn).await?;
+        self.import_permits(&mut stats, conn).await?;
+        tx.commit().await?;
+        Ok(stats)
+    }
+
+    async fn import_users(
+        &self,
+        stats: &mut ImportStats,
+        conn: &mut SqliteConnection,
+    ) -> Result<()> {
+        for user in &self.users {
+            if local_id(USERS, user.uuid, conn).await?.is_some() {
+                stats.existing += 1;
+                continue;
+            }
+            let existing: Option<i64> =
+                sqlx::query_scalar("SELECT userid FROM sc_users WHERE username=?")
+                    .bind(&user.username)
+                    .fetch_optional(&mut *conn)
+                    .await?;
+            let id = match existing {
+                Some(id) => {
+                    stats.existing += 1;
+                    id
+                }
+                None => {
+                    stats.inserted += 1;
+                    sqlx::query(
+                        r#"INSERT INTO sc_users (username, useremail, pwhash, userstatus, usersince,
+                        userdisplayname, userprofile, usertimezone) VALUES (?, ?, ?, ?, ?, ?, ?, ?)"#,
+                    )
+                    .bind(&user.username)
+                    .bind(&user.email)
+                    .bind(&user.pwhash)
+                    .bind(user.status)
+                    .bind(&user.since)
+                    .bind(&user.display_name)
+                    .bind(&user.profile)
+                    .bind(&user.timezone)
+                    .execute(&mut *conn)
+                    .await?
+                    .last_insert_rowid()
+                }
+            };
+            remember(USERS, id, user.uuid, conn).await?;
+        }
+        Ok(())
+    }
+
+    async fn import_preferences(&self, conn: &mut SqliteConnection) -> Result<()> {
+        for user in &self.users {
+            let Some(prefs) = &user.preferences else {
+                continue;
+            };
+            let userid = require_id(USERS, user.uuid, conn).await?;
+            let source = optional_id(SOURCES, prefs.default_source, conn).await?;
+            sqlx::query(
+                r#"INSERT OR IGNORE INTO sc_user_prefs (userid, defaultsource, defaultcertainty,
+                defaultdatecurrent, yearstartmonth, region, permitpolicy) VALUES (?, ?, ?, ?, ?, ?, ?)"#,
+            )
+            .bind(userid)
+            .bind(source)
+            .bind(prefs.default_certainty)
+            .bind(prefs.default_date_current)
+            .bind(prefs.year_start_month)
+            .bind(&prefs.region)
+            .bind(prefs.permit_policy)
+            .execute(&mut *conn)
+            .await?;
+        }
+        Ok(())
+    }
+
+    async fn import_grades(&self, conn: &mut SqliteConnection) -> Result<()> {
+        for user in &self.users {
+            let userid = require_id(USERS, user.uuid, conn).await?;
+            for grade in &user.grades {
+                sqlx::query(
+                    r#"INSERT OR IGNORE INTO sc_quality_grades (userid, gradelabel, graderank,
+                    gradecriteria) VALUES (?, ?, ?, ?)"#,
+                )
+                .bind(userid)
+                .bind(&grade.label)
+                .bind(grade.rank)
+                .bind(&grade.criteria)
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn import_organizations(
+        &self,
+        stats: &mut ImportStats,
+        conn: &mut SqliteConnection,
+    ) -> Result<()> {
+        for org in &self.organizations {
+            let id = match local_id(ORGANIZATIONS, org.uuid, conn).await? {
+                Some(id) => {
+                    stats.existing += 1;
+                    id
+                }
+                None => {
+                    let existing: Option<i64> =
+                        sqlx::query_scalar("SELECT orgid FROM sc_organizations WHERE orgname=?")
+                            .bind(&org.name)
+                            .fetch_optional(&mut *conn)
+                            .await?;
+                    let id = match existing {
+                        Some(id) => {
+                            stats.existing += 1;
+                            id
+                        }
+                        None => {
+                            stats.inserted += 1;
+                            sqlx::query(
+                                "INSERT INTO sc_organizations (orgname, orgdescription, orgcreated) VALUES (?, ?, ?)",
+                            )
+                            .bind(&org.name)
+                            .bind(&org.description)
+                            .bind(&org.created)
+                            .execute(&mut *conn)
+                            .await?
+                            .last_insert_rowid()
+                        }
+                    };
+                    remember(ORGANIZATIONS, id, org.uuid, conn).await?;
+                    id
+                }
+            };
+            for member in &org.members {
+                let userid = require_id(USERS, member.user, conn).await?;
+                sqlx::query(
+                    "INSERT OR IGNORE INTO sc_org_members (orgid, userid, memberrole) VALUES (?, ?, ?)",
+                )
+                .bind(id)
+                .bind(userid)
+                .bind(member.role)
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn import_sources(
+        &self,
+        stats: &mut ImportStats,
+        conn: &mut SqliteConnection,
+    ) -> Result<()> {
+        for source in &self.sources {
+            if local_id(SOURCES, source.uuid, conn).await?.is_some() {
+                stats.existing += 1;
+                continue;
+            }
+            let userid = require_id(USERS, source.user, conn).await?;
+            let orgid = optional_id(ORGANIZATIONS, source.organization, conn).await?;
+            sqlx::query(
+                r#"INSERT INTO sc_sources (srcname, srcdesc, latitude, longitude, userid, srcversion,
+                srcorgid, srcuuid, srchabitat, srcmoisture, srclight, srcsensitive)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+            )
+            .bind(&source.name)
+            .bind(&source.description)
+            .bind(source.latitude)
+            .bind(source.longitude)
+            .bind(userid)
+            .bind(source.version)
+            .bind(orgid)
+            .bind(source.uuid.to_string())
+            .bind(source.habitat)
+            .bind(source.moisture)
+            .bind(source.light)
+            .bind(source.sensitive)
+            .execute(&mut *conn)
+            .await?;
+            stats.inserted += 1;
+        }
+        Ok(())
+    }
+
+    async fn import_trips(
+        &self,
+        stats: &mut ImportStats,
+        conn: &mut SqliteConnection,
+    ) -> Result<()> {
+        for trip in &self.trips {
+            let id = match local_id(TRIPS, trip.uuid, conn).await? {
+                Some(id) => {
+                    stats.existing += 1;
+                    id
+                }
+                None => {
+                    let userid = require_id(USERS, trip.user, conn).await?;
+                    let id = sqlx::query(
+                        r#"INSERT INTO sc_trips (tripname, tripdate, tripnotes, userid, tripversion)
+                        VALUES (?, ?, ?, ?, ?)"#,
+                    )
+                    .bind(&trip.name)
+                    .bind(&trip.date)
+                    .bind(&trip.notes)
+                    .bind(userid)
+                    .bind(trip.version)
+                    .execute(&mut *conn)
+                    .await?
+                    .last_insert_rowid();
+                    remember(TRIPS, id, trip.uuid, conn).await?;
+                    stats.inserted += 1;
+                    id
+                }
+            };
+            for participant in &trip.participants {
+                sqlx::query(
+                    "INSERT OR IGNORE INTO sc_trip_participants (tripid, participant) VALUES (?, ?)",
+                )
+                .bind(id)
+                .bind(participant)
+                .execute(&mut *conn)
+                .await?;
+            }
+            for source in &trip.target_sources {
+                let srcid = require_id(SOURCES, *source, conn).await?;
+                sqlx::query("INSERT OR IGNORE INTO sc_trip_sources (tripid, srcid) VALUES (?, ?)")
+                    .bind(id)
+                    .bind(srcid)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            for tsn in &trip.target_taxa {
+                sqlx::query("INSERT OR IGNORE INTO sc_trip_taxa (tripid, tsn) VALUES (?, ?)")
+                    .bind(id)
+                    .bind(tsn)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn import_samples(
+        &self,
+        stats: &mut ImportStats,
+        conn: &mut SqliteConnection,
+    ) -> Result<()> {
+        for sample in &self.samples {
+            let id = match local_id(SAMPLES, sample.uuid, conn).await? {
+                Some(id) => {
+                    stats.existing += 1;
+                    id
+                }
+                None => {
+                    let userid = require_id(USERS, sample.user, conn).await?;
+                    let srcid = require_id(SOURCES, sample.source, conn).await?;
+                    let orgid = optional_id(ORGANIZATIONS, sample.organization, conn).await?;
+                    let tripid = optional_id(TRIPS, sample.trip, conn).await?;
+                    let gradeid: Option<i64> = match &sample.grade {
+                        Some(label) => sqlx::query_scalar(
+                            "SELECT gradeid FROM sc_quality_grades WHERE userid=? AND gradelabel=?",
+                        )
+                        .bind(userid)
+                        .bind(label)
+                        .fetch_optional(&mut *conn)
+                        .await?
+                        .ok_or_else(|| {
+                            Error::InvalidValue(format!(
+                                "sample {} refers to the unknown grade '{label}'",
+                                sample.uuid
+                            ))
+                        })
+                        .map(Some)?,
+                        None => None,
+                    };
+                    let id = sqlx::query(
+                        r#"INSERT INTO sc_samples (tsn, certainty, month, year, srcid, notes,
+                        quantity, userid, sampleversion, sampleorgid, purchasevendor, purchaselot,
+                        purchasedate, purchaseprice, purchaseorigin, tripid, sampleuuid, gradeid)
+                        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+                    )
+                    .bind(sample.tsn)
+                    .bind(sample.certainty)
+                    .bind(sample.month)
+                    .bind(sample.year)
+                    .bind(srcid)
+                    .bind(&sample.notes)
+                    .bind(sample.quantity)
+                    .bind(userid)
+                    .bind(sample.version)
+                    .bind(orgid)
+                    .bind(&sample.purchase_vendor)
+                    .bind(&sample.purchase_lot)
+                    .bind(&sample.purchase_date)
+                    .bind(sample.purchase_price)
+                    .bind(&sample.purchase_origin)
+                    .bind(tripid)
+                    .bind(sample.uuid.to_string())
+                    .bind(gradeid)
+                    .execute(&mut *conn)
+                    .await?
+                    .last_insert_rowid();
+                    stats.inserted += 1;
+                    id
+                }
+            };
+            for flag in &sample.flags {
+                sqlx::query(
+                    "INSERT OR IGNORE INTO sc_sample_flags (sampleid, flagreason, flagcreated) VALUES (?, ?, ?)",
+                )
+                .bind(id)
+                .bind(&flag.reason)
+                .bind(&flag.created)
+                .execute(&mut *conn)
+                .await?;
+            }
+            // tests have no natural key, so a test is only skipped if an identical one exists
+            for test in &sample.quality_tests {
+                sqlx::query(
+                    r#"INSERT INTO sc_quality_tests (sampleid, qtestdate, qtestfill, qtestmethod, qtestpurity, qtesttester)
+                    SELECT ?1, ?2, ?3, ?4, ?5, ?6 WHERE NOT EXISTS (SELECT 1 FROM sc_quality_tests
+                    WHERE sampleid=?1 AND qtestdate=?2 AND qtestfill IS ?3 AND qtestmethod IS ?4
+                    AND qtestpurity IS ?5 AND qtesttester IS ?6)"#,
+                )
+                .bind(id)
+                .bind(&test.date)
+                .bind(test.fill)
+                .bind(test.method)
+                .bind(test.purity)
+                .bind(&test.tester)
+                .execute(&mut *conn)
+                .await?;
+            }
+            if let Some(label) = &sample.label {
+                sqlx::query(
+                    "INSERT OR IGNORE INTO sc_label_queue (sampleid, labelqueued, labelprinted) VALUES (?, ?, ?)",
+                )
+                .bind(id)
+                .bind(&label.queued)
+                .bind(&label.printed)
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn import_projects(
+        &self,
+        stats: &mut ImportStats,
+        conn: &mut SqliteConnection,
+    ) -> Result<()> {
+        for project in &self.projects {
+            let projectid = match local_id(PROJECTS, project.uuid, conn).await? {
+                Some(id) => {
+                    stats.existing += 1;
+                    id
+                }
+                None => {
+                    let userid = require_id(USERS, project.user, conn).await?;
+                    let orgid = optional_id(ORGANIZATIONS, project.organization, conn).await?;
+                    let id = sqlx::query(
+                        r#"INSERT INTO sc_projects (projname, projdescription, userid, projversion,
+                        projorgid, projuuid) VALUES (?, ?, ?, ?, ?, ?)"#,
+                    )
+                    .bind(&project.name)
+                    .bind(&project.description)
+                    .bind(userid)
+                    .bind(project.version)
+                    .bind(orgid)
+                    .bind(project.uuid.to_string())
+                    .execute(&mut *conn)
+                    .await?
+                    .last_insert_rowid();
+                    stats.inserted += 1;
+                    id
+                }
+            };
+            for allocation in &project.allocations {
+                let psid = match local_id(ALLOCATIONS, allocation.uuid, conn).await? {
+                    Some(id) => {
+                        stats.existing += 1;
+                        id
+                    }
+                    None => {
+                        let sampleid = require_id(SAMPLES, allocation.sample, conn).await?;
+                        let existing: Option<i64> = sqlx::query_scalar(
+                            "SELECT psid FROM sc_project_samples WHERE projectid=? AND sampleid=?",
+                        )
+                        .bind(projectid)
+                        .bind(sampleid)
+                        .fetch_optional(&mut *conn)
+                        .await?;
+                        // the sample may have been added to the project independently, in which
+                        // case the allocation keeps its own UUID
+                        match existing {
+                            Some(id) => {
+                                stats.existing += 1;
+                                id
+                            }
+                            None => {
+                                stats.inserted += 1;
+                                sqlx::query(
+                                    "INSERT INTO sc_project_samples (projectid, sampleid, psuuid) VALUES (?, ?, ?)",
+                                )
+                                .bind(projectid)
+                                .bind(sampleid)
+                                .bind(allocation.uuid.to_string())
+                                .execute(&mut *conn)
+                                .await?
+                                .last_insert_rowid()
+                            }
+                        }
+                    }
+                };
+                for note in &allocation.notes {
+                    if local_id(NOTES, note.uuid, conn).await?.is_some() {
+                        stats.existing += 1;
+                        continue;
+                    }
+                    sqlx::query(
+                        r#"INSERT INTO sc_project_notes (psid, notedate, notetype, notesummary,
+                        notedetails, pnoteuuid) VALUES (?, ?, ?, ?, ?, ?)"#,
+                    )
+                    .bind(psid)
+                    .bind(&note.date)
+                    .bind(note.kind)
+                    .bind(&note.summary)
+                    .bind(&note.details)
+                    .bind(note.uuid.to_string())
+                    .execute(&mut *conn)
+                    .await?;
+                    stats.inserted += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn import_permits(
+        &self,
+        stats: &mut ImportStats,
+        conn: &mut SqliteConnection,
+    ) -> Result<()> {
+        for permit in &self.permits {
+            if local_id(PERMITS, permit.uuid, conn).await?.is_some() {
+                stats.existing += 1;
+                continue;
+            }
+            let userid = require_id(USERS, permit.user, conn).await?;
+            let id = sqlx::query(
+                r#"INSERT INTO sc_permits (userid, tsn, region, permitnumber, permitexpires,
+                permitnotes) VALUES (?, ?, ?, ?, ?, ?)"#,
+            )
+            .bind(userid)
+            .bind(permit.tsn)
+            .bind(&permit.region)
+            .bind(&permit.number)
+            .bind(&permit.expires)
+            .bind(&permit.notes)
+            .execute(&mut *conn)
+            .await?
+            .last_insert_rowid();
+            remember(PERMITS, id, permit.uuid, conn).await?;
+            stats.inserted += 1;
+        }
+        Ok(())
+    }
+}
+
+async fn export_users(conn: &mut SqliteConnection) -> Result<Vec<UserRecord>> {
+    let rows = sqlx::query(
+        r#"SELECT U.*, P.defaultsource, P.defaultcertainty, P.defaultdatecurrent,
+        P.yearstartmonth, P.region, P.permitpolicy
+        FROM sc_users U LEFT JOIN sc_user_prefs P ON P.userid=U.userid ORDER BY U.userid"#,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut users = Vec::new();
+    for row in rows {
+        let preferences = match row.try_get::<Option<i64>, _>("defaultcertainty")? {
+            Some(default_certainty) => Some(PreferencesRecord {
+                default_source: optional_uuid_of(SOURCES, row.try_get("defaultsource")?, conn)
+                    .await?,
+                default_certainty,
+                default_date_current: row.try_get("defaultdatecurrent")?,
+                year_start_month: row.try_get("yearstartmonth")?,
+                region: row.try_get("region")?,
+                permit_policy: row.try_get("permitpolicy")?,
+            }),
+            None => None,
+        };
+        let userid: i64 = row.try_get("userid")?;
+        let grades = sqlx::query(
+            "SELECT * FROM sc_quality_grades WHERE userid=? ORDER BY graderank, gradelabel",
+        )
+        .bind(userid)
+        .fetch_all(&mut *conn)
+        .await?
+        .iter()
+        .map(|grade| {
+            Ok(GradeRecord {
+                label: grade.try_get("gradelabel")?,
+                rank: grade.try_get("graderank")?,
+                criteria: grade.try_get("gradecriteria")?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+        users.push(UserRecord {
+            uuid: uuid_of(USERS, userid, conn).await?,
+            username: row.try_get("username")?,
+            email: row.try_get("useremail")?,
+            pwhash: row.try_get("pwhash")?,
+            status: row.try_get("userstatus")?,
+            since: row.try_get("usersince")?,
+            display_name: row.try_get("userdisplayname")?,
+            profile: row.try_get("userprofile")?,
+            timezone: row.try_get("usertimezone")?,
+            preferences,
+            grades,
+        });
+    }
+    Ok(users)
+}
+
+async fn export_organizations(conn: &mut SqliteConnection) -> Result<Vec<OrganizationRecord>> {
+    let rows = sqlx::query("SELECT * FROM sc_organizations ORDER BY orgid")
+        .fetch_all(&mut *conn)
+        .await?;
+    let mut orgs = Vec::new();
+    for row in rows {
+        let id: i64 = row.try_get("orgid")?;
+        let member_rows = sqlx::query(
+            "SELECT userid, memberrole FROM sc_org_members WHERE orgid=? ORDER BY userid",
+        )
+        .bind(id)
+        .fetch_all(&mut *conn)
+        .await?;
+        let mut members = Vec::new();
+        for member in member_rows {
+            members.push(MemberRecord {
+                user: uuid_of(USERS, member.try_get("userid")?, conn).await?,
+                role: member.try_get("memberrole")?,
+            });
+        }
+        orgs.push(OrganizationRecord {
+            uuid: uuid_of(ORGANIZATIONS, id, conn).await?,
+            name: row.try_get("orgname")?,
+            description: row.try_get("orgdescription")?,
+            created: row.try_get("orgcreated")?,
+            members,
+        });
+    }
+    Ok(orgs)
+}
+
+async fn export_sources(conn: &mut SqliteConnection) -> Result<Vec<SourceRecord>> {
+    let rows = sqlx::query("SELECT * FROM sc_sources ORDER BY srcid")
+        .fetch_all(&mut *conn)
+        .await?;
+    let mut sources = Vec::new();
+    for row in rows {
+        sources.push(SourceRecord {
+            uuid: uuid_of(SOURCES, row.try_get("srcid")?, conn).await?,
+            user: uuid_of(USERS, row.try_get("userid")?, conn).await?,
+            organization: optional_uuid_of(ORGANIZATIONS, row.try_get("srcorgid")?, conn).await?,
+            name: row.try_get("srcname")?,
+            description: row.try_get("srcdesc")?,
+            latitude: row.try_get("latitude")?,
+            longitude: row.try_get("longitude")?,
+            version: row.try_get("srcversion")?,
+            habitat: row.try_get("srchabitat")?,
+            moisture: row.try_get("srcmoisture")?,
+            light: row.try_get("srclight")?,
+            sensitive: row.try_get("srcsensitive")?,
+        });
+    }
+    Ok(sources)
+}
+
+async fn export_trips(conn: &mut SqliteConnection) -> Result<Vec<TripRecord>> {
+    let rows = sqlx::query("SELECT * FROM sc_trips ORDER BY tripid")
+        .fetch_all(&mut *conn)
+        .await?;
+    let mut trips = Vec::new();
+    for row in rows {
+        let id: i64 = row.try_get("tripid")?;
+        let participants = sqlx::query_scalar(
+            "SELECT participant FROM sc_trip_participants WHERE tripid=? ORDER BY participant",
+        )
+        .bind(id)
+        .fetch_all(&mut *conn)
+        .await?;
+        let srcids: Vec<i64> =
+            sqlx::query_scalar("SELECT srcid FROM sc_trip_sources WHERE tripid=? ORDER BY srcid")
+                .bind(id)
+                .fetch_all(&mut *conn)
+                .await?;
+        let mut target_sources = Vec::new();
+        for srcid in srcids {
+            target_sources.push(uuid_of(SOURCES, srcid, conn).await?);
+        }
+        let target_taxa =
+            sqlx::query_scalar("SELECT tsn FROM sc_trip_taxa WHERE tripid=? ORDER BY tsn")
+                .bind(id)
+                .fetch_all(&mut *conn)
+                .await?;
+        trips.push(TripRecord {
+            uuid: uuid_of(TRIPS, id, conn).await?,
+            user: uuid_of(USERS, row.try_get("userid")?, conn).await?,
+            name: row.try_get("tripname")?,
+            date: row.try_get("tripdate")?,
+            notes: row.try_get("tripnotes")?,
+            version: row.try_get("tripversion")?,
+            participants,
+            target_sources,
+            target_taxa,
+        });
+    }
+    Ok(trips)
+}
+
+async fn export_samples(conn: &mut SqliteConnection) -> Result<Vec<SampleRecord>> {
+    // the source column was declared as TEXT, so it needs to be converted explicitly
+    let rows = sqlx::query(
+        r#"SELECT S.*, CAST(S.srcid AS INTEGER) AS sourceid, L.labelqueued, L.labelprinted,
+        G.gradelabel
+        FROM sc_samples S LEFT JOIN sc_label_queue L ON L.sampleid=S.sampleid
+        LEFT JOIN sc_quality_grades G ON G.gradeid=S.gradeid
+        ORDER BY S.sampleid"#,
+    )
+    .fetch_all(&mut *conn)
+    .await?;
+    let mut samples = Vec::new();
+    for row in rows {
+        let id: i64 = row.try_get("sampleid")?;
+        let flag_rows = sqlx::query(
+            "SELECT flagreason, flagcreated FROM sc_sample_flags WHERE sampleid=? ORDER BY flagid",
+        )
+        .bind(id)
+        .fetch_all(&mut *conn)
+        .await?;
+        let flags = flag_rows
+            .iter()
+            .map(|flag| {
+                Ok(FlagRecord {
+                    reason: flag.try_get("flagreason")?,
+                    created: flag.try_get("flagcreated")?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let test_rows = sqlx::query(
+            "SELECT * FROM sc_quality_tests WHERE sampleid=? ORDER BY qtestdate, qtestid",
+        )
+        .bind(id)
+        .fetch_all(&mut *conn)
+        .await?;
+        let quality_tests = test_rows
+            .iter()
+            .map(|test| {
+                Ok(QualityTestRecord {
+                    date: test.try_get("qtestdate")?,
+                    fill: test.try_get("qtestfill")?,
+                    method: test.try_get("qtestmethod")?,
+                    purity: test.try_get("qtestpurity")?,
+                    tester: test.try_get("qtesttester")?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let label = match row.try_get::<Option<String>, _>("labelqueued")? {
+            Some(queued) => Some(LabelRecord {
+                queued,
+                printed: row.try_get("labelprinted")?,
+            }),
+            None => None,
+        };
+        samples.push(SampleRecord {
+            uuid: uuid_of(SAMPLES, id, conn).await?,
+            user: uuid_of(USERS, row.try_get("userid")?, conn).await?,
+            source: uuid_of(SOURCES, row.try_get("sourceid")?, conn).await?,
+            organization: optional_uuid_of(ORGANIZATIONS, row.try_get("sampleorgid")?, conn)
+                .await?,
+            trip: optional_uuid_of(TRIPS, row.try_get("tripid")?, conn).await?,
+            tsn: row.try_get("tsn")?,
+            certainty: row.try_get("certainty")?,
+            month: row.try_get("month")?,
+            year: row.try_get("year")?,
+            quantity: row.try_get("quantity")?,
+            notes: row.try_get("notes")?,
+            version: row.try_get("sampleversion")?,
+            purchase_vendor: row.try_get("purchasevendor")?,
+            purchase_lot: row.try_get("purchaselot")?,
+            purchase_date: row.try_get("purchasedate")?,
+            purchase_price: row.try_get("purchaseprice")?,
+            purchase_origin: row.try_get("purchaseorigin")?,
+            flags,
+            quality_tests,
+            label,
+            grade: row.try_get("gradelabel")?,
+        });
+    }
+    Ok(samples)
+}
+
+async fn export_projects(conn: &mut SqliteConnection) -> Result<Vec<ProjectRecord>> {
+    let rows = sqlx::query("SELECT * FROM sc_projects ORDER BY projectid")
+        .fetch_all(&mut *conn)
+        .await?;
+    let mut projects = Vec::new();
+    for row in rows {
+        let id: i64 = row.try_get("projectid")?;
+        let allocation_rows = sqlx::query(
+            "SELECT psid, sampleid FROM sc_project_samples WHERE projectid=? ORDER BY psid",
+        )
+        .bind(id)
+        .fetch_all(&mut *conn)
+        .await?;
+        let mut allocations = Vec::new();
+        for allocation in allocation_rows {
+            let psid: i64 = allocation.try_get("psid")?;
+            let note_rows =
+                sqlx::query("SELECT * FROM sc_project_notes WHERE psid=? ORDER BY pnoteid")
+                    .bind(psid)
+                    .fetch_all(&mut *conn)
+                    .await?;
+            let mut notes = Vec::new();
+            for note in note_rows {
+                notes.push(NoteRecord {
+                    uuid: uuid_of(NOTES, note.try_get("pnoteid")?, conn).await?,
+                    date: note.try_get("notedate")?,
+                    kind: note.try_get("notetype")?,
+                    summary: note.try_get("notesummary")?,
+                    details: note.try_get("notedetails")?,
+                });
+            }
+            allocations.push(AllocationRecord {
+                uuid: uuid_of(ALLOCATIONS, psid, conn).await?,
+                sample: uuid_of(SAMPLES, allocation.try_get("sampleid")?, conn).await?,
+                notes,
+            });
+        }
+        projects.push(ProjectRecord {
+            uuid: uuid_of(PROJECTS, id, conn).await?,
+            user: uuid_of(USERS, row.try_get("userid")?, conn).await?,
+            organization: optional_uuid_of(ORGANIZATIONS, row.try_get("projorgid")?, conn).await?,
+            name: row.try_get("projname")?,
+            description: row.try_get("projdescription")?,
+            version: row.try_get("projversion")?,
+            allocations,
+        });
+    }
+    Ok(projects)
+}
+
+async fn export_permits(conn: &mut SqliteConnection) -> Result<Vec<PermitRecord>> {
+    let rows = sqlx::query("SELECT * FROM sc_permits ORDER BY permitid")
+        .fetch_all(&mut *conn)
+        .await?;
+    let mut permits = Vec::new();
+    for row in rows {
+        permits.push(PermitRecord {
+            uuid: uuid_of(PERMITS, row.try_get("permitid")?, conn).await?,
+            user: uuid_of(USERS, row.try_get("userid")?, conn).await?,
+            tsn: row.try_get("tsn")?,
+            region: row.try_get("region")?,
+            number: row.try_get("permitnumber")?,
+            expires: row.try_get("permitexpires")?,
+            notes: row.try_get("permitnotes")?,
+        });
+    }
+    Ok(permits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grade::QualityGrade, loadable::Loadable, sample::Sample};
+    use sqlx::sqlite::SqlitePoolOptions;
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "csnotes")
+        )
+    ))]
+    async fn test_export_import(pool: Pool<Sqlite>) {
+        let scale = QualityGrade::create_default_scale(1, &pool).await.unwrap();
+        let mut sample = Sample::load(1, &pool).await.unwrap();
+        sample.set_grade(Some(&scale[0]), &pool).await.unwrap();
+        let dump = Dump::export(&pool).await.expect("Failed to export");
+        assert_eq!(dump.users[0].grades.len(), 3);
+        assert_eq!(dump.samples[0].grade.as_deref(), Some("A"));
+        assert_eq!(dump.users.len(), 2);
+        assert_eq!(dump.samples.len(), 3);
+        assert_eq!(dump.projects.len(), 2);
+        assert_eq!(dump.projects[0].allocations[0].notes.len(), 2);
+        assert_eq!(
+            dump,
+            Dump::export(&pool).await.unwrap(),
+            "exporting again keeps the same UUIDs"
+        );
+        // the serialized form can be read back
+        let json = serde_json::to_string(&dump).unwrap();
+        assert_eq!(serde_json::from_str::<Dump>(&json).unwrap(), dump);
+
+        // importing into the same database doesn't duplicate anything
+        let stats = dump.import(&pool).await.expect("Failed to import");
+        assert_eq!(stats.inserted, 0);
+        assert_eq!(Dump::export(&pool).await.unwrap(), dump);
+
+        // a different database with taxonomy and some unrelated data of its own
+        let other = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("../db/migrations")
+            .run(&other)
+            .await
+            .unwrap();
+        sqlx::query(include_str!("../../db/fixtures/taxa.sql"))
+            .execute(&other)
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"INSERT INTO sc_users (username, useremail, pwhash) VALUES ("other", "other@example.com", "");
+            INSERT INTO sc_sources (srcname, userid) VALUES ("Other source", 1);"#,
+        )
+        .execute(&other)
+        .await
+        .unwrap();
+
+        let stats = dump.import(&other).await.expect("Failed to import");
+        assert_eq!(stats.existing, 0);
+        assert!(stats.inserted > 0);
+        assert_eq!(dump.import(&other).await.unwrap().inserted, 0);
+        let restored = Dump::export(&other).await.unwrap();
+        assert_eq!(restored.users.len(), dump.users.len() + 1);
+        let user = restored.users.iter().find(|u| u.uuid == dump.users[0].uuid);
+        assert_eq!(user.unwrap().grades, dump.users[0].grades);
+        assert_eq!(restored.samples, dump.samples);
+        assert_eq!(restored.projects, dump.projects);
+        assert_eq!(restored.trips, dump.trips);
+
+        // the records were given new IDs, but still refer to each other
+        let source = dump.samples[0].source;
+        let id = |pool: Pool<Sqlite>| async move {
+            sqlx::query_scalar::<_, i64>("SELECT srcid FROM sc_sources WHERE srcuuid=?")
+                .bind(source.to_string())
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+        };
+        assert_ne!(id(pool.clone()).await, id(other.clone()).await);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users", "sources", "taxa"))
+    ))]
+    async fn test_import_invalid(pool: Pool<Sqlite>) {
+        let mut dump = Dump::export(&pool).await.unwrap();
+        dump.format = FORMAT_VERSION + 1;
+        assert!(dump.import(&pool).await.is_err());
+
+        // a dangling reference aborts the whole import
+        dump.format = FORMAT_VERSION;
+        let mut source = dump.sources[0].clone();
+        source.uuid = Uuid::new_v4();
+        source.user = Uuid::new_v4();
+        dump.sources.insert(0, source.clone());
+        let mut valid = source.clone();
+        valid.uuid = Uuid::new_v4();
+        valid.user = dump.users[0].uuid;
+        dump.sources.insert(0, valid);
+        assert!(matches!(
+            dump.import(&pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        assert_eq!(
+            Dump::export(&pool).await.unwrap().sources.len(),
+            dump.sources.len() - 2
+        );
+    }
+}
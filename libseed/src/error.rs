@@ -27,6 +27,15 @@ pub enum Error {
     #[error("can't insert the object, it already exists in the database with id = {}", .0)]
     InvalidOperationObjectAlreadyExists(i64),
 
+    #[error("can't delete the object, it is still used by {} samples", .0)]
+    InvalidOperationObjectInUse(i64),
+
+    #[error("invalid value: {}", .0)]
+    InvalidValue(String),
+
+    #[error("{}, which requires a collecting permit", .0)]
+    PermitRequired(String),
+
     #[error("invalid state: the object is not loaded")]
     InvalidStateNotLoaded,
 
@@ -38,6 +47,9 @@ pub enum Error {
 
     #[error("Database error: row not found")]
     DatabaseRowNotFound(#[source] sqlx::Error),
+
+    #[error("Database error: the object was modified or removed since version {} was loaded", .0)]
+    DatabaseVersionConflict(i64),
 }
 
 impl std::convert::From<sqlx::Error> for Error {
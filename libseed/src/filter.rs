@@ -1,7 +1,9 @@
 //! utilities for filtering database queries for the various objects
 //!
+use crate::error::{Error, Result};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use sqlx::{Pool, QueryBuilder, Sqlite};
+use std::{str::FromStr, sync::Arc};
 
 #[derive(Clone)]
 pub enum Op {
@@ -119,7 +121,17 @@ impl std::fmt::Display for Cmp {
 /// An object that allows you to specify the limit and offset for an SQL query
 pub struct LimitSpec(pub i32, pub Option<i32>);
 
-#[derive(Deserialize, Serialize, Clone)]
+impl LimitSpec {
+    /// Add the limit and offset to the end of a query
+    pub fn push_to(&self, builder: &mut QueryBuilder<'_, Sqlite>) {
+        builder.push(" LIMIT ").push_bind(self.0);
+        if let Some(offset) = self.1 {
+            builder.push(" OFFSET ").push_bind(offset);
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub enum SortOrder {
     #[serde(rename = "asc")]
     Ascending,
@@ -128,6 +140,7 @@ pub enum SortOrder {
 }
 
 /// An object that allows you to specify the sort for an SQL query
+#[derive(Serialize, Clone, Debug, PartialEq)]
 pub struct SortSpec<T: ToString> {
     pub field: T,
     pub order: SortOrder,
@@ -139,4 +152,255 @@ impl<T: ToString> SortSpec<T> {
     }
 }
 
+/// Parses the name of a field, which sorts in descending order when it is prefixed with a `-`,
+/// e.g. `-date`
+impl<T: ToString + FromStr> FromStr for SortSpec<T> {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (name, order) = match s.strip_prefix('-') {
+            Some(name) => (name, SortOrder::Descending),
+            None => (s.strip_prefix('+').unwrap_or(s), SortOrder::Ascending),
+        };
+        let field = name
+            .trim()
+            .parse()
+            .map_err(|_| Error::InvalidValue(format!("unknown sort field '{name}'")))?;
+        Ok(Self::new(field, order))
+    }
+}
+
+impl<T: ToString> std::fmt::Display for SortSpec<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.order {
+            SortOrder::Ascending => write!(f, "{}", self.field.to_string()),
+            SortOrder::Descending => write!(f, "-{}", self.field.to_string()),
+        }
+    }
+}
+
+/// One or more sort keys for an SQL query. Rows that are equal by the first key are sorted by the
+/// second one, and so on. As text, the keys are written as a comma-separated list of fields in
+/// the format of [SortSpec], e.g. `name,-date`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SortSpecs<T: ToString>(Vec<SortSpec<T>>);
+
+impl<T: ToString> SortSpecs<T> {
+    pub fn new(first: SortSpec<T>) -> Self {
+        Self(vec![first])
+    }
+
+    /// Add a key that sorts the rows that are equal by all of the previous keys. A field that is
+    /// already sorted by is ignored.
+    pub fn then(mut self, spec: SortSpec<T>) -> Self {
+        if !self.contains(&spec.field) {
+            self.0.push(spec);
+        }
+        self
+    }
+
+    fn contains(&self, field: &T) -> bool {
+        let name = field.to_string();
+        self.0.iter().any(|spec| spec.field.to_string() == name)
+    }
+
+    pub fn keys(&self) -> &[SortSpec<T>] {
+        &self.0
+    }
+
+    /// Add an `ORDER BY` clause for the keys to the query. `column` returns the SQL expression
+    /// that a field is sorted by.
+    pub fn push_order_by<F>(&self, builder: &mut QueryBuilder<Sqlite>, column: F)
+    where
+        F: Fn(&T) -> &'static str,
+    {
+        builder.push(" ORDER BY ");
+        let mut separated = builder.separated(", ");
+        for spec in &self.0 {
+            separated.push(column(&spec.field));
+            separated.push_unseparated(match spec.order {
+                SortOrder::Ascending => " ASC",
+                SortOrder::Descending => " DESC",
+            });
+        }
+    }
+}
+
+impl<T: ToString> From<SortSpec<T>> for SortSpecs<T> {
+    fn from(value: SortSpec<T>) -> Self {
+        Self::new(value)
+    }
+}
+
+/// Sort by a single field in ascending order
+impl<T: ToString> From<T> for SortSpecs<T> {
+    fn from(value: T) -> Self {
+        Self::new(SortSpec::new(value, SortOrder::Ascending))
+    }
+}
+
+impl<T: ToString + FromStr> FromStr for SortSpecs<T> {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut keys = s.split(',');
+        let mut specs = Self::new(keys.next().unwrap_or_default().parse()?);
+        for key in keys {
+            let key: SortSpec<T> = key.parse()?;
+            if specs.contains(&key.field) {
+                return Err(Error::InvalidValue(format!(
+                    "the list is already sorted by '{}'",
+                    key.field.to_string()
+                )));
+            }
+            specs = specs.then(key);
+        }
+        Ok(specs)
+    }
+}
+
+impl<T: ToString> std::fmt::Display for SortSpecs<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let keys: Vec<String> = self.0.iter().map(|spec| spec.to_string()).collect();
+        write!(f, "{}", keys.join(","))
+    }
+}
+
+impl<T: ToString> Serialize for SortSpecs<T> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de, T: ToString + FromStr> Deserialize<'de> for SortSpecs<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
 pub type DynFilterPart = Arc<dyn FilterPart + Sync>;
+
+/// The columns, tables and filter of a query that lists objects. Both the list of objects and the
+/// number of objects in it are built from it, so that a count always matches the list that it is
+/// shown with.
+#[derive(Clone)]
+pub struct ListQuery {
+    columns: &'static str,
+    tables: &'static str,
+    filter: Option<DynFilterPart>,
+    group_by: Option<&'static str>,
+}
+
+impl ListQuery {
+    /// A query of the given columns from the given tables, including any joins, that match
+    /// `filter`
+    pub fn new(columns: &'static str, tables: &'static str, filter: Option<DynFilterPart>) -> Self {
+        Self {
+            columns,
+            tables,
+            filter,
+            group_by: None,
+        }
+    }
+
+    /// Group the rows by the given columns, e.g. when a join can return several rows for a single
+    /// object
+    pub fn group_by(mut self, columns: &'static str) -> Self {
+        self.group_by = Some(columns);
+        self
+    }
+
+    fn push_conditions(&self, builder: &mut QueryBuilder<'static, Sqlite>) {
+        if let Some(f) = &self.filter {
+            builder.push(" WHERE ");
+            f.add_to_query(builder);
+        }
+        if let Some(columns) = self.group_by {
+            builder.push(format!(" GROUP BY {columns}"));
+        }
+    }
+
+    /// A query of the matching objects. A sort order and limit can be pushed to the end of it.
+    pub fn select(&self) -> QueryBuilder<'static, Sqlite> {
+        let mut builder =
+            QueryBuilder::new(format!("SELECT {} FROM {}", self.columns, self.tables));
+        self.push_conditions(&mut builder);
+        builder
+    }
+
+    /// A query of the number of matching objects, which is returned in the column `count`
+    pub fn count(&self) -> QueryBuilder<'static, Sqlite> {
+        let mut builder = match self.group_by {
+            // count the groups rather than the rows
+            Some(_) => QueryBuilder::new(format!(
+                "SELECT COUNT(*) AS count FROM (SELECT 1 FROM {}",
+                self.tables
+            )),
+            None => QueryBuilder::new(format!("SELECT COUNT(*) AS count FROM {}", self.tables)),
+        };
+        self.push_conditions(&mut builder);
+        if self.group_by.is_some() {
+            builder.push(")");
+        }
+        builder
+    }
+
+    /// Fetch the number of matching objects
+    pub async fn fetch_count(&self, pool: &Pool<Sqlite>) -> Result<i64> {
+        Ok(self.count().build_query_scalar().fetch_one(pool).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample::Sort;
+
+    #[test]
+    fn parse_sort_specs() {
+        let specs: SortSpecs<Sort> = "name,-date".parse().unwrap();
+        assert_eq!(
+            specs.keys(),
+            [
+                SortSpec::new(Sort::TaxonName, SortOrder::Ascending),
+                SortSpec::new(Sort::CollectionDate, SortOrder::Descending),
+            ]
+        );
+        assert_eq!(specs.to_string(), "name,-date");
+        let specs: SortSpecs<Sort> = " +id ".parse().unwrap();
+        assert_eq!(specs, Sort::Id.into());
+
+        for invalid in ["", "name,", "bogus", "name,-name", "--id"] {
+            assert!(
+                invalid.parse::<SortSpecs<Sort>>().is_err(),
+                "'{invalid}' should not parse"
+            );
+        }
+
+        // a field that is already sorted by is ignored when it is added again
+        let specs = SortSpecs::from(Sort::Id).then(SortSpec::new(Sort::Id, SortOrder::Descending));
+        assert_eq!(specs.to_string(), "id");
+    }
+
+    #[test]
+    fn push_order_by() {
+        let specs: SortSpecs<Sort> = "source,-qty".parse().unwrap();
+        let mut builder = QueryBuilder::new("SELECT * FROM vsamples");
+        specs.push_order_by(&mut builder, |field| match field {
+            Sort::SourceName => "srcname",
+            _ => "quantity",
+        });
+        assert_eq!(
+            builder.sql(),
+            "SELECT * FROM vsamples ORDER BY srcname ASC, quantity DESC"
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! A yield forecast estimates how much seed can be expected from each source for each taxon in the
+//! coming season, based on the quantities that were collected there in previous years.
+use crate::{error::Result, stats::CollectionYear, try_get_uuid};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Row, Sqlite};
+use uuid::Uuid;
+
+/// The relative change per year in collected quantity above which a yield is considered to be
+/// rising (or falling)
+const TREND_THRESHOLD: f64 = 0.1;
+
+/// The direction in which the collected quantity has been developing over the years
+#[derive(strum_macros::Display, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Trend {
+    Rising,
+    Steady,
+    Falling,
+    /// there are not enough years of data to tell
+    Unknown,
+}
+
+/// The forecast for a single taxon at a single source
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct YieldForecast {
+    pub taxon_id: i64,
+    pub taxon_name: String,
+    pub source_id: i64,
+    pub source_uuid: Uuid,
+    pub source_name: String,
+    /// the total quantity that was collected in each collection year, ordered by year
+    pub history: Vec<(u32, i64)>,
+    /// the average quantity collected per year, which is the expected yield for the coming season
+    pub expected: f64,
+    pub trend: Trend,
+}
+
+impl YieldForecast {
+    fn new(
+        taxon_id: i64,
+        taxon_name: String,
+        source_id: i64,
+        source_uuid: Uuid,
+        source_name: String,
+    ) -> Self {
+        Self {
+            taxon_id,
+            taxon_name,
+            source_id,
+            source_uuid,
+            source_name,
+            history: Vec::new(),
+            expected: 0.0,
+            trend: Trend::Unknown,
+        }
+    }
+
+    /// Compute the expected yield and the trend from the yearly history
+    fn estimate(&mut self) {
+        let n = self.history.len() as f64;
+        if self.history.is_empty() {
+            return;
+        }
+        self.expected = self.history.iter().map(|(_, q)| *q as f64).sum::<f64>() / n;
+        if self.history.len() < 2 || self.expected <= 0.0 {
+            return;
+        }
+        // the slope of a least-squares line through the yearly quantities, relative to the
+        // average quantity
+        let mean_year = self.history.iter().map(|(y, _)| f64::from(*y)).sum::<f64>() / n;
+        let (cov, var) = self
+            .history
+            .iter()
+            .fold((0.0, 0.0), |(cov, var), (year, quantity)| {
+                let dy = f64::from(*year) - mean_year;
+                (cov + dy * (*quantity as f64 - self.expected), var + dy * dy)
+            });
+        let change = cov / var / self.expected;
+        self.trend = if change > TREND_THRESHOLD {
+            Trend::Rising
+        } else if change < -TREND_THRESHOLD {
+            Trend::Falling
+        } else {
+            Trend::Steady
+        };
+    }
+
+    pub fn first_year(&self) -> Option<u32> {
+        self.history.first().map(|(y, _)| *y)
+    }
+
+    pub fn last_year(&self) -> Option<u32> {
+        self.history.last().map(|(y, _)| *y)
+    }
+}
+
+/// Forecast the yield of each taxon at each source from the user's samples that have both a
+/// collection year and a quantity. The forecasts are ordered by taxon and source name. Unless
+/// `include_invasive` is set, taxa that are listed as invasive are left out.
+pub async fn load(
+    userid: i64,
+    years: &CollectionYear,
+    include_invasive: bool,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<YieldForecast>> {
+    let rows = sqlx::query(&format!(
+        r#"SELECT S.tsn, T.complete_name, L.srcid, L.srcuuid, L.srcname, {} AS cyear,
+        SUM(S.quantity) AS quantity
+        FROM sc_samples S
+        INNER JOIN taxonomic_units T ON T.tsn=S.tsn
+        INNER JOIN sc_sources L ON L.srcid=S.srcid
+        WHERE S.userid=? AND S.year IS NOT NULL AND S.quantity IS NOT NULL
+        AND (? OR S.tsn NOT IN
+            (SELECT tsn FROM mntaxa WHERE invasive_status IS NOT NULL AND invasive_status != ''))
+        GROUP BY S.tsn, L.srcid, cyear
+        ORDER BY T.complete_name, L.srcname, L.srcid, cyear"#,
+        years.sql("S.month", "S.year")
+    ))
+    .bind(userid)
+    .bind(include_invasive)
+    .fetch_all(pool)
+    .await?;
+
+    let mut forecasts: Vec<YieldForecast> = Vec::new();
+    for row in rows {
+        let taxon_id: i64 = row.try_get("tsn")?;
+        let source_id: i64 = row.try_get("srcid")?;
+        if forecasts.last().map(|f| (f.taxon_id, f.source_id)) != Some((taxon_id, source_id)) {
+            forecasts.push(YieldForecast::new(
+                taxon_id,
+                row.try_get("complete_name")?,
+                source_id,
+                try_get_uuid(&row, "srcuuid")?,
+                row.try_get("srcname")?,
+            ));
+        }
+        if let Some(forecast) = forecasts.last_mut() {
+            forecast
+                .history
+                .push((row.try_get("cyear")?, row.try_get("quantity")?));
+        }
+    }
+    forecasts.iter_mut().for_each(YieldForecast::estimate);
+    Ok(forecasts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    fn forecast(history: &[(u32, i64)]) -> YieldForecast {
+        let mut f =
+            YieldForecast::new(1, "taxon".to_string(), 1, Uuid::nil(), "source".to_string());
+        f.history = history.to_vec();
+        f.estimate();
+        f
+    }
+
+    #[test]
+    fn test_estimate() {
+        let f = forecast(&[(2021, 100)]);
+        assert_eq!(f.expected, 100.0);
+        assert_eq!(f.trend, Trend::Unknown);
+        let f = forecast(&[(2021, 100), (2022, 200), (2023, 300)]);
+        assert_eq!(f.expected, 200.0);
+        assert_eq!(f.trend, Trend::Rising);
+        let f = forecast(&[(2020, 300), (2023, 100)]);
+        assert_eq!(f.trend, Trend::Falling);
+        let f = forecast(&[(2021, 100), (2022, 95), (2023, 105)]);
+        assert_eq!(f.trend, Trend::Steady);
+        assert_eq!(f.first_year(), Some(2021));
+        assert_eq!(f.last_year(), Some(2023));
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn test_forecast(pool: Pool<Sqlite>) {
+        // only sample 2 has a quantity
+        let calendar = CollectionYear::default();
+        let forecasts = load(1, &calendar, false, &pool)
+            .await
+            .expect("Failed to load forecast");
+        assert_eq!(forecasts.len(), 1);
+        assert_eq!(forecasts[0].taxon_id, 40683);
+        assert_eq!(forecasts[0].source_id, 2);
+        assert_eq!(forecasts[0].history, vec![(2023, 100)]);
+        assert_eq!(forecasts[0].expected, 100.0);
+
+        sqlx::query(
+            r#"INSERT INTO sc_samples (tsn, userid, srcid, month, year, quantity)
+            VALUES (40683, 1, 2, 10, 2021, 40), (40683, 1, 2, 9, 2023, 20),
+                (40683, 1, 1, 10, 2022, 10)"#,
+        )
+        .execute(&pool)
+        .await
+        .expect("Failed to insert samples");
+        let forecasts = load(1, &calendar, false, &pool)
+            .await
+            .expect("Failed to load forecast");
+        assert_eq!(forecasts.len(), 2);
+        let at_source = forecasts
+            .iter()
+            .find(|f| f.source_id == 2)
+            .expect("Missing forecast for source 2");
+        // samples from the same year are added up
+        assert_eq!(at_source.history, vec![(2021, 40), (2023, 120)]);
+        assert_eq!(at_source.expected, 80.0);
+        assert_eq!(at_source.trend, Trend::Rising);
+
+        // with a year running from October to September, the sample from September 2023 belongs
+        // to the year that starts in 2022
+        let october = CollectionYear::starting_in(10).unwrap();
+        let forecasts = load(1, &october, false, &pool)
+            .await
+            .expect("Failed to load forecast");
+        let at_source = forecasts
+            .iter()
+            .find(|f| f.source_id == 2)
+            .expect("Missing forecast for source 2");
+        assert_eq!(at_source.history, vec![(2021, 40), (2022, 20), (2023, 100)]);
+
+        // invasive taxa are only included on request
+        sqlx::query(
+            "INSERT INTO mntaxa (tsn, native_status, invasive_status) VALUES (40683, 'I', 'RN')",
+        )
+        .execute(&pool)
+        .await
+        .expect("Failed to insert invasive status");
+        assert!(load(1, &calendar, false, &pool)
+            .await
+            .expect("Failed to load forecast")
+            .is_empty());
+        assert_eq!(
+            load(1, &calendar, true, &pool)
+                .await
+                .expect("Failed to load forecast")
+                .len(),
+            2
+        );
+
+        assert!(load(2, &calendar, false, &pool)
+            .await
+            .expect("Failed to load forecast")
+            .is_empty());
+    }
+}
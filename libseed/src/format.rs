@@ -0,0 +1,225 @@
+//! Formatting of numbers and measurements for display, according to the preferences of a user.
+//! The same rules are used for the output of the command line tool and the filters of the web
+//! templates, so a quantity looks the same everywhere.
+use serde::{Deserialize, Serialize};
+use strum_macros::{Display, EnumIter, EnumString};
+
+/// The number of grams in an avoirdupois ounce
+pub const GRAMS_PER_OUNCE: f64 = 28.349523125;
+
+/// How the digits of a number are grouped and separated from the decimals
+#[derive(
+    Clone,
+    Copy,
+    Default,
+    Deserialize,
+    Serialize,
+    Debug,
+    sqlx::Type,
+    PartialEq,
+    Eq,
+    Display,
+    EnumString,
+    EnumIter,
+)]
+#[repr(i32)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+#[serde(rename_all = "lowercase")]
+pub enum NumberStyle {
+    /// 1,234.5
+    #[default]
+    Point = 0,
+    /// 1.234,5
+    Comma = 1,
+    /// 1 234,5
+    Space = 2,
+}
+
+impl NumberStyle {
+    fn decimal_separator(&self) -> char {
+        match self {
+            Self::Point => '.',
+            Self::Comma | Self::Space => ',',
+        }
+    }
+
+    fn group_separator(&self) -> char {
+        match self {
+            Self::Point => ',',
+            Self::Comma => '.',
+            // a no-break space, so that a number is never split across lines
+            Self::Space => '\u{a0}',
+        }
+    }
+
+    /// An example of a number in this style, for choosing a style in a form
+    pub fn example(&self) -> String {
+        NumberFormat::new(*self, WeightUnit::default()).number(1234.5, 1)
+    }
+}
+
+/// The unit that seed weights are displayed in
+#[derive(
+    Clone,
+    Copy,
+    Default,
+    Deserialize,
+    Serialize,
+    Debug,
+    sqlx::Type,
+    PartialEq,
+    Eq,
+    Display,
+    EnumString,
+    EnumIter,
+)]
+#[repr(i32)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+#[serde(rename_all = "lowercase")]
+pub enum WeightUnit {
+    #[default]
+    Grams = 0,
+    Ounces = 1,
+}
+
+impl WeightUnit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Grams => "g",
+            Self::Ounces => "oz",
+        }
+    }
+}
+
+/// Formats numbers and measurements in a user's preferred style
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct NumberFormat {
+    pub style: NumberStyle,
+    pub weight: WeightUnit,
+}
+
+impl NumberFormat {
+    pub fn new(style: NumberStyle, weight: WeightUnit) -> Self {
+        Self { style, weight }
+    }
+
+    /// Format a number rounded to the given number of decimals, with grouped thousands
+    pub fn number(&self, value: f64, decimals: usize) -> String {
+        let digits = format!("{:.*}", decimals, value.abs());
+        let (whole, fraction) = digits.split_once('.').unwrap_or((&digits, ""));
+        // don't show a sign for values that are rounded to zero
+        let negative = value < 0.0 && digits.chars().any(|c| c.is_ascii_digit() && c != '0');
+        let mut formatted = String::new();
+        if negative {
+            formatted.push('-');
+        }
+        formatted.push_str(&self.group(whole));
+        if !fraction.is_empty() {
+            formatted.push(self.style.decimal_separator());
+            formatted.push_str(fraction);
+        }
+        formatted
+    }
+
+    /// Format a whole number, e.g. a seed count, with grouped thousands
+    pub fn integer(&self, value: i64) -> String {
+        let grouped = self.group(&value.unsigned_abs().to_string());
+        match value < 0 {
+            true => format!("-{grouped}"),
+            false => grouped,
+        }
+    }
+
+    /// Format a weight that is given in grams in the preferred unit, e.g. `12.50 g` or `0.44 oz`
+    pub fn weight(&self, grams: f64) -> String {
+        let value = match self.weight {
+            WeightUnit::Grams => grams,
+            WeightUnit::Ounces => grams / GRAMS_PER_OUNCE,
+        };
+        format!("{} {}", self.number(value, 2), self.weight.symbol())
+    }
+
+    /// Format a pair of coordinates in decimal degrees with the given number of decimals. The
+    /// coordinates are separated by a semicolon if the decimal separator is a comma.
+    pub fn coordinates(&self, latitude: f64, longitude: f64, decimals: usize) -> String {
+        let separator = match self.style.decimal_separator() {
+            ',' => ';',
+            _ => ',',
+        };
+        // coordinates never have more than three whole digits, so they are not grouped
+        let degrees = |value: f64| {
+            format!("{value:.decimals$}").replace('.', &self.style.decimal_separator().to_string())
+        };
+        format!("{}{separator} {}", degrees(latitude), degrees(longitude))
+    }
+
+    fn group(&self, digits: &str) -> String {
+        let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                grouped.push(self.style.group_separator());
+            }
+            grouped.push(c);
+        }
+        grouped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers() {
+        let point = NumberFormat::default();
+        assert_eq!(point.number(1234567.891, 2), "1,234,567.89");
+        assert_eq!(point.number(999.5, 0), "1,000");
+        assert_eq!(point.number(12.0, 1), "12.0");
+        assert_eq!(point.number(-1234.5, 1), "-1,234.5");
+        assert_eq!(point.number(-0.001, 2), "0.00");
+        assert_eq!(point.integer(0), "0");
+        assert_eq!(point.integer(123), "123");
+        assert_eq!(point.integer(-1234567), "-1,234,567");
+
+        let comma = NumberFormat::new(NumberStyle::Comma, WeightUnit::Grams);
+        assert_eq!(comma.number(1234567.891, 2), "1.234.567,89");
+        assert_eq!(comma.integer(100000), "100.000");
+
+        let space = NumberFormat::new(NumberStyle::Space, WeightUnit::Grams);
+        assert_eq!(space.number(1234.5, 1), "1\u{a0}234,5");
+        assert_eq!(NumberStyle::Space.example(), "1\u{a0}234,5");
+        assert_eq!(NumberStyle::Point.example(), "1,234.5");
+    }
+
+    #[test]
+    fn test_weights() {
+        let grams = NumberFormat::default();
+        assert_eq!(grams.weight(12.5), "12.50 g");
+        assert_eq!(grams.weight(1500.0), "1,500.00 g");
+
+        let ounces = NumberFormat::new(NumberStyle::Comma, WeightUnit::Ounces);
+        assert_eq!(ounces.weight(GRAMS_PER_OUNCE * 2.0), "2,00 oz");
+        assert_eq!(ounces.weight(12.5), "0,44 oz");
+    }
+
+    #[test]
+    fn test_coordinates() {
+        let point = NumberFormat::default();
+        assert_eq!(
+            point.coordinates(44.977753, -93.265011, 5),
+            "44.97775, -93.26501"
+        );
+        let comma = NumberFormat::new(NumberStyle::Comma, WeightUnit::Grams);
+        assert_eq!(
+            comma.coordinates(44.977753, -93.265011, 3),
+            "44,978; -93,265"
+        );
+    }
+
+    #[test]
+    fn test_parse_preferences() {
+        assert_eq!("comma".parse::<NumberStyle>().unwrap(), NumberStyle::Comma);
+        assert_eq!("Ounces".parse::<WeightUnit>().unwrap(), WeightUnit::Ounces);
+        assert!("metric".parse::<WeightUnit>().is_err());
+    }
+}
@@ -0,0 +1,182 @@
+//! Statistics that summarize the results of a germination trial, and a comparison of trials of the
+//! same taxon that were germinated with different treatments.
+//!
+//! A trial is described by the number of seeds that were sown and a series of counts of the seeds
+//! that germinated on each day of the trial. The counts are the number of seeds that germinated
+//! since the previous count, not the cumulative totals.
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// The number of seeds that germinated since the previous count
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
+pub struct GerminationCount {
+    /// the day of the trial on which the count was made, counted from the day of sowing
+    pub day: u32,
+    pub germinated: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct TrialStats {
+    pub sown: u32,
+    pub germinated: u32,
+    /// the percentage of the sown seeds that germinated
+    pub percentage: f64,
+    /// the (interpolated) day on which half of the seeds that eventually germinated had
+    /// germinated. `None` if no seeds germinated.
+    pub t50: Option<f64>,
+    /// the average number of days that a germinated seed took to germinate. `None` if no seeds
+    /// germinated.
+    pub mean_time: Option<f64>,
+}
+
+impl TrialStats {
+    /// Calculate the statistics of a trial in which `sown` seeds were sown. The counts don't need
+    /// to be in order, but each day may only be counted once.
+    pub fn calculate(sown: u32, counts: &[GerminationCount]) -> Result<Self> {
+        if sown == 0 {
+            return Err(Error::InvalidValue(
+                "a trial needs at least one seed".to_string(),
+            ));
+        }
+        let mut counts = counts.to_vec();
+        counts.sort_by_key(|c| c.day);
+        if counts.windows(2).any(|w| w[0].day == w[1].day) {
+            return Err(Error::InvalidValue(
+                "each day of a trial can only be counted once".to_string(),
+            ));
+        }
+        let germinated: u32 = counts.iter().map(|c| c.germinated).sum();
+        if germinated > sown {
+            return Err(Error::InvalidValue(format!(
+                "{germinated} seeds germinated, but only {sown} were sown"
+            )));
+        }
+
+        let (t50, mean_time) = match germinated {
+            0 => (None, None),
+            _ => {
+                let total_days: f64 = counts
+                    .iter()
+                    .map(|c| f64::from(c.day) * f64::from(c.germinated))
+                    .sum();
+                (
+                    Some(t50(&counts, germinated)),
+                    Some(total_days / f64::from(germinated)),
+                )
+            }
+        };
+        Ok(Self {
+            sown,
+            germinated,
+            percentage: f64::from(germinated) * 100.0 / f64::from(sown),
+            t50,
+            mean_time,
+        })
+    }
+}
+
+/// The day on which half of the `total` germinated seeds had germinated, interpolated linearly
+/// between the two counts that surround the midpoint (Coolbear et al., 1984). The counts must be
+/// sorted by day and contain at least one germinated seed.
+fn t50(counts: &[GerminationCount], total: u32) -> f64 {
+    let half = f64::from(total) / 2.0;
+    let (mut prev_day, mut prev_total) = (0.0, 0.0);
+    for count in counts.iter().filter(|c| c.germinated > 0) {
+        let day = f64::from(count.day);
+        let cumulative = prev_total + f64::from(count.germinated);
+        if cumulative >= half {
+            // there's nothing to interpolate from if the midpoint falls within the first count
+            if prev_total == 0.0 {
+                return day;
+            }
+            return prev_day + (half - prev_total) * (day - prev_day) / (cumulative - prev_total);
+        }
+        (prev_day, prev_total) = (day, cumulative);
+    }
+    prev_day
+}
+
+/// Order the trials of a taxon so that the most effective treatment comes first: trials with a
+/// higher germination percentage first, and of trials with equal percentages, the one that
+/// germinated faster.
+pub fn compare<T>(trials: &mut [(T, TrialStats)]) {
+    trials.sort_by(|(_, a), (_, b)| {
+        b.percentage
+            .partial_cmp(&a.percentage)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| {
+                a.mean_time
+                    .unwrap_or(f64::INFINITY)
+                    .partial_cmp(&b.mean_time.unwrap_or(f64::INFINITY))
+                    .unwrap_or(Ordering::Equal)
+            })
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(values: &[(u32, u32)]) -> Vec<GerminationCount> {
+        values
+            .iter()
+            .map(|(day, germinated)| GerminationCount {
+                day: *day,
+                germinated: *germinated,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trial_stats() {
+        let stats = TrialStats::calculate(50, &counts(&[(7, 4), (3, 2), (5, 10), (10, 0)]))
+            .expect("Failed to calculate stats");
+        assert_eq!(stats.germinated, 16);
+        assert_eq!(stats.percentage, 32.0);
+        // (3 * 2 + 5 * 10 + 7 * 4) / 16
+        assert_eq!(stats.mean_time, Some(5.25));
+        // 8 seeds is the midpoint, which is reached between day 3 (2 seeds) and day 5 (12 seeds)
+        assert!((stats.t50.unwrap() - 4.2).abs() < 1e-9);
+
+        // the midpoint falls within the first count
+        let stats = TrialStats::calculate(10, &counts(&[(4, 6), (8, 2)])).unwrap();
+        assert_eq!(stats.t50, Some(4.0));
+        // the midpoint is reached exactly
+        let stats = TrialStats::calculate(10, &counts(&[(2, 2), (4, 2), (6, 4)])).unwrap();
+        assert_eq!(stats.t50, Some(4.0));
+
+        let stats = TrialStats::calculate(20, &counts(&[(5, 0)])).unwrap();
+        assert_eq!(stats.percentage, 0.0);
+        assert_eq!(stats.t50, None);
+        assert_eq!(stats.mean_time, None);
+
+        assert!(TrialStats::calculate(0, &[]).is_err());
+        assert!(TrialStats::calculate(5, &counts(&[(1, 3), (2, 3)])).is_err());
+        assert!(TrialStats::calculate(5, &counts(&[(1, 1), (1, 1)])).is_err());
+    }
+
+    #[test]
+    fn compare_trials() {
+        let mut trials = vec![
+            (
+                "untreated",
+                TrialStats::calculate(20, &counts(&[(10, 4)])).unwrap(),
+            ),
+            (
+                "cold stratified",
+                TrialStats::calculate(20, &counts(&[(10, 12)])).unwrap(),
+            ),
+            (
+                "scarified",
+                TrialStats::calculate(20, &counts(&[(4, 12)])).unwrap(),
+            ),
+            ("failed", TrialStats::calculate(20, &[]).unwrap()),
+        ];
+        compare(&mut trials);
+        assert_eq!(
+            trials.iter().map(|(name, _)| *name).collect::<Vec<_>>(),
+            vec!["scarified", "cold stratified", "untreated", "failed"]
+        );
+    }
+}
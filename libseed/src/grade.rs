@@ -0,0 +1,259 @@
+//! Quality grades give a quick overall judgement of a sample, e.g. to decide which samples to use
+//! first. Each user has a grading scale of their own: either the default A/B/C scale or any other
+//! grades that they define. Each grade has a rank that orders the scale from best to worst and
+//! criteria that describe what a sample needs to be given the grade.
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, FromRow, Pool, Row, Sqlite};
+
+/// The grades that a user's scale starts with if they choose the default scale, as (label,
+/// criteria)
+pub const DEFAULT_SCALE: [(&str, &str); 3] = [
+    (
+        "A",
+        "Clean, fully ripe seed with high fill, free of pests and disease",
+    ),
+    (
+        "B",
+        "Usable seed with some chaff, unripe or empty seed, or minor damage",
+    ),
+    (
+        "C",
+        "Poor seed that is only worth using when nothing better is available",
+    ),
+];
+
+/// A grade of a user's quality grading scale
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct QualityGrade {
+    pub id: i64,
+    pub userid: i64,
+    pub label: String,
+    /// the position of the grade in the scale, where the best grade has the lowest rank
+    pub rank: i64,
+    /// what a sample needs to be given this grade
+    pub criteria: Option<String>,
+}
+
+impl FromRow<'_, SqliteRow> for QualityGrade {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            id: row.try_get("gradeid")?,
+            userid: row.try_get("userid")?,
+            label: row.try_get("gradelabel")?,
+            rank: row.try_get("graderank")?,
+            criteria: row.try_get("gradecriteria")?,
+        })
+    }
+}
+
+impl QualityGrade {
+    pub fn new(userid: i64, label: String, rank: i64, criteria: Option<String>) -> Self {
+        Self {
+            id: -1,
+            userid,
+            label,
+            rank,
+            criteria,
+        }
+    }
+
+    /// The grade of the sample in the given row of the sample view, if it has one
+    pub(crate) fn from_sample_row(row: &SqliteRow) -> sqlx::Result<Option<Self>> {
+        match row.try_get::<Option<i64>, _>("gradeid").unwrap_or(None) {
+            Some(_) => Self::from_row(row).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        sqlx::query_as("SELECT * FROM sc_quality_grades WHERE gradeid=?")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Load the grading scale of the given user, from the best grade to the worst
+    pub async fn load_scale(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            "SELECT * FROM sc_quality_grades WHERE userid=? ORDER BY graderank, gradelabel",
+        )
+        .bind(userid)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Give a user without any grades the [default scale](DEFAULT_SCALE)
+    pub async fn create_default_scale(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        if !Self::load_scale(userid, pool).await?.is_empty() {
+            return Err(Error::InvalidOperation(
+                "You already have a grading scale".to_string(),
+            ));
+        }
+        for (rank, (label, criteria)) in DEFAULT_SCALE.iter().enumerate() {
+            Self::new(
+                userid,
+                label.to_string(),
+                rank as i64 + 1,
+                Some(criteria.to_string()),
+            )
+            .insert(pool)
+            .await?;
+        }
+        Self::load_scale(userid, pool).await
+    }
+
+    fn validate(&mut self) -> Result<()> {
+        self.label = self.label.trim().to_string();
+        if self.label.is_empty() {
+            return Err(Error::InvalidValue("The grade needs a label".to_string()));
+        }
+        self.criteria = self
+            .criteria
+            .as_ref()
+            .map(|c| c.trim().to_string())
+            .filter(|c| !c.is_empty());
+        Ok(())
+    }
+
+    /// The error for a label that is already used by another grade of the scale
+    fn map_duplicate(&self, e: sqlx::Error) -> Error {
+        match e {
+            sqlx::Error::Database(ref db) if db.is_unique_violation() => {
+                Error::InvalidValue(format!("The scale already has a grade '{}'", self.label))
+            }
+            e => e.into(),
+        }
+    }
+
+    pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        if self.id != -1 {
+            return Err(Error::InvalidOperationObjectAlreadyExists(self.id));
+        }
+        self.validate()?;
+        let res = sqlx::query(
+            r#"INSERT INTO sc_quality_grades (userid, gradelabel, graderank, gradecriteria)
+            VALUES (?, ?, ?, ?)"#,
+        )
+        .bind(self.userid)
+        .bind(&self.label)
+        .bind(self.rank)
+        .bind(&self.criteria)
+        .execute(pool)
+        .await
+        .map_err(|e| self.map_duplicate(e))?;
+        self.id = res.last_insert_rowid();
+        Ok(())
+    }
+
+    pub async fn update(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        if self.id < 0 {
+            return Err(Error::InvalidOperationObjectNotFound);
+        }
+        self.validate()?;
+        sqlx::query(
+            "UPDATE sc_quality_grades SET gradelabel=?, graderank=?, gradecriteria=? WHERE gradeid=?",
+        )
+        .bind(&self.label)
+        .bind(self.rank)
+        .bind(&self.criteria)
+        .bind(self.id)
+        .execute(pool)
+        .await
+        .map_err(|e| self.map_duplicate(e))?;
+        Ok(())
+    }
+
+    /// Remove the grade from the scale. Samples that had this grade become ungraded.
+    pub async fn delete(&self, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query("DELETE FROM sc_quality_grades WHERE gradeid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        filter::{SortOrder, SortSpec},
+        loadable::Loadable,
+        sample::{self, Sample},
+    };
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn test_grades(pool: Pool<Sqlite>) {
+        let scale = QualityGrade::create_default_scale(1, &pool).await.unwrap();
+        assert_eq!(
+            scale.iter().map(|g| g.label.as_str()).collect::<Vec<_>>(),
+            ["A", "B", "C"]
+        );
+        assert!(QualityGrade::create_default_scale(1, &pool).await.is_err());
+        assert!(QualityGrade::load_scale(2, &pool).await.unwrap().is_empty());
+
+        let mut duplicate = QualityGrade::new(1, " B ".to_string(), 5, None);
+        assert!(matches!(
+            duplicate.insert(&pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        let mut custom = QualityGrade::new(1, "Excellent".to_string(), 0, Some(" ".to_string()));
+        custom.insert(&pool).await.unwrap();
+        assert_eq!(custom.criteria, None);
+        assert_eq!(
+            QualityGrade::load_scale(1, &pool).await.unwrap()[0].label,
+            "Excellent"
+        );
+
+        // another user's grade can't be given to a sample
+        let mut other = QualityGrade::new(2, "A".to_string(), 1, None);
+        other.insert(&pool).await.unwrap();
+        let mut sample1 = Sample::load(1, &pool).await.unwrap();
+        assert!(sample1.set_grade(Some(&other), &pool).await.is_err());
+
+        sample1.set_grade(Some(&scale[1]), &pool).await.unwrap();
+        let mut sample2 = Sample::load(2, &pool).await.unwrap();
+        sample2.set_grade(Some(&scale[0]), &pool).await.unwrap();
+        assert_eq!(
+            Sample::load(1, &pool).await.unwrap().grade.as_ref(),
+            Some(&scale[1])
+        );
+        let graded = Sample::load_all_user(
+            1,
+            Some(sample::Filter::Grade(Some(scale[1].id)).into()),
+            None,
+            &pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(graded.iter().map(|s| s.id).collect::<Vec<_>>(), [1]);
+        let ungraded =
+            Sample::load_all_user(1, Some(sample::Filter::Grade(None).into()), None, &pool)
+                .await
+                .unwrap();
+        assert_eq!(ungraded.iter().map(|s| s.id).collect::<Vec<_>>(), [3]);
+        let sorted = Sample::load_all_user(
+            1,
+            None,
+            Some(SortSpec::new(sample::Sort::Grade, SortOrder::Ascending).into()),
+            &pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(sorted.iter().map(|s| s.id).collect::<Vec<_>>(), [2, 1, 3]);
+
+        // the samples of a deleted grade become ungraded
+        scale[1].delete(&pool).await.unwrap();
+        assert_eq!(Sample::load(1, &pool).await.unwrap().grade, None);
+    }
+}
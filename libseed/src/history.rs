@@ -0,0 +1,289 @@
+//! The change history of samples. Whenever one of the main fields of a sample is modified, the
+//! old and new values are recorded along with the user who made the change, so that the history of
+//! a sample can be reviewed and individual changes can be reverted.
+use crate::{
+    error::{Error, Result},
+    loadable::PartialUpdate,
+    sample::{Certainty, Sample, SampleField},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Sqlite};
+use time::OffsetDateTime;
+
+/// Taxa and sources are stored by id, so their names are looked up to show the values to the user.
+/// If the taxon or source no longer exists, the id is shown instead.
+const SELECT: &str = r#"SELECT H.*, U.username,
+    COALESCE(CASE H.historyfield
+        WHEN 'taxon' THEN (SELECT complete_name FROM taxonomic_units WHERE tsn=CAST(H.historyold AS INTEGER))
+        WHEN 'source' THEN (SELECT srcname FROM sc_sources WHERE srcid=CAST(H.historyold AS INTEGER))
+        END, H.historyold) AS oldlabel,
+    COALESCE(CASE H.historyfield
+        WHEN 'taxon' THEN (SELECT complete_name FROM taxonomic_units WHERE tsn=CAST(H.historynew AS INTEGER))
+        WHEN 'source' THEN (SELECT srcname FROM sc_sources WHERE srcid=CAST(H.historynew AS INTEGER))
+        END, H.historynew) AS newlabel
+    FROM sc_sample_history H LEFT JOIN sc_users U ON U.userid=H.userid"#;
+
+/// A change to a single field of a sample
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Change {
+    #[sqlx(rename = "historyid")]
+    pub id: i64,
+    pub sampleid: i64,
+    /// the user who made the change, if known
+    pub userid: Option<i64>,
+    pub username: Option<String>,
+    /// the name of the field, as returned by [`field_name`]
+    #[sqlx(rename = "historyfield")]
+    pub field: String,
+    #[sqlx(rename = "historyold")]
+    pub old: Option<String>,
+    #[sqlx(rename = "historynew")]
+    pub new: Option<String>,
+    /// the old and new values in a form that can be shown to the user
+    #[sqlx(rename = "oldlabel")]
+    pub old_label: Option<String>,
+    #[sqlx(rename = "newlabel")]
+    pub new_label: Option<String>,
+    #[sqlx(rename = "historydate")]
+    pub date: OffsetDateTime,
+}
+
+/// The name that is used for a field in the change history
+pub fn field_name(field: &SampleField) -> &'static str {
+    match field {
+        SampleField::Taxon(_) => "taxon",
+        SampleField::Source(_) => "source",
+        SampleField::Quantity(_) => "quantity",
+        SampleField::Notes(_) => "notes",
+        SampleField::Date { .. } => "date",
+        SampleField::Certainty(_) => "certainty",
+    }
+}
+
+/// The value of a field as it is stored in the change history
+fn field_value(field: &SampleField) -> Option<String> {
+    match field {
+        SampleField::Taxon(id) | SampleField::Source(id) => Some(id.to_string()),
+        SampleField::Quantity(quantity) => quantity.map(|q| q.to_string()),
+        SampleField::Notes(notes) => notes.clone(),
+        SampleField::Date { month, year } => match (month, year) {
+            (Some(month), Some(year)) => Some(format!("{month}/{year}")),
+            (None, Some(year)) => Some(year.to_string()),
+            _ => None,
+        },
+        SampleField::Certainty(certainty) => Some(certainty.to_string()),
+    }
+}
+
+/// Parse a value that was stored in the change history by [`field_value`]
+fn parse_field(name: &str, value: Option<&str>) -> Result<SampleField> {
+    let invalid =
+        || Error::InvalidValue(format!("'{}' is not a valid {name}", value.unwrap_or("")));
+    let number = |v: &str| v.parse::<i64>().map_err(|_| invalid());
+    let date_part = |v: &str| v.parse::<u32>().map_err(|_| invalid());
+    Ok(match (name, value) {
+        ("taxon", Some(v)) => SampleField::Taxon(number(v)?),
+        ("source", Some(v)) => SampleField::Source(number(v)?),
+        ("quantity", v) => SampleField::Quantity(v.map(number).transpose()?),
+        ("notes", v) => SampleField::Notes(v.map(str::to_string)),
+        ("date", None) => SampleField::Date {
+            month: None,
+            year: None,
+        },
+        ("date", Some(v)) => match v.split_once('/') {
+            Some((month, year)) => SampleField::Date {
+                month: Some(date_part(month)?),
+                year: Some(date_part(year)?),
+            },
+            None => SampleField::Date {
+                month: None,
+                year: Some(date_part(v)?),
+            },
+        },
+        ("certainty", Some("Certain")) => SampleField::Certainty(Certainty::Certain),
+        ("certainty", Some("Uncertain")) => SampleField::Certainty(Certainty::Uncertain),
+        _ => return Err(invalid()),
+    })
+}
+
+/// The current values of all of the fields of a sample that are tracked in the history
+fn tracked_fields(sample: &Sample) -> [SampleField; 6] {
+    [
+        SampleField::Taxon(sample.taxon.id()),
+        SampleField::Source(sample.source.id()),
+        SampleField::Quantity(sample.quantity),
+        SampleField::Date {
+            month: sample.month,
+            year: sample.year,
+        },
+        SampleField::Certainty(sample.certainty.clone()),
+        SampleField::Notes(sample.notes.clone()),
+    ]
+}
+
+/// Record the differences between two versions of a sample in its history. `before` is the sample
+/// as it was loaded from the database and `after` is the sample once it has been saved. Returns
+/// the number of fields that changed.
+pub async fn record(
+    before: &Sample,
+    after: &Sample,
+    userid: Option<i64>,
+    pool: &Pool<Sqlite>,
+) -> Result<usize> {
+    if before.id != after.id {
+        return Err(Error::InvalidValue(
+            "the history can only compare versions of the same sample".to_string(),
+        ));
+    }
+    let mut changed = 0;
+    for (old, new) in tracked_fields(before)
+        .into_iter()
+        .zip(tracked_fields(after))
+        .filter(|(old, new)| old != new)
+    {
+        sqlx::query(
+            r#"INSERT INTO sc_sample_history (sampleid, userid, historyfield, historyold, historynew)
+            VALUES (?, ?, ?, ?, ?)"#,
+        )
+        .bind(after.id)
+        .bind(userid)
+        .bind(field_name(&new))
+        .bind(field_value(&old))
+        .bind(field_value(&new))
+        .execute(pool)
+        .await?;
+        changed += 1;
+    }
+    Ok(changed)
+}
+
+impl Change {
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        sqlx::query_as(&format!("{SELECT} WHERE H.historyid=?"))
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Load the history of the given sample, most recent changes first
+    pub async fn load_sample(sampleid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(&format!(
+            "{SELECT} WHERE H.sampleid=? ORDER BY H.historydate DESC, H.historyid DESC"
+        ))
+        .bind(sampleid)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// The value of the field before the change was made
+    pub fn old_value(&self) -> Result<SampleField> {
+        parse_field(&self.field, self.old.as_deref())
+    }
+
+    /// Set the field that was modified by this change back to the value it had before the change.
+    /// The revert is recorded in the history of the sample as a change by the given user.
+    pub async fn revert(
+        &self,
+        sample: &mut Sample,
+        userid: i64,
+        pool: &Pool<Sqlite>,
+    ) -> Result<()> {
+        if sample.id != self.sampleid {
+            return Err(Error::InvalidValue(format!(
+                "change {} does not belong to sample {}",
+                self.id, sample.id
+            )));
+        }
+        let before = sample.clone();
+        sample.update_field(self.old_value()?, pool).await?;
+        record(&before, sample, Some(userid), pool).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loadable::{ExternalRef, Loadable};
+    use test_log::test;
+
+    #[test]
+    fn test_parse_field() {
+        for field in [
+            SampleField::Taxon(40683),
+            SampleField::Quantity(None),
+            SampleField::Quantity(Some(12)),
+            SampleField::Notes(Some("Collected by hand".to_string())),
+            SampleField::Date {
+                month: Some(9),
+                year: Some(2023),
+            },
+            SampleField::Date {
+                month: None,
+                year: Some(2023),
+            },
+            SampleField::Certainty(Certainty::Uncertain),
+        ] {
+            assert_eq!(
+                parse_field(field_name(&field), field_value(&field).as_deref()).unwrap(),
+                field
+            );
+        }
+        assert!(parse_field("taxon", None).is_err());
+        assert!(parse_field("quantity", Some("lots")).is_err());
+        assert!(parse_field("color", Some("red")).is_err());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn test_history(pool: Pool<Sqlite>) {
+        let before = Sample::load(1, &pool).await.unwrap();
+        let mut sample = before.clone();
+        sample.quantity = Some(before.quantity.unwrap_or(0) + 100);
+        sample.notes = Some("Recounted".to_string());
+        sample.update(&pool).await.unwrap();
+        assert_eq!(record(&before, &sample, Some(1), &pool).await.unwrap(), 2);
+        // nothing is recorded if nothing changed
+        assert_eq!(record(&sample, &sample, Some(1), &pool).await.unwrap(), 0);
+
+        let history = Change::load_sample(1, &pool).await.unwrap();
+        assert_eq!(history.len(), 2);
+        let quantity = history.iter().find(|c| c.field == "quantity").unwrap();
+        assert_eq!(quantity.username.as_deref(), Some("testuser"));
+        assert_eq!(quantity.old, before.quantity.map(|q| q.to_string()));
+        assert!(Change::load_sample(2, &pool).await.unwrap().is_empty());
+
+        // reverting restores the old value and is recorded as well
+        quantity.revert(&mut sample, 1, &pool).await.unwrap();
+        let reloaded = Sample::load(1, &pool).await.unwrap();
+        assert_eq!(reloaded.quantity, before.quantity);
+        assert_eq!(reloaded.notes.as_deref(), Some("Recounted"));
+        let history = Change::load_sample(1, &pool).await.unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].new, quantity.old);
+
+        // changes to the taxon show the names of the taxa
+        let mut other = reloaded.clone();
+        other.taxon = ExternalRef::Stub(40683);
+        other.update(&pool).await.unwrap();
+        record(&reloaded, &other, None, &pool).await.unwrap();
+        let change = &Change::load_sample(1, &pool).await.unwrap()[0];
+        assert_eq!(change.field, "taxon");
+        assert_eq!(
+            change.old_label.as_deref(),
+            Some(reloaded.taxon.object().unwrap().complete_name.as_str())
+        );
+        assert_eq!(change.username, None);
+
+        // a change can only be reverted on its own sample
+        let mut unrelated = Sample::load(2, &pool).await.unwrap();
+        assert!(change.revert(&mut unrelated, 1, &pool).await.is_err());
+    }
+}
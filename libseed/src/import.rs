@@ -0,0 +1,1084 @@
+//! Importing samples from spreadsheets exported by other seed collection tools. The columns of the
+//! spreadsheet are mapped to sample fields with a [ColumnMapping], and each row is converted to an
+//! [ImportRecord]. A [SampleImport] keeps an uploaded CSV file in the database so that the mapping
+//! can be chosen and the result previewed before the samples are added in the background. The file
+//! is stored in chunks of at most [CHUNK_SIZE] bytes, up to [MAX_UPLOAD_SIZE] in total, which can
+//! be uploaded one at a time, and its rows are parsed from one chunk at a time with an
+//! [ImportReader], so that large files never have to be held in memory as a whole.
+use crate::{
+    conservation::{self, PermitPolicy},
+    error::{Error, Result},
+    loadable::Loadable,
+    preferences::Preferences,
+    sample::{Certainty, Sample},
+    source::Source,
+    taxonomy::{import::TaxaMatcher, Taxon},
+};
+use csv_core::ReadRecordResult;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Sqlite};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt,
+};
+use strum_macros::{Display, EnumString};
+use time::{Duration, OffsetDateTime};
+
+/// How many rows are imported between updates of the recorded progress
+const PROGRESS_INTERVAL: i64 = 20;
+
+/// The maximum size in bytes of a single chunk of an uploaded file
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The maximum size in bytes of an uploaded file, counting all of its chunks
+pub const MAX_UPLOAD_SIZE: usize = 64 * CHUNK_SIZE;
+
+/// Why a row of a spreadsheet could not be converted to an [ImportRecord]
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum MappingError {
+    #[error("No column is mapped to the '{0}' field")]
+    MissingRequiredField(SampleField),
+    #[error("Column '{0}' has an invalid value '{1}'")]
+    InvalidValue(String, String),
+}
+
+/// The sample fields that a spreadsheet column can be mapped to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum SampleField {
+    Taxon,
+    Source,
+    Date,
+    Month,
+    Year,
+    Quantity,
+    Notes,
+    Uncertain,
+}
+
+impl SampleField {
+    pub const ALL: [SampleField; 8] = [
+        SampleField::Taxon,
+        SampleField::Source,
+        SampleField::Date,
+        SampleField::Month,
+        SampleField::Year,
+        SampleField::Quantity,
+        SampleField::Notes,
+        SampleField::Uncertain,
+    ];
+
+    /// Guess which field a column represents based on common column names used by other tools
+    pub fn guess(header: &str) -> Option<Self> {
+        let header = header.trim().to_lowercase().replace(['_', '-', '.'], " ");
+        match header.as_str() {
+            "taxon" | "taxon name" | "species" | "scientific name" | "botanical name"
+            | "latin name" | "name" | "accepted name" | "full name" | "tsn" => Some(Self::Taxon),
+            "source" | "source name" | "location" | "locality" | "collection site" | "site"
+            | "supplier" | "vendor" | "provenance" | "origin" => Some(Self::Source),
+            "date" | "collection date" | "coll date" | "collected" | "date collected"
+            | "harvest date" | "accession date" => Some(Self::Date),
+            "month" | "collection month" => Some(Self::Month),
+            "year" | "collection year" | "harvest year" | "packed for" => Some(Self::Year),
+            "quantity" | "qty" | "count" | "seed count" | "amount" => Some(Self::Quantity),
+            "notes" | "note" | "comments" | "comment" | "remarks" | "description" => {
+                Some(Self::Notes)
+            }
+            "uncertain" | "certainty" | "id uncertain" | "cf" | "id confidence" => {
+                Some(Self::Uncertain)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SampleField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            SampleField::Taxon => "Taxon",
+            SampleField::Source => "Source",
+            SampleField::Date => "Date",
+            SampleField::Month => "Month",
+            SampleField::Year => "Year",
+            SampleField::Quantity => "Quantity",
+            SampleField::Notes => "Notes",
+            SampleField::Uncertain => "Uncertain",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// A mapping from spreadsheet column names to sample fields. Columns that are not in the mapping
+/// are ignored.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq)]
+pub struct ColumnMapping {
+    pub columns: BTreeMap<String, SampleField>,
+}
+
+impl ColumnMapping {
+    /// A mapping of the columns with a recognized name, see [SampleField::guess]
+    pub fn guess<'a>(headers: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            columns: headers
+                .into_iter()
+                .filter_map(|h| SampleField::guess(h).map(|f| (h.to_string(), f)))
+                .collect(),
+        }
+    }
+
+    /// Make sure that the required fields are mapped to a column
+    pub fn validate(&self) -> Result<(), MappingError> {
+        for required in [SampleField::Taxon, SampleField::Source] {
+            if !self.columns.values().any(|f| *f == required) {
+                return Err(MappingError::MissingRequiredField(required));
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert a single row of the spreadsheet to an [ImportRecord] using this mapping
+    pub fn map_record<'a>(
+        &self,
+        headers: impl IntoIterator<Item = &'a str>,
+        values: impl IntoIterator<Item = &'a str>,
+    ) -> Result<ImportRecord, MappingError> {
+        let mut result = ImportRecord::default();
+        for (header, value) in headers.into_iter().zip(values) {
+            let value = value.trim();
+            let Some(field) = self.columns.get(header) else {
+                continue;
+            };
+            if value.is_empty() {
+                continue;
+            }
+            let invalid = || MappingError::InvalidValue(header.to_string(), value.to_string());
+            match field {
+                SampleField::Taxon => result.taxon = value.to_string(),
+                SampleField::Source => result.source = value.to_string(),
+                SampleField::Date => {
+                    let (month, year) = parse_date(value).ok_or_else(invalid)?;
+                    result.month = result.month.or(month);
+                    result.year = Some(year);
+                }
+                SampleField::Month => {
+                    result.month = Some(parse_month(value).ok_or_else(invalid)?);
+                }
+                SampleField::Year => result.year = Some(value.parse().map_err(|_| invalid())?),
+                SampleField::Quantity => {
+                    // allow for things like "1,200" or "~300", but not a fractional amount like
+                    // "2.5", which is most likely a weight rather than a seed count
+                    if value.contains('.') {
+                        return Err(invalid());
+                    }
+                    let digits: String = value.chars().filter(|c| c.is_ascii_digit()).collect();
+                    result.quantity = Some(digits.parse().map_err(|_| invalid())?);
+                }
+                SampleField::Notes => match result.notes {
+                    Some(ref mut notes) => {
+                        notes.push('\n');
+                        notes.push_str(value);
+                    }
+                    None => result.notes = Some(value.to_string()),
+                },
+                SampleField::Uncertain => {
+                    result.uncertain = matches!(
+                        value.to_lowercase().as_str(),
+                        "1" | "y" | "yes" | "true" | "x" | "?" | "cf" | "uncertain"
+                    )
+                }
+            }
+        }
+        if result.taxon.is_empty() {
+            return Err(MappingError::MissingRequiredField(SampleField::Taxon));
+        }
+        if result.source.is_empty() {
+            return Err(MappingError::MissingRequiredField(SampleField::Source));
+        }
+        Ok(result)
+    }
+}
+
+/// The values for a single sample read from a spreadsheet row. The taxon and source are still
+/// names at this point and need to be matched against the database.
+#[derive(Debug, Default, Clone, Serialize, PartialEq)]
+pub struct ImportRecord {
+    pub taxon: String,
+    pub source: String,
+    pub month: Option<u32>,
+    pub year: Option<u32>,
+    pub quantity: Option<i64>,
+    pub notes: Option<String>,
+    pub uncertain: bool,
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+fn parse_month(s: &str) -> Option<u32> {
+    if let Ok(m) = s.parse::<u32>() {
+        return (1..=12).contains(&m).then_some(m);
+    }
+    let s = s.to_lowercase();
+    MONTHS
+        .iter()
+        .position(|m| s.starts_with(m))
+        .map(|pos| pos as u32 + 1)
+}
+
+fn parse_year(s: &str) -> Option<u32> {
+    s.parse::<u32>().ok().filter(|y| *y > 999)
+}
+
+/// Parse the month and year from the date formats commonly found in spreadsheets, e.g.
+/// "2023-09-14", "2023-09", "9/14/2023", "9/2023", "Sep 2023" or just "2023"
+fn parse_date(s: &str) -> Option<(Option<u32>, u32)> {
+    let parts: Vec<&str> = s
+        .split(['-', '/', ' ', '.', ','])
+        .filter(|p| !p.is_empty())
+        .collect();
+    match parts.as_slice() {
+        [y] => parse_year(y).map(|y| (None, y)),
+        [a, b] => match parse_year(a) {
+            Some(y) => parse_month(b).map(|m| (Some(m), y)),
+            None => Some((Some(parse_month(a)?), parse_year(b)?)),
+        },
+        [a, b, c] => match parse_year(a) {
+            Some(y) => parse_month(b).map(|m| (Some(m), y)),
+            None => {
+                // either month/day/year or day month year
+                let y = parse_year(c)?;
+                match parse_month(a) {
+                    Some(m) if a.parse::<u32>().is_err() || b.parse::<u32>().is_ok() => {
+                        Some((Some(m), y))
+                    }
+                    _ => Some((Some(parse_month(b)?), y)),
+                }
+            }
+        },
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, sqlx::Type, PartialEq, Display)]
+#[repr(i32)]
+pub enum ImportStatus {
+    /// the file was uploaded, but its columns haven't been mapped yet
+    Uploaded = 0,
+    Running = 1,
+    Finished = 2,
+    Failed = 3,
+    /// the chunks of the file are still being uploaded
+    Uploading = 4,
+}
+
+/// A row of an import that could not be imported
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct RowError {
+    /// the line of the file, where line 1 is the header
+    #[sqlx(rename = "importrow")]
+    pub line: i64,
+    #[sqlx(rename = "importmessage")]
+    pub message: String,
+}
+
+/// How a row of an import would be imported with a given mapping
+#[derive(Debug, Serialize, Clone)]
+pub struct PreviewRow {
+    /// the line of the file, where line 1 is the header
+    pub line: i64,
+    pub record: Option<ImportRecord>,
+    /// the taxon that the row was matched to
+    pub taxon: Option<Taxon>,
+    /// the existing source that the row was matched to. If the row can be imported but no source
+    /// was matched, a new source is created for it.
+    pub source: Option<Source>,
+    pub error: Option<String>,
+}
+
+/// A CSV file of samples that was uploaded to be imported. See the [module documentation](self).
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct SampleImport {
+    #[sqlx(rename = "importid")]
+    pub id: i64,
+    /// the user that uploaded the file, who the samples are added for
+    pub userid: i64,
+    #[sqlx(rename = "importfilename")]
+    pub filename: String,
+    #[sqlx(rename = "importstatus")]
+    pub status: ImportStatus,
+    /// the number of rows in the file, not counting the header. This is only known once the
+    /// upload is finished.
+    #[sqlx(rename = "importtotal")]
+    pub total: i64,
+    #[sqlx(rename = "importprocessed")]
+    pub processed: i64,
+    /// the number of samples that were added
+    #[sqlx(rename = "importadded")]
+    pub added: i64,
+    /// why the import failed as a whole, if it did
+    #[sqlx(rename = "importerror")]
+    pub error: Option<String>,
+    #[sqlx(rename = "importcreated")]
+    pub created: Option<OffsetDateTime>,
+}
+
+/// Parses CSV records incrementally from the chunks of a file. A record can span several chunks,
+/// so the fields of the current record are kept until it is complete.
+struct CsvParser {
+    reader: csv_core::Reader,
+    output: Vec<u8>,
+    ends: Vec<usize>,
+    outlen: usize,
+    endlen: usize,
+}
+
+impl CsvParser {
+    fn new() -> Self {
+        Self {
+            reader: csv_core::Reader::new(),
+            output: vec![0; 1024],
+            ends: vec![0; 32],
+            outlen: 0,
+            endlen: 0,
+        }
+    }
+
+    /// Parse the next chunk of the file and add the records that were completed by it to
+    /// `records`. An empty chunk marks the end of the file.
+    fn feed(&mut self, mut input: &[u8], records: &mut VecDeque<Vec<String>>) -> Result<()> {
+        loop {
+            let (res, nin, nout, nend) = self.reader.read_record(
+                input,
+                &mut self.output[self.outlen..],
+                &mut self.ends[self.endlen..],
+            );
+            input = &input[nin..];
+            self.outlen += nout;
+            self.endlen += nend;
+            match res {
+                ReadRecordResult::InputEmpty | ReadRecordResult::End => return Ok(()),
+                ReadRecordResult::OutputFull => self.output.resize(self.output.len() * 2, 0),
+                ReadRecordResult::OutputEndsFull => self.ends.resize(self.ends.len() * 2, 0),
+                ReadRecordResult::Record => {
+                    let mut start = 0;
+                    let mut record = Vec::with_capacity(self.endlen);
+                    for &end in &self.ends[..self.endlen] {
+                        let field =
+                            std::str::from_utf8(&self.output[start..end]).map_err(|_| {
+                                Error::InvalidValue(
+                                    "The file is not a UTF-8 encoded CSV file".to_string(),
+                                )
+                            })?;
+                        record.push(field.trim().to_string());
+                        start = end;
+                    }
+                    records.push_back(record);
+                    self.outlen = 0;
+                    self.endlen = 0;
+                }
+            }
+        }
+    }
+}
+
+/// Reads the rows of an uploaded file, loading one chunk of it at a time. See
+/// [SampleImport::reader].
+pub struct ImportReader<'a> {
+    importid: i64,
+    pool: &'a Pool<Sqlite>,
+    parser: CsvParser,
+    records: VecDeque<Vec<String>>,
+    next_chunk: i64,
+    finished: bool,
+    line: i64,
+    /// the names of the columns of the file
+    pub headers: Vec<String>,
+}
+
+impl<'a> ImportReader<'a> {
+    async fn next_record(&mut self) -> Result<Option<Vec<String>>> {
+        loop {
+            if let Some(record) = self.records.pop_front() {
+                return Ok(Some(record));
+            }
+            if self.finished {
+                return Ok(None);
+            }
+            let chunk: Option<Vec<u8>> = sqlx::query_scalar(
+                "SELECT chunkdata FROM sc_sample_import_chunks WHERE importid=? AND chunkno=?",
+            )
+            .bind(self.importid)
+            .bind(self.next_chunk)
+            .fetch_optional(self.pool)
+            .await?;
+            match chunk {
+                Some(data) => {
+                    self.parser.feed(&data, &mut self.records)?;
+                    self.next_chunk += 1;
+                }
+                None => {
+                    self.parser.feed(&[], &mut self.records)?;
+                    self.finished = true;
+                }
+            }
+        }
+    }
+
+    /// The values of the next row of the file with its line number, where line 1 is the header
+    pub async fn next_row(&mut self) -> Result<Option<(i64, Vec<String>)>> {
+        let record = self.next_record().await?;
+        Ok(record.map(|values| {
+            self.line += 1;
+            (self.line, values)
+        }))
+    }
+}
+
+/// Matches the taxa and sources of import records for a user, caching the results
+struct RowResolver {
+    userid: i64,
+    taxa: TaxaMatcher,
+    sources: HashMap<String, Source>,
+    prefs: Preferences,
+}
+
+impl RowResolver {
+    async fn new(userid: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        Ok(Self {
+            userid,
+            taxa: TaxaMatcher::new(),
+            sources: Source::load_all_user(userid, pool)
+                .await?
+                .into_iter()
+                .map(|src| (src.name.to_lowercase(), src))
+                .collect(),
+            prefs: Preferences::load(userid, pool).await?,
+        })
+    }
+
+    /// Match the taxon of the record and check that the user may add a sample of it. Returns the
+    /// taxon id and the existing source of the record, if there is one.
+    async fn resolve(
+        &mut self,
+        record: &ImportRecord,
+        pool: &Pool<Sqlite>,
+    ) -> Result<(i64, Option<Source>), String> {
+        let tsn = self
+            .taxa
+            .resolve(&record.taxon, pool)
+            .await
+            .map_err(|e| e.to_string())?;
+        // there is nobody to show warnings to, so only the 'require' policy is applied
+        if self.prefs.permit_policy == PermitPolicy::Require {
+            let today = OffsetDateTime::now_utc().date();
+            let unpermitted = conservation::unpermitted_listings(
+                self.userid,
+                tsn,
+                self.prefs.region.as_deref(),
+                today,
+                pool,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+            self.prefs
+                .permit_policy
+                .check(&unpermitted)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok((
+            tsn,
+            self.sources.get(&record.source.to_lowercase()).cloned(),
+        ))
+    }
+
+    /// The id of the source of the record, adding a new source if it doesn't exist yet
+    async fn source_id(&mut self, record: &ImportRecord, pool: &Pool<Sqlite>) -> Result<i64> {
+        if let Some(src) = self.sources.get(&record.source.to_lowercase()) {
+            return Ok(src.id);
+        }
+        let mut src = Source::new(record.source.clone(), None, None, None, self.userid);
+        src.insert(pool).await?;
+        let id = src.id;
+        self.sources.insert(record.source.to_lowercase(), src);
+        Ok(id)
+    }
+}
+
+impl SampleImport {
+    /// Start a new upload of a CSV file for the given user. The file is added with
+    /// [add_chunk](SampleImport::add_chunk) and the upload completed with
+    /// [finish_upload](SampleImport::finish_upload).
+    pub async fn create(userid: i64, filename: String, pool: &Pool<Sqlite>) -> Result<Self> {
+        let res = sqlx::query(
+            "INSERT INTO sc_sample_imports (userid, importfilename, importstatus) VALUES (?, ?, ?)",
+        )
+        .bind(userid)
+        .bind(filename)
+        .bind(ImportStatus::Uploading)
+        .execute(pool)
+        .await?;
+        Self::load(res.last_insert_rowid(), pool).await
+    }
+
+    /// Store the chunk with the given index of the uploaded file. The chunks have to be added in
+    /// order, but a chunk can be added again, e.g. when its upload has to be retried.
+    pub async fn add_chunk(&self, index: i64, data: &[u8], pool: &Pool<Sqlite>) -> Result<()> {
+        if self.status != ImportStatus::Uploading {
+            return Err(Error::InvalidOperation(
+                "The upload of this file is already finished".to_string(),
+            ));
+        }
+        if data.is_empty() || data.len() > CHUNK_SIZE {
+            return Err(Error::InvalidValue(format!(
+                "A chunk must contain between 1 and {CHUNK_SIZE} bytes"
+            )));
+        }
+        // a chunk that is uploaded again replaces the earlier upload, so it doesn't count towards
+        // the size of the file
+        let (count, size): (i64, i64) = sqlx::query_as(
+            r#"SELECT COUNT(*), COALESCE(SUM(IIF(chunkno=?, 0, LENGTH(chunkdata))), 0)
+            FROM sc_sample_import_chunks WHERE importid=?"#,
+        )
+        .bind(index)
+        .bind(self.id)
+        .fetch_one(pool)
+        .await?;
+        if index < 0 || index > count {
+            return Err(Error::InvalidValue(format!(
+                "Expected chunk {count} of the file, but got chunk {index}"
+            )));
+        }
+        if usize::try_from(size).unwrap_or(usize::MAX) + data.len() > MAX_UPLOAD_SIZE {
+            return Err(Error::InvalidValue(format!(
+                "The file is larger than the maximum of {} MiB",
+                MAX_UPLOAD_SIZE / (1024 * 1024)
+            )));
+        }
+        sqlx::query(
+            r#"INSERT OR REPLACE INTO sc_sample_import_chunks (importid, chunkno, chunkdata)
+            VALUES (?, ?, ?)"#,
+        )
+        .bind(self.id)
+        .bind(index)
+        .bind(data)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Complete the upload after all chunks were added. The file is read once to make sure that
+    /// it is a valid CSV file with a header row and to count its rows.
+    pub async fn finish_upload(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        if self.status != ImportStatus::Uploading {
+            return Err(Error::InvalidOperation(
+                "The upload of this file is already finished".to_string(),
+            ));
+        }
+        let mut reader = self.reader(pool).await?;
+        let mut total = 0;
+        while reader.next_row().await?.is_some() {
+            total += 1;
+        }
+        let res = sqlx::query(
+            r#"UPDATE sc_sample_imports SET importstatus=?, importtotal=?
+            WHERE importid=? AND importstatus=?"#,
+        )
+        .bind(ImportStatus::Uploaded)
+        .bind(total)
+        .bind(self.id)
+        .bind(ImportStatus::Uploading)
+        .execute(pool)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::InvalidOperation(
+                "The upload of this file is already finished".to_string(),
+            ));
+        }
+        self.status = ImportStatus::Uploaded;
+        self.total = total;
+        Ok(())
+    }
+
+    /// A reader for the rows of the file, which has already read the header row
+    pub async fn reader<'a>(&self, pool: &'a Pool<Sqlite>) -> Result<ImportReader<'a>> {
+        let mut reader = ImportReader {
+            importid: self.id,
+            pool,
+            parser: CsvParser::new(),
+            records: VecDeque::new(),
+            next_chunk: 0,
+            finished: false,
+            line: 1,
+            headers: Vec::new(),
+        };
+        let mut headers = reader.next_record().await?.unwrap_or_default();
+        // spreadsheet applications often start their CSV files with a byte order mark
+        if let Some(first) = headers.first_mut() {
+            *first = first.trim_start_matches('\u{feff}').trim().to_string();
+        }
+        if headers.iter().all(|h| h.is_empty()) {
+            return Err(Error::InvalidValue(
+                "The file has no header row".to_string(),
+            ));
+        }
+        reader.headers = headers;
+        Ok(reader)
+    }
+
+    /// The names of the columns of the file
+    pub async fn headers(&self, pool: &Pool<Sqlite>) -> Result<Vec<String>> {
+        Ok(self.reader(pool).await?.headers)
+    }
+
+    /// Remove the uploads that were started more than `max_age` ago but never finished, e.g.
+    /// because the upload page was closed. Returns the number of uploads that were removed.
+    pub async fn delete_abandoned(max_age: Duration, pool: &Pool<Sqlite>) -> Result<u64> {
+        let res = sqlx::query(
+            "DELETE FROM sc_sample_imports WHERE importstatus=? AND importcreated < datetime('now', ?)",
+        )
+        .bind(ImportStatus::Uploading)
+        .bind(format!("-{} seconds", max_age.whole_seconds()))
+        .execute(pool)
+        .await?;
+        Ok(res.rows_affected())
+    }
+
+    /// Remove the import, e.g. when the uploaded file turned out not to be valid
+    pub async fn delete(&self, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query("DELETE FROM sc_sample_imports WHERE importid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        sqlx::query_as("SELECT * FROM sc_sample_imports WHERE importid=?")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Load the imports of the given user, the most recent first
+    pub async fn load_all_user(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            "SELECT * FROM sc_sample_imports WHERE userid=? ORDER BY importcreated DESC, importid DESC",
+        )
+        .bind(userid)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Show how the first `limit` rows of the file would be imported with the given mapping,
+    /// without changing anything
+    pub async fn preview(
+        &self,
+        mapping: &ColumnMapping,
+        limit: usize,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<PreviewRow>> {
+        let mut reader = self.reader(pool).await?;
+        let mut resolver = RowResolver::new(self.userid, pool).await?;
+        let mut preview = Vec::new();
+        while preview.len() < limit {
+            let Some((line, values)) = reader.next_row().await? else {
+                break;
+            };
+            let mut result = PreviewRow {
+                line,
+                record: None,
+                taxon: None,
+                source: None,
+                error: None,
+            };
+            match mapping.map_record(
+                reader.headers.iter().map(String::as_str),
+                values.iter().map(String::as_str),
+            ) {
+                Ok(record) => {
+                    match resolver.resolve(&record, pool).await {
+                        Ok((tsn, source)) => {
+                            result.taxon = Some(Taxon::load(tsn, pool).await?);
+                            result.source = source;
+                        }
+                        Err(e) => result.error = Some(e),
+                    }
+                    result.record = Some(record);
+                }
+                Err(e) => result.error = Some(e.to_string()),
+            }
+            preview.push(result);
+        }
+        Ok(preview)
+    }
+
+    /// Mark the import as running, so that it isn't started twice
+    pub async fn start(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        let res = sqlx::query(
+            "UPDATE sc_sample_imports SET importstatus=? WHERE importid=? AND importstatus=?",
+        )
+        .bind(ImportStatus::Running)
+        .bind(self.id)
+        .bind(ImportStatus::Uploaded)
+        .execute(pool)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::InvalidOperation(
+                "the import has already been started".to_string(),
+            ));
+        }
+        self.status = ImportStatus::Running;
+        Ok(())
+    }
+
+    async fn save_progress(&self, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query(
+            r#"UPDATE sc_sample_imports SET importstatus=?, importprocessed=?, importadded=?,
+            importerror=? WHERE importid=?"#,
+        )
+        .bind(self.status)
+        .bind(self.processed)
+        .bind(self.added)
+        .bind(&self.error)
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Add a sample for each row of the file using the given mapping. Sources that don't exist
+    /// yet are added as well. Rows that can't be imported are skipped and recorded for the
+    /// [error report](SampleImport::errors). The progress is saved periodically while the import
+    /// runs. The import must have been [started](SampleImport::start).
+    pub async fn run(&mut self, mapping: &ColumnMapping, pool: &Pool<Sqlite>) -> Result<()> {
+        if self.status != ImportStatus::Running {
+            return Err(Error::InvalidOperation(
+                "the import has not been started".to_string(),
+            ));
+        }
+        mapping
+            .validate()
+            .map_err(|e| Error::InvalidValue(e.to_string()))?;
+        let mut reader = self.reader(pool).await?;
+        let mut resolver = RowResolver::new(self.userid, pool).await?;
+        while let Some((line, values)) = reader.next_row().await? {
+            let res = match mapping.map_record(
+                reader.headers.iter().map(String::as_str),
+                values.iter().map(String::as_str),
+            ) {
+                Ok(record) => match resolver.resolve(&record, pool).await {
+                    Ok((tsn, _)) => match resolver.source_id(&record, pool).await {
+                        Ok(srcid) => {
+                            let certainty = match record.uncertain {
+                                true => Certainty::Uncertain,
+                                false => Certainty::Certain,
+                            };
+                            let mut sample = Sample::new(
+                                tsn,
+                                self.userid,
+                                srcid,
+                                record.month,
+                                record.year,
+                                record.quantity,
+                                record.notes,
+                                certainty,
+                            );
+                            sample
+                                .insert(pool)
+                                .await
+                                .map(|_| ())
+                                .map_err(|e| e.to_string())
+                        }
+                        // e.g. the new source was rejected by a plugin
+                        Err(e) => Err(e.to_string()),
+                    },
+                    Err(e) => Err(e),
+                },
+                Err(e) => Err(e.to_string()),
+            };
+            match res {
+                Ok(()) => self.added += 1,
+                Err(message) => {
+                    sqlx::query(
+                        r#"INSERT INTO sc_sample_import_errors (importid, importrow, importmessage)
+                        VALUES (?, ?, ?)"#,
+                    )
+                    .bind(self.id)
+                    .bind(line)
+                    .bind(message)
+                    .execute(pool)
+                    .await?;
+                }
+            }
+            self.processed += 1;
+            if self.processed % PROGRESS_INTERVAL == 0 {
+                self.save_progress(pool).await?;
+            }
+        }
+        self.status = ImportStatus::Finished;
+        self.save_progress(pool).await
+    }
+
+    /// Record that the import could not be completed
+    pub async fn fail(&mut self, error: &str, pool: &Pool<Sqlite>) -> Result<()> {
+        self.status = ImportStatus::Failed;
+        self.error = Some(error.to_string());
+        self.save_progress(pool).await
+    }
+
+    /// The rows that could not be imported, in the order of the file
+    pub async fn errors(&self, pool: &Pool<Sqlite>) -> Result<Vec<RowError>> {
+        sqlx::query_as("SELECT * FROM sc_sample_import_errors WHERE importid=? ORDER BY importrow")
+            .bind(self.id)
+            .fetch_all(pool)
+            .await
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(parse_date("2023-09-14"), Some((Some(9), 2023)));
+        assert_eq!(parse_date("2023-09"), Some((Some(9), 2023)));
+        assert_eq!(parse_date("9/14/2023"), Some((Some(9), 2023)));
+        assert_eq!(parse_date("14 Sep 2023"), Some((Some(9), 2023)));
+        assert_eq!(parse_date("Sep 2023"), Some((Some(9), 2023)));
+        assert_eq!(parse_date("2023"), Some((None, 2023)));
+        assert_eq!(parse_date("last fall"), None);
+    }
+
+    #[test]
+    fn test_map_record() {
+        let headers = ["Species", "Site", "Collected", "Qty", "Comments", "Extra"];
+        let mapping = ColumnMapping::guess(headers);
+        assert_eq!(mapping.columns.len(), 5);
+        assert!(mapping.validate().is_ok());
+        let record = mapping
+            .map_record(
+                headers,
+                [
+                    "Elymus canadensis",
+                    "Smith Prairie",
+                    "9/2023",
+                    "~1,200",
+                    "Dry",
+                    "x",
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            record,
+            ImportRecord {
+                taxon: "Elymus canadensis".to_string(),
+                source: "Smith Prairie".to_string(),
+                month: Some(9),
+                year: Some(2023),
+                quantity: Some(1200),
+                notes: Some("Dry".to_string()),
+                uncertain: false,
+            }
+        );
+        assert_eq!(
+            mapping.map_record(headers, ["", "Smith Prairie", "", "", "", ""]),
+            Err(MappingError::MissingRequiredField(SampleField::Taxon))
+        );
+        assert!(matches!(
+            mapping.map_record(headers, ["Elymus", "Smith Prairie", "soon", "", "", ""]),
+            Err(MappingError::InvalidValue(..))
+        ));
+        assert_eq!(
+            mapping.map_record(headers, ["Elymus", "Smith Prairie", "", "2.5", "", ""]),
+            Err(MappingError::InvalidValue(
+                "Qty".to_string(),
+                "2.5".to_string()
+            ))
+        );
+        // a weight is not a seed count
+        assert_eq!(SampleField::guess("Weight"), None);
+        assert_eq!(
+            SampleField::guess("Seed_Count"),
+            Some(SampleField::Quantity)
+        );
+        assert_eq!(
+            ColumnMapping::guess(["Species"]).validate(),
+            Err(MappingError::MissingRequiredField(SampleField::Source))
+        );
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users", "sources", "taxa"))
+    ))]
+    async fn test_sample_import(pool: Pool<Sqlite>) {
+        let mut empty = SampleImport::create(1, "empty.csv".to_string(), &pool)
+            .await
+            .unwrap();
+        assert_eq!(empty.status, ImportStatus::Uploading);
+        assert!(empty.add_chunk(0, b"", &pool).await.is_err());
+        assert!(empty.finish_upload(&pool).await.is_err());
+
+        // the chunks split a row and a multibyte character
+        let data = "Taxon,Source,Year\n40683,Test source 1,2023\nElymus nonexistens,Test source 1,2023\n40683,New place \u{e9},2022\n40683,,2021\n";
+        let split = data.find('\u{e9}').unwrap() + 1;
+        let mut import = SampleImport::create(1, "samples.csv".to_string(), &pool)
+            .await
+            .unwrap();
+        import
+            .add_chunk(0, &data.as_bytes()[..20], &pool)
+            .await
+            .unwrap();
+        // chunks can't be skipped, but can be uploaded again
+        assert!(import
+            .add_chunk(2, &data.as_bytes()[split..], &pool)
+            .await
+            .is_err());
+        import
+            .add_chunk(1, &data.as_bytes()[20..], &pool)
+            .await
+            .unwrap();
+        import
+            .add_chunk(1, &data.as_bytes()[20..split], &pool)
+            .await
+            .unwrap();
+        import
+            .add_chunk(2, &data.as_bytes()[split..], &pool)
+            .await
+            .unwrap();
+        // the import can't be started before the upload is finished
+        assert!(import.start(&pool).await.is_err());
+        import.finish_upload(&pool).await.unwrap();
+        assert_eq!(import.total, 4);
+        assert!(import.add_chunk(3, b"40683,x,2020\n", &pool).await.is_err());
+        assert_eq!(import.status, ImportStatus::Uploaded);
+        assert_eq!(
+            import.headers(&pool).await.unwrap(),
+            vec!["Taxon", "Source", "Year"]
+        );
+        let mut reader = import.reader(&pool).await.unwrap();
+        reader.next_row().await.unwrap();
+        reader.next_row().await.unwrap();
+        assert_eq!(
+            reader.next_row().await.unwrap(),
+            Some((
+                4,
+                vec![
+                    "40683".to_string(),
+                    "New place \u{e9}".to_string(),
+                    "2022".to_string()
+                ]
+            ))
+        );
+        assert_eq!(
+            SampleImport::load_all_user(1, &pool).await.unwrap()[0],
+            import
+        );
+        assert!(SampleImport::load_all_user(2, &pool)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let headers = import.headers(&pool).await.unwrap();
+        let mapping = ColumnMapping::guess(headers.iter().map(String::as_str));
+        let preview = import.preview(&mapping, 10, &pool).await.unwrap();
+        assert_eq!(preview.len(), 4);
+        assert_eq!(preview[0].line, 2);
+        assert_eq!(preview[0].taxon.as_ref().map(|t| t.id), Some(40683));
+        assert_eq!(preview[0].source.as_ref().map(|s| s.id), Some(1));
+        assert!(preview[1].error.is_some());
+        assert!(preview[2].error.is_none());
+        assert!(preview[2].source.is_none());
+        assert!(preview[3].error.is_some());
+        assert_eq!(import.preview(&mapping, 1, &pool).await.unwrap().len(), 1);
+
+        // it has to be started first
+        assert!(import.run(&mapping, &pool).await.is_err());
+        import.start(&pool).await.unwrap();
+        assert!(import.clone().start(&pool).await.is_err());
+        import
+            .run(&mapping, &pool)
+            .await
+            .expect("Failed to run import");
+        let loaded = SampleImport::load(import.id, &pool).await.unwrap();
+        assert_eq!(loaded.status, ImportStatus::Finished);
+        assert_eq!(loaded.processed, 4);
+        assert_eq!(loaded.added, 2);
+        let errors = import.errors(&pool).await.unwrap();
+        assert_eq!(
+            errors.iter().map(|e| e.line).collect::<Vec<_>>(),
+            vec![3, 5]
+        );
+        let sources = Source::load_all_user(1, &pool).await.unwrap();
+        assert!(sources.iter().any(|s| s.name == "New place \u{e9}"));
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users"))
+    ))]
+    async fn test_upload_limits(pool: Pool<Sqlite>) {
+        let import = SampleImport::create(1, "large.csv".to_string(), &pool)
+            .await
+            .unwrap();
+        // the first chunk fills the file up to just below the limit
+        sqlx::query(
+            "INSERT INTO sc_sample_import_chunks (importid, chunkno, chunkdata) VALUES (?, 0, zeroblob(?))",
+        )
+        .bind(import.id)
+        .bind(i64::try_from(MAX_UPLOAD_SIZE).unwrap() - 10)
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert!(matches!(
+            import.add_chunk(1, &[b'x'; 11], &pool).await,
+            Err(Error::InvalidValue(..))
+        ));
+        import.add_chunk(1, &[b'x'; 10], &pool).await.unwrap();
+        // a chunk that is uploaded again only counts once
+        import.add_chunk(1, &[b'x'; 10], &pool).await.unwrap();
+        import.add_chunk(0, b"Taxon\n", &pool).await.unwrap();
+        import.add_chunk(2, &[b'x'; 100], &pool).await.unwrap();
+
+        let mut finished = SampleImport::create(1, "finished.csv".to_string(), &pool)
+            .await
+            .unwrap();
+        finished
+            .add_chunk(0, b"Taxon\n40683\n", &pool)
+            .await
+            .unwrap();
+        finished.finish_upload(&pool).await.unwrap();
+
+        // only uploads that were never finished are removed once they are old enough
+        assert_eq!(
+            SampleImport::delete_abandoned(Duration::hours(1), &pool)
+                .await
+                .unwrap(),
+            0
+        );
+        sqlx::query("UPDATE sc_sample_imports SET importcreated=datetime('now', '-2 hours')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            SampleImport::delete_abandoned(Duration::hours(1), &pool)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(SampleImport::load(import.id, &pool).await.is_err());
+        assert!(SampleImport::load(finished.id, &pool).await.is_ok());
+        let chunks: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sc_sample_import_chunks")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(chunks, 1);
+    }
+}
@@ -2,20 +2,73 @@
 //! collection and keep track of everything inside of a database.
 
 use serde::{Deserialize, Deserializer};
+use sqlx::{sqlite::SqliteRow, Row};
 use std::str::FromStr;
+use time::{macros::format_description, Date};
+use uuid::Uuid;
 
+pub mod audit;
+pub mod bulk;
+pub mod conservation;
+pub mod cultivation;
+pub mod dataquality;
+pub mod dedupe;
+pub mod deletion;
+pub mod demo;
+pub mod dump;
 pub mod error;
 pub mod filter;
+pub mod forecast;
+pub mod format;
+pub mod germination;
+pub mod grade;
+pub mod history;
+pub mod import;
 pub mod loadable;
+pub mod mailin;
+pub mod mailqueue;
+pub mod monitoring;
+pub mod notes;
+pub mod organization;
+pub mod photo;
+pub mod plugin;
+pub mod preferences;
 pub mod project;
+pub mod quality;
+pub mod reminder;
+pub mod report;
 pub mod sample;
+pub mod scope;
+pub mod search;
+pub mod sitematch;
 pub mod source;
+pub mod stats;
+pub mod stock;
 pub mod taxonomy;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
+pub mod timezone;
+pub mod trip;
 pub mod user;
 
 pub use error::Error;
 pub use error::Result;
 
+/// Parse a date in the format `YYYY-MM-DD`, as submitted by date inputs
+pub fn parse_date(value: &str) -> Result<Date> {
+    Date::parse(value.trim(), format_description!("[year]-[month]-[day]"))
+        .map_err(|_| Error::InvalidValue(format!("'{value}' is not a valid date")))
+}
+
+/// Read a UUID that is stored as text in the given column
+pub(crate) fn try_get_uuid(row: &SqliteRow, column: &str) -> sqlx::Result<Uuid> {
+    let value: String = row.try_get(column)?;
+    Uuid::parse_str(&value).map_err(|e| sqlx::Error::ColumnDecode {
+        index: column.to_string(),
+        source: Box::new(e),
+    })
+}
+
 pub fn empty_string_as_none<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
 where
     D: Deserializer<'de>,
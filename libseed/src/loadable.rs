@@ -29,15 +29,43 @@ pub trait Loadable {
         Self: Sized;
 
     async fn delete(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
-        Self::delete_id(&self.id(), pool).await.map(|r| {
+        Self::delete_id(&self.id(), pool).await.inspect(|_| {
             self.set_id(Self::Id::invalid_value());
-            r
         })
     }
 
     async fn delete_id(id: &Self::Id, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult>;
 }
 
+/// An object whose fields can be saved individually. Unlike a full update, which writes every
+/// column, this only modifies the given fields so that it is possible to change one field
+/// without overwriting another field that was changed in the meantime.
+#[async_trait]
+pub trait PartialUpdate: Loadable {
+    /// A new value for one of the fields of the object
+    type Field: Clone + Send + Sync;
+
+    /// Save the new values of the given fields to the database in a single statement and apply
+    /// them to this object. This fails with [Error::DatabaseVersionConflict] if the object has
+    /// been modified in the database since it was loaded.
+    async fn update_fields(
+        &mut self,
+        fields: &[Self::Field],
+        pool: &Pool<Sqlite>,
+    ) -> Result<SqliteQueryResult>;
+
+    async fn update_field(
+        &mut self,
+        field: Self::Field,
+        pool: &Pool<Sqlite>,
+    ) -> Result<SqliteQueryResult>
+    where
+        Self: Send,
+    {
+        self.update_fields(std::slice::from_ref(&field), pool).await
+    }
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum ExternalRef<T: Loadable + Sync + Send> {
@@ -0,0 +1,403 @@
+//! Adding notes by email. Each user can generate a secret mail-in key, which is added to the
+//! address of the site's mail-in inbox as a plus-address (e.g. `notes+<key>@example.com`). The
+//! subject of a message starts with the accession number of a sample (e.g. `S0012`), optionally
+//! followed by the number of a project (e.g. `P0003`), and the rest of the message becomes a note.
+//!
+//! Fetching the messages and verifying their sender is up to the application, this module only
+//! manages the keys and adds the notes.
+use crate::{
+    error::{Error, Result},
+    filter::{CompoundFilter, Op},
+    history,
+    loadable::Loadable,
+    organization::{self, Permission},
+    project::{allocation, Allocation, Note, NoteType},
+    sample::Sample,
+};
+use password_hash::rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Sqlite};
+use time::{Date, OffsetDateTime};
+
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct MailInKey {
+    pub userid: i64,
+    #[sqlx(rename = "mailinkey")]
+    pub key: String,
+    #[sqlx(rename = "mailincreated")]
+    pub created: OffsetDateTime,
+}
+
+impl MailInKey {
+    /// Load the user's mail-in key, if they have enabled adding notes by email
+    pub async fn load(userid: i64, pool: &Pool<Sqlite>) -> Result<Option<Self>> {
+        sqlx::query_as("SELECT * FROM sc_mailin_keys WHERE userid=?")
+            .bind(userid)
+            .fetch_optional(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Find the key that a message was addressed to
+    pub async fn find(key: &str, pool: &Pool<Sqlite>) -> Result<Option<Self>> {
+        sqlx::query_as("SELECT * FROM sc_mailin_keys WHERE mailinkey=?")
+            .bind(key.to_ascii_lowercase())
+            .fetch_optional(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Generate a new mail-in key for the user, replacing their previous key if they had one
+    pub async fn generate(userid: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        let mut bytes = [0u8; 10];
+        OsRng.fill_bytes(&mut bytes);
+        let key: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        sqlx::query_as(
+            r#"INSERT INTO sc_mailin_keys (userid, mailinkey) VALUES (?, ?)
+            ON CONFLICT(userid) DO UPDATE SET mailinkey=excluded.mailinkey,
+                mailincreated=CURRENT_TIMESTAMP
+            RETURNING *"#,
+        )
+        .bind(userid)
+        .bind(key)
+        // with fetch_one() the statement is not run to completion, so the key isn't saved
+        .fetch_all(pool)
+        .await?
+        .pop()
+        .ok_or(Error::DatabaseRowNotFound(sqlx::Error::RowNotFound))
+    }
+
+    /// Stop accepting notes by email for the user
+    pub async fn remove(userid: i64, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query("DELETE FROM sc_mailin_keys WHERE userid=?")
+            .bind(userid)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// The address that the user sends notes to, given the address of the site's inbox
+    pub fn address(&self, inbox: &str) -> Option<String> {
+        let (local, domain) = inbox.split_once('@')?;
+        Some(format!("{local}+{}@{domain}", self.key))
+    }
+}
+
+/// Extract the mail-in key from an address that has the same local part and domain as the inbox,
+/// e.g. `notes+abc123@example.com` for the inbox `notes@example.com`
+pub fn key_from_address<'a>(address: &'a str, inbox: &str) -> Option<&'a str> {
+    let (inbox_local, inbox_domain) = inbox.split_once('@')?;
+    let (local, domain) = address.trim().rsplit_once('@')?;
+    let (local, key) = local.split_once('+')?;
+    (local.eq_ignore_ascii_case(inbox_local)
+        && domain.eq_ignore_ascii_case(inbox_domain)
+        && !key.is_empty())
+    .then_some(key)
+}
+
+/// The sample and optionally the project that a note is about
+#[derive(Debug, PartialEq)]
+pub struct Accession {
+    pub sampleid: i64,
+    pub projectid: Option<i64>,
+}
+
+/// Parse an accession number such as `S0012` with the given prefix
+fn parse_number(word: &str, prefix: char) -> Option<i64> {
+    let mut chars = word.chars();
+    let first = chars.next()?;
+    if !first.eq_ignore_ascii_case(&prefix) {
+        return None;
+    }
+    let number = chars.as_str();
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    number.parse().ok()
+}
+
+/// Split the subject of a message into the accession numbers at its beginning and the rest of the
+/// subject, which becomes the summary of the note
+pub fn parse_subject(subject: &str) -> Result<(Accession, String)> {
+    let mut sampleid = None;
+    let mut projectid = None;
+    let mut rest = subject.trim();
+    while let Some(word) = rest.split_whitespace().next() {
+        let token = word.trim_end_matches([':', ',', ';', '-']);
+        if let (None, Some(id)) = (sampleid, parse_number(token, 'S')) {
+            sampleid = Some(id);
+        } else if let (None, Some(id)) = (projectid, parse_number(token, 'P')) {
+            projectid = Some(id);
+        } else {
+            break;
+        }
+        rest = rest[word.len()..].trim_start();
+    }
+    let sampleid = sampleid.ok_or_else(|| {
+        Error::InvalidValue(
+            "the subject must start with the number of a sample, e.g. S0012".to_string(),
+        )
+    })?;
+    Ok((
+        Accession {
+            sampleid,
+            projectid,
+        },
+        rest.trim_start_matches(['-', ':']).trim().to_string(),
+    ))
+}
+
+/// Where a note that was sent by email was added
+#[derive(Debug)]
+pub enum Delivery {
+    /// the note was added to the sample's allocation to a project
+    Allocation(Box<Allocation>, Note),
+    /// the sample isn't part of any project, so the note was appended to the sample's notes
+    Sample(Box<Sample>),
+}
+
+/// Add a note that was sent by the given user. The summary of the note is the subject without the
+/// accession numbers, or the first line of the body if the subject has no other text. Errors that
+/// should be reported back to the sender are returned as [`Error::InvalidValue`].
+pub async fn add_note(
+    userid: i64,
+    subject: &str,
+    body: &str,
+    date: Date,
+    pool: &Pool<Sqlite>,
+) -> Result<Delivery> {
+    let (accession, mut summary) = parse_subject(subject)?;
+    let sample_name = format!("S{:04}", accession.sampleid);
+    let sample = match Sample::load(accession.sampleid, pool).await {
+        Ok(sample)
+            if organization::has_permission(&sample, userid, Permission::Edit, pool).await? =>
+        {
+            sample
+        }
+        Ok(_) | Err(Error::DatabaseRowNotFound(_)) => {
+            return Err(Error::InvalidValue(format!(
+                "sample {sample_name} does not exist or you are not allowed to modify it"
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut body = body.trim();
+    if summary.is_empty() {
+        let (first, rest) = body.split_once('\n').unwrap_or((body, ""));
+        summary = first.trim().to_string();
+        body = rest.trim();
+    }
+    if summary.is_empty() {
+        return Err(Error::InvalidValue("the message is empty".to_string()));
+    }
+    let details = Some(body.to_string()).filter(|b| !b.is_empty());
+
+    let mut filter = CompoundFilter::builder(Op::And).push(allocation::Filter::SampleId(sample.id));
+    if let Some(projectid) = accession.projectid {
+        filter = filter.push(allocation::Filter::ProjectId(projectid));
+    }
+    let mut allocations = Allocation::load_all(Some(filter.build()), None, pool).await?;
+    match (allocations.len(), accession.projectid) {
+        (0, None) => {
+            let before = sample.clone();
+            let mut sample = sample;
+            let mut text = format!("{date}: {summary}");
+            if let Some(details) = details {
+                text = format!("{text}\n{details}");
+            }
+            sample.notes = Some(match sample.notes.take() {
+                Some(notes) if !notes.trim().is_empty() => format!("{notes}\n\n{text}"),
+                _ => text,
+            });
+            sample.update(pool).await?;
+            history::record(&before, &sample, Some(userid), pool).await?;
+            Ok(Delivery::Sample(Box::new(sample)))
+        }
+        (0, Some(projectid)) => Err(Error::InvalidValue(format!(
+            "sample {sample_name} is not part of project P{projectid:04}"
+        ))),
+        (1, _) => {
+            let allocation = allocations.remove(0);
+            if !organization::has_permission(&allocation.project, userid, Permission::Edit, pool)
+                .await?
+            {
+                return Err(Error::InvalidValue(format!(
+                    "you are not allowed to add notes to project P{:04}",
+                    allocation.project.id
+                )));
+            }
+            let note = Note::new(allocation.id, date, NoteType::Other, summary, details)
+                .insert(pool)
+                .await?;
+            Ok(Delivery::Allocation(Box::new(allocation), note))
+        }
+        _ => Err(Error::InvalidValue(format!(
+            "sample {sample_name} is part of several projects, add the number of the project (e.g. P0003) to the subject"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project::Project;
+    use test_log::test;
+    use time::macros::date;
+
+    #[test]
+    fn test_parse_subject() {
+        assert_eq!(
+            parse_subject("S0012 Sowed 50 seeds").unwrap(),
+            (
+                Accession {
+                    sampleid: 12,
+                    projectid: None
+                },
+                "Sowed 50 seeds".to_string()
+            )
+        );
+        assert_eq!(
+            parse_subject(" p3 s12: - first sprouts").unwrap(),
+            (
+                Accession {
+                    sampleid: 12,
+                    projectid: Some(3)
+                },
+                "first sprouts".to_string()
+            )
+        );
+        assert_eq!(parse_subject("S0012").unwrap().1, "");
+        // only the first number of each kind is used
+        assert_eq!(parse_subject("S1 S2").unwrap().1, "S2");
+        assert!(parse_subject("Sowed S0012").is_err());
+        assert!(parse_subject("Seeds").is_err());
+        assert!(parse_subject("").is_err());
+    }
+
+    #[test]
+    fn test_key_from_address() {
+        let inbox = "notes@example.com";
+        assert_eq!(
+            key_from_address("notes+abc123@example.com", inbox),
+            Some("abc123")
+        );
+        assert_eq!(
+            key_from_address("Notes+abc123@Example.COM", inbox),
+            Some("abc123")
+        );
+        assert_eq!(key_from_address("notes@example.com", inbox), None);
+        assert_eq!(key_from_address("notes+@example.com", inbox), None);
+        assert_eq!(key_from_address("other+abc123@example.com", inbox), None);
+        assert_eq!(key_from_address("notes+abc123@example.org", inbox), None);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn test_keys(pool: Pool<Sqlite>) {
+        assert_eq!(MailInKey::load(1, &pool).await.unwrap(), None);
+        let key = MailInKey::generate(1, &pool).await.unwrap();
+        assert_eq!(key.key.len(), 20);
+        assert_eq!(
+            key.address("notes@example.com").unwrap(),
+            format!("notes+{}@example.com", key.key)
+        );
+        assert_eq!(
+            MailInKey::find(&key.key.to_uppercase(), &pool)
+                .await
+                .unwrap(),
+            Some(key.clone())
+        );
+        // a new key replaces the old one
+        let newkey = MailInKey::generate(1, &pool).await.unwrap();
+        assert_ne!(newkey.key, key.key);
+        assert_eq!(MailInKey::find(&key.key, &pool).await.unwrap(), None);
+        MailInKey::remove(1, &pool).await.unwrap();
+        assert_eq!(MailInKey::load(1, &pool).await.unwrap(), None);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn test_add_note(pool: Pool<Sqlite>) {
+        let today = date!(2024 - 07 - 01);
+        let delivery = add_note(1, "S0001 Sowed 50 seeds", "In flat 3\n", today, &pool)
+            .await
+            .expect("Failed to add note");
+        let Delivery::Allocation(allocation, note) = delivery else {
+            panic!("The note was not added to the allocation");
+        };
+        assert_eq!(allocation.project.id, 1);
+        assert_eq!(note.summary, "Sowed 50 seeds");
+        assert_eq!(note.details.as_deref(), Some("In flat 3"));
+        assert_eq!(note.date, today);
+
+        // the summary can be given in the body instead
+        let Delivery::Allocation(_, note) = add_note(1, "S2", "Watered\nAll of them", today, &pool)
+            .await
+            .unwrap()
+        else {
+            panic!("The note was not added to the allocation");
+        };
+        assert_eq!(note.summary, "Watered");
+        assert_eq!(note.details.as_deref(), Some("All of them"));
+
+        // samples that belong to several projects need a project number
+        let mut project = Project::load(2, &pool).await.unwrap();
+        project
+            .allocate_sample(crate::loadable::ExternalRef::Stub(1), &pool)
+            .await
+            .unwrap();
+        assert!(matches!(
+            add_note(1, "S0001 Sowed", "", today, &pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        let Delivery::Allocation(allocation, _) =
+            add_note(1, "S0001 P0002 Sowed", "", today, &pool)
+                .await
+                .unwrap()
+        else {
+            panic!("The note was not added to the allocation");
+        };
+        assert_eq!(allocation.project.id, 2);
+        assert!(add_note(1, "S0002 P0002 Sowed", "", today, &pool)
+            .await
+            .is_err());
+
+        // other users' samples can't be modified
+        assert!(matches!(
+            add_note(1, "S0004 Sowed", "", today, &pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        assert!(add_note(1, "S9999 Sowed", "", today, &pool).await.is_err());
+        assert!(add_note(1, "S0001 P0002", "  ", today, &pool)
+            .await
+            .is_err());
+
+        // samples that aren't part of a project get the note appended to their notes
+        sqlx::query("DELETE FROM sc_project_samples WHERE sampleid=3")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let Delivery::Sample(sample) = add_note(1, "S0003 Cleaned", "", today, &pool)
+            .await
+            .unwrap()
+        else {
+            panic!("The note was not added to the sample");
+        };
+        assert_eq!(sample.notes.as_deref(), Some("2024-07-01: Cleaned"));
+        assert_eq!(
+            history::Change::load_sample(3, &pool).await.unwrap().len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! A persistent queue of outgoing emails. Messages are stored in the database before they are
+//! sent, so that a message that can't be delivered right away (e.g. because the mail server is
+//! unavailable) isn't lost and can be retried later with an increasing delay between attempts.
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteQueryResult, FromRow, Pool, Sqlite};
+use std::time::Duration;
+use strum_macros::Display;
+use time::OffsetDateTime;
+
+/// The number of delivery attempts after which a message is given up on
+pub const MAX_ATTEMPTS: i64 = 8;
+
+/// The delay before the first retry. It doubles with every failed attempt.
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, sqlx::Type, PartialEq, Display)]
+#[repr(i32)]
+pub enum MailStatus {
+    Pending = 0,
+    Sent = 1,
+    Failed = 2,
+}
+
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct QueuedMail {
+    #[sqlx(rename = "mailid")]
+    pub id: i64,
+    #[sqlx(rename = "mailsender")]
+    pub sender: String,
+    #[sqlx(rename = "mailrecipient")]
+    pub recipient: String,
+    #[sqlx(rename = "mailsubject")]
+    pub subject: String,
+    /// The complete formatted message, ready to be handed to a mail transport
+    #[sqlx(rename = "mailmessage")]
+    pub message: Vec<u8>,
+    #[sqlx(rename = "mailstatus")]
+    pub status: MailStatus,
+    #[sqlx(rename = "mailattempts")]
+    pub attempts: i64,
+    #[sqlx(rename = "maillasterror")]
+    pub last_error: Option<String>,
+    #[sqlx(rename = "mailcreated")]
+    pub created: Option<OffsetDateTime>,
+    #[sqlx(rename = "mailnextattempt")]
+    pub next_attempt: Option<OffsetDateTime>,
+    #[sqlx(rename = "mailsent")]
+    pub sent: Option<OffsetDateTime>,
+}
+
+/// How long to wait before retrying a message that has failed `attempts` times, or `None` if the
+/// message should not be retried anymore
+pub fn retry_delay(attempts: i64) -> Option<Duration> {
+    if attempts >= MAX_ATTEMPTS {
+        return None;
+    }
+    let factor = 2u32.pow(attempts.clamp(1, MAX_ATTEMPTS) as u32 - 1);
+    Some(BASE_RETRY_DELAY * factor)
+}
+
+impl QueuedMail {
+    pub fn new(sender: String, recipient: String, subject: String, message: Vec<u8>) -> Self {
+        Self {
+            id: -1,
+            sender,
+            recipient,
+            subject,
+            message,
+            status: MailStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            created: None,
+            next_attempt: None,
+            sent: None,
+        }
+    }
+
+    /// Add the message to the queue. It is due to be sent immediately.
+    pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        let res = sqlx::query(
+            r#"INSERT INTO sc_mail_queue (mailsender, mailrecipient, mailsubject, mailmessage)
+            VALUES (?, ?, ?, ?)"#,
+        )
+        .bind(&self.sender)
+        .bind(&self.recipient)
+        .bind(&self.subject)
+        .bind(&self.message)
+        .execute(pool)
+        .await?;
+        self.id = res.last_insert_rowid();
+        Ok(res)
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        sqlx::query_as("SELECT * FROM sc_mail_queue WHERE mailid=?")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Load all queued messages with the given status, most recent first
+    pub async fn load_all(status: Option<MailStatus>, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            "SELECT * FROM sc_mail_queue WHERE ? IS NULL OR mailstatus=? ORDER BY mailid DESC",
+        )
+        .bind(status)
+        .bind(status)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Load the pending messages whose next delivery attempt is due, oldest first
+    pub async fn load_due(limit: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            r#"SELECT * FROM sc_mail_queue WHERE mailstatus=? AND mailnextattempt <= datetime('now')
+            ORDER BY mailnextattempt, mailid LIMIT ?"#,
+        )
+        .bind(MailStatus::Pending)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Record that the message was delivered successfully
+    pub async fn mark_sent(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query(
+            r#"UPDATE sc_mail_queue SET mailstatus=?, mailattempts=mailattempts + 1,
+            maillasterror=NULL, mailsent=CURRENT_TIMESTAMP WHERE mailid=?"#,
+        )
+        .bind(MailStatus::Sent)
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+        *self = Self::load(self.id, pool).await?;
+        Ok(())
+    }
+
+    /// Record a failed delivery attempt and schedule the next one. Once the message has failed
+    /// [`MAX_ATTEMPTS`] times, it is marked as failed and won't be retried automatically anymore.
+    pub async fn mark_failed(&mut self, error: &str, pool: &Pool<Sqlite>) -> Result<()> {
+        let attempts = self.attempts + 1;
+        let (status, delay) = match retry_delay(attempts) {
+            Some(delay) => (MailStatus::Pending, delay),
+            None => (MailStatus::Failed, Duration::ZERO),
+        };
+        sqlx::query(
+            r#"UPDATE sc_mail_queue SET mailstatus=?, mailattempts=?, maillasterror=?,
+            mailnextattempt=datetime('now', ?) WHERE mailid=?"#,
+        )
+        .bind(status)
+        .bind(attempts)
+        .bind(error)
+        .bind(format!("+{} seconds", delay.as_secs()))
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+        *self = Self::load(self.id, pool).await?;
+        Ok(())
+    }
+
+    /// Put the message back into the queue so that it is sent again as soon as possible, with a
+    /// full set of retries
+    pub async fn resend(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query(
+            r#"UPDATE sc_mail_queue SET mailstatus=?, mailattempts=0,
+            mailnextattempt=CURRENT_TIMESTAMP WHERE mailid=?"#,
+        )
+        .bind(MailStatus::Pending)
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+        *self = Self::load(self.id, pool).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    #[test]
+    fn retry_delays() {
+        assert_eq!(retry_delay(1), Some(Duration::from_secs(60)));
+        assert_eq!(retry_delay(2), Some(Duration::from_secs(120)));
+        assert_eq!(retry_delay(3), Some(Duration::from_secs(240)));
+        assert_eq!(
+            retry_delay(MAX_ATTEMPTS - 1),
+            Some(Duration::from_secs(64 * 60))
+        );
+        assert_eq!(retry_delay(MAX_ATTEMPTS), None);
+    }
+
+    #[test(sqlx::test(migrations = "../db/migrations/"))]
+    async fn mail_queue(pool: Pool<Sqlite>) {
+        let mut mail = QueuedMail::new(
+            "sender@example.com".to_string(),
+            "someone@example.com".to_string(),
+            "Hello".to_string(),
+            b"Subject: Hello\r\n\r\nHi".to_vec(),
+        );
+        mail.insert(&pool).await.expect("Failed to queue mail");
+        let mut other = mail.clone();
+        other.insert(&pool).await.expect("Failed to queue mail");
+
+        let due = QueuedMail::load_due(10, &pool)
+            .await
+            .expect("Failed to load due mail");
+        assert_eq!(
+            due.iter().map(|m| m.id).collect::<Vec<_>>(),
+            vec![mail.id, other.id]
+        );
+        assert_eq!(due[0].message, mail.message);
+
+        // a failed message is not due again until the retry delay has passed
+        mail.mark_failed("connection refused", &pool)
+            .await
+            .expect("Failed to mark mail as failed");
+        assert_eq!(mail.status, MailStatus::Pending);
+        assert_eq!(mail.attempts, 1);
+        assert_eq!(mail.last_error.as_deref(), Some("connection refused"));
+        let due = QueuedMail::load_due(10, &pool)
+            .await
+            .expect("Failed to load due mail");
+        assert_eq!(due.iter().map(|m| m.id).collect::<Vec<_>>(), vec![other.id]);
+
+        other
+            .mark_sent(&pool)
+            .await
+            .expect("Failed to mark mail as sent");
+        assert_eq!(other.status, MailStatus::Sent);
+        assert!(other.sent.is_some());
+        assert!(QueuedMail::load_due(10, &pool).await.unwrap().is_empty());
+
+        // give up after too many attempts
+        for _ in 1..MAX_ATTEMPTS {
+            mail.mark_failed("connection refused", &pool)
+                .await
+                .expect("Failed to mark mail as failed");
+        }
+        assert_eq!(mail.status, MailStatus::Failed);
+        assert_eq!(mail.attempts, MAX_ATTEMPTS);
+        assert_eq!(
+            QueuedMail::load_all(Some(MailStatus::Failed), &pool)
+                .await
+                .unwrap(),
+            vec![mail.clone()]
+        );
+        assert_eq!(QueuedMail::load_all(None, &pool).await.unwrap().len(), 2);
+
+        // a manual resend makes the message due immediately
+        mail.resend(&pool).await.expect("Failed to resend mail");
+        assert_eq!(mail.status, MailStatus::Pending);
+        assert_eq!(mail.attempts, 0);
+        let due = QueuedMail::load_due(10, &pool)
+            .await
+            .expect("Failed to load due mail");
+        assert_eq!(due, vec![QueuedMail::load(mail.id, &pool).await.unwrap()]);
+    }
+}
@@ -0,0 +1,586 @@
+//! Monitoring of the sources that a user looks after. A monitoring schedule says how often a source
+//! should be visited during the months of its season, from which the upcoming visits are worked
+//! out. The outcome of each visit, such as an estimate of the size of a population and the
+//! phenophase that it was in, is recorded so that it can be shown alongside the collection
+//! calendar when planning when to collect seeds.
+use crate::{
+    error::{Error, Result},
+    stats::{calendar_week, CALENDAR_WEEKS},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, FromRow, Pool, QueryBuilder, Row, Sqlite};
+use strum_macros::{Display, EnumIter, EnumString};
+use time::{Date, Duration, Month};
+use uuid::Uuid;
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+const SELECT_SCHEDULES: &str = r#"SELECT M.*,
+    (SELECT MAX(V.visitdate) FROM sc_source_visits V
+        WHERE V.srcid=M.srcid AND V.userid=M.userid) AS lastvisit
+    FROM sc_monitoring_schedules M"#;
+
+const SELECT_VISITS: &str = r#"SELECT V.*, T.complete_name
+    FROM sc_source_visits V LEFT JOIN taxonomic_units T ON T.tsn=V.tsn"#;
+
+/// The stage of its yearly cycle that a population of plants was observed in
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Eq,
+    sqlx::Type,
+    Display,
+    EnumIter,
+    EnumString,
+)]
+#[repr(i64)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum Phenophase {
+    Vegetative = 1,
+    Budding = 2,
+    Flowering = 3,
+    Fruiting = 4,
+    /// the seeds are ready to be collected
+    SeedRipe = 5,
+    /// most of the seeds have already dropped
+    Dispersed = 6,
+    Dormant = 7,
+}
+
+/// How often a user wants to visit a source during its season
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Schedule {
+    pub id: i64,
+    pub userid: i64,
+    pub source: i64,
+    pub interval_weeks: i64,
+    /// the first and last month of the season. The season wraps around the end of the year if it
+    /// ends in an earlier month than it starts.
+    pub season_start: u8,
+    pub season_end: u8,
+    /// the date of the most recent visit of the user to the source
+    #[serde(with = "iso_date::option")]
+    pub last_visit: Option<Date>,
+}
+
+impl FromRow<'_, SqliteRow> for Schedule {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            id: row.try_get("scheduleid")?,
+            userid: row.try_get("userid")?,
+            source: row.try_get("srcid")?,
+            interval_weeks: row.try_get("intervalweeks")?,
+            season_start: row.try_get("seasonstart")?,
+            season_end: row.try_get("seasonend")?,
+            last_visit: row.try_get("lastvisit")?,
+        })
+    }
+}
+
+impl Schedule {
+    /// Load the user's schedule for the given source, if there is one
+    pub async fn load(userid: i64, source: i64, pool: &Pool<Sqlite>) -> Result<Option<Self>> {
+        sqlx::query_as(&format!(
+            "{SELECT_SCHEDULES} WHERE M.userid=? AND M.srcid=?"
+        ))
+        .bind(userid)
+        .bind(source)
+        .fetch_optional(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Save the user's schedule for the given source. Saving no interval removes the schedule.
+    pub async fn save(
+        userid: i64,
+        source: i64,
+        interval_weeks: Option<i64>,
+        season: (u8, u8),
+        pool: &Pool<Sqlite>,
+    ) -> Result<Option<Self>> {
+        let Some(interval_weeks) = interval_weeks else {
+            sqlx::query("DELETE FROM sc_monitoring_schedules WHERE userid=? AND srcid=?")
+                .bind(userid)
+                .bind(source)
+                .execute(pool)
+                .await?;
+            return Ok(None);
+        };
+        if !(1..=52).contains(&interval_weeks) {
+            return Err(Error::InvalidValue(
+                "Visits must be between 1 and 52 weeks apart".to_string(),
+            ));
+        }
+        let (start, end) = season;
+        if Month::try_from(start).is_err() || Month::try_from(end).is_err() {
+            return Err(Error::InvalidValue(
+                "The season must start and end in a month of the year".to_string(),
+            ));
+        }
+        sqlx::query(
+            r#"INSERT INTO sc_monitoring_schedules
+            (userid, srcid, intervalweeks, seasonstart, seasonend) VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(userid, srcid) DO UPDATE SET intervalweeks=excluded.intervalweeks,
+            seasonstart=excluded.seasonstart, seasonend=excluded.seasonend"#,
+        )
+        .bind(userid)
+        .bind(source)
+        .bind(interval_weeks)
+        .bind(start)
+        .bind(end)
+        .execute(pool)
+        .await?;
+        Self::load(userid, source, pool).await
+    }
+
+    /// Load all of the user's schedules
+    pub async fn load_all(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(&format!(
+            "{SELECT_SCHEDULES} WHERE M.userid=? ORDER BY M.srcid"
+        ))
+        .bind(userid)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    fn start_month(&self) -> Month {
+        Month::try_from(self.season_start).unwrap_or(Month::January)
+    }
+
+    /// Whether the given date falls within the season
+    pub fn in_season(&self, date: Date) -> bool {
+        let month = u8::from(date.month());
+        if self.season_start <= self.season_end {
+            (self.season_start..=self.season_end).contains(&month)
+        } else {
+            month >= self.season_start || month <= self.season_end
+        }
+    }
+
+    /// The first day of the season that `date` falls in. The date needs to be in season.
+    fn current_season_start(&self, date: Date) -> Date {
+        let start =
+            Date::from_calendar_date(date.year(), self.start_month(), 1).expect("valid date");
+        if start > date {
+            start.replace_year(date.year() - 1).expect("valid date")
+        } else {
+            start
+        }
+    }
+
+    /// The given date if it is in season, otherwise the first day of the next season
+    fn season_from(&self, date: Date) -> Date {
+        if self.in_season(date) {
+            return date;
+        }
+        let start =
+            Date::from_calendar_date(date.year(), self.start_month(), 1).expect("valid date");
+        if start < date {
+            start.replace_year(date.year() + 1).expect("valid date")
+        } else {
+            start
+        }
+    }
+
+    /// The date of the next visit. A source that hasn't been visited yet is due right away if it
+    /// is in season. A visit that was missed earlier in the current season is overdue and keeps
+    /// its date, but visits that were missed in earlier seasons are not carried over.
+    pub fn next_visit(&self, today: Date) -> Date {
+        let due = match self.last_visit {
+            Some(last) => last + Duration::weeks(self.interval_weeks),
+            None => today,
+        };
+        if due >= today {
+            return self.season_from(due);
+        }
+        if self.in_season(today) && due >= self.current_season_start(today) {
+            due
+        } else {
+            self.season_from(today)
+        }
+    }
+
+    /// The dates of the visits that are due up to and including `until`, starting with the next
+    /// visit
+    pub fn upcoming(&self, today: Date, until: Date) -> Vec<Date> {
+        let mut dates = Vec::new();
+        let mut date = self.next_visit(today);
+        while date <= until {
+            dates.push(date);
+            date = self.season_from(date + Duration::weeks(self.interval_weeks));
+        }
+        dates
+    }
+}
+
+/// A visit to a source that is due according to its monitoring schedule
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct UpcomingVisit {
+    pub source: i64,
+    pub source_uuid: Uuid,
+    pub source_name: String,
+    #[serde(with = "iso_date")]
+    pub date: Date,
+    /// whether the visit should already have happened
+    pub overdue: bool,
+}
+
+/// The visits to the user's monitored sources that are due up to and including `until`, ordered
+/// by date. Visits can optionally be limited to a single source.
+pub async fn upcoming_visits(
+    userid: i64,
+    source: Option<i64>,
+    today: Date,
+    until: Date,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<UpcomingVisit>> {
+    let mut builder = QueryBuilder::new(format!(
+        r#"SELECT M.*, L.srcname, L.srcuuid FROM ({SELECT_SCHEDULES}) M
+        INNER JOIN sc_sources L ON L.srcid=M.srcid
+        WHERE M.userid="#
+    ));
+    builder.push_bind(userid);
+    if let Some(srcid) = source {
+        builder.push(" AND M.srcid=").push_bind(srcid);
+    }
+    let rows = builder.build().fetch_all(pool).await?;
+    let mut visits = Vec::new();
+    for row in rows {
+        let schedule = Schedule::from_row(&row)?;
+        let source_uuid = crate::try_get_uuid(&row, "srcuuid")?;
+        let source_name: String = row.try_get("srcname")?;
+        for date in schedule.upcoming(today, until) {
+            visits.push(UpcomingVisit {
+                source: schedule.source,
+                source_uuid,
+                source_name: source_name.clone(),
+                date,
+                overdue: date < today,
+            });
+        }
+    }
+    visits.sort_by(|a, b| a.date.cmp(&b.date).then(a.source_name.cmp(&b.source_name)));
+    Ok(visits)
+}
+
+/// What was observed on a visit to a source
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Visit {
+    #[sqlx(rename = "visitid")]
+    pub id: i64,
+    pub userid: i64,
+    #[sqlx(rename = "srcid")]
+    pub source: i64,
+    #[sqlx(rename = "visitdate")]
+    #[serde(with = "iso_date")]
+    pub date: Date,
+    /// the taxon whose population was observed, if the visit wasn't about the whole site
+    pub tsn: Option<i64>,
+    pub complete_name: Option<String>,
+    /// an estimate of the number of plants
+    pub population: Option<i64>,
+    pub phenophase: Option<Phenophase>,
+    #[sqlx(rename = "visitnotes")]
+    pub notes: Option<String>,
+}
+
+impl Visit {
+    pub fn new(userid: i64, source: i64, date: Date) -> Self {
+        Self {
+            id: -1,
+            userid,
+            source,
+            date,
+            tsn: None,
+            complete_name: None,
+            population: None,
+            phenophase: None,
+            notes: None,
+        }
+    }
+
+    pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        if self.population.is_some_and(|n| n < 0) {
+            return Err(Error::InvalidValue(
+                "The population size can't be negative".to_string(),
+            ));
+        }
+        self.notes = self
+            .notes
+            .as_ref()
+            .map(|n| n.trim().to_string())
+            .filter(|n| !n.is_empty());
+        let res = sqlx::query(
+            r#"INSERT INTO sc_source_visits
+            (userid, srcid, visitdate, tsn, population, phenophase, visitnotes)
+            VALUES (?, ?, ?, ?, ?, ?, ?)"#,
+        )
+        .bind(self.userid)
+        .bind(self.source)
+        .bind(self.date)
+        .bind(self.tsn)
+        .bind(self.population)
+        .bind(self.phenophase)
+        .bind(&self.notes)
+        .execute(pool)
+        .await?;
+        *self = Self::load(res.last_insert_rowid(), pool).await?;
+        Ok(())
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        sqlx::query_as(&format!("{SELECT_VISITS} WHERE V.visitid=?"))
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Load the user's visits to the given source, most recent visits first
+    pub async fn load_source(userid: i64, source: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(&format!(
+            r#"{SELECT_VISITS} WHERE V.userid=? AND V.srcid=?
+            ORDER BY V.visitdate DESC, V.visitid DESC"#
+        ))
+        .bind(userid)
+        .bind(source)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn delete(&self, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query("DELETE FROM sc_source_visits WHERE visitid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+/// A row of the phenology calendar: the phenophase that a taxon was observed in during each week
+/// of the year, regardless of the year of the visit. If a taxon was observed more than once in the
+/// same week, the most recent observation is shown.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct PhenologyRow {
+    pub id: i64,
+    pub name: String,
+    pub nvisits: i64,
+    pub weeks: Vec<Option<Phenophase>>,
+}
+
+/// Lay out the phenophases that were observed on the user's visits in each week of the year. Visits
+/// can optionally be limited to a single source.
+pub async fn phenology_calendar(
+    userid: i64,
+    source: Option<i64>,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<PhenologyRow>> {
+    let mut builder = QueryBuilder::new(format!(
+        "{SELECT_VISITS} WHERE V.tsn IS NOT NULL AND V.phenophase IS NOT NULL AND V.userid="
+    ));
+    builder.push_bind(userid);
+    if let Some(srcid) = source {
+        builder.push(" AND V.srcid=").push_bind(srcid);
+    }
+    builder.push(" ORDER BY V.tsn, V.visitdate, V.visitid");
+    let visits: Vec<Visit> = builder.build_query_as().fetch_all(pool).await?;
+
+    let mut calendar: Vec<PhenologyRow> = Vec::new();
+    for visit in visits {
+        let Some(id) = visit.tsn else {
+            continue;
+        };
+        if calendar.last().map(|r| r.id) != Some(id) {
+            calendar.push(PhenologyRow {
+                id,
+                name: visit.complete_name.clone().unwrap_or_default(),
+                nvisits: 0,
+                weeks: vec![None; CALENDAR_WEEKS],
+            });
+        }
+        if let Some(entry) = calendar.last_mut() {
+            entry.nvisits += 1;
+            entry.weeks[calendar_week(visit.date)] = visit.phenophase;
+        }
+    }
+    calendar.sort_by_cached_key(|r| (r.weeks.iter().position(Option::is_some), r.name.clone()));
+    Ok(calendar)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+    use time::macros::date;
+
+    fn schedule(interval_weeks: i64, season: (u8, u8), last_visit: Option<Date>) -> Schedule {
+        Schedule {
+            id: 1,
+            userid: 1,
+            source: 1,
+            interval_weeks,
+            season_start: season.0,
+            season_end: season.1,
+            last_visit,
+        }
+    }
+
+    #[test]
+    fn next_visit() {
+        let today = date!(2024 - 06 - 10);
+        // never visited
+        assert_eq!(schedule(2, (5, 9), None).next_visit(today), today);
+        assert_eq!(
+            schedule(2, (8, 9), None).next_visit(today),
+            date!(2024 - 08 - 01)
+        );
+        // the season wraps around the end of the year
+        let winter = schedule(4, (11, 2), None);
+        assert!(winter.in_season(date!(2024 - 01 - 15)));
+        assert!(!winter.in_season(today));
+        assert_eq!(winter.next_visit(today), date!(2024 - 11 - 01));
+        assert_eq!(
+            winter.next_visit(date!(2024 - 12 - 15)),
+            date!(2024 - 12 - 15)
+        );
+
+        // the next visit is due once the interval has passed
+        let visited = schedule(2, (5, 9), Some(date!(2024 - 06 - 03)));
+        assert_eq!(visited.next_visit(today), date!(2024 - 06 - 17));
+        // a missed visit in the current season is overdue
+        let missed = schedule(2, (5, 9), Some(date!(2024 - 05 - 06)));
+        assert_eq!(missed.next_visit(today), date!(2024 - 05 - 20));
+        // but visits that were missed in an earlier season aren't
+        let last_year = schedule(2, (5, 9), Some(date!(2023 - 09 - 20)));
+        assert_eq!(last_year.next_visit(today), today);
+        // visits that would be due after the end of the season move to the next season
+        let late = schedule(4, (5, 9), Some(date!(2024 - 09 - 16)));
+        assert_eq!(late.next_visit(today), date!(2025 - 05 - 01));
+
+        assert_eq!(
+            visited.upcoming(today, date!(2024 - 07 - 15)),
+            vec![
+                date!(2024 - 06 - 17),
+                date!(2024 - 07 - 01),
+                date!(2024 - 07 - 15)
+            ]
+        );
+        assert_eq!(
+            schedule(3, (5, 9), Some(date!(2024 - 09 - 02))).upcoming(today, date!(2025 - 05 - 31)),
+            vec![
+                date!(2024 - 09 - 23),
+                date!(2025 - 05 - 01),
+                date!(2025 - 05 - 22)
+            ]
+        );
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users", "sources", "taxa"))
+    ))]
+    async fn monitoring(pool: Pool<Sqlite>) {
+        assert!(Schedule::load(1, 1, &pool).await.unwrap().is_none());
+        assert!(Schedule::save(1, 1, Some(0), (5, 9), &pool).await.is_err());
+        assert!(Schedule::save(1, 1, Some(2), (5, 13), &pool).await.is_err());
+        let saved = Schedule::save(1, 1, Some(2), (5, 9), &pool)
+            .await
+            .expect("Failed to save schedule")
+            .expect("No schedule");
+        assert_eq!(saved.interval_weeks, 2);
+        assert_eq!(saved.last_visit, None);
+        let saved = Schedule::save(1, 1, Some(3), (4, 10), &pool)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((saved.interval_weeks, saved.season_start), (3, 4));
+        assert_eq!(Schedule::load_all(1, &pool).await.unwrap(), vec![saved]);
+
+        let mut visit = Visit::new(1, 1, date!(2024 - 06 - 03));
+        visit.tsn = Some(40683);
+        visit.population = Some(-1);
+        assert!(visit.insert(&pool).await.is_err());
+        visit.population = Some(200);
+        visit.phenophase = Some(Phenophase::Flowering);
+        visit.notes = Some("  ".to_string());
+        visit.insert(&pool).await.expect("Failed to insert visit");
+        assert_eq!(visit.notes, None);
+        assert!(visit.complete_name.is_some());
+        let mut later = Visit::new(1, 1, date!(2024 - 07 - 22));
+        later.tsn = Some(40683);
+        later.phenophase = Some(Phenophase::SeedRipe);
+        later.insert(&pool).await.unwrap();
+        let mut site = Visit::new(1, 1, date!(2024 - 07 - 29));
+        site.notes = Some("Mowed along the trail".to_string());
+        site.insert(&pool).await.unwrap();
+        assert_eq!(
+            Visit::load_source(1, 1, &pool).await.unwrap(),
+            vec![site.clone(), later.clone(), visit.clone()]
+        );
+        assert!(Visit::load_source(2, 1, &pool).await.unwrap().is_empty());
+
+        // the schedule continues from the most recent visit
+        let schedule = Schedule::load(1, 1, &pool).await.unwrap().unwrap();
+        assert_eq!(schedule.last_visit, Some(date!(2024 - 07 - 29)));
+        let upcoming =
+            upcoming_visits(1, None, date!(2024 - 08 - 01), date!(2024 - 09 - 01), &pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            upcoming.iter().map(|v| v.date).collect::<Vec<_>>(),
+            vec![date!(2024 - 08 - 19)]
+        );
+        assert!(!upcoming[0].overdue);
+        let overdue = upcoming_visits(
+            1,
+            Some(1),
+            date!(2024 - 09 - 01),
+            date!(2024 - 09 - 01),
+            &pool,
+        )
+        .await
+        .unwrap();
+        assert!(overdue[0].overdue);
+        assert!(upcoming_visits(
+            1,
+            Some(2),
+            date!(2024 - 08 - 01),
+            date!(2024 - 09 - 01),
+            &pool
+        )
+        .await
+        .unwrap()
+        .is_empty());
+
+        let phenology = phenology_calendar(1, None, &pool).await.unwrap();
+        assert_eq!(phenology.len(), 1);
+        assert_eq!(phenology[0].nvisits, 2);
+        assert_eq!(
+            phenology[0].weeks[calendar_week(visit.date)],
+            Some(Phenophase::Flowering)
+        );
+        assert_eq!(
+            phenology[0].weeks[calendar_week(later.date)],
+            Some(Phenophase::SeedRipe)
+        );
+        assert!(phenology_calendar(1, Some(2), &pool)
+            .await
+            .unwrap()
+            .is_empty());
+
+        site.delete(&pool).await.unwrap();
+        assert_eq!(Visit::load_source(1, 1, &pool).await.unwrap().len(), 2);
+        assert!(Schedule::save(1, 1, None, (1, 12), &pool)
+            .await
+            .unwrap()
+            .is_none());
+        assert!(Schedule::load(1, 1, &pool).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,333 @@
+//! Browsing all of the notes about a user's seeds in one place. Notes are kept in two places: the
+//! free-text notes of a sample, and the dated notes about a sample in a project (see
+//! [Note](crate::project::Note)). This module queries both of them as a single list, so that they
+//! can be filtered and displayed together.
+use crate::{
+    error::Result,
+    filter::{DynFilterPart, FilterPart, LimitSpec, ListQuery},
+    organization::push_accessible_condition,
+    project::note::NoteType,
+    sample::push_descendant_join,
+    try_get_uuid,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, FromRow, Pool, QueryBuilder, Row, Sqlite};
+use std::sync::Arc;
+use strum_macros::{Display, EnumIter, EnumString};
+use time::Date;
+use uuid::Uuid;
+
+/// Where a note is stored
+#[derive(
+    sqlx::Type, Debug, Copy, Clone, Serialize, Deserialize, Display, EnumString, EnumIter, PartialEq,
+)]
+#[repr(i64)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum NoteSource {
+    /// the notes of a sample
+    Sample = 1,
+    /// a note about a sample in a project
+    Allocation = 2,
+}
+
+/// The columns of the notes query. Sample notes have no date, type or details, and are not
+/// associated with a project.
+const COLUMNS: &str = r#"notesource, noteid, noteuuid, notedate, notetype, notesummary,
+    notedetails, sampleid, sampleuuid, tsn, complete_name, userid, orgid, psid, psuuid, projectid,
+    projname, projuuid"#;
+
+const TABLES: &str = r#"(SELECT 1 AS notesource, S.sampleid AS noteid, S.sampleuuid AS noteuuid,
+        NULL AS notedate, NULL AS notetype, S.notes AS notesummary, NULL AS notedetails,
+        S.sampleid, S.sampleuuid, S.tsn, T.complete_name, S.userid, S.sampleorgid AS orgid,
+        NULL AS psid, NULL AS psuuid, NULL AS projectid, NULL AS projname, NULL AS projuuid
+        FROM sc_samples S
+        INNER JOIN taxonomic_units T ON T.tsn=S.tsn
+        WHERE S.notes IS NOT NULL AND S.notes != ''
+    UNION ALL
+    SELECT 2, N.pnoteid, N.pnoteuuid, N.notedate, N.notetype, N.notesummary, N.notedetails,
+        S.sampleid, S.sampleuuid, S.tsn, T.complete_name, P.userid, P.projorgid, PS.psid,
+        PS.psuuid, P.projectid, P.projname, P.projuuid
+        FROM sc_project_notes N
+        INNER JOIN sc_project_samples PS ON PS.psid=N.psid
+        INNER JOIN sc_projects P ON P.projectid=PS.projectid
+        INNER JOIN sc_samples S ON S.sampleid=PS.sampleid
+        INNER JOIN taxonomic_units T ON T.tsn=S.tsn) AS notes"#;
+
+#[derive(Clone)]
+pub enum Filter {
+    /// notes about samples or projects that are owned by the given user or by one of the user's
+    /// organizations
+    Accessible(i64),
+    Source(NoteSource),
+    /// allocation notes of the given type. Sample notes have no type, so they never match.
+    Type(NoteType),
+    /// notes dated on or after the given date. Sample notes have no date, so they never match.
+    DateFrom(Date),
+    /// notes dated on or before the given date. Sample notes have no date, so they never match.
+    DateTo(Date),
+    /// notes whose summary or details contain the given text
+    TextLike(String),
+    /// notes about samples in the given project
+    ProjectId(i64),
+    /// notes about the given sample, including the notes about it in any project
+    SampleId(i64),
+    /// notes about samples of the given taxon or of any taxon below it in the ITIS hierarchy
+    AncestorTsn(i64),
+}
+
+impl From<Filter> for DynFilterPart {
+    fn from(value: Filter) -> Self {
+        Arc::new(value)
+    }
+}
+
+impl FilterPart for Filter {
+    fn add_to_query(&self, builder: &mut QueryBuilder<Sqlite>) {
+        match self {
+            Self::Accessible(id) => push_accessible_condition(builder, "userid", "orgid", *id),
+            Self::Source(source) => _ = builder.push(" notesource=").push_bind(*source as i64),
+            Self::Type(kind) => _ = builder.push(" notetype=").push_bind(*kind as i64),
+            Self::DateFrom(date) => _ = builder.push(" notedate >= ").push_bind(*date),
+            Self::DateTo(date) => _ = builder.push(" notedate <= ").push_bind(*date),
+            Self::TextLike(s) => {
+                let wildcard = format!("%{s}%");
+                builder
+                    .push(" (notesummary LIKE ")
+                    .push_bind(wildcard.clone())
+                    .push(" OR notedetails LIKE ")
+                    .push_bind(wildcard)
+                    .push(")");
+            }
+            Self::ProjectId(id) => _ = builder.push(" projectid=").push_bind(*id),
+            Self::SampleId(id) => _ = builder.push(" sampleid=").push_bind(*id),
+            Self::AncestorTsn(tsn) => {
+                builder.push(" tsn IN (SELECT H.TSN FROM hierarchy P ");
+                push_descendant_join(builder);
+                builder.push(" WHERE P.TSN=").push_bind(*tsn).push(") ");
+            }
+        }
+    }
+}
+
+/// The project that an allocation note belongs to
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct NoteProject {
+    pub id: i64,
+    pub uuid: Uuid,
+    pub name: String,
+    /// the id of the sample's allocation to the project
+    pub allocid: i64,
+    pub allocuuid: Uuid,
+}
+
+/// A note from either of the places that notes are stored
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct NoteEntry {
+    pub source: NoteSource,
+    /// the id of the note, or of the sample for sample notes
+    pub id: i64,
+    /// the identifier of the note in URLs, or of the sample for sample notes
+    pub uuid: Uuid,
+    pub date: Option<Date>,
+    pub kind: Option<NoteType>,
+    pub summary: String,
+    pub details: Option<String>,
+    pub sampleid: i64,
+    pub sampleuuid: Uuid,
+    pub tsn: i64,
+    /// the complete name of the sample's taxon
+    pub taxon: String,
+    pub project: Option<NoteProject>,
+}
+
+impl FromRow<'_, SqliteRow> for NoteEntry {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        let project = match row.try_get::<Option<i64>, _>("projectid")? {
+            Some(id) => Some(NoteProject {
+                id,
+                uuid: try_get_uuid(row, "projuuid")?,
+                name: row.try_get("projname")?,
+                allocid: row.try_get("psid")?,
+                allocuuid: try_get_uuid(row, "psuuid")?,
+            }),
+            None => None,
+        };
+        Ok(Self {
+            source: row.try_get("notesource")?,
+            id: row.try_get("noteid")?,
+            uuid: try_get_uuid(row, "noteuuid")?,
+            date: row.try_get("notedate")?,
+            kind: row.try_get("notetype")?,
+            summary: row.try_get("notesummary")?,
+            details: row.try_get("notedetails")?,
+            sampleid: row.try_get("sampleid")?,
+            sampleuuid: try_get_uuid(row, "sampleuuid")?,
+            tsn: row.try_get("tsn")?,
+            taxon: row.try_get("complete_name")?,
+            project,
+        })
+    }
+}
+
+impl NoteEntry {
+    fn list_query(filter: Option<DynFilterPart>) -> ListQuery {
+        ListQuery::new(COLUMNS, TABLES, filter)
+    }
+
+    /// Load the notes that match `filter`, the most recent first. Sample notes have no date, so
+    /// they are listed after all of the dated notes.
+    pub async fn load_all(
+        filter: Option<DynFilterPart>,
+        limit: Option<LimitSpec>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let mut builder = Self::list_query(filter).select();
+        builder.push(" ORDER BY notedate IS NULL, notedate DESC, notesource, noteid DESC");
+        if let Some(limit) = limit {
+            limit.push_to(&mut builder);
+        }
+        tracing::debug!("GENERATED SQL: {}", builder.sql());
+        Ok(builder.build_query_as().fetch_all(pool).await?)
+    }
+
+    /// The number of notes that match `filter`
+    pub async fn count(filter: Option<DynFilterPart>, pool: &Pool<Sqlite>) -> Result<i64> {
+        Self::list_query(filter).fetch_count(pool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::{CompoundFilter, Op};
+    use test_log::test;
+    use time::macros::date;
+
+    async fn ids(filter: impl Into<DynFilterPart>, pool: &Pool<Sqlite>) -> Vec<(NoteSource, i64)> {
+        NoteEntry::load_all(Some(filter.into()), None, pool)
+            .await
+            .expect("Failed to load notes")
+            .into_iter()
+            .map(|n| (n.source, n.id))
+            .collect()
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "csnotes")
+        )
+    ))]
+    async fn load_notes(pool: Pool<Sqlite>) {
+        let notes = NoteEntry::load_all(Some(Filter::Accessible(1).into()), None, &pool)
+            .await
+            .expect("Failed to load notes");
+        // allocation notes by date, then the sample notes
+        assert_eq!(
+            notes.iter().map(|n| (n.source, n.id)).collect::<Vec<_>>(),
+            [
+                (NoteSource::Allocation, 3),
+                (NoteSource::Allocation, 1),
+                (NoteSource::Allocation, 2),
+                (NoteSource::Sample, 1),
+            ]
+        );
+        let note = &notes[0];
+        assert_eq!(note.date, Some(date!(2024 - 01 - 16)));
+        assert_eq!(note.kind, Some(NoteType::Preparation));
+        assert_eq!(note.summary, "summary 3");
+        assert_eq!(note.details.as_deref(), Some("details 3"));
+        assert_eq!(note.sampleid, 2);
+        assert_eq!(note.taxon, "Elymus canadensis");
+        let project = note.project.as_ref().expect("no project");
+        assert_eq!((project.id, project.allocid), (1, 2));
+        let note = &notes[3];
+        assert_eq!(note.summary, "These are some notes");
+        assert_eq!((note.date, note.kind, &note.project), (None, None, &None));
+        assert!(
+            NoteEntry::load_all(Some(Filter::Accessible(2).into()), None, &pool)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(
+            NoteEntry::count(Some(Filter::Accessible(1).into()), &pool)
+                .await
+                .unwrap(),
+            4
+        );
+        let page = NoteEntry::load_all(None, Some(LimitSpec(2, Some(1))), &pool)
+            .await
+            .unwrap();
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].id, 1);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "csnotes")
+        )
+    ))]
+    async fn filter_notes(pool: Pool<Sqlite>) {
+        use NoteSource::*;
+        sqlx::query(
+            r#"INSERT INTO hierarchy (hierarchy_string, TSN, Parent_TSN, level, ChildrenCount)
+            VALUES ("202422-846542-846620-40351-40677", 40677, 40351, 4, 1),
+                   ("202422-846542-846620-40351-40677-40683", 40683, 40677, 5, 0),
+                   ("202422-846542-897479-43190-43237", 43237, 43190, 4, 1),
+                   ("202422-846542-897479-43190-43237-43254", 43254, 43237, 5, 0)"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert_eq!(ids(Filter::Source(Sample), &pool).await, [(Sample, 1)]);
+        assert_eq!(
+            ids(Filter::Type(NoteType::Planting), &pool).await,
+            [(Allocation, 1), (Allocation, 2)]
+        );
+        assert_eq!(
+            ids(Filter::DateFrom(date!(2024 - 01 - 13)), &pool).await,
+            [(Allocation, 3), (Allocation, 1)]
+        );
+        assert_eq!(
+            ids(Filter::DateTo(date!(2024 - 01 - 13)), &pool).await,
+            [(Allocation, 2)]
+        );
+        // the text is matched in the summary and the details
+        assert_eq!(
+            ids(Filter::TextLike("details".to_string()), &pool).await,
+            [(Allocation, 3), (Allocation, 1)]
+        );
+        assert_eq!(
+            ids(Filter::TextLike("SOME".to_string()), &pool).await,
+            [(Sample, 1)]
+        );
+        assert_eq!(
+            ids(Filter::ProjectId(2), &pool).await,
+            Vec::<(NoteSource, i64)>::new()
+        );
+        assert_eq!(
+            ids(Filter::SampleId(1), &pool).await,
+            [(Allocation, 1), (Allocation, 2), (Sample, 1)]
+        );
+        // sample 3 is the only sample of a taxon below 43237, and it has no notes
+        assert!(ids(Filter::AncestorTsn(43237), &pool).await.is_empty());
+        assert_eq!(ids(Filter::AncestorTsn(40677), &pool).await.len(), 4);
+        assert_eq!(
+            ids(
+                CompoundFilter::builder(Op::And)
+                    .push(Filter::ProjectId(1))
+                    .push(Filter::DateFrom(date!(2024 - 01 - 14)))
+                    .push(Filter::TextLike("summary".to_string()))
+                    .build(),
+                &pool
+            )
+            .await,
+            [(Allocation, 3), (Allocation, 1)]
+        );
+    }
+}
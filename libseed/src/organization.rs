@@ -0,0 +1,461 @@
+//! Organizations allow a group of users to share ownership of objects in the database. A project,
+//! source or sample that is owned by an organization is accessible to all members of the
+//! organization according to their role, in addition to the user that created it.
+use crate::{
+    error::{Error, Result},
+    loadable::Loadable,
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteQueryResult, FromRow, Pool, Sqlite};
+use strum_macros::{Display, EnumIter, EnumString};
+use time::OffsetDateTime;
+
+/// The role of a user within an organization. Roles are ordered, so each role has all of the
+/// permissions of the roles below it.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    sqlx::Type,
+    Display,
+    EnumIter,
+    EnumString,
+)]
+#[repr(i64)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum OrgRole {
+    /// can view the organization's objects
+    Viewer = 1,
+    /// can also modify the organization's objects and add new ones
+    Member = 2,
+    /// can also delete the organization's objects and manage the organization's members
+    Admin = 3,
+}
+
+/// The kinds of access that a user can have to an object
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Permission {
+    View,
+    Edit,
+    /// delete the object or change its owner
+    Manage,
+}
+
+impl OrgRole {
+    pub fn permits(&self, permission: Permission) -> bool {
+        match permission {
+            Permission::View => true,
+            Permission::Edit => *self >= OrgRole::Member,
+            Permission::Manage => *self >= OrgRole::Admin,
+        }
+    }
+}
+
+/// An object that is owned by a user and can optionally be shared with an organization
+pub trait Owned {
+    /// the id of the user that owns the object
+    fn owner(&self) -> i64;
+
+    /// the id of the organization that the object belongs to, if any
+    fn organization(&self) -> Option<i64>;
+}
+
+/// Check whether the user has the given permission for an object. The owner of an object always
+/// has every permission, and members of the organization that the object belongs to have the
+/// permissions granted by their role.
+pub async fn has_permission<T: Owned + ?Sized>(
+    object: &T,
+    userid: i64,
+    permission: Permission,
+    pool: &Pool<Sqlite>,
+) -> Result<bool> {
+    if object.owner() == userid {
+        return Ok(true);
+    }
+    match object.organization() {
+        Some(orgid) => Ok(Organization::role(orgid, userid, pool)
+            .await?
+            .is_some_and(|role| role.permits(permission))),
+        None => Ok(false),
+    }
+}
+
+/// An SQL condition that matches the rows whose owner column is the given user or whose
+/// organization column is one of the organizations that the user is a member of
+pub(crate) fn push_accessible_condition(
+    builder: &mut sqlx::QueryBuilder<Sqlite>,
+    usercol: &str,
+    orgcol: &str,
+    userid: i64,
+) {
+    builder
+        .push(format!(" ({usercol} = "))
+        .push_bind(userid)
+        .push(format!(
+            " OR {orgcol} IN (SELECT orgid FROM sc_org_members WHERE userid = "
+        ))
+        .push_bind(userid)
+        .push("))");
+}
+
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Organization {
+    #[sqlx(rename = "orgid")]
+    pub id: i64,
+    #[sqlx(rename = "orgname")]
+    pub name: String,
+    #[sqlx(rename = "orgdescription")]
+    pub description: Option<String>,
+    #[sqlx(rename = "orgcreated")]
+    pub created: Option<OffsetDateTime>,
+}
+
+/// A user's membership in an organization
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Member {
+    pub orgid: i64,
+    pub userid: i64,
+    pub username: String,
+    #[sqlx(rename = "userdisplayname")]
+    pub display_name: Option<String>,
+    #[sqlx(rename = "memberrole")]
+    pub role: OrgRole,
+}
+
+#[async_trait]
+impl Loadable for Organization {
+    type Id = i64;
+
+    fn id(&self) -> Self::Id {
+        self.id
+    }
+
+    fn set_id(&mut self, id: Self::Id) {
+        self.id = id
+    }
+
+    async fn load(id: Self::Id, pool: &Pool<Sqlite>) -> Result<Self> {
+        sqlx::query_as("SELECT * FROM sc_organizations WHERE orgid=?")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    async fn delete_id(id: &Self::Id, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        sqlx::query("DELETE FROM sc_organizations WHERE orgid=?")
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+}
+
+impl Organization {
+    pub fn new(name: String, description: Option<String>) -> Self {
+        Self {
+            id: -1,
+            name,
+            description,
+            created: None,
+        }
+    }
+
+    /// Add the organization to the database, with the given user as its first admin
+    pub async fn insert(&mut self, admin: i64, pool: &Pool<Sqlite>) -> Result<()> {
+        if self.id != -1 {
+            return Err(Error::InvalidOperationObjectAlreadyExists(self.id));
+        }
+        if self.name.trim().is_empty() {
+            return Err(Error::InvalidStateMissingAttribute("name".to_string()));
+        }
+        let mut tx = pool.begin().await?;
+        let orgid =
+            sqlx::query("INSERT INTO sc_organizations (orgname, orgdescription) VALUES (?, ?)")
+                .bind(&self.name)
+                .bind(&self.description)
+                .execute(&mut *tx)
+                .await?
+                .last_insert_rowid();
+        sqlx::query("INSERT INTO sc_org_members (orgid, userid, memberrole) VALUES (?, ?, ?)")
+            .bind(orgid)
+            .bind(admin)
+            .bind(OrgRole::Admin)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        *self = Self::load(orgid, pool).await?;
+        Ok(())
+    }
+
+    pub async fn update(&self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        if self.name.trim().is_empty() {
+            return Err(Error::InvalidStateMissingAttribute("name".to_string()));
+        }
+        sqlx::query("UPDATE sc_organizations SET orgname=?, orgdescription=? WHERE orgid=?")
+            .bind(&self.name)
+            .bind(&self.description)
+            .bind(self.id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Load all organizations that the user is a member of, along with the user's role in each
+    pub async fn load_all_user(
+        userid: i64,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<(Organization, OrgRole)>> {
+        let rows: Vec<(i64, OrgRole)> = sqlx::query_as(
+            r#"SELECT O.orgid, M.memberrole FROM sc_organizations O
+            INNER JOIN sc_org_members M ON M.orgid=O.orgid
+            WHERE M.userid=? ORDER BY O.orgname"#,
+        )
+        .bind(userid)
+        .fetch_all(pool)
+        .await?;
+        let mut orgs = Vec::new();
+        for (orgid, role) in rows {
+            orgs.push((Organization::load(orgid, pool).await?, role));
+        }
+        Ok(orgs)
+    }
+
+    /// The role of the user in the organization, or `None` if the user isn't a member
+    pub async fn role(orgid: i64, userid: i64, pool: &Pool<Sqlite>) -> Result<Option<OrgRole>> {
+        sqlx::query_scalar("SELECT memberrole FROM sc_org_members WHERE orgid=? AND userid=?")
+            .bind(orgid)
+            .bind(userid)
+            .fetch_optional(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    pub async fn members(&self, pool: &Pool<Sqlite>) -> Result<Vec<Member>> {
+        sqlx::query_as(
+            r#"SELECT M.orgid, M.userid, M.memberrole, U.username, U.userdisplayname
+            FROM sc_org_members M INNER JOIN sc_users U ON U.userid=M.userid
+            WHERE M.orgid=? ORDER BY M.memberrole DESC, U.username"#,
+        )
+        .bind(self.id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    /// Add a user to the organization, or change the role of an existing member
+    pub async fn set_member(
+        &self,
+        userid: i64,
+        role: OrgRole,
+        pool: &Pool<Sqlite>,
+    ) -> Result<SqliteQueryResult> {
+        if role != OrgRole::Admin {
+            self.check_other_admins(userid, pool).await?;
+        }
+        sqlx::query(
+            r#"INSERT INTO sc_org_members (orgid, userid, memberrole) VALUES (?, ?, ?)
+            ON CONFLICT(orgid, userid) DO UPDATE SET memberrole=excluded.memberrole"#,
+        )
+        .bind(self.id)
+        .bind(userid)
+        .bind(role)
+        .execute(pool)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    pub async fn remove_member(&self, userid: i64, pool: &Pool<Sqlite>) -> Result<()> {
+        self.check_other_admins(userid, pool).await?;
+        let res = sqlx::query("DELETE FROM sc_org_members WHERE orgid=? AND userid=?")
+            .bind(self.id)
+            .bind(userid)
+            .execute(pool)
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound.into());
+        }
+        Ok(())
+    }
+
+    /// An organization must always have at least one admin, so fail if the given user is the only
+    /// admin of the organization
+    async fn check_other_admins(&self, userid: i64, pool: &Pool<Sqlite>) -> Result<()> {
+        let others: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM sc_org_members WHERE orgid=? AND memberrole=? AND userid != ?",
+        )
+        .bind(self.id)
+        .bind(OrgRole::Admin)
+        .bind(userid)
+        .fetch_one(pool)
+        .await?;
+        if others == 0 && Self::role(self.id, userid, pool).await? == Some(OrgRole::Admin) {
+            return Err(Error::InvalidOperation(
+                "An organization must have at least one admin".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        filter::{CompoundFilter, Op},
+        project::{self, Project},
+        sample::{self, Sample},
+        source::Source,
+    };
+    use test_log::test;
+
+    #[test]
+    fn role_permissions() {
+        assert!(OrgRole::Viewer.permits(Permission::View));
+        assert!(!OrgRole::Viewer.permits(Permission::Edit));
+        assert!(OrgRole::Member.permits(Permission::Edit));
+        assert!(!OrgRole::Member.permits(Permission::Manage));
+        assert!(OrgRole::Admin.permits(Permission::Manage));
+        assert_eq!("member".parse::<OrgRole>().unwrap(), OrgRole::Member);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users"))
+    ))]
+    async fn manage_members(pool: Pool<Sqlite>) {
+        let mut org = Organization::new("Prairie Group".to_string(), None);
+        org.insert(1, &pool)
+            .await
+            .expect("Failed to insert organization");
+        assert!(org.id > 0);
+        assert_eq!(
+            Organization::role(org.id, 1, &pool).await.unwrap(),
+            Some(OrgRole::Admin)
+        );
+        assert_eq!(Organization::role(org.id, 2, &pool).await.unwrap(), None);
+
+        org.set_member(2, OrgRole::Viewer, &pool)
+            .await
+            .expect("Failed to add member");
+        let members = org.members(&pool).await.expect("Failed to load members");
+        assert_eq!(
+            members
+                .iter()
+                .map(|m| (m.userid, m.role))
+                .collect::<Vec<_>>(),
+            vec![(1, OrgRole::Admin), (2, OrgRole::Viewer)]
+        );
+        let orgs = Organization::load_all_user(2, &pool).await.unwrap();
+        assert_eq!(orgs, vec![(org.clone(), OrgRole::Viewer)]);
+
+        // the last admin can't be removed or demoted
+        assert!(org.remove_member(1, &pool).await.is_err());
+        assert!(org.set_member(1, OrgRole::Member, &pool).await.is_err());
+        org.set_member(2, OrgRole::Admin, &pool).await.unwrap();
+        org.set_member(1, OrgRole::Member, &pool)
+            .await
+            .expect("Failed to change role");
+        org.remove_member(1, &pool)
+            .await
+            .expect("Failed to remove member");
+        assert!(org.remove_member(1, &pool).await.is_err());
+
+        // names are unique
+        let mut dup = Organization::new("Prairie Group".to_string(), None);
+        assert!(dup.insert(2, &pool).await.is_err());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn shared_objects(pool: Pool<Sqlite>) {
+        let mut org = Organization::new("Prairie Group".to_string(), None);
+        org.insert(1, &pool)
+            .await
+            .expect("Failed to insert organization");
+
+        let mut project = Project::load(1, &pool).await.unwrap();
+        assert!(!has_permission(&project, 2, Permission::View, &pool)
+            .await
+            .unwrap());
+        project.orgid = Some(org.id);
+        project
+            .update(&pool)
+            .await
+            .expect("Failed to update project");
+        let mut sample = Sample::load(1, &pool).await.unwrap();
+        sample.orgid = Some(org.id);
+        sample.update(&pool).await.expect("Failed to update sample");
+
+        // members of the organization can access its objects according to their role
+        org.set_member(2, OrgRole::Viewer, &pool).await.unwrap();
+        let project = Project::load(1, &pool).await.unwrap();
+        assert_eq!(project.orgid, Some(org.id));
+        assert!(has_permission(&project, 2, Permission::View, &pool)
+            .await
+            .unwrap());
+        assert!(!has_permission(&project, 2, Permission::Edit, &pool)
+            .await
+            .unwrap());
+        // the owner always has full access
+        assert!(has_permission(&project, 1, Permission::Manage, &pool)
+            .await
+            .unwrap());
+        org.set_member(2, OrgRole::Member, &pool).await.unwrap();
+        assert!(has_permission(&project, 2, Permission::Edit, &pool)
+            .await
+            .unwrap());
+        assert!(!has_permission(&project, 2, Permission::Manage, &pool)
+            .await
+            .unwrap());
+
+        let projects = Project::load_all(Some(project::Filter::Accessible(2).into()), &pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            projects.iter().map(|p| p.id).collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+        let samples = Sample::load_all_user(2, None, None, &pool).await.unwrap();
+        let mut ids = samples.iter().map(|s| s.id).collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(ids, vec![1, 4]);
+        let sample = Sample::load(1, &pool).await.unwrap();
+        assert_eq!(sample.orgid, Some(org.id));
+        assert!(Sample::load_all(
+            Some(
+                CompoundFilter::builder(Op::And)
+                    .push(sample::Filter::Accessible(2))
+                    .push(sample::Filter::Id(crate::filter::Cmp::Equal, 2))
+                    .build()
+            ),
+            None,
+            &pool
+        )
+        .await
+        .unwrap()
+        .is_empty());
+        assert!(Source::load_all_user(2, &pool).await.unwrap().is_empty());
+
+        // deleting the organization returns its objects to their owners
+        Organization::delete_id(&org.id, &pool).await.unwrap();
+        let project = Project::load(1, &pool).await.unwrap();
+        assert_eq!(project.orgid, None);
+        assert!(!has_permission(&project, 2, Permission::View, &pool)
+            .await
+            .unwrap());
+    }
+}
@@ -0,0 +1,267 @@
+//! Photos of samples, e.g. of the seeds or the seed heads they were collected from. The photos of
+//! all samples of a taxon make up a gallery that can be used as a visual reference when
+//! identifying seeds.
+//!
+//! The images are stored in the database rather than in a separate attachment store. Photos are
+//! limited to [MAX_PHOTO_SIZE], they are removed together with their sample, and a backup of the
+//! database file includes them, so an installation has nothing else to manage. They are only
+//! served by the app after checking that the user can view the sample, which doesn't need signed
+//! download URLs. [Photo::insert] and [Photo::load] are the only places that read or write the
+//! image data, so a storage backend for the image data can be added there if the database grows
+//! too large.
+use crate::{
+    error::{Error, Result},
+    organization::push_accessible_condition,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteQueryResult, FromRow, Pool, QueryBuilder, Sqlite};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// The types of images that can be uploaded, which all browsers can display
+pub const CONTENT_TYPES: [&str; 4] = ["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+/// The largest photo that can be uploaded, in bytes
+pub const MAX_PHOTO_SIZE: usize = 10 * 1024 * 1024;
+
+/// The type of image that the data is according to the magic bytes at its start, so that files
+/// that aren't images can't be uploaded with an image content type
+fn detect_content_type(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, ..] => Some("image/png"),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Photo {
+    #[sqlx(rename = "photoid")]
+    pub id: i64,
+    pub sampleid: i64,
+    /// the MIME type of the image, one of [CONTENT_TYPES]
+    #[sqlx(rename = "phototype")]
+    pub content_type: String,
+    /// the image itself. It is only loaded by [Photo::load], not when listing photos.
+    #[sqlx(rename = "photodata", default)]
+    #[serde(skip)]
+    pub data: Option<Vec<u8>>,
+    #[sqlx(rename = "photocaption")]
+    pub caption: Option<String>,
+    #[sqlx(rename = "photoadded")]
+    pub added: Option<OffsetDateTime>,
+}
+
+/// The columns of a photo without its data
+const LIST_COLUMNS: &str = "P.photoid, P.sampleid, P.phototype, P.photocaption, P.photoadded";
+
+impl Photo {
+    pub fn new(
+        sampleid: i64,
+        content_type: String,
+        data: Vec<u8>,
+        caption: Option<String>,
+    ) -> Self {
+        Self {
+            id: -1,
+            sampleid,
+            content_type,
+            data: Some(data),
+            caption,
+            added: None,
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !CONTENT_TYPES.contains(&self.content_type.as_str()) {
+            return Err(Error::InvalidValue(
+                "Photos must be JPEG, PNG, GIF or WebP images".to_string(),
+            ));
+        }
+        match self.data.as_ref().map(Vec::len) {
+            None | Some(0) => Err(Error::InvalidValue("The photo is empty".to_string())),
+            Some(len) if len > MAX_PHOTO_SIZE => Err(Error::InvalidValue(format!(
+                "Photos can be at most {} MB",
+                MAX_PHOTO_SIZE / 1024 / 1024
+            ))),
+            Some(_) => match self.data.as_deref().and_then(detect_content_type) {
+                Some(detected) if detected == self.content_type => Ok(()),
+                _ => Err(Error::InvalidValue(
+                    "The file is not a valid image of the given type".to_string(),
+                )),
+            },
+        }
+    }
+
+    pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        if self.id != -1 {
+            return Err(Error::InvalidOperationObjectAlreadyExists(self.id));
+        }
+        self.validate()?;
+        self.caption = self
+            .caption
+            .as_deref()
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .map(str::to_string);
+        let res = sqlx::query(
+            r#"INSERT INTO sc_sample_photos (sampleid, phototype, photodata, photocaption)
+            VALUES (?, ?, ?, ?)"#,
+        )
+        .bind(self.sampleid)
+        .bind(&self.content_type)
+        .bind(&self.data)
+        .bind(&self.caption)
+        .execute(pool)
+        .await?;
+        self.id = res.last_insert_rowid();
+        Ok(res)
+    }
+
+    /// Load the photo with the given id, including the image
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        Ok(
+            sqlx::query_as("SELECT * FROM sc_sample_photos WHERE photoid=?")
+                .bind(id)
+                .fetch_one(pool)
+                .await?,
+        )
+    }
+
+    /// Load all photos of a sample in the order that they were added, without the images
+    pub async fn load_sample(sampleid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as(&format!(
+            "SELECT {LIST_COLUMNS} FROM sc_sample_photos P WHERE sampleid=? ORDER BY photoid"
+        ))
+        .bind(sampleid)
+        .fetch_all(pool)
+        .await?)
+    }
+
+    pub async fn delete(&self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        sqlx::query("DELETE FROM sc_sample_photos WHERE photoid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+}
+
+/// A photo in the gallery of a taxon, along with the details of the sample that it shows
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct GalleryPhoto {
+    #[sqlx(flatten)]
+    pub photo: Photo,
+    #[sqlx(rename = "sampleuuid", try_from = "String")]
+    pub sampleuuid: Uuid,
+    #[sqlx(rename = "srcname")]
+    pub source: String,
+    pub month: Option<u32>,
+    pub year: Option<u32>,
+}
+
+/// The photos of all samples of the given taxon that the user has access to, the photos of the
+/// most recently collected samples first. The images are not loaded.
+pub async fn load_gallery(tsn: i64, userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<GalleryPhoto>> {
+    let mut builder = QueryBuilder::new(format!(
+        r#"SELECT {LIST_COLUMNS}, S.sampleuuid, L.srcname, S.month, S.year
+        FROM sc_sample_photos P
+        INNER JOIN sc_samples S ON S.sampleid=P.sampleid
+        INNER JOIN sc_sources L ON L.srcid=S.srcid
+        WHERE S.tsn="#
+    ));
+    builder.push_bind(tsn).push(" AND");
+    push_accessible_condition(&mut builder, "S.userid", "S.sampleorgid", userid);
+    builder.push(
+        " ORDER BY S.year IS NULL, S.year DESC, S.month IS NULL, S.month DESC, P.sampleid, P.photoid",
+    );
+    Ok(builder.build_query_as().fetch_all(pool).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\nfirst";
+    const JPEG: &[u8] = b"\xff\xd8\xffsecond";
+
+    #[test]
+    fn content_types() {
+        assert_eq!(detect_content_type(PNG), Some("image/png"));
+        assert_eq!(detect_content_type(JPEG), Some("image/jpeg"));
+        assert_eq!(detect_content_type(b"GIF87a"), Some("image/gif"));
+        assert_eq!(
+            detect_content_type(b"RIFF\0\0\0\0WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(detect_content_type(b"RIFF\0\0\0\0WAVE"), None);
+        assert_eq!(detect_content_type(b"<svg>"), None);
+        assert_eq!(detect_content_type(b""), None);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn sample_photos(pool: Pool<Sqlite>) {
+        let mut photo = Photo::new(2, "text/plain".to_string(), b"hello".to_vec(), None);
+        assert!(matches!(
+            photo.insert(&pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        let mut photo = Photo::new(2, "image/png".to_string(), Vec::new(), None);
+        assert!(matches!(
+            photo.insert(&pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+
+        // the data has to match the content type
+        let mut photo = Photo::new(2, "image/png".to_string(), b"<html>".to_vec(), None);
+        assert!(matches!(
+            photo.insert(&pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        let mut photo = Photo::new(2, "image/png".to_string(), JPEG.to_vec(), None);
+        assert!(matches!(
+            photo.insert(&pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+
+        let mut first = Photo::new(
+            2,
+            "image/png".to_string(),
+            PNG.to_vec(),
+            Some("  Seed heads ".to_string()),
+        );
+        first.insert(&pool).await.expect("Failed to insert photo");
+        assert_eq!(first.caption.as_deref(), Some("Seed heads"));
+        let mut second = Photo::new(3, "image/jpeg".to_string(), JPEG.to_vec(), None);
+        second.insert(&pool).await.expect("Failed to insert photo");
+        // a sample of the same taxon that belongs to somebody else
+        let mut other = Photo::new(4, "image/gif".to_string(), b"GIF89a".to_vec(), None);
+        other.insert(&pool).await.expect("Failed to insert photo");
+
+        let loaded = Photo::load(first.id, &pool).await.unwrap();
+        assert_eq!(loaded.data.as_deref(), Some(PNG));
+        let listed = Photo::load_sample(2, &pool).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].data, None);
+        assert_eq!(listed[0].caption.as_deref(), Some("Seed heads"));
+
+        let gallery = load_gallery(40683, 1, &pool).await.unwrap();
+        let mut ids: Vec<i64> = gallery.iter().map(|p| p.photo.id).collect();
+        ids.sort();
+        assert_eq!(ids, vec![first.id, second.id]);
+        assert!(gallery.iter().all(|p| p.photo.data.is_none()));
+        assert!(load_gallery(43254, 1, &pool).await.unwrap().is_empty());
+
+        first.delete(&pool).await.unwrap();
+        assert!(Photo::load_sample(2, &pool).await.unwrap().is_empty());
+    }
+}
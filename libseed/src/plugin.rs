@@ -0,0 +1,335 @@
+//! Hooks that let a deployment add its own validation or enrichment of the data without changing
+//! this library. A [Plugin] implements any of the hooks that it needs, and is registered once at
+//! startup with [register]. The hooks of all registered plugins are called in the order that the
+//! plugins were registered whenever a sample or source is saved.
+//!
+//! A few generally useful plugins are built in and can be enabled by name with [load_builtin],
+//! which is how the web app and `seedctl` enable the plugins listed in their configuration.
+use crate::{
+    conservation::Listing,
+    error::{Error, Result},
+    sample::Sample,
+    source::Source,
+};
+use async_trait::async_trait;
+use sqlx::{Pool, Sqlite};
+use std::{
+    fmt::Debug,
+    sync::{Arc, PoisonError, RwLock},
+};
+use time::OffsetDateTime;
+use tracing::debug;
+
+static PLUGINS: RwLock<Vec<Arc<dyn Plugin>>> = RwLock::new(Vec::new());
+
+/// The names of the plugins that are built in, see [builtin]
+pub const BUILTIN_PLUGINS: [&str; 3] = [
+    RequireCoordinates::NAME,
+    NoFutureDates::NAME,
+    ProtectListedTaxa::NAME,
+];
+
+/// Hooks that are called when objects are saved. All hooks do nothing by default. A hook that
+/// returns an error prevents the object from being saved, or in the case of the hooks that run
+/// after saving, is returned from the function that saved it.
+#[async_trait]
+pub trait Plugin: Debug + Send + Sync {
+    /// A short name for the plugin, used in log messages
+    fn name(&self) -> &str;
+
+    /// Called before a new or modified sample is saved. The sample may be modified.
+    async fn before_sample_save(&self, _sample: &mut Sample, _pool: &Pool<Sqlite>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after a sample has been saved
+    async fn after_sample_save(&self, _sample: &Sample, _pool: &Pool<Sqlite>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called before a new or modified source is saved. The source may be modified.
+    async fn before_source_save(&self, _source: &mut Source, _pool: &Pool<Sqlite>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called after a new source has been added to the database
+    async fn after_source_create(&self, _source: &Source, _pool: &Pool<Sqlite>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Add a plugin whose hooks are called from now on
+pub fn register(plugin: Arc<dyn Plugin>) {
+    debug!(plugin = plugin.name(), "Registering plugin");
+    PLUGINS
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(plugin);
+}
+
+/// Create the built-in plugin with the given name
+pub fn builtin(name: &str) -> Result<Arc<dyn Plugin>> {
+    match name {
+        RequireCoordinates::NAME => Ok(Arc::new(RequireCoordinates)),
+        NoFutureDates::NAME => Ok(Arc::new(NoFutureDates)),
+        ProtectListedTaxa::NAME => Ok(Arc::new(ProtectListedTaxa)),
+        _ => Err(Error::InvalidValue(format!(
+            "Unknown plugin '{name}'. Available plugins: {}",
+            BUILTIN_PLUGINS.join(", ")
+        ))),
+    }
+}
+
+/// Register the built-in plugins with the given names. Nothing is registered if any of the names
+/// is unknown.
+pub fn load_builtin<S: AsRef<str>>(names: &[S]) -> Result<()> {
+    let plugins = names
+        .iter()
+        .map(|name| builtin(name.as_ref()))
+        .collect::<Result<Vec<_>>>()?;
+    plugins.into_iter().for_each(register);
+    Ok(())
+}
+
+/// The registered plugins. The list is copied so that the lock isn't held while hooks run.
+fn registered() -> Vec<Arc<dyn Plugin>> {
+    PLUGINS
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+pub(crate) async fn before_sample_save(sample: &mut Sample, pool: &Pool<Sqlite>) -> Result<()> {
+    for plugin in registered() {
+        plugin.before_sample_save(sample, pool).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn after_sample_save(sample: &Sample, pool: &Pool<Sqlite>) -> Result<()> {
+    for plugin in registered() {
+        plugin.after_sample_save(sample, pool).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn before_source_save(source: &mut Source, pool: &Pool<Sqlite>) -> Result<()> {
+    for plugin in registered() {
+        plugin.before_source_save(source, pool).await?;
+    }
+    Ok(())
+}
+
+pub(crate) async fn after_source_create(source: &Source, pool: &Pool<Sqlite>) -> Result<()> {
+    for plugin in registered() {
+        plugin.after_source_create(source, pool).await?;
+    }
+    Ok(())
+}
+
+/// Rejects sources without coordinates, for collections that need to know where every sample was
+/// collected
+#[derive(Debug)]
+pub struct RequireCoordinates;
+
+impl RequireCoordinates {
+    const NAME: &'static str = "require-coordinates";
+}
+
+#[async_trait]
+impl Plugin for RequireCoordinates {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    async fn before_source_save(&self, source: &mut Source, _pool: &Pool<Sqlite>) -> Result<()> {
+        match (source.latitude, source.longitude) {
+            (Some(_), Some(_)) => Ok(()),
+            _ => Err(Error::InvalidValue(format!(
+                "The source '{}' needs a latitude and longitude",
+                source.name
+            ))),
+        }
+    }
+}
+
+/// Rejects samples with a collection date in the future, which is usually a typo in the year
+#[derive(Debug)]
+pub struct NoFutureDates;
+
+impl NoFutureDates {
+    const NAME: &'static str = "no-future-dates";
+}
+
+#[async_trait]
+impl Plugin for NoFutureDates {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    async fn before_sample_save(&self, sample: &mut Sample, _pool: &Pool<Sqlite>) -> Result<()> {
+        let today = OffsetDateTime::now_utc().date();
+        let (year, month) = (today.year() as u32, today.month() as u32);
+        let future = match (sample.year, sample.month) {
+            (Some(y), _) if y > year => true,
+            (Some(y), Some(m)) if y == year => m > month,
+            _ => false,
+        };
+        match future {
+            true => Err(Error::InvalidValue(
+                "The collection date of a sample can't be in the future".to_string(),
+            )),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Marks the source of a sample as sensitive when the sample's taxon has a conservation listing,
+/// so that the location of the population is protected without having to remember to do it
+#[derive(Debug)]
+pub struct ProtectListedTaxa;
+
+impl ProtectListedTaxa {
+    const NAME: &'static str = "protect-listed-taxa";
+}
+
+#[async_trait]
+impl Plugin for ProtectListedTaxa {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    async fn after_sample_save(&self, sample: &Sample, pool: &Pool<Sqlite>) -> Result<()> {
+        if Listing::load_taxon(sample.taxon.id(), pool)
+            .await?
+            .is_empty()
+        {
+            return Ok(());
+        }
+        sqlx::query(
+            r#"UPDATE sc_sources SET srcsensitive=1, srcversion=srcversion+1
+            WHERE srcid=? AND srcsensitive=0"#,
+        )
+        .bind(sample.source.id())
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{loadable::Loadable, source::Source};
+    use test_log::test;
+
+    /// Plugins are registered for the whole process, so this one only touches sources that are
+    /// created by its own test
+    #[derive(Debug)]
+    struct Marker;
+
+    const MARKER: &str = "plugin test:";
+
+    #[async_trait]
+    impl Plugin for Marker {
+        fn name(&self) -> &str {
+            "marker"
+        }
+
+        async fn before_source_save(
+            &self,
+            source: &mut Source,
+            _pool: &Pool<Sqlite>,
+        ) -> Result<()> {
+            if !source.name.starts_with(MARKER) {
+                return Ok(());
+            }
+            if source.name.ends_with("reject") {
+                return Err(Error::InvalidValue("rejected".to_string()));
+            }
+            source.description = Some("enriched".to_string());
+            Ok(())
+        }
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users"))
+    ))]
+    async fn test_registered_hooks(pool: Pool<Sqlite>) {
+        register(Arc::new(Marker));
+        let mut source = Source::new(format!("{MARKER} enrich"), None, None, None, 1);
+        source.insert(&pool).await.expect("Failed to insert source");
+        assert_eq!(source.description.as_deref(), Some("enriched"));
+        let loaded = Source::load(source.id, &pool).await.unwrap();
+        assert_eq!(loaded.description.as_deref(), Some("enriched"));
+
+        let mut source = Source::new(format!("{MARKER} reject"), None, None, None, 1);
+        assert!(matches!(
+            source.insert(&pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        assert_eq!(source.id, -1);
+    }
+
+    #[test]
+    fn test_builtin() {
+        for name in BUILTIN_PLUGINS {
+            assert_eq!(builtin(name).unwrap().name(), name);
+        }
+        assert!(builtin("county-lookup").is_err());
+        assert!(load_builtin(&["no-future-dates", "county-lookup"]).is_err());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn test_builtin_hooks(pool: Pool<Sqlite>) {
+        let mut source = Source::load(1, &pool).await.unwrap();
+        assert!(RequireCoordinates
+            .before_source_save(&mut source, &pool)
+            .await
+            .is_ok());
+        source.latitude = None;
+        assert!(RequireCoordinates
+            .before_source_save(&mut source, &pool)
+            .await
+            .is_err());
+
+        let mut sample = Sample::load(1, &pool).await.unwrap();
+        assert!(NoFutureDates
+            .before_sample_save(&mut sample, &pool)
+            .await
+            .is_ok());
+        sample.year = Some(OffsetDateTime::now_utc().year() as u32 + 1);
+        assert!(NoFutureDates
+            .before_sample_save(&mut sample, &pool)
+            .await
+            .is_err());
+
+        // the source only becomes sensitive once the taxon is listed
+        let srcid = sample.source.id();
+        let sensitive = || async { Source::load(srcid, &pool).await.unwrap().sensitive };
+        ProtectListedTaxa
+            .after_sample_save(&sample, &pool)
+            .await
+            .unwrap();
+        assert!(!sensitive().await);
+        sqlx::query(
+            "INSERT INTO sc_conservation_status (tsn, region, consstatus) VALUES (?, 'MN', 1)",
+        )
+        .bind(sample.taxon.id())
+        .execute(&pool)
+        .await
+        .unwrap();
+        ProtectListedTaxa
+            .after_sample_save(&sample, &pool)
+            .await
+            .unwrap();
+        assert!(sensitive().await);
+    }
+}
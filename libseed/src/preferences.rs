@@ -0,0 +1,172 @@
+//! Per-user preferences, such as the default values used when adding new samples
+use crate::{
+    conservation::{normalize_region, PermitPolicy},
+    error::Result,
+    sample::Certainty,
+    stats::CollectionYear,
+    timezone::TimeZone,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteQueryResult, FromRow, Pool, Sqlite};
+
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Preferences {
+    pub userid: i64,
+    /// the source that new samples are collected from, unless another one is specified
+    #[sqlx(rename = "defaultsource")]
+    pub default_source: Option<i64>,
+    #[sqlx(rename = "defaultcertainty")]
+    pub default_certainty: Certainty,
+    /// whether new samples default to being collected in the current month and year
+    #[sqlx(rename = "defaultdatecurrent")]
+    pub default_date_current: bool,
+    /// the month (1-12) that the user's collection year starts in
+    #[sqlx(rename = "yearstartmonth")]
+    pub year_start_month: u8,
+    /// the state or other region that the user collects in. Only conservation listings in this
+    /// region require a permit. If not set, the listings of all regions do.
+    pub region: Option<String>,
+    /// whether a permit is required to add samples of taxa that are listed in the user's region
+    #[sqlx(rename = "permitpolicy")]
+    pub permit_policy: PermitPolicy,
+    /// whether reminders are sent by email once they are due
+    #[sqlx(rename = "emailreminders")]
+    pub email_reminders: bool,
+    /// whether the user is alerted by email when samples fall below their minimum quantity
+    #[sqlx(rename = "emaillowstock")]
+    pub email_low_stock: bool,
+}
+
+impl Preferences {
+    /// The preferences that are used for users that haven't saved any preferences yet
+    pub fn new(userid: i64) -> Self {
+        Self {
+            userid,
+            default_source: None,
+            default_certainty: Certainty::Certain,
+            default_date_current: false,
+            year_start_month: 1,
+            region: None,
+            permit_policy: PermitPolicy::default(),
+            email_reminders: false,
+            email_low_stock: false,
+        }
+    }
+
+    /// Load the user's preferences, or the default preferences if the user hasn't saved any
+    pub async fn load(userid: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        let prefs = sqlx::query_as("SELECT * FROM sc_user_prefs WHERE userid=?")
+            .bind(userid)
+            .fetch_optional(pool)
+            .await?;
+        Ok(prefs.unwrap_or_else(|| Self::new(userid)))
+    }
+
+    pub async fn save(&self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        self.collection_year()?;
+        sqlx::query(
+            r#"INSERT INTO sc_user_prefs (userid, defaultsource, defaultcertainty, defaultdatecurrent,
+                yearstartmonth, region, permitpolicy, emailreminders, emaillowstock)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(userid) DO UPDATE SET defaultsource=excluded.defaultsource,
+                defaultcertainty=excluded.defaultcertainty,
+                defaultdatecurrent=excluded.defaultdatecurrent,
+                yearstartmonth=excluded.yearstartmonth, region=excluded.region,
+                permitpolicy=excluded.permitpolicy, emailreminders=excluded.emailreminders,
+                emaillowstock=excluded.emaillowstock"#,
+        )
+        .bind(self.userid)
+        .bind(self.default_source)
+        .bind(&self.default_certainty)
+        .bind(self.default_date_current)
+        .bind(self.year_start_month)
+        .bind(
+            self.region
+                .as_deref()
+                .map(normalize_region)
+                .filter(|r| !r.is_empty()),
+        )
+        .bind(self.permit_policy)
+        .bind(self.email_reminders)
+        .bind(self.email_low_stock)
+        .execute(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// The collection year that reports group the user's samples by
+    pub fn collection_year(&self) -> Result<CollectionYear> {
+        CollectionYear::starting_in(self.year_start_month)
+    }
+
+    /// The default collection month and year for new samples, based on the current date in the
+    /// given time zone
+    pub fn default_date(&self, tz: &TimeZone) -> (Option<u32>, Option<u32>) {
+        if !self.default_date_current {
+            return (None, None);
+        }
+        let now = tz.now();
+        (
+            Some(u8::from(now.month()).into()),
+            now.year().try_into().ok(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users", "sources"))
+    ))]
+    async fn save_preferences(pool: Pool<Sqlite>) {
+        let mut prefs = Preferences::load(1, &pool)
+            .await
+            .expect("Failed to load preferences");
+        assert_eq!(prefs, Preferences::new(1));
+        assert_eq!(prefs.default_date(&TimeZone::utc()), (None, None));
+
+        prefs.default_source = Some(1);
+        prefs.default_certainty = Certainty::Uncertain;
+        prefs.default_date_current = true;
+        prefs.year_start_month = 7;
+        prefs.region = Some("MN".to_string());
+        prefs.permit_policy = PermitPolicy::Warn;
+        prefs.email_reminders = true;
+        prefs.email_low_stock = true;
+        prefs.save(&pool).await.expect("Failed to save preferences");
+        let loaded = Preferences::load(1, &pool)
+            .await
+            .expect("Failed to load preferences");
+        assert_eq!(loaded, prefs);
+        let (month, year) = loaded.default_date(&TimeZone::utc());
+        assert!(month.is_some_and(|m| (1..=12).contains(&m)));
+        assert!(year.is_some());
+        assert_eq!(
+            loaded.collection_year().unwrap(),
+            CollectionYear::starting_in(7).unwrap()
+        );
+        let mut invalid = loaded.clone();
+        invalid.year_start_month = 13;
+        assert!(invalid.save(&pool).await.is_err());
+
+        // other users are not affected
+        assert_eq!(
+            Preferences::load(2, &pool).await.unwrap(),
+            Preferences::new(2)
+        );
+
+        // removing the source clears the default
+        sqlx::query("DELETE FROM sc_sources WHERE srcid=1")
+            .execute(&pool)
+            .await
+            .expect("Failed to delete source");
+        let loaded = Preferences::load(1, &pool)
+            .await
+            .expect("Failed to load preferences");
+        assert_eq!(loaded.default_source, None);
+    }
+}
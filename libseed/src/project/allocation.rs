@@ -1,12 +1,15 @@
 use super::{
     note::{self, Note},
+    status::AllocationStatus,
     Project,
 };
 use crate::{
     error::Result,
-    filter::{Cmp, DynFilterPart, FilterPart, SortOrder, SortSpec},
+    filter::{Cmp, DynFilterPart, FilterPart, LimitSpec, ListQuery, SortSpecs},
     loadable::Loadable,
+    organization::push_accessible_condition,
     sample::Sample,
+    try_get_uuid,
 };
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
@@ -16,6 +19,8 @@ use sqlx::{
     Pool, QueryBuilder, Sqlite,
 };
 use std::sync::Arc;
+use time::Date;
+use uuid::Uuid;
 
 impl From<Filter> for DynFilterPart {
     fn from(value: Filter) -> Self {
@@ -26,20 +31,35 @@ impl From<Filter> for DynFilterPart {
 #[derive(Clone)]
 pub enum Filter {
     Id(i64),
+    Uuid(Uuid),
     UserId(i64),
+    /// allocations in projects that are owned by the given user or by one of the user's
+    /// organizations
+    Accessible(i64),
     ProjectId(i64),
+    ProjectUuid(Uuid),
     SampleId(i64),
     TaxonNameLike(String),
     SourceName(Cmp, String),
     Notes(Cmp, String),
+    /// allocations without a note dated on or after the given date
+    InactiveSince(Date),
+    Status(AllocationStatus),
 }
 
 impl FilterPart for Filter {
     fn add_to_query(&self, builder: &mut sqlx::QueryBuilder<sqlx::Sqlite>) {
         match self {
             Self::Id(id) => _ = builder.push(" PS.psid = ").push_bind(*id),
+            Self::Uuid(uuid) => _ = builder.push(" PS.psuuid = ").push_bind(uuid.to_string()),
             Self::UserId(id) => _ = builder.push(" S.userid = ").push_bind(*id),
+            Self::Accessible(id) => {
+                push_accessible_condition(builder, "P.userid", "P.projorgid", *id)
+            }
             Self::ProjectId(id) => _ = builder.push(" PS.projectid = ").push_bind(*id),
+            Self::ProjectUuid(uuid) => {
+                _ = builder.push(" P.projuuid = ").push_bind(uuid.to_string())
+            }
             Self::SampleId(id) => _ = builder.push(" PS.sampleid = ").push_bind(*id),
             Self::TaxonNameLike(s) => {
                 if !s.is_empty() {
@@ -64,6 +84,13 @@ impl FilterPart for Filter {
                 builder.push(" S.srcname ").push(cmp).push_bind(s);
             }
             Self::Notes(cmp, s) => _ = builder.push("notes").push(cmp).push_bind(format!("%{s}%")),
+            Self::InactiveSince(date) => {
+                _ = builder
+                    .push(" (N.notedate IS NULL OR DATE(N.notedate) < ")
+                    .push_bind(*date)
+                    .push(") ")
+            }
+            Self::Status(status) => _ = builder.push(" PS.psstatus = ").push_bind(*status),
         }
     }
 }
@@ -71,8 +98,11 @@ impl FilterPart for Filter {
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub struct Allocation {
     pub id: i64,
+    /// an identifier that is used for the allocation in URLs and the API instead of the numeric id
+    pub uuid: Uuid,
     pub sample: Sample,
     pub project: Project,
+    pub status: AllocationStatus,
     pub notes: Vec<Note>,
 }
 
@@ -101,69 +131,75 @@ impl Loadable for Allocation {
     }
 }
 
-#[derive(strum_macros::Display, Deserialize, Serialize, Clone)]
+#[derive(
+    strum_macros::Display, strum_macros::EnumString, Deserialize, Serialize, Clone, Debug, PartialEq,
+)]
 #[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
 pub enum SortField {
     Taxon,
     #[serde(rename = "id")]
+    #[strum(serialize = "id")]
     SampleId,
     #[serde(rename = "date")]
+    #[strum(serialize = "date")]
     CollectionDate,
     Activity,
     #[serde(rename = "qty")]
+    #[strum(serialize = "qty")]
     Quantity,
     #[serde(rename = "src")]
+    #[strum(serialize = "src")]
     Source,
+    Grade,
+}
+
+impl SortField {
+    fn column(&self) -> &'static str {
+        match self {
+            SortField::SampleId => "S.sampleid",
+            SortField::Taxon => "seq",
+            SortField::Activity => "N.notedate",
+            SortField::Quantity => "S.quantity",
+            SortField::Source => "S.srcname",
+            SortField::CollectionDate => "S.year * 100 + IFNULL(S.month, 0)",
+            // ungraded samples sort after the graded ones in either direction
+            SortField::Grade => "S.graderank IS NULL, S.graderank",
+        }
+    }
 }
 
 impl Allocation {
-    pub fn build_query(
-        filter: Option<DynFilterPart>,
-        sort: Option<SortSpec<SortField>>,
-    ) -> QueryBuilder<'static, Sqlite> {
-        let sort = sort.unwrap_or(SortSpec::new(SortField::Taxon, SortOrder::Ascending));
-        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
-            r#"
-            SELECT PS.psid,
+    fn list_query(filter: Option<DynFilterPart>) -> ListQuery {
+        ListQuery::new(
+            r#"PS.psid, PS.psuuid, PS.psstatus,
             S.*,
-            P.projectid, P.projname, P.projdescription,
-            N.pnoteid, N.notedate, N.notetype, N.notesummary, N.notedetails
-
-            FROM sc_project_samples PS
+            P.projectid, P.projuuid, P.projname, P.projdescription, P.projversion, P.projorgid,
+            N.pnoteid, N.pnoteuuid, N.notedate, N.notetype, N.notesummary, N.notedetails"#,
+            r#"sc_project_samples PS
             INNER JOIN vsamples S ON PS.sampleid=S.sampleid
             INNER JOIN sc_projects P on P.projectid=PS.projectid
-            LEFT JOIN ( SELECT * FROM
-            (SELECT *, ROW_NUMBER() OVER (PARTITION BY psid ORDER BY DATE(notedate) DESC, pnoteid DESC) AS rownr
-            FROM sc_project_notes ORDER BY pnoteid DESC)
-            WHERE rownr = 1) N ON N.psid = PS.psid
-            "#,
-        );
-        if let Some(f) = filter {
-            builder.push(" WHERE ");
-            f.add_to_query(&mut builder);
-        }
-        builder.push(" ORDER BY ");
-
-        match sort.field {
-            SortField::SampleId => _ = builder.push(" S.sampleid"),
-            SortField::Taxon => _ = builder.push(" seq"),
-            SortField::Activity => _ = builder.push(" N.notedate"),
-            SortField::Quantity => _ = builder.push(" S.quantity"),
-            SortField::Source => _ = builder.push(" S.srcname"),
-            SortField::CollectionDate => _ = builder.push(" CONCAT(S.year, S.month)"),
-        }
-
-        match sort.order {
-            SortOrder::Ascending => _ = builder.push(" ASC"),
-            SortOrder::Descending => _ = builder.push(" DESC"),
-        }
+            LEFT JOIN sc_project_notes N ON N.pnoteid = (SELECT pnoteid FROM sc_project_notes
+                WHERE psid = PS.psid ORDER BY DATE(notedate) DESC, pnoteid DESC LIMIT 1)"#,
+            filter,
+        )
+    }
 
+    pub fn build_query(
+        filter: Option<DynFilterPart>,
+        sort: Option<SortSpecs<SortField>>,
+    ) -> QueryBuilder<'static, Sqlite> {
+        let mut builder = Self::list_query(filter).select();
+        sort.unwrap_or(SortField::Taxon.into())
+            .push_order_by(&mut builder, SortField::column);
         builder
     }
 
-    pub async fn load_all(
+    /// Load all allocations that match `filter`, regardless of who they belong to. Allocations
+    /// are listed on behalf of a user with a [UserScope](crate::scope::UserScope).
+    pub(crate) async fn load_all(
         filter: Option<DynFilterPart>,
-        sort: Option<SortSpec<SortField>>,
+        sort: Option<SortSpecs<SortField>>,
         pool: &Pool<Sqlite>,
     ) -> Result<Vec<Self>, sqlx::Error> {
         Self::build_query(filter, sort)
@@ -172,6 +208,20 @@ impl Allocation {
             .await
     }
 
+    /// Load a single page of the allocations that match `filter`. The allocations are sorted by
+    /// id after the given sort order, so that the pages don't overlap.
+    pub async fn load_page(
+        filter: Option<DynFilterPart>,
+        sort: Option<SortSpecs<SortField>>,
+        limit: LimitSpec,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let mut builder = Self::build_query(filter, sort);
+        builder.push(", PS.psid ASC");
+        limit.push_to(&mut builder);
+        Ok(builder.build_query_as().fetch_all(pool).await?)
+    }
+
     pub async fn load_one(
         filter: Option<DynFilterPart>,
         pool: &Pool<Sqlite>,
@@ -182,6 +232,17 @@ impl Allocation {
             .await
     }
 
+    /// The number of allocations that match `filter`
+    pub async fn count(filter: Option<DynFilterPart>, pool: &Pool<Sqlite>) -> Result<i64> {
+        Self::list_query(filter).fetch_count(pool).await
+    }
+
+    /// Load the allocation with the given [uuid](Allocation::uuid)
+    pub async fn load_uuid(uuid: Uuid, pool: &Pool<Sqlite>) -> Result<Self> {
+        let mut builder = Self::build_query(Some(Filter::Uuid(uuid).into()), None);
+        Ok(builder.build_query_as().fetch_one(pool).await?)
+    }
+
     pub async fn load_notes(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
         self.notes = Note::load_all(
             Some(Arc::new(note::NoteFilter::AllocationId(self.id))),
@@ -190,6 +251,37 @@ impl Allocation {
         .await?;
         Ok(())
     }
+
+    /// Change the status of the allocation on behalf of the given user and record the change as
+    /// an event. Nothing is recorded if the status doesn't change.
+    pub async fn set_status(
+        &mut self,
+        status: AllocationStatus,
+        userid: Option<i64>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<()> {
+        if status == self.status {
+            return Ok(());
+        }
+        let mut tx = pool.begin().await?;
+        sqlx::query("UPDATE sc_project_samples SET psstatus=? WHERE psid=?")
+            .bind(status)
+            .bind(self.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query(
+            "INSERT INTO sc_allocation_events (psid, userid, evfrom, evto) VALUES (?, ?, ?, ?)",
+        )
+        .bind(self.id)
+        .bind(userid)
+        .bind(self.status)
+        .bind(status)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        self.status = status;
+        Ok(())
+    }
 }
 
 impl FromRow<'_, SqliteRow> for Allocation {
@@ -201,8 +293,10 @@ impl FromRow<'_, SqliteRow> for Allocation {
         }
         Ok(Self {
             id: row.try_get("psid")?,
+            uuid: try_get_uuid(row, "psuuid")?,
             sample: Sample::from_row(row)?,
             project: Project::from_row(row)?,
+            status: row.try_get("psstatus")?,
             notes,
         })
     }
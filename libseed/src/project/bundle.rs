@@ -0,0 +1,181 @@
+//! Export bundles of a project. A bundle is a zip file with all of the data of a project that is
+//! generated in the background, since it can take a while for large projects. The finished file is
+//! stored in the database so that it can be downloaded once it is ready, and it is removed again
+//! after a while.
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Sqlite};
+use strum_macros::Display;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, sqlx::Type, PartialEq, Display)]
+#[repr(i32)]
+pub enum BundleStatus {
+    Pending = 0,
+    Ready = 1,
+    Failed = 2,
+}
+
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Bundle {
+    #[sqlx(rename = "bundleid")]
+    pub id: i64,
+    #[sqlx(rename = "bundleuuid", try_from = "String")]
+    pub uuid: Uuid,
+    pub projectid: i64,
+    /// the user that requested the bundle, who is the only one that can download it
+    pub userid: i64,
+    #[sqlx(rename = "bundlestatus")]
+    pub status: BundleStatus,
+    /// the contents of the zip file, once the bundle is ready
+    #[sqlx(rename = "bundledata")]
+    #[serde(skip)]
+    pub data: Option<Vec<u8>>,
+    #[sqlx(rename = "bundleerror")]
+    pub error: Option<String>,
+    #[sqlx(rename = "bundlecreated")]
+    pub created: Option<OffsetDateTime>,
+}
+
+impl Bundle {
+    pub fn new(projectid: i64, userid: i64) -> Self {
+        Self {
+            id: -1,
+            uuid: Uuid::new_v4(),
+            projectid,
+            userid,
+            status: BundleStatus::Pending,
+            data: None,
+            error: None,
+            created: None,
+        }
+    }
+
+    pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        let res = sqlx::query(
+            "INSERT INTO sc_project_bundles (bundleuuid, projectid, userid) VALUES (?, ?, ?)",
+        )
+        .bind(self.uuid.to_string())
+        .bind(self.projectid)
+        .bind(self.userid)
+        .execute(pool)
+        .await?;
+        *self = Self::load(res.last_insert_rowid(), pool).await?;
+        Ok(())
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        sqlx::query_as("SELECT * FROM sc_project_bundles WHERE bundleid=?")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Load the bundle with the given [uuid](Bundle::uuid)
+    pub async fn load_uuid(uuid: Uuid, pool: &Pool<Sqlite>) -> Result<Self> {
+        sqlx::query_as("SELECT * FROM sc_project_bundles WHERE bundleuuid=?")
+            .bind(uuid.to_string())
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Store the generated zip file and mark the bundle as ready for download
+    pub async fn complete(&mut self, data: Vec<u8>, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query(
+            "UPDATE sc_project_bundles SET bundlestatus=?, bundledata=?, bundleerror=NULL WHERE bundleid=?",
+        )
+        .bind(BundleStatus::Ready)
+        .bind(&data)
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+        self.status = BundleStatus::Ready;
+        self.data = Some(data);
+        self.error = None;
+        Ok(())
+    }
+
+    /// Record that the bundle could not be generated
+    pub async fn fail(&mut self, error: &str, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query("UPDATE sc_project_bundles SET bundlestatus=?, bundleerror=? WHERE bundleid=?")
+            .bind(BundleStatus::Failed)
+            .bind(error)
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        self.status = BundleStatus::Failed;
+        self.error = Some(error.to_string());
+        Ok(())
+    }
+
+    /// Remove all bundles that were requested more than `max_age` ago. Returns the number of
+    /// bundles that were removed.
+    pub async fn delete_expired(max_age: Duration, pool: &Pool<Sqlite>) -> Result<u64> {
+        let res =
+            sqlx::query("DELETE FROM sc_project_bundles WHERE bundlecreated < datetime('now', ?)")
+                .bind(format!("-{} seconds", max_age.whole_seconds()))
+                .execute(pool)
+                .await?;
+        Ok(res.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn bundles(pool: Pool<Sqlite>) {
+        let mut bundle = Bundle::new(1, 1);
+        bundle.insert(&pool).await.expect("Failed to insert bundle");
+        assert_eq!(bundle.status, BundleStatus::Pending);
+        assert!(bundle.created.is_some());
+        assert_eq!(Bundle::load_uuid(bundle.uuid, &pool).await.unwrap(), bundle);
+
+        bundle
+            .complete(vec![1, 2, 3], &pool)
+            .await
+            .expect("Failed to complete bundle");
+        let loaded = Bundle::load_uuid(bundle.uuid, &pool).await.unwrap();
+        assert_eq!(loaded.status, BundleStatus::Ready);
+        assert_eq!(loaded.data, Some(vec![1, 2, 3]));
+
+        let mut failed = Bundle::new(1, 1);
+        failed.insert(&pool).await.unwrap();
+        failed.fail("oops", &pool).await.unwrap();
+        let loaded = Bundle::load(failed.id, &pool).await.unwrap();
+        assert_eq!(loaded.status, BundleStatus::Failed);
+        assert_eq!(loaded.error.as_deref(), Some("oops"));
+
+        // nothing has expired yet
+        assert_eq!(
+            Bundle::delete_expired(Duration::hours(1), &pool)
+                .await
+                .unwrap(),
+            0
+        );
+        sqlx::query("UPDATE sc_project_bundles SET bundlecreated=datetime('now', '-2 hours') WHERE bundleid=?")
+            .bind(bundle.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            Bundle::delete_expired(Duration::hours(1), &pool)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(Bundle::load(bundle.id, &pool).await.is_err());
+        assert!(Bundle::load(failed.id, &pool).await.is_ok());
+    }
+}
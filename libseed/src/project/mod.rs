@@ -2,34 +2,52 @@
 //! specific purpose. It could be something like a group of seeds that you intend to plant for a
 //! particular restoration project, etc.
 use crate::{
+    bulk::BulkResult,
     error::{Error, Result},
-    filter::{Cmp, CompoundFilter, DynFilterPart, FilterPart, Op, SortSpec},
+    filter::{Cmp, CompoundFilter, DynFilterPart, FilterPart, LimitSpec, ListQuery, Op, SortSpecs},
     loadable::{ExternalRef, Loadable},
+    organization::{push_accessible_condition, Owned},
     sample::Sample,
 };
 pub use allocation::Allocation;
 use async_trait::async_trait;
 pub use note::{Note, NoteFilter, NoteType};
 use serde::{Deserialize, Serialize};
-use sqlx::{sqlite::SqliteQueryResult, Pool, QueryBuilder, Row, Sqlite};
+use sqlx::{sqlite::SqliteQueryResult, Pool, QueryBuilder, Sqlite};
+pub use status::AllocationStatus;
 use std::sync::Arc;
 use tracing::debug;
+use uuid::Uuid;
 
 pub mod allocation;
+pub mod bundle;
 pub mod note;
+pub mod planting;
+pub mod propagation;
+pub mod status;
+pub mod template;
+pub mod timeline;
 
 #[derive(sqlx::FromRow, Debug, Deserialize, Serialize, PartialEq)]
 pub struct Project {
     #[sqlx(rename = "projectid")]
     pub id: i64,
+    /// an identifier that is used for the project in URLs and the API instead of the numeric id
+    #[sqlx(rename = "projuuid", try_from = "String")]
+    pub uuid: Uuid,
     #[sqlx(rename = "projname")]
     pub name: String,
     #[sqlx(rename = "projdescription")]
     pub description: Option<String>,
     #[sqlx(skip)]
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub allocations: Vec<Allocation>,
     pub userid: i64,
+    #[sqlx(rename = "projversion")]
+    pub version: i64,
+    /// the organization that shares ownership of the project, if any
+    #[sqlx(rename = "projorgid", default)]
+    pub orgid: Option<i64>,
 }
 
 #[async_trait]
@@ -70,7 +88,12 @@ impl From<Filter> for DynFilterPart {
 #[derive(Clone)]
 pub enum Filter {
     Id(i64),
+    Uuid(Uuid),
     User(i64),
+    /// projects that are owned by the given user or by one of the user's organizations
+    Accessible(i64),
+    /// projects that are owned by the given organization
+    OrgId(i64),
     Name(Cmp, String),
     Description(Cmp, String),
 }
@@ -79,7 +102,12 @@ impl FilterPart for Filter {
     fn add_to_query(&self, builder: &mut sqlx::QueryBuilder<sqlx::Sqlite>) {
         match self {
             Self::Id(id) => _ = builder.push(" P.projectid = ").push_bind(*id),
+            Self::Uuid(uuid) => _ = builder.push(" P.projuuid = ").push_bind(uuid.to_string()),
             Self::User(id) => _ = builder.push(" P.userid = ").push_bind(*id),
+            Self::Accessible(id) => {
+                push_accessible_condition(builder, "P.userid", "P.projorgid", *id)
+            }
+            Self::OrgId(id) => _ = builder.push(" P.projorgid = ").push_bind(*id),
             Self::Name(cmp, frag) => {
                 let s = match cmp {
                     Cmp::Like => format!("%{frag}%"),
@@ -99,28 +127,24 @@ impl FilterPart for Filter {
 }
 
 impl Project {
-    fn build_query(filter: Option<DynFilterPart>) -> QueryBuilder<'static, Sqlite> {
-        let mut builder = QueryBuilder::new(
-            r#"SELECT P.projectid, P.projname, P.projdescription, P.userid, U.username
-            FROM sc_projects P INNER JOIN sc_users U ON U.userid=P.userid"#,
-        );
-        if let Some(f) = filter {
-            builder.push(" WHERE ");
-            f.add_to_query(&mut builder);
-        }
-        builder
+    fn list_query(filter: Option<DynFilterPart>) -> ListQuery {
+        ListQuery::new(
+            "P.projectid, P.projuuid, P.projname, P.projdescription, P.userid, P.projversion, P.projorgid, U.username",
+            "sc_projects P INNER JOIN sc_users U ON U.userid=P.userid",
+            filter,
+        )
     }
 
-    fn build_count(filter: Option<DynFilterPart>) -> QueryBuilder<'static, Sqlite> {
-        let mut builder = QueryBuilder::new("SELECT COUNT(*) as nprojects FROM sc_projects P");
-        if let Some(f) = filter {
-            builder.push(" WHERE ");
-            f.add_to_query(&mut builder);
-        }
-        builder
+    fn build_query(filter: Option<DynFilterPart>) -> QueryBuilder<'static, Sqlite> {
+        Self::list_query(filter).select()
     }
 
-    pub async fn load_all(filter: Option<DynFilterPart>, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+    /// Load all projects that match `filter`, regardless of who they belong to. Projects are
+    /// listed on behalf of a user with a [UserScope](crate::scope::UserScope).
+    pub(crate) async fn load_all(
+        filter: Option<DynFilterPart>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Self>> {
         Self::build_query(filter)
             .build_query_as()
             .fetch_all(pool)
@@ -128,19 +152,36 @@ impl Project {
             .map_err(|e| e.into())
     }
 
-    pub async fn count(filter: Option<DynFilterPart>, pool: &Pool<Sqlite>) -> Result<i64> {
-        Self::build_count(filter)
-            .build()
+    /// Load a single page of the projects that match `filter`, sorted by name
+    pub async fn load_page(
+        filter: Option<DynFilterPart>,
+        limit: LimitSpec,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let mut builder = Self::build_query(filter);
+        builder.push(" ORDER BY P.projname, P.projectid");
+        limit.push_to(&mut builder);
+        Ok(builder.build_query_as().fetch_all(pool).await?)
+    }
+
+    /// Load the project with the given [uuid](Project::uuid)
+    pub async fn load_uuid(uuid: Uuid, pool: &Pool<Sqlite>) -> Result<Self> {
+        Self::build_query(Some(Filter::Uuid(uuid).into()))
+            .build_query_as()
             .fetch_one(pool)
-            .await?
-            .try_get("nprojects")
+            .await
             .map_err(|e| e.into())
     }
 
+    /// The number of projects that match `filter`
+    pub async fn count(filter: Option<DynFilterPart>, pool: &Pool<Sqlite>) -> Result<i64> {
+        Self::list_query(filter).fetch_count(pool).await
+    }
+
     pub async fn load_samples(
         &mut self,
         filter: Option<DynFilterPart>,
-        sort: Option<SortSpec<allocation::SortField>>,
+        sort: Option<SortSpecs<allocation::SortField>>,
         pool: &Pool<Sqlite>,
     ) -> Result<()> {
         let mut fbuilder =
@@ -158,30 +199,64 @@ impl Project {
         sample: ExternalRef<Sample>,
         pool: &Pool<Sqlite>,
     ) -> Result<SqliteQueryResult> {
-        sqlx::query("INSERT INTO sc_project_samples (projectid, sampleid) VALUES (?, ?)")
+        sqlx::query("INSERT INTO sc_project_samples (projectid, sampleid, psuuid) VALUES (?, ?, ?)")
             .bind(self.id)
             .bind(sample.id())
+            .bind(Uuid::new_v4().to_string())
             .execute(pool)
             .await
             .map_err(|e| e.into())
     }
 
+    /// Allocate each of the given samples to the project. A sample that can't be allocated, e.g.
+    /// because it is already part of the project, doesn't prevent the others from being
+    /// allocated.
+    pub async fn allocate_samples(
+        &mut self,
+        sampleids: &[i64],
+        pool: &Pool<Sqlite>,
+    ) -> BulkResult<i64> {
+        let mut result = BulkResult::new();
+        for id in sampleids {
+            match self.allocate_sample(ExternalRef::Stub(*id), pool).await {
+                Ok(_) => result.succeed(*id),
+                Err(Error::DatabaseUnspecified(sqlx::Error::Database(e)))
+                    if e.is_unique_violation() =>
+                {
+                    result.fail(*id, "The sample is already part of this project")
+                }
+                Err(Error::DatabaseUnspecified(sqlx::Error::Database(e)))
+                    if e.is_foreign_key_violation() =>
+                {
+                    result.fail(*id, "The sample does not exist")
+                }
+                Err(e) => result.fail(*id, e.to_string()),
+            }
+        }
+        result
+    }
+
     pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
         debug!(?self, "Inserting project into database");
-        sqlx::query("INSERT INTO sc_projects (projname, projdescription, userid) VALUES (?, ?, ?)")
-            .bind(self.name.clone())
-            .bind(self.description.clone())
-            .bind(self.userid)
+        sqlx::query(
+            "INSERT INTO sc_projects (projname, projdescription, userid, projorgid, projuuid) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(self.name.clone())
+        .bind(self.description.clone())
+        .bind(self.userid)
+        .bind(self.orgid)
+        .bind(self.uuid.to_string())
             .execute(pool)
             .await
-            .map(|r| {
+            .inspect(|r| {
                 self.id = r.last_insert_rowid();
-                r
             })
             .map_err(|e| e.into())
     }
 
-    pub async fn update(&self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+    /// Save the changes to this project to the database. If the project has been modified in the
+    /// database since it was loaded, this fails with [Error::DatabaseVersionConflict].
+    pub async fn update(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
         if self.name.is_empty() {
             return Err(Error::InvalidStateMissingAttribute("name".to_string()));
         }
@@ -189,29 +264,49 @@ impl Project {
             return Err(Error::InvalidStateMissingAttribute("id".to_string()));
         }
         debug!(?self, "Updating project in database");
-        sqlx::query(
-            "UPDATE sc_projects SET projname=?, projdescription=?, userid=? WHERE projectid=?",
+        let res = sqlx::query(
+            r#"UPDATE sc_projects SET projname=?, projdescription=?, userid=?, projorgid=?,
+            projversion=projversion+1 WHERE projectid=? AND projversion=?"#,
         )
         .bind(self.name.clone())
         .bind(self.description.as_ref().cloned())
         .bind(self.userid)
+        .bind(self.orgid)
         .bind(self.id)
+        .bind(self.version)
         .execute(pool)
-        .await
-        .map_err(|e| e.into())
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::DatabaseVersionConflict(self.version));
+        }
+        self.version += 1;
+        Ok(res)
     }
 
     pub fn new(name: String, description: Option<String>, userid: i64) -> Self {
         Self {
             id: -1,
+            uuid: Uuid::new_v4(),
             name,
             description,
             userid,
             allocations: Default::default(),
+            version: 1,
+            orgid: None,
         }
     }
 }
 
+impl Owned for Project {
+    fn owner(&self) -> i64 {
+        self.userid
+    }
+
+    fn organization(&self) -> Option<i64> {
+        self.orgid
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::loadable::Loadable;
@@ -227,9 +322,9 @@ mod tests {
     async fn test_insert_projects(pool: Pool<Sqlite>) {
         async fn check(pool: &Pool<Sqlite>, name: String, desc: Option<String>, userid: i64) {
             let mut c = Project::new(name, desc, userid);
-            let res = c.insert(&pool).await.expect("failed to insert");
+            let res = c.insert(pool).await.expect("failed to insert");
             assert_eq!(res.rows_affected(), 1);
-            let cload = Project::load(res.last_insert_rowid(), &pool)
+            let cload = Project::load(res.last_insert_rowid(), pool)
                 .await
                 .expect("Failed to load project");
             assert_eq!(c, cload);
@@ -245,4 +340,32 @@ mod tests {
 
         check(&pool, "test name".to_string(), None, 1).await;
     }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn test_allocate_samples(pool: Pool<Sqlite>) {
+        // sample 1 is already part of project 1 and sample 99 doesn't exist, but that doesn't
+        // keep sample 4 from being allocated
+        let mut project = Project::load(1, &pool).await.unwrap();
+        let result = project.allocate_samples(&[1, 99, 4], &pool).await;
+        assert_eq!(result.succeeded().collect::<Vec<_>>(), vec![&4]);
+        assert_eq!(
+            result.failed().collect::<Vec<_>>(),
+            vec![
+                (&1, "The sample is already part of this project"),
+                (&99, "The sample does not exist"),
+            ]
+        );
+        let count: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM sc_project_samples WHERE projectid=1")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(count, 4);
+    }
 }
@@ -5,6 +5,7 @@ use std::sync::Arc;
 use strum_macros::{EnumIter, EnumString, FromRepr};
 use time::Date;
 use tracing::debug;
+use uuid::Uuid;
 
 use crate::{
     error::{Error, Result},
@@ -36,6 +37,7 @@ pub enum NoteType {
 #[derive(Clone)]
 pub enum NoteFilter {
     Id(i64),
+    Uuid(Uuid),
     AllocationId(i64),
 }
 
@@ -43,6 +45,9 @@ pub enum NoteFilter {
 pub struct Note {
     #[sqlx(rename = "pnoteid")]
     pub id: i64,
+    /// an identifier that is used for the note in URLs and the API instead of the numeric id
+    #[sqlx(rename = "pnoteuuid", try_from = "String")]
+    pub uuid: Uuid,
     pub psid: i64,
     #[sqlx(rename = "notedate")]
     pub date: Date,
@@ -86,6 +91,7 @@ impl FilterPart for NoteFilter {
     fn add_to_query(&self, builder: &mut sqlx::QueryBuilder<sqlx::Sqlite>) {
         match self {
             Self::Id(i) => _ = builder.push(" pnoteid=").push_bind(*i),
+            Self::Uuid(uuid) => _ = builder.push(" pnoteuuid=").push_bind(uuid.to_string()),
             Self::AllocationId(i) => _ = builder.push(" psid=").push_bind(*i),
         }
     }
@@ -101,6 +107,7 @@ impl Note {
     ) -> Self {
         Self {
             id: -1,
+            uuid: Uuid::new_v4(),
             psid,
             date,
             kind,
@@ -110,7 +117,7 @@ impl Note {
     }
     fn build_query(filter: Option<DynFilterPart>) -> QueryBuilder<'static, Sqlite> {
         let mut builder = QueryBuilder::new(
-            r#"SELECT pnoteid, psid, notedate, notetype, notesummary, notedetails FROM sc_project_notes"#,
+            r#"SELECT pnoteid, pnoteuuid, psid, notedate, notetype, notesummary, notedetails FROM sc_project_notes"#,
         );
         if let Some(f) = filter {
             builder.push(" WHERE ");
@@ -131,6 +138,15 @@ impl Note {
             .await
     }
 
+    /// Load the note with the given [uuid](Note::uuid)
+    pub async fn load_uuid(uuid: Uuid, pool: &Pool<Sqlite>) -> Result<Self> {
+        Self::build_query(Some(Arc::new(NoteFilter::Uuid(uuid))))
+            .build_query_as()
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
     pub async fn insert(&self, pool: &Pool<Sqlite>) -> Result<Note> {
         if self.summary.is_empty() {
             return Err(Error::InvalidStateMissingAttribute("summary".to_string()));
@@ -138,14 +154,15 @@ impl Note {
         debug!(?self, "Inserting note into database");
         sqlx::query_as(
             r#"INSERT INTO sc_project_notes
-            (psid, notedate, notetype, notesummary, notedetails)
-            VALUES (?, ?, ?, ?, ?) RETURNING *"#,
+            (psid, notedate, notetype, notesummary, notedetails, pnoteuuid)
+            VALUES (?, ?, ?, ?, ?, ?) RETURNING *"#,
         )
         .bind(self.psid)
         .bind(self.date)
         .bind(self.kind as i64)
         .bind(&self.summary)
         .bind(&self.details)
+        .bind(self.uuid.to_string())
         .fetch_one(pool)
         .await
         .map_err(|e| e.into())
@@ -164,8 +181,12 @@ impl Note {
         .bind(&self.summary)
         .bind(&self.details)
         .bind(self.id)
-        .fetch_one(pool)
-        .await
+        // fetch all rows so that the statement is stepped to completion and the update is
+        // actually applied before the statement is finalized
+        .fetch_all(pool)
+        .await?
+        .pop()
+        .ok_or(sqlx::Error::RowNotFound)
     }
 }
 
@@ -0,0 +1,477 @@
+//! The outcomes of growing out the samples of a project. Once a sample has been sown, a planting
+//! records how many plugs were produced and how many of them were planted out where. Survival
+//! checks some months later count how many of the plants are still alive, which gives the
+//! establishment rate of the planting.
+use crate::{
+    error::{Error, Result},
+    reminder::{Reminder, ReminderTarget},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, FromRow, Pool, Row, Sqlite};
+use time::{Date, Month};
+
+/// The number of months after planting that survival is usually checked. A reminder is scheduled
+/// for each of them when a planting is recorded.
+pub const SURVIVAL_CHECKS: [u8; 2] = [1, 12];
+
+/// A count of the plants of a planting that were still alive some months after it was planted
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct SurvivalCheck {
+    pub id: i64,
+    pub plantingid: i64,
+    pub date: Date,
+    /// the number of months after planting that the check was made for, e.g. 1 or 12
+    pub months: i64,
+    pub alive: i64,
+}
+
+impl FromRow<'_, SqliteRow> for SurvivalCheck {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            id: row.try_get("checkid")?,
+            plantingid: row.try_get("plantingid")?,
+            date: row.try_get("checkdate")?,
+            months: row.try_get("checkmonths")?,
+            alive: row.try_get("checkalive")?,
+        })
+    }
+}
+
+impl SurvivalCheck {
+    /// The fraction of the planted plants that were alive at the check
+    pub fn rate(&self, planting: &Planting) -> Option<f64> {
+        (planting.planted > 0).then(|| self.alive as f64 / planting.planted as f64)
+    }
+
+    pub async fn delete(&self, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query("DELETE FROM sc_survival_checks WHERE checkid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+/// The outcome of growing out a sample that was allocated to a project
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Planting {
+    pub id: i64,
+    /// the id of the sample's allocation to the project
+    pub psid: i64,
+    /// the date the plants were planted out
+    pub date: Date,
+    /// the number of plugs that were produced from the sample, if known
+    pub plugs: Option<i64>,
+    /// the number of plants that were planted out
+    pub planted: i64,
+    /// where the plants were planted, e.g. the name of a site or a bed
+    pub location: Option<String>,
+    pub notes: Option<String>,
+    /// the survival checks of the planting, ordered by date
+    pub checks: Vec<SurvivalCheck>,
+}
+
+impl FromRow<'_, SqliteRow> for Planting {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            id: row.try_get("plantingid")?,
+            psid: row.try_get("psid")?,
+            date: row.try_get("plantingdate")?,
+            plugs: row.try_get("plantingplugs")?,
+            planted: row.try_get("plantingcount")?,
+            location: row.try_get("plantinglocation")?,
+            notes: row.try_get("plantingnotes")?,
+            checks: Vec::new(),
+        })
+    }
+}
+
+/// The date that is the given number of months after `date`. The day is moved back to the end of
+/// the month if the month is shorter.
+pub fn add_months(date: Date, months: u8) -> Result<Date> {
+    let index = date.year() * 12 + i32::from(u8::from(date.month())) - 1 + i32::from(months);
+    let year = index.div_euclid(12);
+    let month = Month::try_from((index.rem_euclid(12) + 1) as u8)
+        .map_err(|e| Error::InvalidValue(e.to_string()))?;
+    let day = date.day().min(month.length(year));
+    Date::from_calendar_date(year, month, day).map_err(|e| Error::InvalidValue(e.to_string()))
+}
+
+impl Planting {
+    pub fn new(
+        psid: i64,
+        date: Date,
+        plugs: Option<i64>,
+        planted: i64,
+        location: Option<String>,
+        notes: Option<String>,
+    ) -> Self {
+        Self {
+            id: -1,
+            psid,
+            date,
+            plugs,
+            planted,
+            location,
+            notes,
+            checks: Vec::new(),
+        }
+    }
+
+    async fn load_checks(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        self.checks = sqlx::query_as(
+            "SELECT * FROM sc_survival_checks WHERE plantingid=? ORDER BY checkdate, checkid",
+        )
+        .bind(self.id)
+        .fetch_all(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        let mut planting: Self = sqlx::query_as("SELECT * FROM sc_plantings WHERE plantingid=?")
+            .bind(id)
+            .fetch_one(pool)
+            .await?;
+        planting.load_checks(pool).await?;
+        Ok(planting)
+    }
+
+    /// Load the plantings of the given allocation along with their survival checks, ordered by
+    /// date
+    pub async fn load_allocation(psid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        let mut plantings: Vec<Self> = sqlx::query_as(
+            "SELECT * FROM sc_plantings WHERE psid=? ORDER BY plantingdate, plantingid",
+        )
+        .bind(psid)
+        .fetch_all(pool)
+        .await?;
+        for planting in plantings.iter_mut() {
+            planting.load_checks(pool).await?;
+        }
+        Ok(plantings)
+    }
+
+    fn validate(&mut self) -> Result<()> {
+        if self.planted < 0 || self.plugs.is_some_and(|p| p < 0) {
+            return Err(Error::InvalidValue(
+                "The number of plants can't be negative".to_string(),
+            ));
+        }
+        if self.plugs.is_some_and(|p| p < self.planted) {
+            return Err(Error::InvalidValue(format!(
+                "{} plants can't be planted from {} plugs",
+                self.planted,
+                self.plugs.unwrap_or_default()
+            )));
+        }
+        for text in [&mut self.location, &mut self.notes] {
+            *text = text
+                .as_ref()
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty());
+        }
+        Ok(())
+    }
+
+    pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        if self.id != -1 {
+            return Err(Error::InvalidOperationObjectAlreadyExists(self.id));
+        }
+        self.validate()?;
+        let res = sqlx::query(
+            r#"INSERT INTO sc_plantings
+            (psid, plantingdate, plantingplugs, plantingcount, plantinglocation, plantingnotes)
+            VALUES (?, ?, ?, ?, ?, ?)"#,
+        )
+        .bind(self.psid)
+        .bind(self.date)
+        .bind(self.plugs)
+        .bind(self.planted)
+        .bind(&self.location)
+        .bind(&self.notes)
+        .execute(pool)
+        .await?;
+        self.id = res.last_insert_rowid();
+        Ok(())
+    }
+
+    pub async fn update(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        if self.id < 0 {
+            return Err(Error::InvalidOperationObjectNotFound);
+        }
+        self.validate()?;
+        sqlx::query(
+            r#"UPDATE sc_plantings SET plantingdate=?, plantingplugs=?, plantingcount=?,
+            plantinglocation=?, plantingnotes=? WHERE plantingid=?"#,
+        )
+        .bind(self.date)
+        .bind(self.plugs)
+        .bind(self.planted)
+        .bind(&self.location)
+        .bind(&self.notes)
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Remove the planting along with its survival checks
+    pub async fn delete(&self, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query("DELETE FROM sc_plantings WHERE plantingid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Record how many of the plants were alive `months` after the planting
+    pub async fn add_check(
+        &mut self,
+        date: Date,
+        months: i64,
+        alive: i64,
+        pool: &Pool<Sqlite>,
+    ) -> Result<SurvivalCheck> {
+        if months < 0 {
+            return Err(Error::InvalidValue(
+                "A survival check can't be made before the planting".to_string(),
+            ));
+        }
+        if alive < 0 || alive > self.planted {
+            return Err(Error::InvalidValue(format!(
+                "Between 0 and {} plants can be alive",
+                self.planted
+            )));
+        }
+        let check: SurvivalCheck = sqlx::query_as(
+            r#"INSERT INTO sc_survival_checks (plantingid, checkdate, checkmonths, checkalive)
+            VALUES (?, ?, ?, ?) RETURNING *"#,
+        )
+        .bind(self.id)
+        .bind(date)
+        .bind(months)
+        .bind(alive)
+        .fetch_all(pool)
+        .await?
+        .pop()
+        .ok_or(sqlx::Error::RowNotFound)?;
+        self.load_checks(pool).await?;
+        Ok(check)
+    }
+
+    /// The most recent check that was made for the given number of months after the planting
+    pub fn check(&self, months: i64) -> Option<&SurvivalCheck> {
+        self.checks.iter().rev().find(|c| c.months == months)
+    }
+
+    /// Schedule a reminder about the project for each of the usual [survival
+    /// checks](SURVIVAL_CHECKS) of the planting. `label` describes the sample in the titles of the
+    /// reminders.
+    pub async fn schedule_checks(
+        &self,
+        userid: i64,
+        projectid: i64,
+        label: &str,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Reminder>> {
+        let mut reminders = Vec::new();
+        for months in SURVIVAL_CHECKS {
+            let mut reminder = Reminder::about(
+                userid,
+                ReminderTarget::Project(projectid),
+                add_months(self.date, months)?,
+                format!("{months}-month survival check of {label}"),
+            );
+            reminder.insert(pool).await?;
+            reminders.push(reminder);
+        }
+        Ok(reminders)
+    }
+}
+
+/// How many of the plants of a project survived until a survival check
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, FromRow)]
+pub struct SurvivalRate {
+    /// the number of months after planting
+    pub months: i64,
+    /// the number of plantings that were checked
+    pub plantings: i64,
+    /// the number of plants in the checked plantings
+    pub planted: i64,
+    pub alive: i64,
+}
+
+impl SurvivalRate {
+    pub fn rate(&self) -> Option<f64> {
+        (self.planted > 0).then(|| self.alive as f64 / self.planted as f64)
+    }
+}
+
+/// The outcomes of all plantings of the samples in a project
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Establishment {
+    pub plantings: i64,
+    pub plugs: i64,
+    pub planted: i64,
+    /// the survival at each of the [usual checks](SURVIVAL_CHECKS) that were made for any of the
+    /// plantings. Only the most recent check of each planting is counted.
+    pub survival: Vec<SurvivalRate>,
+}
+
+impl Establishment {
+    pub async fn load_project(projectid: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        let (plantings, plugs, planted): (i64, i64, i64) = sqlx::query_as(
+            r#"SELECT COUNT(P.plantingid), IFNULL(SUM(P.plantingplugs), 0),
+            IFNULL(SUM(P.plantingcount), 0)
+            FROM sc_plantings P INNER JOIN sc_project_samples PS ON PS.psid=P.psid
+            WHERE PS.projectid=?"#,
+        )
+        .bind(projectid)
+        .fetch_one(pool)
+        .await?;
+        let mut survival = Vec::new();
+        for months in SURVIVAL_CHECKS {
+            let rate: SurvivalRate = sqlx::query_as(
+                r#"SELECT ? AS months, COUNT(P.plantingid) AS plantings,
+                IFNULL(SUM(P.plantingcount), 0) AS planted, IFNULL(SUM(C.checkalive), 0) AS alive
+                FROM sc_plantings P
+                INNER JOIN sc_project_samples PS ON PS.psid=P.psid
+                INNER JOIN sc_survival_checks C ON C.checkid=(
+                    SELECT checkid FROM sc_survival_checks
+                    WHERE plantingid=P.plantingid AND checkmonths=?
+                    ORDER BY checkdate DESC, checkid DESC LIMIT 1)
+                WHERE PS.projectid=?"#,
+            )
+            .bind(i64::from(months))
+            .bind(i64::from(months))
+            .bind(projectid)
+            .fetch_one(pool)
+            .await?;
+            if rate.plantings > 0 {
+                survival.push(rate);
+            }
+        }
+        Ok(Self {
+            plantings,
+            plugs,
+            planted,
+            survival,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+    use time::macros::date;
+
+    #[test]
+    fn test_add_months() {
+        assert_eq!(
+            add_months(date!(2024 - 05 - 15), 1).unwrap(),
+            date!(2024 - 06 - 15)
+        );
+        assert_eq!(
+            add_months(date!(2024 - 01 - 31), 1).unwrap(),
+            date!(2024 - 02 - 29)
+        );
+        assert_eq!(
+            add_months(date!(2024 - 12 - 10), 12).unwrap(),
+            date!(2025 - 12 - 10)
+        );
+        assert_eq!(
+            add_months(date!(2024 - 11 - 30), 3).unwrap(),
+            date!(2025 - 02 - 28)
+        );
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn test_plantings(pool: Pool<Sqlite>) {
+        let mut invalid = Planting::new(1, date!(2024 - 05 - 01), Some(10), 20, None, None);
+        assert!(matches!(
+            invalid.insert(&pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        let mut first = Planting::new(
+            1,
+            date!(2024 - 05 - 01),
+            Some(40),
+            30,
+            Some(" North meadow ".to_string()),
+            Some(String::new()),
+        );
+        first.insert(&pool).await.unwrap();
+        assert_eq!(first.location.as_deref(), Some("North meadow"));
+        assert_eq!(first.notes, None);
+        let mut second = Planting::new(2, date!(2024 - 06 - 01), None, 10, None, None);
+        second.insert(&pool).await.unwrap();
+        let mut other = Planting::new(4, date!(2024 - 06 - 01), None, 10, None, None);
+        other.insert(&pool).await.unwrap();
+
+        assert!(first
+            .add_check(date!(2024 - 06 - 01), 1, 31, &pool)
+            .await
+            .is_err());
+        first
+            .add_check(date!(2024 - 06 - 01), 1, 27, &pool)
+            .await
+            .unwrap();
+        // a later check for the same month replaces the earlier one in the statistics
+        first
+            .add_check(date!(2024 - 06 - 03), 1, 24, &pool)
+            .await
+            .unwrap();
+        second
+            .add_check(date!(2024 - 07 - 01), 1, 6, &pool)
+            .await
+            .unwrap();
+        assert_eq!(first.checks.len(), 2);
+        assert_eq!(first.check(1).unwrap().alive, 24);
+        assert_eq!(first.check(1).unwrap().rate(&first), Some(0.8));
+        assert_eq!(first.check(12), None);
+        assert_eq!(Planting::load(first.id, &pool).await.unwrap(), first);
+        let plantings = Planting::load_allocation(1, &pool).await.unwrap();
+        assert_eq!(plantings, vec![first.clone()]);
+
+        let stats = Establishment::load_project(1, &pool).await.unwrap();
+        assert_eq!(stats.plantings, 2);
+        assert_eq!(stats.plugs, 40);
+        assert_eq!(stats.planted, 40);
+        assert_eq!(stats.survival.len(), 1);
+        assert_eq!(stats.survival[0].months, 1);
+        assert_eq!(stats.survival[0].plantings, 2);
+        assert_eq!(stats.survival[0].alive, 30);
+        assert_eq!(stats.survival[0].rate(), Some(0.75));
+        let empty = Establishment::load_project(2, &pool).await.unwrap();
+        assert_eq!(empty.plantings, 0);
+        assert!(empty.survival.is_empty());
+
+        let reminders = first.schedule_checks(1, 1, "S0001", &pool).await.unwrap();
+        assert_eq!(
+            reminders.iter().map(|r| r.due).collect::<Vec<_>>(),
+            [date!(2024 - 06 - 01), date!(2025 - 05 - 01)]
+        );
+        assert!(reminders[1].title.starts_with("12-month survival check"));
+        assert_eq!(reminders[0].target, Some(ReminderTarget::Project(1)));
+
+        first.checks[0].delete(&pool).await.unwrap();
+        first.delete(&pool).await.unwrap();
+        assert!(Planting::load(first.id, &pool).await.is_err());
+        assert!(
+            sqlx::query("SELECT * FROM sc_survival_checks WHERE plantingid=?")
+                .bind(first.id)
+                .fetch_optional(&pool)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
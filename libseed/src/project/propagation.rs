@@ -0,0 +1,218 @@
+//! A propagation plan rolls up the samples allocated to a project by species so that the
+//! germination treatments that need to be started can be worked through as a to-do list.
+use crate::{
+    error::Result,
+    filter::{SortOrder, SortSpec},
+    loadable::Loadable,
+    project::{allocation, Allocation},
+    quality,
+    taxonomy::{Germination, Rank, Taxon},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// The samples of a single species within a project, along with everything that is needed to
+/// germinate them
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct PlanItem {
+    /// The species-level taxon. Samples of subspecies and varieties are grouped under their
+    /// species.
+    pub taxon: Taxon,
+    /// The id and uuid of each of the samples
+    pub samples: Vec<(i64, Uuid)>,
+    /// The total quantity of all samples that have a known quantity
+    pub quantity: Option<i64>,
+    /// The part of `quantity` that is usable seed, according to the most recent quality test of
+    /// each sample. Untested samples count in full.
+    pub usable_quantity: Option<i64>,
+    /// The union of the germination codes of all of the grouped taxa
+    pub germination: Vec<Germination>,
+    /// The longest cold stratification period required by any of the germination codes
+    pub stratification_days: Option<u32>,
+}
+
+#[derive(strum_macros::Display, Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanSortField {
+    Taxon,
+    #[serde(rename = "strat")]
+    Stratification,
+    #[serde(rename = "qty")]
+    Quantity,
+}
+
+/// Build the propagation plan for the project with the given id
+pub async fn load(
+    projectid: i64,
+    sort: Option<SortSpec<PlanSortField>>,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<PlanItem>> {
+    let allocations = Allocation::load_all(
+        Some(Arc::new(allocation::Filter::ProjectId(projectid))),
+        None,
+        pool,
+    )
+    .await?;
+
+    let mut items: Vec<PlanItem> = Vec::new();
+    for alloc in allocations {
+        let mut taxon = alloc.sample.taxon.object()?.clone();
+        taxon.load_germination_info(pool).await?;
+        let species = match (
+            taxon.rank.clone() as i64 > Rank::Species as i64,
+            taxon.parentid,
+        ) {
+            (true, Some(parentid)) => parentid,
+            _ => taxon.id,
+        };
+        let idx = match items.iter().position(|item| item.taxon.id == species) {
+            Some(idx) => idx,
+            None => {
+                let species_taxon = match species == taxon.id {
+                    true => taxon.clone(),
+                    false => {
+                        let mut t = Taxon::load(species, pool).await?;
+                        t.load_germination_info(pool).await?;
+                        t
+                    }
+                };
+                items.push(PlanItem {
+                    germination: species_taxon.germination.clone().unwrap_or_default(),
+                    taxon: species_taxon,
+                    samples: Vec::new(),
+                    quantity: None,
+                    usable_quantity: None,
+                    stratification_days: None,
+                });
+                items.len() - 1
+            }
+        };
+        let item = &mut items[idx];
+        item.samples.push((alloc.sample.id, alloc.sample.uuid));
+        if let Some(qty) = alloc.sample.quantity {
+            item.quantity = Some(item.quantity.unwrap_or(0) + qty);
+            let usable = quality::usable_quantity(alloc.sample.id, qty, pool).await?;
+            item.usable_quantity = Some(item.usable_quantity.unwrap_or(0) + usable);
+        }
+        for germ in taxon.germination.unwrap_or_default() {
+            if !item.germination.iter().any(|g| g.id == germ.id) {
+                item.germination.push(germ);
+            }
+        }
+    }
+
+    for item in items.iter_mut() {
+        item.germination.sort_by(|a, b| a.code.cmp(&b.code));
+        item.stratification_days = item
+            .germination
+            .iter()
+            .filter_map(Germination::stratification_days)
+            .max();
+    }
+    sort_items(
+        &mut items,
+        sort.unwrap_or(SortSpec::new(
+            PlanSortField::Stratification,
+            SortOrder::Descending,
+        )),
+    );
+    Ok(items)
+}
+
+fn sort_items(items: &mut [PlanItem], sort: SortSpec<PlanSortField>) {
+    let taxon_order = |a: &PlanItem, b: &PlanItem| {
+        a.taxon
+            .seq
+            .cmp(&b.taxon.seq)
+            .then_with(|| a.taxon.complete_name.cmp(&b.taxon.complete_name))
+    };
+    items.sort_by(|a, b| {
+        let ord = match sort.field {
+            PlanSortField::Taxon => taxon_order(a, b),
+            PlanSortField::Stratification => a.stratification_days.cmp(&b.stratification_days),
+            PlanSortField::Quantity => a.quantity.cmp(&b.quantity),
+        };
+        let ord = match sort.order {
+            SortOrder::Ascending => ord,
+            SortOrder::Descending => ord.reverse(),
+        };
+        // ties are always listed in taxonomic order
+        ord.then_with(|| taxon_order(a, b))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn propagation_plan(pool: Pool<Sqlite>) {
+        sqlx::query(
+            r#"INSERT INTO sc_germination_codes (germid, code) VALUES (1, 'A'), (2, 'C(30)'), (3, 'C(60)');
+            INSERT INTO sc_taxon_germination (tsn, germid) VALUES (40683, 2), (40683, 3), (43254, 1);"#,
+        )
+        .execute(&pool)
+        .await
+        .expect("Failed to insert germination codes");
+        sqlx::query(
+            "INSERT INTO sc_quality_tests (sampleid, qtestdate, qtestfill) VALUES (2, '2024-01-01', 75)",
+        )
+        .execute(&pool)
+        .await
+        .expect("Failed to insert quality test");
+
+        let plan = load(1, None, &pool)
+            .await
+            .expect("Failed to load propagation plan");
+        assert_eq!(plan.len(), 2);
+        // the species with the longest stratification comes first by default
+        assert_eq!(plan[0].taxon.id, 40683);
+        assert_eq!(
+            plan[0].samples.iter().map(|s| s.0).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert_eq!(plan[0].quantity, Some(100));
+        assert_eq!(plan[0].usable_quantity, Some(75));
+        assert_eq!(
+            plan[0]
+                .germination
+                .iter()
+                .map(|g| g.code.as_str())
+                .collect::<Vec<_>>(),
+            vec!["C(30)", "C(60)"]
+        );
+        assert_eq!(plan[0].stratification_days, Some(60));
+        assert_eq!(plan[1].taxon.id, 43254);
+        assert_eq!(
+            plan[1].samples.iter().map(|s| s.0).collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert_eq!(plan[1].stratification_days, None);
+
+        let plan = load(
+            1,
+            Some(SortSpec::new(
+                PlanSortField::Stratification,
+                SortOrder::Ascending,
+            )),
+            &pool,
+        )
+        .await
+        .expect("Failed to load propagation plan");
+        assert_eq!(plan[0].taxon.id, 43254);
+
+        assert!(load(2, None, &pool)
+            .await
+            .expect("Failed to load propagation plan")
+            .is_empty());
+    }
+}
@@ -0,0 +1,244 @@
+//! The cultivation status of allocated samples. The current status is stored with the allocation,
+//! and every change to it is recorded as an event along with the time and the user who made the
+//! change, so that the progress of a project can be charted over time.
+use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Sqlite};
+use strum_macros::{Display, EnumIter, EnumString, FromRepr};
+use time::{Date, OffsetDateTime};
+
+/// The stage that an allocated sample has reached
+#[derive(
+    sqlx::Type,
+    Debug,
+    Default,
+    Copy,
+    Clone,
+    Serialize,
+    Deserialize,
+    Display,
+    EnumString,
+    EnumIter,
+    FromRepr,
+    PartialEq,
+)]
+#[repr(i64)]
+pub enum AllocationStatus {
+    /// the sample has been added to the project, but nothing has been done with it yet
+    #[default]
+    Allocated = 1,
+    Sown = 2,
+    Germinated = 3,
+    Planted = 4,
+    Established = 5,
+    Failed = 6,
+}
+
+/// A change to the status of an allocation
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct StatusEvent {
+    #[sqlx(rename = "evid")]
+    pub id: i64,
+    pub psid: i64,
+    /// the user who made the change, if known
+    pub userid: Option<i64>,
+    pub username: Option<String>,
+    /// the status before the change
+    #[sqlx(rename = "evfrom")]
+    pub from: Option<AllocationStatus>,
+    #[sqlx(rename = "evto")]
+    pub to: AllocationStatus,
+    #[sqlx(rename = "evtime")]
+    pub time: OffsetDateTime,
+}
+
+impl StatusEvent {
+    /// Load the status changes of the given allocation, most recent changes first
+    pub async fn load_allocation(psid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            r#"SELECT E.*, U.username FROM sc_allocation_events E
+            LEFT JOIN sc_users U ON U.userid=E.userid
+            WHERE E.psid=? ORDER BY E.evtime DESC, E.evid DESC"#,
+        )
+        .bind(psid)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+}
+
+/// The length of the periods that status changes are grouped by
+#[derive(Debug, Default, Copy, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    /// weeks start on Monday
+    #[default]
+    Week,
+    Month,
+}
+
+impl Period {
+    /// An SQL expression for the first day of the period that contains the time of an event
+    fn start_expr(&self) -> &'static str {
+        match self {
+            Period::Day => "DATE(E.evtime)",
+            Period::Week => "DATE(E.evtime, '-6 days', 'weekday 1')",
+            Period::Month => "DATE(E.evtime, 'start of month')",
+        }
+    }
+}
+
+/// The number of allocations of a project that reached a status within a single period
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct StatusProgress {
+    /// the first day of the period
+    pub period: Date,
+    pub status: AllocationStatus,
+    /// the number of changes to this status within the period
+    pub count: i64,
+    /// the number of changes to this status up to the end of the period
+    pub total: i64,
+}
+
+impl StatusProgress {
+    /// Group the status changes of the project's allocations by period and status, in
+    /// chronological order. Only the changes within the given dates are counted, if any.
+    pub async fn load_project(
+        projectid: i64,
+        period: Period,
+        since: Option<Date>,
+        until: Option<Date>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let start = period.start_expr();
+        sqlx::query_as(&format!(
+            r#"SELECT {start} AS period, E.evto AS status, COUNT(*) AS count,
+            SUM(COUNT(*)) OVER (PARTITION BY E.evto ORDER BY {start}) AS total
+            FROM sc_allocation_events E
+            INNER JOIN sc_project_samples PS ON PS.psid=E.psid
+            WHERE PS.projectid=?1
+            AND (?2 IS NULL OR DATE(E.evtime) >= ?2) AND (?3 IS NULL OR DATE(E.evtime) <= ?3)
+            GROUP BY period, E.evto
+            ORDER BY period, E.evto"#
+        ))
+        .bind(projectid)
+        .bind(since)
+        .bind(until)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{loadable::Loadable, project::Allocation};
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../../db/fixtures",
+            scripts("users", "sources", "taxa", "assigned-samples")
+        )
+    ))]
+    async fn status_events(pool: Pool<Sqlite>) {
+        let mut alloc = Allocation::load(1, &pool).await.unwrap();
+        assert_eq!(alloc.status, AllocationStatus::Allocated);
+        assert!(StatusEvent::load_allocation(1, &pool)
+            .await
+            .unwrap()
+            .is_empty());
+
+        alloc
+            .set_status(AllocationStatus::Sown, Some(1), &pool)
+            .await
+            .unwrap();
+        // setting the same status again is not recorded
+        alloc
+            .set_status(AllocationStatus::Sown, Some(1), &pool)
+            .await
+            .unwrap();
+        alloc
+            .set_status(AllocationStatus::Germinated, None, &pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            Allocation::load(1, &pool).await.unwrap().status,
+            AllocationStatus::Germinated
+        );
+        let events = StatusEvent::load_allocation(1, &pool).await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].from, Some(AllocationStatus::Sown));
+        assert_eq!(events[0].to, AllocationStatus::Germinated);
+        assert_eq!(events[0].username, None);
+        assert_eq!(events[1].from, Some(AllocationStatus::Allocated));
+        assert_eq!(events[1].username.as_deref(), Some("testuser"));
+
+        // backdate the events to check the grouping by period
+        sqlx::query("UPDATE sc_allocation_events SET evtime='2024-03-06 10:00:00' WHERE evid=?")
+            .bind(events[1].id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE sc_allocation_events SET evtime='2024-04-02 10:00:00' WHERE evid=?")
+            .bind(events[0].id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut other = Allocation::load(2, &pool).await.unwrap();
+        other
+            .set_status(AllocationStatus::Sown, Some(1), &pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE sc_allocation_events SET evtime='2024-03-10 10:00:00' WHERE psid=2")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let weekly = StatusProgress::load_project(1, Period::Week, None, None, &pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            weekly,
+            vec![
+                StatusProgress {
+                    period: time::macros::date!(2024 - 03 - 04),
+                    status: AllocationStatus::Sown,
+                    count: 2,
+                    total: 2,
+                },
+                StatusProgress {
+                    period: time::macros::date!(2024 - 04 - 01),
+                    status: AllocationStatus::Germinated,
+                    count: 1,
+                    total: 1,
+                },
+            ]
+        );
+        let daily = StatusProgress::load_project(1, Period::Day, None, None, &pool)
+            .await
+            .unwrap();
+        assert_eq!(daily.len(), 3);
+        assert_eq!(daily[1].total, 2);
+        let monthly = StatusProgress::load_project(
+            1,
+            Period::Month,
+            Some(time::macros::date!(2024 - 04 - 01)),
+            None,
+            &pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(monthly.len(), 1);
+        assert_eq!(monthly[0].period, time::macros::date!(2024 - 04 - 01));
+        assert!(
+            StatusProgress::load_project(2, Period::Week, None, None, &pool)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
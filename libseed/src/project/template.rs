@@ -0,0 +1,266 @@
+//! Templates for the notes that are added to the samples of a project again and again, e.g. a
+//! weekly germination check. Each project has its own templates. A template pre-fills the type of
+//! the note, a skeleton of the summary and details such as a checklist, so only the parts that
+//! differ from one note to the next need to be filled in.
+use crate::{
+    error::{Error, Result},
+    project::{Note, NoteType},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, FromRow, Pool, Row, Sqlite};
+use time::Date;
+
+/// A template for the notes of a project
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct NoteTemplate {
+    pub id: i64,
+    pub projectid: i64,
+    /// the name that the template is chosen by, unique within the project
+    pub name: String,
+    pub kind: NoteType,
+    pub summary: String,
+    pub details: Option<String>,
+}
+
+impl FromRow<'_, SqliteRow> for NoteTemplate {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            id: row.try_get("templateid")?,
+            projectid: row.try_get("projectid")?,
+            name: row.try_get("templatename")?,
+            kind: row.try_get("notetype")?,
+            summary: row.try_get("notesummary")?,
+            details: row.try_get("notedetails")?,
+        })
+    }
+}
+
+impl NoteTemplate {
+    pub fn new(
+        projectid: i64,
+        name: String,
+        kind: NoteType,
+        summary: String,
+        details: Option<String>,
+    ) -> Self {
+        Self {
+            id: -1,
+            projectid,
+            name,
+            kind,
+            summary,
+            details,
+        }
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        sqlx::query_as("SELECT * FROM sc_note_templates WHERE templateid=?")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Load the templates of the given project, ordered by name
+    pub async fn load_project(projectid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as("SELECT * FROM sc_note_templates WHERE projectid=? ORDER BY templatename")
+            .bind(projectid)
+            .fetch_all(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Load the template of the given project with the given name
+    pub async fn load_by_name(projectid: i64, name: &str, pool: &Pool<Sqlite>) -> Result<Self> {
+        sqlx::query_as("SELECT * FROM sc_note_templates WHERE projectid=? AND templatename=?")
+            .bind(projectid)
+            .bind(name.trim())
+            .fetch_optional(pool)
+            .await?
+            .ok_or_else(|| Error::InvalidValue(format!("The project has no template '{name}'")))
+    }
+
+    /// A new note for the given allocation that is pre-filled from this template
+    pub fn note(&self, psid: i64, date: Date) -> Note {
+        Note::new(
+            psid,
+            date,
+            self.kind,
+            self.summary.clone(),
+            self.details.clone(),
+        )
+    }
+
+    fn validate(&mut self) -> Result<()> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() {
+            return Err(Error::InvalidValue("The template needs a name".to_string()));
+        }
+        self.summary = self.summary.trim().to_string();
+        if self.summary.is_empty() {
+            return Err(Error::InvalidValue(
+                "The template needs a summary".to_string(),
+            ));
+        }
+        self.details = self
+            .details
+            .as_ref()
+            .map(|d| d.trim_end().to_string())
+            .filter(|d| !d.trim().is_empty());
+        Ok(())
+    }
+
+    /// The error for a name that is already used by another template of the project
+    fn map_duplicate(&self, e: sqlx::Error) -> Error {
+        match e {
+            sqlx::Error::Database(ref db) if db.is_unique_violation() => Error::InvalidValue(
+                format!("The project already has a template '{}'", self.name),
+            ),
+            e => e.into(),
+        }
+    }
+
+    pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        if self.id != -1 {
+            return Err(Error::InvalidOperationObjectAlreadyExists(self.id));
+        }
+        self.validate()?;
+        let res = sqlx::query(
+            r#"INSERT INTO sc_note_templates
+            (projectid, templatename, notetype, notesummary, notedetails)
+            VALUES (?, ?, ?, ?, ?)"#,
+        )
+        .bind(self.projectid)
+        .bind(&self.name)
+        .bind(self.kind as i64)
+        .bind(&self.summary)
+        .bind(&self.details)
+        .execute(pool)
+        .await
+        .map_err(|e| self.map_duplicate(e))?;
+        self.id = res.last_insert_rowid();
+        Ok(())
+    }
+
+    pub async fn update(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        if self.id < 0 {
+            return Err(Error::InvalidOperationObjectNotFound);
+        }
+        self.validate()?;
+        sqlx::query(
+            r#"UPDATE sc_note_templates
+            SET templatename=?, notetype=?, notesummary=?, notedetails=? WHERE templateid=?"#,
+        )
+        .bind(&self.name)
+        .bind(self.kind as i64)
+        .bind(&self.summary)
+        .bind(&self.details)
+        .bind(self.id)
+        .execute(pool)
+        .await
+        .map_err(|e| self.map_duplicate(e))?;
+        Ok(())
+    }
+
+    /// Remove the template. Notes that were created from it are not affected.
+    pub async fn delete(&self, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query("DELETE FROM sc_note_templates WHERE templateid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+    use time::macros::date;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn test_templates(pool: Pool<Sqlite>) {
+        let mut weekly = NoteTemplate::new(
+            1,
+            " Weekly check ".to_string(),
+            NoteType::Germination,
+            "Germination check: __ seedlings".to_string(),
+            Some("- [ ] moisture\n- [ ] mold\n\n".to_string()),
+        );
+        weekly.insert(&pool).await.unwrap();
+        assert_eq!(weekly.name, "Weekly check");
+        assert_eq!(
+            weekly.details.as_deref(),
+            Some("- [ ] moisture\n- [ ] mold")
+        );
+        let mut sowing = NoteTemplate::new(
+            1,
+            "Sowing".to_string(),
+            NoteType::Planting,
+            "Sowed".to_string(),
+            Some(" ".to_string()),
+        );
+        sowing.insert(&pool).await.unwrap();
+        assert_eq!(sowing.details, None);
+
+        let mut duplicate = NoteTemplate::new(
+            1,
+            "Sowing".to_string(),
+            NoteType::Other,
+            "Again".to_string(),
+            None,
+        );
+        assert!(matches!(
+            duplicate.insert(&pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        let mut empty =
+            NoteTemplate::new(1, "Empty".to_string(), NoteType::Other, " ".into(), None);
+        assert!(empty.insert(&pool).await.is_err());
+
+        // templates belong to a project
+        let mut other = NoteTemplate::new(
+            2,
+            "Sowing".to_string(),
+            NoteType::Planting,
+            "Sowed".to_string(),
+            None,
+        );
+        other.insert(&pool).await.unwrap();
+        let templates = NoteTemplate::load_project(1, &pool).await.unwrap();
+        assert_eq!(
+            templates
+                .iter()
+                .map(|t| t.name.as_str())
+                .collect::<Vec<_>>(),
+            ["Sowing", "Weekly check"]
+        );
+        assert_eq!(
+            NoteTemplate::load_by_name(1, "Weekly check", &pool)
+                .await
+                .unwrap(),
+            weekly
+        );
+        assert!(NoteTemplate::load_by_name(1, "Harvest", &pool)
+            .await
+            .is_err());
+
+        let note = weekly.note(1, date!(2024 - 05 - 01));
+        assert_eq!(note.kind, NoteType::Germination);
+        assert_eq!(note.summary, weekly.summary);
+        assert_eq!(note.details, weekly.details);
+        note.insert(&pool).await.unwrap();
+
+        weekly.kind = NoteType::Growing;
+        weekly.update(&pool).await.unwrap();
+        assert_eq!(NoteTemplate::load(weekly.id, &pool).await.unwrap(), weekly);
+        weekly.delete(&pool).await.unwrap();
+        assert!(NoteTemplate::load(weekly.id, &pool).await.is_err());
+    }
+}
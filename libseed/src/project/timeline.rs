@@ -0,0 +1,336 @@
+//! A timeline of the key dates of a project, such as the notes about its samples, when they need
+//! to be stratified and sown, when they were planted out and the trips that target their taxa.
+//! The dates that are recorded in the database are collected with a single query, and the
+//! stratification periods and sowing windows are derived from the germination codes of each
+//! sample's taxon.
+use super::NoteType;
+use crate::{error::Result, taxonomy::Germination, try_get_uuid};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Row, Sqlite};
+use strum_macros::Display;
+use time::{Date, Duration};
+use uuid::Uuid;
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+/// The number of days after the end of stratification during which a sample should be sown
+pub const SOWING_WINDOW_DAYS: i64 = 14;
+
+/// What happens on a date of the timeline
+#[derive(sqlx::Type, Debug, Copy, Clone, Serialize, Deserialize, Display, PartialEq)]
+#[repr(i64)]
+pub enum EventKind {
+    Note = 1,
+    /// the cold moist stratification that a sample needs before it can be sown
+    Stratification = 2,
+    /// the days during which a sample should be sown once it has been stratified
+    SowingWindow = 3,
+    Planting = 4,
+    SurvivalCheck = 5,
+    Reminder = 6,
+    Trip = 7,
+}
+
+/// A single date or range of dates on the timeline
+#[derive(FromRow, Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct TimelineEvent {
+    /// the allocation that the event belongs to, or `None` for events of the whole project
+    pub psid: Option<i64>,
+    pub kind: EventKind,
+    #[serde(with = "iso_date")]
+    pub start: Date,
+    /// the last day of an event that spans several days
+    #[serde(with = "iso_date::option")]
+    pub end: Option<Date>,
+    pub label: String,
+    /// the type of the note, for events of kind [EventKind::Note]
+    pub notetype: Option<NoteType>,
+}
+
+impl TimelineEvent {
+    /// The last day of the event
+    pub fn last_day(&self) -> Date {
+        self.end.unwrap_or(self.start)
+    }
+}
+
+/// The events of a single allocated sample, or of the project as a whole
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct TimelineRow {
+    /// the allocation that the row shows, or `None` for the row with the events of the project
+    pub psid: Option<i64>,
+    pub uuid: Option<Uuid>,
+    pub sampleid: Option<i64>,
+    pub label: String,
+    /// the events of the row, ordered by date
+    pub events: Vec<TimelineEvent>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Timeline {
+    /// the row with the events of the whole project, if there are any, followed by a row for each
+    /// allocated sample in taxonomic order
+    pub rows: Vec<TimelineRow>,
+}
+
+impl Timeline {
+    /// Collect the key dates of the project with the given id
+    pub async fn load(projectid: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        let events = load_events(projectid, pool).await?;
+        let codes = load_germination_codes(projectid, pool).await?;
+        let mut rows: Vec<TimelineRow> = sqlx::query(
+            r#"SELECT PS.psid, PS.psuuid, S.sampleid, T.complete_name
+            FROM sc_project_samples PS
+            INNER JOIN sc_samples S ON S.sampleid=PS.sampleid
+            INNER JOIN taxonomic_units T ON T.tsn=S.tsn
+            WHERE PS.projectid=?
+            ORDER BY T.phylo_sort_seq, S.sampleid"#,
+        )
+        .bind(projectid)
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| {
+            Ok(TimelineRow {
+                psid: Some(row.try_get("psid")?),
+                uuid: Some(try_get_uuid(row, "psuuid")?),
+                sampleid: Some(row.try_get("sampleid")?),
+                label: row.try_get("complete_name")?,
+                events: Vec::new(),
+            })
+        })
+        .collect::<Result<_>>()?;
+
+        let mut project = TimelineRow {
+            psid: None,
+            uuid: None,
+            sampleid: None,
+            label: "Project".to_string(),
+            events: Vec::new(),
+        };
+        for event in events {
+            match rows
+                .iter_mut()
+                .find(|r| r.psid.is_some() && r.psid == event.psid)
+            {
+                Some(row) => row.events.push(event),
+                None => project.events.push(event),
+            }
+        }
+        for row in rows.iter_mut() {
+            let days = codes
+                .iter()
+                .filter(|(psid, _)| row.psid == Some(*psid))
+                .filter_map(|(_, g)| g.stratification_days())
+                .max();
+            row.events.extend(sowing_schedule(&row.events, days));
+            row.events.sort_by_key(|e| e.start);
+        }
+        if !project.events.is_empty() {
+            rows.insert(0, project);
+        }
+        Ok(Self { rows })
+    }
+
+    /// The first and last day of all of the events, or `None` if there are no events
+    pub fn span(&self) -> Option<(Date, Date)> {
+        let events = self.rows.iter().flat_map(|r| r.events.iter());
+        let first = events.clone().map(|e| e.start).min()?;
+        let last = events.map(TimelineEvent::last_day).max()?;
+        Some((first, last))
+    }
+}
+
+/// The stratification period and sowing window of an allocation that needs `days` days of cold
+/// stratification, which is assumed to start on the date of its latest preparation note
+fn sowing_schedule(events: &[TimelineEvent], days: Option<u32>) -> Vec<TimelineEvent> {
+    let (Some(days), Some(prepared)) = (
+        days,
+        events
+            .iter()
+            .filter(|e| e.notetype == Some(NoteType::Preparation))
+            .max_by_key(|e| e.start),
+    ) else {
+        return Vec::new();
+    };
+    let stratified = prepared.start + Duration::days(days.into());
+    vec![
+        TimelineEvent {
+            psid: prepared.psid,
+            kind: EventKind::Stratification,
+            start: prepared.start,
+            end: Some(stratified),
+            label: format!("{days} days of cold stratification"),
+            notetype: None,
+        },
+        TimelineEvent {
+            psid: prepared.psid,
+            kind: EventKind::SowingWindow,
+            start: stratified,
+            end: Some(stratified + Duration::days(SOWING_WINDOW_DAYS)),
+            label: "Sowing window".to_string(),
+            notetype: None,
+        },
+    ]
+}
+
+/// All dates that are recorded for the project: the notes about its samples, plantings, survival
+/// checks, pending reminders and the owner's trips that target any of its taxa or sources
+async fn load_events(projectid: i64, pool: &Pool<Sqlite>) -> Result<Vec<TimelineEvent>> {
+    Ok(sqlx::query_as(
+        r#"SELECT N.psid, 1 AS kind, N.notedate AS start, NULL AS end, N.notesummary AS label,
+            N.notetype
+        FROM sc_project_notes N INNER JOIN sc_project_samples PS ON PS.psid=N.psid
+        WHERE PS.projectid=?1
+        UNION ALL
+        SELECT PL.psid, 4, PL.plantingdate, NULL,
+            'Planted ' || PL.plantingcount || COALESCE(' at ' || PL.plantinglocation, ''), NULL
+        FROM sc_plantings PL INNER JOIN sc_project_samples PS ON PS.psid=PL.psid
+        WHERE PS.projectid=?1
+        UNION ALL
+        SELECT PL.psid, 5, C.checkdate, NULL,
+            C.checkalive || ' alive after ' || C.checkmonths || ' months', NULL
+        FROM sc_survival_checks C
+        INNER JOIN sc_plantings PL ON PL.plantingid=C.plantingid
+        INNER JOIN sc_project_samples PS ON PS.psid=PL.psid
+        WHERE PS.projectid=?1
+        UNION ALL
+        SELECT N.psid, 6, R.reminderdue, NULL, R.remindertitle, NULL
+        FROM sc_reminders R
+        LEFT JOIN sc_project_notes N ON N.pnoteid=R.pnoteid
+        LEFT JOIN sc_project_samples PS ON PS.psid=N.psid
+        WHERE R.reminderdismissed=0 AND (R.projectid=?1 OR PS.projectid=?1)
+        UNION ALL
+        SELECT NULL, 7, T.tripdate, NULL, T.tripname, NULL
+        FROM sc_trips T
+        WHERE T.userid=(SELECT userid FROM sc_projects WHERE projectid=?1) AND T.tripid IN (
+            SELECT TT.tripid FROM sc_trip_taxa TT
+            INNER JOIN sc_samples S ON S.tsn=TT.tsn
+            INNER JOIN sc_project_samples PS ON PS.sampleid=S.sampleid
+            WHERE PS.projectid=?1
+            UNION
+            SELECT TS.tripid FROM sc_trip_sources TS
+            INNER JOIN sc_samples S ON S.srcid=TS.srcid
+            INNER JOIN sc_project_samples PS ON PS.sampleid=S.sampleid
+            WHERE PS.projectid=?1)
+        ORDER BY start, kind"#,
+    )
+    .bind(projectid)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// The germination codes of the taxon of each of the project's allocations
+async fn load_germination_codes(
+    projectid: i64,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<(i64, Germination)>> {
+    sqlx::query(
+        r#"SELECT PS.psid, G.* FROM sc_project_samples PS
+        INNER JOIN sc_samples S ON S.sampleid=PS.sampleid
+        INNER JOIN sc_taxon_germination TG ON TG.tsn=S.tsn
+        INNER JOIN sc_germination_codes G ON G.germid=TG.germid
+        WHERE PS.projectid=?"#,
+    )
+    .bind(projectid)
+    .fetch_all(pool)
+    .await?
+    .iter()
+    .map(|row| Ok((row.try_get("psid")?, Germination::from_row(row)?)))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+    use time::macros::date;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn project_timeline(pool: Pool<Sqlite>) {
+        let timeline = Timeline::load(1, &pool).await.unwrap();
+        assert_eq!(
+            timeline.rows.iter().map(|r| r.psid).collect::<Vec<_>>(),
+            vec![Some(2), Some(3), Some(1)]
+        );
+        assert_eq!(timeline.span(), None);
+
+        sqlx::query(
+            r#"INSERT INTO sc_germination_codes (germid, code) VALUES (1, 'C(60)');
+            INSERT INTO sc_taxon_germination (tsn, germid) VALUES (40683, 1);
+            INSERT INTO sc_project_notes (psid, notedate, notetype, notesummary) VALUES
+                (2, '2024-01-10', 1, 'Started stratification'), (1, '2024-01-12', 1, 'Soaked'),
+                (4, '2024-01-01', 1, 'Other project');
+            INSERT INTO sc_plantings (plantingid, psid, plantingdate, plantingcount, plantinglocation)
+                VALUES (1, 1, '2024-06-01', 12, 'North bed');
+            INSERT INTO sc_survival_checks (plantingid, checkdate, checkmonths, checkalive)
+                VALUES (1, '2024-07-01', 1, 10);
+            INSERT INTO sc_reminders (userid, reminderdue, remindertitle, projectid)
+                VALUES (1, '2024-05-01', 'Order flats', 1);
+            INSERT INTO sc_trips (tripid, tripname, tripdate, userid)
+                VALUES (1, 'Prairie walk', '2024-09-15', 1), (2, 'Unrelated', '2024-09-20', 1);
+            INSERT INTO sc_trip_taxa (tripid, tsn) VALUES (1, 43254);"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let timeline = Timeline::load(1, &pool).await.unwrap();
+        assert_eq!(
+            timeline.span(),
+            Some((date!(2024 - 01 - 10), date!(2024 - 09 - 15)))
+        );
+        let kinds = |row: &TimelineRow| {
+            row.events
+                .iter()
+                .map(|e| (e.kind, e.start, e.end))
+                .collect::<Vec<_>>()
+        };
+        let project = &timeline.rows[0];
+        assert_eq!(project.psid, None);
+        assert_eq!(
+            kinds(project),
+            vec![
+                (EventKind::Reminder, date!(2024 - 05 - 01), None),
+                (EventKind::Trip, date!(2024 - 09 - 15), None),
+            ]
+        );
+        // sample 2 needs to be stratified for 60 days before it can be sown
+        let stratified = &timeline.rows[1];
+        assert_eq!(stratified.psid, Some(2));
+        assert_eq!(
+            kinds(stratified),
+            vec![
+                (EventKind::Note, date!(2024 - 01 - 10), None),
+                (
+                    EventKind::Stratification,
+                    date!(2024 - 01 - 10),
+                    Some(date!(2024 - 03 - 10))
+                ),
+                (
+                    EventKind::SowingWindow,
+                    date!(2024 - 03 - 10),
+                    Some(date!(2024 - 03 - 24))
+                ),
+            ]
+        );
+        // sample 1 doesn't need stratification
+        let planted = &timeline.rows[3];
+        assert_eq!(planted.psid, Some(1));
+        assert_eq!(
+            kinds(planted),
+            vec![
+                (EventKind::Note, date!(2024 - 01 - 12), None),
+                (EventKind::Planting, date!(2024 - 06 - 01), None),
+                (EventKind::SurvivalCheck, date!(2024 - 07 - 01), None),
+            ]
+        );
+        assert_eq!(planted.events[1].label, "Planted 12 at North bed");
+    }
+}
@@ -0,0 +1,242 @@
+//! Quality tests measure how much of a sample is actually usable seed. A fill test (either cutting
+//! seeds open or taking an x-ray) finds the percentage of the seeds that are filled, and a purity
+//! test finds the percentage of the sample's weight that is seed rather than chaff. The most recent
+//! test of a sample is used to estimate its usable quantity.
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteQueryResult, FromRow, Pool, Sqlite};
+use strum_macros::{Display, EnumIter, EnumString};
+use time::Date;
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+/// How the fill of a sample was determined
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Eq,
+    sqlx::Type,
+    Display,
+    EnumIter,
+    EnumString,
+)]
+#[repr(i64)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum FillMethod {
+    /// a number of seeds were cut open and inspected
+    Cut = 1,
+    /// a number of seeds were x-rayed
+    #[serde(rename = "x-ray")]
+    #[strum(serialize = "x-ray")]
+    XRay = 2,
+}
+
+/// The result of a purity and/or fill test of a sample
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct QualityTest {
+    #[sqlx(rename = "qtestid")]
+    pub id: i64,
+    pub sampleid: i64,
+    /// the day that the test was done
+    #[sqlx(rename = "qtestdate")]
+    #[serde(with = "iso_date")]
+    pub tested: Date,
+    /// the percentage of the seeds that are filled
+    #[sqlx(rename = "qtestfill")]
+    pub fill: Option<f64>,
+    #[sqlx(rename = "qtestmethod")]
+    pub method: Option<FillMethod>,
+    /// the percentage of the sample's weight that is pure seed
+    #[sqlx(rename = "qtestpurity")]
+    pub purity: Option<f64>,
+    /// who did the test
+    #[sqlx(rename = "qtesttester")]
+    pub tester: Option<String>,
+}
+
+impl QualityTest {
+    pub fn new(sampleid: i64, tested: Date) -> Self {
+        Self {
+            id: -1,
+            sampleid,
+            tested,
+            fill: None,
+            method: None,
+            purity: None,
+            tester: None,
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.fill.is_none() && self.purity.is_none() {
+            return Err(Error::InvalidValue(
+                "either the fill or the purity is required".to_string(),
+            ));
+        }
+        for (name, value) in [("fill", self.fill), ("purity", self.purity)] {
+            if value.is_some_and(|v| !(0.0..=100.0).contains(&v)) {
+                return Err(Error::InvalidValue(format!(
+                    "the {name} must be a percentage between 0 and 100"
+                )));
+            }
+        }
+        if self.method.is_some() && self.fill.is_none() {
+            return Err(Error::InvalidValue(
+                "a fill method was given without a fill percentage".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        if self.id != -1 {
+            return Err(Error::InvalidOperationObjectAlreadyExists(self.id));
+        }
+        self.validate()?;
+        self.tester = self
+            .tester
+            .as_deref()
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(str::to_string);
+        let res = sqlx::query(
+            r#"INSERT INTO sc_quality_tests (sampleid, qtestdate, qtestfill, qtestmethod,
+            qtestpurity, qtesttester) VALUES (?, ?, ?, ?, ?, ?)"#,
+        )
+        .bind(self.sampleid)
+        .bind(self.tested)
+        .bind(self.fill)
+        .bind(self.method)
+        .bind(self.purity)
+        .bind(&self.tester)
+        .execute(pool)
+        .await?;
+        self.id = res.last_insert_rowid();
+        Ok(res)
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        Ok(
+            sqlx::query_as("SELECT * FROM sc_quality_tests WHERE qtestid=?")
+                .bind(id)
+                .fetch_one(pool)
+                .await?,
+        )
+    }
+
+    /// Load all tests of a sample, the most recent test first
+    pub async fn load_sample(sampleid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as(
+            "SELECT * FROM sc_quality_tests WHERE sampleid=? ORDER BY qtestdate DESC, qtestid DESC",
+        )
+        .bind(sampleid)
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Load the most recent test of a sample, if it has been tested
+    pub async fn latest(sampleid: i64, pool: &Pool<Sqlite>) -> Result<Option<Self>> {
+        Ok(sqlx::query_as(
+            r#"SELECT * FROM sc_quality_tests WHERE sampleid=?
+            ORDER BY qtestdate DESC, qtestid DESC LIMIT 1"#,
+        )
+        .bind(sampleid)
+        .fetch_optional(pool)
+        .await?)
+    }
+
+    pub async fn delete(&self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        sqlx::query("DELETE FROM sc_quality_tests WHERE qtestid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// The fraction of a sample that is usable seed according to this test. A measurement that
+    /// wasn't taken is assumed to be 100%.
+    pub fn usable_fraction(&self) -> f64 {
+        self.fill.unwrap_or(100.0) / 100.0 * self.purity.unwrap_or(100.0) / 100.0
+    }
+
+    /// The part of `quantity` that is usable seed according to this test, rounded to the nearest
+    /// whole number
+    pub fn usable_quantity(&self, quantity: i64) -> i64 {
+        (quantity as f64 * self.usable_fraction()).round() as i64
+    }
+}
+
+/// The usable part of `quantity` according to the most recent test of the sample. Untested
+/// samples are assumed to be entirely usable.
+pub async fn usable_quantity(sampleid: i64, quantity: i64, pool: &Pool<Sqlite>) -> Result<i64> {
+    Ok(QualityTest::latest(sampleid, pool)
+        .await?
+        .map_or(quantity, |test| test.usable_quantity(quantity)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+    use time::macros::date;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn quality_tests(pool: Pool<Sqlite>) {
+        assert_eq!(QualityTest::latest(1, &pool).await.unwrap(), None);
+        assert_eq!(usable_quantity(1, 1000, &pool).await.unwrap(), 1000);
+
+        let mut test = QualityTest::new(1, date!(2024 - 01 - 15));
+        assert!(matches!(
+            test.insert(&pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        test.fill = Some(120.0);
+        assert!(matches!(
+            test.insert(&pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        test.fill = Some(80.0);
+        test.method = Some(FillMethod::Cut);
+        test.tester = Some("  ".to_string());
+        test.insert(&pool).await.expect("Failed to insert test");
+        assert_eq!(test.tester, None);
+        assert_eq!(test.usable_quantity(1000), 800);
+
+        let mut newer = QualityTest::new(1, date!(2024 - 03 - 01));
+        newer.fill = Some(90.0);
+        newer.method = Some(FillMethod::XRay);
+        newer.purity = Some(50.0);
+        newer.tester = Some("Seed lab".to_string());
+        newer.insert(&pool).await.expect("Failed to insert test");
+        // an older test that was entered later doesn't replace the most recent one
+        let mut older = QualityTest::new(1, date!(2023 - 10 - 01));
+        older.purity = Some(10.0);
+        older.insert(&pool).await.expect("Failed to insert test");
+
+        let tests = QualityTest::load_sample(1, &pool).await.unwrap();
+        assert_eq!(
+            tests.iter().map(|t| t.id).collect::<Vec<_>>(),
+            vec![newer.id, test.id, older.id]
+        );
+        assert_eq!(
+            QualityTest::latest(1, &pool).await.unwrap(),
+            Some(newer.clone())
+        );
+        assert_eq!(usable_quantity(1, 1000, &pool).await.unwrap(), 450);
+
+        newer.delete(&pool).await.unwrap();
+        assert_eq!(usable_quantity(1, 1000, &pool).await.unwrap(), 800);
+        assert!(QualityTest::load(newer.id, &pool).await.is_err());
+    }
+}
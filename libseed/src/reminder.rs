@@ -0,0 +1,383 @@
+//! Reminders about things that need to be done at a later date, such as taking seeds out of cold
+//! stratification. A reminder is shown to the user once it is due until it is dismissed, and it
+//! can also be sent by email if the user has enabled that in their preferences. A reminder can be
+//! about a project note, or about a sample, project or source.
+use crate::{
+    error::{Error, Result},
+    project::Note,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, FromRow, Pool, Row, Sqlite};
+use time::{Date, Duration};
+use uuid::Uuid;
+
+const SELECT: &str = r#"SELECT R.*, PS.psuuid, P.projuuid, S.sampleuuid, L.srcuuid
+    FROM sc_reminders R
+    LEFT JOIN sc_project_notes N ON N.pnoteid=R.pnoteid
+    LEFT JOIN sc_project_samples PS ON PS.psid=N.psid
+    LEFT JOIN sc_projects P ON P.projectid=IFNULL(R.projectid, PS.projectid)
+    LEFT JOIN sc_samples S ON S.sampleid=R.sampleid
+    LEFT JOIN sc_sources L ON L.srcid=R.srcid"#;
+
+/// An object that a reminder is about
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
+pub enum ReminderTarget {
+    Sample(i64),
+    Project(i64),
+    Source(i64),
+}
+
+impl ReminderTarget {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Option<Self>> {
+        let sample: Option<i64> = row.try_get("sampleid")?;
+        let project: Option<i64> = row.try_get("projectid")?;
+        let source: Option<i64> = row.try_get("srcid")?;
+        Ok(match (sample, project, source) {
+            (Some(id), _, _) => Some(Self::Sample(id)),
+            (_, Some(id), _) => Some(Self::Project(id)),
+            (_, _, Some(id)) => Some(Self::Source(id)),
+            _ => None,
+        })
+    }
+
+    fn ids(target: Option<Self>) -> (Option<i64>, Option<i64>, Option<i64>) {
+        match target {
+            Some(Self::Sample(id)) => (Some(id), None, None),
+            Some(Self::Project(id)) => (None, Some(id), None),
+            Some(Self::Source(id)) => (None, None, Some(id)),
+            None => (None, None, None),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Reminder {
+    pub id: i64,
+    pub userid: i64,
+    /// the project note that the reminder was scheduled for, if any
+    pub noteid: Option<i64>,
+    /// the sample, project or source that the reminder is about, if any
+    pub target: Option<ReminderTarget>,
+    pub due: Date,
+    pub title: String,
+    /// whether the reminder has already been sent by email
+    pub emailed: bool,
+    pub dismissed: bool,
+    /// the uuids of the project and the allocation that the note belongs to, or of the target
+    /// project, so that the reminder can link to them
+    pub project_uuid: Option<Uuid>,
+    pub allocation_uuid: Option<Uuid>,
+    /// the uuid of the target sample or source, so that the reminder can link to it
+    pub sample_uuid: Option<Uuid>,
+    pub source_uuid: Option<Uuid>,
+}
+
+fn optional_uuid(row: &SqliteRow, column: &str) -> sqlx::Result<Option<Uuid>> {
+    let value: Option<String> = row.try_get(column)?;
+    value
+        .map(|v| Uuid::parse_str(&v))
+        .transpose()
+        .map_err(|e| sqlx::Error::ColumnDecode {
+            index: column.to_string(),
+            source: Box::new(e),
+        })
+}
+
+impl FromRow<'_, SqliteRow> for Reminder {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            id: row.try_get("reminderid")?,
+            userid: row.try_get("userid")?,
+            noteid: row.try_get("pnoteid")?,
+            target: ReminderTarget::from_row(row)?,
+            due: row.try_get("reminderdue")?,
+            title: row.try_get("remindertitle")?,
+            emailed: row.try_get("reminderemailed")?,
+            dismissed: row.try_get("reminderdismissed")?,
+            project_uuid: optional_uuid(row, "projuuid")?,
+            allocation_uuid: optional_uuid(row, "psuuid")?,
+            sample_uuid: optional_uuid(row, "sampleuuid")?,
+            source_uuid: optional_uuid(row, "srcuuid")?,
+        })
+    }
+}
+
+impl Reminder {
+    pub fn new(userid: i64, due: Date, title: String) -> Self {
+        Self {
+            id: -1,
+            userid,
+            noteid: None,
+            target: None,
+            due,
+            title,
+            emailed: false,
+            dismissed: false,
+            project_uuid: None,
+            allocation_uuid: None,
+            sample_uuid: None,
+            source_uuid: None,
+        }
+    }
+
+    /// A reminder about the given sample, project or source
+    pub fn about(userid: i64, target: ReminderTarget, due: Date, title: String) -> Self {
+        let mut reminder = Self::new(userid, due, title);
+        reminder.target = Some(target);
+        reminder
+    }
+
+    /// A reminder for the end of a cold stratification of `days` days that was started on the
+    /// date of the given note
+    pub fn stratification(userid: i64, note: &Note, days: u32, title: String) -> Result<Self> {
+        if days == 0 {
+            return Err(Error::InvalidValue(
+                "the stratification period must be at least one day".to_string(),
+            ));
+        }
+        let due = note
+            .date
+            .checked_add(Duration::days(days.into()))
+            .ok_or_else(|| Error::InvalidValue(format!("{days} days is too long")))?;
+        let mut reminder = Self::new(userid, due, title);
+        reminder.noteid = Some(note.id);
+        Ok(reminder)
+    }
+
+    pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        if self.title.is_empty() {
+            return Err(Error::InvalidStateMissingAttribute("title".to_string()));
+        }
+        let (sampleid, projectid, srcid) = ReminderTarget::ids(self.target);
+        let res = sqlx::query(
+            r#"INSERT INTO sc_reminders
+            (userid, pnoteid, sampleid, projectid, srcid, reminderdue, remindertitle)
+            VALUES (?, ?, ?, ?, ?, ?, ?)"#,
+        )
+        .bind(self.userid)
+        .bind(self.noteid)
+        .bind(sampleid)
+        .bind(projectid)
+        .bind(srcid)
+        .bind(self.due)
+        .bind(&self.title)
+        .execute(pool)
+        .await?;
+        *self = Self::load(res.last_insert_rowid(), pool).await?;
+        Ok(())
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        sqlx::query_as(&format!("{SELECT} WHERE R.reminderid=?"))
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Load all of the user's reminders that haven't been dismissed yet, including the ones that
+    /// aren't due yet, ordered by due date
+    pub async fn load_active(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(&format!(
+            "{SELECT} WHERE R.userid=? AND R.reminderdismissed=0 ORDER BY R.reminderdue, R.reminderid"
+        ))
+        .bind(userid)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Load the user's reminders about the given object that haven't been dismissed yet, ordered by
+    /// due date
+    pub async fn load_target(
+        userid: i64,
+        target: ReminderTarget,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Self>> {
+        let (column, id) = match target {
+            ReminderTarget::Sample(id) => ("sampleid", id),
+            ReminderTarget::Project(id) => ("projectid", id),
+            ReminderTarget::Source(id) => ("srcid", id),
+        };
+        sqlx::query_as(&format!(
+            r#"{SELECT} WHERE R.userid=? AND R.{column}=? AND R.reminderdismissed=0
+            ORDER BY R.reminderdue, R.reminderid"#
+        ))
+        .bind(userid)
+        .bind(id)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Load the reminders that are due on or before `today` and that still need to be sent by
+    /// email because their user has enabled email reminders
+    pub async fn load_unsent(today: Date, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(&format!(
+            r#"{SELECT} INNER JOIN sc_user_prefs U ON U.userid=R.userid
+            WHERE U.emailreminders=1 AND R.reminderemailed=0 AND R.reminderdismissed=0
+            AND R.reminderdue <= ? ORDER BY R.reminderdue, R.reminderid"#
+        ))
+        .bind(today)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub fn is_due(&self, today: Date) -> bool {
+        self.due <= today
+    }
+
+    /// Record that the reminder was sent by email
+    pub async fn mark_emailed(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query("UPDATE sc_reminders SET reminderemailed=1 WHERE reminderid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        self.emailed = true;
+        Ok(())
+    }
+
+    /// Stop showing the reminder to the user
+    pub async fn dismiss(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query("UPDATE sc_reminders SET reminderdismissed=1 WHERE reminderid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        self.dismissed = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{loadable::Loadable, preferences::Preferences, sample::Sample};
+    use test_log::test;
+    use time::macros::date;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "assigned-samples")
+        )
+    ))]
+    async fn reminders(pool: Pool<Sqlite>) {
+        let mut note = Note::load(1, &pool).await.expect("Failed to load note");
+        assert!(Reminder::stratification(1, &note, 0, "Done".to_string()).is_err());
+        let mut reminder = Reminder::stratification(1, &note, 60, "Done".to_string())
+            .expect("Failed to create reminder");
+        assert_eq!(reminder.due, date!(2024 - 02 - 23));
+        reminder.insert(&pool).await.expect("Failed to insert");
+        assert_eq!(reminder.noteid, Some(1));
+        assert!(reminder.allocation_uuid.is_some());
+        assert!(reminder.project_uuid.is_some());
+
+        let mut other = Reminder::new(1, date!(2024 - 06 - 01), "Later".to_string());
+        other.insert(&pool).await.expect("Failed to insert");
+        assert_eq!(other.allocation_uuid, None);
+        let active = Reminder::load_active(1, &pool).await.unwrap();
+        assert_eq!(active, vec![reminder.clone(), other.clone()]);
+        assert!(Reminder::load_active(2, &pool).await.unwrap().is_empty());
+        assert!(reminder.is_due(date!(2024 - 03 - 01)));
+        assert!(!other.is_due(date!(2024 - 03 - 01)));
+
+        // nothing is emailed unless the user asked for it
+        let today = date!(2024 - 03 - 01);
+        assert!(Reminder::load_unsent(today, &pool)
+            .await
+            .unwrap()
+            .is_empty());
+        let mut prefs = Preferences::load(1, &pool).await.unwrap();
+        prefs.email_reminders = true;
+        prefs.save(&pool).await.unwrap();
+        assert_eq!(
+            Reminder::load_unsent(today, &pool).await.unwrap(),
+            vec![reminder.clone()]
+        );
+        reminder.mark_emailed(&pool).await.unwrap();
+        assert!(Reminder::load_unsent(today, &pool)
+            .await
+            .unwrap()
+            .is_empty());
+
+        reminder.dismiss(&pool).await.unwrap();
+        assert_eq!(
+            Reminder::load_active(1, &pool).await.unwrap(),
+            vec![other.clone()]
+        );
+
+        // reminders about other objects link to them
+        let mut sample = Reminder::about(
+            1,
+            ReminderTarget::Sample(1),
+            date!(2024 - 03 - 15),
+            "Re-test viability".to_string(),
+        );
+        sample.insert(&pool).await.expect("Failed to insert");
+        assert_eq!(sample.target, Some(ReminderTarget::Sample(1)));
+        assert!(sample.sample_uuid.is_some());
+        assert_eq!(sample.project_uuid, None);
+        let mut project = Reminder::about(
+            1,
+            ReminderTarget::Project(2),
+            date!(2024 - 04 - 01),
+            "Order pots".to_string(),
+        );
+        project.insert(&pool).await.expect("Failed to insert");
+        assert!(project.project_uuid.is_some());
+        assert_eq!(project.allocation_uuid, None);
+        let mut source = Reminder::about(
+            1,
+            ReminderTarget::Source(1),
+            date!(2024 - 08 - 01),
+            "Return to the site".to_string(),
+        );
+        source.insert(&pool).await.expect("Failed to insert");
+        assert!(source.source_uuid.is_some());
+        assert_eq!(
+            Reminder::load_active(1, &pool).await.unwrap(),
+            vec![
+                sample.clone(),
+                project.clone(),
+                other.clone(),
+                source.clone()
+            ]
+        );
+        assert_eq!(
+            Reminder::load_target(1, ReminderTarget::Sample(1), &pool)
+                .await
+                .unwrap(),
+            vec![sample.clone()]
+        );
+        assert!(Reminder::load_target(2, ReminderTarget::Sample(1), &pool)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // reminders are removed along with the object that they are about
+        let id = sqlx::query("INSERT INTO sc_samples (tsn, userid, srcid) VALUES (40683, 1, 1)")
+            .execute(&pool)
+            .await
+            .unwrap()
+            .last_insert_rowid();
+        let mut reminder = Reminder::about(
+            1,
+            ReminderTarget::Sample(id),
+            date!(2024 - 03 - 15),
+            "Sow".to_string(),
+        );
+        reminder.insert(&pool).await.unwrap();
+        Sample::delete_id(&id, &pool)
+            .await
+            .expect("Failed to delete sample");
+        assert!(Reminder::load(reminder.id, &pool).await.is_err());
+
+        // reminders are removed along with their note
+        let mut reminder = Reminder::stratification(1, &note, 30, "Done".to_string()).unwrap();
+        reminder.insert(&pool).await.unwrap();
+        note.delete(&pool).await.expect("Failed to delete note");
+        assert!(Reminder::load(reminder.id, &pool).await.is_err());
+    }
+}
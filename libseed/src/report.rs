@@ -0,0 +1,385 @@
+//! Custom reports that users can define without any changes to the code. A report is a
+//! [minijinja](https://docs.rs/minijinja) template that is rendered with the user's samples that
+//! match the report's saved filter. The filter works the same as the filter of the sample list: it
+//! matches samples whose taxon name, source name or notes contain the filter text.
+//!
+//! Reports are written by users, so they are rendered in a restricted environment: templates can't
+//! include or import other templates, and rendering is aborted if a template takes too long.
+use crate::{
+    error::{Error, Result},
+    filter::{Cmp, CompoundFilter, DynFilterPart, FilterPart, Op},
+    loadable::Loadable,
+    sample::{self, Sample},
+};
+use async_trait::async_trait;
+use minijinja::{context, AutoEscape, Environment};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteQueryResult, FromRow, Pool, QueryBuilder, Sqlite};
+use std::sync::Arc;
+use strum_macros::{Display, EnumIter, EnumString};
+use time::OffsetDateTime;
+
+/// The maximum amount of work that rendering a report may take, so that a template with an
+/// (almost) endless loop can't tie up the server
+const RENDER_FUEL: u64 = 1_000_000;
+
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Display,
+    EnumIter,
+    EnumString,
+    PartialEq,
+    Serialize,
+    sqlx::Type,
+)]
+#[repr(i32)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Html = 0,
+    Csv = 1,
+}
+
+impl ReportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ReportFormat::Html => "text/html; charset=utf-8",
+            ReportFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Html => "html",
+            ReportFormat::Csv => "csv",
+        }
+    }
+}
+
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Report {
+    #[sqlx(rename = "reportid")]
+    pub id: i64,
+    pub userid: i64,
+    #[sqlx(rename = "reportname")]
+    pub name: String,
+    #[sqlx(rename = "reportformat")]
+    pub format: ReportFormat,
+    /// only samples that match this text are included in the report
+    #[sqlx(rename = "reportfilter")]
+    pub filter: Option<String>,
+    #[sqlx(rename = "reporttemplate")]
+    pub template: String,
+}
+
+#[derive(Clone)]
+pub enum Filter {
+    Id(i64),
+    User(i64),
+    Name(String),
+}
+
+impl From<Filter> for DynFilterPart {
+    fn from(value: Filter) -> Self {
+        Arc::new(value)
+    }
+}
+
+impl FilterPart for Filter {
+    fn add_to_query(&self, builder: &mut QueryBuilder<Sqlite>) {
+        match self {
+            Self::Id(id) => _ = builder.push(" reportid = ").push_bind(*id),
+            Self::User(id) => _ = builder.push(" userid = ").push_bind(*id),
+            Self::Name(name) => _ = builder.push(" reportname = ").push_bind(name.clone()),
+        }
+    }
+}
+
+#[async_trait]
+impl Loadable for Report {
+    type Id = i64;
+
+    fn id(&self) -> Self::Id {
+        self.id
+    }
+
+    fn set_id(&mut self, id: Self::Id) {
+        self.id = id
+    }
+
+    async fn load(id: Self::Id, pool: &Pool<Sqlite>) -> Result<Self> {
+        Self::build_query(Some(Filter::Id(id).into()))
+            .build_query_as()
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    async fn delete_id(id: &Self::Id, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        sqlx::query("DELETE FROM sc_reports WHERE reportid=?")
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+}
+
+/// Quote a value so that it can be used as a field of a CSV file
+fn csv_field(value: String) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// Format an id with a prefix and leading zeroes, e.g. `S0012`, the same as elsewhere in the app
+fn idfmt(id: i64, prefix: Option<String>) -> String {
+    format!("{}{id:04}", prefix.unwrap_or_default())
+}
+
+impl Report {
+    pub fn new(
+        name: String,
+        format: ReportFormat,
+        filter: Option<String>,
+        template: String,
+        userid: i64,
+    ) -> Self {
+        Self {
+            id: -1,
+            userid,
+            name,
+            format,
+            filter,
+            template,
+        }
+    }
+
+    fn build_query(filter: Option<DynFilterPart>) -> QueryBuilder<'static, Sqlite> {
+        let mut builder = QueryBuilder::new("SELECT * FROM sc_reports");
+        if let Some(f) = filter {
+            builder.push(" WHERE ");
+            f.add_to_query(&mut builder);
+        }
+        builder.push(" ORDER BY reportname");
+        builder
+    }
+
+    pub async fn load_all(filter: Option<DynFilterPart>, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        Self::build_query(filter)
+            .build_query_as()
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Load the user's report with the given name
+    pub async fn load_by_name(userid: i64, name: &str, pool: &Pool<Sqlite>) -> Result<Self> {
+        Self::build_query(Some(
+            CompoundFilter::builder(Op::And)
+                .push(Filter::User(userid))
+                .push(Filter::Name(name.to_string()))
+                .build(),
+        ))
+        .build_query_as()
+        .fetch_one(pool)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    /// The environment that report templates are rendered in. It has no template loader, so
+    /// templates can't access any other templates or files.
+    fn environment(&self) -> Environment<'static> {
+        let mut env = Environment::new();
+        env.set_fuel(Some(RENDER_FUEL));
+        let escape = match self.format {
+            ReportFormat::Html => AutoEscape::Html,
+            ReportFormat::Csv => AutoEscape::None,
+        };
+        env.set_auto_escape_callback(move |_| escape);
+        env.add_filter("csv", csv_field);
+        env.add_filter("idfmt", idfmt);
+        env
+    }
+
+    fn validate(&mut self) -> Result<()> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() {
+            return Err(Error::InvalidStateMissingAttribute("name".to_string()));
+        }
+        if self.template.trim().is_empty() {
+            return Err(Error::InvalidStateMissingAttribute("template".to_string()));
+        }
+        self.filter = self
+            .filter
+            .as_ref()
+            .map(|f| f.trim().to_string())
+            .filter(|f| !f.is_empty());
+        self.environment()
+            .template_from_str(&self.template)
+            .map_err(|e| Error::InvalidValue(format!("the report template is invalid: {e}")))?;
+        Ok(())
+    }
+
+    pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        self.validate()?;
+        let res = sqlx::query(
+            r#"INSERT INTO sc_reports (userid, reportname, reportformat, reportfilter, reporttemplate)
+            VALUES (?, ?, ?, ?, ?)"#,
+        )
+        .bind(self.userid)
+        .bind(&self.name)
+        .bind(self.format)
+        .bind(&self.filter)
+        .bind(&self.template)
+        .execute(pool)
+        .await?;
+        self.id = res.last_insert_rowid();
+        Ok(res)
+    }
+
+    pub async fn update(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        if self.id < 0 {
+            return Err(Error::InvalidOperationObjectNotFound);
+        }
+        self.validate()?;
+        sqlx::query(
+            r#"UPDATE sc_reports SET reportname=?, reportformat=?, reportfilter=?, reporttemplate=?
+            WHERE reportid=?"#,
+        )
+        .bind(&self.name)
+        .bind(self.format)
+        .bind(&self.filter)
+        .bind(&self.template)
+        .bind(self.id)
+        .execute(pool)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    /// Load the samples of the report's user that match the report's filter
+    pub async fn load_samples(&self, pool: &Pool<Sqlite>) -> Result<Vec<Sample>> {
+        let filter = self.filter.as_ref().map(|f| {
+            CompoundFilter::builder(Op::Or)
+                .push(sample::Filter::TaxonNameLike(f.clone()))
+                .push(sample::Filter::Notes(Cmp::Like, f.clone()))
+                .push(sample::Filter::SourceNameLike(f.clone()))
+                .build()
+        });
+        Sample::load_all_user(self.userid, filter, None, pool).await
+    }
+
+    /// Render the report. The template has access to the matching samples as `samples`, the
+    /// report itself as `report` and the time at which it was generated as `generated`.
+    pub async fn render(&self, pool: &Pool<Sqlite>) -> Result<String> {
+        let samples = self.load_samples(pool).await?;
+        let generated = OffsetDateTime::now_utc()
+            .format(&time::format_description::well_known::Rfc3339)
+            .map_err(|e| Error::InvalidValue(e.to_string()))?;
+        self.environment()
+            .render_str(
+                &self.template,
+                context!(samples => samples, report => self, generated => generated),
+            )
+            .map_err(|e| Error::InvalidValue(format!("failed to render the report: {e}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn reports(pool: Pool<Sqlite>) {
+        let all = Sample::load_all_user(1, None, None, &pool).await.unwrap();
+        let mut report = Report::new(
+            " Sample list ".to_string(),
+            ReportFormat::Csv,
+            Some("".to_string()),
+            "{% for s in samples %}{{ s.id | idfmt('S') }},{{ s.taxon.complete_name | csv }}\n{% endfor %}"
+                .to_string(),
+            1,
+        );
+        report.insert(&pool).await.expect("Failed to insert report");
+        assert_eq!(report.name, "Sample list");
+        assert_eq!(report.filter, None);
+        assert_eq!(
+            Report::load_by_name(1, "Sample list", &pool).await.unwrap(),
+            report
+        );
+
+        let output = report.render(&pool).await.expect("Failed to render");
+        assert_eq!(output.lines().count(), all.len());
+        assert!(output.starts_with(&format!("S{:04},", all[0].id)));
+
+        // only the matching samples are included
+        let taxon = all[0].taxon.object().unwrap().complete_name.clone();
+        report.filter = Some(taxon.clone());
+        report.update(&pool).await.expect("Failed to update");
+        let output = report.render(&pool).await.unwrap();
+        assert!(output.lines().count() < all.len());
+        assert!(output.lines().all(|l| l.contains(&taxon)));
+
+        // html reports are escaped
+        let mut html = Report::new(
+            "Html".to_string(),
+            ReportFormat::Html,
+            None,
+            "{{ '<b>' }}".to_string(),
+            1,
+        );
+        html.insert(&pool).await.unwrap();
+        assert_eq!(html.render(&pool).await.unwrap(), "&lt;b&gt;");
+
+        // names are unique for each user
+        let mut dup = Report::new(
+            "Html".to_string(),
+            ReportFormat::Html,
+            None,
+            "x".to_string(),
+            1,
+        );
+        assert!(dup.insert(&pool).await.is_err());
+        dup.userid = 2;
+        assert!(dup.insert(&pool).await.is_ok());
+
+        // templates are validated and can't access other templates
+        let mut bad = Report::new(
+            "Bad".to_string(),
+            ReportFormat::Html,
+            None,
+            "{% for %}".to_string(),
+            1,
+        );
+        assert!(bad.insert(&pool).await.is_err());
+        bad.template = "{% include 'root.html' %}".to_string();
+        bad.insert(&pool).await.unwrap();
+        assert!(bad.render(&pool).await.is_err());
+        // endless templates are aborted
+        bad.template =
+            "{% for i in range(10000) %}{% for j in range(10000) %}{% endfor %}{% endfor %}"
+                .to_string();
+        bad.update(&pool).await.unwrap();
+        assert!(bad.render(&pool).await.is_err());
+        assert_eq!(
+            Report::load_all(Some(Filter::User(1).into()), &pool)
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+}
@@ -1,10 +1,19 @@
 //! Objects to keep track of samples of seeds that were collected or purchased
 use crate::{
     error::{Error, Result},
-    filter::{Cmp, CompoundFilter, DynFilterPart, FilterPart, Op},
-    loadable::{ExternalRef, Loadable},
-    source::Source,
-    taxonomy::Taxon,
+    filter::{
+        Cmp, CompoundFilter, DynFilterPart, FilterPart, LimitSpec, ListQuery, Op, SortOrder,
+        SortSpec, SortSpecs,
+    },
+    grade::QualityGrade,
+    loadable::{ExternalRef, Loadable, PartialUpdate},
+    organization::{push_accessible_condition, Owned},
+    plugin,
+    scope::UserScope,
+    source::{HabitatType, LightCondition, SoilMoisture, Source},
+    stock,
+    taxonomy::{Rank, Taxon},
+    try_get_uuid,
     user::User,
 };
 use async_trait::async_trait;
@@ -14,7 +23,9 @@ use sqlx::{
     FromRow, Pool, QueryBuilder, Row, Sqlite,
 };
 use std::sync::Arc;
-use strum_macros::Display;
+use strum_macros::{Display, EnumString};
+use time::{Date, OffsetDateTime};
+use uuid::Uuid;
 
 #[derive(Clone, Deserialize, Serialize, Debug, sqlx::Type, PartialEq, Display)]
 #[repr(i32)]
@@ -23,9 +34,52 @@ pub enum Certainty {
     Uncertain = 2,
 }
 
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+/// Details about a sample that was bought from a vendor rather than collected
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Purchase {
+    pub vendor: String,
+    pub lot: Option<String>,
+    #[serde(default, with = "iso_date::option")]
+    pub date: Option<Date>,
+    pub price: Option<f64>,
+    /// where the vendor's seed was originally collected, as certified by the vendor
+    pub certified_origin: Option<String>,
+}
+
+impl Purchase {
+    pub fn new(vendor: String) -> Self {
+        Self {
+            vendor,
+            lot: None,
+            date: None,
+            price: None,
+            certified_origin: None,
+        }
+    }
+
+    /// Parse a purchase date in the format `YYYY-MM-DD`
+    pub fn parse_date(value: &str) -> Result<Date> {
+        crate::parse_date(value)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.vendor.trim().is_empty() {
+            return Err(Error::InvalidValue("the vendor is empty".to_string()));
+        }
+        if self.price.is_some_and(|p| p < 0.0 || !p.is_finite()) {
+            return Err(Error::InvalidValue("the price is not valid".to_string()));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
 pub struct Sample {
     pub id: i64,
+    /// an identifier that is used for the sample in URLs and the API instead of the numeric id
+    pub uuid: Uuid,
     pub user: ExternalRef<User>,
     pub taxon: ExternalRef<Taxon>,
     pub source: ExternalRef<Source>,
@@ -34,6 +88,15 @@ pub struct Sample {
     pub year: Option<u32>,
     pub notes: Option<String>,
     pub certainty: Certainty,
+    pub version: i64,
+    /// the organization that shares ownership of the sample, if any
+    pub orgid: Option<i64>,
+    /// the purchase details if the sample was bought rather than collected
+    pub purchase: Option<Purchase>,
+    /// the collection trip that the sample was collected on, if any
+    pub trip: Option<i64>,
+    /// the grade of the owner's quality grading scale that the sample was given, if any
+    pub grade: Option<QualityGrade>,
 }
 
 impl From<Filter> for DynFilterPart {
@@ -45,13 +108,47 @@ impl From<Filter> for DynFilterPart {
 #[derive(Clone)]
 pub enum Filter {
     Id(Cmp, i64),
+    Uuid(Uuid),
     IdNotIn(Vec<i64>),
     SourceId(Cmp, i64),
     SourceNameLike(String),
     TaxonId(Cmp, i64),
     TaxonNameLike(String),
     UserId(i64),
+    /// samples that are owned by the given user or by one of the user's organizations
+    Accessible(i64),
+    /// samples that are owned by the given organization
+    OrgId(i64),
     Notes(Cmp, String),
+    /// samples of any taxon that belongs to the family with the given name
+    Family(String),
+    /// samples of any taxon that belongs to the order with the given name
+    Order(String),
+    /// samples of the given taxon or of any taxon below it in the ITIS hierarchy
+    AncestorTsn(i64),
+    /// samples that have been flagged for review, optionally only those flagged for the given
+    /// reason
+    Flagged(Option<String>),
+    /// samples that were purchased (`true`) or collected (`false`)
+    Purchased(bool),
+    VendorLike(String),
+    /// samples whose label has not been printed since they were created or last modified
+    LabelPending,
+    /// samples that were collected on the given trip
+    TripId(i64),
+    /// samples collected at a source with the given habitat type
+    SourceHabitat(HabitatType),
+    /// samples collected at a source with the given soil moisture class
+    SourceMoisture(SoilMoisture),
+    /// samples collected at a source with the given light conditions
+    SourceLight(LightCondition),
+    /// samples whose quantity has not been recorded
+    QuantityMissing,
+    Certainty(Certainty),
+    /// samples whose quantity is below the given user's threshold for them
+    LowStock(i64),
+    /// samples with the quality grade with the given id, or samples without a grade
+    Grade(Option<i64>),
 }
 
 #[async_trait]
@@ -84,6 +181,7 @@ impl FilterPart for Filter {
     fn add_to_query(&self, builder: &mut sqlx::QueryBuilder<sqlx::Sqlite>) {
         match self {
             Self::Id(cmp, id) => _ = builder.push("sampleid").push(cmp).push_bind(*id),
+            Self::Uuid(uuid) => _ = builder.push("sampleuuid=").push_bind(uuid.to_string()),
             Self::IdNotIn(list) => {
                 _ = builder.push("sampleid NOT IN (");
                 let mut sep = builder.separated(", ");
@@ -110,6 +208,10 @@ impl FilterPart for Filter {
                 }
             }
             Self::UserId(id) => _ = builder.push("userid=").push_bind(*id),
+            Self::Accessible(id) => {
+                push_accessible_condition(builder, "userid", "sampleorgid", *id)
+            }
+            Self::OrgId(id) => _ = builder.push("sampleorgid=").push_bind(*id),
             Self::Notes(cmp, s) => _ = builder.push("notes").push(cmp).push_bind(format!("%{s}%")),
             Self::SourceNameLike(s) => {
                 if !s.is_empty() {
@@ -118,89 +220,439 @@ impl FilterPart for Filter {
                     builder.push_bind(wildcard);
                 }
             }
+            Self::Flagged(reason) => {
+                builder.push(" sampleid IN (SELECT sampleid FROM sc_sample_flags");
+                if let Some(reason) = reason {
+                    builder.push(" WHERE flagreason=").push_bind(reason.clone());
+                }
+                builder.push(") ");
+            }
+            Self::LabelPending => _ = builder.push(
+                " sampleid IN (SELECT sampleid FROM sc_label_queue WHERE labelprinted IS NULL) ",
+            ),
+            Self::TripId(id) => _ = builder.push(" tripid=").push_bind(*id),
+            Self::Purchased(true) => _ = builder.push(" purchasevendor IS NOT NULL "),
+            Self::Purchased(false) => _ = builder.push(" purchasevendor IS NULL "),
+            Self::VendorLike(s) => {
+                _ = builder
+                    .push(" purchasevendor LIKE ")
+                    .push_bind(format!("%{s}%"))
+            }
+            Self::Family(name) => push_ancestor_name(builder, Rank::Family, name),
+            Self::Order(name) => push_ancestor_name(builder, Rank::Order, name),
+            Self::AncestorTsn(tsn) => {
+                builder.push(" tsn IN (SELECT H.TSN FROM hierarchy P ");
+                push_descendant_join(builder);
+                builder.push(" WHERE P.TSN=").push_bind(*tsn).push(") ");
+            }
+            Self::SourceHabitat(habitat) => {
+                push_source_condition(builder, "srchabitat", *habitat as i64)
+            }
+            Self::SourceMoisture(moisture) => {
+                push_source_condition(builder, "srcmoisture", *moisture as i64)
+            }
+            Self::SourceLight(light) => push_source_condition(builder, "srclight", *light as i64),
+            Self::QuantityMissing => _ = builder.push(" quantity IS NULL "),
+            Self::Certainty(certainty) => {
+                _ = builder.push(" certainty=").push_bind(certainty.clone())
+            }
+            Self::LowStock(userid) => stock::push_low_stock_condition(builder, *userid),
+            Self::Grade(Some(id)) => _ = builder.push(" gradeid=").push_bind(*id),
+            Self::Grade(None) => _ = builder.push(" gradeid IS NULL "),
         };
     }
 }
 
+fn push_source_condition(builder: &mut QueryBuilder<Sqlite>, column: &str, value: i64) {
+    builder
+        .push(format!(
+            " srcid IN (SELECT srcid FROM sc_sources WHERE {column}="
+        ))
+        .push_bind(value)
+        .push(") ");
+}
+
+/// Join the hierarchy table `P` to the hierarchy entries `H` of the taxon itself and all of its
+/// descendants. The hierarchy string of a taxon lists the tsn of every ancestor of the taxon and the
+/// taxon itself separated by dashes, so the strings of its descendants all start with its own
+/// string followed by a dash, and '.' is the character that sorts right after the dash. Comparing
+/// the strings this way can use the index on the hierarchy string.
+pub(crate) fn push_descendant_join(builder: &mut QueryBuilder<Sqlite>) {
+    builder.push(
+        r#" INNER JOIN hierarchy H ON H.hierarchy_string >= P.hierarchy_string
+            AND H.hierarchy_string < P.hierarchy_string || '.' "#,
+    );
+}
+
+fn push_ancestor_name(builder: &mut QueryBuilder<Sqlite>, rank: Rank, name: &str) {
+    builder.push(
+        r#" tsn IN (SELECT H.TSN FROM taxonomic_units A
+            INNER JOIN hierarchy P ON P.TSN=A.tsn "#,
+    );
+    push_descendant_join(builder);
+    builder.push(" WHERE A.rank_id=");
+    builder.push_bind(rank as i64);
+    builder.push(" AND A.unit_name1 LIKE ");
+    builder.push_bind(name.to_string());
+    builder.push(") ");
+}
+
+/// A summary of all of the samples of a single taxon
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct TaxonGroup {
+    pub taxon: Taxon,
+    /// the number of samples of this taxon
+    pub nsamples: i64,
+    /// the total quantity of all samples of this taxon that have a known quantity
+    pub quantity: Option<i64>,
+    /// the earliest collection year of all samples of this taxon
+    pub first_year: Option<u32>,
+    /// the latest collection year of all samples of this taxon
+    pub last_year: Option<u32>,
+}
+
+impl FromRow<'_, SqliteRow> for TaxonGroup {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            taxon: Taxon::from_row(row)?,
+            nsamples: row.try_get("nsamples")?,
+            quantity: row.try_get("totalquantity")?,
+            first_year: row.try_get("firstyear")?,
+            last_year: row.try_get("lastyear")?,
+        })
+    }
+}
+
+/// A flag that marks a sample as needing review, e.g. because its identification still needs to be
+/// confirmed. A sample can have several flags, but only one for each reason.
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct SampleFlag {
+    #[sqlx(rename = "flagid")]
+    pub id: i64,
+    pub sampleid: i64,
+    #[sqlx(rename = "flagreason")]
+    pub reason: String,
+    #[sqlx(rename = "flagcreated")]
+    pub created: Option<OffsetDateTime>,
+}
+
+impl SampleFlag {
+    /// Reasons for flagging a sample that are offered as suggestions. Any other reason can be
+    /// used as well.
+    pub const COMMON_REASONS: [&'static str; 4] = [
+        "Needs ID confirmation",
+        "Low viability suspected",
+        "Needs cleaning",
+        "Quantity needs checking",
+    ];
+
+    /// Load the flags of all of the user's samples
+    pub async fn load_all_user(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<SampleFlag>> {
+        Ok(sqlx::query_as(
+            r#"SELECT F.* FROM sc_sample_flags F
+            INNER JOIN sc_samples S ON S.sampleid=F.sampleid
+            WHERE S.userid=? ORDER BY F.sampleid, F.flagid"#,
+        )
+        .bind(userid)
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// The distinct reasons that the user's samples are currently flagged for
+    pub async fn reasons(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<String>> {
+        Ok(sqlx::query_scalar(
+            r#"SELECT DISTINCT F.flagreason FROM sc_sample_flags F
+            INNER JOIN sc_samples S ON S.sampleid=F.sampleid
+            WHERE S.userid=? ORDER BY F.flagreason"#,
+        )
+        .bind(userid)
+        .fetch_all(pool)
+        .await?)
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<SampleFlag> {
+        Ok(
+            sqlx::query_as("SELECT * FROM sc_sample_flags WHERE flagid=?")
+                .bind(id)
+                .fetch_one(pool)
+                .await?,
+        )
+    }
+
+    pub async fn delete(&self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        sqlx::query("DELETE FROM sc_sample_flags WHERE flagid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+}
+
+/// A new value for a single field of a sample, used to modify a sample with
+/// [`PartialUpdate::update_fields`] without having to save all of its other fields as well
+#[derive(Debug, Clone, PartialEq)]
+pub enum SampleField {
+    Taxon(i64),
+    Source(i64),
+    Quantity(Option<i64>),
+    Notes(Option<String>),
+    /// the collection date. A month can only be given together with a year.
+    Date {
+        month: Option<u32>,
+        year: Option<u32>,
+    },
+    Certainty(Certainty),
+}
+
+impl SampleField {
+    /// Check that the value is valid for the field, returning the value that should be stored
+    fn validate(&self) -> Result<Self> {
+        match self {
+            Self::Quantity(Some(q)) if *q < 0 => Err(Error::InvalidValue(
+                "the quantity cannot be negative".to_string(),
+            )),
+            Self::Notes(notes) => Ok(Self::Notes(
+                notes
+                    .as_ref()
+                    .map(|n| n.trim().to_string())
+                    .filter(|n| !n.is_empty()),
+            )),
+            Self::Date { month: Some(m), .. } if !(1..=12).contains(m) => {
+                Err(Error::InvalidValue(format!("{m} is not a valid month")))
+            }
+            Self::Date {
+                month: Some(_),
+                year: None,
+            } => Err(Error::InvalidValue(
+                "a month requires a year as well".to_string(),
+            )),
+            field => Ok(field.clone()),
+        }
+    }
+
+    fn push_assignment(&self, builder: &mut QueryBuilder<Sqlite>) {
+        match self {
+            Self::Taxon(id) => _ = builder.push("tsn=").push_bind(*id),
+            Self::Source(id) => _ = builder.push("srcid=").push_bind(*id),
+            Self::Quantity(quantity) => _ = builder.push("quantity=").push_bind(*quantity),
+            Self::Notes(notes) => _ = builder.push("notes=").push_bind(notes.clone()),
+            Self::Date { month, year } => {
+                builder.push("month=").push_bind(*month);
+                builder.push(", year=").push_bind(*year);
+            }
+            Self::Certainty(certainty) => {
+                _ = builder.push("certainty=").push_bind(certainty.clone())
+            }
+        }
+    }
+
+    fn apply(self, sample: &mut Sample) {
+        match self {
+            Self::Taxon(id) => sample.taxon = ExternalRef::Stub(id),
+            Self::Source(id) => sample.source = ExternalRef::Stub(id),
+            Self::Quantity(quantity) => sample.quantity = quantity,
+            Self::Notes(notes) => sample.notes = notes,
+            Self::Date { month, year } => {
+                sample.month = month;
+                sample.year = year;
+            }
+            Self::Certainty(certainty) => sample.certainty = certainty,
+        }
+    }
+}
+
+#[async_trait]
+impl PartialUpdate for Sample {
+    type Field = SampleField;
+
+    async fn update_fields(
+        &mut self,
+        fields: &[SampleField],
+        pool: &Pool<Sqlite>,
+    ) -> Result<SqliteQueryResult> {
+        if self.id < 0 {
+            return Err(Error::InvalidOperationObjectNotFound);
+        }
+        if fields.is_empty() {
+            return Err(Error::InvalidValue("no fields to update".to_string()));
+        }
+        let mut values = Vec::with_capacity(fields.len());
+        for field in fields {
+            if values
+                .iter()
+                .any(|v| std::mem::discriminant(v) == std::mem::discriminant(field))
+            {
+                return Err(Error::InvalidValue(
+                    "a field was given more than once".to_string(),
+                ));
+            }
+            values.push(field.validate()?);
+        }
+        let mut builder = QueryBuilder::new("UPDATE sc_samples SET ");
+        for value in &values {
+            value.push_assignment(&mut builder);
+            builder.push(", ");
+        }
+        builder
+            .push("sampleversion=sampleversion+1 WHERE sampleid=")
+            .push_bind(self.id)
+            .push(" AND sampleversion=")
+            .push_bind(self.version);
+        let res = builder.build().execute(pool).await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::DatabaseVersionConflict(self.version));
+        }
+        for value in values {
+            value.apply(self);
+        }
+        self.version += 1;
+        self.queue_label(pool).await?;
+        plugin::after_sample_save(self, pool).await?;
+        Ok(res)
+    }
+}
+
+/// The fields that samples can be sorted by. The names are used to specify the sort in query
+/// parameters and on the command line.
+#[derive(Clone, Debug, PartialEq, Display, EnumString)]
 pub enum Sort {
+    #[strum(serialize = "id")]
     Id,
+    #[strum(serialize = "name")]
     TaxonName,
+    #[strum(serialize = "taxon")]
     TaxonSequence,
+    #[strum(serialize = "srcid")]
     SourceId,
+    #[strum(serialize = "source")]
     SourceName,
+    #[strum(serialize = "date")]
+    CollectionDate,
+    #[strum(serialize = "qty")]
+    Quantity,
+    #[strum(serialize = "grade")]
+    Grade,
 }
 
-impl Sample {
-    fn build_query(
-        filter: Option<DynFilterPart>,
-        sort: Option<Sort>,
-    ) -> QueryBuilder<'static, Sqlite> {
-        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new("SELECT * FROM vsamples");
-        if let Some(f) = filter {
-            builder.push(" WHERE ");
-            f.add_to_query(&mut builder);
-        }
-        builder.push(" ORDER BY ");
-        let s = match sort.unwrap_or(Sort::TaxonSequence) {
+impl Sort {
+    fn column(&self) -> &'static str {
+        match self {
             Sort::Id => "sampleid",
             Sort::TaxonName => "complete_name",
             Sort::TaxonSequence => "seq",
             Sort::SourceId => "srcid",
             Sort::SourceName => "srcname",
-        };
-        builder.push(s);
-        builder
+            // samples without a month sort before the samples of the same year with a month
+            Sort::CollectionDate => "year * 100 + IFNULL(month, 0)",
+            Sort::Quantity => "quantity",
+            // ungraded samples sort after the graded ones in either direction
+            Sort::Grade => "graderank IS NULL, graderank",
+        }
     }
+}
 
-    fn build_count(filter: Option<DynFilterPart>) -> QueryBuilder<'static, Sqlite> {
-        let mut builder: QueryBuilder<Sqlite> =
-            QueryBuilder::new("SELECT COUNT(*) as nsamples FROM vsamples");
-        if let Some(f) = filter {
-            builder.push(" WHERE ");
-            f.add_to_query(&mut builder);
-        }
+impl Sample {
+    fn list_query(filter: Option<DynFilterPart>) -> ListQuery {
+        ListQuery::new("*", "vsamples", filter)
+    }
+
+    fn build_query(
+        filter: Option<DynFilterPart>,
+        sort: Option<SortSpecs<Sort>>,
+    ) -> QueryBuilder<'static, Sqlite> {
+        let mut builder = Self::list_query(filter).select();
+        sort.unwrap_or(Sort::TaxonSequence.into())
+            .push_order_by(&mut builder, Sort::column);
         builder
     }
 
+    /// Load the samples that the user has access to, i.e. the user's own samples and the samples
+    /// of the user's organizations
     pub async fn load_all_user(
         userid: i64,
         filter: Option<DynFilterPart>,
-        sort: Option<Sort>,
+        sort: Option<SortSpecs<Sort>>,
         pool: &Pool<Sqlite>,
     ) -> Result<Vec<Sample>> {
-        let mut fbuilder = CompoundFilter::builder(Op::And).push(Filter::UserId(userid));
+        UserScope::new(userid).samples(filter, sort, pool).await
+    }
+
+    /// Load a summary of the samples that the user has access to with one entry per taxon, sorted
+    /// taxonomically
+    pub async fn load_grouped_by_taxon(
+        userid: i64,
+        filter: Option<DynFilterPart>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<TaxonGroup>> {
+        let mut fbuilder = CompoundFilter::builder(Op::And).push(Filter::Accessible(userid));
         if let Some(f) = filter {
             fbuilder = fbuilder.push(f);
         }
-        let newfilter = fbuilder.build();
-        let mut builder = Self::build_query(Some(newfilter), sort);
+        let mut builder: QueryBuilder<Sqlite> = QueryBuilder::new(
+            r#"SELECT tsn, parentid, complete_name, unit_name1, unit_name2, unit_name3, seq, cnames,
+            COUNT(sampleid) AS nsamples, SUM(quantity) AS totalquantity,
+            MIN(year) AS firstyear, MAX(year) AS lastyear
+            FROM vsamples WHERE "#,
+        );
+        fbuilder.build().add_to_query(&mut builder);
+        builder.push(" GROUP BY tsn ORDER BY seq");
         Ok(builder.build_query_as().fetch_all(pool).await?)
     }
 
-    pub async fn load_all(
+    /// Load all samples that were collected from the given source, including the samples of other
+    /// users. The caller has to check the user's permissions for each of them.
+    pub async fn load_source(srcid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Sample>> {
+        Self::load_all(Some(Filter::SourceId(Cmp::Equal, srcid).into()), None, pool).await
+    }
+
+    /// Load all samples that match `filter`, regardless of who they belong to. Samples are listed
+    /// on behalf of a user with a [UserScope].
+    pub(crate) async fn load_all(
         filter: Option<DynFilterPart>,
-        sort: Option<Sort>,
+        sort: Option<SortSpecs<Sort>>,
         pool: &Pool<Sqlite>,
     ) -> Result<Vec<Sample>> {
         let mut builder = Self::build_query(filter, sort);
         Ok(builder.build_query_as().fetch_all(pool).await?)
     }
 
+    /// Load a single page of the samples that match `filter`. The samples are sorted by id after
+    /// the given sort order, so that the pages don't overlap.
+    pub async fn load_page(
+        filter: Option<DynFilterPart>,
+        sort: Option<SortSpecs<Sort>>,
+        limit: LimitSpec,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Sample>> {
+        let sort = sort
+            .unwrap_or(Sort::TaxonSequence.into())
+            .then(SortSpec::new(Sort::Id, SortOrder::Ascending));
+        let mut builder = Self::build_query(filter, Some(sort));
+        limit.push_to(&mut builder);
+        Ok(builder.build_query_as().fetch_all(pool).await?)
+    }
+
+    /// Load the sample with the given [uuid](Sample::uuid)
+    pub async fn load_uuid(uuid: Uuid, pool: &Pool<Sqlite>) -> Result<Self> {
+        let mut builder = Self::build_query(Some(Filter::Uuid(uuid).into()), None);
+        Ok(builder.build_query_as().fetch_one(pool).await?)
+    }
+
+    /// The number of samples that match `filter`
     pub async fn count(filter: Option<DynFilterPart>, pool: &Pool<Sqlite>) -> Result<i64> {
-        let mut builder = Self::build_count(filter);
-        builder
-            .build()
-            .fetch_one(pool)
-            .await?
-            .try_get("nsamples")
-            .map_err(|e| e.into())
+        Self::list_query(filter).fetch_count(pool).await
     }
 
     pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
         if self.id != -1 {
             return Err(Error::InvalidOperationObjectAlreadyExists(self.id));
         }
-        sqlx::query("INSERT INTO sc_samples (tsn, userid, srcid, month, year, quantity, notes, certainty) VALUES (?, ?, ?, ?, ?, ?, ?, ?)")
+        plugin::before_sample_save(self, pool).await?;
+        if let Some(purchase) = &self.purchase {
+            purchase.validate()?;
+        }
+        let purchase = self.purchase.as_ref();
+        let res = sqlx::query("INSERT INTO sc_samples (tsn, userid, srcid, month, year, quantity, notes, certainty, sampleorgid, purchasevendor, purchaselot, purchasedate, purchaseprice, purchaseorigin, tripid, sampleuuid, gradeid) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
         .bind(self.taxon.id())
         .bind(self.user.id())
         .bind(self.source.id())
@@ -209,13 +661,26 @@ impl Sample {
         .bind(self.quantity)
         .bind(&self.notes)
         .bind(&self.certainty)
+        .bind(self.orgid)
+        .bind(purchase.map(|p| &p.vendor))
+        .bind(purchase.and_then(|p| p.lot.as_ref()))
+        .bind(purchase.and_then(|p| p.date))
+        .bind(purchase.and_then(|p| p.price))
+        .bind(purchase.and_then(|p| p.certified_origin.as_ref()))
+        .bind(self.trip)
+        .bind(self.uuid.to_string())
+        .bind(self.grade.as_ref().map(|g| g.id))
         .execute(pool)
-        .await
-        .map(|r| { self.id = r.last_insert_rowid(); r})
-        .map_err(|e| e.into())
+        .await?;
+        self.id = res.last_insert_rowid();
+        self.queue_label(pool).await?;
+        plugin::after_sample_save(self, pool).await?;
+        Ok(res)
     }
 
-    pub async fn update(&self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+    /// Save the changes to this sample to the database. If the sample has been modified in the
+    /// database since it was loaded, this fails with [Error::DatabaseVersionConflict].
+    pub async fn update(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
         if self.id < 0 {
             return Err(Error::InvalidOperationObjectNotFound);
         }
@@ -225,8 +690,13 @@ impl Sample {
         if self.source.id() < 0 {
             return Err(Error::InvalidStateMissingAttribute("source".to_string()));
         }
+        plugin::before_sample_save(self, pool).await?;
+        if let Some(purchase) = &self.purchase {
+            purchase.validate()?;
+        }
 
-        sqlx::query("Update sc_samples SET tsn=?, srcid=?, month=?, year=?, quantity=?, notes=?, certainty=? WHERE sampleid=?")
+        let purchase = self.purchase.as_ref();
+        let res = sqlx::query("Update sc_samples SET tsn=?, srcid=?, month=?, year=?, quantity=?, notes=?, certainty=?, sampleorgid=?, purchasevendor=?, purchaselot=?, purchasedate=?, purchaseprice=?, purchaseorigin=?, tripid=?, gradeid=?, sampleversion=sampleversion+1 WHERE sampleid=? AND sampleversion=?")
             .bind(self.taxon.id())
             .bind(self.source.id())
             .bind(self.month)
@@ -234,9 +704,116 @@ impl Sample {
             .bind(self.quantity)
             .bind(&self.notes)
             .bind(&self.certainty)
+            .bind(self.orgid)
+            .bind(purchase.map(|p| &p.vendor))
+            .bind(purchase.and_then(|p| p.lot.as_ref()))
+            .bind(purchase.and_then(|p| p.date))
+            .bind(purchase.and_then(|p| p.price))
+            .bind(purchase.and_then(|p| p.certified_origin.as_ref()))
+            .bind(self.trip)
+            .bind(self.grade.as_ref().map(|g| g.id))
             .bind(self.id)
+            .bind(self.version)
             .execute(pool)
-            .await.map_err(|e| e.into())
+            .await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::DatabaseVersionConflict(self.version));
+        }
+        self.version += 1;
+        self.queue_label(pool).await?;
+        plugin::after_sample_save(self, pool).await?;
+        Ok(res)
+    }
+
+    /// Add this sample to the queue of labels that need to be printed. If its label was already
+    /// printed, it is queued again.
+    pub async fn queue_label(&self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        sqlx::query(
+            r#"INSERT INTO sc_label_queue (sampleid) VALUES (?) ON CONFLICT(sampleid)
+            DO UPDATE SET labelqueued=CURRENT_TIMESTAMP, labelprinted=NULL"#,
+        )
+        .bind(self.id)
+        .execute(pool)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    /// Record that the labels of the given samples have been printed, removing them from the
+    /// queue of pending labels
+    pub async fn mark_labels_printed(ids: &[i64], pool: &Pool<Sqlite>) -> Result<u64> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        let mut builder = QueryBuilder::new(
+            "UPDATE sc_label_queue SET labelprinted=CURRENT_TIMESTAMP WHERE labelprinted IS NULL AND sampleid IN (",
+        );
+        let mut sep = builder.separated(", ");
+        for id in ids {
+            sep.push_bind(*id);
+        }
+        builder.push(")");
+        Ok(builder.build().execute(pool).await?.rows_affected())
+    }
+
+    /// Give the sample a grade of its owner's grading scale, or remove its grade. Like flags, the
+    /// grade can be changed without saving the other fields of the sample.
+    pub async fn set_grade(
+        &mut self,
+        grade: Option<&QualityGrade>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<()> {
+        if grade.is_some_and(|g| g.userid != self.user.id()) {
+            return Err(Error::InvalidValue(
+                "The grade is not part of the grading scale of the sample's owner".to_string(),
+            ));
+        }
+        sqlx::query("UPDATE sc_samples SET gradeid=? WHERE sampleid=?")
+            .bind(grade.map(|g| g.id))
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        self.grade = grade.cloned();
+        self.queue_label(pool).await?;
+        Ok(())
+    }
+
+    pub async fn load_flags(&self, pool: &Pool<Sqlite>) -> Result<Vec<SampleFlag>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM sc_sample_flags WHERE sampleid=? ORDER BY flagid")
+                .bind(self.id)
+                .fetch_all(pool)
+                .await?,
+        )
+    }
+
+    /// Flag this sample for review for the given reason. If the sample is already flagged for
+    /// this reason, the existing flag is returned.
+    pub async fn flag(&self, reason: &str, pool: &Pool<Sqlite>) -> Result<SampleFlag> {
+        let reason = reason.trim();
+        if reason.is_empty() {
+            return Err(Error::InvalidValue("the flag reason is empty".to_string()));
+        }
+        sqlx::query("INSERT OR IGNORE INTO sc_sample_flags (sampleid, flagreason) VALUES (?, ?)")
+            .bind(self.id)
+            .bind(reason)
+            .execute(pool)
+            .await?;
+        Ok(
+            sqlx::query_as("SELECT * FROM sc_sample_flags WHERE sampleid=? AND flagreason=?")
+                .bind(self.id)
+                .bind(reason)
+                .fetch_one(pool)
+                .await?,
+        )
+    }
+
+    /// Remove all of the review flags from this sample
+    pub async fn clear_flags(&self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        sqlx::query("DELETE FROM sc_sample_flags WHERE sampleid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.into())
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -252,6 +829,7 @@ impl Sample {
     ) -> Self {
         Self {
             id: -1,
+            uuid: Uuid::new_v4(),
             user: ExternalRef::Stub(userid),
             taxon: ExternalRef::Stub(taxonid),
             source: ExternalRef::Stub(sourceid),
@@ -260,14 +838,30 @@ impl Sample {
             year,
             notes,
             certainty,
+            version: 1,
+            orgid: None,
+            purchase: None,
+            trip: None,
+            grade: None,
         }
     }
 }
 
+impl Owned for Sample {
+    fn owner(&self) -> i64 {
+        self.user.id()
+    }
+
+    fn organization(&self) -> Option<i64> {
+        self.orgid
+    }
+}
+
 impl FromRow<'_, SqliteRow> for Sample {
     fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
         Ok(Self {
             id: row.try_get("sampleid")?,
+            uuid: try_get_uuid(row, "sampleuuid")?,
             user: FromRow::from_row(row)?,
             taxon: FromRow::from_row(row)?,
             source: FromRow::from_row(row)?,
@@ -276,6 +870,20 @@ impl FromRow<'_, SqliteRow> for Sample {
             year: row.try_get("year").unwrap_or(None),
             notes: row.try_get("notes").unwrap_or(None),
             certainty: row.try_get("certainty").unwrap_or(Certainty::Uncertain),
+            version: row.try_get("sampleversion")?,
+            orgid: row.try_get("sampleorgid").unwrap_or(None),
+            purchase: match row.try_get("purchasevendor").unwrap_or(None) {
+                Some(vendor) => Some(Purchase {
+                    vendor,
+                    lot: row.try_get("purchaselot")?,
+                    date: row.try_get("purchasedate")?,
+                    price: row.try_get("purchaseprice")?,
+                    certified_origin: row.try_get("purchaseorigin")?,
+                }),
+                None => None,
+            },
+            trip: row.try_get("tripid").unwrap_or(None),
+            grade: QualityGrade::from_sample_row(row)?,
         })
     }
 }
@@ -283,6 +891,7 @@ impl FromRow<'_, SqliteRow> for Sample {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::project::allocation::{self, Allocation};
     use test_log::test;
 
     #[test(sqlx::test(
@@ -290,6 +899,7 @@ mod tests {
         fixtures(path = "../../db/fixtures", scripts("users", "sources", "taxa"))
     ))]
     async fn insert_samples(pool: Pool<Sqlite>) {
+        #[allow(clippy::too_many_arguments)]
         async fn check(
             pool: &Pool<Sqlite>,
             taxon: i64,
@@ -309,6 +919,14 @@ mod tests {
                 .await
                 .expect("Failed to load sample from database");
             assert_eq!(sample.id, loaded.id);
+            assert_eq!(sample.uuid, loaded.uuid);
+            assert_eq!(
+                Sample::load_uuid(sample.uuid, pool)
+                    .await
+                    .expect("Failed to load sample by uuid")
+                    .id,
+                sample.id
+            );
             assert_eq!(sample.user, loaded.user);
             assert_eq!(sample.taxon.id(), loaded.taxon.id());
             assert_eq!(sample.source.id(), loaded.source.id());
@@ -343,4 +961,642 @@ mod tests {
         )
         .await;
     }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn purchased_samples(pool: Pool<Sqlite>) {
+        let mut sample = Sample::new(
+            40683,
+            1,
+            1,
+            None,
+            Some(2024),
+            Some(1000),
+            None,
+            Certainty::Certain,
+        );
+        sample.purchase = Some(Purchase {
+            vendor: "Prairie Moon".to_string(),
+            lot: Some("L-1234".to_string()),
+            date: Some(Purchase::parse_date("2024-02-29").expect("Failed to parse date")),
+            price: Some(4.5),
+            certified_origin: Some("Dane County, WI".to_string()),
+        });
+        sample.insert(&pool).await.expect("Failed to insert sample");
+        let mut loaded = Sample::load(sample.id, &pool)
+            .await
+            .expect("Failed to load sample");
+        assert_eq!(loaded.purchase, sample.purchase);
+
+        let ids = |samples: Vec<Sample>| samples.iter().map(|s| s.id).collect::<Vec<_>>();
+        let purchased = Sample::load_all_user(1, Some(Filter::Purchased(true).into()), None, &pool)
+            .await
+            .unwrap();
+        assert_eq!(ids(purchased), [sample.id]);
+        let collected = Sample::load_all_user(
+            1,
+            Some(Filter::Purchased(false).into()),
+            Some(Sort::Id.into()),
+            &pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(ids(collected), [1, 2, 3]);
+        let vendor = Sample::load_all_user(
+            1,
+            Some(Filter::VendorLike("moon".into()).into()),
+            None,
+            &pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(ids(vendor), [sample.id]);
+
+        loaded.purchase = Some(Purchase::new(" ".to_string()));
+        assert!(matches!(
+            loaded.update(&pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        loaded.purchase = None;
+        loaded.update(&pool).await.expect("Failed to update sample");
+        let loaded = Sample::load(sample.id, &pool)
+            .await
+            .expect("Failed to load sample");
+        assert_eq!(loaded.purchase, None);
+        assert!(Purchase::parse_date("2024-02-30").is_err());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn update_sample_version_conflict(pool: Pool<Sqlite>) {
+        let mut sample1 = Sample::load(1, &pool).await.expect("Failed to load sample");
+        let mut sample2 = Sample::load(1, &pool).await.expect("Failed to load sample");
+        assert_eq!(sample1.version, sample2.version);
+
+        sample1.quantity = Some(42);
+        sample1
+            .update(&pool)
+            .await
+            .expect("Failed to update sample");
+        assert_eq!(sample1.version, sample2.version + 1);
+
+        // sample2 is now stale and should not overwrite the changes from sample1
+        sample2.notes = Some("conflicting change".to_string());
+        let res = sample2.update(&pool).await;
+        assert!(matches!(res, Err(Error::DatabaseVersionConflict(_))));
+        let loaded = Sample::load(1, &pool).await.expect("Failed to load sample");
+        assert_eq!(loaded, sample1);
+
+        // after refreshing the version, the update should succeed
+        sample2.version = loaded.version;
+        sample2
+            .update(&pool)
+            .await
+            .expect("Failed to update sample");
+        let loaded = Sample::load(1, &pool).await.expect("Failed to load sample");
+        assert_eq!(loaded, sample2);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn group_samples_by_taxon(pool: Pool<Sqlite>) {
+        let groups = Sample::load_grouped_by_taxon(1, None, &pool)
+            .await
+            .expect("Failed to load sample groups");
+        assert_eq!(groups.len(), 2);
+        let elymus = groups
+            .iter()
+            .find(|g| g.taxon.id == 40683)
+            .expect("No group for Elymus canadensis");
+        assert_eq!(elymus.nsamples, 2);
+        assert_eq!(elymus.quantity, Some(100));
+        assert_eq!(elymus.first_year, Some(2023));
+        assert_eq!(elymus.last_year, Some(2023));
+        let sisyrinchium = groups
+            .iter()
+            .find(|g| g.taxon.id == 43254)
+            .expect("No group for Sisyrinchium campestre");
+        assert_eq!(sisyrinchium.nsamples, 1);
+        assert_eq!(sisyrinchium.quantity, None);
+        assert_eq!(sisyrinchium.first_year, Some(2022));
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn filter_by_ancestor(pool: Pool<Sqlite>) {
+        sqlx::query(
+            r#"INSERT INTO hierarchy (hierarchy_string, TSN, Parent_TSN, level, ChildrenCount)
+            VALUES ("202422", 202422, NULL, 0, 1),
+                   ("202422-846542", 846542, 202422, 1, 2),
+                   ("202422-846542-846620", 846620, 846542, 2, 2),
+                   ("202422-846542-846620-40351", 40351, 846620, 3, 1),
+                   ("202422-846542-846620-40351-40677", 40677, 40351, 4, 1),
+                   ("202422-846542-846620-40351-40677-40683", 40683, 40677, 5, 0),
+                   ("202422-846542-897479", 897479, 846542, 2, 1),
+                   ("202422-846542-897479-43190", 43190, 897479, 3, 1),
+                   ("202422-846542-897479-43190-43237", 43237, 43190, 4, 1),
+                   ("202422-846542-897479-43190-43237-43254", 43254, 43237, 5, 0)"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        async fn taxa(filter: Filter, pool: &Pool<Sqlite>) -> Vec<i64> {
+            let mut taxa: Vec<i64> = Sample::load_all_user(1, Some(filter.into()), None, pool)
+                .await
+                .expect("Failed to load samples")
+                .iter()
+                .map(|s| s.taxon.id())
+                .collect();
+            taxa.dedup();
+            taxa
+        }
+
+        assert_eq!(taxa(Filter::Family("Poaceae".into()), &pool).await, [40683]);
+        assert_eq!(
+            taxa(Filter::Family("iridaceae".into()), &pool).await,
+            [43254]
+        );
+        assert_eq!(taxa(Filter::Order("Poales".into()), &pool).await, [40683]);
+        // a family name doesn't match an order
+        assert!(taxa(Filter::Order("Poaceae".into()), &pool)
+            .await
+            .is_empty());
+        assert_eq!(taxa(Filter::AncestorTsn(43237), &pool).await, [43254]);
+        assert_eq!(taxa(Filter::AncestorTsn(40683), &pool).await, [40683]);
+        // don't match tsns that are a substring of another tsn
+        assert!(taxa(Filter::AncestorTsn(4068), &pool).await.is_empty());
+        let mut all = taxa(Filter::AncestorTsn(846542), &pool).await;
+        all.sort();
+        assert_eq!(all, [40683, 43254]);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn flag_samples(pool: Pool<Sqlite>) {
+        let sample = Sample::load(1, &pool).await.expect("Failed to load sample");
+        let flag = sample
+            .flag("Needs ID confirmation", &pool)
+            .await
+            .expect("Failed to flag sample");
+        assert_eq!(flag.sampleid, 1);
+        assert_eq!(flag.reason, "Needs ID confirmation");
+        // flagging again for the same reason doesn't add another flag
+        let again = sample
+            .flag(" Needs ID confirmation ", &pool)
+            .await
+            .expect("Failed to flag sample");
+        assert_eq!(again.id, flag.id);
+        assert!(matches!(
+            sample.flag("  ", &pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        let other = Sample::load(2, &pool).await.expect("Failed to load sample");
+        other
+            .flag("Low viability suspected", &pool)
+            .await
+            .expect("Failed to flag sample");
+        other
+            .flag("Needs ID confirmation", &pool)
+            .await
+            .expect("Failed to flag sample");
+        assert_eq!(other.load_flags(&pool).await.unwrap().len(), 2);
+        assert_eq!(SampleFlag::load_all_user(1, &pool).await.unwrap().len(), 3);
+        assert_eq!(
+            SampleFlag::reasons(1, &pool).await.unwrap(),
+            vec!["Low viability suspected", "Needs ID confirmation"]
+        );
+
+        let ids = |samples: Vec<Sample>| samples.iter().map(|s| s.id).collect::<Vec<_>>();
+        let flagged = Sample::load_all_user(
+            1,
+            Some(Filter::Flagged(None).into()),
+            Some(Sort::Id.into()),
+            &pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(ids(flagged), [1, 2]);
+        let flagged = Sample::load_all_user(
+            1,
+            Some(Filter::Flagged(Some("Low viability suspected".to_string())).into()),
+            None,
+            &pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(ids(flagged), [2]);
+
+        flag.delete(&pool).await.expect("Failed to remove flag");
+        assert!(sample.load_flags(&pool).await.unwrap().is_empty());
+        other
+            .clear_flags(&pool)
+            .await
+            .expect("Failed to clear flags");
+        assert!(SampleFlag::load_all_user(1, &pool)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn label_queue(pool: Pool<Sqlite>) {
+        async fn pending(pool: &Pool<Sqlite>) -> Vec<i64> {
+            Sample::load_all_user(
+                1,
+                Some(Filter::LabelPending.into()),
+                Some(Sort::Id.into()),
+                pool,
+            )
+            .await
+            .expect("Failed to load samples")
+            .iter()
+            .map(|s| s.id)
+            .collect()
+        }
+
+        // samples from the fixtures were never queued
+        assert!(pending(&pool).await.is_empty());
+        let mut sample = Sample::new(
+            40683,
+            1,
+            1,
+            None,
+            Some(2024),
+            None,
+            None,
+            Certainty::Certain,
+        );
+        sample.insert(&pool).await.expect("Failed to insert sample");
+        let mut existing = Sample::load(1, &pool).await.expect("Failed to load sample");
+        existing.quantity = Some(20);
+        existing
+            .update(&pool)
+            .await
+            .expect("Failed to update sample");
+        assert_eq!(pending(&pool).await, [1, sample.id]);
+
+        assert_eq!(
+            Sample::mark_labels_printed(&[1, sample.id], &pool)
+                .await
+                .expect("Failed to mark labels printed"),
+            2
+        );
+        assert!(pending(&pool).await.is_empty());
+        // already printed labels aren't marked again
+        assert_eq!(
+            Sample::mark_labels_printed(&[1], &pool)
+                .await
+                .expect("Failed to mark labels printed"),
+            0
+        );
+
+        // modifying a sample queues its label again
+        existing.notes = Some("moved to freezer".to_string());
+        existing
+            .update(&pool)
+            .await
+            .expect("Failed to update sample");
+        assert_eq!(pending(&pool).await, [1]);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn partial_update(pool: Pool<Sqlite>) {
+        let mut sample = Sample::load(1, &pool).await.expect("Failed to load sample");
+        let version = sample.version;
+        sample
+            .update_field(SampleField::Quantity(Some(42)), &pool)
+            .await
+            .expect("Failed to update quantity");
+        sample
+            .update_field(SampleField::Notes(Some("  cleaned  ".to_string())), &pool)
+            .await
+            .expect("Failed to update notes");
+        sample
+            .update_field(
+                SampleField::Date {
+                    month: Some(9),
+                    year: Some(2021),
+                },
+                &pool,
+            )
+            .await
+            .expect("Failed to update date");
+        assert_eq!(sample.version, version + 3);
+        let loaded = Sample::load(1, &pool).await.expect("Failed to load sample");
+        assert_eq!(loaded, sample);
+        assert_eq!(loaded.quantity, Some(42));
+        assert_eq!(loaded.notes.as_deref(), Some("cleaned"));
+        assert_eq!((loaded.month, loaded.year), (Some(9), Some(2021)));
+
+        // invalid values are rejected without modifying the sample
+        for field in [
+            SampleField::Quantity(Some(-1)),
+            SampleField::Date {
+                month: Some(13),
+                year: Some(2021),
+            },
+            SampleField::Date {
+                month: Some(5),
+                year: None,
+            },
+        ] {
+            assert!(matches!(
+                sample.update_field(field, &pool).await,
+                Err(Error::InvalidValue(_))
+            ));
+        }
+        assert_eq!(
+            Sample::load(1, &pool).await.expect("Failed to load sample"),
+            sample
+        );
+
+        // several fields can be changed at once, but each only once
+        sample
+            .update_fields(
+                &[
+                    SampleField::Certainty(Certainty::Uncertain),
+                    SampleField::Source(2),
+                ],
+                &pool,
+            )
+            .await
+            .expect("Failed to update fields");
+        let loaded = Sample::load(1, &pool).await.expect("Failed to load sample");
+        assert_eq!(loaded.certainty, Certainty::Uncertain);
+        assert_eq!(loaded.source.id(), 2);
+        assert_eq!(loaded.version, sample.version);
+        assert!(matches!(
+            sample
+                .update_fields(
+                    &[
+                        SampleField::Quantity(Some(1)),
+                        SampleField::Quantity(Some(2))
+                    ],
+                    &pool
+                )
+                .await,
+            Err(Error::InvalidValue(_))
+        ));
+        assert!(matches!(
+            sample.update_fields(&[], &pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+
+        // a stale version is a conflict
+        let mut stale = sample.clone();
+        stale.version -= 1;
+        assert!(matches!(
+            stale.update_field(SampleField::Notes(None), &pool).await,
+            Err(Error::DatabaseVersionConflict(_))
+        ));
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn assign_uuids(pool: Pool<Sqlite>) {
+        // samples that are inserted without a uuid are assigned a random one
+        let samples = Sample::load_all(None, None, &pool).await.unwrap();
+        assert_eq!(samples.len(), 4);
+        for sample in &samples {
+            assert_eq!(sample.uuid.get_version_num(), 4);
+            assert_eq!(sample.source.object().unwrap().uuid.get_version_num(), 4);
+            assert!(samples
+                .iter()
+                .all(|s| s.id == sample.id || s.uuid != sample.uuid));
+        }
+        assert!(Sample::load_uuid(Uuid::new_v4(), &pool).await.is_err());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn filter_source_habitat(pool: Pool<Sqlite>) {
+        sqlx::query("UPDATE sc_sources SET srchabitat=?, srcmoisture=? WHERE srcid=2")
+            .bind(HabitatType::Woodland)
+            .bind(SoilMoisture::Mesic)
+            .execute(&pool)
+            .await
+            .expect("Failed to update source");
+        let ids = |samples: Vec<Sample>| samples.iter().map(|s| s.id).collect::<Vec<_>>();
+        let expected =
+            ids(
+                Sample::load_all(Some(Filter::SourceId(Cmp::Equal, 2).into()), None, &pool)
+                    .await
+                    .unwrap(),
+            );
+        assert!(!expected.is_empty());
+        let found = Sample::load_all(
+            Some(Filter::SourceHabitat(HabitatType::Woodland).into()),
+            None,
+            &pool,
+        )
+        .await
+        .unwrap();
+        assert_eq!(ids(found), expected);
+        let found = Sample::load_all(
+            Some(
+                CompoundFilter::builder(Op::And)
+                    .push(Filter::SourceMoisture(SoilMoisture::Mesic))
+                    .push(Filter::SourceLight(LightCondition::Shade))
+                    .build(),
+            ),
+            None,
+            &pool,
+        )
+        .await
+        .unwrap();
+        assert!(found.is_empty());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn sort_by_several_fields(pool: Pool<Sqlite>) {
+        async fn ids(sort: &str, pool: &Pool<Sqlite>) -> Vec<i64> {
+            Sample::load_all_user(1, None, Some(sort.parse().unwrap()), pool)
+                .await
+                .expect("Failed to load samples")
+                .iter()
+                .map(|s| s.id)
+                .collect()
+        }
+
+        assert_eq!(ids("srcid,-id", &pool).await, [3, 1, 2]);
+        assert_eq!(ids("-date", &pool).await, [3, 2, 1]);
+        // samples without a quantity come first
+        assert_eq!(ids("qty,id", &pool).await, [1, 3, 2]);
+        assert_eq!(ids("qty,-id", &pool).await, [3, 1, 2]);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn load_pages(pool: Pool<Sqlite>) {
+        async fn ids(limit: LimitSpec, pool: &Pool<Sqlite>) -> Vec<i64> {
+            Sample::load_page(
+                Some(Filter::Accessible(1).into()),
+                Some(Sort::SourceId.into()),
+                limit,
+                pool,
+            )
+            .await
+            .expect("Failed to load samples")
+            .iter()
+            .map(|s| s.id)
+            .collect()
+        }
+
+        // samples with the same source are sorted by id
+        assert_eq!(ids(LimitSpec(2, None), &pool).await, [1, 3]);
+        assert_eq!(ids(LimitSpec(2, Some(2)), &pool).await, [2]);
+        assert!(ids(LimitSpec(2, Some(4)), &pool).await.is_empty());
+    }
+
+    /// The steps of the query plan of a query. The parameters of the query are not bound, which
+    /// sqlite treats as NULL, but that doesn't change the plan.
+    async fn query_plan(builder: QueryBuilder<'_, Sqlite>, pool: &Pool<Sqlite>) -> Vec<String> {
+        sqlx::query(&format!("EXPLAIN QUERY PLAN {}", builder.sql()))
+            .fetch_all(pool)
+            .await
+            .expect("Failed to explain query")
+            .iter()
+            .map(|row| row.get("detail"))
+            .collect()
+    }
+
+    fn assert_no_scans(name: &str, plan: &[String]) {
+        assert!(
+            !plan.iter().any(|step| step.starts_with("SCAN ")),
+            "{name} scans a whole table: {plan:#?}"
+        );
+    }
+
+    #[test(sqlx::test(migrations = "../db/migrations/"))]
+    async fn query_plans(pool: Pool<Sqlite>) {
+        let accessible = |filter: Option<DynFilterPart>| {
+            let mut builder = CompoundFilter::builder(Op::And).push(Filter::Accessible(1));
+            if let Some(f) = filter {
+                builder = builder.push(f);
+            }
+            Some(builder.build())
+        };
+        let text = CompoundFilter::builder(Op::Or)
+            .push(Filter::TaxonNameLike("prairie".to_string()))
+            .push(Filter::Notes(Cmp::Like, "prairie".to_string()))
+            .push(Filter::SourceNameLike("prairie".to_string()))
+            .build();
+        let filters: Vec<(&str, Option<DynFilterPart>)> = vec![
+            ("all samples", accessible(None)),
+            ("text filter", accessible(Some(text.clone()))),
+            (
+                "family",
+                accessible(Some(Filter::Family("Poaceae".into()).into())),
+            ),
+            (
+                "order",
+                accessible(Some(Filter::Order("Poales".into()).into())),
+            ),
+            (
+                "ancestor",
+                accessible(Some(Filter::AncestorTsn(40351).into())),
+            ),
+            (
+                "taxon",
+                accessible(Some(Filter::TaxonId(Cmp::Equal, 40683).into())),
+            ),
+            (
+                "source",
+                accessible(Some(Filter::SourceId(Cmp::Equal, 1).into())),
+            ),
+            ("trip", accessible(Some(Filter::TripId(1).into()))),
+            ("uuid", Some(Filter::Uuid(Uuid::new_v4()).into())),
+        ];
+        for (name, filter) in filters {
+            for sort in [Sort::TaxonSequence, Sort::SourceName] {
+                let plan = query_plan(
+                    Sample::build_query(filter.clone(), Some(sort.into())),
+                    &pool,
+                )
+                .await;
+                assert_no_scans(name, &plan);
+            }
+        }
+
+        let allocations = CompoundFilter::builder(Op::And)
+            .push(allocation::Filter::ProjectId(1))
+            .push(
+                CompoundFilter::builder(Op::Or)
+                    .push(allocation::Filter::TaxonNameLike("prairie".to_string()))
+                    .push(allocation::Filter::SourceName(
+                        Cmp::Like,
+                        "prairie".to_string(),
+                    ))
+                    .build(),
+            )
+            .build();
+        let plan = query_plan(
+            Allocation::build_query(
+                Some(allocations),
+                Some(SortSpec::new(allocation::SortField::Activity, SortOrder::Descending).into()),
+            ),
+            &pool,
+        )
+        .await;
+        assert_no_scans("project allocations", &plan);
+    }
 }
@@ -0,0 +1,309 @@
+//! Listing objects on behalf of a user. The `load_all` functions of each object list everything
+//! that matches their filter, so a caller that forgets to restrict the filter to the user's objects
+//! would list the objects of every user. A [UserScope] always adds the condition for the objects
+//! that the user has access to: their own objects and, for objects that can be shared, the objects
+//! of their organizations. Any other filter only narrows the result further.
+use crate::{
+    dedupe::{self, Candidate},
+    error::Result,
+    filter::{CompoundFilter, DynFilterPart, LimitSpec, Op, SortSpecs},
+    notes::{self, NoteEntry},
+    project::{
+        self,
+        allocation::{self, Allocation},
+        Project,
+    },
+    report::{self, Report},
+    sample::{self, Sample},
+    source::{self, NearbySource, Source},
+    trip::{self, Trip},
+};
+use sqlx::{Pool, Sqlite};
+
+/// The objects that a user has access to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UserScope {
+    userid: i64,
+}
+
+impl UserScope {
+    pub fn new(userid: i64) -> Self {
+        Self { userid }
+    }
+
+    pub fn userid(&self) -> i64 {
+        self.userid
+    }
+
+    /// Combine the condition of the scope with an optional filter from the caller
+    fn restrict(
+        condition: impl Into<DynFilterPart>,
+        filter: Option<DynFilterPart>,
+    ) -> DynFilterPart {
+        let mut builder = CompoundFilter::builder(Op::And).push(condition.into());
+        if let Some(filter) = filter {
+            builder = builder.push(filter);
+        }
+        builder.build()
+    }
+
+    /// The user's own samples and the samples of their organizations
+    pub async fn samples(
+        &self,
+        filter: Option<DynFilterPart>,
+        sort: Option<SortSpecs<sample::Sort>>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Sample>> {
+        let filter = Self::restrict(sample::Filter::Accessible(self.userid), filter);
+        Sample::load_all(Some(filter), sort, pool).await
+    }
+
+    /// A single page of the samples that the user has access to, along with the total number of
+    /// samples that match `filter`
+    pub async fn sample_page(
+        &self,
+        filter: Option<DynFilterPart>,
+        sort: Option<SortSpecs<sample::Sort>>,
+        limit: LimitSpec,
+        pool: &Pool<Sqlite>,
+    ) -> Result<(Vec<Sample>, i64)> {
+        let filter = Self::restrict(sample::Filter::Accessible(self.userid), filter);
+        Ok((
+            Sample::load_page(Some(filter.clone()), sort, limit, pool).await?,
+            Sample::count(Some(filter), pool).await?,
+        ))
+    }
+
+    /// The user's own sources and the sources of their organizations
+    pub async fn sources(
+        &self,
+        filter: Option<DynFilterPart>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Source>> {
+        let filter = Self::restrict(source::Filter::Accessible(self.userid), filter);
+        Source::load_all(Some(filter), pool).await
+    }
+
+    /// A single page of the sources that the user has access to, along with the total number of
+    /// sources that match `filter`
+    pub async fn source_page(
+        &self,
+        filter: Option<DynFilterPart>,
+        limit: LimitSpec,
+        pool: &Pool<Sqlite>,
+    ) -> Result<(Vec<Source>, i64)> {
+        let filter = Self::restrict(source::Filter::Accessible(self.userid), filter);
+        Ok((
+            Source::load_page(Some(filter.clone()), limit, pool).await?,
+            Source::count(Some(filter), pool).await?,
+        ))
+    }
+
+    /// The sources that the user has access to within `radius_km` kilometers of the given
+    /// coordinates, see [Source::load_near]
+    pub async fn sources_near(
+        &self,
+        latitude: f64,
+        longitude: f64,
+        radius_km: f64,
+        filter: Option<DynFilterPart>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<NearbySource>> {
+        let filter = Self::restrict(source::Filter::Accessible(self.userid), filter);
+        Source::load_near(latitude, longitude, radius_km, Some(filter), pool).await
+    }
+
+    /// The pairs of sources that the user has access to that may be duplicates of each other
+    pub async fn duplicate_sources(&self, pool: &Pool<Sqlite>) -> Result<Vec<Candidate>> {
+        let filter = Self::restrict(source::Filter::Accessible(self.userid), None);
+        dedupe::find_candidates(Some(filter), pool).await
+    }
+
+    /// The user's own projects and the projects of their organizations
+    pub async fn projects(
+        &self,
+        filter: Option<DynFilterPart>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Project>> {
+        let filter = Self::restrict(project::Filter::Accessible(self.userid), filter);
+        Project::load_all(Some(filter), pool).await
+    }
+
+    /// A single page of the projects that the user has access to, along with the total number of
+    /// projects that match `filter`
+    pub async fn project_page(
+        &self,
+        filter: Option<DynFilterPart>,
+        limit: LimitSpec,
+        pool: &Pool<Sqlite>,
+    ) -> Result<(Vec<Project>, i64)> {
+        let filter = Self::restrict(project::Filter::Accessible(self.userid), filter);
+        Ok((
+            Project::load_page(Some(filter.clone()), limit, pool).await?,
+            Project::count(Some(filter), pool).await?,
+        ))
+    }
+
+    /// The allocations of the projects that the user has access to
+    pub async fn allocations(
+        &self,
+        filter: Option<DynFilterPart>,
+        sort: Option<SortSpecs<allocation::SortField>>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Allocation>> {
+        let filter = Self::restrict(allocation::Filter::Accessible(self.userid), filter);
+        Ok(Allocation::load_all(Some(filter), sort, pool).await?)
+    }
+
+    /// The single allocation of a project that the user has access to that matches `filter`, e.g.
+    /// an allocation with a given uuid. It is an error if there is no such allocation.
+    pub async fn allocation(
+        &self,
+        filter: DynFilterPart,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Allocation> {
+        let filter = Self::restrict(allocation::Filter::Accessible(self.userid), Some(filter));
+        Ok(Allocation::load_one(Some(filter), pool).await?)
+    }
+
+    /// A single page of the allocations of the projects that the user has access to, along with
+    /// the total number of allocations that match `filter`
+    pub async fn allocation_page(
+        &self,
+        filter: Option<DynFilterPart>,
+        sort: Option<SortSpecs<allocation::SortField>>,
+        limit: LimitSpec,
+        pool: &Pool<Sqlite>,
+    ) -> Result<(Vec<Allocation>, i64)> {
+        let filter = Self::restrict(allocation::Filter::Accessible(self.userid), filter);
+        Ok((
+            Allocation::load_page(Some(filter.clone()), sort, limit, pool).await?,
+            Allocation::count(Some(filter), pool).await?,
+        ))
+    }
+
+    /// The notes of the samples, sources and projects that the user has access to
+    pub async fn notes(
+        &self,
+        filter: Option<DynFilterPart>,
+        limit: Option<LimitSpec>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<NoteEntry>> {
+        let filter = Self::restrict(notes::Filter::Accessible(self.userid), filter);
+        NoteEntry::load_all(Some(filter), limit, pool).await
+    }
+
+    /// The user's collection trips. Trips are never shared with an organization.
+    pub async fn trips(
+        &self,
+        filter: Option<DynFilterPart>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Trip>> {
+        let filter = Self::restrict(trip::Filter::User(self.userid), filter);
+        Trip::load_all(Some(filter), pool).await
+    }
+
+    /// The user's saved reports. Reports are never shared with an organization.
+    pub async fn reports(
+        &self,
+        filter: Option<DynFilterPart>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Report>> {
+        let filter = Self::restrict(report::Filter::User(self.userid), filter);
+        Report::load_all(Some(filter), pool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Cmp;
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn test_scope(pool: Pool<Sqlite>) {
+        let scope = UserScope::new(1);
+        let samples = scope.samples(None, None, &pool).await.unwrap();
+        assert!(!samples.is_empty());
+        assert!(samples.iter().all(|s| s.user.id() == 1));
+        let sources = scope.sources(None, &pool).await.unwrap();
+        assert!(!sources.is_empty());
+        assert!(sources.iter().all(|s| s.userid == 1));
+        let projects = scope.projects(None, &pool).await.unwrap();
+        assert!(!projects.is_empty());
+        assert!(projects.iter().all(|p| p.userid == 1));
+        let allocations = scope.allocations(None, None, &pool).await.unwrap();
+        assert!(allocations.iter().all(|a| a.project.userid == 1));
+
+        // a filter that selects another user's objects can't widen the scope
+        let others = scope
+            .samples(Some(sample::Filter::UserId(2).into()), None, &pool)
+            .await
+            .unwrap();
+        assert!(others.is_empty());
+        let others = scope
+            .sources(
+                Some(
+                    CompoundFilter::builder(Op::Or)
+                        .push(source::Filter::UserId(2))
+                        .push(source::Filter::Name(Cmp::Like, String::new()))
+                        .build(),
+                ),
+                &pool,
+            )
+            .await
+            .unwrap();
+        assert_eq!(others.len(), sources.len());
+        let other_samples = UserScope::new(2).samples(None, None, &pool).await.unwrap();
+        assert!(other_samples.iter().all(|s| s.user.id() == 2));
+        assert!(other_samples.iter().all(|s| !samples.contains(s)));
+
+        // single allocations and nearby sources
+        let allocation = scope
+            .allocation(allocation::Filter::Id(1).into(), &pool)
+            .await
+            .unwrap();
+        assert_eq!(allocation.project.userid, 1);
+        assert!(scope
+            .allocation(allocation::Filter::Id(4).into(), &pool)
+            .await
+            .is_err());
+        let nearby = scope
+            .sources_near(40.123, -90.123, 10.0, None, &pool)
+            .await
+            .unwrap();
+        assert_eq!(nearby.len(), 1);
+        assert!(UserScope::new(2)
+            .sources_near(40.123, -90.123, 10.0, None, &pool)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // pages are counted within the scope as well
+        let (page, total) = scope
+            .sample_page(None, None, LimitSpec(1, Some(1)), &pool)
+            .await
+            .unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(total, samples.len() as i64);
+        assert_eq!(page[0], samples[1]);
+        let (page, total) = scope
+            .project_page(None, LimitSpec(10, None), &pool)
+            .await
+            .unwrap();
+        assert_eq!(total, projects.len() as i64);
+        assert!(page.iter().all(|p| p.userid == 1));
+        let (page, total) = UserScope::new(2)
+            .allocation_page(None, None, LimitSpec(10, None), &pool)
+            .await
+            .unwrap();
+        assert_eq!(total, page.len() as i64);
+        assert!(page.iter().all(|a| a.project.userid == 2));
+    }
+}
@@ -0,0 +1,255 @@
+//! A unified search across the different kinds of objects in the database, e.g. for a "jump to"
+//! box in a user interface. Results of all kinds are returned in a single list, ranked by how well
+//! they match the query.
+use crate::{
+    error::Result,
+    filter::{Cmp, CompoundFilter, LimitSpec, Op},
+    project::{self, Project},
+    sample::{self, Sample},
+    source::{self, Source},
+    taxonomy::{self, Taxon},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite};
+use uuid::Uuid;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub enum ResultKind {
+    Sample,
+    Project,
+    Source,
+    Taxon,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct SearchResult {
+    pub kind: ResultKind,
+    pub id: i64,
+    /// The identifier of the object in URLs. Taxa don't have one.
+    pub uuid: Option<Uuid>,
+    pub title: String,
+    pub subtitle: Option<String>,
+    /// How well the result matches the query. Higher is better.
+    pub score: u32,
+}
+
+/// How well `text` matches the query. The objects being scored have already been matched by the
+/// database, so even a text that doesn't contain the query as a phrase gets a minimal score.
+fn text_score(query: &str, text: &str) -> u32 {
+    let query = query.to_lowercase();
+    let text = text.to_lowercase();
+    if text == query {
+        100
+    } else if text.starts_with(&query) {
+        60
+    } else if text
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| word.starts_with(&query))
+    {
+        40
+    } else if text.contains(&query) {
+        20
+    } else {
+        // every word of the query matches somewhere, but not as a single phrase
+        10
+    }
+}
+
+/// How well the scientific name or one of the common names of a taxon matches the query
+fn taxon_score(query: &str, taxon: &Taxon) -> u32 {
+    taxon
+        .vernaculars
+        .iter()
+        .map(|v| text_score(query, v))
+        .fold(text_score(query, &taxon.complete_name), u32::max)
+}
+
+/// Parse an object id as it might be typed by a user, either as a plain number or formatted with
+/// the given prefix, e.g. `S0012`.
+fn parse_id(query: &str, prefix: char) -> Option<i64> {
+    let query = query.trim();
+    let digits = query
+        .strip_prefix(prefix)
+        .or_else(|| query.strip_prefix(prefix.to_ascii_lowercase()))
+        .unwrap_or(query);
+    digits.parse().ok()
+}
+
+/// Search the samples, projects and sources accessible to the given user, as well as all taxa, for
+/// the given query. At most `limit` results are returned, best matches first.
+pub async fn search(
+    userid: i64,
+    query: &str,
+    limit: usize,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<SearchResult>> {
+    let words: Vec<&str> = query.split_whitespace().collect();
+    if words.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut results = Vec::new();
+
+    let mut samplefilter = CompoundFilter::builder(Op::And);
+    let mut projectfilter =
+        CompoundFilter::builder(Op::And).push(project::Filter::Accessible(userid));
+    let mut sourcefilter =
+        CompoundFilter::builder(Op::And).push(source::Filter::Accessible(userid));
+    for word in &words {
+        samplefilter = samplefilter.push(
+            CompoundFilter::builder(Op::Or)
+                .push(sample::Filter::TaxonNameLike(word.to_string()))
+                .push(sample::Filter::SourceNameLike(word.to_string()))
+                .push(sample::Filter::Notes(Cmp::Like, word.to_string()))
+                .build(),
+        );
+        projectfilter = projectfilter.push(
+            CompoundFilter::builder(Op::Or)
+                .push(project::Filter::Name(Cmp::Like, word.to_string()))
+                .push(project::Filter::Description(Cmp::Like, word.to_string()))
+                .build(),
+        );
+        sourcefilter = sourcefilter.push(
+            CompoundFilter::builder(Op::Or)
+                .push(source::Filter::Name(Cmp::Like, word.to_string()))
+                .push(source::Filter::Description(Cmp::Like, word.to_string()))
+                .build(),
+        );
+    }
+    let mut samplefilter = CompoundFilter::builder(Op::Or).push(samplefilter.build());
+    if let Some(id) = parse_id(query, 'S') {
+        samplefilter = samplefilter.push(sample::Filter::Id(Cmp::Equal, id));
+    }
+    for sample in Sample::load_all_user(userid, Some(samplefilter.build()), None, pool).await? {
+        let taxon = sample.taxon.object()?;
+        let source = sample.source.object()?;
+        let score = match parse_id(query, 'S') {
+            Some(id) if id == sample.id => 200,
+            _ => taxon_score(query, taxon).max(text_score(query, &source.name) / 2),
+        };
+        results.push(SearchResult {
+            kind: ResultKind::Sample,
+            id: sample.id,
+            uuid: Some(sample.uuid),
+            title: taxon.complete_name.clone(),
+            subtitle: Some(source.name.clone()),
+            score,
+        });
+    }
+
+    let mut projectfilter = CompoundFilter::builder(Op::Or).push(projectfilter.build());
+    if let Some(id) = parse_id(query, 'P') {
+        projectfilter = projectfilter.push(
+            CompoundFilter::builder(Op::And)
+                .push(project::Filter::Accessible(userid))
+                .push(project::Filter::Id(id))
+                .build(),
+        );
+    }
+    for project in Project::load_all(Some(projectfilter.build()), pool).await? {
+        let score = match parse_id(query, 'P') {
+            Some(id) if id == project.id => 200,
+            _ => text_score(query, &project.name),
+        };
+        results.push(SearchResult {
+            kind: ResultKind::Project,
+            id: project.id,
+            uuid: Some(project.uuid),
+            title: project.name,
+            subtitle: project.description,
+            score,
+        });
+    }
+
+    for source in Source::load_all(Some(sourcefilter.build()), pool).await? {
+        results.push(SearchResult {
+            kind: ResultKind::Source,
+            id: source.id,
+            uuid: Some(source.uuid),
+            score: text_score(query, &source.name),
+            title: source.name,
+            subtitle: source.description,
+        });
+    }
+
+    if let Some(filter) = taxonomy::quickfind(query.to_string()) {
+        let mut taxa =
+            Taxon::load_all(Some(filter), Some(LimitSpec(limit as i32, None)), pool).await?;
+        taxonomy::rank_quickfind_results(query, &mut taxa);
+        for taxon in taxa {
+            // taxa are less likely to be what the user is looking for than their own objects, so
+            // they get a slightly lower score
+            let score = taxon_score(query, &taxon).saturating_sub(5);
+            results.push(SearchResult {
+                kind: ResultKind::Taxon,
+                id: taxon.id,
+                uuid: None,
+                title: taxon.complete_name,
+                subtitle: taxon.vernaculars.first().cloned(),
+                score,
+            });
+        }
+    }
+
+    // sort_by is stable, so results with equal scores stay grouped by kind
+    results.sort_by_key(|r| std::cmp::Reverse(r.score));
+    results.truncate(limit);
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn search_everything(pool: Pool<Sqlite>) {
+        assert!(search(1, "  ", 10, &pool).await.unwrap().is_empty());
+
+        let results = search(1, "elymus", 20, &pool)
+            .await
+            .expect("Failed to search");
+        let kinds: Vec<(ResultKind, i64)> = results.iter().map(|r| (r.kind, r.id)).collect();
+        // the user's own samples, but not samples of other users
+        assert!(kinds.contains(&(ResultKind::Sample, 2)));
+        assert!(kinds.contains(&(ResultKind::Sample, 3)));
+        assert!(!kinds.contains(&(ResultKind::Sample, 4)));
+        assert!(kinds.contains(&(ResultKind::Taxon, 40683)));
+        // the genus matches exactly, so it is ranked first
+        assert_eq!(kinds[0], (ResultKind::Taxon, 40677));
+
+        let results = search(1, "S0001", 10, &pool)
+            .await
+            .expect("Failed to search");
+        assert_eq!(results[0].kind, ResultKind::Sample);
+        assert_eq!(results[0].id, 1);
+
+        let results = search(1, "second project", 10, &pool)
+            .await
+            .expect("Failed to search");
+        assert_eq!(results.len(), 1);
+        assert_eq!((results[0].kind, results[0].id), (ResultKind::Project, 2));
+
+        let results = search(1, "source 2", 10, &pool)
+            .await
+            .expect("Failed to search");
+        assert_eq!(results[0].kind, ResultKind::Source);
+        assert_eq!(results[0].id, 2);
+
+        // projects of other users are never found, not even by id
+        let results = search(1, "P3", 10, &pool).await.expect("Failed to search");
+        assert!(!results
+            .iter()
+            .any(|r| r.kind == ResultKind::Project && r.id == 3));
+
+        let results = search(1, "elymus", 2, &pool)
+            .await
+            .expect("Failed to search");
+        assert_eq!(results.len(), 2);
+    }
+}
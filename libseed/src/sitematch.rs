@@ -0,0 +1,286 @@
+//! Site matching recommends samples for a restoration planting. Seed is best sourced from places
+//! that resemble the planting site, so each sample is scored by how closely the habitat attributes
+//! of its source match the attributes of the site and by how close the source is to the site.
+use crate::{
+    error::{Error, Result},
+    loadable::Loadable,
+    sample::Sample,
+    source::{distance_km, HabitatType, LightCondition, SoilMoisture, Source},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+
+/// The distance in kilometers at which the proximity of a source drops to one half
+const PROXIMITY_HALF_KM: f64 = 50.0;
+
+/// The share of the habitat similarity in the score when both the similarity and the proximity
+/// are known
+const SIMILARITY_WEIGHT: f64 = 0.6;
+
+/// The attributes of a site that is going to be planted. Every attribute is optional, but at least
+/// one of them has to be given.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct PlantingSite {
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub habitat: Option<HabitatType>,
+    pub moisture: Option<SoilMoisture>,
+    pub light: Option<LightCondition>,
+}
+
+/// A sample that is recommended for a planting site
+#[derive(Clone, Debug, Serialize)]
+pub struct SiteMatch {
+    pub sample: Sample,
+    pub source: Source,
+    /// the distance between the source and the site, if both have coordinates
+    pub distance_km: Option<f64>,
+    /// how closely the habitat attributes of the source match those of the site, from 0 to 1. Not
+    /// set if no habitat attributes were given for the site.
+    pub similarity: Option<f64>,
+    /// the overall score from 0 to 1 that the recommendations are ranked by
+    pub score: f64,
+}
+
+impl PlantingSite {
+    fn coordinates(&self) -> Result<Option<(f64, f64)>> {
+        match (self.latitude, self.longitude) {
+            (Some(latitude), Some(longitude)) => {
+                if !(-90.0..=90.0).contains(&latitude) {
+                    return Err(Error::InvalidValue(format!("invalid latitude {latitude}")));
+                }
+                if !(-180.0..=180.0).contains(&longitude) {
+                    return Err(Error::InvalidValue(format!(
+                        "invalid longitude {longitude}"
+                    )));
+                }
+                Ok(Some((latitude, longitude)))
+            }
+            (None, None) => Ok(None),
+            _ => Err(Error::InvalidValue(
+                "both latitude and longitude are required for the location of the site".to_string(),
+            )),
+        }
+    }
+
+    /// How closely the attributes of the source match those of the site, as the average of the
+    /// similarity of each attribute that is given for the site. Attributes that are not known for
+    /// the source don't match at all.
+    pub fn similarity(&self, source: &Source) -> Option<f64> {
+        let scores = [
+            self.habitat
+                .map(|h| source.habitat.map_or(0.0, |s| habitat_similarity(h, s))),
+            self.moisture.map(|m| {
+                source
+                    .moisture
+                    .map_or(0.0, |s| ordinal_similarity(m as i64, s as i64, 4))
+            }),
+            self.light.map(|l| {
+                source
+                    .light
+                    .map_or(0.0, |s| ordinal_similarity(l as i64, s as i64, 2))
+            }),
+        ];
+        let given: Vec<f64> = scores.into_iter().flatten().collect();
+        match given.is_empty() {
+            true => None,
+            false => Some(given.iter().sum::<f64>() / given.len() as f64),
+        }
+    }
+
+    /// Score the samples that the user has access to for this site and return the `limit` best
+    /// matches, ordered by their score and then by their distance
+    pub async fn recommend(
+        &self,
+        userid: i64,
+        limit: usize,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<SiteMatch>> {
+        let coordinates = self.coordinates()?;
+        if coordinates.is_none()
+            && self.habitat.is_none()
+            && self.moisture.is_none()
+            && self.light.is_none()
+        {
+            return Err(Error::InvalidValue(
+                "no location or habitat attributes were given for the site".to_string(),
+            ));
+        }
+        let mut sources = HashMap::new();
+        for mut source in Source::load_all_user(userid, pool).await? {
+            source.reveal_for(userid, pool).await?;
+            sources.insert(source.id, source);
+        }
+        let mut matches = Vec::new();
+        for sample in Sample::load_all_user(userid, None, None, pool).await? {
+            let source = match sources.get(&sample.source.id()) {
+                Some(source) => source.clone(),
+                // the sample is shared with the user, but its source isn't
+                None => {
+                    let mut source = Source::load(sample.source.id(), pool).await?;
+                    source.reveal_for(userid, pool).await?;
+                    sources.insert(source.id, source.clone());
+                    source
+                }
+            };
+            // the distance to a sensitive source that the user may not locate exactly is measured
+            // to its generalized location
+            let distance_km = match (coordinates, source.visible_coordinates()) {
+                (Some((lat, lon)), Some((srclat, srclon))) => {
+                    Some(distance_km(lat, lon, srclat, srclon))
+                }
+                _ => None,
+            };
+            let similarity = self.similarity(&source);
+            // sources without coordinates are treated as being infinitely far away
+            let proximity = coordinates
+                .map(|_| distance_km.map_or(0.0, |d| PROXIMITY_HALF_KM / (PROXIMITY_HALF_KM + d)));
+            let score = match (similarity, proximity) {
+                (Some(s), Some(p)) => SIMILARITY_WEIGHT * s + (1.0 - SIMILARITY_WEIGHT) * p,
+                (Some(s), None) => s,
+                (None, Some(p)) => p,
+                (None, None) => 0.0,
+            };
+            matches.push(SiteMatch {
+                sample,
+                source,
+                distance_km,
+                similarity,
+                score,
+            });
+        }
+        matches.sort_by(|a, b| {
+            b.score.total_cmp(&a.score).then_with(|| {
+                a.distance_km
+                    .unwrap_or(f64::INFINITY)
+                    .total_cmp(&b.distance_km.unwrap_or(f64::INFINITY))
+            })
+        });
+        matches.truncate(limit);
+        Ok(matches)
+    }
+}
+
+/// Habitats that form a gradient from open to closed canopy, or that are both wet, partially match
+fn habitat_similarity(a: HabitatType, b: HabitatType) -> f64 {
+    use HabitatType::*;
+    match (a, b) {
+        _ if a == b => 1.0,
+        (Prairie, Savanna)
+        | (Savanna, Prairie)
+        | (Savanna, Woodland)
+        | (Woodland, Savanna)
+        | (Woodland, Forest)
+        | (Forest, Woodland)
+        | (Wetland, Shoreline)
+        | (Shoreline, Wetland) => 0.5,
+        _ => 0.0,
+    }
+}
+
+/// The similarity of two values on a scale with `steps` steps between its lowest and highest value
+fn ordinal_similarity(a: i64, b: i64, steps: i64) -> f64 {
+    1.0 - (a - b).abs() as f64 / steps as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    #[test]
+    fn test_similarity() {
+        let mut source = Source::new("test".to_string(), None, None, None, 1);
+        source.habitat = Some(HabitatType::Savanna);
+        source.moisture = Some(SoilMoisture::DryMesic);
+        let site = PlantingSite::default();
+        assert_eq!(site.similarity(&source), None);
+        let site = PlantingSite {
+            habitat: Some(HabitatType::Savanna),
+            ..Default::default()
+        };
+        assert_eq!(site.similarity(&source), Some(1.0));
+        let site = PlantingSite {
+            habitat: Some(HabitatType::Prairie),
+            moisture: Some(SoilMoisture::Mesic),
+            ..Default::default()
+        };
+        assert_eq!(site.similarity(&source), Some((0.5 + 0.75) / 2.0));
+        // the light conditions of the source are unknown
+        let site = PlantingSite {
+            light: Some(LightCondition::FullSun),
+            ..Default::default()
+        };
+        assert_eq!(site.similarity(&source), Some(0.0));
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn test_recommend(pool: Pool<Sqlite>) {
+        sqlx::query("UPDATE sc_sources SET srchabitat=?, srcmoisture=? WHERE srcid=2")
+            .bind(HabitatType::Prairie)
+            .bind(SoilMoisture::Mesic)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let source_ids = |matches: &[SiteMatch]| {
+            let mut ids: Vec<i64> = matches.iter().map(|m| m.source.id).collect();
+            ids.dedup();
+            ids
+        };
+
+        // by habitat only
+        let site = PlantingSite {
+            habitat: Some(HabitatType::Prairie),
+            moisture: Some(SoilMoisture::Mesic),
+            ..Default::default()
+        };
+        let matches = site.recommend(1, 10, &pool).await.unwrap();
+        assert!(!matches.is_empty());
+        assert_eq!(source_ids(&matches)[0], 2);
+        assert_eq!(matches[0].score, 1.0);
+        assert_eq!(matches[0].distance_km, None);
+
+        // by location only, next to source 1
+        let site = PlantingSite {
+            latitude: Some(40.123),
+            longitude: Some(-90.123),
+            ..Default::default()
+        };
+        let matches = site.recommend(1, 10, &pool).await.unwrap();
+        assert_eq!(source_ids(&matches)[0], 1);
+        assert_eq!(matches[0].distance_km, Some(0.0));
+        assert_eq!(matches[0].similarity, None);
+        let matches = site.recommend(1, 1, &pool).await.unwrap();
+        assert_eq!(matches.len(), 1);
+
+        // a matching habitat far away outweighs a nearby source without habitat attributes
+        let site = PlantingSite {
+            latitude: Some(40.123),
+            longitude: Some(-90.123),
+            habitat: Some(HabitatType::Prairie),
+            ..Default::default()
+        };
+        let matches = site.recommend(1, 10, &pool).await.unwrap();
+        assert_eq!(source_ids(&matches), vec![2, 1]);
+
+        assert!(matches!(
+            PlantingSite::default().recommend(1, 10, &pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+        let site = PlantingSite {
+            latitude: Some(40.0),
+            ..Default::default()
+        };
+        assert!(matches!(
+            site.recommend(1, 10, &pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+    }
+}
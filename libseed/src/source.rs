@@ -1,25 +1,123 @@
 //! Objects to keep track of the origin of seed samples
 use crate::{
     error::{Error, Result},
-    filter::{Cmp, DynFilterPart, FilterPart},
+    filter::{Cmp, CompoundFilter, DynFilterPart, FilterPart, LimitSpec, ListQuery, Op},
     loadable::{ExternalRef, Loadable},
+    organization::{has_permission, push_accessible_condition, Owned, Permission},
+    plugin,
+    scope::UserScope,
 };
 use async_trait::async_trait;
 use serde::Deserialize;
 use serde::Serialize;
+use serde::Serializer;
 use sqlx::Pool;
 use sqlx::QueryBuilder;
 use sqlx::Sqlite;
 use sqlx::{
     prelude::*,
     sqlite::{SqliteQueryResult, SqliteRow},
+    SqliteConnection,
 };
 use std::sync::Arc;
+use strum_macros::{Display, EnumIter, EnumString};
+use uuid::Uuid;
 
-#[derive(Debug, sqlx::FromRow, Deserialize, Serialize, PartialEq, Clone)]
+/// The plant community at a source
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Eq,
+    sqlx::Type,
+    Display,
+    EnumIter,
+    EnumString,
+)]
+#[repr(i64)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum HabitatType {
+    Prairie = 1,
+    Savanna = 2,
+    Woodland = 3,
+    Forest = 4,
+    Wetland = 5,
+    Shoreline = 6,
+    /// roadsides, old fields and other disturbed ground
+    Disturbed = 7,
+}
+
+/// The soil moisture class of a source, as commonly used to select species for a planting
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Eq,
+    sqlx::Type,
+    Display,
+    EnumIter,
+    EnumString,
+)]
+#[repr(i64)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum SoilMoisture {
+    Dry = 1,
+    DryMesic = 2,
+    Mesic = 3,
+    WetMesic = 4,
+    Wet = 5,
+}
+
+/// The amount of sunlight at a source
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Deserialize,
+    Serialize,
+    PartialEq,
+    Eq,
+    sqlx::Type,
+    Display,
+    EnumIter,
+    EnumString,
+)]
+#[repr(i64)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case", ascii_case_insensitive)]
+pub enum LightCondition {
+    FullSun = 1,
+    PartShade = 2,
+    Shade = 3,
+}
+
+/// The number of decimal places that the coordinates of a sensitive source are rounded to when they
+/// are shown to users that may not see its exact location. One decimal place is about 11 km.
+const GENERALIZED_DECIMALS: i32 = 1;
+
+/// Round a coordinate of a sensitive source so that it no longer reveals the exact location
+pub fn generalize_coordinate(value: f64) -> f64 {
+    let factor = 10f64.powi(GENERALIZED_DECIMALS);
+    (value * factor).round() / factor
+}
+
+/// A source. When a source is serialized, e.g. for a template or the API, the coordinates of a
+/// sensitive source are generalized unless they have been revealed with [Source::reveal_for].
+#[derive(Debug, sqlx::FromRow, Deserialize, PartialEq, Clone)]
 pub struct Source {
     #[sqlx(rename = "srcid")]
     pub id: i64,
+    /// an identifier that is used for the source in URLs and the API instead of the numeric id
+    #[sqlx(rename = "srcuuid", try_from = "String")]
+    pub uuid: Uuid,
     #[sqlx(rename = "srcname")]
     pub name: String,
     #[sqlx(rename = "srcdesc", default)]
@@ -29,6 +127,68 @@ pub struct Source {
     #[sqlx(default)]
     pub longitude: Option<f64>,
     pub userid: i64,
+    #[sqlx(rename = "srcversion")]
+    pub version: i64,
+    /// the organization that shares ownership of the source, if any
+    #[sqlx(rename = "srcorgid", default)]
+    pub orgid: Option<i64>,
+    #[sqlx(rename = "srchabitat", default)]
+    pub habitat: Option<HabitatType>,
+    #[sqlx(rename = "srcmoisture", default)]
+    pub moisture: Option<SoilMoisture>,
+    #[sqlx(rename = "srclight", default)]
+    pub light: Option<LightCondition>,
+    /// whether the exact location of the source needs to be protected, e.g. because it is the
+    /// site of a rare plant population
+    #[sqlx(rename = "srcsensitive", default)]
+    #[serde(default)]
+    pub sensitive: bool,
+    #[sqlx(skip)]
+    #[serde(skip)]
+    revealed: bool,
+}
+
+/// The serialized form of a [Source], with the coordinates that may be shown
+#[derive(Serialize)]
+struct SourceView<'a> {
+    id: i64,
+    uuid: &'a Uuid,
+    name: &'a str,
+    description: &'a Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    userid: i64,
+    version: i64,
+    orgid: Option<i64>,
+    habitat: Option<HabitatType>,
+    moisture: Option<SoilMoisture>,
+    light: Option<LightCondition>,
+    sensitive: bool,
+    /// whether the coordinates are generalized rather than exact
+    generalized: bool,
+}
+
+impl Serialize for Source {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let coordinates = self.visible_coordinates();
+        SourceView {
+            id: self.id,
+            uuid: &self.uuid,
+            name: &self.name,
+            description: &self.description,
+            latitude: coordinates.map(|(lat, _)| lat),
+            longitude: coordinates.map(|(_, lon)| lon),
+            userid: self.userid,
+            version: self.version,
+            orgid: self.orgid,
+            habitat: self.habitat,
+            moisture: self.moisture,
+            light: self.light,
+            sensitive: self.sensitive,
+            generalized: self.is_generalized(),
+        }
+        .serialize(serializer)
+    }
 }
 
 impl FromRow<'_, SqliteRow> for ExternalRef<Source> {
@@ -60,20 +220,104 @@ impl Loadable for Source {
     }
 
     async fn delete_id(id: &Self::Id, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
-        sqlx::query(r#"DELETE FROM sc_sources WHERE srcid=?1"#)
-            .bind(id)
-            .execute(pool)
-            .await
-            .map_err(|e| e.into())
+        delete_source(*id, OnDelete::Restrict, pool).await
+    }
+}
+
+/// What happens to the samples of a source when the source is deleted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OnDelete {
+    /// refuse to delete a source that still has samples
+    Restrict,
+    /// move the samples to the source with the given id before deleting the source
+    Reassign(i64),
+    /// delete the samples along with the source, including their allocations to projects
+    Cascade,
+}
+
+async fn delete_source(
+    id: i64,
+    on_delete: OnDelete,
+    pool: &Pool<Sqlite>,
+) -> Result<SqliteQueryResult> {
+    let mut tx = pool.begin().await?;
+    let res = delete_source_in(id, on_delete, &mut tx).await?;
+    tx.commit().await?;
+    Ok(res)
+}
+
+/// Delete a source as part of a larger transaction
+pub(crate) async fn delete_source_in(
+    id: i64,
+    on_delete: OnDelete,
+    tx: &mut SqliteConnection,
+) -> Result<SqliteQueryResult> {
+    let nsamples: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sc_samples WHERE srcid=?")
+        .bind(id)
+        .fetch_one(&mut *tx)
+        .await?;
+    if nsamples > 0 {
+        match on_delete {
+            OnDelete::Restrict => return Err(Error::InvalidOperationObjectInUse(nsamples)),
+            OnDelete::Reassign(newid) if newid == id => {
+                return Err(Error::InvalidValue(
+                    "Samples can't be reassigned to the source that is being deleted".to_string(),
+                ))
+            }
+            OnDelete::Reassign(newid) => {
+                sqlx::query("UPDATE sc_samples SET srcid=? WHERE srcid=?")
+                    .bind(newid)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            OnDelete::Cascade => {
+                sqlx::query(
+                    r#"DELETE FROM sc_project_notes WHERE psid IN
+                        (SELECT PS.psid FROM sc_project_samples PS
+                        INNER JOIN sc_samples S ON S.sampleid=PS.sampleid WHERE S.srcid=?);
+                    DELETE FROM sc_project_samples WHERE sampleid IN
+                        (SELECT sampleid FROM sc_samples WHERE srcid=?);
+                    DELETE FROM sc_samples WHERE srcid=?"#,
+                )
+                .bind(id)
+                .bind(id)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
     }
+    sqlx::query("DELETE FROM sc_sources WHERE srcid=?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .map_err(Into::into)
 }
 
 #[derive(Clone)]
 pub enum Filter {
     Id(i64),
+    Uuid(Uuid),
     UserId(i64),
+    /// sources that are owned by the given user or by one of the user's organizations
+    Accessible(i64),
+    /// sources that are owned by the given organization
+    OrgId(i64),
     Name(Cmp, String),
     Description(Cmp, String),
+    Habitat(HabitatType),
+    Moisture(SoilMoisture),
+    Light(LightCondition),
+    /// sources within a box around the given coordinates that contains every point within
+    /// `radius_km` of them. This is only a coarse prefilter, see [`Source::load_near()`]
+    Near {
+        latitude: f64,
+        longitude: f64,
+        radius_km: f64,
+    },
+    /// sources whose coordinates have not been recorded
+    LocationMissing,
 }
 
 impl From<Filter> for DynFilterPart {
@@ -92,7 +336,12 @@ impl FilterPart for Filter {
     fn add_to_query(&self, builder: &mut sqlx::QueryBuilder<sqlx::Sqlite>) {
         match self {
             Self::Id(id) => _ = builder.push(" L.srcid = ").push_bind(*id),
+            Self::Uuid(uuid) => _ = builder.push(" L.srcuuid = ").push_bind(uuid.to_string()),
             Self::UserId(id) => _ = builder.push(" L.userid = ").push_bind(*id),
+            Self::Accessible(id) => {
+                push_accessible_condition(builder, "L.userid", "L.srcorgid", *id)
+            }
+            Self::OrgId(id) => _ = builder.push(" L.srcorgid = ").push_bind(*id),
             Self::Name(cmp, frag) => {
                 let s = match cmp {
                     Cmp::Like => format!("%{frag}%"),
@@ -107,44 +356,175 @@ impl FilterPart for Filter {
                 };
                 builder.push(" L.srcdesc ").push(cmp).push_bind(s);
             }
+            Filter::Habitat(habitat) => _ = builder.push(" L.srchabitat = ").push_bind(*habitat),
+            Filter::Moisture(moisture) => {
+                _ = builder.push(" L.srcmoisture = ").push_bind(*moisture)
+            }
+            Filter::Light(light) => _ = builder.push(" L.srclight = ").push_bind(*light),
+            Filter::Near {
+                latitude,
+                longitude,
+                radius_km,
+            } => push_bounding_box(builder, *latitude, *longitude, *radius_km),
+            Filter::LocationMissing => {
+                _ = builder.push(" (L.latitude IS NULL OR L.longitude IS NULL) ")
+            }
         }
     }
 }
 
+/// The mean radius of the earth in kilometers
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// The great-circle distance in kilometers between two coordinates, using the haversine formula
+pub fn distance_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let dlat = (lat2 - lat1).to_radians();
+    let dlon = (lon2 - lon1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
+
+/// Restrict the query to coordinates within the bounding box of a circle around the given point.
+/// The longitude is not restricted if the circle includes a pole, and the box wraps around if it
+/// crosses the antimeridian.
+fn push_bounding_box(
+    builder: &mut sqlx::QueryBuilder<sqlx::Sqlite>,
+    latitude: f64,
+    longitude: f64,
+    radius_km: f64,
+) {
+    let angle = radius_km / EARTH_RADIUS_KM;
+    let dlat = angle.to_degrees();
+    builder
+        .push(" (L.latitude BETWEEN ")
+        .push_bind(latitude - dlat)
+        .push(" AND ")
+        .push_bind(latitude + dlat);
+    let ratio = angle.sin() / latitude.to_radians().cos();
+    if latitude.abs() + dlat < 90.0 && ratio < 1.0 {
+        let dlon = ratio.asin().to_degrees();
+        let (min, max) = (longitude - dlon, longitude + dlon);
+        if min < -180.0 {
+            builder
+                .push(" AND (L.longitude >= ")
+                .push_bind(min + 360.0)
+                .push(" OR L.longitude <= ")
+                .push_bind(max)
+                .push(")");
+        } else if max > 180.0 {
+            builder
+                .push(" AND (L.longitude >= ")
+                .push_bind(min)
+                .push(" OR L.longitude <= ")
+                .push_bind(max - 360.0)
+                .push(")");
+        } else {
+            builder
+                .push(" AND L.longitude BETWEEN ")
+                .push_bind(min)
+                .push(" AND ")
+                .push_bind(max);
+        }
+    }
+    builder.push(")");
+}
+
+/// A source and its distance from the point that was searched for
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct NearbySource {
+    pub source: Source,
+    pub distance_km: f64,
+}
+
+impl NearbySource {
+    /// Reveal the exact location of the source to the given user as with [Source::reveal_for].
+    /// If the location stays generalized, the distance from the point that was searched for is
+    /// measured to the generalized location instead, so that it can't be used to find the source.
+    pub async fn reveal_for(
+        &mut self,
+        userid: i64,
+        latitude: f64,
+        longitude: f64,
+        pool: &Pool<Sqlite>,
+    ) -> Result<()> {
+        self.source.reveal_for(userid, pool).await?;
+        if self.source.is_generalized() {
+            if let Some((srclat, srclon)) = self.source.visible_coordinates() {
+                self.distance_km = distance_km(latitude, longitude, srclat, srclon);
+            }
+        }
+        Ok(())
+    }
+}
+
 const MAP_TILER_KEY: &str = "OfKZsQq0kXBWp83M3Wjx";
 
+/// The URI of a map centered on the given coordinates
+pub fn map_viewer_uri(latitude: f64, longitude: f64, zoom: f32) -> String {
+    format!(
+        "https://api.maptiler.com/maps/topo-v2/?key={MAP_TILER_KEY}#{zoom}/{latitude}/{longitude}"
+    )
+}
+
 impl Source {
+    fn list_query(filter: Option<DynFilterPart>) -> ListQuery {
+        ListQuery::new(
+            r#"L.srcid, L.srcuuid, L.srcname, L.srcdesc, L.latitude, L.longitude,
+            L.userid, L.srcversion, L.srcorgid, L.srchabitat, L.srcmoisture, L.srclight,
+            L.srcsensitive, U.username"#,
+            "sc_sources L INNER JOIN sc_users U ON U.userid=L.userid",
+            filter,
+        )
+    }
+
     fn build_query(filter: Option<DynFilterPart>) -> QueryBuilder<'static, Sqlite> {
-        let mut qb = QueryBuilder::new(
-            r#"SELECT L.srcid, L.srcname, L.srcdesc, L.latitude, L.longitude,
-            L.userid, U.username FROM sc_sources L
-            INNER JOIN sc_users U ON U.userid=L.userid"#,
-        );
-        if let Some(f) = filter {
-            qb.push(" WHERE ");
-            f.add_to_query(&mut qb);
-        }
+        let mut qb = Self::list_query(filter).select();
         qb.push(" ORDER BY srcname ASC");
         qb
     }
 
-    fn build_count(filter: Option<DynFilterPart>) -> QueryBuilder<'static, Sqlite> {
-        let mut qb = QueryBuilder::new("SELECT COUNT(*) as nsources FROM sc_sources L");
-        if let Some(f) = filter {
-            qb.push(" WHERE ");
-            f.add_to_query(&mut qb);
-        }
-        qb
+    /// A map of the location of the source. A generalized location is shown zoomed out, so that
+    /// the map doesn't suggest more precision than there is.
+    pub fn map_viewer_uri(&self, zoom: f32) -> Option<String> {
+        let zoom = match self.is_generalized() {
+            true => zoom.min(9.0),
+            false => zoom,
+        };
+        self.visible_coordinates()
+            .map(|(latitude, longitude)| map_viewer_uri(latitude, longitude, zoom))
     }
 
-    pub fn map_viewer_uri(&self, zoom: f32) -> Option<String> {
+    /// Whether the coordinates of the source are generalized when they are shown
+    pub fn is_generalized(&self) -> bool {
+        self.sensitive && !self.revealed
+    }
+
+    /// The coordinates of the source as they may be shown: the exact coordinates, unless the source
+    /// is sensitive and they have not been revealed with [Source::reveal_for]
+    pub fn visible_coordinates(&self) -> Option<(f64, f64)> {
         match (self.latitude, self.longitude) {
-            (Some(latitude), Some(longitude)) => Some(format!("https://api.maptiler.com/maps/topo-v2/?key={MAP_TILER_KEY}#{zoom}/{latitude}/{longitude}")),
+            (Some(latitude), Some(longitude)) if self.is_generalized() => Some((
+                generalize_coordinate(latitude),
+                generalize_coordinate(longitude),
+            )),
+            (Some(latitude), Some(longitude)) => Some((latitude, longitude)),
             _ => None,
         }
     }
 
-    pub async fn load_all(
+    /// Reveal the exact coordinates of a sensitive source if the given user is allowed to edit it.
+    /// Everyone else, including the other members of its organization with a role that only
+    /// permits viewing, only gets to see the generalized coordinates.
+    pub async fn reveal_for(&mut self, userid: i64, pool: &Pool<Sqlite>) -> Result<()> {
+        self.revealed =
+            !self.sensitive || has_permission(self, userid, Permission::Edit, pool).await?;
+        Ok(())
+    }
+
+    /// Load all sources that match `filter`, regardless of who they belong to. Sources are listed
+    /// on behalf of a user with a [UserScope].
+    pub(crate) async fn load_all(
         filter: Option<DynFilterPart>,
         pool: &Pool<Sqlite>,
     ) -> Result<Vec<Source>> {
@@ -155,71 +535,171 @@ impl Source {
             .map_err(|e| e.into())
     }
 
+    /// Load a single page of the sources that match `filter`, sorted by name
+    pub async fn load_page(
+        filter: Option<DynFilterPart>,
+        limit: LimitSpec,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Source>> {
+        let mut builder = Self::build_query(filter);
+        builder.push(", srcid ASC");
+        limit.push_to(&mut builder);
+        Ok(builder.build_query_as().fetch_all(pool).await?)
+    }
+
+    /// Load the sources that the user has access to, i.e. the user's own sources and the sources of
+    /// the user's organizations
     pub async fn load_all_user(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Source>> {
-        Self::load_all(Some(Filter::UserId(userid).into()), pool).await
+        UserScope::new(userid).sources(None, pool).await
     }
 
-    pub async fn count(filter: Option<DynFilterPart>, pool: &Pool<Sqlite>) -> Result<i64> {
-        Self::build_count(filter)
-            .build()
-            .fetch_one(pool)
+    /// Load the sources within `radius_km` kilometers of the given coordinates that also match
+    /// `filter`, ordered by their distance. The database has no trigonometric functions, so the
+    /// query only selects the sources within a bounding box and the exact distance is computed
+    /// afterwards.
+    pub async fn load_near(
+        latitude: f64,
+        longitude: f64,
+        radius_km: f64,
+        filter: Option<DynFilterPart>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<NearbySource>> {
+        if !(-90.0..=90.0).contains(&latitude) {
+            return Err(Error::InvalidValue(format!("invalid latitude {latitude}")));
+        }
+        if !(-180.0..=180.0).contains(&longitude) {
+            return Err(Error::InvalidValue(format!(
+                "invalid longitude {longitude}"
+            )));
+        }
+        if !radius_km.is_finite() || radius_km <= 0.0 {
+            return Err(Error::InvalidValue(format!("invalid radius {radius_km}")));
+        }
+        let mut builder = CompoundFilter::builder(Op::And).push(Filter::Near {
+            latitude,
+            longitude,
+            radius_km,
+        });
+        if let Some(f) = filter {
+            builder = builder.push(f);
+        }
+        let mut nearby: Vec<NearbySource> = Self::load_all(Some(builder.build()), pool)
             .await?
-            .try_get("nsources")
+            .into_iter()
+            .filter_map(|source| {
+                let distance_km =
+                    distance_km(latitude, longitude, source.latitude?, source.longitude?);
+                (distance_km <= radius_km).then_some(NearbySource {
+                    source,
+                    distance_km,
+                })
+            })
+            .collect();
+        nearby.sort_by(|a, b| a.distance_km.total_cmp(&b.distance_km));
+        Ok(nearby)
+    }
+
+    /// Load the source with the given [uuid](Source::uuid)
+    pub async fn load_uuid(uuid: Uuid, pool: &Pool<Sqlite>) -> Result<Self> {
+        Self::build_query(Some(Filter::Uuid(uuid).into()))
+            .build_query_as()
+            .fetch_one(pool)
+            .await
             .map_err(|e| e.into())
     }
 
+    /// The number of sources that match `filter`
+    pub async fn count(filter: Option<DynFilterPart>, pool: &Pool<Sqlite>) -> Result<i64> {
+        Self::list_query(filter).fetch_count(pool).await
+    }
+
     pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
         if self.id != -1 {
             return Err(Error::InvalidOperationObjectAlreadyExists(self.id));
         }
+        plugin::before_source_save(self, pool).await?;
 
-        sqlx::query(
+        let res = sqlx::query(
             r#"INSERT INTO sc_sources
-          (srcname, srcdesc, latitude, longitude, userid)
-          VALUES (?, ?, ?, ?, ?)"#,
+          (srcname, srcdesc, latitude, longitude, userid, srcorgid, srcuuid, srchabitat,
+          srcmoisture, srclight, srcsensitive)
+          VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
         )
         .bind(&self.name)
         .bind(&self.description)
         .bind(self.latitude)
         .bind(self.longitude)
         .bind(self.userid)
+        .bind(self.orgid)
+        .bind(self.uuid.to_string())
+        .bind(self.habitat)
+        .bind(self.moisture)
+        .bind(self.light)
+        .bind(self.sensitive)
         .execute(pool)
-        .await
-        .map(|r| {
-            self.id = r.last_insert_rowid();
-            r
-        })
-        .map_err(|e| e.into())
+        .await?;
+        self.id = res.last_insert_rowid();
+        plugin::after_source_create(self, pool).await?;
+        Ok(res)
     }
 
-    pub async fn update(&self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+    /// Save the changes to this source to the database. If the source has been modified in the
+    /// database since it was loaded, this fails with [Error::DatabaseVersionConflict].
+    pub async fn update(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
         if self.id < 0 {
             return Err(Error::InvalidStateMissingAttribute("id".to_string()));
         }
+        plugin::before_source_save(self, pool).await?;
 
-        sqlx::query(
-            "UPDATE sc_sources SET srcname=?, srcdesc=?, latitude=?, longitude=? WHERE srcid=?",
+        let res = sqlx::query(
+            r#"UPDATE sc_sources SET srcname=?, srcdesc=?, latitude=?, longitude=?, srcorgid=?,
+            srchabitat=?, srcmoisture=?, srclight=?, srcsensitive=?,
+            srcversion=srcversion+1 WHERE srcid=? AND srcversion=?"#,
         )
         .bind(self.name.clone())
         .bind(self.description.as_ref().cloned())
         .bind(self.latitude)
         .bind(self.longitude)
+        .bind(self.orgid)
+        .bind(self.habitat)
+        .bind(self.moisture)
+        .bind(self.light)
+        .bind(self.sensitive)
         .bind(self.id)
+        .bind(self.version)
         .execute(pool)
-        .await
-        .map_err(|e| e.into())
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::DatabaseVersionConflict(self.version));
+        }
+        self.version += 1;
+        Ok(res)
     }
 
+    /// Delete the source. This fails with [Error::InvalidOperationObjectInUse] if any samples
+    /// still refer to it; use [Source::delete_with] to decide what happens to them instead.
     pub async fn delete(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
-        sqlx::query(r#"DELETE FROM sc_sources WHERE srcid=?1"#)
+        self.delete_with(OnDelete::Restrict, pool).await
+    }
+
+    /// Delete the source, handling the samples that refer to it as specified by `on_delete`
+    pub async fn delete_with(
+        &mut self,
+        on_delete: OnDelete,
+        pool: &Pool<Sqlite>,
+    ) -> Result<SqliteQueryResult> {
+        delete_source(self.id, on_delete, pool).await.inspect(|_| {
+            self.id = -1;
+        })
+    }
+
+    /// The number of samples that were collected from this source
+    pub async fn count_samples(&self, pool: &Pool<Sqlite>) -> Result<i64> {
+        sqlx::query_scalar("SELECT COUNT(*) FROM sc_samples WHERE srcid=?")
             .bind(self.id)
-            .execute(pool)
+            .fetch_one(pool)
             .await
             .map_err(|e| e.into())
-            .map(|r| {
-                self.id = -1;
-                r
-            })
     }
 
     pub fn new(
@@ -231,15 +711,33 @@ impl Source {
     ) -> Self {
         Self {
             id: -1,
+            uuid: Uuid::new_v4(),
             name,
             description,
             latitude,
             longitude,
             userid,
+            version: 1,
+            orgid: None,
+            habitat: None,
+            moisture: None,
+            light: None,
+            sensitive: false,
+            revealed: false,
         }
     }
 }
 
+impl Owned for Source {
+    fn owner(&self) -> i64 {
+        self.userid
+    }
+
+    fn organization(&self) -> Option<i64> {
+        self.orgid
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -260,9 +758,9 @@ mod tests {
         ) {
             let mut src = Source::new(name, desc, lat, lon, userid);
             // full data
-            let res = src.insert(&pool).await.expect("failed to insert");
+            let res = src.insert(pool).await.expect("failed to insert");
             assert_eq!(res.rows_affected(), 1);
-            let srcloaded = Source::load(res.last_insert_rowid(), &pool)
+            let srcloaded = Source::load(res.last_insert_rowid(), pool)
                 .await
                 .expect("Failed to load inserted object");
             assert_eq!(src, srcloaded);
@@ -298,4 +796,214 @@ mod tests {
         check(&pool, "test name".to_string(), None, None, None, 1).await;
         check(&pool, "".to_string(), None, None, None, 1).await;
     }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "assigned-samples")
+        )
+    ))]
+    async fn test_delete_sources(pool: Pool<Sqlite>) {
+        let mut unused = Source::new("unused".to_string(), None, None, None, 1);
+        unused.insert(&pool).await.unwrap();
+        Source::delete_id(&unused.id, &pool)
+            .await
+            .expect("Failed to delete unused source");
+
+        let mut src = Source::load(1, &pool).await.unwrap();
+        assert_eq!(src.count_samples(&pool).await.unwrap(), 1);
+        // sources that still have samples can't be deleted by default
+        assert!(matches!(
+            src.delete(&pool).await,
+            Err(Error::InvalidOperationObjectInUse(1))
+        ));
+        assert!(Source::delete_id(&1, &pool).await.is_err());
+        assert!(src.delete_with(OnDelete::Reassign(1), &pool).await.is_err());
+
+        src.delete_with(OnDelete::Reassign(2), &pool)
+            .await
+            .expect("Failed to delete source");
+        assert_eq!(src.id, -1);
+        assert!(Source::load(1, &pool).await.is_err());
+        let mut other = Source::load(2, &pool).await.unwrap();
+        assert_eq!(other.count_samples(&pool).await.unwrap(), 3);
+
+        // cascading removes the samples along with their allocations and notes
+        other
+            .delete_with(OnDelete::Cascade, &pool)
+            .await
+            .expect("Failed to delete source");
+        for table in ["sc_samples", "sc_project_samples", "sc_project_notes"] {
+            let n: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {table}"))
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+            assert_eq!(n, 0, "{table} is not empty");
+        }
+    }
+
+    #[test]
+    fn test_distance() {
+        // one degree along the equator
+        assert!((distance_km(0.0, 0.0, 0.0, 1.0) - 111.195).abs() < 0.01);
+        assert_eq!(distance_km(45.0, -93.0, 45.0, -93.0), 0.0);
+        // across the antimeridian
+        assert!((distance_km(0.0, 179.5, 0.0, -179.5) - 111.195).abs() < 0.01);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users", "sources"))
+    ))]
+    async fn test_load_near(pool: Pool<Sqlite>) {
+        let insert = |name: &str, lat: f64, lon: f64| {
+            let mut src = Source::new(name.to_string(), None, Some(lat), Some(lon), 1);
+            let pool = pool.clone();
+            async move {
+                src.insert(&pool).await.expect("failed to insert");
+                src.id
+            }
+        };
+        // about 8.5km north of source 1
+        let north = insert("north", 40.2, -90.123).await;
+        // on either side of the antimeridian
+        let east = insert("east", 10.0, 179.95).await;
+        let west = insert("west", 10.0, -179.95).await;
+        insert("no coordinates", 0.0, 0.0).await;
+        sqlx::query("UPDATE sc_sources SET latitude=NULL, longitude=NULL WHERE srcname=?")
+            .bind("no coordinates")
+            .execute(&pool)
+            .await
+            .expect("Failed to clear coordinates");
+
+        let ids =
+            |nearby: Vec<NearbySource>| nearby.iter().map(|n| n.source.id).collect::<Vec<_>>();
+        let nearby = Source::load_near(40.123, -90.123, 10.0, None, &pool)
+            .await
+            .expect("Failed to load sources");
+        assert_eq!(nearby[0].distance_km, 0.0);
+        assert_eq!(ids(nearby), vec![1, north]);
+        let nearby = Source::load_near(40.19, -90.123, 10.0, None, &pool)
+            .await
+            .expect("Failed to load sources");
+        // ordered by distance
+        assert_eq!(ids(nearby), vec![north, 1]);
+        let nearby = Source::load_near(40.123, -90.123, 5.0, None, &pool)
+            .await
+            .expect("Failed to load sources");
+        assert_eq!(ids(nearby), vec![1]);
+
+        let nearby = Source::load_near(10.0, 179.99, 20.0, None, &pool)
+            .await
+            .expect("Failed to load sources");
+        assert_eq!(ids(nearby), vec![east, west]);
+
+        // other filters still apply
+        let nearby = Source::load_near(
+            40.123,
+            -90.123,
+            10.0,
+            Some(Filter::Name(Cmp::Equal, "north".to_string()).into()),
+            &pool,
+        )
+        .await
+        .expect("Failed to load sources");
+        assert_eq!(ids(nearby), vec![north]);
+
+        for (lat, lon, radius) in [(91.0, 0.0, 1.0), (0.0, -181.0, 1.0), (0.0, 0.0, 0.0)] {
+            assert!(matches!(
+                Source::load_near(lat, lon, radius, None, &pool).await,
+                Err(Error::InvalidValue(_))
+            ));
+        }
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users", "sources"))
+    ))]
+    async fn test_habitat(pool: Pool<Sqlite>) {
+        let mut src = Source::load(1, &pool).await.unwrap();
+        assert_eq!(src.habitat, None);
+        src.habitat = Some(HabitatType::Prairie);
+        src.moisture = Some(SoilMoisture::DryMesic);
+        src.light = Some(LightCondition::FullSun);
+        src.update(&pool).await.expect("Failed to update source");
+        let loaded = Source::load(1, &pool).await.unwrap();
+        assert_eq!(loaded.habitat, Some(HabitatType::Prairie));
+        assert_eq!(loaded.moisture, Some(SoilMoisture::DryMesic));
+        assert_eq!(loaded.light, Some(LightCondition::FullSun));
+
+        let mut wet = Source::new("wet".to_string(), None, None, None, 1);
+        wet.habitat = Some(HabitatType::Wetland);
+        wet.moisture = Some(SoilMoisture::Wet);
+        wet.insert(&pool).await.expect("Failed to insert source");
+        assert_eq!(Source::load(wet.id, &pool).await.unwrap(), wet);
+
+        let ids = |sources: Vec<Source>| sources.iter().map(|s| s.id).collect::<Vec<_>>();
+        let found = Source::load_all(Some(Filter::Habitat(HabitatType::Prairie).into()), &pool)
+            .await
+            .unwrap();
+        assert_eq!(ids(found), vec![1]);
+        let found = Source::load_all(Some(Filter::Moisture(SoilMoisture::Wet).into()), &pool)
+            .await
+            .unwrap();
+        assert_eq!(ids(found), vec![wet.id]);
+        let found = Source::load_all(Some(Filter::Light(LightCondition::Shade).into()), &pool)
+            .await
+            .unwrap();
+        assert!(found.is_empty());
+
+        assert_eq!(
+            "dry-mesic".parse::<SoilMoisture>(),
+            Ok(SoilMoisture::DryMesic)
+        );
+        assert_eq!(LightCondition::PartShade.to_string(), "part-shade");
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users", "sources"))
+    ))]
+    async fn test_sensitive(pool: Pool<Sqlite>) {
+        let mut src = Source::load(1, &pool).await.unwrap();
+        src.sensitive = true;
+        src.update(&pool).await.expect("Failed to update source");
+
+        let mut src = Source::load(1, &pool).await.unwrap();
+        assert!(src.sensitive);
+        // coordinates are generalized until they are revealed
+        assert!(src.is_generalized());
+        assert_eq!(src.visible_coordinates(), Some((40.1, -90.1)));
+        let json = serde_json::to_value(&src).unwrap();
+        assert_eq!(json["latitude"], 40.1);
+        assert_eq!(json["longitude"], -90.1);
+        assert_eq!(json["generalized"], true);
+        // the exact coordinates are still saved
+        assert_eq!(src.latitude, Some(40.123));
+
+        // a user that can't edit the source only gets the generalized coordinates
+        src.reveal_for(2, &pool).await.unwrap();
+        assert!(src.is_generalized());
+        src.reveal_for(1, &pool).await.unwrap();
+        assert!(!src.is_generalized());
+        let json = serde_json::to_value(&src).unwrap();
+        assert_eq!(json["latitude"], 40.123);
+        assert_eq!(json["generalized"], false);
+
+        let mut src = Source::load(2, &pool).await.unwrap();
+        assert!(!src.is_generalized());
+        src.reveal_for(2, &pool).await.unwrap();
+        assert_eq!(src.visible_coordinates(), Some((34.123, -83.123)));
+
+        let mut nearby = Source::load_near(40.123, -90.123, 10.0, None, &pool)
+            .await
+            .unwrap();
+        nearby[0]
+            .reveal_for(2, 40.123, -90.123, &pool)
+            .await
+            .unwrap();
+        assert!(nearby[0].distance_km > 1.0);
+    }
 }
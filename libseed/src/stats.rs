@@ -0,0 +1,691 @@
+//! Aggregate statistics about a user's collection. These are computed with dedicated aggregate
+//! queries so that they don't require loading every object from the database.
+use crate::{
+    error::{Error, Result},
+    project::NoteType,
+    taxonomy::{Rank, KINGDOM_PLANTAE},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, FromRow, Pool, QueryBuilder, Row, Sqlite};
+use std::ops::RangeInclusive;
+use time::{Date, Month};
+
+/// The number of items that fall into a particular group. Items that could not be assigned to any
+/// group (e.g. samples without a collection year) are counted with an empty `label`
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct GroupCount {
+    /// the database id of the group (e.g. the family's taxon id), if the group has one
+    pub id: Option<i64>,
+    pub label: Option<String>,
+    pub count: i64,
+}
+
+impl FromRow<'_, SqliteRow> for GroupCount {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            id: row.try_get("id").unwrap_or(None),
+            label: row.try_get("label")?,
+            count: row.try_get("count")?,
+        })
+    }
+}
+
+/// Count the user's samples for each taxonomic family
+pub async fn samples_per_family(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<GroupCount>> {
+    Ok(sqlx::query_as(
+        r#"WITH RECURSIVE ancestors(sampleid, tsn, parent_tsn, rank_id) AS (
+            SELECT S.sampleid, T.tsn, T.parent_tsn, T.rank_id
+            FROM sc_samples S INNER JOIN taxonomic_units T ON T.tsn=S.tsn
+            WHERE S.userid=?
+            UNION ALL
+            SELECT A.sampleid, T.tsn, T.parent_tsn, T.rank_id
+            FROM ancestors A INNER JOIN taxonomic_units T ON T.tsn=A.parent_tsn
+            WHERE A.rank_id > ? AND T.kingdom_id=?
+        )
+        SELECT F.tsn AS id, F.complete_name AS label, COUNT(A.sampleid) AS count
+        FROM ancestors A INNER JOIN taxonomic_units F ON F.tsn=A.tsn
+        WHERE A.rank_id=?
+        GROUP BY F.tsn
+        ORDER BY count DESC, F.phylo_sort_seq"#,
+    )
+    .bind(userid)
+    .bind(Rank::Family as i64)
+    .bind(KINGDOM_PLANTAE)
+    .bind(Rank::Family as i64)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// The months that make up a year in per-year reports. By default this is the calendar year, but a
+/// reporting year can also run e.g. from July to June. A collection year is identified by the
+/// calendar year that it starts in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CollectionYear {
+    start: Month,
+}
+
+impl Default for CollectionYear {
+    fn default() -> Self {
+        Self {
+            start: Month::January,
+        }
+    }
+}
+
+impl CollectionYear {
+    /// A collection year that starts on the first day of the given month (1-12)
+    pub fn starting_in(month: u8) -> Result<Self> {
+        Month::try_from(month)
+            .map(|start| Self { start })
+            .map_err(|_| Error::InvalidValue(format!("{month} is not a valid month")))
+    }
+
+    pub fn start_month(&self) -> Month {
+        self.start
+    }
+
+    /// The collection year that a sample from the given month and calendar year belongs to.
+    /// Samples without a month are counted in the collection year that starts in their calendar
+    /// year.
+    pub fn year_of(&self, month: Option<u32>, year: u32) -> u32 {
+        match month {
+            Some(m) if m < u32::from(u8::from(self.start)) => year - 1,
+            _ => year,
+        }
+    }
+
+    /// A human-readable name for the given collection year, e.g. `2023` or `2023/24`
+    pub fn label(&self, year: u32) -> String {
+        match self.start {
+            Month::January => year.to_string(),
+            _ => format!("{year}/{:02}", (year + 1) % 100),
+        }
+    }
+
+    /// An SQL expression that computes the collection year from the given month and year
+    /// expressions
+    pub(crate) fn sql(&self, month: &str, year: &str) -> String {
+        format!(
+            "(CASE WHEN {month} < {} THEN {year} - 1 ELSE {year} END)",
+            u8::from(self.start)
+        )
+    }
+}
+
+/// Count the user's samples for each collection year
+pub async fn samples_per_year(
+    userid: i64,
+    years: &CollectionYear,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<GroupCount>> {
+    let rows = sqlx::query(&format!(
+        r#"SELECT {} AS cyear, COUNT(sampleid) AS count
+        FROM sc_samples WHERE userid=?
+        GROUP BY cyear ORDER BY cyear"#,
+        years.sql("month", "year")
+    ))
+    .bind(userid)
+    .fetch_all(pool)
+    .await?;
+    rows.iter()
+        .map(|row| {
+            Ok(GroupCount {
+                id: None,
+                label: row
+                    .try_get::<Option<u32>, _>("cyear")?
+                    .map(|y| years.label(y)),
+                count: row.try_get("count")?,
+            })
+        })
+        .collect()
+}
+
+/// Count the user's samples for each source
+pub async fn samples_per_source(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<GroupCount>> {
+    Ok(sqlx::query_as(
+        r#"SELECT L.srcid AS id, L.srcname AS label, COUNT(S.sampleid) AS count
+        FROM sc_sources L INNER JOIN sc_samples S ON S.srcid=L.srcid
+        WHERE S.userid=?
+        GROUP BY L.srcid ORDER BY count DESC, L.srcname"#,
+    )
+    .bind(userid)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Count the user's samples that were collected and the ones that were purchased
+pub async fn samples_per_origin(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<GroupCount>> {
+    Ok(sqlx::query_as(
+        r#"SELECT CASE WHEN purchasevendor IS NULL THEN 'Collected' ELSE 'Purchased' END AS label,
+        COUNT(sampleid) AS count
+        FROM sc_samples WHERE userid=?
+        GROUP BY label ORDER BY label"#,
+    )
+    .bind(userid)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// What the user bought from a single vendor in a single collection year
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, FromRow)]
+pub struct VendorSummary {
+    pub vendor: String,
+    /// the collection year of purchase, or of the sample if the purchase date isn't known
+    pub year: Option<u32>,
+    pub nsamples: i64,
+    /// the total quantity of the samples that have a known quantity
+    pub quantity: Option<i64>,
+    /// the total price of the samples that have a known price
+    pub spent: Option<f64>,
+}
+
+/// Summarize the user's purchased samples per vendor and collection year
+pub async fn vendor_summary(
+    userid: i64,
+    years: &CollectionYear,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<VendorSummary>> {
+    Ok(sqlx::query_as(&format!(
+        r#"SELECT purchasevendor AS vendor, COALESCE({}, {}) AS year,
+        COUNT(sampleid) AS nsamples, SUM(quantity) AS quantity, SUM(purchaseprice) AS spent
+        FROM sc_samples WHERE userid=? AND purchasevendor IS NOT NULL
+        GROUP BY purchasevendor COLLATE NOCASE, 2
+        ORDER BY purchasevendor COLLATE NOCASE, 2"#,
+        years.sql(
+            "CAST(strftime('%m', purchasedate) AS INTEGER)",
+            "CAST(strftime('%Y', purchasedate) AS INTEGER)"
+        ),
+        years.sql("month", "year"),
+    ))
+    .bind(userid)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// The number of samples allocated to a project, grouped by the type of the most recent note for
+/// each sample. This gives a rough indication of how far along the samples in the project are.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ProjectStatus {
+    pub id: i64,
+    pub name: String,
+    pub statuses: Vec<GroupCount>,
+    /// the number of samples of each quality grade, see [project_grades]
+    pub grades: Vec<GroupCount>,
+}
+
+/// Summarize the status of each of the user's projects
+pub async fn project_status(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<ProjectStatus>> {
+    let rows = sqlx::query(
+        r#"SELECT P.projectid, P.projname, N.notetype, COUNT(PS.psid) AS count
+        FROM sc_projects P
+        LEFT JOIN sc_project_samples PS ON PS.projectid=P.projectid
+        LEFT JOIN (SELECT * FROM
+            (SELECT psid, notetype, ROW_NUMBER() OVER
+                (PARTITION BY psid ORDER BY DATE(notedate) DESC, pnoteid DESC) AS rownr
+            FROM sc_project_notes)
+            WHERE rownr = 1) N ON N.psid=PS.psid
+        WHERE P.userid=?
+        GROUP BY P.projectid, N.notetype
+        ORDER BY P.projectid, N.notetype"#,
+    )
+    .bind(userid)
+    .fetch_all(pool)
+    .await?;
+
+    let mut projects: Vec<ProjectStatus> = Vec::new();
+    for row in rows {
+        let id: i64 = row.try_get("projectid")?;
+        let count: i64 = row.try_get("count")?;
+        let kind: Option<NoteType> = row.try_get("notetype")?;
+        if projects.last().map(|p| p.id) != Some(id) {
+            projects.push(ProjectStatus {
+                id,
+                name: row.try_get("projname")?,
+                statuses: Vec::new(),
+                grades: project_grades(id, pool).await?,
+            });
+        }
+        if count > 0 {
+            if let Some(project) = projects.last_mut() {
+                project.statuses.push(GroupCount {
+                    id: kind.map(|k| k as i64),
+                    label: kind.map(|k| format!("{k:?}")),
+                    count,
+                });
+            }
+        }
+    }
+    Ok(projects)
+}
+
+/// The number of samples allocated to a project for each quality grade, from the best grade to
+/// the worst. The samples without a grade are counted last with an empty label.
+pub async fn project_grades(projectid: i64, pool: &Pool<Sqlite>) -> Result<Vec<GroupCount>> {
+    Ok(sqlx::query_as(
+        r#"SELECT G.gradeid AS id, G.gradelabel AS label, COUNT(PS.psid) AS count
+        FROM sc_project_samples PS
+        INNER JOIN sc_samples S ON S.sampleid=PS.sampleid
+        LEFT JOIN sc_quality_grades G ON G.gradeid=S.gradeid
+        WHERE PS.projectid=?
+        GROUP BY G.gradeid
+        ORDER BY G.graderank IS NULL, G.graderank, G.gradelabel"#,
+    )
+    .bind(projectid)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// The size of a user's collection on a particular day, see [record_snapshots]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, FromRow)]
+pub struct Snapshot {
+    #[sqlx(rename = "snapdate")]
+    pub date: Date,
+    pub nsamples: i64,
+    /// the number of distinct taxa of the user's samples
+    pub ntaxa: i64,
+    pub nsources: i64,
+    /// the total quantity of the samples that have a known quantity
+    pub quantity: i64,
+}
+
+/// Record a snapshot of the collection statistics dated `today` for every user that has samples
+/// and that doesn't have a snapshot from the last `interval_days` days yet. Returns the number of
+/// snapshots that were recorded.
+pub async fn record_snapshots(today: Date, interval_days: u32, pool: &Pool<Sqlite>) -> Result<u64> {
+    let since = today - time::Duration::days(i64::from(interval_days.max(1)) - 1);
+    Ok(sqlx::query(
+        r#"INSERT INTO sc_stats_snapshots (userid, snapdate, nsamples, ntaxa, nsources, quantity)
+        SELECT S.userid, ?, COUNT(S.sampleid), COUNT(DISTINCT S.tsn),
+            (SELECT COUNT(L.srcid) FROM sc_sources L WHERE L.userid=S.userid),
+            COALESCE(SUM(S.quantity), 0)
+        FROM sc_samples S
+        WHERE NOT EXISTS (SELECT H.snapshotid FROM sc_stats_snapshots H
+            WHERE H.userid=S.userid AND H.snapdate >= ?)
+        GROUP BY S.userid"#,
+    )
+    .bind(today)
+    .bind(since)
+    .execute(pool)
+    .await?
+    .rows_affected())
+}
+
+/// All recorded snapshots of the user's collection, the oldest first
+pub async fn snapshot_history(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Snapshot>> {
+    Ok(sqlx::query_as(
+        r#"SELECT snapdate, nsamples, ntaxa, nsources, quantity
+        FROM sc_stats_snapshots WHERE userid=? ORDER BY snapdate"#,
+    )
+    .bind(userid)
+    .fetch_all(pool)
+    .await?)
+}
+
+/// The number of weeks in the collection calendar. The last day or two of the year are counted in
+/// the last week.
+pub const CALENDAR_WEEKS: usize = 52;
+
+/// A year without a leap day, so that the calendar weeks of a month are the same every year
+const CALENDAR_YEAR: i32 = 2023;
+
+/// The (0-based) week of the collection calendar that the given day of the year falls in
+pub fn calendar_week(date: Date) -> usize {
+    ((usize::from(date.ordinal()) - 1) / 7).min(CALENDAR_WEEKS - 1)
+}
+
+/// The weeks of the collection calendar that overlap with the given month
+pub fn month_weeks(month: Month) -> RangeInclusive<usize> {
+    let first = Date::from_calendar_date(CALENDAR_YEAR, month, 1).expect("valid date");
+    let last = first
+        .replace_day(month.length(CALENDAR_YEAR))
+        .expect("valid date");
+    calendar_week(first)..=calendar_week(last)
+}
+
+/// The months of the year and the number of calendar weeks that start in each of them, e.g. for
+/// labelling the columns of the calendar
+pub fn calendar_months() -> Vec<(Month, usize)> {
+    let mut months: Vec<(Month, usize)> = Vec::new();
+    for week in 0..CALENDAR_WEEKS {
+        let start =
+            Date::from_ordinal_date(CALENDAR_YEAR, (week * 7 + 1) as u16).expect("valid date");
+        match months.last_mut() {
+            Some((month, n)) if *month == start.month() => *n += 1,
+            _ => months.push((start.month(), 1)),
+        }
+    }
+    months
+}
+
+/// A row of the collection calendar: the number of the user's samples of a taxon that were
+/// collected in each week of the year, regardless of the year of collection. Samples only record
+/// the month that they were collected, so a sample is counted in every week of its month.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct CalendarRow {
+    pub id: i64,
+    pub name: String,
+    pub nsamples: i64,
+    pub weeks: Vec<i64>,
+}
+
+/// Lay out which taxa are typically ready for collection in each week of the year, based on the
+/// collection months of the user's samples. Samples can optionally be limited to a single source.
+pub async fn collection_calendar(
+    userid: i64,
+    source: Option<i64>,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<CalendarRow>> {
+    let mut builder = QueryBuilder::new(
+        r#"SELECT S.tsn, T.complete_name, S.month, COUNT(S.sampleid) AS count
+        FROM sc_samples S INNER JOIN taxonomic_units T ON T.tsn=S.tsn
+        WHERE S.month BETWEEN 1 AND 12 AND S.userid="#,
+    );
+    builder.push_bind(userid);
+    if let Some(srcid) = source {
+        builder.push(" AND S.srcid=").push_bind(srcid);
+    }
+    builder.push(" GROUP BY S.tsn, S.month ORDER BY S.tsn");
+    let rows = builder.build().fetch_all(pool).await?;
+
+    let mut calendar: Vec<CalendarRow> = Vec::new();
+    for row in rows {
+        let id: i64 = row.try_get("tsn")?;
+        let count: i64 = row.try_get("count")?;
+        let Ok(month) = Month::try_from(row.try_get::<u8, _>("month")?) else {
+            continue;
+        };
+        if calendar.last().map(|r| r.id) != Some(id) {
+            calendar.push(CalendarRow {
+                id,
+                name: row.try_get("complete_name")?,
+                nsamples: 0,
+                weeks: vec![0; CALENDAR_WEEKS],
+            });
+        }
+        if let Some(entry) = calendar.last_mut() {
+            entry.nsamples += count;
+            for week in month_weeks(month) {
+                entry.weeks[week] += count;
+            }
+        }
+    }
+    // list the taxa in the order that they become ready for collection
+    calendar.sort_by_cached_key(|r| (r.weeks.iter().position(|n| *n > 0), r.name.clone()));
+    Ok(calendar)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{grade::QualityGrade, loadable::Loadable, sample::Sample};
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "csnotes")
+        )
+    ))]
+    async fn test_stats(pool: Pool<Sqlite>) {
+        let families = samples_per_family(1, &pool)
+            .await
+            .expect("Failed to count families");
+        assert_eq!(
+            families,
+            vec![
+                GroupCount {
+                    id: Some(40351),
+                    label: Some("Poaceae".to_string()),
+                    count: 2
+                },
+                GroupCount {
+                    id: Some(43190),
+                    label: Some("Iridaceae".to_string()),
+                    count: 1
+                },
+            ]
+        );
+
+        let years = samples_per_year(1, &CollectionYear::default(), &pool)
+            .await
+            .expect("Failed to count years");
+        assert_eq!(years.len(), 2);
+        assert_eq!(years[0].label.as_deref(), Some("2022"));
+        assert_eq!(years[0].count, 2);
+        assert_eq!(years[1].label.as_deref(), Some("2023"));
+        assert_eq!(years[1].count, 1);
+
+        // sample 1 was collected in September 2023, the others don't have a month
+        let july = CollectionYear::starting_in(7).unwrap();
+        let years = samples_per_year(1, &july, &pool)
+            .await
+            .expect("Failed to count years");
+        assert_eq!(
+            years
+                .iter()
+                .map(|g| (g.label.as_deref(), g.count))
+                .collect::<Vec<_>>(),
+            vec![(Some("2022/23"), 2), (Some("2023/24"), 1)]
+        );
+        let october = CollectionYear::starting_in(10).unwrap();
+        let years = samples_per_year(1, &october, &pool)
+            .await
+            .expect("Failed to count years");
+        assert_eq!(
+            years
+                .iter()
+                .map(|g| (g.label.as_deref(), g.count))
+                .collect::<Vec<_>>(),
+            vec![(Some("2022/23"), 3)]
+        );
+
+        let sources = samples_per_source(1, &pool)
+            .await
+            .expect("Failed to count sources");
+        let total: i64 = sources.iter().map(|s| s.count).sum();
+        assert_eq!(total, 3);
+
+        let projects = project_status(1, &pool)
+            .await
+            .expect("Failed to summarize projects");
+        assert_eq!(projects.len(), 2);
+        let allocated: i64 = projects
+            .iter()
+            .flat_map(|p| p.statuses.iter().map(|s| s.count))
+            .sum();
+        assert_eq!(allocated, 3);
+        let graded: i64 = projects
+            .iter()
+            .flat_map(|p| p.grades.iter().map(|g| g.count))
+            .sum();
+        assert_eq!(graded, 3);
+
+        let scale = QualityGrade::create_default_scale(1, &pool).await.unwrap();
+        let (projectid, sampleid): (i64, i64) =
+            sqlx::query_as("SELECT projectid, sampleid FROM sc_project_samples ORDER BY psid")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        let mut sample = Sample::load(sampleid, &pool).await.unwrap();
+        sample.set_grade(Some(&scale[1]), &pool).await.unwrap();
+        let grades = project_grades(projectid, &pool).await.unwrap();
+        assert_eq!(grades[0].label.as_deref(), Some("B"));
+        assert_eq!(grades[0].count, 1);
+        assert_eq!(grades.last().unwrap().label, None);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn test_vendor_summary(pool: Pool<Sqlite>) {
+        sqlx::query(
+            r#"INSERT INTO sc_samples (tsn, userid, srcid, year, quantity, purchasevendor,
+                purchasedate, purchaseprice)
+            VALUES (40683, 1, 1, 2022, 500, 'Prairie Moon', '2023-02-01', 12.5),
+                (43254, 1, 1, 2023, 200, 'prairie moon', NULL, 4.0),
+                (43254, 1, 1, 2023, NULL, 'Prairie Moon', '2023-03-15', NULL),
+                (43254, 1, 1, 2022, 50, 'Seed Savers', NULL, NULL),
+                (43254, 2, 1, 2023, 50, 'Seed Savers', NULL, 3.0)"#,
+        )
+        .execute(&pool)
+        .await
+        .expect("Failed to insert samples");
+
+        let origins = samples_per_origin(1, &pool)
+            .await
+            .expect("Failed to count origins");
+        assert_eq!(
+            origins
+                .iter()
+                .map(|g| (g.label.as_deref(), g.count))
+                .collect::<Vec<_>>(),
+            vec![(Some("Collected"), 3), (Some("Purchased"), 4)]
+        );
+
+        let summary = vendor_summary(1, &CollectionYear::default(), &pool)
+            .await
+            .expect("Failed to summarize vendors");
+        assert_eq!(summary.len(), 2);
+        // vendor names are compared without case, and the purchase date takes precedence over
+        // the year of the sample
+        assert_eq!(summary[0].vendor.to_lowercase(), "prairie moon");
+        assert_eq!(summary[0].year, Some(2023));
+        assert_eq!(summary[0].nsamples, 3);
+        assert_eq!(summary[0].quantity, Some(700));
+        assert_eq!(summary[0].spent, Some(16.5));
+        assert_eq!(summary[1].vendor, "Seed Savers");
+        assert_eq!(summary[1].year, Some(2022));
+        assert_eq!(summary[1].quantity, Some(50));
+        assert_eq!(summary[1].spent, None);
+
+        // with a year that starts in March, the purchase in February falls in the previous year
+        let march = CollectionYear::starting_in(3).unwrap();
+        let summary = vendor_summary(1, &march, &pool)
+            .await
+            .expect("Failed to summarize vendors");
+        assert_eq!(
+            summary
+                .iter()
+                .map(|v| (v.year, v.nsamples))
+                .collect::<Vec<_>>(),
+            vec![(Some(2022), 1), (Some(2023), 2), (Some(2022), 1)]
+        );
+    }
+
+    #[test]
+    fn test_collection_year() {
+        let calendar = CollectionYear::default();
+        assert_eq!(calendar.year_of(Some(1), 2024), 2024);
+        assert_eq!(calendar.label(2024), "2024");
+        let july = CollectionYear::starting_in(7).unwrap();
+        assert_eq!(july.start_month(), Month::July);
+        assert_eq!(july.year_of(Some(6), 2024), 2023);
+        assert_eq!(july.year_of(Some(7), 2024), 2024);
+        assert_eq!(july.year_of(None, 2024), 2024);
+        assert_eq!(july.label(2023), "2023/24");
+        assert_eq!(july.label(1999), "1999/00");
+        assert!(CollectionYear::starting_in(0).is_err());
+        assert!(CollectionYear::starting_in(13).is_err());
+    }
+
+    #[test]
+    fn test_calendar_weeks() {
+        assert_eq!(month_weeks(Month::January), 0..=4);
+        assert_eq!(month_weeks(Month::February), 4..=8);
+        assert_eq!(month_weeks(Month::December), 47..=51);
+        let last = Date::from_calendar_date(2024, Month::December, 31).unwrap();
+        assert_eq!(calendar_week(last), CALENDAR_WEEKS - 1);
+        let months = calendar_months();
+        assert_eq!(months.len(), 12);
+        assert_eq!(months.iter().map(|(_, n)| n).sum::<usize>(), CALENDAR_WEEKS);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn test_collection_calendar(pool: Pool<Sqlite>) {
+        let calendar = collection_calendar(1, None, &pool)
+            .await
+            .expect("Failed to build calendar");
+        // samples 2 and 3 were collected in October and November, sample 1 in December
+        assert_eq!(
+            calendar.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![40683, 43254]
+        );
+        let early = &calendar[0];
+        assert_eq!(early.nsamples, 2);
+        assert_eq!(early.weeks.len(), CALENDAR_WEEKS);
+        assert_eq!(early.weeks.iter().position(|n| *n > 0), Some(39));
+        // the week that overlaps both months counts both samples
+        assert_eq!(early.weeks[43], 2);
+        assert_eq!(early.weeks[47], 1);
+        assert_eq!(early.weeks[48], 0);
+
+        // sample 4 belongs to a different user
+        let calendar = collection_calendar(2, None, &pool)
+            .await
+            .expect("Failed to build calendar");
+        assert_eq!(calendar.len(), 1);
+        assert_eq!(calendar[0].nsamples, 1);
+
+        // only sample 2 was collected at source 2
+        let calendar = collection_calendar(1, Some(2), &pool)
+            .await
+            .expect("Failed to build calendar");
+        assert_eq!(calendar.len(), 1);
+        assert_eq!(calendar[0].id, 40683);
+        assert_eq!(calendar[0].weeks[43], 1);
+        assert_eq!(calendar[0].weeks[44], 0);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn test_snapshots(pool: Pool<Sqlite>) {
+        let start = time::macros::date!(2024 - 03 - 01);
+        assert_eq!(record_snapshots(start, 7, &pool).await.unwrap(), 2);
+        let history = snapshot_history(1, &pool).await.unwrap();
+        assert_eq!(
+            history,
+            vec![Snapshot {
+                date: start,
+                nsamples: 3,
+                ntaxa: 2,
+                nsources: 2,
+                quantity: 100
+            }]
+        );
+
+        // nothing is recorded again until the interval has passed
+        let next = start + time::Duration::days(6);
+        assert_eq!(record_snapshots(next, 7, &pool).await.unwrap(), 0);
+        sqlx::query("DELETE FROM sc_samples WHERE sampleid=3")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let next = next + time::Duration::days(1);
+        assert_eq!(record_snapshots(next, 7, &pool).await.unwrap(), 2);
+        let history = snapshot_history(1, &pool).await.unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|s| (s.date, s.nsamples, s.ntaxa))
+                .collect::<Vec<_>>(),
+            vec![(start, 3, 2), (next, 2, 2)]
+        );
+        assert_eq!(snapshot_history(2, &pool).await.unwrap().len(), 2);
+    }
+}
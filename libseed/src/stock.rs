@@ -0,0 +1,298 @@
+//! Low stock alerts. A user can set the minimum quantity that they want to keep of a single sample
+//! or of each sample of a taxon. A threshold for a sample takes precedence over the threshold for
+//! its taxon. Samples whose quantity falls below their threshold are shown as low on stock, and
+//! the user can choose to be notified about them by email.
+use crate::{
+    error::{Error, Result},
+    filter::{CompoundFilter, Op},
+    sample::{self, Sample},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{sqlite::SqliteRow, FromRow, Pool, QueryBuilder, Row, Sqlite};
+
+/// What a threshold applies to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThresholdTarget {
+    /// the sample with the given id
+    Sample(i64),
+    /// all samples of the taxon with the given id
+    Taxon(i64),
+}
+
+impl ThresholdTarget {
+    fn ids(&self) -> (Option<i64>, Option<i64>) {
+        match self {
+            ThresholdTarget::Sample(id) => (Some(*id), None),
+            ThresholdTarget::Taxon(id) => (None, Some(*id)),
+        }
+    }
+}
+
+/// The minimum quantity that a user wants to keep of a sample or a taxon
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Threshold {
+    #[sqlx(rename = "thresholdid")]
+    pub id: i64,
+    pub userid: i64,
+    pub sampleid: Option<i64>,
+    pub tsn: Option<i64>,
+    #[sqlx(rename = "minquantity")]
+    pub quantity: i64,
+}
+
+impl Threshold {
+    /// Load the user's threshold for the given target, if there is one. The threshold of a
+    /// sample's taxon isn't returned for the sample.
+    pub async fn load(
+        userid: i64,
+        target: ThresholdTarget,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Option<Self>> {
+        let (sampleid, tsn) = target.ids();
+        sqlx::query_as(
+            r#"SELECT * FROM sc_stock_thresholds WHERE userid=?
+            AND (sampleid=? OR tsn=?)"#,
+        )
+        .bind(userid)
+        .bind(sampleid)
+        .bind(tsn)
+        .fetch_optional(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Save the user's threshold for the given target. Saving no quantity removes the threshold.
+    pub async fn save(
+        userid: i64,
+        target: ThresholdTarget,
+        quantity: Option<i64>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Option<Self>> {
+        let (sampleid, tsn) = target.ids();
+        let Some(quantity) = quantity else {
+            sqlx::query("DELETE FROM sc_stock_thresholds WHERE userid=? AND (sampleid=? OR tsn=?)")
+                .bind(userid)
+                .bind(sampleid)
+                .bind(tsn)
+                .execute(pool)
+                .await?;
+            return Ok(None);
+        };
+        if quantity < 0 {
+            return Err(Error::InvalidValue(
+                "The minimum quantity can't be negative".to_string(),
+            ));
+        }
+        let conflict = match target {
+            ThresholdTarget::Sample(_) => "(userid, sampleid) WHERE sampleid IS NOT NULL",
+            ThresholdTarget::Taxon(_) => "(userid, tsn) WHERE tsn IS NOT NULL",
+        };
+        sqlx::query(&format!(
+            r#"INSERT INTO sc_stock_thresholds (userid, sampleid, tsn, minquantity)
+            VALUES (?, ?, ?, ?)
+            ON CONFLICT{conflict} DO UPDATE SET minquantity=excluded.minquantity"#
+        ))
+        .bind(userid)
+        .bind(sampleid)
+        .bind(tsn)
+        .bind(quantity)
+        .execute(pool)
+        .await?;
+        Self::load(userid, target, pool).await
+    }
+}
+
+/// Add a condition that matches the samples whose quantity is below the user's threshold for
+/// them. Samples without a recorded quantity are never low on stock.
+pub(crate) fn push_low_stock_condition(builder: &mut QueryBuilder<Sqlite>, userid: i64) {
+    builder
+        .push(
+            r#" sampleid IN (SELECT S.sampleid FROM sc_samples S
+            WHERE S.quantity < COALESCE(
+                (SELECT T.minquantity FROM sc_stock_thresholds T WHERE T.sampleid=S.sampleid AND T.userid="#,
+        )
+        .push_bind(userid)
+        .push(
+            r#"),
+                (SELECT T.minquantity FROM sc_stock_thresholds T WHERE T.tsn=S.tsn AND T.userid="#,
+        )
+        .push_bind(userid)
+        .push(")))");
+}
+
+/// A sample that is low on stock
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct LowStock {
+    pub sample: Sample,
+    /// the minimum quantity of the sample
+    pub threshold: i64,
+    /// whether the threshold was set for the sample's taxon rather than for the sample itself
+    pub taxon_threshold: bool,
+}
+
+impl FromRow<'_, SqliteRow> for LowStock {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        let sample_threshold: Option<i64> = row.try_get("samplethreshold")?;
+        let taxon_threshold: Option<i64> = row.try_get("taxonthreshold")?;
+        Ok(Self {
+            sample: Sample::from_row(row)?,
+            threshold: sample_threshold.or(taxon_threshold).unwrap_or_default(),
+            taxon_threshold: sample_threshold.is_none(),
+        })
+    }
+}
+
+impl LowStock {
+    fn build_query(userid: i64, unalerted: bool) -> QueryBuilder<'static, Sqlite> {
+        let mut builder = QueryBuilder::new(
+            r#"SELECT vsamples.*,
+            (SELECT T.minquantity FROM sc_stock_thresholds T
+                WHERE T.sampleid=vsamples.sampleid AND T.userid="#,
+        );
+        builder
+            .push_bind(userid)
+            .push(
+                r#") AS samplethreshold,
+            (SELECT T.minquantity FROM sc_stock_thresholds T
+                WHERE T.tsn=vsamples.tsn AND T.userid="#,
+            )
+            .push_bind(userid)
+            .push(") AS taxonthreshold FROM vsamples WHERE ");
+        CompoundFilter::builder(Op::And)
+            .push(sample::Filter::Accessible(userid))
+            .push(sample::Filter::LowStock(userid))
+            .build()
+            .add_to_query(&mut builder);
+        if unalerted {
+            builder
+                .push(" AND sampleid NOT IN (SELECT sampleid FROM sc_stock_alerts WHERE userid=")
+                .push_bind(userid)
+                .push(")");
+        }
+        builder.push(" ORDER BY seq, sampleid");
+        builder
+    }
+
+    /// Load the samples that the user has access to and that are below the user's thresholds, in
+    /// taxonomic order
+    pub async fn load_user(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        Self::build_query(userid, false)
+            .build_query_as()
+            .fetch_all(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Load the samples that are low on stock that the user hasn't been alerted about yet. The
+    /// alerts of samples that are no longer low on stock are forgotten, so that the user is
+    /// alerted again if they run low again.
+    pub async fn load_unalerted(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        let mut builder = QueryBuilder::new("DELETE FROM sc_stock_alerts WHERE userid=");
+        builder.push_bind(userid).push(" AND NOT");
+        push_low_stock_condition(&mut builder, userid);
+        builder.build().execute(pool).await?;
+        Self::build_query(userid, true)
+            .build_query_as()
+            .fetch_all(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Record that the user was alerted about the given samples
+    pub async fn mark_alerted(userid: i64, items: &[Self], pool: &Pool<Sqlite>) -> Result<()> {
+        for item in items {
+            sqlx::query("INSERT OR IGNORE INTO sc_stock_alerts (userid, sampleid) VALUES (?, ?)")
+                .bind(userid)
+                .bind(item.sample.id)
+                .execute(pool)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// The users that want to be alerted by email when their samples are low on stock
+pub async fn users_with_email_alerts(pool: &Pool<Sqlite>) -> Result<Vec<i64>> {
+    sqlx::query_scalar("SELECT userid FROM sc_user_prefs WHERE emaillowstock=1 ORDER BY userid")
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preferences::Preferences;
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn low_stock(pool: Pool<Sqlite>) {
+        // sample 2 has a quantity of 100, sample 3 of the same taxon has no quantity
+        assert!(LowStock::load_user(1, &pool).await.unwrap().is_empty());
+        assert!(
+            Threshold::save(1, ThresholdTarget::Sample(2), Some(-1), &pool)
+                .await
+                .is_err()
+        );
+
+        // taxon thresholds apply to all of the user's samples of the taxon with a quantity
+        let taxon = Threshold::save(1, ThresholdTarget::Taxon(40683), Some(150), &pool)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(taxon.quantity, 150);
+        let low = LowStock::load_user(1, &pool).await.unwrap();
+        assert_eq!(low.len(), 1);
+        assert_eq!(low[0].sample.id, 2);
+        assert_eq!(low[0].threshold, 150);
+        assert!(low[0].taxon_threshold);
+        // thresholds are personal
+        assert!(LowStock::load_user(2, &pool).await.unwrap().is_empty());
+
+        // a sample threshold takes precedence over the taxon threshold
+        Threshold::save(1, ThresholdTarget::Sample(2), Some(100), &pool)
+            .await
+            .unwrap();
+        assert!(LowStock::load_user(1, &pool).await.unwrap().is_empty());
+        Threshold::save(1, ThresholdTarget::Sample(2), Some(120), &pool)
+            .await
+            .unwrap();
+        let low = LowStock::load_user(1, &pool).await.unwrap();
+        assert_eq!(low[0].threshold, 120);
+        assert!(!low[0].taxon_threshold);
+        assert_eq!(
+            Sample::count(Some(sample::Filter::LowStock(1).into()), &pool)
+                .await
+                .unwrap(),
+            1
+        );
+
+        // users are only alerted once, until the sample has been restocked
+        assert!(users_with_email_alerts(&pool).await.unwrap().is_empty());
+        let mut prefs = Preferences::load(1, &pool).await.unwrap();
+        prefs.email_low_stock = true;
+        prefs.save(&pool).await.unwrap();
+        assert_eq!(users_with_email_alerts(&pool).await.unwrap(), vec![1]);
+        let new = LowStock::load_unalerted(1, &pool).await.unwrap();
+        assert_eq!(new.len(), 1);
+        LowStock::mark_alerted(1, &new, &pool).await.unwrap();
+        assert!(LowStock::load_unalerted(1, &pool).await.unwrap().is_empty());
+        Threshold::save(1, ThresholdTarget::Sample(2), None, &pool)
+            .await
+            .unwrap();
+        Threshold::save(1, ThresholdTarget::Taxon(40683), Some(50), &pool)
+            .await
+            .unwrap();
+        assert!(LowStock::load_unalerted(1, &pool).await.unwrap().is_empty());
+        Threshold::save(1, ThresholdTarget::Taxon(40683), Some(500), &pool)
+            .await
+            .unwrap();
+        assert_eq!(LowStock::load_unalerted(1, &pool).await.unwrap().len(), 1);
+    }
+}
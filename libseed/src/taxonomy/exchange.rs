@@ -0,0 +1,452 @@
+//! Curated reference data about taxa that can be shared between databases: the germination codes
+//! and which taxa they apply to, and the native and invasive status of taxa in the
+//! [status region](super::STATUS_REGION).
+//!
+//! The taxonomy is imported from ITIS, so taxa are identified by the same TSN in every database.
+//! Germination codes have different IDs in each database and are identified by their code
+//! instead. The complete name of each taxon is included so that an import can detect taxa whose
+//! TSN refers to a different taxon in the other database, e.g. after an ITIS update.
+use super::{InvasiveStatus, NativeStatus, STATUS_REGION};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Row, Sqlite, SqliteConnection};
+use std::{collections::BTreeMap, str::FromStr};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// The version of the export format written by [TaxonDataExport::export]
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaxonDataExport {
+    pub format: u32,
+    /// when the data was exported, in RFC 3339 format
+    pub exported: String,
+    /// the region that the native and invasive statuses refer to
+    pub region: String,
+    pub germination_codes: Vec<GerminationCodeRecord>,
+    pub taxa: Vec<TaxonDataRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GerminationCodeRecord {
+    pub code: String,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+}
+
+/// The reference data of a single taxon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaxonDataRecord {
+    pub tsn: i64,
+    /// the complete name of the taxon in the exporting database
+    pub name: String,
+    /// the germination codes that apply to the taxon
+    #[serde(default)]
+    pub germination: Vec<String>,
+    pub native_status: Option<String>,
+    pub invasive_status: Option<String>,
+}
+
+/// What to do with records that exist in both databases but differ
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConflictPolicy {
+    /// keep the value in this database and report the conflict
+    #[default]
+    Keep,
+    /// replace the value in this database with the imported one
+    Replace,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportReport {
+    /// records that were added to the database
+    pub inserted: usize,
+    /// records that already existed in the database with the same value
+    pub existing: usize,
+    /// records that differed and were replaced by the imported value
+    pub replaced: usize,
+    /// records that differed and were left unchanged
+    pub conflicts: Vec<String>,
+    /// taxa that were not imported because they don't exist in this database or have a
+    /// different name
+    pub skipped: Vec<String>,
+}
+
+impl TaxonDataExport {
+    /// Export the germination codes and the native and invasive status of taxa
+    pub async fn export(pool: &Pool<Sqlite>) -> Result<Self> {
+        let germination_codes = sqlx::query(
+            "SELECT code, summary, description FROM sc_germination_codes ORDER BY code",
+        )
+        .fetch_all(pool)
+        .await?
+        .iter()
+        .map(|row| {
+            Ok(GerminationCodeRecord {
+                code: row.try_get("code")?,
+                summary: row.try_get("summary")?,
+                description: row.try_get("description")?,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+        let mut taxa: BTreeMap<i64, TaxonDataRecord> = BTreeMap::new();
+        let statuses = sqlx::query(
+            r#"SELECT M.tsn, T.complete_name, M.native_status, M.invasive_status FROM mntaxa M
+            INNER JOIN taxonomic_units T ON T.tsn=M.tsn"#,
+        )
+        .fetch_all(pool)
+        .await?;
+        for row in statuses {
+            let tsn: i64 = row.try_get("tsn")?;
+            taxa.insert(
+                tsn,
+                TaxonDataRecord {
+                    tsn,
+                    name: row.try_get("complete_name")?,
+                    germination: Vec::new(),
+                    native_status: non_empty(row.try_get("native_status")?),
+                    invasive_status: non_empty(row.try_get("invasive_status")?),
+                },
+            );
+        }
+        let codes = sqlx::query(
+            r#"SELECT TG.tsn, T.complete_name, G.code FROM sc_taxon_germination TG
+            INNER JOIN sc_germination_codes G ON G.germid=TG.germid
+            INNER JOIN taxonomic_units T ON T.tsn=TG.tsn
+            ORDER BY G.code"#,
+        )
+        .fetch_all(pool)
+        .await?;
+        for row in codes {
+            let tsn: i64 = row.try_get("tsn")?;
+            let name: String = row.try_get("complete_name")?;
+            taxa.entry(tsn)
+                .or_insert_with(|| TaxonDataRecord {
+                    tsn,
+                    name,
+                    germination: Vec::new(),
+                    native_status: None,
+                    invasive_status: None,
+                })
+                .germination
+                .push(row.try_get("code")?);
+        }
+
+        Ok(Self {
+            format: FORMAT_VERSION,
+            exported: OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .map_err(|e| Error::InvalidValue(e.to_string()))?,
+            region: STATUS_REGION.to_string(),
+            germination_codes,
+            taxa: taxa.into_values().collect(),
+        })
+    }
+
+    /// Import the data into the database. Germination codes are matched by their code, and the
+    /// germination codes of a taxon are added to the ones it already has. Codes and statuses
+    /// that differ from the ones in the database are handled according to `policy`. Either
+    /// everything is imported or nothing is; with `dry_run`, nothing is imported but the report
+    /// describes what would have been.
+    pub async fn import(
+        &self,
+        policy: ConflictPolicy,
+        dry_run: bool,
+        pool: &Pool<Sqlite>,
+    ) -> Result<ImportReport> {
+        if self.format != FORMAT_VERSION {
+            return Err(Error::InvalidValue(format!(
+                "unsupported export format version {}",
+                self.format
+            )));
+        }
+        if self.region != STATUS_REGION {
+            return Err(Error::InvalidValue(format!(
+                "the statuses refer to region '{}', but this database uses '{STATUS_REGION}'",
+                self.region
+            )));
+        }
+        let mut report = ImportReport::default();
+        let mut tx = pool.begin().await?;
+        let conn = &mut *tx;
+        for code in &self.germination_codes {
+            self.import_code(code, policy, &mut report, conn).await?;
+        }
+        for taxon in &self.taxa {
+            self.import_taxon(taxon, policy, &mut report, conn).await?;
+        }
+        match dry_run {
+            true => tx.rollback().await?,
+            false => tx.commit().await?,
+        }
+        Ok(report)
+    }
+
+    async fn import_code(
+        &self,
+        record: &GerminationCodeRecord,
+        policy: ConflictPolicy,
+        report: &mut ImportReport,
+        conn: &mut SqliteConnection,
+    ) -> Result<()> {
+        let existing = sqlx::query(
+            "SELECT germid, summary, description FROM sc_germination_codes WHERE code=?",
+        )
+        .bind(&record.code)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let Some(existing) = existing else {
+            sqlx::query(
+                "INSERT INTO sc_germination_codes (code, summary, description) VALUES (?, ?, ?)",
+            )
+            .bind(&record.code)
+            .bind(&record.summary)
+            .bind(&record.description)
+            .execute(&mut *conn)
+            .await?;
+            report.inserted += 1;
+            return Ok(());
+        };
+        let summary: Option<String> = existing.try_get("summary")?;
+        let description: Option<String> = existing.try_get("description")?;
+        if summary == record.summary && description == record.description {
+            report.existing += 1;
+            return Ok(());
+        }
+        match policy {
+            ConflictPolicy::Keep => report.conflicts.push(format!(
+                "Germination code '{}' has a different description",
+                record.code
+            )),
+            ConflictPolicy::Replace => {
+                sqlx::query(
+                    "UPDATE sc_germination_codes SET summary=?, description=? WHERE germid=?",
+                )
+                .bind(&record.summary)
+                .bind(&record.description)
+                .bind(existing.try_get::<i64, _>("germid")?)
+                .execute(&mut *conn)
+                .await?;
+                report.replaced += 1;
+            }
+        }
+        Ok(())
+    }
+
+    async fn import_taxon(
+        &self,
+        record: &TaxonDataRecord,
+        policy: ConflictPolicy,
+        report: &mut ImportReport,
+        conn: &mut SqliteConnection,
+    ) -> Result<()> {
+        let name: Option<String> =
+            sqlx::query_scalar("SELECT complete_name FROM taxonomic_units WHERE tsn=?")
+                .bind(record.tsn)
+                .fetch_optional(&mut *conn)
+                .await?;
+        match name {
+            None => {
+                report.skipped.push(format!(
+                    "Taxon {} ({}) does not exist in this database",
+                    record.tsn, record.name
+                ));
+                return Ok(());
+            }
+            Some(name) if name != record.name => {
+                report.skipped.push(format!(
+                    "Taxon {} is '{name}' in this database, but '{}' in the export",
+                    record.tsn, record.name
+                ));
+                return Ok(());
+            }
+            Some(_) => (),
+        }
+        if let Some(status) = &record.native_status {
+            NativeStatus::from_str(status).map_err(|_| {
+                Error::InvalidValue(format!(
+                    "taxon {} has an invalid native status '{status}'",
+                    record.tsn
+                ))
+            })?;
+        }
+        if let Some(status) = &record.invasive_status {
+            InvasiveStatus::from_str(status).map_err(|_| {
+                Error::InvalidValue(format!(
+                    "taxon {} has an invalid invasive status '{status}'",
+                    record.tsn
+                ))
+            })?;
+        }
+
+        for code in &record.germination {
+            let germid: i64 =
+                sqlx::query_scalar("SELECT germid FROM sc_germination_codes WHERE code=?")
+                    .bind(code)
+                    .fetch_optional(&mut *conn)
+                    .await?
+                    .ok_or_else(|| {
+                        Error::InvalidValue(format!(
+                            "taxon {} refers to the unknown germination code '{code}'",
+                            record.tsn
+                        ))
+                    })?;
+            let res = sqlx::query(
+                "INSERT OR IGNORE INTO sc_taxon_germination (tsn, germid) VALUES (?, ?)",
+            )
+            .bind(record.tsn)
+            .bind(germid)
+            .execute(&mut *conn)
+            .await?;
+            match res.rows_affected() {
+                0 => report.existing += 1,
+                _ => report.inserted += 1,
+            }
+        }
+
+        if record.native_status.is_none() && record.invasive_status.is_none() {
+            return Ok(());
+        }
+        let existing = sqlx::query("SELECT native_status, invasive_status FROM mntaxa WHERE tsn=?")
+            .bind(record.tsn)
+            .fetch_optional(&mut *conn)
+            .await?;
+        let Some(existing) = existing else {
+            sqlx::query(
+                "INSERT INTO mntaxa (tsn, native_status, invasive_status) VALUES (?, ?, ?)",
+            )
+            .bind(record.tsn)
+            .bind(&record.native_status)
+            .bind(&record.invasive_status)
+            .execute(&mut *conn)
+            .await?;
+            report.inserted += 1;
+            return Ok(());
+        };
+        let native = non_empty(existing.try_get("native_status")?);
+        let invasive = non_empty(existing.try_get("invasive_status")?);
+        if native == record.native_status && invasive == record.invasive_status {
+            report.existing += 1;
+            return Ok(());
+        }
+        match policy {
+            ConflictPolicy::Keep => report.conflicts.push(format!(
+                "Taxon {} ({}) has a different native or invasive status",
+                record.tsn, record.name
+            )),
+            ConflictPolicy::Replace => {
+                sqlx::query("UPDATE mntaxa SET native_status=?, invasive_status=? WHERE tsn=?")
+                    .bind(&record.native_status)
+                    .bind(&record.invasive_status)
+                    .bind(record.tsn)
+                    .execute(&mut *conn)
+                    .await?;
+                report.replaced += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use test_log::test;
+
+    async fn add_code(code: &str, summary: &str, tsn: i64, pool: &Pool<Sqlite>) {
+        let germid = sqlx::query("INSERT INTO sc_germination_codes (code, summary) VALUES (?, ?)")
+            .bind(code)
+            .bind(summary)
+            .execute(pool)
+            .await
+            .unwrap()
+            .last_insert_rowid();
+        sqlx::query("INSERT INTO sc_taxon_germination (tsn, germid) VALUES (?, ?)")
+            .bind(tsn)
+            .bind(germid)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn test_export_import(pool: Pool<Sqlite>) {
+        add_code("C(60)", "60 days cold moist stratification", 40683, &pool).await;
+        add_code("A", "Sow in spring", 43254, &pool).await;
+        sqlx::query("INSERT INTO mntaxa (tsn, native_status) VALUES (40683, 'N')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let export = TaxonDataExport::export(&pool).await.unwrap();
+        assert_eq!(export.germination_codes.len(), 2);
+        assert_eq!(export.taxa.len(), 2);
+        let rye = export.taxa.iter().find(|t| t.tsn == 40683).unwrap();
+        assert_eq!(rye.germination, ["C(60)"]);
+        assert_eq!(rye.native_status.as_deref(), Some("N"));
+
+        let other = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("../db/migrations")
+            .run(&other)
+            .await
+            .unwrap();
+        sqlx::query(include_str!("../../../db/fixtures/taxa.sql"))
+            .execute(&other)
+            .await
+            .unwrap();
+        add_code("A", "Sow in fall", 43254, &other).await;
+
+        // a dry run doesn't change anything
+        let report = export
+            .import(ConflictPolicy::Keep, true, &other)
+            .await
+            .unwrap();
+        assert_eq!(report.inserted, 3);
+        let codes: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sc_germination_codes")
+            .fetch_one(&other)
+            .await
+            .unwrap();
+        assert_eq!(codes, 1);
+
+        let report = export
+            .import(ConflictPolicy::Keep, false, &other)
+            .await
+            .unwrap();
+        assert_eq!(report.inserted, 3);
+        assert_eq!(report.existing, 1);
+        assert_eq!(report.conflicts.len(), 1);
+        let restored = TaxonDataExport::export(&other).await.unwrap();
+        assert_ne!(restored.germination_codes, export.germination_codes);
+        assert_eq!(restored.taxa, export.taxa);
+
+        let report = export
+            .import(ConflictPolicy::Replace, false, &other)
+            .await
+            .unwrap();
+        assert_eq!(report.inserted, 0);
+        assert_eq!(report.replaced, 1);
+        let restored = TaxonDataExport::export(&other).await.unwrap();
+        assert_eq!(restored.germination_codes, export.germination_codes);
+
+        // taxa with a different name in this database are skipped
+        let mut renamed = export.clone();
+        renamed.taxa[0].name = "Something else".to_string();
+        let report = renamed
+            .import(ConflictPolicy::Keep, false, &other)
+            .await
+            .unwrap();
+        assert_eq!(report.skipped.len(), 1);
+    }
+}
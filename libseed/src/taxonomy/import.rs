@@ -0,0 +1,214 @@
+//! Matching the taxa of imported rows to the taxonomic database. Files that are imported, such as
+//! seed weights, conservation listings or samples, refer to taxa either by their id (TSN) or by
+//! their complete scientific name. A [TaxaMatcher] resolves these references, remembering the
+//! result for each distinct value so that large files with many rows for the same taxon only query
+//! the database once per taxon.
+//!
+//! ```no_run
+//! # async fn example(pool: &sqlx::Pool<sqlx::Sqlite>) -> libseed::error::Result<()> {
+//! use libseed::taxonomy::import::TaxaMatcher;
+//!
+//! let mut matcher = TaxaMatcher::new().on_progress(|progress| {
+//!     eprintln!("{} taxa matched, {} failed", progress.matched, progress.failed)
+//! });
+//! for value in ["40683", "Elymus canadensis", "Elymus nonexistens"] {
+//!     match matcher.resolve(value, pool).await {
+//!         Ok(tsn) => println!("{value} is taxon {tsn}"),
+//!         Err(e) => println!("{e}"),
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use super::{Filter, Taxon};
+use crate::{error::Error, loadable::Loadable};
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+
+/// The names of the column that holds the taxon in an imported file, after lowercasing and
+/// replacing underscores and dashes with spaces
+pub const TAXON_COLUMN_NAMES: [&str; 4] = ["taxon", "tsn", "species", "scientific name"];
+
+/// Find the column whose header matches one of `names`. Headers are compared ignoring case and
+/// treating underscores and dashes as spaces.
+pub fn find_column<'a>(
+    headers: impl IntoIterator<Item = &'a str>,
+    names: &[&str],
+) -> Option<usize> {
+    headers.into_iter().position(|h| {
+        let h = h.trim().to_lowercase().replace(['_', '-'], " ");
+        names.contains(&h.as_str())
+    })
+}
+
+/// Why a value could not be matched to a taxon
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum MatchError {
+    #[error("No taxon given")]
+    Empty,
+    #[error("No taxon found with id {0}")]
+    UnknownId(i64),
+    #[error("No taxon found matching '{0}'")]
+    NotFound(String),
+    #[error("Multiple taxa found matching '{0}'")]
+    Ambiguous(String),
+    /// the database could not be queried. The message of the underlying error is kept so that the
+    /// result can be cached and reported for every row.
+    #[error("Failed to look up taxon '{0}': {1}")]
+    Database(String, String),
+}
+
+/// How far the matching has progressed. Values that were already matched before count again, so
+/// `processed` is the number of calls to [TaxaMatcher::resolve].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MatchProgress {
+    pub processed: usize,
+    pub matched: usize,
+    pub failed: usize,
+    /// the number of distinct values that had to be looked up in the database
+    pub lookups: usize,
+}
+
+type ProgressCallback = Box<dyn FnMut(&MatchProgress) + Send>;
+
+/// Resolves references to taxa in imported files. See the [module documentation](self).
+#[derive(Default)]
+pub struct TaxaMatcher {
+    cache: HashMap<String, Result<i64, MatchError>>,
+    progress: MatchProgress,
+    callback: Option<ProgressCallback>,
+}
+
+impl TaxaMatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call `callback` after each value that is resolved, e.g. to show a progress indicator
+    pub fn on_progress<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&MatchProgress) + Send + 'static,
+    {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    /// How many values have been resolved so far
+    pub fn progress(&self) -> MatchProgress {
+        self.progress
+    }
+
+    /// Resolve a value to the id of a taxon. A value that is a number is treated as the id of the
+    /// taxon, and anything else as its complete scientific name, which must match exactly one
+    /// taxon. Names are compared ignoring case.
+    pub async fn resolve(&mut self, value: &str, pool: &Pool<Sqlite>) -> Result<i64, MatchError> {
+        let value = value.trim();
+        let key = value.to_lowercase();
+        let result = match self.cache.get(&key) {
+            Some(result) => result.clone(),
+            None => {
+                let result = lookup(value, pool).await;
+                self.progress.lookups += 1;
+                self.cache.insert(key, result.clone());
+                result
+            }
+        };
+        self.progress.processed += 1;
+        match result {
+            Ok(_) => self.progress.matched += 1,
+            Err(_) => self.progress.failed += 1,
+        }
+        if let Some(callback) = self.callback.as_mut() {
+            callback(&self.progress);
+        }
+        result
+    }
+}
+
+async fn lookup(value: &str, pool: &Pool<Sqlite>) -> Result<i64, MatchError> {
+    let db_error = |e: Error| MatchError::Database(value.to_string(), e.to_string());
+    let query_error = |e: sqlx::Error| db_error(e.into());
+    if value.is_empty() {
+        return Err(MatchError::Empty);
+    }
+    if let Ok(tsn) = value.parse::<i64>() {
+        return match Taxon::load(tsn, pool).await {
+            Ok(taxon) => Ok(taxon.id),
+            Err(Error::DatabaseRowNotFound(_)) => Err(MatchError::UnknownId(tsn)),
+            Err(e) => Err(db_error(e)),
+        };
+    }
+    let taxa = Taxon::load_all(
+        Some(Filter::CompleteName(value.to_string()).into()),
+        None,
+        pool,
+    )
+    .await
+    .map_err(query_error)?;
+    match taxa.as_slice() {
+        [taxon] => Ok(taxon.id),
+        [] => Err(MatchError::NotFound(value.to_string())),
+        _ => Err(MatchError::Ambiguous(value.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use test_log::test;
+
+    #[test]
+    fn test_find_column() {
+        let headers = ["Notes", "Scientific_Name", "Seeds-per-gram"];
+        assert_eq!(find_column(headers, &TAXON_COLUMN_NAMES), Some(1));
+        assert_eq!(find_column(headers, &["seeds per gram"]), Some(2));
+        assert_eq!(find_column(headers, &["status"]), None);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn test_resolve(pool: Pool<Sqlite>) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let calls = seen.clone();
+        let mut matcher = TaxaMatcher::new().on_progress(move |p| calls.lock().unwrap().push(*p));
+
+        assert_eq!(matcher.resolve("40683", &pool).await.unwrap(), 40683);
+        assert_eq!(
+            matcher.resolve(" elymus canadensis ", &pool).await.unwrap(),
+            40683
+        );
+        assert!(matches!(
+            matcher.resolve("1", &pool).await,
+            Err(MatchError::UnknownId(1))
+        ));
+        assert!(matches!(
+            matcher.resolve("Elymus nonexistens", &pool).await,
+            Err(MatchError::NotFound(_))
+        ));
+        assert!(matches!(
+            matcher.resolve("Elymus%", &pool).await,
+            Err(MatchError::Ambiguous(_))
+        ));
+        assert!(matches!(
+            matcher.resolve("", &pool).await,
+            Err(MatchError::Empty)
+        ));
+        // values that were resolved before are not looked up again
+        assert_eq!(
+            matcher.resolve("Elymus Canadensis", &pool).await.unwrap(),
+            40683
+        );
+
+        let progress = matcher.progress();
+        assert_eq!(progress.processed, 7);
+        assert_eq!(progress.matched, 3);
+        assert_eq!(progress.failed, 4);
+        assert_eq!(progress.lookups, 6);
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 7);
+        assert_eq!(seen.last(), Some(&progress));
+    }
+}
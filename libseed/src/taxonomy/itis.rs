@@ -0,0 +1,390 @@
+//! Installing the taxonomy from a release of the ITIS database. ITIS publishes its data as a
+//! SQLite database (see `db/itis/README`). The plant taxa of a release are copied into the ITIS
+//! tables of this database, either to fill a new database or to upgrade an existing one.
+//!
+//! Before a release is installed, [ItisRelease::preview] shows which taxa it would add, remove,
+//! rename or move, in the same form as the [report](super::upgrade::UpgradeReport) that is
+//! recorded once it has been installed.
+//!
+//! ITIS does not record a schema version in its database, so a release is checked against the
+//! ITIS tables of this database instead: it can only be installed if it has all of their tables
+//! and columns.
+use super::{
+    ensure_taxonomic_order, refresh_complete_names, upgrade::TaxonChange, COMPLETE_NAME_SQL,
+    KINGDOM_PLANTAE,
+};
+use crate::error::{Error, Result};
+use sqlx::{pool::PoolConnection, Connection, Pool, Sqlite, SqliteConnection};
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// The ITIS tables that are kept in this database along with the condition that selects the rows
+/// that belong to plants. The other tables of a release are not used.
+const ITIS_TABLES: [(&str, &str); 9] = [
+    ("kingdoms", "kingdom_id=?1"),
+    ("taxon_unit_types", "kingdom_id=?1"),
+    ("taxon_authors_lkp", "kingdom_id=?1"),
+    (
+        "strippedauthor",
+        "taxon_author_id IN (SELECT taxon_author_id FROM itis.taxon_authors_lkp WHERE kingdom_id=?1)",
+    ),
+    ("taxonomic_units", "kingdom_id=?1"),
+    (
+        "hierarchy",
+        "tsn IN (SELECT tsn FROM itis.taxonomic_units WHERE kingdom_id=?1)",
+    ),
+    (
+        "longnames",
+        "tsn IN (SELECT tsn FROM itis.taxonomic_units WHERE kingdom_id=?1)",
+    ),
+    (
+        "synonym_links",
+        "tsn IN (SELECT tsn FROM itis.taxonomic_units WHERE kingdom_id=?1)",
+    ),
+    (
+        "vernaculars",
+        "tsn IN (SELECT tsn FROM itis.taxonomic_units WHERE kingdom_id=?1)",
+    ),
+];
+
+/// A release of the ITIS database that has been checked against this database
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItisRelease {
+    pub path: PathBuf,
+    /// the date of the most recent change to a taxon in the release
+    pub updated: Option<String>,
+    /// the number of plant taxa in the release
+    pub taxa: i64,
+}
+
+/// What changed when a release was installed
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct InstallReport {
+    /// the number of plant taxa that were added or updated
+    pub taxa: u64,
+    /// taxa that are no longer part of the release. They are kept since samples may still refer
+    /// to them, but they are marked as invalid.
+    pub retired: u64,
+}
+
+/// The column names of `table` in the given schema of the connection
+async fn columns(schema: &str, table: &str, conn: &mut SqliteConnection) -> Result<Vec<String>> {
+    sqlx::query_scalar("SELECT name FROM pragma_table_info(?, ?)")
+        .bind(table)
+        .bind(schema)
+        .fetch_all(conn)
+        .await
+        .map_err(Into::into)
+}
+
+/// Attach the database at `path` to a connection as the `itis` schema
+async fn attach(path: &Path, pool: &Pool<Sqlite>) -> Result<PoolConnection<Sqlite>> {
+    if !path.is_file() {
+        return Err(Error::InvalidValue(format!(
+            "The ITIS database {} does not exist",
+            path.display()
+        )));
+    }
+    let mut conn = pool.acquire().await?;
+    sqlx::query("ATTACH DATABASE ? AS itis")
+        .bind(path.to_string_lossy().to_string())
+        .execute(&mut *conn)
+        .await?;
+    Ok(conn)
+}
+
+async fn detach(mut conn: PoolConnection<Sqlite>) -> Result<()> {
+    sqlx::query("DETACH DATABASE itis")
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+impl ItisRelease {
+    /// Open the ITIS database at `path` and check that it can be installed into this database
+    pub async fn open(path: &Path, pool: &Pool<Sqlite>) -> Result<Self> {
+        let mut conn = attach(path, pool).await?;
+        let res = Self::check(path, &mut conn).await;
+        detach(conn).await?;
+        res
+    }
+
+    async fn check(path: &Path, conn: &mut SqliteConnection) -> Result<Self> {
+        let mut missing = Vec::new();
+        for (table, _) in ITIS_TABLES {
+            let available = columns("itis", table, conn).await?;
+            if available.is_empty() {
+                missing.push(table.to_string());
+                continue;
+            }
+            for column in columns("main", table, conn).await? {
+                if !available.iter().any(|c| c.eq_ignore_ascii_case(&column)) {
+                    missing.push(format!("{table}.{column}"));
+                }
+            }
+        }
+        if !missing.is_empty() {
+            return Err(Error::InvalidValue(format!(
+                "{} is not a compatible ITIS database, it is missing {}",
+                path.display(),
+                missing.join(", ")
+            )));
+        }
+        let (updated, taxa): (Option<String>, i64) = sqlx::query_as(
+            "SELECT MAX(update_date), COUNT(*) FROM itis.taxonomic_units WHERE kingdom_id=?",
+        )
+        .bind(KINGDOM_PLANTAE)
+        .fetch_one(conn)
+        .await?;
+        if taxa == 0 {
+            return Err(Error::InvalidValue(format!(
+                "The ITIS database {} does not contain any plants",
+                path.display()
+            )));
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            updated,
+            taxa,
+        })
+    }
+
+    /// The accepted plant taxa that installing the release would add, remove, rename or move to
+    /// another parent, without changing this database. The changes are not part of a recorded
+    /// upgrade, so their `id` and `upgradeid` are 0.
+    pub async fn preview(&self, pool: &Pool<Sqlite>) -> Result<Vec<TaxonChange>> {
+        let mut conn = attach(&self.path, pool).await?;
+        let res = sqlx::query_as(&format!(
+            r#"WITH old AS (SELECT tsn, parent_tsn, complete_name, rank_id
+                FROM main.taxonomic_units WHERE kingdom_id=?1 AND name_usage="accepted"),
+            names AS (SELECT tsn, parent_tsn, rank_id, name_usage, {COMPLETE_NAME_SQL} AS complete_name
+                FROM itis.taxonomic_units WHERE kingdom_id=?1),
+            new AS (SELECT * FROM names WHERE name_usage="accepted")
+            SELECT * FROM (SELECT 0 AS changeid, 0 AS upgradeid, O.tsn, COALESCE(N.rank_id, O.rank_id) AS rank_id,
+                O.complete_name AS oldname, N.complete_name AS newname,
+                O.parent_tsn AS oldparenttsn, OP.complete_name AS oldparentname,
+                N.parent_tsn AS newparenttsn, NP.complete_name AS newparentname
+                FROM old O
+                LEFT JOIN new N ON N.tsn=O.tsn
+                LEFT JOIN main.taxonomic_units OP ON OP.tsn=O.parent_tsn
+                LEFT JOIN names NP ON NP.tsn=N.parent_tsn
+                WHERE N.tsn IS NULL OR N.complete_name IS NOT O.complete_name
+                    OR N.parent_tsn IS NOT O.parent_tsn
+            UNION ALL
+            SELECT 0, 0, N.tsn, N.rank_id, NULL, N.complete_name, NULL, NULL, N.parent_tsn,
+                NP.complete_name
+                FROM new N
+                LEFT JOIN names NP ON NP.tsn=N.parent_tsn
+                WHERE N.tsn NOT IN (SELECT tsn FROM old))
+            ORDER BY rank_id, COALESCE(newname, oldname)"#
+        ))
+        .bind(KINGDOM_PLANTAE)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(Into::into);
+        detach(conn).await?;
+        res
+    }
+
+    /// Copy the plant taxa of the release into this database, replacing the taxonomy that it
+    /// had before. Taxa keep their TSN, so samples and other data that refer to a taxon still
+    /// refer to the same taxon afterwards.
+    pub async fn install(&self, pool: &Pool<Sqlite>) -> Result<InstallReport> {
+        let mut conn = attach(&self.path, pool).await?;
+        let res = Self::copy_tables(&mut conn).await;
+        detach(conn).await?;
+        let report = res?;
+        ensure_taxonomic_order(pool).await?;
+        refresh_complete_names(pool).await?;
+        Ok(report)
+    }
+
+    async fn copy_tables(conn: &mut SqliteConnection) -> Result<InstallReport> {
+        let mut report = InstallReport::default();
+        let mut tx = conn.begin().await?;
+        for (table, condition) in ITIS_TABLES {
+            debug!(table, "Installing ITIS table");
+            let columns: Vec<String> = columns("main", table, &mut tx)
+                .await?
+                .iter()
+                .map(|c| format!(r#""{c}""#))
+                .collect();
+            let list = columns.join(", ");
+            if table != "taxonomic_units" {
+                sqlx::query(&format!("DELETE FROM main.{table}"))
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query(&format!(
+                    "INSERT INTO main.{table} ({list}) SELECT {list} FROM itis.{table} WHERE {condition}"
+                ))
+                .bind(KINGDOM_PLANTAE)
+                .execute(&mut *tx)
+                .await?;
+                continue;
+            }
+            // samples and other data refer to taxa, so taxa are updated in place rather than
+            // replaced
+            let updates = columns
+                .iter()
+                .map(|c| format!("{c}=excluded.{c}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            report.taxa = sqlx::query(&format!(
+                r#"INSERT INTO main.{table} ({list}) SELECT {list} FROM itis.{table}
+                WHERE {condition} ON CONFLICT(tsn) DO UPDATE SET {updates}"#
+            ))
+            .bind(KINGDOM_PLANTAE)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            report.retired = sqlx::query(
+                r#"UPDATE main.taxonomic_units SET name_usage="invalid",
+                    unaccept_reason="removed from ITIS"
+                WHERE name_usage<>"invalid"
+                    AND tsn NOT IN (SELECT tsn FROM itis.taxonomic_units)"#,
+            )
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        }
+        tx.commit().await?;
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqliteConnectOptions;
+    use test_log::test;
+
+    /// Copy the database of the pool to a file that can be used as a release
+    async fn make_release(name: &str, pool: &Pool<Sqlite>) -> (PathBuf, SqliteConnection) {
+        let path =
+            std::env::temp_dir().join(format!("libseed-itis-{name}-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        sqlx::query("VACUUM INTO ?")
+            .bind(path.to_string_lossy().to_string())
+            .execute(pool)
+            .await
+            .unwrap();
+        let conn = SqliteConnection::connect_with(&SqliteConnectOptions::new().filename(&path))
+            .await
+            .unwrap();
+        (path, conn)
+    }
+
+    async fn exec(sql: &str, conn: &mut SqliteConnection) {
+        sqlx::query(sql).execute(conn).await.unwrap();
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn test_install_release(pool: Pool<Sqlite>) {
+        let (path, mut release) = make_release("install", &pool).await;
+        // rename a genus, drop a species and add a new one
+        exec(
+            "UPDATE taxonomic_units SET unit_name1='Leymus' WHERE tsn=40677",
+            &mut release,
+        )
+        .await;
+        exec("DELETE FROM taxonomic_units WHERE tsn=43254", &mut release).await;
+        exec(
+            r#"INSERT INTO taxonomic_units (tsn, unit_name1, unit_name2, name_usage,
+            credibility_rtng, initial_time_stamp, parent_tsn, kingdom_id, rank_id, update_date)
+            VALUES (999999, 'Elymus', 'virginicus', 'accepted', 'TWG standards met',
+            '2024-01-01 00:00:00', 40677, 3, 220, '2024-01-01')"#,
+            &mut release,
+        )
+        .await;
+        release.close().await.unwrap();
+
+        let itis = ItisRelease::open(&path, &pool).await.unwrap();
+        let preview = itis.preview(&pool).await.unwrap();
+        assert_eq!(preview.len(), 3);
+        let change = |tsn| preview.iter().find(|c| c.tsn == tsn).unwrap();
+        assert!(change(40677).is_renamed());
+        assert_eq!(change(40677).newname.as_deref(), Some("Leymus"));
+        assert!(change(43254).is_removed());
+        assert!(change(999999).is_added());
+        assert_eq!(change(999999).newparentname.as_deref(), Some("Leymus"));
+        let ntaxa: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM taxonomic_units")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(itis.taxa, ntaxa);
+        assert_eq!(itis.updated.as_deref(), Some("2024-01-01"));
+
+        let report = itis.install(&pool).await.unwrap();
+        assert_eq!(report.taxa, ntaxa as u64);
+        assert_eq!(report.retired, 1);
+
+        let name = |tsn: i64| {
+            sqlx::query_as::<_, (String, String)>(
+                "SELECT complete_name, name_usage FROM taxonomic_units WHERE tsn=?",
+            )
+            .bind(tsn)
+            .fetch_one(&pool)
+        };
+        assert_eq!(
+            name(40677).await.unwrap(),
+            ("Leymus".to_string(), "accepted".to_string())
+        );
+        assert_eq!(
+            name(999999).await.unwrap(),
+            ("Elymus virginicus".to_string(), "accepted".to_string())
+        );
+        // the dropped species is kept, but it is no longer valid
+        assert_eq!(name(43254).await.unwrap().1, "invalid");
+        // the release is detached again
+        assert!(sqlx::query("SELECT * FROM itis.taxonomic_units")
+            .fetch_all(&pool)
+            .await
+            .is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn test_incompatible_release(pool: Pool<Sqlite>) {
+        assert!(
+            ItisRelease::open(Path::new("/nonexistent/itis.sqlite"), &pool)
+                .await
+                .is_err()
+        );
+
+        let (path, mut release) = make_release("incompatible", &pool).await;
+        // an ITIS release doesn't have the views of this database
+        let views: Vec<String> =
+            sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type='view'")
+                .fetch_all(&mut release)
+                .await
+                .unwrap();
+        for view in views {
+            exec(&format!("DROP VIEW {view}"), &mut release).await;
+        }
+        exec("DROP TABLE vernaculars", &mut release).await;
+        exec(
+            "ALTER TABLE longnames RENAME COLUMN completename TO name",
+            &mut release,
+        )
+        .await;
+        release.close().await.unwrap();
+        let Err(Error::InvalidValue(msg)) = ItisRelease::open(&path, &pool).await else {
+            panic!("release should not be compatible");
+        };
+        assert!(msg.contains("vernaculars"));
+        assert!(msg.contains("longnames.completename"));
+        std::fs::remove_file(&path).unwrap();
+
+        let (path, mut release) = make_release("noplants", &pool).await;
+        exec("UPDATE taxonomic_units SET kingdom_id=5", &mut release).await;
+        release.close().await.unwrap();
+        assert!(ItisRelease::open(&path, &pool).await.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
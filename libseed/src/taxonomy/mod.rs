@@ -0,0 +1,1221 @@
+//! Objects related to querying the taxonomic database
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    error::Error::ColumnDecode,
+    sqlite::{SqliteQueryResult, SqliteRow},
+    FromRow, Pool, Row, Sqlite,
+};
+use std::{str::FromStr, sync::Arc};
+use strum_macros::{Display, EnumIter, EnumString, FromRepr};
+use tracing::debug;
+
+use crate::{
+    error::Result,
+    filter::{
+        Cmp, CompoundFilter, DynFilterPart, FilterPart, LimitSpec, ListQuery, Op, SortOrder,
+        SortSpec, SortSpecs,
+    },
+    loadable::{ExternalRef, Loadable},
+    organization::push_accessible_condition,
+    Error,
+};
+
+pub mod exchange;
+pub mod import;
+pub mod itis;
+pub mod names;
+pub mod staged;
+pub mod upgrade;
+
+pub const KINGDOM_PLANTAE: i64 = 3;
+
+#[derive(
+    Debug, Clone, Display, EnumString, EnumIter, FromRepr, Deserialize, Serialize, PartialEq,
+)]
+#[strum(ascii_case_insensitive)]
+pub enum Rank {
+    Unknown = 0,
+    Kingdom = 10,
+    Subkingdom = 20,
+    Infrakingdom = 25,
+    Superdivision = 27,
+    Division = 30,
+    Subdivision = 40,
+    Infradivision = 45,
+    Superclass = 50,
+    Class = 60,
+    Subclass = 70,
+    Infraclass = 80,
+    Superorder = 90,
+    Order = 100,
+    Suborder = 110,
+    Family = 140,
+    Subfamily = 150,
+    Tribe = 160,
+    Subtribe = 170,
+    Genus = 180,
+    Subgenus = 190,
+    Section = 200,
+    Subsection = 210,
+    Species = 220,
+    Subspecies = 230,
+    Variety = 240,
+    Subvariety = 250,
+    Form = 260,
+    Subform = 270,
+}
+
+#[derive(Debug, Display, EnumString, FromRepr, Serialize, Deserialize, PartialEq, Clone)]
+pub enum NativeStatus {
+    #[strum(serialize = "Native", serialize = "N")]
+    Native,
+    #[strum(serialize = "Introduced", serialize = "I")]
+    Introduced,
+    #[strum(serialize = "Unknown", serialize = "U")]
+    Unknown,
+}
+
+/// The region that the native and invasive status of taxa refers to
+pub const STATUS_REGION: &str = "MN";
+
+/// Whether the native and invasive status of taxa applies to a user in the given region. If no
+/// region is given, it is assumed to apply.
+pub fn status_applies_to(region: Option<&str>) -> bool {
+    region
+        .map(crate::conservation::normalize_region)
+        .filter(|r| !r.is_empty())
+        .is_none_or(|r| r == STATUS_REGION)
+}
+
+/// Why a taxon is considered invasive, as listed in the Minnesota taxa list
+#[derive(Debug, Display, EnumString, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum InvasiveStatus {
+    #[strum(serialize = "Federal noxious weed", serialize = "FN")]
+    #[serde(rename = "Federal noxious weed")]
+    FederalNoxiousWeed,
+    #[strum(serialize = "State prohibited noxious weed", serialize = "SN")]
+    #[serde(rename = "State prohibited noxious weed")]
+    ProhibitedNoxiousWeed,
+    #[strum(serialize = "State restricted noxious weed", serialize = "RN")]
+    #[serde(rename = "State restricted noxious weed")]
+    RestrictedNoxiousWeed,
+    #[strum(serialize = "Prohibited invasive species", serialize = "PI")]
+    #[serde(rename = "Prohibited invasive species")]
+    ProhibitedInvasiveSpecies,
+    #[strum(serialize = "State prohibited weed seed", serialize = "PS")]
+    #[serde(rename = "State prohibited weed seed")]
+    ProhibitedWeedSeed,
+    #[strum(serialize = "State restricted weed seed", serialize = "RS")]
+    #[serde(rename = "State restricted weed seed")]
+    RestrictedWeedSeed,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+/// An object representing a particular taxon from the database
+pub struct Taxon {
+    pub id: i64,
+    pub rank: Rank,
+    pub name1: Option<String>,
+    pub name2: Option<String>,
+    pub name3: Option<String>,
+    pub complete_name: String,
+    pub vernaculars: Vec<String>,
+    pub native_status: Option<NativeStatus>,
+    pub invasive_status: Option<InvasiveStatus>,
+    pub parentid: Option<i64>,
+    pub seq: Option<i64>,
+    pub germination: Option<Vec<Germination>>,
+    pub seed_weight: Option<SeedWeight>,
+}
+
+#[async_trait]
+impl Loadable for Taxon {
+    type Id = i64;
+
+    fn id(&self) -> Self::Id {
+        self.id
+    }
+
+    fn set_id(&mut self, id: Self::Id) {
+        self.id = id
+    }
+
+    async fn load(id: Self::Id, pool: &Pool<Sqlite>) -> Result<Self> {
+        let mut query = Taxon::build_query(Some(Filter::Id(id).into()), None);
+        Ok(query.build_query_as().fetch_one(pool).await?)
+    }
+
+    async fn delete_id(_id: &Self::Id, _pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        Err(Error::InvalidOperation("Cannot delete taxon".to_string()))
+    }
+}
+
+/// A position in the taxonomic order of the taxa, used to continue loading a list of taxa right
+/// after the last one that was loaded (see [Taxon::load_page]). Unlike an offset, a cursor still
+/// points to the same place when taxa before it are added or removed. It is written as
+/// `<seq>-<id>`, which is how it is passed around in urls.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone, Copy)]
+pub struct TaxonCursor {
+    seq: i64,
+    id: i64,
+}
+
+impl TaxonCursor {
+    /// The position right after the given taxon
+    pub fn after(taxon: &Taxon) -> Self {
+        Self {
+            seq: taxon.seq.unwrap_or_default(),
+            id: taxon.id,
+        }
+    }
+}
+
+impl std::fmt::Display for TaxonCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.seq, self.id)
+    }
+}
+
+impl FromStr for TaxonCursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.split_once('-')
+            .and_then(|(seq, id)| Some((seq.parse().ok()?, id.parse().ok()?)))
+            .map(|(seq, id)| Self { seq, id })
+            .ok_or_else(|| Error::InvalidValue(format!("Invalid cursor '{s}'")))
+    }
+}
+
+/// A page of taxa in taxonomic order, see [Taxon::load_page]
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct TaxonPage {
+    pub taxa: Vec<Taxon>,
+    /// where the next page starts, or `None` if this is the last page
+    pub next: Option<TaxonCursor>,
+}
+
+#[derive(FromRow, Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Germination {
+    #[sqlx(rename = "germid")]
+    pub id: i64,
+    pub code: String,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Conditions for selecting germination codes
+#[derive(Clone)]
+pub enum GerminationFilter {
+    Id(i64),
+    Code(Cmp, String),
+    /// the codes that apply to the taxon with the given id
+    Taxon(i64),
+    /// the codes that apply to the taxon of any sample that the given user has access to
+    UsedBySamples(i64),
+}
+
+impl From<GerminationFilter> for DynFilterPart {
+    fn from(value: GerminationFilter) -> Self {
+        Arc::new(value)
+    }
+}
+
+impl From<GerminationFilter> for Option<DynFilterPart> {
+    fn from(value: GerminationFilter) -> Self {
+        Some(Arc::new(value))
+    }
+}
+
+impl FilterPart for GerminationFilter {
+    fn add_to_query(&self, builder: &mut sqlx::QueryBuilder<sqlx::Sqlite>) {
+        match self {
+            Self::Id(id) => _ = builder.push(" G.germid = ").push_bind(*id),
+            Self::Code(cmp, frag) => {
+                let s = match cmp {
+                    Cmp::Like => format!("%{frag}%"),
+                    _ => frag.to_string(),
+                };
+                builder.push(" G.code ").push(cmp).push_bind(s);
+            }
+            Self::Taxon(tsn) => {
+                builder
+                    .push(" G.germid IN (SELECT germid FROM sc_taxon_germination WHERE tsn = ")
+                    .push_bind(*tsn)
+                    .push(")");
+            }
+            Self::UsedBySamples(userid) => {
+                builder.push(
+                    r#" G.germid IN (SELECT TG.germid FROM sc_taxon_germination TG
+                    INNER JOIN sc_samples S ON S.tsn=TG.tsn WHERE "#,
+                );
+                push_accessible_condition(builder, "S.userid", "S.sampleorgid", *userid);
+                builder.push(")");
+            }
+        }
+    }
+}
+
+/// The fields that germination codes can be sorted by. The names are used to specify the sort in
+/// query parameters and on the command line.
+#[derive(Clone, Debug, PartialEq, Display, EnumString)]
+pub enum GerminationSort {
+    #[strum(serialize = "id")]
+    Id,
+    #[strum(serialize = "code")]
+    Code,
+    #[strum(serialize = "summary")]
+    Summary,
+    /// the number of taxa that the code applies to
+    #[strum(serialize = "taxa")]
+    TaxonCount,
+}
+
+impl GerminationSort {
+    fn column(&self) -> &'static str {
+        match self {
+            Self::Id => "G.germid",
+            Self::Code => "G.code",
+            Self::Summary => "G.summary",
+            Self::TaxonCount => "ntaxa",
+        }
+    }
+}
+
+impl Germination {
+    /// Load the germination codes that match `filter`, sorted by code unless another sort order
+    /// is given
+    pub async fn load_all(
+        filter: Option<DynFilterPart>,
+        sort: Option<SortSpecs<GerminationSort>>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Germination>> {
+        let mut builder = ListQuery::new(
+            r#"G.*, (SELECT COUNT(*) FROM sc_taxon_germination TG WHERE TG.germid=G.germid)
+            AS ntaxa"#,
+            "sc_germination_codes G",
+            filter,
+        )
+        .select();
+        sort.unwrap_or(GerminationSort::Code.into())
+            .then(SortSpec::new(GerminationSort::Id, SortOrder::Ascending))
+            .push_order_by(&mut builder, GerminationSort::column);
+        Ok(builder.build_query_as().fetch_all(pool).await?)
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Germination> {
+        sqlx::query_as("SELECT * FROM sc_germination_codes WHERE germid=?")
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// The number of days of cold moist stratification that this code requires, if any. Such
+    /// codes include the duration in parentheses, e.g. `C(60)`.
+    pub fn stratification_days(&self) -> Option<u32> {
+        let (_, rest) = self.code.split_once('(')?;
+        let (days, _) = rest.split_once(')')?;
+        days.trim().parse().ok()
+    }
+
+    pub async fn update(&self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        if self.id < 0 {
+            return Err(Error::InvalidOperationObjectNotFound);
+        }
+        sqlx::query(
+            "UPDATE sc_germination_codes SET code=?, summary=?, description=? WHERE germid=?",
+        )
+        .bind(&self.code)
+        .bind(&self.summary)
+        .bind(&self.description)
+        .bind(self.id)
+        .execute(pool)
+        .await
+        .map_err(Into::into)
+    }
+}
+
+/// Reference data about the weight of the seeds of a taxon. Sample quantities are seed counts, so
+/// this is used to estimate the weight of a sample.
+#[derive(FromRow, Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct SeedWeight {
+    pub tsn: i64,
+    #[sqlx(rename = "seedspergram")]
+    pub seeds_per_gram: f64,
+    /// where the reference value came from
+    #[sqlx(rename = "weightsource")]
+    pub source: Option<String>,
+}
+
+impl SeedWeight {
+    pub async fn load(tsn: i64, pool: &Pool<Sqlite>) -> Result<Option<SeedWeight>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM sc_taxon_seed_weights WHERE tsn=?")
+                .bind(tsn)
+                .fetch_optional(pool)
+                .await?,
+        )
+    }
+
+    pub async fn load_all(pool: &Pool<Sqlite>) -> Result<Vec<SeedWeight>> {
+        Ok(sqlx::query_as(
+            r#"SELECT W.* FROM sc_taxon_seed_weights W
+            INNER JOIN taxonomic_units T ON T.tsn=W.tsn
+            ORDER BY T.phylo_sort_seq"#,
+        )
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Add the reference value for this taxon, replacing any existing value
+    pub async fn save(&self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        if !(self.seeds_per_gram.is_finite() && self.seeds_per_gram > 0.0) {
+            return Err(Error::InvalidValue(format!(
+                "seeds per gram must be positive: {}",
+                self.seeds_per_gram
+            )));
+        }
+        sqlx::query(
+            r#"INSERT INTO sc_taxon_seed_weights (tsn, seedspergram, weightsource) VALUES (?, ?, ?)
+            ON CONFLICT(tsn) DO UPDATE SET seedspergram=excluded.seedspergram,
+                weightsource=excluded.weightsource"#,
+        )
+        .bind(self.tsn)
+        .bind(self.seeds_per_gram)
+        .bind(&self.source)
+        .execute(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    pub async fn delete(tsn: i64, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        sqlx::query("DELETE FROM sc_taxon_seed_weights WHERE tsn=?")
+            .bind(tsn)
+            .execute(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// The estimated weight in grams of the given number of seeds
+    pub fn estimated_weight(&self, seeds: i64) -> f64 {
+        seeds as f64 / self.seeds_per_gram
+    }
+}
+
+impl FromRow<'_, SqliteRow> for ExternalRef<Taxon> {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Taxon::from_row(row)
+            .map(ExternalRef::Object)
+            .or_else(|_| row.try_get("tsn").map(ExternalRef::Stub))
+    }
+}
+
+impl FromRow<'_, SqliteRow> for Taxon {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        let rank = match row.try_get::<i64, _>("rank_id") {
+            Err(_) => Rank::Unknown,
+            Ok(r) => {
+                let rankid: usize =
+                    r.try_into()
+                        .map_err(|e: std::num::TryFromIntError| ColumnDecode {
+                            index: "rank".to_string(),
+                            source: e.into(),
+                        })?;
+                Rank::from_repr(rankid).ok_or_else(|| ColumnDecode {
+                    index: "rank".to_string(),
+                    source: Box::new(strum::ParseError::VariantNotFound),
+                })?
+            }
+        };
+        let status = match row.try_get("native_status") {
+            Err(_) => None,
+            Ok("") => None,
+            Ok(val) => Some(NativeStatus::from_str(val).map_err(|e| ColumnDecode {
+                index: "native_status".to_string(),
+                source: e.into(),
+            })?),
+        };
+        let invasive_status = match row.try_get("invasive_status") {
+            Err(_) => None,
+            Ok(None) | Ok(Some("")) => None,
+            Ok(Some(val)) => Some(InvasiveStatus::from_str(val).map_err(|e| ColumnDecode {
+                index: "invasive_status".to_string(),
+                source: e.into(),
+            })?),
+        };
+        let vernaculars = match row.try_get::<&str, _>("cnames") {
+            Ok(s) if !s.is_empty() => {
+                let splits = s.split('@').map(|x| x.to_string());
+                splits.collect::<Vec<String>>()
+            }
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            id: row.try_get("tsn")?,
+            rank,
+            complete_name: row.try_get("complete_name")?,
+            vernaculars,
+            name1: row.try_get("unit_name1")?,
+            name2: row.try_get("unit_name2")?,
+            name3: row.try_get("unit_name3")?,
+            native_status: status,
+            invasive_status,
+            parentid: row.try_get("parentid")?,
+            seq: row.try_get("seq").unwrap_or(None),
+            germination: None,
+            seed_weight: None,
+        })
+    }
+}
+
+impl From<Filter> for DynFilterPart {
+    fn from(value: Filter) -> Self {
+        Arc::new(value)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub enum Filter {
+    Id(i64),
+    Rank(Rank),
+    Genus(String),
+    Species(String),
+    Name1(String),
+    Name2(String),
+    Name3(String),
+    /// matches the start of the genus (or other uninomial) name
+    Name1Prefix(String),
+    Vernacular(String),
+    Minnesota(bool),
+    ParentId(i64),
+    CompleteName(String),
+    /// the taxa that come after the cursor in taxonomic order
+    After(TaxonCursor),
+}
+
+impl FilterPart for Filter {
+    fn add_to_query(&self, builder: &mut sqlx::QueryBuilder<sqlx::Sqlite>) {
+        match self {
+            Self::Id(n) => builder.push("T.tsn=").push_bind(*n),
+            Self::ParentId(n) => builder.push("T.parent_tsn=").push_bind(*n),
+            Self::CompleteName(s) => builder.push("T.complete_name LIKE ").push_bind(s.clone()),
+            Self::Genus(s) => builder.push("T.unit_name1 LIKE ").push_bind(s.clone()),
+            Self::Species(s) => builder.push("T.unit_name2 LIKE ").push_bind(s.clone()),
+            Self::Rank(rank) => builder.push("T.rank_id=").push_bind(rank.clone() as i64),
+            Self::Name1(s) => builder
+                .push("T.unit_name1 LIKE ")
+                .push_bind(format!("%{s}%")),
+            Self::Name2(s) => builder
+                .push("T.unit_name2 LIKE ")
+                .push_bind(format!("%{s}%")),
+            Self::Name3(s) => builder
+                .push("T.unit_name3 LIKE ")
+                .push_bind(format!("%{s}%")),
+            Self::Name1Prefix(s) => builder
+                .push("T.unit_name1 LIKE ")
+                .push_bind(format!("{s}%")),
+            Self::Vernacular(s) => builder
+                .push("V.vernacular_name LIKE ")
+                .push_bind(format!("%{s}%")),
+            Self::Minnesota(val) => match val {
+                true => builder.push("M.tsn IS NOT NULL"),
+                false => builder.push("M.tsn IS NULL"),
+            },
+            Self::After(cursor) => builder
+                .push("(COALESCE(T.phylo_sort_seq, 0), T.tsn) > (")
+                .push_bind(cursor.seq)
+                .push(", ")
+                .push_bind(cursor.id)
+                .push(")"),
+        };
+    }
+}
+
+/// A single search term of a quickfind query
+#[derive(Debug, PartialEq, Clone)]
+enum QueryTerm {
+    /// a (partial) name, matched against any name part or vernacular name
+    Name(String),
+    /// an abbreviated genus name such as `A.`, matched against the start of the genus
+    Abbreviation(String),
+}
+
+/// Abbreviations that occur in scientific names to indicate the rank of the following name part,
+/// e.g. `var.`. These don't need to match anything, so they are dropped from queries.
+const RANK_MARKERS: [&str; 6] = ["var", "subsp", "ssp", "f", "fo", "cv"];
+
+/// Markers that indicate a hybrid, e.g. `Elymus ×macounii` or `Elymus x macounii`
+const HYBRID_MARKERS: [&str; 3] = ["×", "x", "X"];
+
+fn parse_query(query: &str) -> Vec<QueryTerm> {
+    let mut terms = Vec::new();
+    for token in query.split_whitespace() {
+        if HYBRID_MARKERS.contains(&token) {
+            continue;
+        }
+        let token = token.trim_start_matches('×');
+        let term = match token.strip_suffix('.') {
+            // only the first term can be an abbreviated genus
+            Some(abbr) if terms.is_empty() => QueryTerm::Abbreviation(abbr.to_string()),
+            Some(marker) if RANK_MARKERS.contains(&marker) => continue,
+            Some(name) => QueryTerm::Name(name.to_string()),
+            None => QueryTerm::Name(token.to_string()),
+        };
+        match term {
+            QueryTerm::Name(ref s) | QueryTerm::Abbreviation(ref s) if s.is_empty() => continue,
+            _ => terms.push(term),
+        }
+    }
+    terms
+}
+
+/// Build a filter for a free-form taxon search as typed by a user. Each whitespace-separated term
+/// must match some part of the taxon's name or one of its common names, so "asc tub" finds
+/// *Asclepias tuberosa*. Genus abbreviations ("A. tuberosa"), rank markers ("var.") and hybrid
+/// markers ("×") are also understood. Use [rank_quickfind_results] to put the best matches first.
+pub fn quickfind(taxon: String) -> Option<DynFilterPart> {
+    let terms = parse_query(&taxon);
+    match terms.is_empty() {
+        true => None,
+        false => {
+            let mut filter = CompoundFilter::builder(Op::And);
+            for term in terms {
+                filter = match term {
+                    QueryTerm::Name(s) => filter.push(any_filter(&s)),
+                    QueryTerm::Abbreviation(s) => filter.push(Filter::Name1Prefix(s)),
+                };
+            }
+            Some(filter.build())
+        }
+    }
+}
+
+/// How well a taxon matches a quickfind query. Higher is better.
+fn quickfind_score(terms: &[QueryTerm], taxon: &Taxon) -> u32 {
+    let names: Vec<String> = [&taxon.name1, &taxon.name2, &taxon.name3]
+        .into_iter()
+        .flatten()
+        .map(|n| n.to_lowercase())
+        .collect();
+    let mut score = 0;
+    let mut positional = true;
+    for (i, term) in terms.iter().enumerate() {
+        let (s, name) = match term {
+            QueryTerm::Name(s) | QueryTerm::Abbreviation(s) => (s.to_lowercase(), names.get(i)),
+        };
+        match name {
+            Some(name) if *name == s => score += 20,
+            Some(name) if name.starts_with(&s) => score += 10,
+            _ => positional = false,
+        }
+    }
+    if positional && terms.len() == names.len() {
+        score += 100;
+    } else if positional {
+        score += 50;
+    }
+    let query = terms
+        .iter()
+        .map(|t| match t {
+            QueryTerm::Name(s) | QueryTerm::Abbreviation(s) => s.to_lowercase(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    for vernacular in taxon.vernaculars.iter().map(|v| v.to_lowercase()) {
+        if vernacular == query {
+            score = score.max(150);
+        } else if vernacular.starts_with(&query) {
+            score = score.max(40);
+        }
+    }
+    score
+}
+
+/// Sort the results of a [quickfind] query so that the best matches come first: taxa whose names
+/// start with the query terms in order, followed by those that merely contain them. Taxa that
+/// match equally well keep their existing (taxonomic) order.
+pub fn rank_quickfind_results(query: &str, taxa: &mut [Taxon]) {
+    let terms = parse_query(query);
+    taxa.sort_by_cached_key(|taxon| std::cmp::Reverse(quickfind_score(&terms, taxon)));
+}
+
+pub fn filter_by(
+    id: Option<i64>,
+    rank: Option<Rank>,
+    genus: Option<String>,
+    species: Option<String>,
+    any: Option<String>,
+    minnesota: Option<bool>,
+) -> Option<DynFilterPart> {
+    let mut f = CompoundFilter::builder(Op::And);
+    if let Some(id) = id {
+        f = f.push(Filter::Id(id));
+    }
+    if let Some(rank) = rank {
+        f = f.push(Filter::Rank(rank));
+    }
+    if let Some(genus) = genus {
+        f = f.push(Filter::Genus(genus));
+    }
+    if let Some(species) = species {
+        f = f.push(Filter::Species(species));
+    }
+    if let Some(f2) = any.and_then(quickfind) {
+        f = f.push(f2);
+    }
+    if let Some(val) = minnesota {
+        f = f.push(Filter::Minnesota(val));
+    }
+
+    Some(f.build())
+}
+
+pub fn any_filter(s: &str) -> DynFilterPart {
+    CompoundFilter::builder(Op::Or)
+        .push(Filter::Name1(s.to_string()))
+        .push(Filter::Name2(s.to_string()))
+        .push(Filter::Name3(s.to_string()))
+        .push(Filter::Vernacular(s.to_string()))
+        .build()
+}
+
+/// Limits a query to the accepted names of plants, which are the only taxa that are used
+struct AcceptedPlants;
+
+impl FilterPart for AcceptedPlants {
+    fn add_to_query(&self, builder: &mut sqlx::QueryBuilder<sqlx::Sqlite>) {
+        builder
+            .push(r#"T.name_usage="accepted" AND T.kingdom_id="#)
+            .push_bind(KINGDOM_PLANTAE);
+    }
+}
+
+/// Recompute the taxonomic sort order (`phylo_sort_seq`) of all taxa from the ITIS hierarchy table
+/// so that sorting by sequence groups related taxa together. Returns the number of taxa whose
+/// sort order changed.
+pub async fn ensure_taxonomic_order(pool: &Pool<Sqlite>) -> Result<u64> {
+    let res = sqlx::query(
+        r#"UPDATE taxonomic_units SET phylo_sort_seq = H.seq
+        FROM (SELECT ROW_NUMBER() OVER (ORDER BY hierarchy_string) AS seq, tsn FROM hierarchy) AS H
+        WHERE H.tsn=taxonomic_units.tsn AND taxonomic_units.phylo_sort_seq IS NOT H.seq"#,
+    )
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// The SQL expression that builds the complete name of a taxon from its individual name parts
+pub(crate) const COMPLETE_NAME_SQL: &str = r#"TRIM(
+    COALESCE(NULLIF(TRIM(unit_ind1), '') || ' ', '') || TRIM(unit_name1) ||
+    COALESCE(' ' || NULLIF(TRIM(unit_ind2), ''), '') ||
+    COALESCE(' ' || NULLIF(TRIM(unit_name2), ''), '') ||
+    COALESCE(' ' || NULLIF(TRIM(unit_ind3), ''), '') ||
+    COALESCE(' ' || NULLIF(TRIM(unit_name3), ''), '') ||
+    COALESCE(' ' || NULLIF(TRIM(unit_ind4), ''), '') ||
+    COALESCE(' ' || NULLIF(TRIM(unit_name4), ''), ''))"#;
+
+/// Rebuild the `complete_name` of all plant taxa from their individual name parts. Returns the
+/// number of taxa whose name changed.
+pub async fn refresh_complete_names(pool: &Pool<Sqlite>) -> Result<u64> {
+    let res = sqlx::query(&format!(
+        r#"UPDATE taxonomic_units SET complete_name = N.name
+        FROM (SELECT tsn, {COMPLETE_NAME_SQL} AS name
+            FROM taxonomic_units WHERE kingdom_id=?) AS N
+        WHERE N.tsn=taxonomic_units.tsn AND taxonomic_units.complete_name IS NOT N.name"#
+    ))
+    .bind(KINGDOM_PLANTAE)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+impl Taxon {
+    /// The invasive status of the taxon, if it applies to a user in the given region
+    pub fn invasive_in(&self, region: Option<&str>) -> Option<InvasiveStatus> {
+        self.invasive_status.filter(|_| status_applies_to(region))
+    }
+
+    pub async fn fetch_hierarchy(&self, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        let mut hierarchy = Vec::new();
+        let mut taxon = Taxon::load(self.id, pool).await?;
+        loop {
+            let parentid = taxon.parentid;
+            hierarchy.push(taxon);
+            match parentid {
+                Some(id) if id > 0 => {
+                    taxon = Taxon::load(id, pool).await?;
+                }
+                _ => break,
+            }
+        }
+
+        Ok(hierarchy)
+    }
+
+    pub async fn fetch_children(&self, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        let mut query = Taxon::build_query(Some(Filter::ParentId(self.id).into()), None);
+        Ok(query.build_query_as().fetch_all(pool).await?)
+    }
+
+    fn list_query(filter: Option<DynFilterPart>) -> ListQuery {
+        let mut conditions =
+            CompoundFilter::builder(Op::And).push(Arc::new(AcceptedPlants) as DynFilterPart);
+        if let Some(filter) = filter {
+            conditions = conditions.push(filter);
+        }
+        ListQuery::new(
+            r#"T.tsn,
+                T.parent_tsn as parentid,
+                T.unit_name1,
+                T.unit_name2,
+                T.unit_name3,
+                T.complete_name,
+                T.rank_id,
+                T.phylo_sort_seq as seq,
+                M.native_status,
+                M.invasive_status,
+                GROUP_CONCAT(V.vernacular_name, "@") as cnames"#,
+            r#"taxonomic_units T
+            LEFT JOIN (
+                SELECT *
+                FROM vernaculars
+                WHERE ( language="English" OR language="unspecified" )
+            ) V on V.tsn=T.tsn
+            LEFT JOIN mntaxa M on T.tsn=M.tsn"#,
+            Some(conditions.build()),
+        )
+        .group_by("T.tsn")
+    }
+
+    fn build_query(
+        filter: Option<DynFilterPart>,
+        limit: Option<LimitSpec>,
+    ) -> sqlx::QueryBuilder<'static, sqlx::Sqlite> {
+        let mut builder = Self::list_query(filter).select();
+        builder.push(" ORDER BY COALESCE(T.phylo_sort_seq, 0), T.tsn");
+        if let Some(limit) = limit {
+            limit.push_to(&mut builder);
+        }
+        debug!("generated sql: <<{}>>", builder.sql());
+        builder
+    }
+
+    pub async fn load_all(
+        filter: Option<DynFilterPart>,
+        limit: Option<LimitSpec>,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Vec<Taxon>, sqlx::Error> {
+        Taxon::build_query(filter, limit)
+            .build_query_as()
+            .fetch_all(pool)
+            .await
+    }
+
+    /// The number of taxa that match `filter`
+    pub async fn count(filter: Option<DynFilterPart>, pool: &Pool<Sqlite>) -> Result<i64> {
+        Self::list_query(filter).fetch_count(pool).await
+    }
+
+    /// Load up to `size` taxa that match `filter`, in taxonomic order and starting after the given
+    /// cursor, or from the start if there is none
+    pub async fn load_page(
+        filter: Option<DynFilterPart>,
+        after: Option<TaxonCursor>,
+        size: i32,
+        pool: &Pool<Sqlite>,
+    ) -> Result<TaxonPage> {
+        let mut conditions = CompoundFilter::builder(Op::And);
+        if let Some(filter) = filter {
+            conditions = conditions.push(filter);
+        }
+        if let Some(cursor) = after {
+            conditions = conditions.push(Filter::After(cursor));
+        }
+        // load one extra taxon to find out whether there is another page
+        let mut taxa = Self::load_all(
+            Some(conditions.build()),
+            Some(LimitSpec(size + 1, None)),
+            pool,
+        )
+        .await?;
+        let next = match taxa.len() > size as usize {
+            true => {
+                taxa.truncate(size as usize);
+                taxa.last().map(TaxonCursor::after)
+            }
+            false => None,
+        };
+        Ok(TaxonPage { taxa, next })
+    }
+
+    pub async fn load_germination_info(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        self.germination = Some(
+            sqlx::query_as(
+                r#"SELECT G.* from sc_germination_codes G
+            INNER JOIN sc_taxon_germination TG ON TG.germid=G.germid
+            WHERE TG.tsn=?"#,
+            )
+            .bind(self.id)
+            .fetch_all(pool)
+            .await?,
+        );
+        Ok(())
+    }
+
+    pub async fn load_seed_weight(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        self.seed_weight = SeedWeight::load(self.id, pool).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    const CANADA_WILD_RYE: i64 = 40683;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn filter_germination_codes(pool: Pool<Sqlite>) {
+        sqlx::query(
+            r#"INSERT INTO sc_germination_codes (germid, code, summary) VALUES
+                (1, 'C(60)', 'Cold moist stratification'), (2, 'D', 'Dry storage'),
+                (3, 'A', 'Sow fresh');
+            INSERT INTO sc_taxon_germination (tsn, germid) VALUES
+                (40683, 1), (43254, 1), (43254, 2), (40677, 3)"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let codes = |filter: Option<DynFilterPart>, sort: Option<&str>| {
+            let sort = sort.map(|s| s.parse().unwrap());
+            let pool = pool.clone();
+            async move {
+                Germination::load_all(filter, sort, &pool)
+                    .await
+                    .unwrap()
+                    .iter()
+                    .map(|g| g.code.clone())
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(codes(None, None).await, ["A", "C(60)", "D"]);
+        assert_eq!(codes(None, Some("-taxa,code")).await, ["C(60)", "A", "D"]);
+        assert_eq!(
+            codes(GerminationFilter::Code(Cmp::Like, "c(".into()).into(), None).await,
+            ["C(60)"]
+        );
+        assert_eq!(
+            codes(GerminationFilter::Taxon(43254).into(), None).await,
+            ["C(60)", "D"]
+        );
+        // only the codes of the taxa that the user has samples of
+        assert_eq!(
+            codes(GerminationFilter::UsedBySamples(1).into(), None).await,
+            ["C(60)", "D"]
+        );
+        assert_eq!(
+            codes(GerminationFilter::UsedBySamples(2).into(), None).await,
+            ["C(60)"]
+        );
+        let both = CompoundFilter::builder(Op::And)
+            .push(GerminationFilter::UsedBySamples(1))
+            .push(GerminationFilter::Code(Cmp::Equal, "D".into()))
+            .build();
+        assert_eq!(codes(Some(both), None).await, ["D"]);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn fetch_taxon(pool: Pool<Sqlite>) {
+        let taxon = Taxon::load(CANADA_WILD_RYE, &pool)
+            .await
+            .expect("Unable to load taxon");
+        assert_eq!(taxon.name1, Some("Elymus".to_string()));
+        assert_eq!(taxon.name2, Some("canadensis".to_string()));
+        assert_eq!(taxon.rank, Rank::Species);
+        assert!(taxon
+            .vernaculars
+            .iter()
+            .find(|v| v == &"Canada wildrye")
+            .is_some());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn invasive_status(pool: Pool<Sqlite>) {
+        let taxon = Taxon::load(CANADA_WILD_RYE, &pool)
+            .await
+            .expect("Unable to load taxon");
+        assert_eq!(taxon.invasive_status, None);
+
+        sqlx::query(
+            "INSERT INTO mntaxa (tsn, native_status, invasive_status) VALUES (?, 'I', 'SN')",
+        )
+        .bind(CANADA_WILD_RYE)
+        .execute(&pool)
+        .await
+        .expect("Failed to insert status");
+        let taxon = Taxon::load(CANADA_WILD_RYE, &pool)
+            .await
+            .expect("Unable to load taxon");
+        assert_eq!(
+            taxon.invasive_status,
+            Some(InvasiveStatus::ProhibitedNoxiousWeed)
+        );
+        assert_eq!(
+            taxon.invasive_in(None),
+            Some(InvasiveStatus::ProhibitedNoxiousWeed)
+        );
+        assert!(taxon.invasive_in(Some("mn")).is_some());
+        assert_eq!(taxon.invasive_in(Some("WI")), None);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn fetch_many(pool: Pool<Sqlite>) {
+        let taxa = Taxon::load_all(
+            Some(Filter::Genus("Elymus".to_string()).into()),
+            None,
+            &pool,
+        )
+        .await
+        .expect("Unable to load taxon");
+        assert_eq!(taxa.len(), 2);
+        assert_eq!(taxa[0].name1, Some("Elymus".to_string()));
+        assert_eq!(taxa[0].name2, None);
+        assert_eq!(taxa[0].rank, Rank::Genus);
+        assert!(taxa[0]
+            .vernaculars
+            .iter()
+            .find(|v| v == &"wildrye")
+            .is_some());
+        assert_eq!(taxa[1].name1, Some("Elymus".to_string()));
+        assert_eq!(taxa[1].name2, Some("canadensis".to_string()));
+        assert_eq!(taxa[1].rank, Rank::Species);
+        assert!(taxa[1]
+            .vernaculars
+            .iter()
+            .find(|v| v == &"Canada wildrye")
+            .is_some());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn count_matches_list(pool: Pool<Sqlite>) {
+        // a second common name, so that joining the common names returns two rows for the taxon
+        sqlx::query(
+            r#"INSERT INTO vernaculars (tsn, vernacular_name, language, approved_ind, update_date, vern_id)
+            VALUES (40683, 'nodding wild rye', 'English', 'N', '2024-01-01', 999999)"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let filters: Vec<Option<DynFilterPart>> = vec![
+            None,
+            Some(Filter::Genus("Elymus".to_string()).into()),
+            Some(Filter::Rank(Rank::Species).into()),
+            Some(Filter::Vernacular("wild".to_string()).into()),
+            Some(any_filter("wild")),
+            Some(Filter::Minnesota(false).into()),
+        ];
+        for filter in filters {
+            let taxa = Taxon::load_all(filter.clone(), None, &pool)
+                .await
+                .expect("Failed to load taxa");
+            let count = Taxon::count(filter, &pool)
+                .await
+                .expect("Failed to count taxa");
+            assert!(!taxa.is_empty());
+            assert_eq!(count, taxa.len() as i64);
+        }
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn quickfind_queries(pool: Pool<Sqlite>) {
+        async fn find(query: &str, pool: &Pool<Sqlite>) -> Vec<i64> {
+            let mut taxa = Taxon::load_all(quickfind(query.to_string()), None, pool)
+                .await
+                .expect("Failed to load taxa");
+            rank_quickfind_results(query, &mut taxa);
+            taxa.iter().map(|t| t.id).collect()
+        }
+
+        assert_eq!(
+            parse_query("A. tuberosa var. interior"),
+            vec![
+                QueryTerm::Abbreviation("A".to_string()),
+                QueryTerm::Name("tuberosa".to_string()),
+                QueryTerm::Name("interior".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse_query("  Elymus x ×macounii "),
+            vec![
+                QueryTerm::Name("Elymus".to_string()),
+                QueryTerm::Name("macounii".to_string()),
+            ]
+        );
+        assert!(quickfind(" × ".to_string()).is_none());
+
+        assert_eq!(find("ely can", &pool).await, vec![CANADA_WILD_RYE]);
+        assert_eq!(find("E. canadensis", &pool).await, vec![CANADA_WILD_RYE]);
+        assert_eq!(
+            find("Elymus × canadensis", &pool).await,
+            vec![CANADA_WILD_RYE]
+        );
+        assert!(find("S. canadensis", &pool).await.is_empty());
+        // the genus is the best match for a single name, the species for two names
+        assert_eq!(find("elymus", &pool).await, vec![40677, CANADA_WILD_RYE]);
+        assert_eq!(find("sisy camp", &pool).await, vec![43254]);
+        // an exact common name ranks above one that merely contains the query
+        assert_eq!(find("wildrye", &pool).await, vec![40677, CANADA_WILD_RYE]);
+        assert_eq!(find("canada wildrye", &pool).await, vec![CANADA_WILD_RYE]);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn reindex(pool: Pool<Sqlite>) {
+        // names in the fixture are already correct
+        assert_eq!(refresh_complete_names(&pool).await.unwrap(), 0);
+        sqlx::query("UPDATE taxonomic_units SET complete_name='broken' WHERE tsn=?")
+            .bind(CANADA_WILD_RYE)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(refresh_complete_names(&pool).await.unwrap(), 1);
+        let taxon = Taxon::load(CANADA_WILD_RYE, &pool).await.unwrap();
+        assert_eq!(taxon.complete_name, "Elymus canadensis");
+
+        sqlx::query(
+            r#"INSERT INTO hierarchy (hierarchy_string, TSN, Parent_TSN, level, ChildrenCount)
+            VALUES ("202422-40677", 40677, 202422, 1, 1),
+                   ("202422-40677-40683", 40683, 40677, 2, 0)"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        assert_eq!(ensure_taxonomic_order(&pool).await.unwrap(), 2);
+        // running it again shouldn't change anything
+        assert_eq!(ensure_taxonomic_order(&pool).await.unwrap(), 0);
+        let genus = Taxon::load(40677, &pool).await.unwrap();
+        let species = Taxon::load(CANADA_WILD_RYE, &pool).await.unwrap();
+        assert_eq!(genus.seq, Some(1));
+        assert_eq!(species.seq, Some(2));
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn seed_weights(pool: Pool<Sqlite>) {
+        let mut taxon = Taxon::load(CANADA_WILD_RYE, &pool).await.unwrap();
+        taxon.load_seed_weight(&pool).await.unwrap();
+        assert_eq!(taxon.seed_weight, None);
+
+        let mut weight = SeedWeight {
+            tsn: CANADA_WILD_RYE,
+            seeds_per_gram: 190.0,
+            source: Some("Prairie Moon".to_string()),
+        };
+        weight
+            .save(&pool)
+            .await
+            .expect("Failed to save seed weight");
+        taxon.load_seed_weight(&pool).await.unwrap();
+        assert_eq!(taxon.seed_weight.as_ref(), Some(&weight));
+
+        // saving again replaces the existing value
+        weight.seeds_per_gram = 200.0;
+        weight.source = None;
+        weight
+            .save(&pool)
+            .await
+            .expect("Failed to update seed weight");
+        let loaded = SeedWeight::load(CANADA_WILD_RYE, &pool)
+            .await
+            .unwrap()
+            .expect("Seed weight not found");
+        assert_eq!(loaded, weight);
+        assert_eq!(SeedWeight::load_all(&pool).await.unwrap().len(), 1);
+        assert_eq!(loaded.estimated_weight(500), 2.5);
+
+        weight.seeds_per_gram = 0.0;
+        assert!(matches!(
+            weight.save(&pool).await,
+            Err(Error::InvalidValue(_))
+        ));
+
+        SeedWeight::delete(CANADA_WILD_RYE, &pool).await.unwrap();
+        assert_eq!(
+            SeedWeight::load(CANADA_WILD_RYE, &pool).await.unwrap(),
+            None
+        );
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn load_taxa_by_page(pool: Pool<Sqlite>) {
+        let all: Vec<i64> = Taxon::load_all(None, None, &pool)
+            .await
+            .unwrap()
+            .iter()
+            .map(|t| t.id)
+            .collect();
+        assert!(all.len() > 5);
+
+        let mut paged = Vec::new();
+        let mut after = None;
+        loop {
+            let page = Taxon::load_page(None, after, 2, &pool).await.unwrap();
+            assert!(page.taxa.len() <= 2);
+            paged.extend(page.taxa.iter().map(|t| t.id));
+            match page.next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(paged, all);
+
+        // the cursor survives a round trip through a url, and filters still apply after it
+        let page = Taxon::load_page(None, None, 1, &pool).await.unwrap();
+        let cursor: TaxonCursor = page.next.unwrap().to_string().parse().unwrap();
+        let page = Taxon::load_page(
+            Some(Filter::Rank(Rank::Species).into()),
+            Some(cursor),
+            10,
+            &pool,
+        )
+        .await
+        .unwrap();
+        assert!(page.taxa.iter().all(|t| t.rank == Rank::Species));
+        assert!(page.taxa.iter().any(|t| t.id == CANADA_WILD_RYE));
+        assert_eq!(page.next, None);
+        assert!("12".parse::<TaxonCursor>().is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! Display names: local names for a taxon that are shown instead of its ITIS name. Each user can
+//! give a taxon a personal name, and each organization can give it a name that is shared by all of
+//! its members. A personal name takes precedence over the names of the user's organizations. The
+//! canonical ITIS name is still used wherever data leaves the application, e.g. in exports.
+use crate::{
+    cultivation::Scope,
+    error::{Error, Result},
+    organization::{Organization, Permission},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Sqlite};
+use std::collections::HashMap;
+
+/// A display name of a taxon as seen by a particular user
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct DisplayName {
+    #[sqlx(rename = "tnameid")]
+    pub id: i64,
+    pub tsn: i64,
+    /// the user that the name belongs to, for personal names
+    pub userid: Option<i64>,
+    /// the organization that the name belongs to, for shared names
+    pub orgid: Option<i64>,
+    pub orgname: Option<String>,
+    #[sqlx(rename = "tname")]
+    pub name: String,
+}
+
+impl DisplayName {
+    /// Load all display names of the taxon that the user can see, starting with the one that is
+    /// shown: the user's personal name followed by the names of the user's organizations (in the
+    /// order of their names)
+    pub async fn load_taxon(tsn: i64, userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            r#"SELECT * FROM vtaxonnames WHERE tsn=? AND viewerid=?
+            ORDER BY tnamelayer, orgname"#,
+        )
+        .bind(tsn)
+        .bind(userid)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Load all display names that the user can see, in taxonomic order
+    pub async fn load_all_user(userid: i64, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(
+            r#"SELECT N.* FROM vtaxonnames N
+            INNER JOIN taxonomic_units T ON T.tsn=N.tsn
+            WHERE N.viewerid=?
+            ORDER BY T.phylo_sort_seq, N.tnamelayer, N.orgname"#,
+        )
+        .bind(userid)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// The name that is shown to the user for each taxon that has a display name, by taxon id
+    pub async fn load_map(userid: i64, pool: &Pool<Sqlite>) -> Result<HashMap<i64, String>> {
+        let mut map = HashMap::new();
+        // the first name of each taxon takes precedence
+        for name in Self::load_all_user(userid, pool).await? {
+            map.entry(name.tsn).or_insert(name.name);
+        }
+        Ok(map)
+    }
+
+    /// Load the display name of the taxon with the given scope, if there is one
+    pub async fn load_scope(tsn: i64, scope: Scope, pool: &Pool<Sqlite>) -> Result<Option<Self>> {
+        let query = match scope {
+            Scope::Personal(userid) => sqlx::query_as(
+                "SELECT * FROM vtaxonnames WHERE tsn=? AND userid=? AND viewerid=userid",
+            )
+            .bind(tsn)
+            .bind(userid),
+            Scope::Organization(orgid) => {
+                sqlx::query_as("SELECT * FROM vtaxonnames WHERE tsn=? AND orgid=? LIMIT 1")
+                    .bind(tsn)
+                    .bind(orgid)
+            }
+        };
+        query.fetch_optional(pool).await.map_err(Into::into)
+    }
+
+    /// Save the display name of the taxon with the given scope on behalf of the given user. The
+    /// user must be allowed to edit the objects of the organization to change its names. Saving an
+    /// empty name removes it.
+    pub async fn save(
+        tsn: i64,
+        scope: Scope,
+        name: &str,
+        editor: i64,
+        pool: &Pool<Sqlite>,
+    ) -> Result<Option<Self>> {
+        let (userid, orgid) = match scope {
+            Scope::Personal(userid) if userid == editor => (Some(userid), None),
+            Scope::Personal(_) => {
+                return Err(Error::InvalidOperation(
+                    "the personal names of other users can't be changed".to_string(),
+                ))
+            }
+            Scope::Organization(orgid) => {
+                if !Organization::role(orgid, editor, pool)
+                    .await?
+                    .is_some_and(|role| role.permits(Permission::Edit))
+                {
+                    return Err(Error::InvalidOperation(
+                        "not allowed to change the names of this organization".to_string(),
+                    ));
+                }
+                (None, Some(orgid))
+            }
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            sqlx::query("DELETE FROM sc_taxon_names WHERE tsn=? AND (userid=? OR orgid=?)")
+                .bind(tsn)
+                .bind(userid)
+                .bind(orgid)
+                .execute(pool)
+                .await?;
+            return Ok(None);
+        }
+        let conflict = match scope {
+            Scope::Personal(_) => "(tsn, userid) WHERE userid IS NOT NULL",
+            Scope::Organization(_) => "(tsn, orgid) WHERE orgid IS NOT NULL",
+        };
+        sqlx::query(&format!(
+            r#"INSERT INTO sc_taxon_names (tsn, userid, orgid, tname) VALUES (?, ?, ?, ?)
+            ON CONFLICT{conflict} DO UPDATE SET tname=excluded.tname"#
+        ))
+        .bind(tsn)
+        .bind(userid)
+        .bind(orgid)
+        .bind(name)
+        .execute(pool)
+        .await?;
+        Self::load_scope(tsn, scope, pool).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::organization::OrgRole;
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("users", "taxa"))
+    ))]
+    async fn display_names(pool: Pool<Sqlite>) {
+        let tsn = 40683;
+        assert!(DisplayName::load_map(1, &pool).await.unwrap().is_empty());
+
+        // organization names are shared with its members
+        let mut org = Organization::new("Prairie Group".to_string(), None);
+        org.insert(1, &pool).await.unwrap();
+        org.set_member(2, OrgRole::Viewer, &pool).await.unwrap();
+        assert!(
+            DisplayName::save(tsn, Scope::Organization(org.id), "Wild rye", 2, &pool)
+                .await
+                .is_err()
+        );
+        let shared = DisplayName::save(tsn, Scope::Organization(org.id), " Wild rye ", 1, &pool)
+            .await
+            .expect("Failed to save name")
+            .expect("Name was not saved");
+        assert_eq!(shared.name, "Wild rye");
+        assert_eq!(shared.orgname.as_deref(), Some("Prairie Group"));
+        assert_eq!(
+            DisplayName::load_map(2, &pool).await.unwrap().get(&tsn),
+            Some(&"Wild rye".to_string())
+        );
+
+        // a personal name takes precedence and isn't visible to other users
+        DisplayName::save(tsn, Scope::Personal(1), "Canada rye", 1, &pool)
+            .await
+            .unwrap();
+        assert!(
+            DisplayName::save(tsn, Scope::Personal(1), "Mine now", 2, &pool)
+                .await
+                .is_err()
+        );
+        let names = DisplayName::load_taxon(tsn, 1, &pool).await.unwrap();
+        assert_eq!(
+            names.iter().map(|n| n.name.as_str()).collect::<Vec<_>>(),
+            vec!["Canada rye", "Wild rye"]
+        );
+        assert_eq!(
+            DisplayName::load_map(1, &pool).await.unwrap().get(&tsn),
+            Some(&"Canada rye".to_string())
+        );
+        assert_eq!(
+            DisplayName::load_taxon(tsn, 2, &pool).await.unwrap().len(),
+            1
+        );
+
+        // empty names are removed
+        assert_eq!(
+            DisplayName::save(tsn, Scope::Personal(1), "  ", 1, &pool)
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            DisplayName::load_map(1, &pool).await.unwrap().get(&tsn),
+            Some(&"Wild rye".to_string())
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! ITIS releases that are staged to upgrade the taxonomy from the web interface. A staged release
+//! is fetched and [checked](StagedRelease::check) in the background, which records the taxa that
+//! installing it would change so that an administrator can review them first. Once the changes
+//! have been reviewed, the release is [applied](StagedRelease::apply) in the background as well,
+//! which records an [UpgradeReport] just like upgrading the taxonomy with `seedctl` does.
+//!
+//! The staged release records its progress while it is fetched, checked and applied, so that the
+//! progress can be shown while the background jobs run. Only the path of the release is kept
+//! here; the file itself belongs to whoever staged the release.
+use super::{
+    itis::ItisRelease,
+    upgrade::{self, TaxonChange, UpgradeReport},
+};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Sqlite};
+use std::path::Path;
+use strum_macros::Display;
+use time::OffsetDateTime;
+
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, sqlx::Type, PartialEq, Display)]
+#[repr(i32)]
+pub enum StagedStatus {
+    /// the release is being downloaded or uploaded
+    Fetching = 0,
+    /// the release is being checked and compared with the current taxonomy
+    Checking = 1,
+    /// the changes of the release can be reviewed before it is applied
+    Ready = 2,
+    Applying = 3,
+    Applied = 4,
+    Failed = 5,
+}
+
+impl StagedStatus {
+    /// Whether a background job is still working on the release
+    pub fn is_running(&self) -> bool {
+        matches!(self, Self::Fetching | Self::Checking | Self::Applying)
+    }
+}
+
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct StagedRelease {
+    #[sqlx(rename = "releaseid")]
+    pub id: i64,
+    /// the administrator that staged the release
+    pub userid: i64,
+    #[sqlx(rename = "releasestatus")]
+    pub status: StagedStatus,
+    /// where the release came from, e.g. the URL it was downloaded from or the name of the
+    /// uploaded file
+    #[sqlx(rename = "releasesource")]
+    pub source: String,
+    /// the path of the ITIS database, once it has been fetched
+    #[sqlx(rename = "releasepath")]
+    pub path: Option<String>,
+    /// the date of the most recent change to a taxon in the release, once it has been checked
+    #[sqlx(rename = "releaseupdated")]
+    pub updated: Option<String>,
+    /// the number of plant taxa in the release, once it has been checked
+    #[sqlx(rename = "releasetaxa")]
+    pub taxa: Option<i64>,
+    /// a description of what the background job is currently doing
+    #[sqlx(rename = "releaseprogress")]
+    pub progress: Option<String>,
+    #[sqlx(rename = "releaseerror")]
+    pub error: Option<String>,
+    /// the recorded upgrade, once the release has been applied
+    pub upgradeid: Option<i64>,
+    #[sqlx(rename = "releasecreated")]
+    pub created: Option<OffsetDateTime>,
+    /// the number of taxa that the release would change, once it has been checked
+    pub nchanges: i64,
+}
+
+const SELECT_RELEASE: &str = r#"SELECT R.*,
+    (SELECT COUNT(*) FROM sc_taxonomy_release_changes C WHERE C.releaseid=R.releaseid) AS nchanges
+    FROM sc_taxonomy_releases R"#;
+
+impl StagedRelease {
+    /// Stage a new release that is about to be fetched from `source`
+    pub async fn create(userid: i64, source: &str, pool: &Pool<Sqlite>) -> Result<Self> {
+        let id = sqlx::query(
+            "INSERT INTO sc_taxonomy_releases (userid, releasestatus, releasesource) VALUES (?, ?, ?)",
+        )
+        .bind(userid)
+        .bind(StagedStatus::Fetching)
+        .bind(source)
+        .execute(pool)
+        .await?
+        .last_insert_rowid();
+        Self::load(id, pool).await
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        sqlx::query_as(&format!("{SELECT_RELEASE} WHERE R.releaseid=?"))
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Load all staged releases, newest first
+    pub async fn load_all(pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(&format!(
+            "{SELECT_RELEASE} ORDER BY R.releasecreated DESC, R.releaseid DESC"
+        ))
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    async fn save(&self, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query(
+            r#"UPDATE sc_taxonomy_releases SET releasestatus=?, releasepath=?, releaseupdated=?,
+                releasetaxa=?, releaseprogress=?, releaseerror=?, upgradeid=?
+            WHERE releaseid=?"#,
+        )
+        .bind(self.status)
+        .bind(&self.path)
+        .bind(&self.updated)
+        .bind(self.taxa)
+        .bind(&self.progress)
+        .bind(&self.error)
+        .bind(self.upgradeid)
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Record what the background job is currently doing
+    pub async fn set_progress(&mut self, progress: &str, pool: &Pool<Sqlite>) -> Result<()> {
+        self.progress = Some(progress.to_string());
+        self.save(pool).await
+    }
+
+    /// Record that the release could not be fetched, checked or applied
+    pub async fn fail(&mut self, error: &str, pool: &Pool<Sqlite>) -> Result<()> {
+        self.status = StagedStatus::Failed;
+        self.progress = None;
+        self.error = Some(error.to_string());
+        self.save(pool).await
+    }
+
+    /// Check that the fetched ITIS database at `path` can be installed and record the taxa that
+    /// installing it would change, so that they can be reviewed
+    pub async fn check(&mut self, path: &Path, pool: &Pool<Sqlite>) -> Result<()> {
+        if self.status != StagedStatus::Fetching {
+            return Err(Error::InvalidValue(
+                "The release has already been checked".to_string(),
+            ));
+        }
+        self.status = StagedStatus::Checking;
+        self.path = Some(path.to_string_lossy().to_string());
+        self.set_progress("Comparing the release with the current taxonomy", pool)
+            .await?;
+        let release = ItisRelease::open(path, pool).await?;
+        let changes = release.preview(pool).await?;
+        let mut tx = pool.begin().await?;
+        sqlx::query("DELETE FROM sc_taxonomy_release_changes WHERE releaseid=?")
+            .bind(self.id)
+            .execute(&mut *tx)
+            .await?;
+        for change in &changes {
+            sqlx::query(
+                r#"INSERT INTO sc_taxonomy_release_changes (releaseid, tsn, rank_id, oldname,
+                    newname, oldparenttsn, oldparentname, newparenttsn, newparentname)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+            )
+            .bind(self.id)
+            .bind(change.tsn)
+            .bind(change.rank_id)
+            .bind(&change.oldname)
+            .bind(&change.newname)
+            .bind(change.oldparenttsn)
+            .bind(&change.oldparentname)
+            .bind(change.newparenttsn)
+            .bind(&change.newparentname)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        self.status = StagedStatus::Ready;
+        self.updated = release.updated;
+        self.taxa = Some(release.taxa);
+        self.progress = None;
+        self.nchanges = changes.len() as i64;
+        self.save(pool).await
+    }
+
+    /// Load the taxa that installing the release would change. They are not part of a recorded
+    /// upgrade yet, so their `upgradeid` is 0.
+    pub async fn changes(&self, pool: &Pool<Sqlite>) -> Result<Vec<TaxonChange>> {
+        sqlx::query_as(
+            r#"SELECT changeid, 0 AS upgradeid, tsn, rank_id, oldname, newname, oldparenttsn,
+                oldparentname, newparenttsn, newparentname
+            FROM sc_taxonomy_release_changes WHERE releaseid=?
+            ORDER BY rank_id, COALESCE(newname, oldname)"#,
+        )
+        .bind(self.id)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Mark a reviewed release as being applied. Only one release can be applied at a time, so
+    /// this fails if another release is being applied already.
+    pub async fn start_applying(&mut self, pool: &Pool<Sqlite>) -> Result<()> {
+        let res = sqlx::query(
+            r#"UPDATE sc_taxonomy_releases SET releasestatus=?1, releaseprogress=NULL
+            WHERE releaseid=?2 AND releasestatus=?3
+                AND NOT EXISTS (SELECT 1 FROM sc_taxonomy_releases WHERE releasestatus=?1)"#,
+        )
+        .bind(StagedStatus::Applying)
+        .bind(self.id)
+        .bind(StagedStatus::Ready)
+        .execute(pool)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::InvalidValue(
+                "The release is not ready to be applied, or another release is being applied"
+                    .to_string(),
+            ));
+        }
+        self.status = StagedStatus::Applying;
+        self.progress = None;
+        Ok(())
+    }
+
+    /// Install the release and record the upgrade. The taxonomy may have changed since the
+    /// release was checked, so the recorded report is compared with the taxonomy as it is right
+    /// before the release is installed.
+    pub async fn apply(&mut self, pool: &Pool<Sqlite>) -> Result<UpgradeReport> {
+        if self.status != StagedStatus::Applying {
+            return Err(Error::InvalidValue(
+                "The release has not been marked as being applied".to_string(),
+            ));
+        }
+        let Some(path) = self.path.clone() else {
+            return Err(Error::InvalidValue(
+                "The release has not been fetched".to_string(),
+            ));
+        };
+        let release = ItisRelease::open(Path::new(&path), pool).await?;
+        self.set_progress("Saving a snapshot of the current taxonomy", pool)
+            .await?;
+        upgrade::snapshot(pool).await?;
+        self.set_progress("Installing the plant taxa of the release", pool)
+            .await?;
+        release.install(pool).await?;
+        self.set_progress("Recording the changes", pool).await?;
+        let label = release.updated.map(|date| format!("ITIS {date}"));
+        let report = UpgradeReport::record(label.as_deref(), pool).await?;
+        self.status = StagedStatus::Applied;
+        self.progress = None;
+        self.upgradeid = Some(report.id);
+        self.save(pool).await?;
+        Ok(report)
+    }
+
+    pub async fn delete(&self, pool: &Pool<Sqlite>) -> Result<()> {
+        sqlx::query("DELETE FROM sc_taxonomy_releases WHERE releaseid=?")
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{sqlite::SqliteConnectOptions, Connection, SqliteConnection};
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("users", "taxa"))
+    ))]
+    async fn test_staged_release(pool: Pool<Sqlite>) {
+        let path = std::env::temp_dir().join(format!(
+            "libseed-staged-release-{}.sqlite",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        sqlx::query("VACUUM INTO ?")
+            .bind(path.to_string_lossy().to_string())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let mut conn = SqliteConnection::connect_with(&SqliteConnectOptions::new().filename(&path))
+            .await
+            .unwrap();
+        sqlx::query("UPDATE taxonomic_units SET unit_name1='Leymus' WHERE tsn=40677")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        conn.close().await.unwrap();
+
+        let mut staged = StagedRelease::create(1, "itisSqlite.zip", &pool)
+            .await
+            .unwrap();
+        assert_eq!(staged.status, StagedStatus::Fetching);
+        // a release can only be applied once it has been reviewed
+        assert!(staged.start_applying(&pool).await.is_err());
+
+        staged.check(&path, &pool).await.unwrap();
+        assert_eq!(staged.status, StagedStatus::Ready);
+        assert_eq!(StagedRelease::load(staged.id, &pool).await.unwrap(), staged);
+        assert_eq!(staged.nchanges, 1);
+        let changes = staged.changes(&pool).await.unwrap();
+        assert_eq!(changes[0].tsn, 40677);
+        assert_eq!(changes[0].newname.as_deref(), Some("Leymus"));
+        // checking the release did not change the taxonomy
+        let name: String =
+            sqlx::query_scalar("SELECT complete_name FROM taxonomic_units WHERE tsn=40677")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(name, "Elymus");
+
+        let mut other = StagedRelease::create(1, "other.zip", &pool).await.unwrap();
+        other.check(&path, &pool).await.unwrap();
+        staged.start_applying(&pool).await.unwrap();
+        // only one release can be applied at a time
+        assert!(other.start_applying(&pool).await.is_err());
+
+        let report = staged.apply(&pool).await.unwrap();
+        assert_eq!(report.nchanges, 1);
+        let loaded = StagedRelease::load(staged.id, &pool).await.unwrap();
+        assert_eq!(loaded.status, StagedStatus::Applied);
+        assert_eq!(loaded.upgradeid, Some(report.id));
+
+        other.fail("oops", &pool).await.unwrap();
+        assert_eq!(StagedRelease::load_all(&pool).await.unwrap().len(), 2);
+        other.delete(&pool).await.unwrap();
+        assert!(StagedRelease::load(other.id, &pool).await.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
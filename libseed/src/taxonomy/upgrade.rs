@@ -0,0 +1,402 @@
+//! Reports of what changed in the taxonomy when the ITIS tables were upgraded to a newer release.
+//!
+//! Before the ITIS tables are replaced, [snapshot] records the accepted plant taxa. Once the new
+//! tables are in place (and [reindexed](super::refresh_complete_names)), [UpgradeReport::record]
+//! compares them with the snapshot and persists every taxon that was added, removed, renamed or
+//! moved to another parent. The changes of a report can be arranged into a before and after
+//! [UpgradeTree] of the affected parts of the hierarchy.
+use super::{Rank, KINGDOM_PLANTAE};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, Pool, Sqlite};
+use std::collections::{BTreeMap, HashSet};
+use time::OffsetDateTime;
+
+/// Record the current state of the accepted plant taxa so that it can be compared with the
+/// taxonomy after an upgrade. Any earlier snapshot that was never recorded is replaced. Returns
+/// the number of taxa in the snapshot.
+pub async fn snapshot(pool: &Pool<Sqlite>) -> Result<u64> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM sc_taxonomy_snapshot")
+        .execute(&mut *tx)
+        .await?;
+    let res = sqlx::query(
+        r#"INSERT INTO sc_taxonomy_snapshot (tsn, parent_tsn, complete_name, rank_id)
+        SELECT tsn, parent_tsn, complete_name, rank_id FROM taxonomic_units
+        WHERE kingdom_id=? AND name_usage="accepted""#,
+    )
+    .bind(KINGDOM_PLANTAE)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(res.rows_affected())
+}
+
+/// A recorded taxonomy upgrade
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct UpgradeReport {
+    #[sqlx(rename = "upgradeid")]
+    pub id: i64,
+    #[sqlx(rename = "upgradedate")]
+    pub date: OffsetDateTime,
+    /// a description of the upgrade, e.g. the ITIS release that was installed
+    #[sqlx(rename = "upgradelabel")]
+    pub label: Option<String>,
+    /// the number of taxa that were changed by the upgrade
+    pub nchanges: i64,
+}
+
+const SELECT_REPORT: &str = r#"SELECT U.*,
+    (SELECT COUNT(*) FROM sc_taxonomy_changes C WHERE C.upgradeid=U.upgradeid) AS nchanges
+    FROM sc_taxonomy_upgrades U"#;
+
+impl UpgradeReport {
+    /// Compare the taxonomy with the [snapshot] taken before the upgrade and persist the
+    /// differences as a new report. The snapshot is discarded afterwards.
+    pub async fn record(label: Option<&str>, pool: &Pool<Sqlite>) -> Result<Self> {
+        let mut tx = pool.begin().await?;
+        let taken: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sc_taxonomy_snapshot")
+            .fetch_one(&mut *tx)
+            .await?;
+        if taken == 0 {
+            return Err(Error::InvalidValue(
+                "There is no taxonomy snapshot to compare with. Take one before upgrading the taxonomy".to_string(),
+            ));
+        }
+        let label = label.map(str::trim).filter(|l| !l.is_empty());
+        let id = sqlx::query("INSERT INTO sc_taxonomy_upgrades (upgradelabel) VALUES (?)")
+            .bind(label)
+            .execute(&mut *tx)
+            .await?
+            .last_insert_rowid();
+        sqlx::query(
+            r#"WITH current AS (SELECT tsn, parent_tsn, complete_name, rank_id FROM taxonomic_units
+                WHERE kingdom_id=?1 AND name_usage="accepted")
+            INSERT INTO sc_taxonomy_changes (upgradeid, tsn, rank_id, oldname, newname,
+                oldparenttsn, oldparentname, newparenttsn, newparentname)
+            SELECT ?2, S.tsn, COALESCE(C.rank_id, S.rank_id), S.complete_name, C.complete_name,
+                S.parent_tsn, SP.complete_name, C.parent_tsn, CP.complete_name
+                FROM sc_taxonomy_snapshot S
+                LEFT JOIN current C ON C.tsn=S.tsn
+                LEFT JOIN sc_taxonomy_snapshot SP ON SP.tsn=S.parent_tsn
+                LEFT JOIN taxonomic_units CP ON CP.tsn=C.parent_tsn
+                WHERE C.tsn IS NULL OR C.complete_name IS NOT S.complete_name
+                    OR C.parent_tsn IS NOT S.parent_tsn
+            UNION ALL
+            SELECT ?2, C.tsn, C.rank_id, NULL, C.complete_name, NULL, NULL, C.parent_tsn,
+                CP.complete_name
+                FROM current C
+                LEFT JOIN taxonomic_units CP ON CP.tsn=C.parent_tsn
+                WHERE C.tsn NOT IN (SELECT tsn FROM sc_taxonomy_snapshot)"#,
+        )
+        .bind(KINGDOM_PLANTAE)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM sc_taxonomy_snapshot")
+            .execute(&mut *tx)
+            .await?;
+        let report = sqlx::query_as(&format!("{SELECT_REPORT} WHERE U.upgradeid=?"))
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(report)
+    }
+
+    pub async fn load(id: i64, pool: &Pool<Sqlite>) -> Result<Self> {
+        sqlx::query_as(&format!("{SELECT_REPORT} WHERE U.upgradeid=?"))
+            .bind(id)
+            .fetch_one(pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Load all recorded upgrades, newest first
+    pub async fn load_all(pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        sqlx::query_as(&format!(
+            "{SELECT_REPORT} ORDER BY U.upgradedate DESC, U.upgradeid DESC"
+        ))
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Load the taxa that were changed by this upgrade
+    pub async fn changes(&self, pool: &Pool<Sqlite>) -> Result<Vec<TaxonChange>> {
+        sqlx::query_as(
+            r#"SELECT * FROM sc_taxonomy_changes WHERE upgradeid=?
+            ORDER BY rank_id, COALESCE(newname, oldname)"#,
+        )
+        .bind(self.id)
+        .fetch_all(pool)
+        .await
+        .map_err(Into::into)
+    }
+}
+
+/// A single taxon that was changed by an upgrade
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct TaxonChange {
+    #[sqlx(rename = "changeid")]
+    pub id: i64,
+    pub upgradeid: i64,
+    pub tsn: i64,
+    pub rank_id: i64,
+    /// the name before the upgrade, `None` if the taxon was added
+    pub oldname: Option<String>,
+    /// the name after the upgrade, `None` if the taxon was removed or is no longer accepted
+    pub newname: Option<String>,
+    pub oldparenttsn: Option<i64>,
+    pub oldparentname: Option<String>,
+    pub newparenttsn: Option<i64>,
+    pub newparentname: Option<String>,
+}
+
+impl TaxonChange {
+    pub fn rank(&self) -> Rank {
+        usize::try_from(self.rank_id)
+            .ok()
+            .and_then(Rank::from_repr)
+            .unwrap_or(Rank::Unknown)
+    }
+
+    pub fn is_added(&self) -> bool {
+        self.oldname.is_none()
+    }
+
+    pub fn is_removed(&self) -> bool {
+        self.newname.is_none()
+    }
+
+    pub fn is_renamed(&self) -> bool {
+        !self.is_added() && !self.is_removed() && self.oldname != self.newname
+    }
+
+    pub fn is_moved(&self) -> bool {
+        !self.is_added() && !self.is_removed() && self.oldparenttsn != self.newparenttsn
+    }
+}
+
+/// A changed taxon on one side of an [UpgradeTree], with the changed taxa below it
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct DiffNode {
+    pub tsn: i64,
+    pub name: String,
+    pub rank: Rank,
+    /// the name of the taxon on the other side of the tree, `None` if it only exists on this side
+    pub counterpart: Option<String>,
+    pub renamed: bool,
+    pub moved: bool,
+    pub children: Vec<DiffNode>,
+}
+
+/// The changed taxa that share an unchanged parent
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct DiffBranch {
+    pub parenttsn: Option<i64>,
+    pub parentname: Option<String>,
+    pub taxa: Vec<DiffNode>,
+}
+
+/// The parts of the hierarchy affected by an upgrade, as they were before and after it
+#[derive(Debug, Serialize, PartialEq, Clone)]
+pub struct UpgradeTree {
+    pub before: Vec<DiffBranch>,
+    pub after: Vec<DiffBranch>,
+}
+
+/// A changed taxon as it appears on one side of the tree
+struct SideTaxon<'a> {
+    change: &'a TaxonChange,
+    name: &'a str,
+    parenttsn: Option<i64>,
+    parentname: Option<&'a str>,
+    counterpart: Option<&'a str>,
+}
+
+impl UpgradeTree {
+    pub fn new(changes: &[TaxonChange]) -> Self {
+        Self {
+            before: Self::side(changes, true),
+            after: Self::side(changes, false),
+        }
+    }
+
+    fn side(changes: &[TaxonChange], before: bool) -> Vec<DiffBranch> {
+        let taxa: Vec<SideTaxon> = changes
+            .iter()
+            .filter_map(|c| {
+                let (name, parenttsn, parentname, counterpart) = match before {
+                    true => (&c.oldname, c.oldparenttsn, &c.oldparentname, &c.newname),
+                    false => (&c.newname, c.newparenttsn, &c.newparentname, &c.oldname),
+                };
+                name.as_deref().map(|name| SideTaxon {
+                    change: c,
+                    name,
+                    parenttsn,
+                    parentname: parentname.as_deref(),
+                    counterpart: counterpart.as_deref(),
+                })
+            })
+            .collect();
+        let tsns: HashSet<i64> = taxa.iter().map(|t| t.change.tsn).collect();
+        // taxa whose parent was changed as well are nested below it, the others are grouped by
+        // their unchanged parent
+        let mut children: BTreeMap<i64, Vec<usize>> = BTreeMap::new();
+        let mut roots: BTreeMap<(Option<&str>, Option<i64>), Vec<usize>> = BTreeMap::new();
+        for (i, taxon) in taxa.iter().enumerate() {
+            match taxon.parenttsn {
+                Some(parent) if tsns.contains(&parent) => {
+                    children.entry(parent).or_default().push(i)
+                }
+                _ => roots
+                    .entry((taxon.parentname, taxon.parenttsn))
+                    .or_default()
+                    .push(i),
+            }
+        }
+        let mut seen = HashSet::new();
+        roots
+            .into_iter()
+            .map(|((parentname, parenttsn), indices)| DiffBranch {
+                parenttsn,
+                parentname: parentname.map(str::to_string),
+                taxa: Self::nodes(&indices, &taxa, &children, &mut seen),
+            })
+            .collect()
+    }
+
+    fn nodes(
+        indices: &[usize],
+        taxa: &[SideTaxon],
+        children: &BTreeMap<i64, Vec<usize>>,
+        seen: &mut HashSet<i64>,
+    ) -> Vec<DiffNode> {
+        let mut nodes = Vec::new();
+        for &i in indices {
+            let taxon = &taxa[i];
+            // guard against a corrupted hierarchy that contains a cycle
+            if !seen.insert(taxon.change.tsn) {
+                continue;
+            }
+            let below = children
+                .get(&taxon.change.tsn)
+                .map(|c| Self::nodes(c, taxa, children, seen))
+                .unwrap_or_default();
+            nodes.push(DiffNode {
+                tsn: taxon.change.tsn,
+                name: taxon.name.to_string(),
+                rank: taxon.change.rank(),
+                counterpart: taxon.counterpart.map(str::to_string),
+                renamed: taxon.change.is_renamed(),
+                moved: taxon.change.is_moved(),
+                children: below,
+            });
+        }
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        nodes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    async fn exec(sql: &str, pool: &Pool<Sqlite>) {
+        sqlx::query(sql).execute(pool).await.unwrap();
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn test_upgrade_report(pool: Pool<Sqlite>) {
+        assert!(UpgradeReport::record(None, &pool).await.is_err());
+        assert!(snapshot(&pool).await.unwrap() > 0);
+
+        // rename a genus and its species, move a genus to another family, drop a species and add
+        // a new one
+        exec(
+            "UPDATE taxonomic_units SET unit_name1='Leymus' WHERE tsn IN (40677, 40683)",
+            &pool,
+        )
+        .await;
+        exec(
+            "UPDATE taxonomic_units SET parent_tsn=40351 WHERE tsn=43237",
+            &pool,
+        )
+        .await;
+        exec(
+            "UPDATE taxonomic_units SET name_usage='not accepted' WHERE tsn=43254",
+            &pool,
+        )
+        .await;
+        exec(
+            r#"INSERT INTO taxonomic_units (tsn, unit_name1, unit_name2, name_usage,
+            credibility_rtng, initial_time_stamp, parent_tsn, kingdom_id, rank_id, update_date)
+            VALUES (999999, 'Leymus', 'virginicus', 'accepted', 'TWG standards met',
+            '2024-01-01 00:00:00', 40677, 3, 220, '2024-01-01')"#,
+            &pool,
+        )
+        .await;
+        crate::taxonomy::refresh_complete_names(&pool)
+            .await
+            .unwrap();
+
+        let report = UpgradeReport::record(Some(" ITIS 2024 "), &pool)
+            .await
+            .unwrap();
+        assert_eq!(report.label.as_deref(), Some("ITIS 2024"));
+        assert_eq!(report.nchanges, 5);
+        // the snapshot has been used up
+        assert!(UpgradeReport::record(None, &pool).await.is_err());
+        let all = UpgradeReport::load_all(&pool).await.unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0], report);
+
+        let changes = report.changes(&pool).await.unwrap();
+        let change = |tsn| changes.iter().find(|c| c.tsn == tsn).unwrap();
+        assert!(change(40677).is_renamed() && !change(40677).is_moved());
+        assert_eq!(change(40683).newname.as_deref(), Some("Leymus canadensis"));
+        assert!(change(43237).is_moved() && !change(43237).is_renamed());
+        assert_eq!(change(43237).oldparentname.as_deref(), Some("Iridaceae"));
+        assert_eq!(change(43237).newparentname.as_deref(), Some("Poaceae"));
+        assert!(change(43254).is_removed());
+        assert!(change(999999).is_added());
+        assert_eq!(change(999999).rank(), Rank::Species);
+
+        let tree = UpgradeTree::new(&changes);
+        let names = |branches: &[DiffBranch]| {
+            branches
+                .iter()
+                .map(|b| b.parentname.clone().unwrap_or_default())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&tree.before), ["Iridaceae", "Poaceae"]);
+        assert_eq!(names(&tree.after), ["Poaceae"]);
+
+        let elymus = &tree.before[1].taxa[0];
+        assert_eq!(elymus.name, "Elymus");
+        assert_eq!(elymus.counterpart.as_deref(), Some("Leymus"));
+        assert_eq!(elymus.children[0].name, "Elymus canadensis");
+        let sisyrinchium = &tree.before[0].taxa[0];
+        assert_eq!(sisyrinchium.children[0].name, "Sisyrinchium campestre");
+        assert_eq!(sisyrinchium.children[0].counterpart, None);
+
+        let after = &tree.after[0].taxa;
+        assert_eq!(after.len(), 2);
+        assert_eq!(after[0].name, "Leymus");
+        assert_eq!(
+            after[0]
+                .children
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<_>>(),
+            ["Leymus canadensis", "Leymus virginicus"]
+        );
+        assert!(after[1].moved);
+        assert!(after[1].children.is_empty());
+    }
+}
@@ -0,0 +1,315 @@
+//! Generating large datasets for performance testing. Unlike the factories, the generator inserts
+//! rows directly with SQL in a single transaction, so that hundreds of thousands of samples can be
+//! added in a reasonable time. The data roughly follows the distributions of a real database: a
+//! few users own most of the samples, a few species are collected much more often than others,
+//! and most seed is collected in late summer and fall.
+use super::{Rng, PLACE_NAMES, PROJECT_NAMES, SAMPLE_NOTES, SITE_TYPES};
+use crate::{
+    error::{Error, Result},
+    project::note::NoteType,
+    sample::Certainty,
+    user::{User, UserStatus},
+};
+use sqlx::{Pool, Sqlite};
+use time::{Date, Month};
+
+/// The relative frequency of collecting seed in June to October
+const MONTH_WEIGHTS: [(u32, f64); 5] = [(6, 0.1), (7, 0.2), (8, 0.3), (9, 0.25), (10, 0.15)];
+
+const NOTE_SUMMARIES: &[(NoteType, &str)] = &[
+    (NoteType::Preparation, "Cold moist stratification"),
+    (NoteType::Preparation, "Scarified with sandpaper"),
+    (NoteType::Germination, "First seedlings emerged"),
+    (NoteType::Germination, "Poor germination"),
+    (NoteType::Planting, "Transplanted to plugs"),
+    (NoteType::Planting, "Planted out in the garden"),
+    (NoteType::Growing, "Flowered in the second year"),
+    (NoteType::Other, "Gave extra seedlings away"),
+];
+
+/// The number of each kind of object to generate
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoadSize {
+    pub users: usize,
+    pub sources: usize,
+    pub samples: usize,
+    pub projects: usize,
+    /// notes about the samples in projects
+    pub notes: usize,
+}
+
+impl LoadSize {
+    /// A dataset of the given number of samples, with the number of the other objects in the
+    /// proportions of a typical database
+    pub fn samples(samples: usize) -> Self {
+        Self {
+            users: samples.div_ceil(2000).max(1),
+            sources: samples.div_ceil(10).max(1),
+            samples,
+            projects: samples.div_ceil(200).max(1),
+            notes: samples / 4,
+        }
+    }
+}
+
+fn random_month(rng: &mut Rng) -> u32 {
+    let mut x = rng.fraction();
+    for (month, weight) in MONTH_WEIGHTS {
+        if x < weight {
+            return month;
+        }
+        x -= weight;
+    }
+    MONTH_WEIGHTS[MONTH_WEIGHTS.len() - 1].0
+}
+
+/// Add the given number of objects to the database. The users that are added all have the password
+/// [super::DEFAULT_PASSWORD]. Samples are of species that are already in the taxonomy database.
+pub async fn generate(size: &LoadSize, seed: u64, pool: &Pool<Sqlite>) -> Result<LoadSize> {
+    if size.samples > 0 && (size.users == 0 || size.sources == 0) {
+        return Err(Error::InvalidValue(
+            "samples need at least one user and one source".to_string(),
+        ));
+    }
+    if size.projects > 0 && size.users == 0 {
+        return Err(Error::InvalidValue(
+            "projects need at least one user".to_string(),
+        ));
+    }
+    if size.notes > 0 && size.projects == 0 {
+        return Err(Error::InvalidValue(
+            "notes need at least one project".to_string(),
+        ));
+    }
+    let mut rng = Rng::new(seed);
+    let species: Vec<i64> = sqlx::query_scalar(
+        "SELECT tsn FROM taxonomic_units WHERE rank_id=220 AND name_usage='accepted' ORDER BY tsn",
+    )
+    .fetch_all(pool)
+    .await?;
+    if species.is_empty() && size.samples > 0 {
+        return Err(Error::InvalidOperation(
+            "there are no species in the taxonomy database".to_string(),
+        ));
+    }
+    // shuffle the species so that the common ones are not all from the same genus
+    let mut species = species;
+    for i in (1..species.len()).rev() {
+        species.swap(i, rng.between(0, i as i64) as usize);
+    }
+    // hashing is slow, so all of the users share a password hash
+    let pwhash = User::hash_password(super::DEFAULT_PASSWORD)?;
+    let run = rng.next_u64() & 0xffff_ffff;
+
+    let mut generated = LoadSize::default();
+    let mut tx = pool.begin().await?;
+    let mut users = Vec::with_capacity(size.users);
+    for i in 0..size.users {
+        let username = format!("load{run:08x}-{i}");
+        let id = sqlx::query(
+            r#"INSERT INTO sc_users (username, useremail, pwhash, userstatus, userdisplayname)
+            VALUES (?, ?, ?, ?, ?)"#,
+        )
+        .bind(&username)
+        .bind(format!("{username}@example.com"))
+        .bind(&pwhash)
+        .bind(UserStatus::Verified as i64)
+        .bind(format!("Load test user {i}"))
+        .execute(&mut *tx)
+        .await?
+        .last_insert_rowid();
+        users.push(id);
+        generated.users += 1;
+    }
+
+    // every user has at least one source, the others mostly belong to the most active users
+    let mut sources: Vec<Vec<i64>> = vec![Vec::new(); users.len()];
+    for i in 0..size.sources {
+        let owner = match i < users.len() {
+            true => i,
+            false => rng.skewed(users.len()),
+        };
+        let id = sqlx::query(
+            r#"INSERT INTO sc_sources (srcname, latitude, longitude, userid, srcuuid)
+            VALUES (?, ?, ?, ?, ?)"#,
+        )
+        .bind(format!(
+            "{} {} {i}",
+            rng.choose(PLACE_NAMES),
+            rng.choose(SITE_TYPES)
+        ))
+        .bind(43.5 + rng.fraction() * 5.5)
+        .bind(-97.0 + rng.fraction() * 7.5)
+        .bind(users[owner])
+        .bind(rng.uuid().to_string())
+        .execute(&mut *tx)
+        .await?
+        .last_insert_rowid();
+        sources[owner].push(id);
+        generated.sources += 1;
+    }
+
+    let mut samples: Vec<Vec<i64>> = vec![Vec::new(); users.len()];
+    for _ in 0..size.samples {
+        let mut owner = rng.skewed(users.len());
+        if sources[owner].is_empty() {
+            owner = 0;
+        }
+        let source = sources[owner][rng.skewed(sources[owner].len())];
+        let quantity = 10f64.powf(1.0 + rng.fraction() * 3.0) as i64;
+        let notes = rng
+            .chance(0.3)
+            .then(|| rng.choose(SAMPLE_NOTES).to_string());
+        let certainty = match rng.chance(0.1) {
+            true => Certainty::Uncertain,
+            false => Certainty::Certain,
+        };
+        let id = sqlx::query(
+            r#"INSERT INTO sc_samples
+            (tsn, userid, srcid, month, year, quantity, notes, certainty, sampleuuid)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+        )
+        .bind(species[rng.skewed(species.len())])
+        .bind(users[owner])
+        .bind(source)
+        .bind(random_month(&mut rng))
+        // recent years are more common
+        .bind(2024 - rng.skewed(15) as i64)
+        .bind(quantity)
+        .bind(notes)
+        .bind(certainty)
+        .bind(rng.uuid().to_string())
+        .execute(&mut *tx)
+        .await?
+        .last_insert_rowid();
+        samples[owner].push(id);
+        generated.samples += 1;
+    }
+
+    let mut allocations = Vec::new();
+    for i in 0..size.projects {
+        let owner = rng.skewed(users.len());
+        let userid = users[owner];
+        let id =
+            sqlx::query("INSERT INTO sc_projects (projname, userid, projuuid) VALUES (?, ?, ?)")
+                .bind(format!("{} {i}", rng.choose(PROJECT_NAMES)))
+                .bind(userid)
+                .bind(rng.uuid().to_string())
+                .execute(&mut *tx)
+                .await?
+                .last_insert_rowid();
+        generated.projects += 1;
+        if samples[owner].is_empty() {
+            continue;
+        }
+        for _ in 0..rng.between(5, 60) {
+            let sample = *rng.choose(&samples[owner]);
+            let res = sqlx::query(
+                r#"INSERT OR IGNORE INTO sc_project_samples (projectid, sampleid, psuuid)
+                VALUES (?, ?, ?)"#,
+            )
+            .bind(id)
+            .bind(sample)
+            .bind(rng.uuid().to_string())
+            .execute(&mut *tx)
+            .await?;
+            if res.rows_affected() > 0 {
+                allocations.push(res.last_insert_rowid());
+            }
+        }
+    }
+
+    if !allocations.is_empty() {
+        for _ in 0..size.notes {
+            let (kind, summary) = rng.choose(NOTE_SUMMARIES);
+            let date = Date::from_calendar_date(
+                2024 - rng.skewed(5) as i32,
+                Month::try_from(rng.between(1, 12) as u8).unwrap_or(Month::January),
+                rng.between(1, 28) as u8,
+            )
+            .map_err(|e| Error::InvalidValue(e.to_string()))?;
+            sqlx::query(
+                r#"INSERT INTO sc_project_notes
+                (psid, notedate, notetype, notesummary, pnoteuuid) VALUES (?, ?, ?, ?, ?)"#,
+            )
+            .bind(allocations[rng.skewed(allocations.len())])
+            .bind(date)
+            .bind(*kind as i64)
+            .bind(summary)
+            .bind(rng.uuid().to_string())
+            .execute(&mut *tx)
+            .await?;
+            generated.notes += 1;
+        }
+    }
+    tx.commit().await?;
+    Ok(generated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{loadable::Loadable, project::Project, sample::Sample};
+    use test_log::test;
+
+    #[test]
+    fn test_distributions() {
+        let mut rng = Rng::new(5);
+        let mut counts = [0; 10];
+        let mut months = [0; 13];
+        for _ in 0..10000 {
+            counts[rng.skewed(10)] += 1;
+            months[random_month(&mut rng) as usize] += 1;
+        }
+        assert!(counts[0] > counts[9] * 5, "{counts:?}");
+        assert!(counts.iter().all(|n| *n > 0), "{counts:?}");
+        assert_eq!(months[..6].iter().sum::<i32>(), 0);
+        assert!(months[8] > months[6], "{months:?}");
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn test_generate(pool: Pool<Sqlite>) {
+        let size = LoadSize::samples(1000);
+        assert_eq!(size.users, 1);
+        assert_eq!(size.sources, 100);
+        let generated = generate(&size, 1, &pool).await.unwrap();
+        assert_eq!(generated, size);
+        let user: i64 = sqlx::query_scalar("SELECT userid FROM sc_users")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            Sample::load_all_user(user, None, None, &pool)
+                .await
+                .unwrap()
+                .len(),
+            1000
+        );
+        let mut project = Project::load(1, &pool).await.unwrap();
+        project.load_samples(None, None, &pool).await.unwrap();
+        assert!(!project.allocations.is_empty());
+
+        // generating again adds more data with different users
+        let size = LoadSize {
+            users: 3,
+            sources: 3,
+            samples: 10,
+            ..Default::default()
+        };
+        assert_eq!(generate(&size, 2, &pool).await.unwrap(), size);
+
+        assert!(generate(
+            &LoadSize {
+                notes: 1,
+                ..Default::default()
+            },
+            1,
+            &pool
+        )
+        .await
+        .is_err());
+    }
+}
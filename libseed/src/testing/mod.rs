@@ -0,0 +1,662 @@
+//! Factories that insert realistic, randomized objects into the database, for writing tests
+//! without hand-crafted SQL fixtures and for populating a database with demo data. Each factory
+//! fills in random values for everything that isn't set explicitly, so a test only has to
+//! mention the values that it cares about:
+//!
+//! ```ignore
+//! let user = UserFactory::new().insert(&pool).await?;
+//! let sample = SampleFactory::new(user.id).quantity(Some(100)).insert(&pool).await?;
+//! ```
+//!
+//! The random values are reproducible: a factory that is given the same seed inserts the same
+//! values. This module is only available with the `test-support` feature. The [load] module
+//! generates much larger datasets for benchmarks.
+use crate::{
+    error::{Error, Result},
+    loadable::ExternalRef,
+    project::Project,
+    sample::{Certainty, Sample},
+    source::Source,
+    user::{User, UserStatus},
+};
+use sqlx::{Pool, Sqlite};
+use uuid::Uuid;
+
+pub mod load;
+
+/// The password of users that are inserted by [UserFactory] unless a different one is given
+pub const DEFAULT_PASSWORD: &str = "password";
+
+const FIRST_NAMES: &[&str] = &[
+    "Alex", "Jordan", "Sam", "Robin", "Casey", "Morgan", "Riley", "Jamie", "Avery", "Quinn",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Anderson", "Nguyen", "Johnson", "Olson", "Larson", "Garcia", "Peterson", "Yang", "Miller",
+];
+
+const PLACE_NAMES: &[&str] = &[
+    "Cedar",
+    "Bluff",
+    "Hidden",
+    "Oak",
+    "Maple",
+    "Crow River",
+    "Lost Lake",
+    "Pine",
+    "Sand Hill",
+    "Willow",
+    "Elk",
+    "Prairie Creek",
+];
+
+const SITE_TYPES: &[&str] = &[
+    "prairie",
+    "savanna",
+    "fen",
+    "woods",
+    "roadside",
+    "railroad right-of-way",
+    "wet meadow",
+    "sedge meadow",
+    "dune",
+    "bluff prairie",
+];
+
+const SAMPLE_NOTES: &[&str] = &[
+    "Collected from a large population",
+    "Only a few plants found",
+    "Seed heads were mostly ripe",
+    "Collected on a windy day, some seed lost",
+    "Shared with a neighbor",
+];
+
+const PROJECT_NAMES: &[&str] = &[
+    "Backyard pollinator garden",
+    "Church prairie planting",
+    "Rain garden",
+    "School native garden",
+    "Boulevard planting",
+    "Shoreline restoration",
+];
+
+/// A small pseudo-random number generator (SplitMix64). It is not suitable for anything that has
+/// to be unpredictable, but it is fast and the same seed always produces the same numbers.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// A generator with a random seed
+    pub fn from_entropy() -> Self {
+        Self(Uuid::new_v4().as_u64_pair().0)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A random number from `low` up to and including `high`
+    pub fn between(&mut self, low: i64, high: i64) -> i64 {
+        assert!(low <= high, "invalid range {low}..={high}");
+        let span = (high - low) as u64 + 1;
+        low + (self.next_u64() % span) as i64
+    }
+
+    /// A random number from 0 up to, but not including, 1
+    pub fn fraction(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns true with the given probability from 0 to 1
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.fraction() < probability
+    }
+
+    /// A random element of a slice, which must not be empty
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.between(0, items.len() as i64 - 1) as usize]
+    }
+
+    /// A random index below `len` where low indexes are much more likely than high ones, so that
+    /// a few items are picked very often and most items rarely, like the popularity of species
+    pub fn skewed(&mut self, len: usize) -> usize {
+        ((self.fraction().powi(3) * len as f64) as usize).min(len.saturating_sub(1))
+    }
+
+    /// A random version 4 UUID
+    pub fn uuid(&mut self) -> Uuid {
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&self.next_u64().to_le_bytes());
+        bytes[8..].copy_from_slice(&self.next_u64().to_le_bytes());
+        uuid::Builder::from_random_bytes(bytes).into_uuid()
+    }
+}
+
+/// Inserts a user with a random name and an email address derived from it. The user is verified
+/// and has the password [DEFAULT_PASSWORD] unless something else is given.
+#[derive(Clone, Debug)]
+pub struct UserFactory {
+    rng: Rng,
+    username: Option<String>,
+    email: Option<String>,
+    password: String,
+    status: UserStatus,
+    display_name: Option<String>,
+}
+
+impl Default for UserFactory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UserFactory {
+    pub fn new() -> Self {
+        Self {
+            rng: Rng::from_entropy(),
+            username: None,
+            email: None,
+            password: DEFAULT_PASSWORD.to_string(),
+            status: UserStatus::Verified,
+            display_name: None,
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    pub fn username(mut self, username: &str) -> Self {
+        self.username = Some(username.to_string());
+        self
+    }
+
+    pub fn email(mut self, email: &str) -> Self {
+        self.email = Some(email.to_string());
+        self
+    }
+
+    pub fn password(mut self, password: &str) -> Self {
+        self.password = password.to_string();
+        self
+    }
+
+    pub fn status(mut self, status: UserStatus) -> Self {
+        self.status = status;
+        self
+    }
+
+    pub fn display_name(mut self, display_name: &str) -> Self {
+        self.display_name = Some(display_name.to_string());
+        self
+    }
+
+    pub async fn insert(mut self, pool: &Pool<Sqlite>) -> Result<User> {
+        let first = *self.rng.choose(FIRST_NAMES);
+        let last = *self.rng.choose(LAST_NAMES);
+        // a random suffix keeps the names of users from the same factory seed unique enough
+        let suffix = self.rng.between(0, 0xffff);
+        let username = self
+            .username
+            .unwrap_or_else(|| format!("{}{}{suffix:04x}", first.to_lowercase(), &last[..1]));
+        let email = self
+            .email
+            .unwrap_or_else(|| format!("{username}@example.com"));
+        let display_name = self
+            .display_name
+            .unwrap_or_else(|| format!("{first} {last}"));
+        let mut user = User::new(
+            username,
+            email,
+            User::hash_password(&self.password)?,
+            self.status,
+            None,
+            Some(display_name),
+            None,
+        );
+        user.insert(pool).await?;
+        Ok(user)
+    }
+}
+
+/// Inserts a source with a random name somewhere in Minnesota
+#[derive(Clone, Debug)]
+pub struct SourceFactory {
+    rng: Rng,
+    userid: i64,
+    name: Option<String>,
+    description: Option<String>,
+    coordinates: Option<Option<(f64, f64)>>,
+}
+
+impl SourceFactory {
+    pub fn new(userid: i64) -> Self {
+        Self {
+            rng: Rng::from_entropy(),
+            userid,
+            name: None,
+            description: None,
+            coordinates: None,
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = Some(description.to_string());
+        self
+    }
+
+    /// The location of the source, or `None` for a source without coordinates
+    pub fn coordinates(mut self, coordinates: Option<(f64, f64)>) -> Self {
+        self.coordinates = Some(coordinates);
+        self
+    }
+
+    pub async fn insert(mut self, pool: &Pool<Sqlite>) -> Result<Source> {
+        let place = *self.rng.choose(PLACE_NAMES);
+        let site = *self.rng.choose(SITE_TYPES);
+        let name = self.name.unwrap_or_else(|| format!("{place} {site}"));
+        let coordinates = self.coordinates.unwrap_or_else(|| {
+            Some((
+                43.5 + self.rng.fraction() * 5.5,
+                -97.0 + self.rng.fraction() * 7.5,
+            ))
+        });
+        let mut source = Source::new(
+            name,
+            self.description,
+            coordinates.map(|c| c.0),
+            coordinates.map(|c| c.1),
+            self.userid,
+        );
+        source.insert(pool).await?;
+        Ok(source)
+    }
+}
+
+/// Inserts a sample of a random species that is in the taxonomy database. A new random source is
+/// inserted for the sample unless a source is given.
+#[derive(Clone, Debug)]
+pub struct SampleFactory {
+    rng: Rng,
+    userid: i64,
+    taxon: Option<i64>,
+    source: Option<i64>,
+    month: Option<Option<u32>>,
+    year: Option<Option<u32>>,
+    quantity: Option<Option<i64>>,
+    notes: Option<Option<String>>,
+    certainty: Option<Certainty>,
+}
+
+impl SampleFactory {
+    pub fn new(userid: i64) -> Self {
+        Self {
+            rng: Rng::from_entropy(),
+            userid,
+            taxon: None,
+            source: None,
+            month: None,
+            year: None,
+            quantity: None,
+            notes: None,
+            certainty: None,
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    pub fn taxon(mut self, tsn: i64) -> Self {
+        self.taxon = Some(tsn);
+        self
+    }
+
+    pub fn source(mut self, sourceid: i64) -> Self {
+        self.source = Some(sourceid);
+        self
+    }
+
+    pub fn month(mut self, month: Option<u32>) -> Self {
+        self.month = Some(month);
+        self
+    }
+
+    pub fn year(mut self, year: Option<u32>) -> Self {
+        self.year = Some(year);
+        self
+    }
+
+    pub fn quantity(mut self, quantity: Option<i64>) -> Self {
+        self.quantity = Some(quantity);
+        self
+    }
+
+    pub fn notes(mut self, notes: Option<&str>) -> Self {
+        self.notes = Some(notes.map(str::to_string));
+        self
+    }
+
+    pub fn certainty(mut self, certainty: Certainty) -> Self {
+        self.certainty = Some(certainty);
+        self
+    }
+
+    pub async fn insert(mut self, pool: &Pool<Sqlite>) -> Result<Sample> {
+        let taxon = match self.taxon {
+            Some(tsn) => tsn,
+            None => random_species(&mut self.rng, pool).await?,
+        };
+        let source = match self.source {
+            Some(id) => id,
+            None => {
+                SourceFactory::new(self.userid)
+                    .seed(self.rng.next_u64())
+                    .insert(pool)
+                    .await?
+                    .id
+            }
+        };
+        // most seed ripens from July to October
+        let month = self
+            .month
+            .unwrap_or_else(|| Some(self.rng.between(6, 10) as u32));
+        let year = self
+            .year
+            .unwrap_or_else(|| Some(self.rng.between(2015, 2024) as u32));
+        let quantity = self
+            .quantity
+            .unwrap_or_else(|| Some(self.rng.between(1, 40) * 50));
+        let notes = self.notes.unwrap_or_else(|| {
+            self.rng
+                .chance(0.3)
+                .then(|| self.rng.choose(SAMPLE_NOTES).to_string())
+        });
+        let certainty = self
+            .certainty
+            .unwrap_or_else(|| match self.rng.chance(0.1) {
+                true => Certainty::Uncertain,
+                false => Certainty::Certain,
+            });
+        let mut sample = Sample::new(
+            taxon,
+            self.userid,
+            source,
+            month,
+            year,
+            quantity,
+            notes,
+            certainty,
+        );
+        sample.insert(pool).await?;
+        Ok(sample)
+    }
+}
+
+/// Inserts a project with a random name and allocates the given samples to it
+#[derive(Clone, Debug)]
+pub struct ProjectFactory {
+    rng: Rng,
+    userid: i64,
+    name: Option<String>,
+    description: Option<Option<String>>,
+    samples: Vec<i64>,
+}
+
+impl ProjectFactory {
+    pub fn new(userid: i64) -> Self {
+        Self {
+            rng: Rng::from_entropy(),
+            userid,
+            name: None,
+            description: None,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn description(mut self, description: Option<&str>) -> Self {
+        self.description = Some(description.map(str::to_string));
+        self
+    }
+
+    pub fn samples(mut self, samples: &[i64]) -> Self {
+        self.samples = samples.to_vec();
+        self
+    }
+
+    pub async fn insert(mut self, pool: &Pool<Sqlite>) -> Result<Project> {
+        let name = self
+            .name
+            .unwrap_or_else(|| self.rng.choose(PROJECT_NAMES).to_string());
+        let description = self.description.unwrap_or_else(|| {
+            self.rng
+                .chance(0.5)
+                .then(|| format!("Planned for {}", self.rng.between(2025, 2027)))
+        });
+        let mut project = Project::new(name, description, self.userid);
+        project.insert(pool).await?;
+        for id in self.samples {
+            project.allocate_sample(ExternalRef::Stub(id), pool).await?;
+        }
+        Ok(project)
+    }
+}
+
+/// A random accepted species from the taxonomy database
+async fn random_species(rng: &mut Rng, pool: &Pool<Sqlite>) -> Result<i64> {
+    let count: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM taxonomic_units WHERE rank_id=220 AND name_usage='accepted'",
+    )
+    .fetch_one(pool)
+    .await?;
+    if count == 0 {
+        return Err(Error::InvalidOperation(
+            "there are no species in the taxonomy database".to_string(),
+        ));
+    }
+    sqlx::query_scalar(
+        r#"SELECT tsn FROM taxonomic_units WHERE rank_id=220 AND name_usage='accepted'
+        ORDER BY tsn LIMIT 1 OFFSET ?"#,
+    )
+    .bind(rng.between(0, count - 1))
+    .fetch_one(pool)
+    .await
+    .map_err(Into::into)
+}
+
+/// What [populate] added to the database
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Populated {
+    pub sources: usize,
+    pub samples: usize,
+    pub projects: usize,
+}
+
+/// Add randomized demo data for a user: a few sources with `samples` samples spread over them and
+/// some projects that each contain part of the samples
+pub async fn populate(
+    userid: i64,
+    samples: usize,
+    seed: u64,
+    pool: &Pool<Sqlite>,
+) -> Result<Populated> {
+    let mut rng = Rng::new(seed);
+    let mut populated = Populated::default();
+    let mut sources = Vec::new();
+    for _ in 0..samples.div_ceil(8).max(1) {
+        let source = SourceFactory::new(userid)
+            .seed(rng.next_u64())
+            .insert(pool)
+            .await?;
+        sources.push(source.id);
+        populated.sources += 1;
+    }
+    let mut ids = Vec::new();
+    for _ in 0..samples {
+        let sample = SampleFactory::new(userid)
+            .seed(rng.next_u64())
+            .source(*rng.choose(&sources))
+            .insert(pool)
+            .await?;
+        ids.push(sample.id);
+        populated.samples += 1;
+    }
+    for _ in 0..samples.div_ceil(15) {
+        let allocated: Vec<i64> = ids.iter().copied().filter(|_| rng.chance(0.3)).collect();
+        ProjectFactory::new(userid)
+            .seed(rng.next_u64())
+            .samples(&allocated)
+            .insert(pool)
+            .await?;
+        populated.projects += 1;
+    }
+    Ok(populated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loadable::Loadable;
+    use test_log::test;
+
+    #[test]
+    fn test_rng() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let numbers: Vec<u64> = (0..10).map(|_| a.next_u64()).collect();
+        assert_eq!(numbers, (0..10).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(
+            numbers,
+            (0..10).map(|_| Rng::new(43).next_u64()).collect::<Vec<_>>()
+        );
+        for _ in 0..1000 {
+            assert!((3..=5).contains(&a.between(3, 5)));
+            assert!((0.0..1.0).contains(&a.fraction()));
+        }
+        assert_eq!(a.between(7, 7), 7);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("taxa"))
+    ))]
+    async fn test_factories(pool: Pool<Sqlite>) {
+        let user = UserFactory::new().seed(1).insert(&pool).await.unwrap();
+        assert!(user.verify_password(DEFAULT_PASSWORD).is_ok());
+        let other = UserFactory::new()
+            .username("botanist")
+            .password("secret")
+            .insert(&pool)
+            .await
+            .unwrap();
+        assert_eq!(other.username, "botanist");
+        assert_eq!(other.email, "botanist@example.com");
+        assert!(other.verify_password("secret").is_ok());
+
+        // a random source is created for the sample
+        let sample = SampleFactory::new(user.id)
+            .seed(2)
+            .insert(&pool)
+            .await
+            .unwrap();
+        let sample = Sample::load(sample.id, &pool).await.unwrap();
+        assert_eq!(sample.user.id(), user.id);
+        assert!(Source::load(sample.source.id(), &pool).await.is_ok());
+        let species: i64 = sqlx::query_scalar("SELECT rank_id FROM taxonomic_units WHERE tsn=?")
+            .bind(sample.taxon.id())
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(species, 220);
+
+        // the values that are given are used instead of random ones
+        let sample = SampleFactory::new(user.id)
+            .taxon(40683)
+            .source(sample.source.id())
+            .quantity(None)
+            .notes(Some("test"))
+            .insert(&pool)
+            .await
+            .unwrap();
+        assert_eq!(sample.taxon.id(), 40683);
+        assert_eq!(sample.quantity, None);
+        assert_eq!(sample.notes.as_deref(), Some("test"));
+
+        let project = ProjectFactory::new(user.id)
+            .name("Test project")
+            .samples(&[sample.id])
+            .insert(&pool)
+            .await
+            .unwrap();
+        let mut project = Project::load(project.id, &pool).await.unwrap();
+        project.load_samples(None, None, &pool).await.unwrap();
+        assert_eq!(project.name, "Test project");
+        assert_eq!(project.allocations.len(), 1);
+
+        // the same seed produces the same data
+        let a = SourceFactory::new(user.id)
+            .seed(7)
+            .insert(&pool)
+            .await
+            .unwrap();
+        let b = SourceFactory::new(user.id)
+            .seed(7)
+            .insert(&pool)
+            .await
+            .unwrap();
+        assert_eq!((a.name, a.latitude), (b.name, b.latitude));
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("users", "taxa"))
+    ))]
+    async fn test_populate(pool: Pool<Sqlite>) {
+        let populated = populate(1, 20, 3, &pool).await.unwrap();
+        assert_eq!(
+            populated,
+            Populated {
+                sources: 3,
+                samples: 20,
+                projects: 2,
+            }
+        );
+        assert_eq!(
+            Sample::load_all_user(1, None, None, &pool)
+                .await
+                .unwrap()
+                .len(),
+            20
+        );
+    }
+}
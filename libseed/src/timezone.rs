@@ -0,0 +1,188 @@
+//! Conversion of the UTC timestamps stored in the database to the local time of a user's time
+//! zone. Time zones are looked up by their IANA name (e.g. `Europe/Berlin`) in the copy of the IANA
+//! time zone database that is built into the `time-tz` crate, so the results don't depend on the
+//! time zone files that happen to be installed on the system.
+use crate::error::{Error, Result};
+use time::{OffsetDateTime, UtcOffset};
+use time_tz::{timezones, Offset, TimeZone as _, Tz};
+
+/// The name of the time zone that is used when a user hasn't chosen one
+pub const UTC: &str = "UTC";
+
+/// The regions of the time zones that users can choose from. The other names in the database
+/// are either aliases for backwards compatibility (e.g. `US/Central`) or fixed offsets.
+const REGIONS: [&str; 9] = [
+    "Africa",
+    "America",
+    "Antarctica",
+    "Asia",
+    "Atlantic",
+    "Australia",
+    "Europe",
+    "Indian",
+    "Pacific",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeZone {
+    /// `None` for UTC
+    tz: Option<&'static Tz>,
+}
+
+fn invalid(name: &str) -> Error {
+    Error::InvalidValue(format!("unknown time zone '{name}'"))
+}
+
+impl TimeZone {
+    /// Coordinated Universal Time, the time zone that timestamps are stored in
+    pub fn utc() -> Self {
+        Self { tz: None }
+    }
+
+    /// Load the time zone with the given IANA name
+    pub fn load(name: &str) -> Result<Self> {
+        if name == UTC {
+            return Ok(Self::utc());
+        }
+        // the database also knows Windows time zone names, which are not accepted here
+        timezones::get_by_name(name)
+            .filter(|tz| tz.name() == name)
+            .map(|tz| Self { tz: Some(tz) })
+            .ok_or_else(|| invalid(name))
+    }
+
+    /// Load the time zone with the given name, or UTC if no name is given or it is unknown
+    pub fn load_or_utc(name: Option<&str>) -> Self {
+        name.and_then(|n| Self::load(n).ok())
+            .unwrap_or_else(Self::utc)
+    }
+
+    /// The names of all time zones that users can choose from
+    pub fn names() -> Vec<String> {
+        let mut names: Vec<String> = timezones::iter()
+            .map(|tz| tz.name())
+            .filter(|name| {
+                name.split_once('/')
+                    .is_some_and(|(region, _)| REGIONS.contains(&region))
+            })
+            .map(str::to_string)
+            .collect();
+        names.sort();
+        names.insert(0, UTC.to_string());
+        names
+    }
+
+    pub fn name(&self) -> &str {
+        self.tz.map(|tz| tz.name()).unwrap_or(UTC)
+    }
+
+    /// The offset from UTC that is in effect in this time zone at the given time
+    pub fn offset_at(&self, dt: OffsetDateTime) -> UtcOffset {
+        match self.tz {
+            Some(tz) => tz.get_offset_utc(&dt).to_utc(),
+            None => UtcOffset::UTC,
+        }
+    }
+
+    /// Convert the given time to the local time of this time zone
+    pub fn to_local(&self, dt: OffsetDateTime) -> OffsetDateTime {
+        dt.to_offset(self.offset_at(dt))
+    }
+
+    /// The current local time in this time zone
+    pub fn now(&self) -> OffsetDateTime {
+        self.to_local(OffsetDateTime::now_utc())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    #[test]
+    fn test_offsets() {
+        let berlin = TimeZone::load("Europe/Berlin").unwrap();
+        assert_eq!(berlin.name(), "Europe/Berlin");
+        assert_eq!(
+            berlin.to_local(datetime!(2024-01-15 12:00 UTC)),
+            datetime!(2024-01-15 13:00 +1)
+        );
+        assert_eq!(
+            berlin.to_local(datetime!(2024-07-01 12:00 UTC)),
+            datetime!(2024-07-01 14:00 +2)
+        );
+        // daylight saving time started at 01:00 UTC on the last Sunday of March 2024
+        assert_eq!(
+            berlin.offset_at(datetime!(2024-03-31 00:59 UTC)),
+            UtcOffset::from_hms(1, 0, 0).unwrap()
+        );
+        assert_eq!(
+            berlin.offset_at(datetime!(2024-03-31 01:00 UTC)),
+            UtcOffset::from_hms(2, 0, 0).unwrap()
+        );
+
+        // daylight saving time spans the turn of the year in the southern hemisphere
+        let auckland = TimeZone::load("Pacific/Auckland").unwrap();
+        assert_eq!(
+            auckland.offset_at(datetime!(2024-01-01 00:00 UTC)),
+            UtcOffset::from_hms(13, 0, 0).unwrap()
+        );
+        assert_eq!(
+            auckland.offset_at(datetime!(2024-07-01 00:00 UTC)),
+            UtcOffset::from_hms(12, 0, 0).unwrap()
+        );
+
+        let kolkata = TimeZone::load("Asia/Kolkata").unwrap();
+        assert_eq!(
+            kolkata.offset_at(datetime!(2024-07-01 00:00 UTC)),
+            UtcOffset::from_hms(5, 30, 0).unwrap()
+        );
+        let chicago = TimeZone::load("America/Chicago").unwrap();
+        assert_eq!(
+            chicago.to_local(datetime!(2024-07-01 12:00 UTC)),
+            datetime!(2024-07-01 07:00 -5)
+        );
+        // the rules of the past are kept: daylight saving time used to start in April
+        assert_eq!(
+            chicago.offset_at(datetime!(2005-03-20 12:00 UTC)),
+            UtcOffset::from_hms(-6, 0, 0).unwrap()
+        );
+        assert_eq!(
+            TimeZone::utc().to_local(datetime!(2024-07-01 12:00 +2)),
+            datetime!(2024-07-01 10:00 UTC)
+        );
+    }
+
+    #[test]
+    fn test_names() {
+        let names = TimeZone::names();
+        assert_eq!(names[0], UTC);
+        for name in ["Europe/Berlin", "America/Chicago", "Pacific/Auckland"] {
+            assert!(names.iter().any(|n| n == name), "{name} should be listed");
+        }
+        assert!(!names
+            .iter()
+            .any(|n| n.starts_with("Etc/") || n == "US/Central"));
+        for name in &names {
+            assert_eq!(TimeZone::load(name).unwrap().name(), name);
+        }
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        assert_eq!(TimeZone::load(UTC).unwrap(), TimeZone::utc());
+        for name in [
+            "",
+            "/etc/passwd",
+            "../zoneinfo/UTC",
+            "Europe/../UTC",
+            "Europe//Berlin",
+            "Central Standard Time",
+        ] {
+            assert!(TimeZone::load(name).is_err(), "{name} should be rejected");
+        }
+        assert_eq!(TimeZone::load_or_utc(Some("Not/AZone")), TimeZone::utc());
+        assert_eq!(TimeZone::load_or_utc(None), TimeZone::utc());
+    }
+}
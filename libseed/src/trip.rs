@@ -0,0 +1,479 @@
+//! Collection trips. A trip is a planned field outing on a particular date. It lists the people
+//! that are taking part and the sources that will be visited and taxa that are targeted for
+//! collection. Samples that are collected during the trip record the trip, so that the targets
+//! can be compared to what was actually collected.
+use crate::{
+    error::{Error, Result},
+    filter::{Cmp, DynFilterPart, FilterPart, ListQuery},
+    loadable::Loadable,
+    organization::Owned,
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    sqlite::{SqliteQueryResult, SqliteRow},
+    FromRow, Pool, QueryBuilder, Row, Sqlite,
+};
+use std::sync::Arc;
+use time::Date;
+use tracing::debug;
+use uuid::Uuid;
+
+time::serde::format_description!(iso_date, Date, "[year]-[month]-[day]");
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Trip {
+    pub id: i64,
+    pub name: String,
+    #[serde(with = "iso_date")]
+    pub date: Date,
+    pub notes: Option<String>,
+    /// the names of the people taking part in the trip, sorted alphabetically
+    pub participants: Vec<String>,
+    pub userid: i64,
+    pub version: i64,
+}
+
+impl FromRow<'_, SqliteRow> for Trip {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        let mut participants = match row.try_get::<Option<&str>, _>("participants")? {
+            Some(s) if !s.is_empty() => s.split('\n').map(|p| p.to_string()).collect(),
+            _ => Vec::new(),
+        };
+        participants.sort();
+        Ok(Self {
+            id: row.try_get("tripid")?,
+            name: row.try_get("tripname")?,
+            date: row.try_get("tripdate")?,
+            notes: row.try_get("tripnotes")?,
+            participants,
+            userid: row.try_get("userid")?,
+            version: row.try_get("tripversion")?,
+        })
+    }
+}
+
+#[async_trait]
+impl Loadable for Trip {
+    type Id = i64;
+
+    fn id(&self) -> Self::Id {
+        self.id
+    }
+
+    fn set_id(&mut self, id: Self::Id) {
+        self.id = id
+    }
+
+    async fn load(id: Self::Id, pool: &Pool<Sqlite>) -> Result<Self> {
+        Self::build_query(Some(Filter::Id(id).into()))
+            .build_query_as()
+            .fetch_one(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    async fn delete_id(id: &Self::Id, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        sqlx::query("DELETE FROM sc_trips WHERE tripid=?")
+            .bind(id)
+            .execute(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+}
+
+impl From<Filter> for DynFilterPart {
+    fn from(value: Filter) -> Self {
+        Arc::new(value)
+    }
+}
+
+#[derive(Clone)]
+pub enum Filter {
+    Id(i64),
+    User(i64),
+    Name(Cmp, String),
+    /// trips that take place on or after the given date
+    After(Date),
+    /// trips that take place on or before the given date
+    Before(Date),
+}
+
+impl FilterPart for Filter {
+    fn add_to_query(&self, builder: &mut sqlx::QueryBuilder<sqlx::Sqlite>) {
+        match self {
+            Self::Id(id) => _ = builder.push(" T.tripid = ").push_bind(*id),
+            Self::User(id) => _ = builder.push(" T.userid = ").push_bind(*id),
+            Self::Name(cmp, frag) => {
+                let s = match cmp {
+                    Cmp::Like => format!("%{frag}%"),
+                    _ => frag.to_string(),
+                };
+                builder.push(" T.tripname ").push(cmp).push_bind(s);
+            }
+            Self::After(date) => _ = builder.push(" T.tripdate >= ").push_bind(*date),
+            Self::Before(date) => _ = builder.push(" T.tripdate <= ").push_bind(*date),
+        }
+    }
+}
+
+/// A taxon that was targeted or collected on a trip
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct TripTaxon {
+    pub tsn: i64,
+    pub complete_name: String,
+    /// the number of samples of the taxon that were collected on the trip
+    pub nsamples: i64,
+}
+
+/// A source that was targeted for a visit on a trip
+#[derive(FromRow, Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct TripSource {
+    #[sqlx(rename = "srcid")]
+    pub id: i64,
+    #[sqlx(rename = "srcuuid", try_from = "String")]
+    pub uuid: Uuid,
+    #[sqlx(rename = "srcname")]
+    pub name: String,
+    /// the number of samples that were collected from the source on the trip
+    pub nsamples: i64,
+}
+
+/// A comparison of the targets of a trip to the samples that were collected on it
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct TripSummary {
+    pub target_taxa: Vec<TripTaxon>,
+    pub target_sources: Vec<TripSource>,
+    /// taxa that were collected on the trip even though they were not targeted
+    pub other_taxa: Vec<TripTaxon>,
+}
+
+impl TripSummary {
+    /// The number of target taxa that were collected at least once
+    pub fn targets_collected(&self) -> usize {
+        self.target_taxa.iter().filter(|t| t.nsamples > 0).count()
+    }
+}
+
+impl Trip {
+    fn list_query(filter: Option<DynFilterPart>) -> ListQuery {
+        ListQuery::new(
+            r#"T.tripid, T.tripname, T.tripdate, T.tripnotes, T.userid, T.tripversion,
+            (SELECT GROUP_CONCAT(P.participant, char(10)) FROM sc_trip_participants P
+                WHERE P.tripid=T.tripid) AS participants"#,
+            "sc_trips T",
+            filter,
+        )
+    }
+
+    fn build_query(filter: Option<DynFilterPart>) -> QueryBuilder<'static, Sqlite> {
+        let mut builder = Self::list_query(filter).select();
+        builder.push(" ORDER BY T.tripdate DESC, T.tripname");
+        builder
+    }
+
+    pub async fn load_all(filter: Option<DynFilterPart>, pool: &Pool<Sqlite>) -> Result<Vec<Self>> {
+        Self::build_query(filter)
+            .build_query_as()
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// The number of trips that match `filter`
+    pub async fn count(filter: Option<DynFilterPart>, pool: &Pool<Sqlite>) -> Result<i64> {
+        Self::list_query(filter).fetch_count(pool).await
+    }
+
+    fn validate(&mut self) -> Result<()> {
+        self.name = self.name.trim().to_string();
+        if self.name.is_empty() {
+            return Err(Error::InvalidStateMissingAttribute("name".to_string()));
+        }
+        let mut participants: Vec<String> = self
+            .participants
+            .iter()
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+        if participants.iter().any(|p| p.contains('\n')) {
+            return Err(Error::InvalidValue(
+                "participant names can't contain line breaks".to_string(),
+            ));
+        }
+        participants.sort();
+        participants.dedup();
+        self.participants = participants;
+        Ok(())
+    }
+
+    async fn save_participants(
+        &self,
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+    ) -> Result<SqliteQueryResult> {
+        let res = sqlx::query("DELETE FROM sc_trip_participants WHERE tripid=?")
+            .bind(self.id)
+            .execute(&mut **tx)
+            .await?;
+        for participant in &self.participants {
+            sqlx::query("INSERT INTO sc_trip_participants (tripid, participant) VALUES (?, ?)")
+                .bind(self.id)
+                .bind(participant)
+                .execute(&mut **tx)
+                .await?;
+        }
+        Ok(res)
+    }
+
+    pub async fn insert(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        if self.id != -1 {
+            return Err(Error::InvalidOperationObjectAlreadyExists(self.id));
+        }
+        self.validate()?;
+        debug!(?self, "Inserting trip into database");
+        let mut tx = pool.begin().await?;
+        let res = sqlx::query(
+            "INSERT INTO sc_trips (tripname, tripdate, tripnotes, userid) VALUES (?, ?, ?, ?)",
+        )
+        .bind(&self.name)
+        .bind(self.date)
+        .bind(&self.notes)
+        .bind(self.userid)
+        .execute(&mut *tx)
+        .await?;
+        self.id = res.last_insert_rowid();
+        self.save_participants(&mut tx).await?;
+        tx.commit().await?;
+        Ok(res)
+    }
+
+    /// Save the changes to this trip and its participants to the database. If the trip has been
+    /// modified in the database since it was loaded, this fails with
+    /// [Error::DatabaseVersionConflict].
+    pub async fn update(&mut self, pool: &Pool<Sqlite>) -> Result<SqliteQueryResult> {
+        if self.id < 0 {
+            return Err(Error::InvalidStateMissingAttribute("id".to_string()));
+        }
+        self.validate()?;
+        debug!(?self, "Updating trip in database");
+        let mut tx = pool.begin().await?;
+        let res = sqlx::query(
+            r#"UPDATE sc_trips SET tripname=?, tripdate=?, tripnotes=?, tripversion=tripversion+1
+            WHERE tripid=? AND tripversion=?"#,
+        )
+        .bind(&self.name)
+        .bind(self.date)
+        .bind(&self.notes)
+        .bind(self.id)
+        .bind(self.version)
+        .execute(&mut *tx)
+        .await?;
+        if res.rows_affected() == 0 {
+            return Err(Error::DatabaseVersionConflict(self.version));
+        }
+        self.save_participants(&mut tx).await?;
+        tx.commit().await?;
+        self.version += 1;
+        Ok(res)
+    }
+
+    pub async fn add_target_taxon(
+        &self,
+        tsn: i64,
+        pool: &Pool<Sqlite>,
+    ) -> Result<SqliteQueryResult> {
+        sqlx::query("INSERT OR IGNORE INTO sc_trip_taxa (tripid, tsn) VALUES (?, ?)")
+            .bind(self.id)
+            .bind(tsn)
+            .execute(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    pub async fn remove_target_taxon(
+        &self,
+        tsn: i64,
+        pool: &Pool<Sqlite>,
+    ) -> Result<SqliteQueryResult> {
+        sqlx::query("DELETE FROM sc_trip_taxa WHERE tripid=? AND tsn=?")
+            .bind(self.id)
+            .bind(tsn)
+            .execute(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    pub async fn add_target_source(
+        &self,
+        srcid: i64,
+        pool: &Pool<Sqlite>,
+    ) -> Result<SqliteQueryResult> {
+        sqlx::query("INSERT OR IGNORE INTO sc_trip_sources (tripid, srcid) VALUES (?, ?)")
+            .bind(self.id)
+            .bind(srcid)
+            .execute(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    pub async fn remove_target_source(
+        &self,
+        srcid: i64,
+        pool: &Pool<Sqlite>,
+    ) -> Result<SqliteQueryResult> {
+        sqlx::query("DELETE FROM sc_trip_sources WHERE tripid=? AND srcid=?")
+            .bind(self.id)
+            .bind(srcid)
+            .execute(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Compare the taxa and sources that were targeted on this trip to the samples that were
+    /// collected on it
+    pub async fn summary(&self, pool: &Pool<Sqlite>) -> Result<TripSummary> {
+        let target_taxa = sqlx::query_as(
+            r#"SELECT T.tsn, T.complete_name, COUNT(S.sampleid) AS nsamples
+            FROM sc_trip_taxa X
+            INNER JOIN taxonomic_units T ON T.tsn=X.tsn
+            LEFT JOIN sc_samples S ON S.tsn=X.tsn AND S.tripid=X.tripid
+            WHERE X.tripid=?
+            GROUP BY T.tsn ORDER BY T.phylo_sort_seq"#,
+        )
+        .bind(self.id)
+        .fetch_all(pool)
+        .await?;
+        let target_sources = sqlx::query_as(
+            r#"SELECT L.srcid, L.srcuuid, L.srcname, COUNT(S.sampleid) AS nsamples
+            FROM sc_trip_sources X
+            INNER JOIN sc_sources L ON L.srcid=X.srcid
+            LEFT JOIN sc_samples S ON S.srcid=X.srcid AND S.tripid=X.tripid
+            WHERE X.tripid=?
+            GROUP BY L.srcid ORDER BY L.srcname"#,
+        )
+        .bind(self.id)
+        .fetch_all(pool)
+        .await?;
+        let other_taxa = sqlx::query_as(
+            r#"SELECT T.tsn, T.complete_name, COUNT(S.sampleid) AS nsamples
+            FROM sc_samples S
+            INNER JOIN taxonomic_units T ON T.tsn=S.tsn
+            WHERE S.tripid=? AND S.tsn NOT IN (SELECT tsn FROM sc_trip_taxa WHERE tripid=?)
+            GROUP BY T.tsn ORDER BY T.phylo_sort_seq"#,
+        )
+        .bind(self.id)
+        .bind(self.id)
+        .fetch_all(pool)
+        .await?;
+        Ok(TripSummary {
+            target_taxa,
+            target_sources,
+            other_taxa,
+        })
+    }
+
+    pub fn new(name: String, date: Date, userid: i64) -> Self {
+        Self {
+            id: -1,
+            name,
+            date,
+            notes: None,
+            participants: Vec::new(),
+            userid,
+            version: 1,
+        }
+    }
+}
+
+impl Owned for Trip {
+    fn owner(&self) -> i64 {
+        self.userid
+    }
+
+    fn organization(&self) -> Option<i64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample::Sample;
+    use test_log::test;
+    use time::macros::date;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn test_trip_summary(pool: Pool<Sqlite>) {
+        let mut trip = Trip::new(" Prairie walk ".to_string(), date!(2023 - 09 - 15), 1);
+        trip.participants = vec!["Sam".to_string(), " Alex".to_string(), "Sam".to_string()];
+        trip.insert(&pool).await.expect("Failed to insert trip");
+        let loaded = Trip::load(trip.id, &pool)
+            .await
+            .expect("Failed to load trip");
+        assert_eq!(loaded, trip);
+        assert_eq!(loaded.name, "Prairie walk");
+        assert_eq!(loaded.participants, ["Alex", "Sam"]);
+
+        trip.add_target_taxon(40683, &pool).await.unwrap();
+        trip.add_target_taxon(43254, &pool).await.unwrap();
+        // adding a target twice is harmless
+        trip.add_target_taxon(43254, &pool).await.unwrap();
+        trip.add_target_source(1, &pool).await.unwrap();
+
+        let summary = trip.summary(&pool).await.expect("Failed to load summary");
+        assert_eq!(summary.target_taxa.len(), 2);
+        assert_eq!(summary.targets_collected(), 0);
+        assert!(summary.other_taxa.is_empty());
+
+        // samples 1 and 3 are from source 1, sample 2 is from source 2
+        for id in [2, 3] {
+            let mut sample = Sample::load(id, &pool).await.unwrap();
+            sample.trip = Some(trip.id);
+            sample.update(&pool).await.expect("Failed to update sample");
+        }
+        trip.remove_target_taxon(43254, &pool).await.unwrap();
+        let summary = trip.summary(&pool).await.expect("Failed to load summary");
+        assert_eq!(summary.target_taxa.len(), 1);
+        assert_eq!(summary.target_taxa[0].tsn, 40683);
+        assert_eq!(summary.target_taxa[0].nsamples, 2);
+        assert_eq!(summary.targets_collected(), 1);
+        assert_eq!(summary.target_sources.len(), 1);
+        assert_eq!(summary.target_sources[0].nsamples, 1);
+        assert!(summary.other_taxa.is_empty());
+
+        let mut sample = Sample::load(1, &pool).await.unwrap();
+        sample.trip = Some(trip.id);
+        sample.update(&pool).await.expect("Failed to update sample");
+        let summary = trip.summary(&pool).await.expect("Failed to load summary");
+        assert_eq!(summary.other_taxa.len(), 1);
+        assert_eq!(summary.other_taxa[0].tsn, 43254);
+
+        // a stale version can't be saved
+        let mut stale = loaded.clone();
+        trip.participants.clear();
+        trip.update(&pool).await.expect("Failed to update trip");
+        assert!(Trip::load(trip.id, &pool)
+            .await
+            .unwrap()
+            .participants
+            .is_empty());
+        assert!(matches!(
+            stale.update(&pool).await,
+            Err(Error::DatabaseVersionConflict(_))
+        ));
+
+        // deleting a trip leaves its samples in place
+        trip.delete(&pool).await.expect("Failed to delete trip");
+        assert_eq!(Sample::load(1, &pool).await.unwrap().trip, None);
+        assert!(Trip::new(" ".to_string(), date!(2023 - 09 - 15), 1)
+            .insert(&pool)
+            .await
+            .is_err());
+    }
+}
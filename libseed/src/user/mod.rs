@@ -2,21 +2,30 @@
 use crate::{
     error::{Error, Result},
     filter::{DynFilterPart, FilterPart},
+    format::{NumberFormat, NumberStyle, WeightUnit},
     loadable::{ExternalRef, Loadable},
+    timezone::TimeZone,
 };
 use argon2::{Argon2, PasswordHasher, PasswordVerifier};
 use async_trait::async_trait;
-use password_hash::{rand_core::OsRng, PasswordHash, SaltString};
+use password_hash::{
+    rand_core::{OsRng, RngCore},
+    PasswordHash, SaltString,
+};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use sqlx::{
     prelude::*,
     sqlite::{SqliteQueryResult, SqliteRow},
     Pool, QueryBuilder, Sqlite,
 };
 use std::sync::Arc;
+use subtle::ConstantTimeEq;
 use time::OffsetDateTime;
 use tracing::debug;
 
+pub mod verification;
+
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize, sqlx::Type)]
 #[repr(i64)]
 pub enum UserStatus {
@@ -51,11 +60,74 @@ pub struct User {
     #[sqlx(rename = "userprofile", default)]
     pub profile: Option<String>,
 
+    /// the IANA name of the time zone that dates and times are displayed in, UTC if unset
+    #[sqlx(rename = "usertimezone", default)]
+    pub timezone: Option<String>,
+
+    /// site administrators can impersonate other users to debug their problems
+    #[sqlx(rename = "useradmin", default)]
+    #[serde(default)]
+    pub admin: bool,
+
+    /// how numbers are displayed to this user
+    #[sqlx(rename = "usernumberstyle", default)]
+    #[serde(default)]
+    pub number_style: NumberStyle,
+
+    /// the unit that seed weights are displayed in
+    #[sqlx(rename = "userweightunit", default)]
+    #[serde(default)]
+    pub weight_unit: WeightUnit,
+
     #[serde(skip_serializing)]
     /// a hashed password for use when authenticating a user
     pub pwhash: String,
 }
 
+/// An API token that can be used to authenticate as a user instead of their password, e.g. from
+/// scripts or API clients. Only a hash of the token is stored in the database, so the token itself
+/// is only available at the time it is created.
+#[derive(FromRow, Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserToken {
+    #[sqlx(rename = "tokenid")]
+    pub id: i64,
+
+    pub userid: i64,
+
+    /// a name to help the user remember what the token is used for
+    #[sqlx(rename = "tokenname")]
+    pub name: String,
+
+    #[serde(skip_serializing)]
+    #[sqlx(rename = "tokenhash")]
+    pub hash: String,
+
+    #[sqlx(rename = "tokencreated")]
+    pub created: Option<OffsetDateTime>,
+
+    #[sqlx(rename = "tokenlastused")]
+    pub last_used: Option<OffsetDateTime>,
+}
+
+/// All API tokens start with this prefix so that they can be easily recognized
+const TOKEN_PREFIX: &str = "sct_";
+
+/// Split a token of the form `sct_<id>_<secret>` into its id and secret
+fn parse_token(token: &str) -> Option<(i64, &str)> {
+    let (id, secret) = token.strip_prefix(TOKEN_PREFIX)?.split_once('_')?;
+    Some((id.parse().ok()?, secret))
+}
+
+/// Hash the secret part of an API token for storing it in the database. The secrets are long
+/// random strings rather than passwords chosen by users, so a fast hash is enough and keeps
+/// authenticating API requests cheap, unlike the key derivation that is used for passwords.
+fn hash_token_secret(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
 #[async_trait]
 impl Loadable for User {
     type Id = i64;
@@ -116,7 +188,11 @@ impl User {
                 userstatus,
                 usersince,
                 userdisplayname,
-                userprofile
+                userprofile,
+                usertimezone,
+                useradmin,
+                usernumberstyle,
+                userweightunit
             FROM
                 sc_users"#,
         );
@@ -162,6 +238,10 @@ impl User {
                         userstatus=?,
                         userdisplayname=?,
                         userprofile=?,
+                        usertimezone=?,
+                        useradmin=?,
+                        usernumberstyle=?,
+                        userweightunit=?,
                         pwhash=?
                     WHERE
                         userid=?",
@@ -171,6 +251,10 @@ impl User {
         .bind(&self.status)
         .bind(&self.display_name)
         .bind(&self.profile)
+        .bind(&self.timezone)
+        .bind(self.admin)
+        .bind(self.number_style)
+        .bind(self.weight_unit)
         .bind(&self.pwhash)
         .bind(self.id)
         .execute(pool)
@@ -195,6 +279,17 @@ impl User {
             .map_err(|e| e.into())
     }
 
+    /// The time zone that dates and times are displayed in for this user. Falls back to UTC if the
+    /// user hasn't chosen a time zone or it is not known on this system.
+    pub fn time_zone(&self) -> TimeZone {
+        TimeZone::load_or_utc(self.timezone.as_deref())
+    }
+
+    /// How numbers and weights are formatted for this user
+    pub fn number_format(&self) -> NumberFormat {
+        NumberFormat::new(self.number_style, self.weight_unit)
+    }
+
     /// hash the given password with a random salt and store it inside the User object.
     pub fn change_password(&mut self, pw: &str) -> Result<()> {
         self.pwhash = Self::hash_password(pw)?;
@@ -220,6 +315,10 @@ impl User {
             register_date,
             display_name,
             profile,
+            timezone: None,
+            admin: false,
+            number_style: NumberStyle::default(),
+            weight_unit: WeightUnit::default(),
         }
     }
 
@@ -254,13 +353,88 @@ impl User {
         .bind(&self.profile)
         .execute(pool)
         .await
-        .map(|r| {
+        .inspect(|r| {
             self.id = r.last_insert_rowid();
-            r
         })
         .map_err(|e| e.into())
     }
 
+    /// Create a new API token for this user. The returned string is the token that the user needs
+    /// to authenticate with. It is not stored anywhere, so it can't be retrieved again later.
+    pub async fn create_token(
+        &self,
+        name: &str,
+        pool: &Pool<Sqlite>,
+    ) -> Result<(UserToken, String)> {
+        if name.trim().is_empty() {
+            return Err(Error::InvalidStateMissingAttribute("name".to_string()));
+        }
+        let mut bytes = [0u8; 24];
+        OsRng.fill_bytes(&mut bytes);
+        let secret: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        let hash = hash_token_secret(&secret);
+        let token: UserToken = sqlx::query_as(
+            r#"INSERT INTO sc_user_tokens (userid, tokenname, tokenhash) VALUES (?, ?, ?)
+            RETURNING *"#,
+        )
+        .bind(self.id)
+        .bind(name)
+        .bind(&hash)
+        .fetch_all(pool)
+        .await?
+        .pop()
+        .ok_or(sqlx::Error::RowNotFound)?;
+        let tokenstr = format!("{TOKEN_PREFIX}{}_{secret}", token.id);
+        Ok((token, tokenstr))
+    }
+
+    /// Fetch all of this user's API tokens from the database
+    pub async fn load_tokens(&self, pool: &Pool<Sqlite>) -> Result<Vec<UserToken>> {
+        sqlx::query_as("SELECT * FROM sc_user_tokens WHERE userid=? ORDER BY tokenid")
+            .bind(self.id)
+            .fetch_all(pool)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Remove one of this user's API tokens so that it can no longer be used to authenticate
+    pub async fn revoke_token(&self, tokenid: i64, pool: &Pool<Sqlite>) -> Result<()> {
+        let res = sqlx::query("DELETE FROM sc_user_tokens WHERE tokenid=? AND userid=?")
+            .bind(tokenid)
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        match res.rows_affected() {
+            0 => Err(sqlx::Error::RowNotFound.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fetch the user that the given API token belongs to. Returns `None` if the token is not
+    /// valid.
+    pub async fn load_by_token(token: &str, pool: &Pool<Sqlite>) -> Result<Option<User>> {
+        let Some((tokenid, secret)) = parse_token(token) else {
+            return Ok(None);
+        };
+        let Some(stored): Option<UserToken> =
+            sqlx::query_as("SELECT * FROM sc_user_tokens WHERE tokenid=?")
+                .bind(tokenid)
+                .fetch_optional(pool)
+                .await?
+        else {
+            return Ok(None);
+        };
+        let hash = hash_token_secret(secret);
+        if !bool::from(hash.as_bytes().ct_eq(stored.hash.as_bytes())) {
+            return Ok(None);
+        }
+        sqlx::query("UPDATE sc_user_tokens SET tokenlastused=CURRENT_TIMESTAMP WHERE tokenid=?")
+            .bind(tokenid)
+            .execute(pool)
+            .await?;
+        Self::load(stored.userid, pool).await.map(Some)
+    }
+
     pub fn validate_username(username: &str) -> Result<()> {
         if username.len() < 5 {
             return Err(Error::AuthInvalidUsernameTooShort);
@@ -343,7 +517,7 @@ mod tests {
 
     #[test(sqlx::test(
         migrations = "../db/migrations/",
-        fixtures(path = "../../db/fixtures", scripts("users"))
+        fixtures(path = "../../../db/fixtures", scripts("users"))
     ))]
     async fn modify_user(pool: Pool<Sqlite>) {
         const NEWNAME: &str = "TestUsername84902";
@@ -351,6 +525,9 @@ mod tests {
             .await
             .expect("Failed to fetch user from database");
         user.username = NEWNAME.to_string();
+        user.timezone = Some("UTC".to_string());
+        user.number_style = NumberStyle::Comma;
+        user.weight_unit = WeightUnit::Ounces;
         user.update(&pool).await.expect("Unable to update user");
         assert!(user.insert(&pool).await.is_err());
 
@@ -359,11 +536,13 @@ mod tests {
             .expect("Unable to load updated user");
         assert_eq!(user, loaded);
         assert_eq!(&loaded.username, NEWNAME);
+        assert_eq!(loaded.time_zone(), TimeZone::utc());
+        assert_eq!(loaded.number_format().weight(12.5), "0,44 oz");
     }
 
     #[test(sqlx::test(
         migrations = "../db/migrations/",
-        fixtures(path = "../../db/fixtures", scripts("users"))
+        fixtures(path = "../../../db/fixtures", scripts("users"))
     ))]
     async fn delete_user(pool: Pool<Sqlite>) {
         User::delete_id(&1, &pool)
@@ -431,4 +610,58 @@ mod tests {
         assert!(user.verify_password("new-password").is_ok());
         assert!(user.verify_password(pw).is_err());
     }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("users"))
+    ))]
+    async fn api_tokens(pool: Pool<Sqlite>) {
+        let user = User::load(1, &pool)
+            .await
+            .expect("Failed to fetch user from database");
+        let (token, tokenstr) = user
+            .create_token("backup script", &pool)
+            .await
+            .expect("Failed to create token");
+        assert_eq!(token.userid, user.id);
+        assert_eq!(token.name, "backup script");
+        assert!(!token.hash.contains(&tokenstr));
+        assert_eq!(token.hash.len(), 64);
+
+        let loaded = User::load_by_token(&tokenstr, &pool)
+            .await
+            .expect("Failed to authenticate with token")
+            .expect("Token was not accepted");
+        assert_eq!(loaded.id, user.id);
+        let tokens = user
+            .load_tokens(&pool)
+            .await
+            .expect("Failed to load tokens");
+        assert_eq!(tokens.len(), 1);
+        assert!(tokens[0].last_used.is_some());
+
+        // tampered or malformed tokens are rejected
+        let mut tampered = tokenstr.clone();
+        tampered.pop();
+        tampered.push('x');
+        for bad in [tampered.as_str(), "sct_1", "topsecret123", ""] {
+            assert!(User::load_by_token(bad, &pool)
+                .await
+                .expect("Failed to check token")
+                .is_none());
+        }
+
+        // other users can't revoke the token
+        let other = User::load(2, &pool)
+            .await
+            .expect("Failed to fetch user from database");
+        assert!(other.revoke_token(token.id, &pool).await.is_err());
+        user.revoke_token(token.id, &pool)
+            .await
+            .expect("Failed to revoke token");
+        assert!(User::load_by_token(&tokenstr, &pool)
+            .await
+            .expect("Failed to check token")
+            .is_none());
+    }
 }
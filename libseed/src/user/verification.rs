@@ -0,0 +1,221 @@
+//! Verification of the email addresses of newly-registered users. A user that registers receives a
+//! verification code by email and remains unverified until they follow the link containing it.
+//! Users that never do so can be reminded by sending them a new code, and accounts that remain
+//! unverified for too long can be expired.
+use super::{User, UserStatus};
+use crate::error::Result;
+use password_hash::rand_core::{OsRng, RngCore};
+use sqlx::{Pool, Sqlite};
+use time::Duration;
+use tracing::debug;
+
+/// How long a verification code stays valid after it has been requested, in hours
+pub const CODE_EXPIRATION_HOURS: i64 = 4 * 60 * 60;
+
+/// Generate a new verification code for the given user. Any previous codes for the user are
+/// invalidated.
+pub async fn new_code(userid: i64, pool: &Pool<Sqlite>) -> Result<String> {
+    let mut bytes = [0u8; 12];
+    OsRng.fill_bytes(&mut bytes);
+    let key: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    debug!(key, "Generated a new verification code");
+    sqlx::query(
+        r#"UPDATE sc_user_verification SET uvexpiration=0 WHERE userid=?;
+        INSERT into sc_user_verification (userid, uvkey, uvexpiration) VALUES(?, ?, ?)"#,
+    )
+    .bind(userid)
+    .bind(userid)
+    .bind(&key)
+    .bind(CODE_EXPIRATION_HOURS)
+    .execute(pool)
+    .await?;
+    Ok(key)
+}
+
+/// Find the unverified users that should be reminded to verify their email address. A user is due
+/// for a reminder when neither their registration nor the most recent verification code is more
+/// recent than `after`, and they haven't already received `max_reminders` reminders in addition to
+/// the original verification email.
+pub async fn users_due_for_reminder(
+    after: Duration,
+    max_reminders: u32,
+    pool: &Pool<Sqlite>,
+) -> Result<Vec<User>> {
+    sqlx::query_as(
+        r#"SELECT U.* FROM sc_users U
+        LEFT JOIN sc_user_verification UV ON UV.userid=U.userid
+        WHERE U.userstatus=? AND U.usersince <= datetime('now', ?)
+        GROUP BY U.userid
+        HAVING COUNT(UV.uvid) <= ?
+            AND (MAX(UV.uvrequested) IS NULL OR MAX(UV.uvrequested) <= datetime('now', ?))
+        ORDER BY U.userid"#,
+    )
+    .bind(UserStatus::Unverified)
+    .bind(format!("-{} seconds", after.whole_seconds()))
+    .bind(max_reminders)
+    .bind(format!("-{} seconds", after.whole_seconds()))
+    .fetch_all(pool)
+    .await
+    .map_err(Into::into)
+}
+
+/// Remove the accounts of users that registered more than `after` ago and still haven't verified
+/// their email address. Accounts that already contain any samples, sources or projects are kept.
+/// Returns the users that were removed.
+pub async fn expire_unverified_users(after: Duration, pool: &Pool<Sqlite>) -> Result<Vec<User>> {
+    let mut tx = pool.begin().await?;
+    let users: Vec<User> = sqlx::query_as(
+        r#"SELECT * FROM sc_users U WHERE U.userstatus=? AND U.usersince <= datetime('now', ?)
+        AND NOT EXISTS (SELECT 1 FROM sc_samples S WHERE S.userid=U.userid)
+        AND NOT EXISTS (SELECT 1 FROM sc_sources S WHERE S.userid=U.userid)
+        AND NOT EXISTS (SELECT 1 FROM sc_projects P WHERE P.userid=U.userid)
+        ORDER BY U.userid"#,
+    )
+    .bind(UserStatus::Unverified)
+    .bind(format!("-{} seconds", after.whole_seconds()))
+    .fetch_all(&mut *tx)
+    .await?;
+    for user in &users {
+        sqlx::query(
+            r#"DELETE FROM sc_user_verification WHERE userid=?;
+            DELETE FROM sc_users WHERE userid=?"#,
+        )
+        .bind(user.id)
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(users)
+}
+
+/// Mark the user as verified without requiring a verification code, e.g. when an administrator
+/// has confirmed the user's email address by other means. Any outstanding codes are invalidated.
+pub async fn mark_verified(userid: i64, pool: &Pool<Sqlite>) -> Result<()> {
+    let res = sqlx::query(
+        r#"UPDATE sc_user_verification SET uvexpiration=0 WHERE userid=? AND uvconfirmed=0;
+        UPDATE sc_users SET userstatus=? WHERE userid=?"#,
+    )
+    .bind(userid)
+    .bind(UserStatus::Verified)
+    .bind(userid)
+    .execute(pool)
+    .await?;
+    if res.rows_affected() == 0 {
+        return Err(sqlx::Error::RowNotFound.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loadable::Loadable;
+    use test_log::test;
+
+    /// Make the user an unverified user that registered the given number of days ago
+    async fn set_registered(userid: i64, days_ago: i64, pool: &Pool<Sqlite>) {
+        sqlx::query(
+            "UPDATE sc_users SET usersince=datetime('now', ?), userstatus=? WHERE userid=?",
+        )
+        .bind(format!("-{days_ago} days"))
+        .bind(UserStatus::Unverified)
+        .bind(userid)
+        .execute(pool)
+        .await
+        .expect("Failed to update registration date");
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("users"))
+    ))]
+    async fn verification_reminders(pool: Pool<Sqlite>) {
+        set_registered(1, 1, &pool).await;
+        let due = |pool: Pool<Sqlite>| async move {
+            users_due_for_reminder(Duration::days(3), 2, &pool)
+                .await
+                .expect("Failed to find users due for a reminder")
+                .iter()
+                .map(|u| u.id)
+                .collect::<Vec<_>>()
+        };
+        // registered too recently, and verified users never get a reminder
+        assert!(due(pool.clone()).await.is_empty());
+
+        set_registered(1, 5, &pool).await;
+        assert_eq!(due(pool.clone()).await, vec![1]);
+
+        // a recent verification code postpones the reminder
+        let key = new_code(1, &pool).await.expect("Failed to create code");
+        assert_eq!(key.len(), 24);
+        assert!(due(pool.clone()).await.is_empty());
+
+        // stop reminding after the maximum number of reminders
+        for _ in 0..2 {
+            sqlx::query("UPDATE sc_user_verification SET uvrequested=datetime('now', '-4 days')")
+                .execute(&pool)
+                .await
+                .unwrap();
+            assert_eq!(due(pool.clone()).await, vec![1]);
+            new_code(1, &pool).await.expect("Failed to create code");
+        }
+        sqlx::query("UPDATE sc_user_verification SET uvrequested=datetime('now', '-4 days')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(due(pool.clone()).await.is_empty());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("users", "sources"))
+    ))]
+    async fn expire_users(pool: Pool<Sqlite>) {
+        set_registered(1, 40, &pool).await;
+        new_code(1, &pool).await.expect("Failed to create code");
+        // user 1 has sources, so the account is kept
+        assert!(expire_unverified_users(Duration::days(30), &pool)
+            .await
+            .expect("Failed to expire users")
+            .is_empty());
+
+        sqlx::query("DELETE FROM sc_sources")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(expire_unverified_users(Duration::days(60), &pool)
+            .await
+            .expect("Failed to expire users")
+            .is_empty());
+        let expired = expire_unverified_users(Duration::days(30), &pool)
+            .await
+            .expect("Failed to expire users");
+        assert_eq!(expired.iter().map(|u| u.id).collect::<Vec<_>>(), vec![1]);
+        assert!(User::load(1, &pool).await.is_err());
+        // verified users never expire
+        assert!(User::load(2, &pool).await.is_ok());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../../db/fixtures", scripts("users"))
+    ))]
+    async fn manually_verify(pool: Pool<Sqlite>) {
+        set_registered(1, 0, &pool).await;
+        new_code(1, &pool).await.expect("Failed to create code");
+        mark_verified(1, &pool)
+            .await
+            .expect("Failed to mark user verified");
+        let user = User::load(1, &pool).await.unwrap();
+        assert_eq!(user.status, UserStatus::Verified);
+        let expiration: i64 =
+            sqlx::query_scalar("SELECT uvexpiration FROM sc_user_verification WHERE userid=1")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(expiration, 0);
+
+        assert!(mark_verified(99, &pool).await.is_err());
+    }
+}
@@ -0,0 +1,16 @@
+//! Objects related to reporting errors from this library
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("invalid url: {0}")]
+    InvalidUrl(String),
+
+    /// the server handled the request but returned an error
+    #[error("server returned status {status}: {message}")]
+    Api { status: u16, message: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
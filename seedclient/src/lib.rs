@@ -0,0 +1,243 @@
+//! A client for the JSON API of the seed collection web application. It returns the same types
+//! that [libseed] uses, so that tools can work with data from a remote server in the same way as
+//! with data loaded from a local database.
+use libseed::{
+    project::{
+        status::{AllocationStatus, Period, StatusEvent, StatusProgress},
+        Allocation,
+    },
+    sample::{Certainty, Sample},
+    stats::{GroupCount, ProjectStatus, VendorSummary},
+};
+use reqwest::{Method, RequestBuilder, Response};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use time::Date;
+use uuid::Uuid;
+
+pub mod error;
+
+pub use error::{Error, Result};
+
+/// The path of the JSON API below the base url of the site
+const API_PREFIX: &str = "api/v1/";
+
+/// All of the collection statistics of the user
+#[derive(Debug, Deserialize, Serialize, PartialEq, Clone)]
+pub struct Stats {
+    pub families: Vec<GroupCount>,
+    pub years: Vec<GroupCount>,
+    pub sources: Vec<GroupCount>,
+    pub origins: Vec<GroupCount>,
+    pub projects: Vec<ProjectStatus>,
+}
+
+/// The fields of a sample to modify. Fields that are `None` are left unchanged, and optional
+/// fields that are `Some(None)` are cleared.
+#[derive(Debug, Serialize, Clone)]
+pub struct SamplePatch {
+    /// the version of the sample that the changes are based on. The update fails if the sample
+    /// was modified since then.
+    pub version: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taxon: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quantity: Option<Option<i64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<Option<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub month: Option<Option<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<Option<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certainty: Option<Certainty>,
+}
+
+impl SamplePatch {
+    /// A patch that doesn't change anything yet for the given version of a sample
+    pub fn new(version: i64) -> Self {
+        Self {
+            version,
+            taxon: None,
+            source: None,
+            quantity: None,
+            notes: None,
+            month: None,
+            year: None,
+            certainty: None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ProgressQuery {
+    period: Period,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    until: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StatusBody {
+    status: AllocationStatus,
+}
+
+/// The body of an error response from the server
+#[derive(Deserialize)]
+struct ApiError {
+    error: String,
+}
+
+/// A client for a single user of a seed collection server
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base: String,
+    token: String,
+}
+
+impl Client {
+    /// Create a client for the site at `base_url`, e.g. `https://example.com/seeds`, that
+    /// authenticates with the given API token
+    pub fn new(base_url: &str, token: &str) -> Result<Self> {
+        if !(base_url.starts_with("http://") || base_url.starts_with("https://")) {
+            return Err(Error::InvalidUrl(base_url.to_string()));
+        }
+        Ok(Self {
+            http: reqwest::Client::new(),
+            base: format!("{}/{API_PREFIX}", base_url.trim_end_matches('/')),
+            token: token.to_string(),
+        })
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.http
+            .request(method, format!("{}{path}", self.base))
+            .bearer_auth(&self.token)
+    }
+
+    /// Send a request and parse the JSON body of the response, turning error responses into
+    /// [Error::Api]
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T> {
+        let response = request.send().await?;
+        Self::check(response)
+            .await?
+            .json()
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn check(response: Response) -> Result<Response> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await?;
+        // errors that are rejected before reaching a handler aren't formatted as json
+        let message = serde_json::from_str::<ApiError>(&body)
+            .map(|e| e.error)
+            .unwrap_or(body);
+        Err(Error::Api {
+            status: status.as_u16(),
+            message,
+        })
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.send(self.request(Method::GET, path)).await
+    }
+
+    /// All of the collection statistics of the user
+    pub async fn stats(&self) -> Result<Stats> {
+        self.get("stats/").await
+    }
+
+    /// The number of samples per taxonomic family
+    pub async fn families(&self) -> Result<Vec<GroupCount>> {
+        self.get("stats/families").await
+    }
+
+    /// The number of samples per collection year
+    pub async fn years(&self) -> Result<Vec<GroupCount>> {
+        self.get("stats/years").await
+    }
+
+    /// The number of samples per source
+    pub async fn sources(&self) -> Result<Vec<GroupCount>> {
+        self.get("stats/sources").await
+    }
+
+    /// The number of samples per origin
+    pub async fn origins(&self) -> Result<Vec<GroupCount>> {
+        self.get("stats/origins").await
+    }
+
+    /// A summary of the purchased samples per vendor and year
+    pub async fn vendors(&self) -> Result<Vec<VendorSummary>> {
+        self.get("stats/vendors").await
+    }
+
+    /// The status of the samples of each of the user's projects
+    pub async fn projects(&self) -> Result<Vec<ProjectStatus>> {
+        self.get("stats/projects").await
+    }
+
+    /// Load the sample with the given uuid
+    pub async fn sample(&self, uuid: Uuid) -> Result<Sample> {
+        self.get(&format!("sample/{uuid}")).await
+    }
+
+    /// Modify the fields of a sample that are set in `patch`, returning the updated sample
+    pub async fn update_sample(&self, uuid: Uuid, patch: &SamplePatch) -> Result<Sample> {
+        self.send(
+            self.request(Method::PATCH, &format!("sample/{uuid}"))
+                .json(patch),
+        )
+        .await
+    }
+
+    /// The number of the project's allocations that reached each status, per period
+    pub async fn project_progress(
+        &self,
+        project: Uuid,
+        period: Period,
+        since: Option<Date>,
+        until: Option<Date>,
+    ) -> Result<Vec<StatusProgress>> {
+        let query = ProgressQuery {
+            period,
+            since: since.map(|d| d.to_string()),
+            until: until.map(|d| d.to_string()),
+        };
+        self.send(
+            self.request(Method::GET, &format!("project/{project}/progress"))
+                .query(&query),
+        )
+        .await
+    }
+
+    /// The status changes of an allocation, most recent changes first
+    pub async fn allocation_events(&self, project: Uuid, alloc: Uuid) -> Result<Vec<StatusEvent>> {
+        self.get(&format!("project/{project}/sample/{alloc}/events"))
+            .await
+    }
+
+    /// Change the status of an allocation, returning the updated allocation
+    pub async fn set_allocation_status(
+        &self,
+        project: Uuid,
+        alloc: Uuid,
+        status: AllocationStatus,
+    ) -> Result<Allocation> {
+        self.send(
+            self.request(
+                Method::PUT,
+                &format!("project/{project}/sample/{alloc}/status"),
+            )
+            .json(&StatusBody { status }),
+        )
+        .await
+    }
+}
@@ -1,10 +1,34 @@
-use clap::{Parser, Subcommand, ValueEnum};
-use libseed::taxonomy;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use libseed::{
+    conservation::PermitPolicy,
+    filter::SortSpecs,
+    format::{NumberStyle, WeightUnit},
+    notes::NoteSource,
+    project::NoteType,
+    quality::FillMethod,
+    report::ReportFormat,
+    sample,
+    source::{HabitatType, LightCondition, SoilMoisture},
+    taxonomy,
+};
 use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[command(author, version, about, after_help = crate::exitcode::HELP)]
 pub struct Cli {
+    #[arg(
+        long,
+        global = true,
+        help = "Use the named profile instead of the current one"
+    )]
+    pub profile: Option<String>,
+    #[arg(
+        long = "yes",
+        visible_alias = "non-interactive",
+        global = true,
+        help = "Never prompt for input. Commands fail if required input is missing, and confirmations are accepted automatically"
+    )]
+    pub non_interactive: bool,
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -17,12 +41,23 @@ pub enum Commands {
         username: Option<String>,
         #[arg(short, long)]
         database: Option<PathBuf>,
+        #[arg(long, help = "Log in with an API token instead of a password")]
+        token: bool,
     },
     #[command(about = "Log out of the database")]
     Logout,
+    #[command(
+        about = "Manage login profiles",
+        after_help = "Each profile stores the login details for a separate database. Commands use the current profile unless a different one is chosen with --profile."
+    )]
+    #[clap(alias = "profile")]
+    Profiles {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
     #[command(
         about = "Show current config status",
-        after_help = "Shows the current configuration, including the path to the database and the logged in user"
+        after_help = "Shows the current configuration, including the path to the database and the logged in user. Built-in plugins that validate or fill in data when samples and sources are saved can be enabled by listing their names as \"plugins\" in the seedctl config file: require-coordinates, no-future-dates and protect-listed-taxa."
     )]
     Status,
     #[command(
@@ -52,6 +87,33 @@ pub enum Commands {
         #[command(subcommand)]
         command: ProjectCommands,
     },
+    #[command(
+        about = "Manage collection trips",
+        after_help = "A trip is a planned field outing. It lists the people taking part and the sources and taxa that you intend to visit and collect. Samples can record the trip that they were collected on, so that the targets can be compared to what was actually collected."
+    )]
+    #[clap(alias = "trip")]
+    Trips {
+        #[command(subcommand)]
+        command: TripCommands,
+    },
+    #[command(
+        about = "Browse the notes of your samples and projects",
+        after_help = "Notes are kept in two places: the notes field of a sample, and the dated notes about a sample in a project. This command lists both of them together."
+    )]
+    #[clap(alias = "note")]
+    Notes {
+        #[command(subcommand)]
+        command: NoteCommands,
+    },
+    #[command(
+        about = "Manage custom reports",
+        after_help = "A report is a template that is rendered with the samples that match its filter, so that you can produce your own lists and spreadsheets. Templates use the Jinja syntax and have access to the matching samples as `samples`."
+    )]
+    #[clap(alias = "report")]
+    Reports {
+        #[command(subcommand)]
+        command: ReportCommands,
+    },
     #[command(about = "Query taxonomy")]
     Taxonomy {
         #[command(subcommand)]
@@ -64,6 +126,79 @@ pub enum Commands {
     },
 }
 
+#[derive(Subcommand, Debug)]
+pub enum NoteCommands {
+    #[command(about = "List notes, the most recent first")]
+    List {
+        #[arg(short, long, help = "Only show notes that contain this text")]
+        text: Option<String>,
+        #[arg(
+            long,
+            help = "Only show sample notes (sample) or notes about samples in projects (allocation)"
+        )]
+        source: Option<NoteSource>,
+        #[arg(
+            long = "type",
+            help = "Only show project notes of this type (Preparation, Germination, Planting, Growing or Other)"
+        )]
+        kind: Option<NoteType>,
+        #[arg(
+            long,
+            help = "Only show notes dated on or after this date (YYYY-MM-DD)"
+        )]
+        from: Option<String>,
+        #[arg(
+            long,
+            help = "Only show notes dated on or before this date (YYYY-MM-DD)"
+        )]
+        to: Option<String>,
+        #[arg(short, long, help = "Only show notes about samples in this project")]
+        project: Option<i64>,
+        #[arg(short, long, help = "Only show notes about this sample")]
+        sample: Option<i64>,
+        #[arg(
+            long,
+            help = "Only show notes about samples of this taxon or of any taxon below it"
+        )]
+        taxon: Option<i64>,
+    },
+    #[command(
+        about = "Add a note about a sample in a project",
+        after_help = "A note template of the project pre-fills the type, summary and details of the note. Any of them that are given on the command line replace the values of the template."
+    )]
+    Add {
+        #[arg(help = "The id of the sample's allocation to the project")]
+        allocation: i64,
+        #[arg(short, long, help = "The name of a note template of the project")]
+        template: Option<String>,
+        #[arg(
+            long = "type",
+            required_unless_present = "template",
+            help = "The type of the note (Preparation, Germination, Planting, Growing or Other)"
+        )]
+        kind: Option<NoteType>,
+        #[arg(short, long, required_unless_present = "template")]
+        summary: Option<String>,
+        #[arg(short, long)]
+        details: Option<String>,
+        #[arg(long, help = "The date of the note (YYYY-MM-DD). Defaults to today")]
+        date: Option<String>,
+    },
+    #[command(about = "List the note templates of a project")]
+    Templates {
+        #[arg(help = "The id of the project")]
+        project: i64,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProfileCommands {
+    #[command(about = "List all profiles")]
+    List,
+    #[command(about = "Make a profile the current profile")]
+    Switch { name: String },
+}
+
 #[derive(Subcommand, Debug)]
 pub enum ProjectCommands {
     #[command(about = "List all projects")]
@@ -95,12 +230,15 @@ pub enum ProjectCommands {
     },
     #[command(about = "Remove a project from the database")]
     Remove { id: i64 },
-    #[command(about = "Add a new sample to the project")]
+    #[command(
+        about = "Add samples to the project",
+        after_help = "Each sample is added on its own, so a sample that can't be added (e.g. because it is already part of the project) doesn't keep the others from being added. The command fails if any sample could not be added."
+    )]
     AddSample {
         #[arg(short, long)]
         project: i64,
-        #[arg(short, long)]
-        sample: i64,
+        #[arg(short, long, num_args = 1.., required = true)]
+        sample: Vec<i64>,
     },
     #[command(about = "Remove an existing sample from the project")]
     RemoveSample {
@@ -117,6 +255,123 @@ pub enum ProjectCommands {
     },
 }
 
+#[derive(Subcommand, Debug)]
+pub enum TripCommands {
+    #[command(about = "List all of your trips")]
+    List {},
+    #[command(about = "Show the targets of a trip and what was collected on it")]
+    Show { id: i64 },
+    #[command(about = "Plan a new trip")]
+    Add {
+        #[arg(short, long)]
+        name: String,
+        #[arg(short, long, help = "The date of the trip (YYYY-MM-DD)")]
+        date: String,
+        #[arg(
+            short,
+            long = "participant",
+            help = "A person taking part in the trip. May be given multiple times"
+        )]
+        participants: Vec<String>,
+        #[arg(long)]
+        notes: Option<String>,
+    },
+    #[command(
+        about="Modify properties of a trip",
+        group(
+            clap::ArgGroup::new("modify")
+                .required(true)
+                .multiple(true)
+                .args(&["name", "date", "participants", "notes"]),
+        ))]
+    #[clap(alias = "edit")]
+    Modify {
+        id: i64,
+        #[arg(short, long)]
+        name: Option<String>,
+        #[arg(short, long, help = "The date of the trip (YYYY-MM-DD)")]
+        date: Option<String>,
+        #[arg(
+            short,
+            long = "participant",
+            help = "A person taking part in the trip, replacing the current participants. May be given multiple times"
+        )]
+        participants: Vec<String>,
+        #[arg(long)]
+        notes: Option<String>,
+    },
+    #[command(about = "Remove a trip from the database. Its samples are kept.")]
+    Remove { id: i64 },
+    #[command(
+        about = "Add target taxa or sources to a trip",
+        group(
+            clap::ArgGroup::new("targets")
+                .required(true)
+                .multiple(true)
+                .args(&["taxon", "source"]),
+        ))]
+    Target {
+        id: i64,
+        #[arg(short, long, help = "A taxon to collect. May be given multiple times")]
+        taxon: Vec<i64>,
+        #[arg(short, long, help = "A source to visit. May be given multiple times")]
+        source: Vec<i64>,
+    },
+    #[command(
+        about = "Remove target taxa or sources from a trip",
+        group(
+            clap::ArgGroup::new("targets")
+                .required(true)
+                .multiple(true)
+                .args(&["taxon", "source"]),
+        ))]
+    Untarget {
+        id: i64,
+        #[arg(short, long)]
+        taxon: Vec<i64>,
+        #[arg(short, long)]
+        source: Vec<i64>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ReportCommands {
+    #[command(about = "List all of your reports")]
+    List {},
+    #[command(about = "Show the template of a report")]
+    Show { id: i64 },
+    #[command(about = "Add a new report")]
+    Add {
+        #[arg(short, long)]
+        name: String,
+        #[arg(short, long, help = "A file containing the report template")]
+        template: PathBuf,
+        #[arg(
+            long,
+            default_value = "html",
+            help = "The format of the report (html or csv)"
+        )]
+        format: ReportFormat,
+        #[arg(
+            long,
+            help = "Only include samples whose taxon, source or notes contain this text"
+        )]
+        filter: Option<String>,
+    },
+    #[command(about = "Remove a report")]
+    Remove { id: i64 },
+    #[command(about = "Generate a report")]
+    Run {
+        id: i64,
+        #[arg(
+            short,
+            long,
+            help = "Write the report to this file instead of the terminal"
+        )]
+        output: Option<PathBuf>,
+    },
+}
+
 #[derive(Subcommand, Debug)]
 pub enum SourceCommands {
     #[command(about = "List all sources")]
@@ -125,9 +380,36 @@ pub enum SourceCommands {
         full: bool,
         #[arg(long)]
         filter: Option<String>,
+        #[arg(
+            long,
+            help = "Only list sources with this habitat type: 'prairie', 'savanna', 'woodland', 'forest', 'wetland', 'shoreline' or 'disturbed'"
+        )]
+        habitat: Option<HabitatType>,
+        #[arg(
+            long,
+            help = "Only list sources with this soil moisture class: 'dry', 'dry-mesic', 'mesic', 'wet-mesic' or 'wet'"
+        )]
+        moisture: Option<SoilMoisture>,
+        #[arg(
+            long,
+            help = "Only list sources with these light conditions: 'full-sun', 'part-shade' or 'shade'"
+        )]
+        light: Option<LightCondition>,
     },
     #[command(about = "Show details about a single source")]
     Show { id: i64 },
+    #[command(
+        about = "Find the sources within a distance of a location",
+        after_help = "The sources are ordered by their distance from the location and are listed together with your samples from each of them."
+    )]
+    Near {
+        #[arg(long = "lat", allow_negative_numbers = true)]
+        latitude: f64,
+        #[arg(long = "long", allow_negative_numbers = true)]
+        longitude: f64,
+        #[arg(long, short, help = "The search radius in kilometers")]
+        radius: f64,
+    },
     #[command(about = "Add a new source to the database")]
     Add {
         #[arg(long)]
@@ -138,18 +420,55 @@ pub enum SourceCommands {
         latitude: Option<f64>,
         #[arg(long = "long")]
         longitude: Option<f64>,
+        #[arg(
+            long,
+            help = "The habitat type: 'prairie', 'savanna', 'woodland', 'forest', 'wetland', 'shoreline' or 'disturbed'"
+        )]
+        habitat: Option<HabitatType>,
+        #[arg(
+            long,
+            help = "The soil moisture class: 'dry', 'dry-mesic', 'mesic', 'wet-mesic' or 'wet'"
+        )]
+        moisture: Option<SoilMoisture>,
+        #[arg(
+            long,
+            help = "The light conditions: 'full-sun', 'part-shade' or 'shade'"
+        )]
+        light: Option<LightCondition>,
+        #[arg(
+            long,
+            help = "Only show the exact coordinates to those who can edit the source"
+        )]
+        sensitive: bool,
         #[arg(long)]
         userid: Option<i64>,
     },
-    #[command(about = "Remove an existing source from the database")]
-    Remove { id: i64 },
+    #[command(
+        about = "Remove an existing source from the database",
+        after_help = "A source that still has samples can only be removed if you specify what should happen to its samples, either with --reassign-to or with --cascade."
+    )]
+    Remove {
+        id: i64,
+        #[arg(
+            long,
+            value_name = "SOURCE",
+            help = "Move the samples of the source to another source"
+        )]
+        reassign_to: Option<i64>,
+        #[arg(
+            long,
+            conflicts_with = "reassign_to",
+            help = "Remove the samples of the source as well, including their allocations to projects"
+        )]
+        cascade: bool,
+    },
     #[command(
         about="Modify properties of a source",
         group(
             clap::ArgGroup::new("modify")
                 .required(true)
                 .multiple(true)
-                .args(&["name", "description", "latitude", "longitude"]),
+                .args(&["name", "description", "latitude", "longitude", "habitat", "moisture", "light", "sensitive"]),
         ))]
     #[clap(alias = "edit")]
     Modify {
@@ -162,15 +481,70 @@ pub enum SourceCommands {
         latitude: Option<f64>,
         #[arg(long = "long")]
         longitude: Option<f64>,
+        #[arg(
+            long,
+            help = "The habitat type: 'prairie', 'savanna', 'woodland', 'forest', 'wetland', 'shoreline' or 'disturbed'"
+        )]
+        habitat: Option<HabitatType>,
+        #[arg(
+            long,
+            help = "The soil moisture class: 'dry', 'dry-mesic', 'mesic', 'wet-mesic' or 'wet'"
+        )]
+        moisture: Option<SoilMoisture>,
+        #[arg(
+            long,
+            help = "The light conditions: 'full-sun', 'part-shade' or 'shade'"
+        )]
+        light: Option<LightCondition>,
+        #[arg(
+            long,
+            help = "Whether the exact coordinates are only shown to those who can edit the source"
+        )]
+        sensitive: Option<bool>,
+    },
+    #[command(
+        about = "Find and merge duplicate sources",
+        after_help = "Without --keep, the sources that may be duplicates of each other are listed. With --keep and --merge, the given sources are merged into the kept source: their samples and trip visits are moved to it and they are removed."
+    )]
+    Dedupe {
+        #[arg(long, value_name = "SOURCE", requires = "merge")]
+        keep: Option<i64>,
+        #[arg(
+            long,
+            value_name = "SOURCE",
+            num_args = 1..,
+            requires = "keep",
+            help = "The sources to merge into the kept source"
+        )]
+        merge: Vec<i64>,
     },
 }
 
-#[derive(ValueEnum, Clone, Debug)]
-pub enum SampleSortField {
-    Id,
-    Taxon,
-    Name,
-    Source,
+#[derive(Args, Debug)]
+pub struct PurchaseArgs {
+    #[arg(long, help = "The vendor that the seed was purchased from")]
+    pub vendor: Option<String>,
+    #[arg(long, help = "The vendor's lot number")]
+    pub lot: Option<String>,
+    #[arg(long, help = "The date of purchase (YYYY-MM-DD)")]
+    pub purchase_date: Option<String>,
+    #[arg(long)]
+    pub price: Option<f64>,
+    #[arg(
+        long,
+        help = "Where the seed was originally collected, as certified by the vendor"
+    )]
+    pub certified_origin: Option<String>,
+}
+
+impl PurchaseArgs {
+    pub fn is_empty(&self) -> bool {
+        self.vendor.is_none()
+            && self.lot.is_none()
+            && self.purchase_date.is_none()
+            && self.price.is_none()
+            && self.certified_origin.is_none()
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -179,14 +553,36 @@ pub enum SampleCommands {
     List {
         #[arg(short, long)]
         full: bool,
-        #[arg(short, long)]
+        #[arg(
+            short,
+            long,
+            help = "Only list your own samples, not the samples of your organizations"
+        )]
         user: bool,
         #[arg(short, long)]
         limit: Option<String>,
-        #[arg(short, long)]
-        sort: Option<SampleSortField>,
+        #[arg(
+            short,
+            long,
+            help = "Sort by a comma-separated list of fields, e.g. 'name,-date'. A field that starts with '-' is sorted in descending order. Fields: id, taxon, name, source, srcid, date, qty, grade"
+        )]
+        sort: Option<SortSpecs<sample::Sort>>,
+        #[arg(long, help = "Only list samples of taxa in the given family")]
+        family: Option<String>,
+        #[arg(long, help = "Only list samples of taxa in the given order")]
+        order: Option<String>,
+        #[arg(
+            long,
+            help = "Only list purchased samples",
+            conflicts_with = "collected"
+        )]
+        purchased: bool,
+        #[arg(long, help = "Only list collected samples")]
+        collected: bool,
     },
-    #[command(about = "Show details for a single sample")]
+    #[command(
+        about = "Show details for a single sample, along with its source, taxon and projects"
+    )]
     Show { id: i64 },
     #[command(about = "Add a new sample to the database")]
     Add {
@@ -202,13 +598,43 @@ pub enum SampleCommands {
         quantity: Option<i64>,
         #[arg(short, long)]
         notes: Option<String>,
-        #[arg(short = '?', long)]
+        #[arg(short = '?', long, conflicts_with = "certain")]
         uncertain: bool,
+        #[arg(long, help = "Mark the identification as certain")]
+        certain: bool,
         #[arg(short, long)]
         userid: Option<i64>,
+        #[arg(
+            long,
+            help = "Don't fill in omitted values from the user's sample defaults"
+        )]
+        no_defaults: bool,
+        #[command(flatten)]
+        purchase: PurchaseArgs,
+        #[arg(long, help = "The trip that the sample was collected on")]
+        trip: Option<i64>,
     },
     #[command(about = "Remove an existing sample from the database")]
     Remove { id: i64 },
+    #[command(
+        about = "Import samples from a CSV file",
+        after_help = "Each column of the CSV file is mapped to a sample field. Unless a saved mapping profile is used, you will be asked interactively which field each column corresponds to. Taxa are matched by their complete scientific name and sources by name; sources that don't exist yet are created."
+    )]
+    Import {
+        #[arg(help = "Path to a CSV file with a header row")]
+        file: PathBuf,
+        #[arg(long, short, help = "Use a previously saved column mapping profile")]
+        profile: Option<String>,
+        #[arg(
+            long,
+            help = "Save the column mapping as a profile with the given name"
+        )]
+        save_profile: Option<String>,
+        #[arg(long, help = "Check the file without adding anything to the database")]
+        dry_run: bool,
+        #[arg(short, long)]
+        userid: Option<i64>,
+    },
     #[command(about = "Modify properties of a sample")]
     #[clap(alias = "edit")]
     Modify {
@@ -229,9 +655,204 @@ pub enum SampleCommands {
         certain: bool,
         #[arg(long, conflicts_with("certain"))]
         uncertain: bool,
+        #[command(flatten)]
+        purchase: PurchaseArgs,
+        #[arg(
+            long,
+            help = "Mark the sample as collected, removing its purchase details",
+            conflicts_with_all = ["vendor", "lot", "purchase_date", "price", "certified_origin"]
+        )]
+        collected: bool,
+        #[arg(long, help = "The trip that the sample was collected on")]
+        trip: Option<i64>,
+        #[arg(
+            long,
+            conflicts_with = "trip",
+            help = "Remove the sample from the trip that it was collected on"
+        )]
+        no_trip: bool,
+    },
+    #[command(
+        about = "Flag a sample for review",
+        after_help = "If no reason is given, you will be asked to choose one of the common reasons or enter your own."
+    )]
+    Flag {
+        id: i64,
+        #[arg(help = "Why the sample needs to be reviewed")]
+        reason: Option<String>,
+    },
+    #[command(about = "Remove review flags from a sample")]
+    Unflag {
+        id: i64,
+        #[arg(
+            long,
+            short,
+            help = "Only remove the flag with this reason instead of all flags"
+        )]
+        reason: Option<String>,
+    },
+    #[command(about = "List the samples that are flagged for review")]
+    Flagged {
+        #[arg(long, short, help = "Only list samples flagged for this reason")]
+        reason: Option<String>,
+    },
+    #[command(
+        about = "Print the labels of all samples that were added or modified since their label was last printed",
+        after_help = "The labels are written to standard output, separated by blank lines, and are then marked as printed."
+    )]
+    Labels {
+        #[arg(long, help = "Show the pending labels without marking them as printed")]
+        dry_run: bool,
+    },
+    #[command(
+        about = "Estimate the yield that can be expected from each source in the coming season",
+        after_help = "The expected quantity is the average yearly quantity of your past samples of a taxon at a source. Taxa that are listed as invasive are left out unless --include-invasive is given."
+    )]
+    Forecast {
+        #[arg(long, help = "Include taxa that are listed as invasive")]
+        include_invasive: bool,
+    },
+    #[command(
+        about = "Recommend samples for a restoration planting site",
+        after_help = "Samples are ranked by how closely the habitat, soil moisture and light conditions of their source match the planting site, and by the distance between their source and the site. At least the location or one of the conditions of the site has to be given."
+    )]
+    Match {
+        #[arg(long = "lat", requires = "longitude", allow_negative_numbers = true)]
+        latitude: Option<f64>,
+        #[arg(long = "long", requires = "latitude", allow_negative_numbers = true)]
+        longitude: Option<f64>,
+        #[arg(
+            long,
+            help = "The habitat type of the site: 'prairie', 'savanna', 'woodland', 'forest', 'wetland', 'shoreline' or 'disturbed'"
+        )]
+        habitat: Option<HabitatType>,
+        #[arg(
+            long,
+            help = "The soil moisture class of the site: 'dry', 'dry-mesic', 'mesic', 'wet-mesic' or 'wet'"
+        )]
+        moisture: Option<SoilMoisture>,
+        #[arg(
+            long,
+            help = "The light conditions of the site: 'full-sun', 'part-shade' or 'shade'"
+        )]
+        light: Option<LightCondition>,
+        #[arg(
+            long,
+            default_value_t = 20,
+            help = "The number of samples to recommend"
+        )]
+        limit: usize,
+    },
+    #[command(
+        about = "Show or change the default values for new samples",
+        after_help = "These defaults are used by 'samples add' and the web interface for any values that are not specified explicitly."
+    )]
+    Defaults {
+        #[arg(long, conflicts_with = "clear_source", help = "Default source ID")]
+        source: Option<i64>,
+        #[arg(long, help = "Remove the default source")]
+        clear_source: bool,
+        #[arg(long, help = "Whether new samples are marked as uncertain by default")]
+        uncertain: Option<bool>,
+        #[arg(
+            long,
+            help = "Whether new samples default to the current month and year"
+        )]
+        current_date: Option<bool>,
+        #[arg(
+            long,
+            value_parser = clap::value_parser!(u8).range(1..=12),
+            help = "The month (1-12) that the collection year used in reports starts in"
+        )]
+        year_start: Option<u8>,
+        #[arg(
+            long,
+            help = "The region (e.g. a state code like 'MN') whose conservation listings apply to new samples"
+        )]
+        region: Option<String>,
+        #[arg(
+            long,
+            help = "What to do when adding a sample of a listed taxon without a permit: 'ignore', 'warn' or 'require'"
+        )]
+        permit_policy: Option<PermitPolicy>,
+    },
+    #[command(
+        about = "Manage collecting permits",
+        after_help = "Taxa that are listed as endangered, threatened or of special concern in a region can only be collected with a permit. Depending on your permit policy, new samples of listed taxa are rejected unless you have a valid permit for them."
+    )]
+    #[clap(alias = "permit")]
+    Permits {
+        #[command(subcommand)]
+        command: PermitCommands,
+    },
+    #[command(
+        about = "Manage the purity and fill tests of samples",
+        after_help = "The most recent test of a sample is used to estimate how much of its quantity is usable seed, e.g. in the propagation plans of projects."
+    )]
+    Quality {
+        #[command(subcommand)]
+        command: QualityCommands,
     },
 }
 
+#[derive(Subcommand, Debug)]
+pub enum QualityCommands {
+    #[command(about = "List the quality tests of a sample")]
+    List { id: i64 },
+    #[command(
+        about = "Record a quality test of a sample",
+        group(
+            clap::ArgGroup::new("measurement")
+                .required(true)
+                .multiple(true)
+                .args(&["fill", "purity"]),
+        ))]
+    Add {
+        #[arg(help = "The sample id")]
+        id: i64,
+        #[arg(long, help = "The date of the test (YYYY-MM-DD)")]
+        date: String,
+        #[arg(long, help = "The percentage of the seeds that are filled")]
+        fill: Option<f64>,
+        #[arg(
+            long,
+            requires = "fill",
+            help = "How the fill was determined: 'cut' or 'x-ray'"
+        )]
+        method: Option<FillMethod>,
+        #[arg(long, help = "The percentage of the sample's weight that is pure seed")]
+        purity: Option<f64>,
+        #[arg(long, help = "Who did the test")]
+        tester: Option<String>,
+    },
+    #[command(about = "Remove a quality test")]
+    Remove {
+        #[arg(help = "The id of the test")]
+        id: i64,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PermitCommands {
+    #[command(about = "List your collecting permits")]
+    List {},
+    #[command(about = "Add a collecting permit")]
+    Add {
+        #[arg(help = "The taxon id")]
+        tsn: i64,
+        #[arg(help = "The region that the permit is valid in, e.g. 'MN'")]
+        region: String,
+        #[arg(help = "The permit number")]
+        number: String,
+        #[arg(long, help = "The date that the permit expires (YYYY-MM-DD)")]
+        expires: Option<String>,
+        #[arg(short, long)]
+        notes: Option<String>,
+    },
+    #[command(about = "Remove a collecting permit")]
+    Remove { id: i64 },
+}
+
 #[derive(Subcommand, Debug)]
 pub enum TaxonomyCommands {
     #[command(about = "Find a taxon in the database")]
@@ -242,13 +863,34 @@ pub enum TaxonomyCommands {
         genus: Option<String>,
         #[arg(long, help = "Only show taxa in the given species")]
         species: Option<String>,
-        #[arg(long, help = "Show taxa with the given string in any field")]
+        #[arg(
+            long,
+            help = "Show taxa matching all of the given words in any field, e.g. 'asc tub' or 'A. tuberosa'"
+        )]
         any: Option<String>,
         #[arg(long, help = "Show only taxa found in Minnesota")]
         minnesota: bool,
     },
     #[command(about = "Show information about a taxon")]
     Show { id: i64 },
+    #[command(
+        about = "List germination codes",
+        after_help = "Germination codes describe how to treat the seeds of a taxon before sowing them."
+    )]
+    GerminationCodes {
+        #[arg(short, long, help = "Only show codes that contain this text")]
+        code: Option<String>,
+        #[arg(short, long, help = "Only show codes for the taxon with this id")]
+        taxon: Option<i64>,
+        #[arg(long, help = "Only show codes for the taxa of my samples")]
+        used_by_my_samples: bool,
+        #[arg(
+            short,
+            long,
+            help = "Sort by a comma-separated list of fields, e.g. '-taxa,code'. A field that starts with '-' is sorted in descending order. Fields: id, code, summary, taxa"
+        )]
+        sort: Option<SortSpecs<taxonomy::GerminationSort>>,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -270,6 +912,283 @@ pub enum AdminCommands {
         #[command(subcommand)]
         command: GerminationCommands,
     },
+    #[command(
+        about = "Manage seed weight reference data",
+        after_help = "The number of seeds per gram for a taxon is used to estimate the weight of a sample from its seed count and vice versa."
+    )]
+    #[clap(alias = "seed-weight")]
+    SeedWeights {
+        #[command(subcommand)]
+        command: SeedWeightCommands,
+    },
+    #[command(
+        about = "Manage conservation listings",
+        after_help = "States publish lists of species that are endangered, threatened or of special concern. Samples of listed taxa are flagged and may require a collecting permit."
+    )]
+    Conservation {
+        #[command(subcommand)]
+        command: ConservationCommands,
+    },
+    #[command(
+        about = "Share germination codes and native status with other databases",
+        after_help = "The germination codes and the native and invasive status of taxa can be exported to a JSON file and imported into another database. Taxa are identified by their ITIS TSN and germination codes by their code."
+    )]
+    TaxonData {
+        #[command(subcommand)]
+        command: TaxonDataCommands,
+    },
+    #[command(about = "Database maintenance")]
+    Database {
+        #[command(subcommand)]
+        command: DatabaseCommands,
+    },
+    #[command(
+        about = "Inspect the queue of outgoing emails",
+        after_help = "Emails sent by the web app are queued in the database and retried with an increasing delay when they can't be delivered. Messages that still can't be delivered after several attempts are marked as failed."
+    )]
+    MailQueue {
+        #[command(subcommand)]
+        command: MailQueueCommands,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum MailStatusFilter {
+    Pending,
+    Sent,
+    Failed,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MailQueueCommands {
+    #[command(about = "List the queued emails")]
+    List {
+        #[arg(long, help = "Only show emails with the given status")]
+        status: Option<MailStatusFilter>,
+    },
+    #[command(about = "Show details about a queued email, including the last delivery error")]
+    Show { id: i64 },
+    #[command(
+        about = "Queue an email to be sent again",
+        after_help = "The email is sent by the web app the next time it processes the mail queue, which happens every minute."
+    )]
+    Resend { id: i64 },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SeedWeightCommands {
+    #[command(about = "List all seed weight reference values")]
+    List {},
+    #[command(about = "Set the seed weight reference value for a taxon")]
+    Set {
+        #[arg(help = "The taxon id")]
+        tsn: i64,
+        seeds_per_gram: f64,
+        #[arg(long, short, help = "Where the reference value came from")]
+        source: Option<String>,
+    },
+    #[command(about = "Remove the seed weight reference value for a taxon")]
+    Remove {
+        #[arg(help = "The taxon id")]
+        tsn: i64,
+    },
+    #[command(
+        about = "Import seed weight reference values from a CSV file",
+        after_help = "The CSV file must have a header row with a 'taxon' column containing either the complete scientific name or the id of the taxon, and a 'seeds per gram' column. An optional 'source' column describes where the value came from. Existing values for a taxon are replaced."
+    )]
+    Import {
+        #[arg(help = "Path to a CSV file with a header row")]
+        file: PathBuf,
+        #[arg(long, help = "Check the file without adding anything to the database")]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConservationCommands {
+    #[command(about = "List the conservation listings")]
+    List {
+        #[arg(long, short, help = "Only list taxa listed in the given region")]
+        region: Option<String>,
+    },
+    #[command(
+        about = "Import the conservation listings of a region from a CSV file",
+        after_help = "The CSV file must have a header row with a 'taxon' column containing either the complete scientific name or the id of the taxon, and a 'status' column containing 'endangered', 'threatened' or 'special concern' (or the abbreviations 'E', 'T' and 'SC'). All existing listings of the region are replaced."
+    )]
+    Import {
+        #[arg(help = "Path to a CSV file with a header row")]
+        file: PathBuf,
+        #[arg(long, short, help = "The region of the list, e.g. 'MN'")]
+        region: String,
+        #[arg(long, help = "Check the file without adding anything to the database")]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TaxonDataCommands {
+    #[command(
+        about = "Export the germination codes and native status of taxa to a JSON file",
+        after_help = "The file includes the format version and the date of the export, and the complete name of each taxon so that taxa whose TSN refers to a different taxon in another database can be detected."
+    )]
+    Export {
+        #[arg(
+            help = "Path of the file to write. If not given, the data is written to standard output"
+        )]
+        file: Option<PathBuf>,
+    },
+    #[command(
+        about = "Import germination codes and native status of taxa from a JSON file",
+        after_help = "Imports a file written by 'export'. Germination codes are matched by their code and the codes of each taxon are added to the ones it already has. When a germination code or the status of a taxon differs from the one in this database, the existing value is kept and the conflict is reported unless --replace is given. Taxa that don't exist in this database or have a different name are skipped."
+    )]
+    Import {
+        #[arg(help = "Path to a file written by 'export'")]
+        file: PathBuf,
+        #[arg(
+            long,
+            help = "Replace existing values that differ from the imported ones"
+        )]
+        replace: bool,
+        #[arg(long, help = "Check the file without changing the database")]
+        dry_run: bool,
+    },
+}
+
+const ITIS_SOURCE_HELP: &str = "The ITIS database is downloaded from the ITIS site unless --bundle or --mirror is given. They can also be set permanently as \"bundle\" and \"mirror\" in the \"itis\" section of the seedctl config file. Downloads are cached, so the ITIS database is only downloaded again when a newer release is published, and an interrupted download is resumed. A download that fails falls back to the last bundle that was downloaded successfully. If --sha256 is not given, a download is checked against the checksum that the server publishes as <url>.sha256, if there is one. Before it is used, the ITIS database is checked for all of the tables and columns that seedctl needs.";
+
+#[derive(Args, Debug)]
+pub struct ItisSourceArgs {
+    #[arg(
+        long,
+        conflicts_with = "mirror",
+        help = "A local copy of the ITIS SQLite zip file, or of the database extracted from it"
+    )]
+    pub bundle: Option<PathBuf>,
+    #[arg(long, help = "A URL to download the ITIS SQLite zip file from")]
+    pub mirror: Option<String>,
+    #[arg(
+        long,
+        help = "The SHA-256 checksum that the ITIS zip file (or the database given with --bundle) must have"
+    )]
+    pub sha256: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DatabaseCommands {
+    #[command(
+        about = "Create a new database with the ITIS taxonomy and an administrator",
+        after_help = ITIS_SOURCE_HELP
+    )]
+    Init {
+        #[arg(help = "Path of the database to create")]
+        database: PathBuf,
+        #[command(flatten)]
+        source: ItisSourceArgs,
+        #[arg(long, help = "The username of the administrator")]
+        username: String,
+        #[arg(long, help = "Email address of the administrator")]
+        email: String,
+        #[arg(
+            long,
+            help = "Optional path to a file containing the administrator's password. If not given, you will be prompted for the password"
+        )]
+        passwordfile: Option<PathBuf>,
+    },
+    #[command(
+        about = "Upgrade the taxonomy to the current ITIS release",
+        after_help = ITIS_SOURCE_HELP
+    )]
+    UpgradeTaxonomy {
+        #[command(flatten)]
+        source: ItisSourceArgs,
+        #[arg(
+            short,
+            long,
+            help = "A description of the upgrade, e.g. the ITIS release. Defaults to the date of the release"
+        )]
+        label: Option<String>,
+    },
+    #[command(
+        about = "Recompute the taxonomic sort order and names",
+        after_help = "Rebuilds the taxonomic sort order from the ITIS hierarchy and regenerates the complete name of each taxon from its parts. This can be useful after modifying the taxonomy tables manually."
+    )]
+    ReindexTaxonomy,
+    #[command(
+        about = "Record the taxonomy before upgrading it",
+        after_help = "Saves the accepted plant taxa so that the taxonomy can be compared with them after the ITIS tables have been replaced by a newer release. Run 'record-taxonomy-upgrade' once the upgrade is done."
+    )]
+    SnapshotTaxonomy,
+    #[command(
+        about = "Record what changed in a taxonomy upgrade",
+        after_help = "Compares the upgraded taxonomy with the snapshot taken by 'snapshot-taxonomy' and saves the taxa that were added, removed, renamed or moved as an upgrade report. Administrators can view the report as a tree in the web interface. Run 'reindex-taxonomy' first so that the names of the new taxa are complete."
+    )]
+    RecordTaxonomyUpgrade {
+        #[arg(
+            short,
+            long,
+            help = "A description of the upgrade, e.g. the ITIS release"
+        )]
+        label: Option<String>,
+    },
+    #[command(
+        about = "Export all user data to a JSON file",
+        after_help = "Writes the users, organizations, sources, samples, trips, projects and permits in the database to a JSON file that can be restored into a different database with 'import-json'. Taxonomic data is not included. Records are identified by UUIDs that stay the same when they are exported again. Note that the file contains the password hashes of the users."
+    )]
+    ExportJson {
+        #[arg(
+            help = "Path of the file to write. If not given, the data is written to standard output"
+        )]
+        file: Option<PathBuf>,
+    },
+    #[command(
+        about = "Import user data from a JSON file",
+        after_help = "Restores the data from a file written by 'export-json'. The records are given new IDs in this database and references between them are preserved. Records that already exist in this database are left unchanged, so a file can safely be imported more than once. Users and organizations are matched by name if they were not imported before."
+    )]
+    ImportJson {
+        #[arg(help = "Path to a file written by 'export-json'")]
+        file: PathBuf,
+    },
+    #[command(
+        about = "Populate the database with randomized demo data",
+        after_help = "Adds sources, samples of random species from the taxonomy database and projects for a user. If no user is given, a new user is created and its name and password are printed. The same seed always generates the same data."
+    )]
+    SeedDemo {
+        #[arg(short, long, help = "The name of the user that the data is added for")]
+        user: Option<String>,
+        #[arg(
+            short,
+            long,
+            default_value_t = 40,
+            help = "The number of samples to add"
+        )]
+        samples: usize,
+        #[arg(
+            long,
+            help = "Seed for the random data. A random seed is used if not given"
+        )]
+        seed: Option<u64>,
+    },
+    #[command(
+        about = "Populate the database with a large generated dataset for performance testing",
+        after_help = "Adds new users with the given number of samples and proportional numbers of sources, projects and project notes, unless they are given explicitly. The data is inserted in a single transaction. Don't use this on a production database."
+    )]
+    GenerateLoad {
+        #[arg(short, long, help = "The number of samples to add")]
+        samples: usize,
+        #[arg(long)]
+        users: Option<usize>,
+        #[arg(long)]
+        sources: Option<usize>,
+        #[arg(long)]
+        projects: Option<usize>,
+        #[arg(long, help = "The number of notes about samples in projects")]
+        notes: Option<usize>,
+        #[arg(
+            long,
+            help = "Seed for the random data. A random seed is used if not given"
+        )]
+        seed: Option<u64>,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -299,7 +1218,7 @@ pub enum UserCommands {
             clap::ArgGroup::new("modify")
                 .required(true)
                 .multiple(true)
-                .args(&["username", "change_password"]),
+                .args(&["username", "change_password", "timezone", "admin", "number_style", "weight_unit"]),
         ))]
     #[clap(alias = "edit")]
     Modify {
@@ -316,6 +1235,60 @@ pub enum UserCommands {
             help = "Optional path to a file containing the new password. If not given, you will be prompted for your password"
         )]
         passwordfile: Option<PathBuf>,
+        #[arg(
+            long,
+            value_name = "NAME",
+            help = "The time zone that dates and times are displayed in for the user, e.g. 'Europe/Berlin'"
+        )]
+        timezone: Option<String>,
+        #[arg(
+            long,
+            value_name = "BOOL",
+            help = "Whether the user is a site administrator who can impersonate other users in the web app"
+        )]
+        admin: Option<bool>,
+        #[arg(
+            long,
+            value_name = "STYLE",
+            help = "How numbers are displayed for the user: 'point' (1,234.5), 'comma' (1.234,5) or 'space' (1 234,5)"
+        )]
+        number_style: Option<NumberStyle>,
+        #[arg(
+            long,
+            value_name = "UNIT",
+            help = "The unit that seed weights are displayed in for the user: 'grams' or 'ounces'"
+        )]
+        weight_unit: Option<WeightUnit>,
+    },
+    #[command(
+        about = "Create an API token for a user",
+        after_help = "An API token can be used to log in to seedctl or to authenticate with the web API instead of the user's password. The token is only displayed once, so make sure to save it somewhere safe."
+    )]
+    CreateToken {
+        #[arg(help = "The user id of the user to create a token for")]
+        id: i64,
+        #[arg(long, help = "A name describing what the token will be used for")]
+        name: String,
+    },
+    #[command(about = "List the API tokens for a user")]
+    ListTokens {
+        #[arg(help = "The user id of the user")]
+        id: i64,
+    },
+    #[command(about = "Revoke one of a user's API tokens")]
+    RevokeToken {
+        #[arg(help = "The user id of the user")]
+        id: i64,
+        #[arg(help = "The id of the token to revoke")]
+        token: i64,
+    },
+    #[command(
+        about = "Mark a user's email address as verified",
+        after_help = "This can be used when a user is unable to complete the verification by email, but their address has been confirmed by other means. Any outstanding verification codes for the user are invalidated."
+    )]
+    Verify {
+        #[arg(help = "The user id of the user to verify")]
+        id: i64,
     },
 }
 
@@ -1,19 +1,44 @@
 use std::{
     io::{stdin, stdout, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
 use crate::{
-    cli::{AdminCommands, GerminationCommands, UserCommands},
-    table::{GerminationRow, SeedctlTable, UserRow},
+    cli::{
+        AdminCommands, ConservationCommands, DatabaseCommands, GerminationCommands, ItisSourceArgs,
+        MailQueueCommands, MailStatusFilter, SeedWeightCommands, TaxonDataCommands, UserCommands,
+    },
+    config::ItisConfig,
+    itis::ItisSource,
+    prompt::{confirm, require_interactive},
+    table::{
+        GerminationRow, ListingRow, MailRow, MailRowFull, SeedWeightRow, SeedctlTable, TokenRow,
+        UserRow,
+    },
 };
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use libseed::{
+    conservation::{self, Listing, ListingStatus},
+    dump::Dump,
     loadable::Loadable,
-    taxonomy::Germination,
-    user::{User, UserStatus},
+    mailqueue::{MailStatus, QueuedMail},
+    taxonomy::{
+        self,
+        exchange::{ConflictPolicy, TaxonDataExport},
+        import::{self, TaxaMatcher},
+        itis::ItisRelease,
+        upgrade::{TaxonChange, UpgradeReport},
+        Germination, SeedWeight, Taxon,
+    },
+    testing::{
+        self,
+        load::{self, LoadSize},
+        Rng, UserFactory,
+    },
+    timezone::{self, TimeZone},
+    user::{verification, User, UserStatus},
 };
-use sqlx::{Pool, Sqlite};
+use sqlx::{sqlite::SqliteConnectOptions, Pool, Sqlite, SqlitePool};
 use tabled::Table;
 use tokio::fs;
 use tracing::debug;
@@ -21,6 +46,7 @@ use tracing::debug;
 async fn get_password(path: Option<PathBuf>, message: Option<String>) -> anyhow::Result<String> {
     let password = match path {
         None => {
+            require_interactive("A password file")?;
             /* read from stdin*/
             let mut s = String::new();
             print!("{}", message.unwrap_or("New password: ".to_string()));
@@ -33,11 +59,232 @@ async fn get_password(path: Option<PathBuf>, message: Option<String>) -> anyhow:
     Ok(password.trim().to_string())
 }
 
+async fn import_seed_weights(file: &Path, dry_run: bool, dbpool: &Pool<Sqlite>) -> Result<()> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_path(file)?;
+    let headers = reader.headers()?.clone();
+    let column = |names: &[&str]| import::find_column(&headers, names);
+    let taxoncol =
+        column(&import::TAXON_COLUMN_NAMES).ok_or_else(|| anyhow!("No 'taxon' column found"))?;
+    let weightcol = column(&["seeds per gram", "seeds/g", "seeds per g", "seedspergram"])
+        .ok_or_else(|| anyhow!("No 'seeds per gram' column found"))?;
+    let sourcecol = column(&["source", "reference"]);
+
+    let mut taxa = TaxaMatcher::new();
+    let mut imported = 0;
+    let mut errors = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        // row 1 is the header
+        let row = i + 2;
+        let record = match record {
+            Ok(r) => r,
+            Err(e) => {
+                errors.push(format!("Row {row}: {e}"));
+                continue;
+            }
+        };
+        let taxon = record.get(taxoncol).unwrap_or_default();
+        let tsn = match taxa.resolve(taxon, dbpool).await {
+            Ok(tsn) => tsn,
+            Err(e) => {
+                errors.push(format!("Row {row}: {e}"));
+                continue;
+            }
+        };
+        let value = record.get(weightcol).unwrap_or_default();
+        let seeds_per_gram = match value.replace(',', "").parse::<f64>() {
+            Ok(v) if v.is_finite() && v > 0.0 => v,
+            _ => {
+                errors.push(format!("Row {row}: Invalid seeds per gram '{value}'"));
+                continue;
+            }
+        };
+        let weight = SeedWeight {
+            tsn,
+            seeds_per_gram,
+            source: sourcecol
+                .and_then(|c| record.get(c))
+                .filter(|s| !s.is_empty())
+                .map(String::from),
+        };
+        if !dry_run {
+            weight.save(dbpool).await?;
+        }
+        imported += 1;
+    }
+
+    for e in &errors {
+        println!("{e}");
+    }
+    match dry_run {
+        true => println!(
+            "{imported} seed weights can be imported, {} rows have errors",
+            errors.len()
+        ),
+        false => println!(
+            "Imported {imported} seed weights, skipped {} rows",
+            errors.len()
+        ),
+    }
+    Ok(())
+}
+
+async fn import_listings(
+    file: &Path,
+    region: &str,
+    dry_run: bool,
+    dbpool: &Pool<Sqlite>,
+) -> Result<()> {
+    let region = conservation::normalize_region(region);
+    if region.is_empty() {
+        return Err(anyhow!("No region specified"));
+    }
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_path(file)?;
+    let headers = reader.headers()?.clone();
+    let column = |names: &[&str]| import::find_column(&headers, names);
+    let taxoncol =
+        column(&import::TAXON_COLUMN_NAMES).ok_or_else(|| anyhow!("No 'taxon' column found"))?;
+    let statuscol = column(&["status", "listing", "listing status", "state status"])
+        .ok_or_else(|| anyhow!("No 'status' column found"))?;
+
+    let mut taxa = TaxaMatcher::new();
+    let mut listings = Vec::new();
+    let mut errors = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        // row 1 is the header
+        let row = i + 2;
+        let record = match record {
+            Ok(r) => r,
+            Err(e) => {
+                errors.push(format!("Row {row}: {e}"));
+                continue;
+            }
+        };
+        let taxon = record.get(taxoncol).unwrap_or_default();
+        let tsn = match taxa.resolve(taxon, dbpool).await {
+            Ok(tsn) => tsn,
+            Err(e) => {
+                errors.push(format!("Row {row}: {e}"));
+                continue;
+            }
+        };
+        let value = record.get(statuscol).unwrap_or_default();
+        match value.parse::<ListingStatus>() {
+            Ok(status) => listings.push((tsn, status)),
+            Err(_) => errors.push(format!("Row {row}: Invalid listing status '{value}'")),
+        }
+    }
+
+    for e in &errors {
+        println!("{e}");
+    }
+    match dry_run {
+        true => println!(
+            "{} listings can be imported for {region}, {} rows have errors",
+            listings.len(),
+            errors.len()
+        ),
+        false => {
+            let n = Listing::replace_region(&region, &listings, dbpool).await?;
+            println!(
+                "Imported {n} listings for {region}, skipped {} rows",
+                errors.len()
+            )
+        }
+    }
+    Ok(())
+}
+
+/// Get the ITIS release from the given source and check that it can be installed
+async fn open_itis_release(
+    args: &ItisSourceArgs,
+    config: &ItisConfig,
+    dbpool: &Pool<Sqlite>,
+) -> Result<ItisRelease> {
+    let source = ItisSource::new(args.bundle.clone(), args.mirror.clone(), config);
+    let cache = xdg::BaseDirectories::new()?.create_cache_directory("seedctl/itis")?;
+    let path = source.fetch(&cache, args.sha256.as_deref()).await?;
+    let release = ItisRelease::open(&path, dbpool).await?;
+    println!(
+        "Using ITIS release from {} with {} plant taxa",
+        release.updated.as_deref().unwrap_or("an unknown date"),
+        release.taxa
+    );
+    Ok(release)
+}
+
+/// Create a new database at `database` that contains the ITIS taxonomy and a single
+/// administrator. This doesn't need a login, since there are no users yet.
+pub async fn init_database(
+    database: &Path,
+    source: &ItisSourceArgs,
+    username: &str,
+    email: &str,
+    passwordfile: Option<PathBuf>,
+    config: &ItisConfig,
+) -> Result<()> {
+    if database.exists() {
+        return Err(anyhow!("{} already exists", database.display()));
+    }
+    let password = get_password(
+        passwordfile,
+        Some(format!("New password for '{username}': ")),
+    )
+    .await?;
+    let dbpool = SqlitePool::connect_with(
+        SqliteConnectOptions::new()
+            .filename(database)
+            .create_if_missing(true),
+    )
+    .await
+    .with_context(|| format!("Unable to create {}", database.display()))?;
+    let res = async {
+        sqlx::migrate!("../db/migrations").run(&dbpool).await?;
+        let release = open_itis_release(source, config, &dbpool).await?;
+        let installed = release.install(&dbpool).await?;
+        println!("Installed {} taxa", installed.taxa);
+        let mut user = User::new(
+            username.to_string(),
+            email.to_string(),
+            User::hash_password(&password)?,
+            UserStatus::Verified,
+            None,
+            None,
+            None,
+        );
+        user.insert(&dbpool).await?;
+        user.admin = true;
+        user.update(&dbpool).await?;
+        anyhow::Ok(())
+    }
+    .await;
+    dbpool.close().await;
+    if res.is_err() {
+        // don't leave a database without a taxonomy behind, so that init can simply be run again
+        let _ = fs::remove_file(database).await;
+    }
+    res?;
+    println!("Created database {}", database.display());
+    println!(
+        "Log in with 'seedctl login --username {username} --database {}'",
+        database.display()
+    );
+    Ok(())
+}
+
 pub async fn handle_command(
     command: AdminCommands,
-    _user: User,
+    user: User,
+    itis: &ItisConfig,
     dbpool: &Pool<Sqlite>,
 ) -> Result<()> {
+    // timestamps are displayed in the time zone of the logged-in user
+    let tz = user.time_zone();
     match command {
         AdminCommands::Users { command } => match command {
             UserCommands::List {} => {
@@ -59,11 +306,13 @@ pub async fn handle_command(
                 .await?;
                 // hash the password
                 let pwhash = User::hash_password(&password)?;
+                // no verification email is sent for accounts that an administrator creates, and
+                // unverified users cannot log in
                 let mut user = User::new(
                     username.clone(),
                     email.clone(),
                     pwhash,
-                    UserStatus::Unverified,
+                    UserStatus::Verified,
                     None,
                     None,
                     None,
@@ -73,28 +322,42 @@ pub async fn handle_command(
                 println!("{}: {}", id, username);
                 Ok(())
             }
-            UserCommands::Remove { id } => {
-                match inquire::Confirm::new("Really remove user?")
-                    .with_default(false)
-                    .prompt()?
-                {
-                    true => User::delete_id(&id, dbpool)
-                        .await
-                        .map(|_| ())
-                        .with_context(|| "failed to remove user"),
-                    false => Ok(()),
-                }
-            }
+            UserCommands::Remove { id } => match confirm("Really remove user?")? {
+                true => User::delete_id(&id, dbpool)
+                    .await
+                    .map(|_| ())
+                    .with_context(|| "failed to remove user"),
+                false => Ok(()),
+            },
             UserCommands::Modify {
                 id,
                 username,
                 change_password,
                 passwordfile,
+                timezone,
+                admin,
+                number_style,
+                weight_unit,
             } => {
                 let mut user = User::load(id, dbpool).await?;
+                if let Some(admin) = admin {
+                    user.admin = admin;
+                }
+                if let Some(style) = number_style {
+                    user.number_style = style;
+                }
+                if let Some(unit) = weight_unit {
+                    user.weight_unit = unit;
+                }
                 if let Some(username) = username {
                     user.username = username;
                 }
+                if let Some(timezone) = timezone {
+                    user.timezone = match timezone.as_str() {
+                        timezone::UTC => None,
+                        name => Some(TimeZone::load(name)?.name().to_string()),
+                    };
+                }
                 if change_password {
                     let password = get_password(passwordfile, None).await?;
                     user.change_password(&password)?;
@@ -104,10 +367,46 @@ pub async fn handle_command(
                     .map(|_| ())
                     .with_context(|| "Failed to modify user")
             }
+            UserCommands::CreateToken { id, name } => {
+                let user = User::load(id, dbpool).await?;
+                let (token, tokenstr) = user.create_token(&name, dbpool).await?;
+                println!("Created token {} for user '{}':", token.id, user.username);
+                println!("{tokenstr}");
+                println!("This token will not be displayed again.");
+                Ok(())
+            }
+            UserCommands::ListTokens { id } => {
+                let user = User::load(id, dbpool).await?;
+                let tokens = user.load_tokens(dbpool).await?;
+                let mut table = Table::new(tokens.iter().map(|t| TokenRow::new(t, &tz)));
+                println!("{}\n", table.styled());
+                println!("{} records found", tokens.len());
+                Ok(())
+            }
+            UserCommands::RevokeToken { id, token } => {
+                let user = User::load(id, dbpool).await?;
+                user.revoke_token(token, dbpool)
+                    .await
+                    .with_context(|| format!("Failed to revoke token {token}"))?;
+                println!("Revoked token {token}");
+                Ok(())
+            }
+            UserCommands::Verify { id } => {
+                let user = User::load(id, dbpool)
+                    .await
+                    .with_context(|| format!("User {id} not found"))?;
+                if user.status == UserStatus::Verified {
+                    println!("User '{}' is already verified", user.username);
+                    return Ok(());
+                }
+                verification::mark_verified(id, dbpool).await?;
+                println!("Marked user '{}' as verified", user.username);
+                Ok(())
+            }
         },
         AdminCommands::Germination { command } => match command {
             GerminationCommands::List {} => {
-                let codes = Germination::load_all(dbpool).await?;
+                let codes = Germination::load_all(None, None, dbpool).await?;
                 let mut table = Table::new(codes.iter().map(GerminationRow::new));
                 println!("{}\n", table.styled());
                 Ok(())
@@ -121,6 +420,7 @@ pub async fn handle_command(
                 let oldval = Germination::load(id, dbpool).await?;
                 let mut newval = oldval.clone();
                 if code.is_none() && summary.is_none() && description.is_none() {
+                    require_interactive("The new germination code values")?;
                     println!("Modifying Germination code {id}. Pres <esc to skip any field.");
                     println!("Current code: '{}'", oldval.code);
                     if let Some(code) = inquire::Text::new("Code:").prompt_skippable()? {
@@ -170,5 +470,289 @@ pub async fn handle_command(
                 Ok(())
             }
         },
+        AdminCommands::SeedWeights { command } => match command {
+            SeedWeightCommands::List {} => {
+                let weights = SeedWeight::load_all(dbpool).await?;
+                let mut rows = Vec::new();
+                for w in &weights {
+                    rows.push(SeedWeightRow::new(w, dbpool).await?);
+                }
+                let mut table = Table::new(rows);
+                println!("{}\n", table.styled());
+                println!("{} records found", weights.len());
+                Ok(())
+            }
+            SeedWeightCommands::Set {
+                tsn,
+                seeds_per_gram,
+                source,
+            } => {
+                let taxon = Taxon::load(tsn, dbpool)
+                    .await
+                    .with_context(|| format!("Taxon {tsn} not found"))?;
+                SeedWeight {
+                    tsn,
+                    seeds_per_gram,
+                    source,
+                }
+                .save(dbpool)
+                .await?;
+                println!(
+                    "Set seed weight for {} to {seeds_per_gram} seeds per gram",
+                    taxon.complete_name
+                );
+                Ok(())
+            }
+            SeedWeightCommands::Remove { tsn } => {
+                if SeedWeight::delete(tsn, dbpool).await?.rows_affected() == 0 {
+                    return Err(anyhow!("No seed weight found for taxon {tsn}"));
+                }
+                println!("Removed seed weight for taxon {tsn}");
+                Ok(())
+            }
+            SeedWeightCommands::Import { file, dry_run } => {
+                import_seed_weights(&file, dry_run, dbpool).await
+            }
+        },
+        AdminCommands::Conservation { command } => match command {
+            ConservationCommands::List { region } => {
+                let listings = Listing::load_all(region.as_deref(), dbpool).await?;
+                let mut rows = Vec::new();
+                for l in &listings {
+                    rows.push(ListingRow::new(l, dbpool).await?);
+                }
+                let mut table = Table::new(rows);
+                println!("{}\n", table.styled());
+                println!("{} records found", listings.len());
+                Ok(())
+            }
+            ConservationCommands::Import {
+                file,
+                region,
+                dry_run,
+            } => import_listings(&file, &region, dry_run, dbpool).await,
+        },
+        AdminCommands::TaxonData { command } => match command {
+            TaxonDataCommands::Export { file } => {
+                let export = TaxonDataExport::export(dbpool).await?;
+                let json = serde_json::to_string_pretty(&export)?;
+                match file {
+                    Some(path) => {
+                        fs::write(&path, json)
+                            .await
+                            .with_context(|| format!("Failed to write {}", path.display()))?;
+                        println!(
+                            "Exported {} germination codes and the data of {} taxa to {}",
+                            export.germination_codes.len(),
+                            export.taxa.len(),
+                            path.display()
+                        );
+                    }
+                    None => println!("{json}"),
+                }
+                Ok(())
+            }
+            TaxonDataCommands::Import {
+                file,
+                replace,
+                dry_run,
+            } => {
+                let contents = fs::read_to_string(&file)
+                    .await
+                    .with_context(|| format!("Failed to read {}", file.display()))?;
+                let export: TaxonDataExport = serde_json::from_str(&contents)
+                    .with_context(|| format!("{} is not a valid export", file.display()))?;
+                let policy = match replace {
+                    true => ConflictPolicy::Replace,
+                    false => ConflictPolicy::Keep,
+                };
+                let report = export.import(policy, dry_run, dbpool).await?;
+                for msg in report.skipped.iter().chain(report.conflicts.iter()) {
+                    println!("{msg}");
+                }
+                let summary = format!(
+                    "{} records, {} already existed, replaced {}, {} conflicts, skipped {} taxa",
+                    report.inserted,
+                    report.existing,
+                    report.replaced,
+                    report.conflicts.len(),
+                    report.skipped.len()
+                );
+                match dry_run {
+                    true => println!("Would import {summary}"),
+                    false => println!("Imported {summary} (exported {})", export.exported),
+                }
+                Ok(())
+            }
+        },
+        AdminCommands::Database { command } => match command {
+            DatabaseCommands::ReindexTaxonomy => {
+                let reordered = taxonomy::ensure_taxonomic_order(dbpool).await?;
+                println!("Updated sort order for {reordered} taxa");
+                let renamed = taxonomy::refresh_complete_names(dbpool).await?;
+                println!("Updated complete name for {renamed} taxa");
+                Ok(())
+            }
+            DatabaseCommands::Init { .. } => {
+                Ok(()) // handled before logging in
+            }
+            DatabaseCommands::UpgradeTaxonomy { source, label } => {
+                let release = open_itis_release(&source, itis, dbpool).await?;
+                taxonomy::upgrade::snapshot(dbpool).await?;
+                let installed = release.install(dbpool).await?;
+                println!(
+                    "Installed {} taxa, {} taxa are no longer in ITIS",
+                    installed.taxa, installed.retired
+                );
+                let label = label.or_else(|| release.updated.map(|date| format!("ITIS {date}")));
+                let report = UpgradeReport::record(label.as_deref(), dbpool).await?;
+                println!(
+                    "Recorded upgrade {} with {} changed taxa",
+                    report.id, report.nchanges
+                );
+                Ok(())
+            }
+            DatabaseCommands::SnapshotTaxonomy => {
+                let taxa = taxonomy::upgrade::snapshot(dbpool).await?;
+                println!("Saved a snapshot of {taxa} taxa");
+                Ok(())
+            }
+            DatabaseCommands::RecordTaxonomyUpgrade { label } => {
+                let report = UpgradeReport::record(label.as_deref(), dbpool).await?;
+                let changes = report.changes(dbpool).await?;
+                let count = |f: fn(&TaxonChange) -> bool| changes.iter().filter(|c| f(c)).count();
+                println!(
+                    "Recorded upgrade {}: {} taxa added, {} removed, {} renamed and {} moved",
+                    report.id,
+                    count(TaxonChange::is_added),
+                    count(TaxonChange::is_removed),
+                    count(TaxonChange::is_renamed),
+                    count(TaxonChange::is_moved)
+                );
+                Ok(())
+            }
+            DatabaseCommands::ExportJson { file } => {
+                let dump = Dump::export(dbpool).await?;
+                let json = serde_json::to_string_pretty(&dump)?;
+                match file {
+                    Some(path) => {
+                        fs::write(&path, json)
+                            .await
+                            .with_context(|| format!("Failed to write {}", path.display()))?;
+                        println!(
+                            "Exported {} users, {} sources, {} samples and {} projects to {}",
+                            dump.users.len(),
+                            dump.sources.len(),
+                            dump.samples.len(),
+                            dump.projects.len(),
+                            path.display()
+                        );
+                    }
+                    None => println!("{json}"),
+                }
+                Ok(())
+            }
+            DatabaseCommands::ImportJson { file } => {
+                let contents = fs::read_to_string(&file)
+                    .await
+                    .with_context(|| format!("Failed to read {}", file.display()))?;
+                let dump: Dump = serde_json::from_str(&contents)
+                    .with_context(|| format!("{} is not a valid export", file.display()))?;
+                let stats = dump.import(dbpool).await?;
+                println!(
+                    "Imported {} records, {} already existed",
+                    stats.inserted, stats.existing
+                );
+                Ok(())
+            }
+            DatabaseCommands::SeedDemo {
+                user,
+                samples,
+                seed,
+            } => {
+                let seed = seed.unwrap_or_else(|| Rng::from_entropy().next_u64());
+                let user = match user {
+                    Some(username) => User::load_by_username(&username, dbpool)
+                        .await?
+                        .ok_or_else(|| anyhow!("User '{username}' not found"))?,
+                    None => {
+                        let user = UserFactory::new().seed(seed).insert(dbpool).await?;
+                        println!(
+                            "Added user '{}' with password '{}'",
+                            user.username,
+                            testing::DEFAULT_PASSWORD
+                        );
+                        user
+                    }
+                };
+                let populated = testing::populate(user.id, samples, seed, dbpool).await?;
+                println!(
+                    "Added {} sources, {} samples and {} projects for user '{}' (seed {seed})",
+                    populated.sources, populated.samples, populated.projects, user.username
+                );
+                Ok(())
+            }
+            DatabaseCommands::GenerateLoad {
+                samples,
+                users,
+                sources,
+                projects,
+                notes,
+                seed,
+            } => {
+                let defaults = LoadSize::samples(samples);
+                let size = LoadSize {
+                    users: users.unwrap_or(defaults.users),
+                    sources: sources.unwrap_or(defaults.sources),
+                    samples,
+                    projects: projects.unwrap_or(defaults.projects),
+                    notes: notes.unwrap_or(defaults.notes),
+                };
+                let seed = seed.unwrap_or_else(|| Rng::from_entropy().next_u64());
+                let generated = load::generate(&size, seed, dbpool).await?;
+                println!(
+                    "Added {} users, {} sources, {} samples, {} projects and {} notes (seed {seed})",
+                    generated.users,
+                    generated.sources,
+                    generated.samples,
+                    generated.projects,
+                    generated.notes
+                );
+                Ok(())
+            }
+        },
+        AdminCommands::MailQueue { command } => match command {
+            MailQueueCommands::List { status } => {
+                let status = status.map(|s| match s {
+                    MailStatusFilter::Pending => MailStatus::Pending,
+                    MailStatusFilter::Sent => MailStatus::Sent,
+                    MailStatusFilter::Failed => MailStatus::Failed,
+                });
+                let mails = QueuedMail::load_all(status, dbpool).await?;
+                let mut table = Table::new(mails.iter().map(|m| MailRow::new(m, &tz)));
+                println!("{}\n", table.styled());
+                println!("{} records found", mails.len());
+                Ok(())
+            }
+            MailQueueCommands::Show { id } => {
+                let mail = QueuedMail::load(id, dbpool)
+                    .await
+                    .with_context(|| format!("Email {id} not found"))?;
+                let tbuilder = Table::builder(vec![MailRowFull::new(&mail, &tz)])
+                    .index()
+                    .column(0)
+                    .transpose();
+                println!("{}\n", tbuilder.build().styled());
+                Ok(())
+            }
+            MailQueueCommands::Resend { id } => {
+                let mut mail = QueuedMail::load(id, dbpool)
+                    .await
+                    .with_context(|| format!("Email {id} not found"))?;
+                mail.resend(dbpool).await?;
+                println!("Queued email {id} to {} to be sent again", mail.recipient);
+                Ok(())
+            }
+        },
     }
 }
@@ -1,4 +1,7 @@
 pub mod admin;
+pub mod notes;
 pub mod projects;
+pub mod reports;
 pub mod samples;
 pub mod sources;
+pub mod trips;
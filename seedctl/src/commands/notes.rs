@@ -0,0 +1,128 @@
+use crate::{
+    cli::NoteCommands,
+    table::{NoteRow, NoteTemplateRow, SeedctlTable},
+};
+use anyhow::{anyhow, Result};
+use libseed::{
+    filter::{CompoundFilter, Op},
+    notes, parse_date,
+    project::{self, allocation, template::NoteTemplate, Note},
+    scope::UserScope,
+    user::User,
+};
+use sqlx::{Pool, Sqlite};
+use tabled::Table;
+
+pub async fn handle_command(
+    command: NoteCommands,
+    user: User,
+    dbpool: &Pool<Sqlite>,
+) -> Result<()> {
+    match command {
+        NoteCommands::List {
+            text,
+            source,
+            kind,
+            from,
+            to,
+            project,
+            sample,
+            taxon,
+        } => {
+            let mut fbuilder = CompoundFilter::builder(Op::And);
+            if let Some(text) = text {
+                fbuilder = fbuilder.push(notes::Filter::TextLike(text));
+            }
+            if let Some(source) = source {
+                fbuilder = fbuilder.push(notes::Filter::Source(source));
+            }
+            if let Some(kind) = kind {
+                fbuilder = fbuilder.push(notes::Filter::Type(kind));
+            }
+            if let Some(from) = from {
+                fbuilder = fbuilder.push(notes::Filter::DateFrom(parse_date(&from)?));
+            }
+            if let Some(to) = to {
+                fbuilder = fbuilder.push(notes::Filter::DateTo(parse_date(&to)?));
+            }
+            if let Some(project) = project {
+                fbuilder = fbuilder.push(notes::Filter::ProjectId(project));
+            }
+            if let Some(sample) = sample {
+                fbuilder = fbuilder.push(notes::Filter::SampleId(sample));
+            }
+            if let Some(taxon) = taxon {
+                fbuilder = fbuilder.push(notes::Filter::AncestorTsn(taxon));
+            }
+            let notes = UserScope::new(user.id)
+                .notes(Some(fbuilder.build()), None, dbpool)
+                .await?;
+            let mut table = Table::new(notes.iter().map(NoteRow::new));
+            println!("{}\n", table.styled());
+            println!("{} records found", notes.len());
+            Ok(())
+        }
+        NoteCommands::Add {
+            allocation,
+            template,
+            kind,
+            summary,
+            details,
+            date,
+        } => {
+            let scope = UserScope::new(user.id);
+            let alloc = scope
+                .allocations(
+                    Some(allocation::Filter::Id(allocation).into()),
+                    None,
+                    dbpool,
+                )
+                .await?
+                .pop()
+                .ok_or_else(|| anyhow!("Allocation {allocation} not found"))?;
+            let date = match date {
+                Some(date) => parse_date(&date)?,
+                None => user.time_zone().now().date(),
+            };
+            let mut note = match template {
+                Some(name) => NoteTemplate::load_by_name(alloc.project.id, &name, dbpool)
+                    .await?
+                    .note(alloc.id, date),
+                None => Note::new(
+                    alloc.id,
+                    date,
+                    kind.ok_or_else(|| anyhow!("The note needs a type"))?,
+                    String::new(),
+                    None,
+                ),
+            };
+            if let Some(kind) = kind {
+                note.kind = kind;
+            }
+            if let Some(summary) = summary {
+                note.summary = summary;
+            }
+            if details.is_some() {
+                note.details = details;
+            }
+            let note = note.insert(dbpool).await?;
+            println!(
+                "Added note {} about sample {} in project '{}'",
+                note.id, alloc.sample.id, alloc.project.name
+            );
+            Ok(())
+        }
+        NoteCommands::Templates { project: id } => {
+            let project = UserScope::new(user.id)
+                .projects(Some(project::Filter::Id(id).into()), dbpool)
+                .await?
+                .pop()
+                .ok_or_else(|| anyhow!("Project {id} not found"))?;
+            let templates = NoteTemplate::load_project(project.id, dbpool).await?;
+            let mut table = Table::new(templates.iter().map(NoteTemplateRow::new));
+            println!("{}\n", table.styled());
+            println!("{} records found", templates.len());
+            Ok(())
+        }
+    }
+}
@@ -2,12 +2,9 @@ use crate::{
     cli::ProjectCommands,
     table::{AllocationRow, AllocationRowFull, ProjectRow, SeedctlTable},
 };
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use libseed::{
-    loadable::{ExternalRef, Loadable},
-    project::Project,
-    user::User,
-    Error::DatabaseRowNotFound,
+    loadable::Loadable, project::Project, scope::UserScope, user::User, Error::DatabaseRowNotFound,
 };
 use sqlx::{Pool, Sqlite};
 use tabled::Table;
@@ -19,7 +16,7 @@ pub async fn handle_command(
 ) -> Result<()> {
     match command {
         ProjectCommands::List {} => {
-            let projects = Project::load_all(None, dbpool).await?;
+            let projects = UserScope::new(user.id).projects(None, dbpool).await?;
             let mut table = Table::new(projects.iter().map(ProjectRow::new));
             println!("{}\n", table.styled());
             println!("{} records found", projects.len());
@@ -60,10 +57,20 @@ pub async fn handle_command(
         }
         ProjectCommands::AddSample { project, sample } => {
             let mut project = Project::load(project, dbpool).await?;
-            project
-                .allocate_sample(ExternalRef::Stub(sample), dbpool)
-                .await?;
-            println!("Added sample to project");
+            let result = project.allocate_samples(&sample, dbpool).await;
+            for id in result.succeeded() {
+                println!("Added sample {id} to project {}", project.id);
+            }
+            for (id, e) in result.failed() {
+                eprintln!("Failed to add sample {id}: {e}");
+            }
+            if result.has_failures() {
+                return Err(anyhow!(
+                    "{} of {} samples could not be added",
+                    result.n_failed(),
+                    result.items.len()
+                ));
+            }
             Ok(())
         }
         ProjectCommands::RemoveSample { project, sample } => {
@@ -98,9 +105,8 @@ pub async fn handle_command(
                 println!("{} records found", projectinfo.allocations.len());
                 Ok(())
             }
-            Err(DatabaseRowNotFound(_)) => {
-                println!("Project {id} not found");
-                Ok(())
+            Err(e @ DatabaseRowNotFound(_)) => {
+                Err(anyhow::Error::from(e).context(format!("Project {id} not found")))
             }
             Err(e) => Err(e.into()),
         },
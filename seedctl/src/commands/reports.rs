@@ -0,0 +1,86 @@
+use crate::{
+    cli::ReportCommands,
+    table::{ReportRow, SeedctlTable},
+};
+use anyhow::{anyhow, Context, Result};
+use libseed::{
+    loadable::Loadable,
+    report::{self, Report},
+    user::User,
+    Error::DatabaseRowNotFound,
+};
+use sqlx::{Pool, Sqlite};
+use tabled::Table;
+
+/// Load a report, making sure that it belongs to the given user
+async fn load_report(id: i64, userid: i64, dbpool: &Pool<Sqlite>) -> Result<Report> {
+    match Report::load(id, dbpool).await {
+        Ok(report) if report.userid == userid => Ok(report),
+        Ok(_) => Err(anyhow!("Report {id} belongs to a different user")),
+        Err(e @ DatabaseRowNotFound(_)) => {
+            Err(anyhow::Error::from(e).context(format!("Report {id} not found")))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn handle_command(
+    command: ReportCommands,
+    user: User,
+    dbpool: &Pool<Sqlite>,
+) -> Result<()> {
+    match command {
+        ReportCommands::List {} => {
+            let reports =
+                Report::load_all(Some(report::Filter::User(user.id).into()), dbpool).await?;
+            let mut table = Table::new(reports.iter().map(ReportRow::new));
+            println!("{}\n", table.styled());
+            println!("{} records found", reports.len());
+            Ok(())
+        }
+        ReportCommands::Show { id } => {
+            let report = load_report(id, user.id, dbpool).await?;
+            println!("Report {}: {} ({})", report.id, report.name, report.format);
+            if let Some(filter) = &report.filter {
+                println!("Filter: {filter}");
+            }
+            println!("\n{}", report.template);
+            Ok(())
+        }
+        ReportCommands::Add {
+            name,
+            template,
+            format,
+            filter,
+        } => {
+            let contents = tokio::fs::read_to_string(&template)
+                .await
+                .with_context(|| format!("Failed to read template {}", template.display()))?;
+            let mut report = Report::new(name, format, filter, contents, user.id);
+            report.insert(dbpool).await?;
+            println!("Added report to database:");
+            println!("{}: {} ({})", report.id, report.name, report.format);
+            Ok(())
+        }
+        ReportCommands::Remove { id } => {
+            let mut report = load_report(id, user.id, dbpool).await?;
+            report.delete(dbpool).await?;
+            println!("Removed report {id}");
+            Ok(())
+        }
+        ReportCommands::Run { id, output } => {
+            let report = load_report(id, user.id, dbpool).await?;
+            let contents = report.render(dbpool).await?;
+            match output {
+                Some(path) => {
+                    tokio::fs::write(&path, contents)
+                        .await
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    println!("Wrote report to {}", path.display());
+                }
+                None => print!("{contents}"),
+            }
+            Ok(())
+        }
+    }
+}
@@ -1,18 +1,271 @@
 use crate::{
-    cli::{SampleCommands, SampleSortField},
-    prompt::{SourceIdPrompt, TaxonIdPrompt},
-    table::{SampleRow, SampleRowDetails, SampleRowFull, SeedctlTable},
+    cli::{PermitCommands, PurchaseArgs, QualityCommands, SampleCommands},
+    commands::trips::load_trip,
+    import::MappingProfile,
+    prompt::{require_interactive, SourceIdPrompt, TaxonIdPrompt},
+    table::{
+        ForecastRow, PermitRow, QualityTestRow, SampleFlagRow, SampleRow, SampleRowDetails,
+        SampleRowFull, SeedctlTable, SiteMatchRow,
+    },
 };
 use anyhow::{anyhow, Result};
 use libseed::{
+    bulk::BulkResult,
+    conservation::{self, Permit, PermitPolicy},
     filter::{CompoundFilter, Op},
+    forecast, history,
+    import::ImportRecord,
     loadable::{ExternalRef, Loadable},
-    sample::{self, Certainty, Sample},
+    preferences::Preferences,
+    quality::QualityTest,
+    sample::{self, Certainty, Purchase, Sample, SampleFlag},
+    sitematch::PlantingSite,
+    source::Source,
+    taxonomy::{self, import::TaxaMatcher, Taxon},
     user::User,
     Error::{AuthUserNotFound, DatabaseRowNotFound},
 };
 use sqlx::{Pool, Sqlite};
+use std::{collections::HashMap, path::Path};
 use tabled::Table;
+use time::Date;
+
+/// The text of the packet label for a sample
+fn label_text(sample: &Sample) -> Result<String> {
+    let taxon = sample.taxon.object()?;
+    let mut lines = vec![
+        format!("S{:04}", sample.id),
+        match sample.certainty {
+            Certainty::Uncertain => format!("{} (?)", taxon.complete_name),
+            Certainty::Certain => taxon.complete_name.clone(),
+        },
+    ];
+    if let Some(name) = taxon.vernaculars.first() {
+        lines.push(name.clone());
+    }
+    lines.push(match &sample.purchase {
+        Some(Purchase {
+            vendor,
+            lot: Some(lot),
+            ..
+        }) => format!("{vendor}, lot {lot}"),
+        Some(purchase) => purchase.vendor.clone(),
+        None => sample.source.object()?.name.clone(),
+    });
+    let date = match (sample.month, sample.year) {
+        (Some(m), Some(y)) => Some(format!("{m}/{y}")),
+        (None, Some(y)) => Some(y.to_string()),
+        _ => None,
+    };
+    let quantity = sample.quantity.map(|q| format!("qty {q}"));
+    let details: Vec<String> = date.into_iter().chain(quantity).collect();
+    if !details.is_empty() {
+        lines.push(details.join(" - "));
+    }
+    Ok(lines.join("\n"))
+}
+
+/// Check whether the user may add a sample of the given taxon according to their permit policy.
+/// With the 'warn' policy, a warning is printed for each listing that the user has no permit for,
+/// but the sample may still be added. A warning is also printed if the taxon is invasive in the
+/// user's region.
+async fn check_taxon(userid: i64, tsn: i64, today: Date, dbpool: &Pool<Sqlite>) -> Result<()> {
+    let prefs = Preferences::load(userid, dbpool).await?;
+    if let Some(status) = Taxon::load(tsn, dbpool)
+        .await?
+        .invasive_in(prefs.region.as_deref())
+    {
+        eprintln!(
+            "Warning: taxon {tsn} is listed as a {} in {}",
+            status.to_string().to_lowercase(),
+            taxonomy::STATUS_REGION
+        );
+    }
+    if prefs.permit_policy == PermitPolicy::Ignore {
+        return Ok(());
+    }
+    let unpermitted =
+        conservation::unpermitted_listings(userid, tsn, prefs.region.as_deref(), today, dbpool)
+            .await?;
+    if prefs.permit_policy == PermitPolicy::Warn {
+        for listing in &unpermitted {
+            eprintln!(
+                "Warning: taxon {tsn} is listed as {} in {} and you have no permit to collect it",
+                listing.status.to_string().to_lowercase(),
+                listing.region
+            );
+        }
+    }
+    prefs.permit_policy.check(&unpermitted)?;
+    Ok(())
+}
+
+async fn import_samples(
+    file: &Path,
+    profile: Option<String>,
+    save_profile: Option<String>,
+    dry_run: bool,
+    userid: i64,
+    today: Date,
+    dbpool: &Pool<Sqlite>,
+) -> Result<()> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_path(file)?;
+    let headers = reader.headers()?.clone();
+    let mut mapping = match profile {
+        Some(ref name) => MappingProfile::load(name).await?,
+        None => MappingProfile::default(),
+    };
+    // ask about any columns that aren't covered by the profile
+    mapping.prompt(&headers)?;
+    if let Some(name) = save_profile {
+        mapping.save(&name).await?;
+        println!("Saved import profile '{name}'");
+    }
+
+    let sources: HashMap<String, i64> = Source::load_all_user(userid, dbpool)
+        .await?
+        .into_iter()
+        .map(|src| (src.name.to_lowercase(), src.id))
+        .collect();
+    let mut importer = RowImporter {
+        mapping,
+        headers,
+        sources,
+        taxa: TaxaMatcher::new(),
+        userid,
+        today,
+        dry_run,
+    };
+    let mut result = BulkResult::new();
+    for (i, record) in reader.records().enumerate() {
+        // row 1 is the header
+        let row = i + 2;
+        let outcome = importer.import(record, dbpool).await;
+        result.record(row, outcome);
+    }
+
+    for (row, e) in result.failed() {
+        println!("Row {row}: {e}");
+    }
+    match dry_run {
+        true => println!(
+            "{} samples can be imported, {} rows have errors",
+            result.n_succeeded(),
+            result.n_failed()
+        ),
+        false => println!(
+            "Imported {} samples, skipped {} rows",
+            result.n_succeeded(),
+            result.n_failed()
+        ),
+    }
+    Ok(())
+}
+
+/// Adds a sample for each row of a CSV file. A row that can't be imported is skipped without
+/// affecting the rest of the file.
+struct RowImporter {
+    mapping: MappingProfile,
+    headers: csv::StringRecord,
+    /// the ids of the user's sources by their lowercase name, including the sources that were
+    /// added by the import
+    sources: HashMap<String, i64>,
+    taxa: TaxaMatcher,
+    userid: i64,
+    today: Date,
+    dry_run: bool,
+}
+
+impl RowImporter {
+    async fn import(
+        &mut self,
+        record: csv::Result<csv::StringRecord>,
+        dbpool: &Pool<Sqlite>,
+    ) -> Result<()> {
+        let ImportRecord {
+            taxon,
+            source,
+            month,
+            year,
+            quantity,
+            notes,
+            uncertain,
+        } = self
+            .mapping
+            .map_record(self.headers.iter(), record?.iter())?;
+        let taxonid = self.taxa.resolve(&taxon, dbpool).await?;
+        check_taxon(self.userid, taxonid, self.today, dbpool).await?;
+        let sourceid = match self.sources.get(&source.to_lowercase()) {
+            Some(id) => *id,
+            None if self.dry_run => -1,
+            None => {
+                let mut src = Source::new(source.clone(), None, None, None, self.userid);
+                src.insert(dbpool).await?;
+                println!("Added source {}: '{}'", src.id, src.name);
+                self.sources.insert(source.to_lowercase(), src.id);
+                src.id
+            }
+        };
+        let certainty = match uncertain {
+            true => Certainty::Uncertain,
+            false => Certainty::Certain,
+        };
+        let mut sample = Sample::new(
+            taxonid,
+            self.userid,
+            sourceid,
+            month,
+            year,
+            quantity,
+            notes,
+            certainty,
+        );
+        if !self.dry_run {
+            sample.insert(dbpool).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Apply the purchase details from the command line to the existing purchase details of a sample.
+/// A vendor is required unless the sample was already purchased.
+fn apply_purchase(existing: Option<Purchase>, args: PurchaseArgs) -> Result<Option<Purchase>> {
+    if args.is_empty() {
+        return Ok(existing);
+    }
+    let mut purchase = match (existing, args.vendor) {
+        (Some(p), Some(vendor)) => Purchase { vendor, ..p },
+        (None, Some(vendor)) => Purchase::new(vendor),
+        (Some(p), None) => p,
+        (None, None) => return Err(anyhow!("A vendor is required for purchased samples")),
+    };
+    if let Some(lot) = args.lot {
+        purchase.lot = Some(lot);
+    }
+    if let Some(date) = args.purchase_date {
+        purchase.date = Some(Purchase::parse_date(&date)?);
+    }
+    if let Some(price) = args.price {
+        purchase.price = Some(price);
+    }
+    if let Some(origin) = args.certified_origin {
+        purchase.certified_origin = Some(origin);
+    }
+    Ok(Some(purchase))
+}
+
+async fn load_sample(id: i64, dbpool: &Pool<Sqlite>) -> Result<Sample> {
+    match Sample::load(id, dbpool).await {
+        Ok(sample) => Ok(sample),
+        Err(e @ DatabaseRowNotFound(_)) => {
+            Err(anyhow::Error::from(e).context(format!("Sample {id} not found")))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
 
 pub async fn handle_command(
     command: SampleCommands,
@@ -25,24 +278,35 @@ pub async fn handle_command(
             user: useronly,
             limit,
             sort,
+            family,
+            order,
+            purchased,
+            collected,
         } => {
-            let filter = limit.map(|s| {
-                let fbuilder = CompoundFilter::builder(Op::Or)
-                    .push(sample::Filter::TaxonNameLike(s.clone()))
-                    .push(sample::Filter::SourceNameLike(s.clone()))
-                    .push(sample::Filter::Notes(libseed::filter::Cmp::Like, s.clone()));
-                fbuilder.build()
-            });
-            let sort = sort.map(|v| match v {
-                SampleSortField::Id => sample::Sort::Id,
-                SampleSortField::Taxon => sample::Sort::TaxonSequence,
-                SampleSortField::Name => sample::Sort::TaxonName,
-                SampleSortField::Source => sample::Sort::SourceName,
-            });
-            let samples = match useronly {
-                true => Sample::load_all_user(user.id, filter, sort, dbpool).await?,
-                false => Sample::load_all(filter, sort, dbpool).await?,
-            };
+            let mut fbuilder = CompoundFilter::builder(Op::And);
+            if let Some(s) = limit {
+                fbuilder = fbuilder.push(
+                    CompoundFilter::builder(Op::Or)
+                        .push(sample::Filter::TaxonNameLike(s.clone()))
+                        .push(sample::Filter::SourceNameLike(s.clone()))
+                        .push(sample::Filter::Notes(libseed::filter::Cmp::Like, s.clone()))
+                        .build(),
+                );
+            }
+            if let Some(family) = family {
+                fbuilder = fbuilder.push(sample::Filter::Family(family));
+            }
+            if let Some(order) = order {
+                fbuilder = fbuilder.push(sample::Filter::Order(order));
+            }
+            if useronly {
+                fbuilder = fbuilder.push(sample::Filter::UserId(user.id));
+            }
+            if purchased || collected {
+                fbuilder = fbuilder.push(sample::Filter::Purchased(purchased));
+            }
+            let filter = Some(fbuilder.build());
+            let samples = Sample::load_all_user(user.id, filter, sort, dbpool).await?;
             let mut table = match full {
                 true => Table::new(
                     samples
@@ -57,17 +321,17 @@ pub async fn handle_command(
         }
         SampleCommands::Show { id } => match Sample::load(id, dbpool).await {
             Ok(mut sample) => {
-                let tbuilder =
-                    Table::builder(vec![SampleRowDetails::new(&mut sample, dbpool).await?])
-                        .index()
-                        .column(0)
-                        .transpose();
+                let tbuilder = Table::builder(vec![
+                    SampleRowDetails::new(&mut sample, &user, dbpool).await?,
+                ])
+                .index()
+                .column(0)
+                .transpose();
                 println!("{}\n", tbuilder.build().styled());
                 Ok(())
             }
-            Err(DatabaseRowNotFound(_)) => {
-                println!("Sample {id} not found");
-                Ok(())
+            Err(e @ DatabaseRowNotFound(_)) => {
+                Err(anyhow::Error::from(e).context(format!("Sample {id} not found")))
             }
             Err(e) => Err(e.into()),
         },
@@ -79,7 +343,11 @@ pub async fn handle_command(
             quantity,
             notes,
             uncertain,
+            certain,
             userid,
+            no_defaults,
+            purchase,
+            trip,
         } => {
             let userid = match userid {
                 Some(id) => {
@@ -88,6 +356,11 @@ pub async fn handle_command(
                 }
                 None => user.id,
             };
+            let prefs = match no_defaults {
+                true => Preferences::new(userid),
+                false => Preferences::load(userid, dbpool).await?,
+            };
+            let (default_month, default_year) = prefs.default_date(&user.time_zone());
             let mut sample = if taxon.is_none()
                 && source.is_none()
                 && month.is_none()
@@ -95,15 +368,37 @@ pub async fn handle_command(
                 && quantity.is_none()
                 && notes.is_none()
                 && !uncertain
+                && !certain
+                && purchase.is_empty()
             {
+                require_interactive("The sample details")?;
                 let taxon = TaxonIdPrompt::new("Taxon:", dbpool).prompt()?;
-                let source = SourceIdPrompt::new("Source:", userid, dbpool).prompt()?;
-                let month = inquire::CustomType::<u32>::new("Month:").prompt_skippable()?;
-                let year = inquire::CustomType::<u32>::new("Year:").prompt_skippable()?;
+                let default_source = match prefs.default_source {
+                    Some(srcid) => Source::load(srcid, dbpool)
+                        .await
+                        .map(|src| format!("{}. {}", src.id, src.name))
+                        .ok(),
+                    None => None,
+                };
+                let mut source_prompt = SourceIdPrompt::new("Source:", userid, dbpool);
+                if let Some(ref initial) = default_source {
+                    source_prompt = source_prompt.with_initial_value(initial);
+                }
+                let source = source_prompt.prompt()?;
+                let mut month_prompt = inquire::CustomType::<u32>::new("Month:");
+                if let Some(m) = default_month {
+                    month_prompt = month_prompt.with_default(m);
+                }
+                let month = month_prompt.prompt_skippable()?;
+                let mut year_prompt = inquire::CustomType::<u32>::new("Year:");
+                if let Some(y) = default_year {
+                    year_prompt = year_prompt.with_default(y);
+                }
+                let year = year_prompt.prompt_skippable()?;
                 let quantity = inquire::CustomType::<i64>::new("Quantity:").prompt_skippable()?;
                 let notes = inquire::Text::new("Notes:").prompt_skippable()?;
                 let certainty = match inquire::Confirm::new("Uncertain ID?")
-                    .with_default(false)
+                    .with_default(prefs.default_certainty == Certainty::Uncertain)
                     .prompt()?
                 {
                     true => Certainty::Uncertain,
@@ -121,14 +416,22 @@ pub async fn handle_command(
                     taxon, userid, source, month, year, quantity, notes, certainty,
                 )
             } else {
-                let certainty = match uncertain {
-                    true => Certainty::Uncertain,
-                    _ => Certainty::Certain,
+                let certainty = match (uncertain, certain) {
+                    (true, _) => Certainty::Uncertain,
+                    (_, true) => Certainty::Certain,
+                    _ => prefs.default_certainty,
+                };
+                // only fill in the date when neither part of it was given explicitly
+                let (month, year) = match (month, year) {
+                    (None, None) => (default_month, default_year),
+                    date => date,
                 };
                 Sample::new(
                     taxon.ok_or_else(|| anyhow!("Taxon not specified"))?,
                     userid,
-                    source.ok_or(anyhow!("No source ID provided"))?,
+                    source
+                        .or(prefs.default_source)
+                        .ok_or(anyhow!("No source ID provided"))?,
                     month,
                     year,
                     quantity,
@@ -136,6 +439,17 @@ pub async fn handle_command(
                     certainty,
                 )
             };
+            sample.purchase = apply_purchase(None, purchase)?;
+            if let Some(trip) = trip {
+                sample.trip = Some(load_trip(trip, userid, dbpool).await?.id);
+            }
+            check_taxon(
+                userid,
+                sample.taxon.id(),
+                user.time_zone().now().date(),
+                dbpool,
+            )
+            .await?;
             let newid = sample.insert(dbpool).await?.last_insert_rowid();
             println!("Added sample {newid} to database");
             Ok(())
@@ -144,6 +458,315 @@ pub async fn handle_command(
             Sample::delete_id(&id, dbpool).await?;
             Ok(())
         }
+        SampleCommands::Flag { id, reason } => {
+            let sample = load_sample(id, dbpool).await?;
+            let reason = match reason {
+                Some(reason) => reason,
+                None => {
+                    require_interactive("The reason for flagging the sample")?;
+                    const OTHER: &str = "Other...";
+                    let mut options = SampleFlag::COMMON_REASONS.to_vec();
+                    options.push(OTHER);
+                    match inquire::Select::new("Why does this sample need review?", options)
+                        .prompt()?
+                    {
+                        OTHER => inquire::Text::new("Reason:").prompt()?,
+                        reason => reason.to_string(),
+                    }
+                }
+            };
+            let flag = sample.flag(&reason, dbpool).await?;
+            println!("Flagged sample {id}: {}", flag.reason);
+            Ok(())
+        }
+        SampleCommands::Unflag { id, reason } => {
+            let sample = load_sample(id, dbpool).await?;
+            let removed = match reason {
+                Some(reason) => {
+                    let mut removed = 0;
+                    for flag in sample.load_flags(dbpool).await? {
+                        if flag.reason.eq_ignore_ascii_case(reason.trim()) {
+                            removed += flag.delete(dbpool).await?.rows_affected();
+                        }
+                    }
+                    removed
+                }
+                None => sample.clear_flags(dbpool).await?.rows_affected(),
+            };
+            println!("Removed {removed} flags from sample {id}");
+            Ok(())
+        }
+        SampleCommands::Flagged { reason } => {
+            let samples = Sample::load_all_user(
+                user.id,
+                Some(sample::Filter::Flagged(reason.clone()).into()),
+                None,
+                dbpool,
+            )
+            .await?;
+            let flags = SampleFlag::load_all_user(user.id, dbpool).await?;
+            let tz = user.time_zone();
+            let mut rows = Vec::new();
+            for sample in &samples {
+                for flag in flags.iter().filter(|f| {
+                    f.sampleid == sample.id && reason.as_ref().is_none_or(|r| *r == f.reason)
+                }) {
+                    rows.push(SampleFlagRow::new(sample, flag, &tz)?);
+                }
+            }
+            let mut table = Table::new(rows);
+            println!("{}\n", table.styled());
+            println!("{} samples need review", samples.len());
+            Ok(())
+        }
+        SampleCommands::Labels { dry_run } => {
+            let samples = Sample::load_all_user(
+                user.id,
+                Some(sample::Filter::LabelPending.into()),
+                Some(sample::Sort::Id.into()),
+                dbpool,
+            )
+            .await?;
+            for sample in &samples {
+                println!("{}\n", label_text(sample)?);
+            }
+            if !dry_run {
+                let ids: Vec<i64> = samples.iter().map(|s| s.id).collect();
+                let n = Sample::mark_labels_printed(&ids, dbpool).await?;
+                eprintln!("Marked {n} labels as printed");
+            }
+            Ok(())
+        }
+        SampleCommands::Forecast { include_invasive } => {
+            let prefs = Preferences::load(user.id, dbpool).await?;
+            let years = prefs.collection_year()?;
+            let include_invasive =
+                include_invasive || !taxonomy::status_applies_to(prefs.region.as_deref());
+            let forecasts = forecast::load(user.id, &years, include_invasive, dbpool).await?;
+            let rows = forecasts.iter().map(|f| ForecastRow::new(f, &years));
+            let mut table = Table::new(rows);
+            println!("{}\n", table.styled());
+            println!("{} records found", forecasts.len());
+            Ok(())
+        }
+        SampleCommands::Match {
+            latitude,
+            longitude,
+            habitat,
+            moisture,
+            light,
+            limit,
+        } => {
+            let site = PlantingSite {
+                latitude,
+                longitude,
+                habitat,
+                moisture,
+                light,
+            };
+            let matches = site.recommend(user.id, limit, dbpool).await?;
+            let rows = matches
+                .iter()
+                .map(SiteMatchRow::new)
+                .collect::<Result<Vec<_>>>()?;
+            let mut table = Table::new(rows);
+            println!("{}\n", table.styled());
+            println!("{} records found", matches.len());
+            Ok(())
+        }
+        SampleCommands::Defaults {
+            source,
+            clear_source,
+            uncertain,
+            current_date,
+            year_start,
+            region,
+            permit_policy,
+        } => {
+            let mut prefs = Preferences::load(user.id, dbpool).await?;
+            if source.is_some()
+                || clear_source
+                || uncertain.is_some()
+                || current_date.is_some()
+                || year_start.is_some()
+                || region.is_some()
+                || permit_policy.is_some()
+            {
+                if let Some(srcid) = source {
+                    let src = Source::load(srcid, dbpool).await?;
+                    if src.userid != user.id {
+                        return Err(anyhow!("Source {srcid} belongs to a different user"));
+                    }
+                    prefs.default_source = Some(srcid);
+                }
+                if clear_source {
+                    prefs.default_source = None;
+                }
+                if let Some(uncertain) = uncertain {
+                    prefs.default_certainty = match uncertain {
+                        true => Certainty::Uncertain,
+                        false => Certainty::Certain,
+                    };
+                }
+                if let Some(current_date) = current_date {
+                    prefs.default_date_current = current_date;
+                }
+                if let Some(year_start) = year_start {
+                    prefs.year_start_month = year_start;
+                }
+                if let Some(region) = region {
+                    prefs.region = Some(region);
+                }
+                if let Some(permit_policy) = permit_policy {
+                    prefs.permit_policy = permit_policy;
+                }
+                prefs.save(dbpool).await?;
+            }
+            let source = match prefs.default_source {
+                Some(srcid) => {
+                    let src = Source::load(srcid, dbpool).await?;
+                    format!("{}. {}", src.id, src.name)
+                }
+                None => "none".to_string(),
+            };
+            println!("Default source: {source}");
+            println!("Default certainty: {:?}", prefs.default_certainty);
+            println!(
+                "Default to current date: {}",
+                if prefs.default_date_current {
+                    "yes"
+                } else {
+                    "no"
+                }
+            );
+            println!(
+                "Collection year starts in: {}",
+                prefs.collection_year()?.start_month()
+            );
+            println!(
+                "Region: {}",
+                prefs
+                    .region
+                    .as_deref()
+                    .unwrap_or("none (all listings apply)")
+            );
+            println!("Permit policy: {}", prefs.permit_policy);
+            Ok(())
+        }
+        SampleCommands::Permits { command } => match command {
+            PermitCommands::List {} => {
+                let permits = Permit::load_all_user(user.id, dbpool).await?;
+                let mut rows = Vec::new();
+                for p in &permits {
+                    rows.push(PermitRow::new(p, dbpool).await?);
+                }
+                let mut table = Table::new(rows);
+                println!("{}\n", table.styled());
+                println!("{} records found", permits.len());
+                Ok(())
+            }
+            PermitCommands::Add {
+                tsn,
+                region,
+                number,
+                expires,
+                notes,
+            } => {
+                let taxon = Taxon::load(tsn, dbpool).await.map_err(|e| match e {
+                    DatabaseRowNotFound(_) => anyhow!("Taxon {tsn} not found"),
+                    e => e.into(),
+                })?;
+                let mut permit = Permit::new(user.id, taxon.id, &region, number);
+                permit.expires = expires.as_deref().map(libseed::parse_date).transpose()?;
+                permit.notes = notes;
+                permit.insert(dbpool).await?;
+                println!(
+                    "Added permit {} for {} in {}",
+                    permit.id, taxon.complete_name, permit.region
+                );
+                Ok(())
+            }
+            PermitCommands::Remove { id } => {
+                let permit = Permit::load(id, dbpool).await.map_err(|e| match e {
+                    DatabaseRowNotFound(_) => anyhow!("Permit {id} not found"),
+                    e => e.into(),
+                })?;
+                if permit.userid != user.id {
+                    return Err(anyhow!("Permit {id} belongs to a different user"));
+                }
+                permit.delete(dbpool).await?;
+                println!("Removed permit {id}");
+                Ok(())
+            }
+        },
+        SampleCommands::Quality { command } => match command {
+            QualityCommands::List { id } => {
+                let sample = load_sample(id, dbpool).await?;
+                let tests = QualityTest::load_sample(sample.id, dbpool).await?;
+                let mut table = Table::new(tests.iter().map(QualityTestRow::new));
+                println!("{}\n", table.styled());
+                println!("{} records found", tests.len());
+                if let (Some(latest), Some(quantity)) = (tests.first(), sample.quantity) {
+                    println!(
+                        "Usable quantity: {} of {quantity}",
+                        latest.usable_quantity(quantity)
+                    );
+                }
+                Ok(())
+            }
+            QualityCommands::Add {
+                id,
+                date,
+                fill,
+                method,
+                purity,
+                tester,
+            } => {
+                let sample = load_sample(id, dbpool).await?;
+                let mut test = QualityTest::new(sample.id, libseed::parse_date(&date)?);
+                test.fill = fill;
+                test.method = method;
+                test.purity = purity;
+                test.tester = tester;
+                test.insert(dbpool).await?;
+                println!("Added quality test {} to sample {id}", test.id);
+                Ok(())
+            }
+            QualityCommands::Remove { id } => {
+                let test = QualityTest::load(id, dbpool).await.map_err(|e| match e {
+                    DatabaseRowNotFound(_) => anyhow!("Quality test {id} not found"),
+                    e => e.into(),
+                })?;
+                test.delete(dbpool).await?;
+                println!("Removed quality test {id} from sample {}", test.sampleid);
+                Ok(())
+            }
+        },
+        SampleCommands::Import {
+            file,
+            profile,
+            save_profile,
+            dry_run,
+            userid,
+        } => {
+            let userid = match userid {
+                Some(id) => {
+                    let _ = User::load(id, dbpool).await.map_err(|_| AuthUserNotFound)?;
+                    id
+                }
+                None => user.id,
+            };
+            import_samples(
+                &file,
+                profile,
+                save_profile,
+                dry_run,
+                userid,
+                user.time_zone().now().date(),
+                dbpool,
+            )
+            .await
+        }
         SampleCommands::Modify {
             id,
             taxon,
@@ -154,6 +777,10 @@ pub async fn handle_command(
             notes,
             certain,
             uncertain,
+            purchase,
+            collected,
+            trip,
+            no_trip,
         } => {
             let oldsample = Sample::load(id, dbpool).await?;
             let mut sample = oldsample.clone();
@@ -165,7 +792,12 @@ pub async fn handle_command(
                 && notes.is_none()
                 && !certain
                 && !uncertain
+                && purchase.is_empty()
+                && !collected
+                && trip.is_none()
+                && !no_trip
             {
+                require_interactive("The new sample values")?;
                 println!("Interactively modifying sample {id}. Press <esc> to skip any field.");
                 let current = sample.taxon.object()?;
                 println!("Current taxon: {}. {}", current.id, current.complete_name);
@@ -279,9 +911,20 @@ pub async fn handle_command(
                     }
                     _ => (),
                 }
+                sample.purchase = match collected {
+                    true => None,
+                    false => apply_purchase(sample.purchase.take(), purchase)?,
+                };
+                if let Some(trip) = trip {
+                    sample.trip = Some(load_trip(trip, sample.user.id(), dbpool).await?.id);
+                }
+                if no_trip {
+                    sample.trip = None;
+                }
             }
             if oldsample != sample {
                 sample.update(dbpool).await?;
+                history::record(&oldsample, &sample, Some(user.id), dbpool).await?;
                 println!("Modified sample...");
             } else {
                 println!("Sample unchanged.")
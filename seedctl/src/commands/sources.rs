@@ -1,15 +1,19 @@
 use crate::{
     cli::SourceCommands,
-    table::{SeedctlTable, SourceRow, SourceRowFull},
+    prompt::{confirm, require_interactive},
+    table::{DuplicateSourceRow, NearbySourceRow, SeedctlTable, SourceRow, SourceRowFull},
 };
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use inquire::validator::Validation;
 use libseed::{
+    dedupe,
     filter::{Cmp, CompoundFilter, Op},
     loadable::Loadable,
-    source::{self, Source},
+    sample::{self, Sample},
+    scope::UserScope,
+    source::{self, OnDelete, Source},
     user::User,
-    Error::{AuthUserNotFound, DatabaseRowNotFound},
+    Error::{AuthUserNotFound, DatabaseRowNotFound, InvalidOperationObjectInUse},
 };
 use sqlx::{Pool, Sqlite};
 use tabled::Table;
@@ -20,14 +24,37 @@ pub async fn handle_command(
     dbpool: &Pool<Sqlite>,
 ) -> Result<()> {
     match command {
-        SourceCommands::List { full, filter } => {
-            let filter = filter.map(|f| {
-                CompoundFilter::builder(Op::Or)
-                    .push(source::Filter::Name(Cmp::Like, f.clone()))
-                    .push(source::Filter::Description(Cmp::Like, f.clone()))
-                    .build()
-            });
-            let sources = Source::load_all(filter, dbpool).await?;
+        SourceCommands::List {
+            full,
+            filter,
+            habitat,
+            moisture,
+            light,
+        } => {
+            let mut fbuilder = CompoundFilter::builder(Op::And);
+            if let Some(f) = filter {
+                fbuilder = fbuilder.push(
+                    CompoundFilter::builder(Op::Or)
+                        .push(source::Filter::Name(Cmp::Like, f.clone()))
+                        .push(source::Filter::Description(Cmp::Like, f.clone()))
+                        .build(),
+                );
+            }
+            if let Some(habitat) = habitat {
+                fbuilder = fbuilder.push(source::Filter::Habitat(habitat));
+            }
+            if let Some(moisture) = moisture {
+                fbuilder = fbuilder.push(source::Filter::Moisture(moisture));
+            }
+            if let Some(light) = light {
+                fbuilder = fbuilder.push(source::Filter::Light(light));
+            }
+            let mut sources = UserScope::new(user.id)
+                .sources(Some(fbuilder.build()), dbpool)
+                .await?;
+            for src in sources.iter_mut() {
+                src.reveal_for(user.id, dbpool).await?;
+            }
             let mut table = match full {
                 true => Table::new(sources.iter().map(SourceRowFull::new)),
                 false => Table::new(sources.iter().map(SourceRow::new)),
@@ -37,7 +64,8 @@ pub async fn handle_command(
             Ok(())
         }
         SourceCommands::Show { id } => match Source::load(id, dbpool).await {
-            Ok(src) => {
+            Ok(mut src) => {
+                src.reveal_for(user.id, dbpool).await?;
                 let tbuilder = Table::builder(vec![SourceRowFull::new(&src)])
                     .index()
                     .column(0)
@@ -45,17 +73,45 @@ pub async fn handle_command(
                 println!("{}\n", tbuilder.build().styled());
                 Ok(())
             }
-            Err(DatabaseRowNotFound(_)) => {
-                println!("Source {id} not found");
-                Ok(())
+            Err(e @ DatabaseRowNotFound(_)) => {
+                Err(anyhow::Error::from(e).context(format!("Source {id} not found")))
             }
             Err(e) => Err(e.into()),
         },
+        SourceCommands::Near {
+            latitude,
+            longitude,
+            radius,
+        } => {
+            let mut nearby = UserScope::new(user.id)
+                .sources_near(latitude, longitude, radius, None, dbpool)
+                .await?;
+            let mut rows = Vec::new();
+            for n in nearby.iter_mut() {
+                n.reveal_for(user.id, latitude, longitude, dbpool).await?;
+                let samples = Sample::load_all_user(
+                    user.id,
+                    Some(sample::Filter::SourceId(Cmp::Equal, n.source.id).into()),
+                    None,
+                    dbpool,
+                )
+                .await?;
+                rows.push(NearbySourceRow::new(n, &samples)?);
+            }
+            let mut table = Table::new(rows);
+            println!("{}\n", table.styled());
+            println!("{} records found", nearby.len());
+            Ok(())
+        }
         SourceCommands::Add {
             name,
             description,
             latitude,
             longitude,
+            habitat,
+            moisture,
+            light,
+            sensitive,
             userid,
         } => {
             let userid = match userid {
@@ -73,6 +129,7 @@ pub async fn handle_command(
                 && latitude.is_none()
                 && longitude.is_none()
             {
+                require_interactive("The source details")?;
                 let name = inquire::Text::new("Name:").prompt()?;
                 let description = inquire::Text::new("Description:").prompt_skippable()?;
                 let latitude = inquire::CustomType::<f64>::new("Latitude:")
@@ -113,24 +170,107 @@ pub async fn handle_command(
                     userid,
                 )
             };
+            source.habitat = habitat;
+            source.moisture = moisture;
+            source.light = light;
+            source.sensitive = sensitive;
 
             let newid = source.insert(dbpool).await?.last_insert_rowid();
             println!("Added source {newid} to database");
             Ok(())
         }
-        SourceCommands::Remove { id } => {
-            Source::delete_id(&id, dbpool).await?;
+        SourceCommands::Remove {
+            id,
+            reassign_to,
+            cascade,
+        } => {
+            let mut src = Source::load(id, dbpool).await?;
+            let nsamples = src.count_samples(dbpool).await?;
+            let on_delete = match (reassign_to, cascade) {
+                (Some(newid), _) => {
+                    // make sure the new source exists before moving anything
+                    let _ = Source::load(newid, dbpool)
+                        .await
+                        .with_context(|| format!("Source {newid} not found"))?;
+                    OnDelete::Reassign(newid)
+                }
+                (None, true) => {
+                    if nsamples > 0
+                        && !confirm(&format!(
+                            "Really remove source {id} along with its {nsamples} samples?"
+                        ))?
+                    {
+                        return Err(anyhow!("Aborted"));
+                    }
+                    OnDelete::Cascade
+                }
+                (None, false) => OnDelete::Restrict,
+            };
+            match src.delete_with(on_delete, dbpool).await {
+                Err(e @ InvalidOperationObjectInUse(_)) => {
+                    return Err(anyhow::Error::from(e).context(format!(
+                        "Source {id} still has samples. Use --reassign-to or --cascade to remove it"
+                    )))
+                }
+                res => res?,
+            };
+            match on_delete {
+                OnDelete::Reassign(newid) if nsamples > 0 => {
+                    println!("Moved {nsamples} samples to source {newid}")
+                }
+                OnDelete::Cascade if nsamples > 0 => println!("Removed {nsamples} samples"),
+                _ => (),
+            }
             println!("Removed source {id} from database");
             Ok(())
         }
+        SourceCommands::Dedupe { keep, merge } => match keep {
+            None => {
+                let candidates = UserScope::new(user.id).duplicate_sources(dbpool).await?;
+                let mut table = Table::new(candidates.iter().map(DuplicateSourceRow::new));
+                println!("{}\n", table.styled());
+                println!("{} possible duplicates found", candidates.len());
+                Ok(())
+            }
+            Some(keep) => {
+                let preview = dedupe::preview_merge(keep, &merge, dbpool).await?;
+                let names: Vec<String> = preview
+                    .remove
+                    .iter()
+                    .map(|src| format!("{} ({})", src.name, src.id))
+                    .collect();
+                println!("Merging into source {keep} '{}':", preview.keep.name);
+                println!(" - removes {}", names.join(", "));
+                println!(" - moves {} samples", preview.samples);
+                println!(" - moves the visits of {} trips", preview.trips);
+                if !confirm("Really merge these sources?")? {
+                    return Err(anyhow!("Aborted"));
+                }
+                let moved = dedupe::merge(keep, &merge, dbpool).await?;
+                println!("Moved {moved} samples to source {keep}");
+                println!("Removed {} sources from database", merge.len());
+                Ok(())
+            }
+        },
         SourceCommands::Modify {
             id,
             name,
             description,
             latitude,
             longitude,
+            habitat,
+            moisture,
+            light,
+            sensitive,
         } => {
-            if name.is_none() && description.is_none() && latitude.is_none() && longitude.is_none()
+            if name.is_none()
+                && description.is_none()
+                && latitude.is_none()
+                && longitude.is_none()
+                && habitat.is_none()
+                && moisture.is_none()
+                && light.is_none()
+                && sensitive.is_none()
             {
                 return Err(anyhow!("Cannot modify source without new values"));
             }
@@ -147,6 +287,18 @@ pub async fn handle_command(
             if let Some(longitude) = longitude {
                 src.longitude = Some(longitude);
             }
+            if habitat.is_some() {
+                src.habitat = habitat;
+            }
+            if moisture.is_some() {
+                src.moisture = moisture;
+            }
+            if light.is_some() {
+                src.light = light;
+            }
+            if let Some(sensitive) = sensitive {
+                src.sensitive = sensitive;
+            }
             src.update(dbpool).await?;
             println!("Modified source...");
             Ok(())
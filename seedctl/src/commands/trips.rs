@@ -0,0 +1,136 @@
+use crate::{
+    cli::TripCommands,
+    table::{SeedctlTable, TripRow, TripSourceRow, TripTaxonRow},
+};
+use anyhow::{anyhow, Result};
+use libseed::{
+    loadable::Loadable,
+    parse_date,
+    trip::{self, Trip},
+    user::User,
+    Error::DatabaseRowNotFound,
+};
+use sqlx::{Pool, Sqlite};
+use tabled::Table;
+
+/// Load a trip, making sure that it belongs to the given user
+pub async fn load_trip(id: i64, userid: i64, dbpool: &Pool<Sqlite>) -> Result<Trip> {
+    match Trip::load(id, dbpool).await {
+        Ok(trip) if trip.userid == userid => Ok(trip),
+        Ok(_) => Err(anyhow!("Trip {id} belongs to a different user")),
+        Err(e @ DatabaseRowNotFound(_)) => {
+            Err(anyhow::Error::from(e).context(format!("Trip {id} not found")))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn handle_command(
+    command: TripCommands,
+    user: User,
+    dbpool: &Pool<Sqlite>,
+) -> Result<()> {
+    match command {
+        TripCommands::List {} => {
+            let trips = Trip::load_all(Some(trip::Filter::User(user.id).into()), dbpool).await?;
+            let mut table = Table::new(trips.iter().map(TripRow::new));
+            println!("{}\n", table.styled());
+            println!("{} records found", trips.len());
+            Ok(())
+        }
+        TripCommands::Show { id } => {
+            let trip = load_trip(id, user.id, dbpool).await?;
+            let summary = trip.summary(dbpool).await?;
+            println!("Trip {}: {}", trip.id, trip.name);
+            println!("Date: {}", trip.date);
+            if !trip.participants.is_empty() {
+                println!("Participants: {}", trip.participants.join(", "));
+            }
+            if let Some(notes) = &trip.notes {
+                println!("Notes: {notes}");
+            }
+            println!(
+                "\nTarget taxa ({} of {} collected):",
+                summary.targets_collected(),
+                summary.target_taxa.len()
+            );
+            let mut table = Table::new(summary.target_taxa.iter().map(TripTaxonRow::new));
+            println!("{}\n", table.styled());
+            if !summary.other_taxa.is_empty() {
+                println!("Also collected:");
+                let mut table = Table::new(summary.other_taxa.iter().map(TripTaxonRow::new));
+                println!("{}\n", table.styled());
+            }
+            println!("Target sources:");
+            let mut table = Table::new(summary.target_sources.iter().map(TripSourceRow::new));
+            println!("{}\n", table.styled());
+            Ok(())
+        }
+        TripCommands::Add {
+            name,
+            date,
+            participants,
+            notes,
+        } => {
+            let mut trip = Trip::new(name, parse_date(&date)?, user.id);
+            trip.participants = participants;
+            trip.notes = notes;
+            trip.insert(dbpool).await?;
+            println!("Added trip to database:");
+            println!("{}: {} ({})", trip.id, trip.name, trip.date);
+            Ok(())
+        }
+        TripCommands::Modify {
+            id,
+            name,
+            date,
+            participants,
+            notes,
+        } => {
+            let mut trip = load_trip(id, user.id, dbpool).await?;
+            if let Some(name) = name {
+                trip.name = name;
+            }
+            if let Some(date) = date {
+                trip.date = parse_date(&date)?;
+            }
+            if !participants.is_empty() {
+                trip.participants = participants;
+            }
+            if let Some(notes) = notes {
+                trip.notes = Some(notes);
+            }
+            trip.update(dbpool).await?;
+            println!("Modified trip...");
+            Ok(())
+        }
+        TripCommands::Remove { id } => {
+            let mut trip = load_trip(id, user.id, dbpool).await?;
+            trip.delete(dbpool).await?;
+            println!("Removed trip {id}");
+            Ok(())
+        }
+        TripCommands::Target { id, taxon, source } => {
+            let trip = load_trip(id, user.id, dbpool).await?;
+            for tsn in taxon {
+                trip.add_target_taxon(tsn, dbpool).await?;
+            }
+            for srcid in source {
+                trip.add_target_source(srcid, dbpool).await?;
+            }
+            println!("Added targets to trip {id}");
+            Ok(())
+        }
+        TripCommands::Untarget { id, taxon, source } => {
+            let trip = load_trip(id, user.id, dbpool).await?;
+            for tsn in taxon {
+                trip.remove_target_taxon(tsn, dbpool).await?;
+            }
+            for srcid in source {
+                trip.remove_target_source(srcid, dbpool).await?;
+            }
+            println!("Removed targets from trip {id}");
+            Ok(())
+        }
+    }
+}
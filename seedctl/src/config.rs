@@ -1,7 +1,9 @@
+use crate::secrets::{self, StoredSecret};
 use libseed::user::User;
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Sqlite, SqlitePool};
 use std::{
+    collections::BTreeMap,
     os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
 };
@@ -11,17 +13,83 @@ use tokio::{
 };
 use tracing::debug;
 
-#[derive(Deserialize, Serialize)]
-pub struct Config {
+/// The name of the profile that is used for configurations that were saved before seedctl
+/// supported multiple profiles, and when logging in without specifying a profile
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// How a profile authenticates with the database
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMethod {
+    #[default]
+    Password,
+    Token,
+}
+
+/// The login details for a single database. The password or API token is not stored in the
+/// config file in plaintext, see [StoredSecret].
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Profile {
     pub username: String,
-    pub password: String,
+    #[serde(default)]
+    pub auth: AuthMethod,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<StoredSecret>,
+    /// a plaintext password written by an older version of seedctl
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    /// a plaintext API token written by an older version of seedctl
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
     pub database: PathBuf,
 }
 
+/// Where the ITIS database is downloaded from when creating a database or upgrading its
+/// taxonomy. If neither is set, it is downloaded from ITIS.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ItisConfig {
+    /// a URL to download the ITIS SQLite zip file from instead of the ITIS site
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mirror: Option<String>,
+    /// a local copy of the ITIS SQLite zip file, or of the database extracted from it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<PathBuf>,
+}
+
+impl ItisConfig {
+    pub fn is_empty(&self) -> bool {
+        self.mirror.is_none() && self.bundle.is_none()
+    }
+}
+
+/// The seedctl configuration, which can store logins for several databases as named profiles
+#[derive(Default, Deserialize, Serialize)]
+pub struct Config {
+    /// the profile that is used when no profile is specified on the command line
+    #[serde(default)]
+    pub current: Option<String>,
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(default, skip_serializing_if = "ItisConfig::is_empty")]
+    pub itis: ItisConfig,
+    /// the names of the built-in plugins to enable, see [libseed::plugin]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub plugins: Vec<String>,
+}
+
+/// Config files written by older versions of seedctl contain a single login at the top level
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ConfigFormat {
+    Profiles(Config),
+    Legacy(Profile),
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Not Logged in")]
     NotLoggedIn,
+    #[error("Profile '{0}' does not exist")]
+    ProfileNotFound(String),
     #[error("Failed to parse config file")]
     ConfigParseFailed(#[source] serde_json::Error),
     #[error("Incorrect username or password")]
@@ -36,11 +104,21 @@ pub enum Error {
     CannotFormatConfig(#[source] serde_json::Error),
     #[error("File permissions error for '{}': {1}", .0.to_string_lossy())]
     FilePermissions(PathBuf, &'static str, #[source] std::io::Error),
+    #[error("Unable to access the stored credentials")]
+    Secret(#[from] secrets::Error),
 }
 
 impl Config {
     fn parse(contents: String) -> Result<Self, serde_json::Error> {
-        serde_json::from_str(&contents)
+        Ok(match serde_json::from_str(&contents)? {
+            ConfigFormat::Profiles(config) => config,
+            ConfigFormat::Legacy(profile) => Config {
+                current: Some(DEFAULT_PROFILE.to_string()),
+                profiles: BTreeMap::from([(DEFAULT_PROFILE.to_string(), profile)]),
+                itis: ItisConfig::default(),
+                plugins: Vec::new(),
+            },
+        })
     }
 
     fn format(&self) -> Result<String, Error> {
@@ -75,25 +153,231 @@ impl Config {
             .map_err(|e| Error::FilePermissions(path.to_owned(), "Writing file", e))
     }
 
-    pub fn new(username: String, password: String, database: PathBuf) -> Self {
-        Config {
+    /// Look up the profile with the given name, or the current profile if no name is given
+    pub fn profile<'a>(&'a self, name: Option<&'a str>) -> Result<(&'a str, &'a Profile), Error> {
+        let name = name.or(self.current.as_deref()).ok_or(Error::NotLoggedIn)?;
+        self.profiles
+            .get(name)
+            .map(|profile| (name, profile))
+            .ok_or_else(|| Error::ProfileNotFound(name.to_string()))
+    }
+
+    /// Add or replace the profile with the given name and make it the current profile
+    pub fn set_profile(&mut self, name: String, profile: Profile) {
+        self.profiles.insert(name.clone(), profile);
+        self.current = Some(name);
+    }
+
+    /// Remove the profile with the given name. If it was the current profile, another remaining
+    /// profile becomes the current one.
+    pub fn remove_profile(&mut self, name: &str) -> Result<Profile, Error> {
+        let profile = self
+            .profiles
+            .remove(name)
+            .ok_or_else(|| Error::ProfileNotFound(name.to_string()))?;
+        if self.current.as_deref() == Some(name) {
+            self.current = self.profiles.keys().next().cloned();
+        }
+        Ok(profile)
+    }
+
+    pub fn switch_profile(&mut self, name: &str) -> Result<(), Error> {
+        if !self.profiles.contains_key(name) {
+            return Err(Error::ProfileNotFound(name.to_string()));
+        }
+        self.current = Some(name.to_string());
+        Ok(())
+    }
+}
+
+impl Profile {
+    pub fn new(username: String, auth: AuthMethod, database: PathBuf) -> Self {
+        Profile {
             username,
-            password,
+            auth,
+            secret: None,
+            password: None,
+            token: None,
             database,
         }
     }
 
+    /// The name of the keyring entry for the secret of the profile with the given name
+    fn account(&self, name: &str) -> String {
+        format!("{name}:{}", self.username)
+    }
+
+    /// Whether this profile still contains a plaintext secret from an older version of seedctl
+    pub fn has_plaintext_secret(&self) -> bool {
+        self.password.is_some() || self.token.is_some()
+    }
+
+    /// Store the password or token for the profile with the given name in secure storage
+    pub async fn store_secret(&mut self, name: &str, secret: &str) -> Result<(), Error> {
+        self.secret = Some(StoredSecret::store(&self.account(name), secret).await?);
+        self.password = None;
+        self.token = None;
+        Ok(())
+    }
+
+    /// Move a plaintext secret from an older version of seedctl to secure storage
+    pub async fn secure_plaintext_secret(&mut self, name: &str) -> Result<(), Error> {
+        let (auth, secret) = match (self.token.clone(), self.password.clone()) {
+            (Some(token), _) => (AuthMethod::Token, token),
+            (None, Some(password)) => (AuthMethod::Password, password),
+            (None, None) => return Ok(()),
+        };
+        self.store_secret(name, &secret).await?;
+        self.auth = auth;
+        Ok(())
+    }
+
+    /// Remove the stored secret for this profile, e.g. when logging out
+    pub async fn remove_secret(&self) -> Result<(), Error> {
+        if let Some(secret) = &self.secret {
+            secret.remove().await?;
+        }
+        Ok(())
+    }
+
+    async fn load_secret(&self) -> Result<(AuthMethod, String), Error> {
+        match (&self.token, &self.password, &self.secret) {
+            (Some(token), _, _) => Ok((AuthMethod::Token, token.clone())),
+            (None, Some(password), _) => Ok((AuthMethod::Password, password.clone())),
+            (None, None, Some(secret)) => Ok((self.auth, secret.retrieve().await?)),
+            (None, None, None) => Err(Error::NotLoggedIn),
+        }
+    }
+
+    /// Connect to the database and authenticate with the stored password or token
     pub async fn validate(&self) -> Result<(Pool<Sqlite>, User), Error> {
+        let (auth, secret) = self.load_secret().await?;
+        self.connect(auth, &secret).await
+    }
+
+    /// Connect to the database and authenticate with the given password or token
+    pub async fn connect(
+        &self,
+        auth: AuthMethod,
+        secret: &str,
+    ) -> Result<(Pool<Sqlite>, User), Error> {
         let dbpool = SqlitePool::connect(&format!("sqlite://{}", self.database.to_string_lossy()))
             .await
             .map_err(Error::DatabaseConnectionFailure)?;
         sqlx::migrate!("../db/migrations").run(&dbpool).await?;
-        let user = User::load_by_username(&self.username, &dbpool)
-            .await
-            .map_err(Error::Database)?
-            .ok_or_else(|| Error::LoginFailure)?;
-        user.verify_password(&self.password)
-            .map_err(|_| Error::LoginFailure)?;
+        let user = match auth {
+            AuthMethod::Token => User::load_by_token(secret, &dbpool)
+                .await
+                .map_err(Error::Database)?
+                .filter(|user| self.username.is_empty() || user.username == self.username)
+                .ok_or(Error::LoginFailure)?,
+            AuthMethod::Password => {
+                let user = User::load_by_username(&self.username, &dbpool)
+                    .await
+                    .map_err(Error::Database)?
+                    .ok_or(Error::LoginFailure)?;
+                user.verify_password(secret)
+                    .map_err(|_| Error::LoginFailure)?;
+                user
+            }
+        };
         Ok((dbpool, user))
     }
+
+    /// A description of where the password or token is stored
+    pub fn secret_storage(&self) -> &'static str {
+        match (&self.secret, self.has_plaintext_secret()) {
+            (_, true) => "plaintext in the config file",
+            (Some(secret), false) => secret.description(),
+            (None, false) => "not stored",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_parse_legacy() {
+        let config = Config::parse(
+            r#"{"username": "testuser", "password": "topsecret123", "database": "/tmp/seeds.sqlite"}"#
+                .to_string(),
+        )
+        .unwrap();
+        assert_eq!(config.current.as_deref(), Some(DEFAULT_PROFILE));
+        assert_eq!(config.profiles.len(), 1);
+        assert!(config.itis.is_empty());
+        assert!(config.plugins.is_empty());
+        let (name, profile) = config.profile(None).unwrap();
+        assert_eq!(name, DEFAULT_PROFILE);
+        assert_eq!(profile.username, "testuser");
+        assert_eq!(profile.database, PathBuf::from("/tmp/seeds.sqlite"));
+        assert!(profile.secret.is_none());
+        assert!(profile.has_plaintext_secret());
+        assert_eq!(profile.secret_storage(), "plaintext in the config file");
+        assert_eq!(
+            profile.load_secret().await.unwrap(),
+            (AuthMethod::Password, "topsecret123".to_string())
+        );
+
+        // a token takes precedence over the password
+        let config = Config::parse(
+            r#"{"username": "", "token": "abc123", "password": "x", "database": "seeds.sqlite"}"#
+                .to_string(),
+        )
+        .unwrap();
+        let (_, profile) = config.profile(Some(DEFAULT_PROFILE)).unwrap();
+        assert_eq!(
+            profile.load_secret().await.unwrap(),
+            (AuthMethod::Token, "abc123".to_string())
+        );
+
+        // once saved again, the config is written in the current format
+        let reparsed = Config::parse(config.format().unwrap()).unwrap();
+        assert_eq!(reparsed.current.as_deref(), Some(DEFAULT_PROFILE));
+        let (_, profile) = reparsed.profile(None).unwrap();
+        assert!(profile.has_plaintext_secret());
+        assert_eq!(profile.database, PathBuf::from("seeds.sqlite"));
+
+        assert!(Config::parse(r#"{"username": "testuser"}"#.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_parse_profiles() {
+        let config = Config::parse(
+            r#"{
+                "current": "work",
+                "profiles": {
+                    "home": {"username": "me", "database": "home.sqlite"},
+                    "work": {
+                        "username": "me",
+                        "auth": "token",
+                        "secret": {"storage": "keyring", "account": "work:me"},
+                        "database": "work.sqlite"
+                    }
+                },
+                "itis": {"mirror": "https://example.com/itis.zip"}
+            }"#
+            .to_string(),
+        )
+        .unwrap();
+        assert_eq!(config.profiles.len(), 2);
+        assert_eq!(
+            config.itis.mirror.as_deref(),
+            Some("https://example.com/itis.zip")
+        );
+        let (name, profile) = config.profile(None).unwrap();
+        assert_eq!(name, "work");
+        assert_eq!(profile.auth, AuthMethod::Token);
+        assert!(!profile.has_plaintext_secret());
+        assert_eq!(profile.secret_storage(), "system keyring");
+        let (_, home) = config.profile(Some("home")).unwrap();
+        assert_eq!(home.auth, AuthMethod::Password);
+        assert_eq!(home.secret_storage(), "not stored");
+        assert!(matches!(
+            config.profile(Some("other")),
+            Err(Error::ProfileNotFound(_))
+        ));
+    }
 }
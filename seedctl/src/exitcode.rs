@@ -0,0 +1,99 @@
+//! Exit codes for the different classes of failure, so that scripts can react to them. These are
+//! documented in the command-line help and must stay stable.
+use crate::{config, import, prompt, secrets};
+
+/// an unspecified failure. Invalid command line arguments exit with 2, which is handled by clap.
+pub const FAILURE: u8 = 1;
+/// not logged in, the login details are incorrect, or the profile does not exist
+pub const NOT_LOGGED_IN: u8 = 3;
+/// the command needs input that was not given and seedctl is not allowed to prompt for it
+pub const INPUT_REQUIRED: u8 = 4;
+/// the requested object does not exist
+pub const NOT_FOUND: u8 = 5;
+/// the database could not be opened, migrated or queried
+pub const DATABASE: u8 = 6;
+/// the given input was invalid
+pub const INVALID_INPUT: u8 = 7;
+
+/// The help text that documents the exit codes
+pub const HELP: &str = "Exit codes:
+  0  Success
+  1  Unspecified failure
+  2  Invalid command line arguments
+  3  Not logged in, incorrect login details, or unknown profile
+  4  Input is required but prompting is disabled (or no terminal is available)
+  5  The requested object was not found
+  6  Database error
+  7  Invalid input";
+
+fn libseed_code(err: &libseed::Error) -> u8 {
+    use libseed::Error::*;
+    match err {
+        AuthUserNotFound | DatabaseRowNotFound(_) => NOT_FOUND,
+        DatabaseUnspecified(_) | DatabaseVersionConflict(_) => DATABASE,
+        AuthInvalidUsernameTooShort
+        | AuthInvalidUsernameFirstCharacter
+        | AuthInvalidUsernameInvalidCharacters(_)
+        | InvalidValue(_) => INVALID_INPUT,
+        _ => FAILURE,
+    }
+}
+
+fn secrets_code(err: &secrets::Error) -> u8 {
+    match err {
+        secrets::Error::DecryptionFailed | secrets::Error::Keyring(_) => NOT_LOGGED_IN,
+        secrets::Error::Prompt(prompt::Error::InputRequired(_))
+        | secrets::Error::Prompt(prompt::Error::Prompt(inquire::InquireError::NotTTY)) => {
+            INPUT_REQUIRED
+        }
+        _ => FAILURE,
+    }
+}
+
+/// Determine the exit code for an error by looking for a recognized error anywhere in its chain
+pub fn from_error(err: &anyhow::Error) -> u8 {
+    for cause in err.chain() {
+        if let Some(e) = cause.downcast_ref::<config::Error>() {
+            return match e {
+                config::Error::NotLoggedIn
+                | config::Error::LoginFailure
+                | config::Error::ProfileNotFound(_) => NOT_LOGGED_IN,
+                config::Error::DatabaseConnectionFailure(_)
+                | config::Error::DatabaseMigrationFailure(_) => DATABASE,
+                config::Error::Database(e) => libseed_code(e),
+                config::Error::Secret(e) => secrets_code(e),
+                _ => FAILURE,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<secrets::Error>() {
+            return secrets_code(e);
+        }
+        if let Some(e) = cause.downcast_ref::<prompt::Error>() {
+            return match e {
+                prompt::Error::InputRequired(_)
+                | prompt::Error::Prompt(inquire::InquireError::NotTTY) => INPUT_REQUIRED,
+                _ => FAILURE,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<import::Error>() {
+            return match e {
+                import::Error::ProfileNotFound(_) | import::Error::Mapping(_) => INVALID_INPUT,
+                import::Error::Prompt(inquire::InquireError::NotTTY) => INPUT_REQUIRED,
+                _ => FAILURE,
+            };
+        }
+        if let Some(inquire::InquireError::NotTTY) = cause.downcast_ref::<inquire::InquireError>() {
+            return INPUT_REQUIRED;
+        }
+        if let Some(e) = cause.downcast_ref::<libseed::Error>() {
+            return libseed_code(e);
+        }
+        if let Some(e) = cause.downcast_ref::<sqlx::Error>() {
+            return match e {
+                sqlx::Error::RowNotFound => NOT_FOUND,
+                _ => DATABASE,
+            };
+        }
+    }
+    FAILURE
+}
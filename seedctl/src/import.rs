@@ -0,0 +1,100 @@
+//! Support for importing samples from spreadsheets exported by other seed collection tools. The
+//! columns of the spreadsheet are mapped to sample fields with a [MappingProfile], which can be
+//! saved and re-used for later imports from the same tool.
+use crate::prompt;
+use libseed::import::{ColumnMapping, MappingError, SampleField};
+use serde::{Deserialize, Serialize};
+use std::{ops::Deref, path::PathBuf};
+use tokio::fs;
+use tracing::debug;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Import profile '{0}' does not exist")]
+    ProfileNotFound(String),
+    #[error("Failed to read import profile '{0}'")]
+    ProfileParseFailed(String, #[source] serde_json::Error),
+    #[error("Failed to save import profile '{0}'")]
+    ProfileSaveFailed(String, #[source] std::io::Error),
+    #[error("Unable to determine the location of the import profiles")]
+    ProfileDirectory(#[from] xdg::BaseDirectoriesError),
+    #[error(transparent)]
+    Mapping(#[from] MappingError),
+    #[error(transparent)]
+    Prompt(#[from] inquire::InquireError),
+}
+
+/// A saved [ColumnMapping] that can be re-used for later imports from the same tool
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct MappingProfile(pub ColumnMapping);
+
+impl Deref for MappingProfile {
+    type Target = ColumnMapping;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+fn profile_path(name: &str) -> Result<PathBuf, Error> {
+    let xdgdirs = xdg::BaseDirectories::new()?;
+    xdgdirs
+        .place_config_file(format!("seedctl/import-profiles/{name}.json"))
+        .map_err(|e| Error::ProfileSaveFailed(name.to_string(), e))
+}
+
+impl MappingProfile {
+    pub async fn load(name: &str) -> Result<Self, Error> {
+        let path = profile_path(name)?;
+        debug!(?path, "Loading import profile");
+        let contents = fs::read_to_string(&path)
+            .await
+            .map_err(|_| Error::ProfileNotFound(name.to_string()))?;
+        serde_json::from_str(&contents).map_err(|e| Error::ProfileParseFailed(name.to_string(), e))
+    }
+
+    pub async fn save(&self, name: &str) -> Result<(), Error> {
+        let path = profile_path(name)?;
+        debug!(?path, "Saving import profile");
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| Error::ProfileParseFailed(name.to_string(), e))?;
+        fs::write(&path, contents)
+            .await
+            .map_err(|e| Error::ProfileSaveFailed(name.to_string(), e))
+    }
+
+    /// Interactively ask the user which field each column corresponds to. Columns that are already
+    /// present in the profile are not asked about again. When running non-interactively, columns
+    /// with a recognized name are mapped automatically and all other columns are ignored.
+    pub fn prompt(&mut self, headers: &csv::StringRecord) -> Result<(), Error> {
+        const IGNORE: &str = "(ignore this column)";
+        let mut options = vec![IGNORE.to_string()];
+        options.extend(SampleField::ALL.iter().map(|f| f.to_string()));
+        for header in headers.iter() {
+            if self.0.columns.contains_key(header) {
+                continue;
+            }
+            if prompt::is_non_interactive() {
+                if let Some(field) = SampleField::guess(header) {
+                    self.0.columns.insert(header.to_string(), field);
+                }
+                continue;
+            }
+            let cursor = SampleField::guess(header)
+                .and_then(|g| SampleField::ALL.iter().position(|f| *f == g))
+                .map(|pos| pos + 1)
+                .unwrap_or(0);
+            let answer = inquire::Select::new(
+                &format!("Which field does column '{header}' contain?"),
+                options.clone(),
+            )
+            .with_starting_cursor(cursor)
+            .prompt()?;
+            if let Some(field) = SampleField::ALL.iter().find(|f| f.to_string() == answer) {
+                self.0.columns.insert(header.to_string(), *field);
+            }
+        }
+        Ok(self.validate()?)
+    }
+}
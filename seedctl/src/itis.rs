@@ -0,0 +1,480 @@
+//! Locating a release of the ITIS database to install with `admin database init` and
+//! `admin database upgrade-taxonomy`. A release is downloaded from ITIS by default, but the
+//! download can come from a mirror instead, or a bundle that was downloaded earlier can be used
+//! when the ITIS site is not available.
+//!
+//! Downloads are cached: the last download is kept along with its ETag and modification date, so
+//! it is only downloaded again when a newer release is published, and an interrupted download is
+//! resumed with a range request. The bundle can be checked against a SHA-256 checksum before it
+//! is installed.
+use crate::config::ItisConfig;
+use anyhow::{anyhow, Context, Result};
+use reqwest::{
+    header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use tokio::task::spawn_blocking;
+
+/// The SQLite release of the ITIS database, published at https://www.itis.gov/downloads/
+pub const ITIS_DOWNLOAD_URL: &str = "https://www.itis.gov/downloads/itisSqlite.zip";
+
+/// The name of the downloaded bundle in the cache directory
+const BUNDLE_NAME: &str = "itisSqlite.zip";
+
+/// Where the ITIS release comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ItisSource {
+    /// a zip file as published by ITIS, or the SQLite database extracted from it
+    Bundle(PathBuf),
+    /// the URL of a zip file as published by ITIS
+    Download(String),
+}
+
+impl ItisSource {
+    /// Options given on the command line take precedence over the config file, which takes
+    /// precedence over the official ITIS download
+    pub fn new(bundle: Option<PathBuf>, mirror: Option<String>, config: &ItisConfig) -> Self {
+        match (bundle, mirror) {
+            (Some(bundle), _) => Self::Bundle(bundle),
+            (None, Some(mirror)) => Self::Download(mirror),
+            (None, None) => match (&config.bundle, &config.mirror) {
+                (Some(bundle), _) => Self::Bundle(bundle.clone()),
+                (None, Some(mirror)) => Self::Download(mirror.clone()),
+                (None, None) => Self::Download(ITIS_DOWNLOAD_URL.to_string()),
+            },
+        }
+    }
+
+    /// Get the SQLite database of the release, downloading and extracting it into `cache` if
+    /// necessary. A download that fails falls back to the bundle that was downloaded last time.
+    /// The bundle is checked against the `sha256` checksum if one is given, or against the
+    /// checksum that a mirror publishes next to the download.
+    pub async fn fetch(&self, cache: &Path, sha256: Option<&str>) -> Result<PathBuf> {
+        let (bundle, expected) = match self {
+            Self::Bundle(path) => {
+                if !path.is_file() {
+                    return Err(anyhow!("The ITIS bundle {} does not exist", path.display()));
+                }
+                (path.clone(), sha256.map(str::to_string))
+            }
+            Self::Download(url) => {
+                let path = cache.join(BUNDLE_NAME);
+                let bundle = match download(url, &path).await {
+                    Ok(()) => path,
+                    Err(e) if path.is_file() => {
+                        eprintln!(
+                            "Warning: unable to download {url}: {e:#}. Using the bundle downloaded earlier instead."
+                        );
+                        path
+                    }
+                    Err(e) => return Err(e),
+                };
+                let expected = match sha256 {
+                    Some(sha256) => Some(sha256.to_string()),
+                    None => published_checksum(url).await,
+                };
+                (bundle, expected)
+            }
+        };
+        if let Some(expected) = expected {
+            let path = bundle.clone();
+            let actual = spawn_blocking(move || checksum(&path)).await??;
+            if !actual.eq_ignore_ascii_case(expected.trim()) {
+                return Err(anyhow!(
+                    "The SHA-256 checksum of {} is {actual}, but {} was expected",
+                    bundle.display(),
+                    expected.trim()
+                ));
+            }
+            println!("Verified the SHA-256 checksum of {}", bundle.display());
+        }
+        let is_zip = bundle
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"));
+        if !is_zip {
+            return Ok(bundle);
+        }
+        let dest = cache.join("ITIS.sqlite");
+        let path = dest.clone();
+        spawn_blocking(move || extract(&bundle, &path)).await??;
+        Ok(dest)
+    }
+}
+
+/// What is known about a download in the cache directory. It is stored next to the download, so
+/// that a complete download is only replaced when the server has a newer release, and an
+/// interrupted download can be resumed.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+struct CacheEntry {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// the SHA-256 checksum of a complete download, taken when it was downloaded
+    sha256: Option<String>,
+}
+
+impl CacheEntry {
+    fn path(download: &Path) -> PathBuf {
+        let mut name = download.as_os_str().to_owned();
+        name.push(".json");
+        PathBuf::from(name)
+    }
+
+    /// The entry of the given download, if it was downloaded from `url`
+    fn load(download: &Path, url: &str) -> Option<Self> {
+        if !download.is_file() {
+            return None;
+        }
+        let json = std::fs::read_to_string(Self::path(download)).ok()?;
+        serde_json::from_str::<Self>(&json)
+            .ok()
+            .filter(|entry| entry.url == url)
+    }
+
+    fn save(&self, download: &Path) -> Result<()> {
+        std::fs::write(Self::path(download), serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    fn remove(download: &Path) {
+        let _ = std::fs::remove_file(Self::path(download));
+    }
+
+    /// The validator that identifies the version of the download on the server
+    fn validator(&self) -> Option<&str> {
+        self.etag.as_deref().or(self.last_modified.as_deref())
+    }
+}
+
+/// The SHA-256 checksum of a file as a hex string
+fn checksum(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// The checksum that is published next to a download as `<url>.sha256`, if there is one
+async fn published_checksum(url: &str) -> Option<String> {
+    let response = reqwest::get(format!("{url}.sha256"))
+        .await
+        .and_then(|r| r.error_for_status())
+        .ok()?;
+    let text = response.text().await.ok()?;
+    text.split_whitespace()
+        .next()
+        .filter(|sum| sum.len() == 64 && sum.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_string)
+}
+
+/// Download `url` to `path`. Nothing is downloaded if `path` is still the same version as the one
+/// on the server, and a download that was interrupted is resumed where it stopped.
+async fn download(url: &str, path: &Path) -> Result<()> {
+    // download to a separate file so that an interrupted download doesn't replace a good bundle
+    let partial = path.with_extension("part");
+    let client = reqwest::Client::new();
+    let mut request = client.get(url);
+    let cached = CacheEntry::load(path, url);
+    if let Some(entry) = &cached {
+        if let Some(etag) = &entry.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        } else if let Some(modified) = &entry.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, modified);
+        }
+    }
+    let resume = CacheEntry::load(&partial, url).and_then(|entry| {
+        let offset = partial.metadata().map(|m| m.len()).unwrap_or(0);
+        (offset > 0)
+            .then(|| entry.validator().map(|v| (v.to_string(), offset)))
+            .flatten()
+    });
+    if let Some((validator, offset)) = &resume {
+        // the server only sends the rest of the file if it hasn't changed in the meantime
+        request = request
+            .header(RANGE, format!("bytes={offset}-"))
+            .header(IF_RANGE, validator);
+    }
+    let mut response = request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to download {url}"))?;
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        let expected = cached.and_then(|entry| entry.sha256);
+        let file = path.to_path_buf();
+        let actual = spawn_blocking(move || checksum(&file)).await??;
+        if expected.as_deref() == Some(actual.as_str()) {
+            println!("The ITIS bundle downloaded earlier is up to date");
+            return Ok(());
+        }
+        // the file was changed since it was downloaded, so download it again
+        eprintln!("Warning: the ITIS bundle downloaded earlier is damaged, downloading it again");
+        CacheEntry::remove(path);
+        return Box::pin(download(url, path)).await;
+    }
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|v: &HeaderValue| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let mut entry = CacheEntry {
+        url: url.to_string(),
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+        sha256: None,
+    };
+    let (mut file, mut received) = match (response.status(), resume) {
+        (StatusCode::PARTIAL_CONTENT, Some((_, offset))) => {
+            println!("Resuming the download of {url}");
+            let file = OpenOptions::new().append(true).open(&partial)?;
+            (file, offset)
+        }
+        _ => {
+            println!("Downloading {url}");
+            let file = File::create(&partial)
+                .with_context(|| format!("Failed to create {}", partial.display()))?;
+            (file, 0)
+        }
+    };
+    entry.save(&partial)?;
+    let total = response.content_length().map(|len| len + received);
+    let mut progress = Progress::new(total);
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .with_context(|| format!("Failed to download {url}"))?
+    {
+        file.write_all(&chunk)?;
+        received += chunk.len() as u64;
+        progress.update(received);
+    }
+    progress.finish();
+    drop(file);
+    if let Some(total) = total {
+        if received != total {
+            return Err(anyhow!(
+                "The download of {url} is incomplete, only {received} of {total} bytes were received"
+            ));
+        }
+    }
+    let file = partial.clone();
+    entry.sha256 = Some(spawn_blocking(move || checksum(&file)).await??);
+    std::fs::rename(&partial, path)?;
+    CacheEntry::remove(&partial);
+    entry.save(path)?;
+    Ok(())
+}
+
+/// A progress bar for a download, written to stderr
+struct Progress {
+    total: Option<u64>,
+    shown: Option<u64>,
+}
+
+impl Progress {
+    const WIDTH: u64 = 40;
+
+    fn new(total: Option<u64>) -> Self {
+        Self { total, shown: None }
+    }
+
+    fn update(&mut self, received: u64) {
+        let mib = received as f64 / (1024.0 * 1024.0);
+        match self.total.filter(|total| *total > 0) {
+            Some(total) => {
+                let percent = (received * 100 / total).min(100);
+                if self.shown == Some(percent) {
+                    return;
+                }
+                self.shown = Some(percent);
+                let filled = (percent * Self::WIDTH / 100) as usize;
+                eprint!(
+                    "\r[{}{}] {percent:3}% {mib:.1} MiB",
+                    "#".repeat(filled),
+                    " ".repeat(Self::WIDTH as usize - filled)
+                );
+            }
+            None => {
+                // without a known size, show the amount received for every MiB
+                let whole = received / (1024 * 1024);
+                if self.shown == Some(whole) {
+                    return;
+                }
+                self.shown = Some(whole);
+                eprint!("\r{mib:.1} MiB");
+            }
+        }
+    }
+
+    fn finish(&self) {
+        if self.shown.is_some() {
+            eprintln!();
+        }
+    }
+}
+
+/// Extract the SQLite database from an ITIS zip file
+fn extract(bundle: &Path, dest: &Path) -> Result<()> {
+    let file =
+        File::open(bundle).with_context(|| format!("Failed to open {}", bundle.display()))?;
+    let mut archive = zip::ZipArchive::new(file)
+        .with_context(|| format!("{} is not a zip file", bundle.display()))?;
+    let name = archive
+        .file_names()
+        .find(|name| name.ends_with(".sqlite"))
+        .map(str::to_string)
+        .ok_or_else(|| {
+            anyhow!(
+                "{} does not contain an ITIS SQLite database",
+                bundle.display()
+            )
+        })?;
+    println!("Extracting {name}");
+    let mut entry = archive.by_name(&name)?;
+    let mut out =
+        File::create(dest).with_context(|| format!("Failed to create {}", dest.display()))?;
+    io::copy(&mut entry, &mut out)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    const ETAG_V1: &str = "\"v1\"";
+
+    /// Serve `body` with an ETag and support for range requests. The first response is cut off
+    /// halfway if `interrupt` is set. The headers of each request are recorded.
+    async fn serve(
+        body: Vec<u8>,
+        interrupt: Arc<AtomicBool>,
+        requests: Arc<Mutex<Vec<String>>>,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let request = String::from_utf8(request).unwrap().to_lowercase();
+                requests.lock().unwrap().push(request.clone());
+                let header = |name: &str| {
+                    request
+                        .lines()
+                        .find_map(|l| l.strip_prefix(&format!("{name}: ")))
+                        .map(str::to_string)
+                };
+                let (status, extra, content) =
+                    if header("if-none-match").as_deref() == Some(ETAG_V1) {
+                        ("304 Not Modified", String::new(), &body[..0])
+                    } else if let Some(range) = header("range") {
+                        let start: usize = range
+                            .trim_start_matches("bytes=")
+                            .trim_end_matches('-')
+                            .parse()
+                            .unwrap();
+                        let extra = format!(
+                            "Content-Range: bytes {start}-{}/{}\r\n",
+                            body.len() - 1,
+                            body.len()
+                        );
+                        ("206 Partial Content", extra, &body[start..])
+                    } else {
+                        ("200 OK", String::new(), &body[..])
+                    };
+                let head = format!(
+                    "HTTP/1.1 {status}\r\nETag: {ETAG_V1}\r\nContent-Length: {}\r\n{extra}Connection: close\r\n\r\n",
+                    content.len()
+                );
+                stream.write_all(head.as_bytes()).await.unwrap();
+                let sent = match interrupt.swap(false, Ordering::SeqCst) {
+                    true => &content[..content.len() / 2],
+                    false => content,
+                };
+                stream.write_all(sent).await.unwrap();
+                stream.flush().await.unwrap();
+            }
+        });
+        format!("http://{addr}/itisSqlite.zip")
+    }
+
+    #[tokio::test]
+    async fn test_download() {
+        let dir = std::env::temp_dir().join(format!("seedctl-itis-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(BUNDLE_NAME);
+        let body: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let interrupt = Arc::new(AtomicBool::new(true));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let url = serve(body.clone(), interrupt, requests.clone()).await;
+
+        // the first download is interrupted and keeps what it received
+        assert!(download(&url, &path).await.is_err());
+        assert!(!path.exists());
+        let received = std::fs::metadata(path.with_extension("part"))
+            .unwrap()
+            .len();
+        assert!(received > 0 && received < body.len() as u64);
+
+        // the next one continues where it stopped
+        download(&url, &path).await.unwrap();
+        assert!(requests.lock().unwrap()[1].contains(&format!("range: bytes={received}-")));
+        assert_eq!(std::fs::read(&path).unwrap(), body);
+        assert!(!path.with_extension("part").exists());
+        let entry = CacheEntry::load(&path, &url).unwrap();
+        assert_eq!(entry.etag.as_deref(), Some(ETAG_V1));
+        assert_eq!(entry.sha256, Some(checksum(&path).unwrap()));
+
+        // the cached download is still the current one
+        download(&url, &path).await.unwrap();
+        assert!(requests.lock().unwrap()[2].contains("if-none-match"));
+        assert_eq!(std::fs::read(&path).unwrap(), body);
+
+        // a damaged cached download is downloaded again
+        std::fs::write(&path, b"damaged").unwrap();
+        download(&url, &path).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), body);
+
+        // a bundle is only used if it has the expected checksum
+        let database = dir.join("ITIS.sqlite");
+        std::fs::copy(&path, &database).unwrap();
+        let sha256 = checksum(&database).unwrap();
+        let bundle = ItisSource::Bundle(database.clone());
+        assert_eq!(
+            bundle
+                .fetch(&dir, Some(&sha256.to_uppercase()))
+                .await
+                .unwrap(),
+            database
+        );
+        let err = bundle.fetch(&dir, Some(&"0".repeat(64))).await.unwrap_err();
+        assert!(err.to_string().contains(&sha256));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -5,11 +5,12 @@ use crate::table::*;
 use anyhow::{anyhow, Result};
 use clap::Parser;
 use libseed::{
+    filter::{Cmp, CompoundFilter, Op},
     loadable::Loadable,
-    taxonomy::{filter_by, Taxon},
+    taxonomy::{filter_by, rank_quickfind_results, Germination, GerminationFilter, Taxon},
     Error::DatabaseRowNotFound,
 };
-use std::path::PathBuf;
+use std::{path::PathBuf, process::ExitCode};
 use tabled::Table;
 use tokio::fs;
 use tracing::debug;
@@ -17,22 +18,86 @@ use tracing::debug;
 mod cli;
 mod commands;
 mod config;
+mod exitcode;
+mod import;
+mod itis;
 mod prompt;
+mod secrets;
 mod table;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     tracing_subscriber::fmt::init();
     let args = Cli::parse();
+    prompt::set_non_interactive(args.non_interactive);
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            ExitCode::from(exitcode::from_error(&e))
+        }
+    }
+}
+
+async fn run(args: Cli) -> Result<()> {
     let xdgdirs = xdg::BaseDirectories::new()?;
     let config_file = xdgdirs.place_config_file("seedctl/config")?;
+    let mut cfg = match Config::load_from_file(&config_file).await {
+        Ok(cfg) => cfg,
+        Err(Error::NotLoggedIn) => Config::default(),
+        Err(e) => return Err(e.into()),
+    };
+    // older versions of seedctl stored passwords in the config file in plaintext
+    let mut secured = false;
+    for (name, profile) in cfg.profiles.iter_mut() {
+        if !profile.has_plaintext_secret() {
+            continue;
+        }
+        match profile.secure_plaintext_secret(name).await {
+            Ok(()) => {
+                eprintln!(
+                    "Moved the credentials for profile '{name}' out of the config file ({})",
+                    profile.secret_storage()
+                );
+                secured = true;
+            }
+            Err(e) => eprintln!(
+                "Warning: unable to secure the credentials for profile '{name}': {:#}",
+                anyhow::Error::from(e)
+            ),
+        }
+    }
+    if secured {
+        cfg.save_to_file(&config_file).await?;
+    }
+    libseed::plugin::load_builtin(&cfg.plugins)?;
     match &args.command {
-        Commands::Login { username, database } => {
-            let username = username
+        Commands::Login {
+            username,
+            database,
+            token,
+        } => {
+            let name = args
+                .profile
                 .as_ref()
+                .or(cfg.current.as_ref())
                 .cloned()
-                .or_else(|| inquire::Text::new("Username:").prompt().ok())
-                .ok_or_else(|| anyhow!("No username specified"))?;
+                .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+            // the username isn't needed when logging in with a token since it identifies the user
+            if username.is_none() && !token {
+                prompt::require_interactive("The username")?;
+            }
+            let username = match token {
+                true => username.as_ref().cloned().unwrap_or_default(),
+                false => username
+                    .as_ref()
+                    .cloned()
+                    .or_else(|| inquire::Text::new("Username:").prompt().ok())
+                    .ok_or_else(|| anyhow!("No username specified"))?,
+            };
+            if database.is_none() {
+                prompt::require_interactive("The database path")?;
+            }
             let database = database
                 .as_ref()
                 .cloned()
@@ -43,42 +108,116 @@ async fn main() -> Result<()> {
                         .ok()
                 })
                 .ok_or_else(|| anyhow!("No database specified"))?;
-            let pwd = inquire::Password::new("Password:")
+            let secret = match token {
+                true => "The API token",
+                false => "The password",
+            };
+            prompt::require_interactive(secret)?;
+            let secret = inquire::Password::new(if *token { "API token:" } else { "Password:" })
                 .with_display_toggle_enabled()
                 .with_display_mode(inquire::PasswordDisplayMode::Masked)
                 .without_confirmation()
                 .prompt()?;
-            let cfg = Config::new(username.clone(), pwd, database.clone());
-            cfg.validate().await?;
+            let auth = match token {
+                true => AuthMethod::Token,
+                false => AuthMethod::Password,
+            };
+            let mut profile = Profile::new(username, auth, database);
+            let (_, user) = profile.connect(auth, &secret).await?;
+            profile.username.clone_from(&user.username);
+            profile.store_secret(&name, &secret).await?;
+            if let Some(old) = cfg.profiles.get(&name) {
+                // logging in as the same user replaces the existing keyring entry
+                if old.username != profile.username {
+                    old.remove_secret().await?;
+                }
+            }
+            cfg.set_profile(name.clone(), profile);
             cfg.save_to_file(&config_file).await?;
-            println!("Logged in as {username}");
+            println!("Logged in as {} (profile '{name}')", user.username);
             return Ok(());
         }
         Commands::Logout => {
-            fs::remove_file(&config_file)
-                .await
-                .or_else(|e| match e.kind() {
-                    std::io::ErrorKind::NotFound => Ok(()),
-                    _ => Err(anyhow::Error::from(e)),
-                })?;
-            println!("Logged out");
+            let (name, _) = cfg.profile(args.profile.as_deref())?;
+            let name = name.to_string();
+            cfg.remove_profile(&name)?.remove_secret().await?;
+            if cfg.profiles.is_empty() && cfg.itis.is_empty() && cfg.plugins.is_empty() {
+                fs::remove_file(&config_file)
+                    .await
+                    .or_else(|e| match e.kind() {
+                        std::io::ErrorKind::NotFound => Ok(()),
+                        _ => Err(anyhow::Error::from(e)),
+                    })?;
+            } else {
+                cfg.save_to_file(&config_file).await?;
+            }
+            println!("Logged out of profile '{name}'");
+            return Ok(());
+        }
+        Commands::Profiles { command } => {
+            match command {
+                ProfileCommands::List => {
+                    if cfg.profiles.is_empty() {
+                        return Err(Error::NotLoggedIn.into());
+                    }
+                    let mut table = Table::new(cfg.profiles.iter().map(|(name, profile)| {
+                        ProfileRow::new(name, profile, cfg.current.as_ref() == Some(name))
+                    }));
+                    println!("{}\n", table.styled());
+                }
+                ProfileCommands::Switch { name } => {
+                    cfg.switch_profile(name)?;
+                    cfg.save_to_file(&config_file).await?;
+                    println!("Switched to profile '{name}'");
+                }
+            }
             return Ok(());
         }
+        Commands::Admin {
+            command:
+                AdminCommands::Database {
+                    command:
+                        DatabaseCommands::Init {
+                            database,
+                            source,
+                            username,
+                            email,
+                            passwordfile,
+                        },
+                },
+        } => {
+            // there are no users to log in as until the database has been created
+            return commands::admin::init_database(
+                database,
+                source,
+                username,
+                email,
+                passwordfile.clone(),
+                &cfg.itis,
+            )
+            .await;
+        }
         _ => (),
     };
 
-    let cfg = config::Config::load_from_file(&config_file).await?;
-    debug!(?cfg.username, ?cfg.database, "logging in");
-    let (dbpool, user) = cfg.validate().await?;
+    let (profile_name, profile) = cfg.profile(args.profile.as_deref())?;
+    debug!(?profile_name, ?profile.username, ?profile.database, "logging in");
+    let (dbpool, user) = profile.validate().await?;
 
     match args.command {
         Commands::Login { .. } => {
             Ok(()) // already handled above
         }
         Commands::Logout => Ok(()),
+        Commands::Profiles { .. } => Ok(()),
         Commands::Status => {
-            println!("Using database '{}'", cfg.database.to_string_lossy());
-            println!("Logged in as user '{}'", cfg.username);
+            println!("Using profile '{profile_name}'");
+            println!("Using database '{}'", profile.database.to_string_lossy());
+            println!("Logged in as user '{}'", profile.username);
+            println!("Credentials stored: {}", profile.secret_storage());
+            if !cfg.plugins.is_empty() {
+                println!("Plugins enabled: {}", cfg.plugins.join(", "));
+            }
             Ok(())
         }
         Commands::Projects { command } => {
@@ -87,6 +226,15 @@ async fn main() -> Result<()> {
         Commands::Sources { command } => {
             commands::sources::handle_command(command, user, &dbpool).await
         }
+        Commands::Trips { command } => {
+            commands::trips::handle_command(command, user, &dbpool).await
+        }
+        Commands::Notes { command } => {
+            commands::notes::handle_command(command, user, &dbpool).await
+        }
+        Commands::Reports { command } => {
+            commands::reports::handle_command(command, user, &dbpool).await
+        }
         Commands::Samples { command } => {
             commands::samples::handle_command(command, user, &dbpool).await
         }
@@ -102,12 +250,15 @@ async fn main() -> Result<()> {
                     true => Some(true),
                     false => None,
                 };
-                let taxa: Vec<Taxon> = Taxon::load_all(
-                    filter_by(None, rank, genus, species, any, minnesota),
+                let mut taxa: Vec<Taxon> = Taxon::load_all(
+                    filter_by(None, rank, genus, species, any.clone(), minnesota),
                     None,
                     &dbpool,
                 )
                 .await?;
+                if let Some(query) = any {
+                    rank_quickfind_results(&query, &mut taxa);
+                }
                 if taxa.is_empty() {
                     return Err(anyhow!("No results found"));
                 }
@@ -118,23 +269,48 @@ async fn main() -> Result<()> {
             }
             TaxonomyCommands::Show { id } => match Taxon::load(id, &dbpool).await {
                 Ok(mut taxon) => {
-                    let tbuilder =
-                        Table::builder(vec![TaxonRowDetails::new(&mut taxon, &dbpool).await?])
-                            .index()
-                            .column(0)
-                            .transpose();
+                    let tbuilder = Table::builder(vec![
+                        TaxonRowDetails::new(&mut taxon, &user, &dbpool).await?,
+                    ])
+                    .index()
+                    .column(0)
+                    .transpose();
                     println!("{}\n", tbuilder.build().styled());
                     Ok(())
                 }
-                Err(DatabaseRowNotFound(_)) => {
-                    println!("Taxon {id} not found");
-                    Ok(())
+                Err(e @ DatabaseRowNotFound(_)) => {
+                    Err(anyhow::Error::from(e).context(format!("Taxon {id} not found")))
                 }
                 Err(e) => Err(e.into()),
             },
+            TaxonomyCommands::GerminationCodes {
+                code,
+                taxon,
+                used_by_my_samples,
+                sort,
+            } => {
+                let mut fbuilder = CompoundFilter::builder(Op::And);
+                if let Some(code) = code {
+                    fbuilder = fbuilder.push(GerminationFilter::Code(Cmp::Like, code));
+                }
+                if let Some(tsn) = taxon {
+                    fbuilder = fbuilder.push(GerminationFilter::Taxon(tsn));
+                }
+                if used_by_my_samples {
+                    fbuilder = fbuilder.push(GerminationFilter::UsedBySamples(user.id));
+                }
+                let codes = Germination::load_all(Some(fbuilder.build()), sort, &dbpool).await?;
+                if codes.is_empty() {
+                    return Err(anyhow!("No results found"));
+                }
+                let mut table = Table::new(codes.iter().map(GerminationRow::new));
+                println!("{}\n", table.styled());
+                println!("{} records found", codes.len());
+                Ok(())
+            }
         },
         Commands::Admin { command } => {
-            commands::admin::handle_command(command, user, &dbpool).await
+            commands::admin::handle_command(command, user, &cfg.itis, &dbpool).await
         }
     }
 }
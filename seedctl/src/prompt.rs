@@ -1,10 +1,14 @@
-use std::convert;
+use std::{
+    convert,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use inquire::{autocompletion::Autocomplete, CustomUserError};
 use libseed::{
     filter::{Cmp, CompoundFilter, Op},
-    source::{self, Source},
-    taxonomy::{quickfind, Taxon},
+    scope::UserScope,
+    source,
+    taxonomy::{quickfind, rank_quickfind_results, Taxon},
 };
 use sqlx::{Pool, Sqlite};
 
@@ -14,10 +18,42 @@ pub enum Error {
     CompletionIdFormatMissingDot(String),
     #[error("Internal Error: unable to parse an integer from '{0}'")]
     CompletionIdFormatParseFailure(String),
+    #[error("{0} must be specified when running non-interactively")]
+    InputRequired(String),
     #[error(transparent)]
     Prompt(#[from] inquire::InquireError),
 }
 
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Disable all prompts. Commands that would need to ask the user for missing input fail with
+/// [Error::InputRequired] instead, and confirmations are automatically accepted.
+pub fn set_non_interactive(non_interactive: bool) {
+    NON_INTERACTIVE.store(non_interactive, Ordering::Relaxed);
+}
+
+pub fn is_non_interactive() -> bool {
+    NON_INTERACTIVE.load(Ordering::Relaxed)
+}
+
+/// Check that it's okay to prompt the user for `what`
+pub fn require_interactive(what: &str) -> Result<(), Error> {
+    match is_non_interactive() {
+        true => Err(Error::InputRequired(what.to_string())),
+        false => Ok(()),
+    }
+}
+
+/// Ask the user to confirm an action. This is always confirmed when running non-interactively.
+pub fn confirm(message: &str) -> Result<bool, Error> {
+    if is_non_interactive() {
+        return Ok(true);
+    }
+    Ok(inquire::Confirm::new(message)
+        .with_default(false)
+        .prompt()?)
+}
+
 pub struct TaxonIdPrompt<'a> {
     text: inquire::Text<'a>,
 }
@@ -63,7 +99,11 @@ impl Autocomplete for TaxonCompleter {
                 quickfind(input.to_string()),
                 None,
                 &self.dbpool,
-            ));
+            ))
+            .map(|mut taxa| {
+                rank_quickfind_results(input, &mut taxa);
+                taxa
+            });
         }
         taxa.map(|taxa| {
             taxa.iter()
@@ -104,6 +144,12 @@ impl<'a> SourceIdPrompt<'a> {
         }
     }
 
+    pub fn with_initial_value(self, value: &'a str) -> Self {
+        Self {
+            text: self.text.with_initial_value(value),
+        }
+    }
+
     pub fn prompt(self) -> Result<i64, Error> {
         let res = self.text.prompt()?;
         // HACK -- the completer generates a string with the following format:
@@ -131,13 +177,15 @@ struct SourceCompleter {
 
 impl Autocomplete for SourceCompleter {
     fn get_suggestions(&mut self, input: &str) -> Result<Vec<String>, CustomUserError> {
-        let fbuilder = CompoundFilter::builder(Op::And)
+        let filter = CompoundFilter::builder(Op::And)
             .push(source::Filter::UserId(self.userid))
-            .push(source::Filter::Name(Cmp::Like, input.to_string()));
+            .push(source::Filter::Name(Cmp::Like, input.to_string()))
+            .build();
         let mut sources = Ok(vec![]);
         if input.len() > 2 {
-            sources =
-                futures::executor::block_on(Source::load_all(Some(fbuilder.build()), &self.dbpool));
+            sources = futures::executor::block_on(
+                UserScope::new(self.userid).sources(Some(filter), &self.dbpool),
+            );
         }
         sources
             .map(|taxa| {
@@ -0,0 +1,302 @@
+//! Storage for the passwords and API tokens of seedctl profiles. Secrets are kept in the operating
+//! system's keyring when one is available. Otherwise they are encrypted with a key derived from a
+//! passphrase and stored in the config file.
+use crate::prompt;
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+const KEYRING_SERVICE: &str = "seedctl";
+
+/// An environment variable that can be used to supply the passphrase for encrypted secrets when
+/// running non-interactively
+pub const PASSPHRASE_ENV: &str = "SEEDCTL_PASSPHRASE";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Unable to access the system keyring")]
+    Keyring(#[from] keyring::Error),
+    #[error("The keyring operation did not complete")]
+    KeyringTask(#[from] tokio::task::JoinError),
+    #[error("Failed to encrypt the secret")]
+    EncryptionFailed,
+    #[error("Unable to decrypt the secret. The passphrase may be incorrect")]
+    DecryptionFailed,
+    #[error("Failed to derive an encryption key from the passphrase: {0}")]
+    KeyDerivation(argon2::Error),
+    #[error("The encrypted secret in the config file is corrupted")]
+    Corrupted,
+    #[error(transparent)]
+    Prompt(#[from] prompt::Error),
+}
+
+/// Where the secret for a profile is stored
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "storage", rename_all = "lowercase")]
+pub enum StoredSecret {
+    /// the secret is stored in the system keyring under the given account name
+    Keyring { account: String },
+    /// the secret is encrypted with a key derived from a passphrase. All values are hex encoded.
+    Encrypted {
+        salt: String,
+        nonce: String,
+        ciphertext: String,
+    },
+}
+
+impl StoredSecret {
+    /// Store the secret in the system keyring if possible, and fall back to encrypting it with a
+    /// passphrase otherwise
+    pub async fn store(account: &str, secret: &str) -> Result<Self, Error> {
+        let entry_secret = secret.to_string();
+        match with_entry(account, move |entry| entry.set_password(&entry_secret)).await {
+            Ok(()) => Ok(Self::Keyring {
+                account: account.to_string(),
+            }),
+            Err(e) => {
+                debug!(
+                    ?e,
+                    "Keyring unavailable, encrypting secret with a passphrase"
+                );
+                let passphrase = passphrase(true)?;
+                encrypt(secret, &passphrase)
+            }
+        }
+    }
+
+    pub async fn retrieve(&self) -> Result<String, Error> {
+        match self {
+            Self::Keyring { account } => with_entry(account, |entry| entry.get_password()).await,
+            Self::Encrypted { .. } => decrypt(self, &passphrase(false)?),
+        }
+    }
+
+    /// Remove the secret from the keyring. Encrypted secrets are removed along with the profile.
+    pub async fn remove(&self) -> Result<(), Error> {
+        if let Self::Keyring { account } = self {
+            match with_entry(account, |entry| entry.delete_credential()).await {
+                Ok(()) | Err(Error::Keyring(keyring::Error::NoEntry)) => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Keyring { .. } => "system keyring",
+            Self::Encrypted { .. } => "encrypted with a passphrase",
+        }
+    }
+}
+
+/// Run an operation on a keyring entry. The keyring API is blocking, so this is done on a
+/// separate thread.
+async fn with_entry<T, F>(account: &str, f: F) -> Result<T, Error>
+where
+    T: Send + 'static,
+    F: FnOnce(keyring::Entry) -> keyring::Result<T> + Send + 'static,
+{
+    let account = account.to_string();
+    Ok(
+        tokio::task::spawn_blocking(move || f(keyring::Entry::new(KEYRING_SERVICE, &account)?))
+            .await??,
+    )
+}
+
+fn passphrase(confirm: bool) -> Result<String, Error> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    prompt::require_interactive(&format!("The passphrase (or {PASSPHRASE_ENV})"))?;
+    let mut p = inquire::Password::new("Passphrase for stored credentials:")
+        .with_display_mode(inquire::PasswordDisplayMode::Masked);
+    if !confirm {
+        p = p.without_confirmation();
+    }
+    Ok(p.prompt().map_err(prompt::Error::from)?)
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, Error> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(Error::KeyDerivation)?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+fn encrypt(secret: &str, passphrase: &str) -> Result<StoredSecret, Error> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher(passphrase, &salt)?
+        .encrypt(&nonce, secret.as_bytes())
+        .map_err(|_| Error::EncryptionFailed)?;
+    Ok(StoredSecret::Encrypted {
+        salt: to_hex(&salt),
+        nonce: to_hex(&nonce),
+        ciphertext: to_hex(&ciphertext),
+    })
+}
+
+fn decrypt(secret: &StoredSecret, passphrase: &str) -> Result<String, Error> {
+    let StoredSecret::Encrypted {
+        salt,
+        nonce,
+        ciphertext,
+    } = secret
+    else {
+        return Err(Error::Corrupted);
+    };
+    let salt = from_hex(salt).ok_or(Error::Corrupted)?;
+    let nonce = from_hex(nonce).ok_or(Error::Corrupted)?;
+    let ciphertext = from_hex(ciphertext).ok_or(Error::Corrupted)?;
+    if nonce.len() != 12 {
+        return Err(Error::Corrupted);
+    }
+    let plaintext = cipher(passphrase, &salt)?
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| Error::DecryptionFailed)?;
+    String::from_utf8(plaintext).map_err(|_| Error::Corrupted)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A copy of an encrypted secret with one of its values replaced
+    fn with(secret: &StoredSecret, field: &str, value: String) -> StoredSecret {
+        let StoredSecret::Encrypted {
+            salt,
+            nonce,
+            ciphertext,
+        } = secret.clone()
+        else {
+            panic!("secret is not encrypted");
+        };
+        match field {
+            "salt" => StoredSecret::Encrypted {
+                salt: value,
+                nonce,
+                ciphertext,
+            },
+            "nonce" => StoredSecret::Encrypted {
+                salt,
+                nonce: value,
+                ciphertext,
+            },
+            _ => StoredSecret::Encrypted {
+                salt,
+                nonce,
+                ciphertext: value,
+            },
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let secret = encrypt("topsecret123", "correct horse").unwrap();
+        assert_eq!(secret.description(), "encrypted with a passphrase");
+        assert_eq!(decrypt(&secret, "correct horse").unwrap(), "topsecret123");
+        // every secret gets its own salt and nonce
+        let again = encrypt("topsecret123", "correct horse").unwrap();
+        let (
+            StoredSecret::Encrypted {
+                salt, ciphertext, ..
+            },
+            StoredSecret::Encrypted {
+                salt: salt2,
+                ciphertext: ciphertext2,
+                ..
+            },
+        ) = (&secret, &again)
+        else {
+            panic!("secrets are not encrypted");
+        };
+        assert_ne!(salt, salt2);
+        assert_ne!(ciphertext, ciphertext2);
+
+        // the stored form survives the config file
+        let json = serde_json::to_string(&secret).unwrap();
+        assert!(json.contains(r#""storage":"encrypted""#));
+        let parsed: StoredSecret = serde_json::from_str(&json).unwrap();
+        assert_eq!(decrypt(&parsed, "correct horse").unwrap(), "topsecret123");
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let secret = encrypt("topsecret123", "correct horse").unwrap();
+        assert!(matches!(
+            decrypt(&secret, "battery staple"),
+            Err(Error::DecryptionFailed)
+        ));
+        assert!(matches!(decrypt(&secret, ""), Err(Error::DecryptionFailed)));
+    }
+
+    #[test]
+    fn test_corrupted() {
+        let secret = encrypt("topsecret123", "correct horse").unwrap();
+        let StoredSecret::Encrypted {
+            nonce, ciphertext, ..
+        } = &secret
+        else {
+            panic!("secret is not encrypted");
+        };
+        // values that aren't valid hex
+        for field in ["salt", "nonce", "ciphertext"] {
+            for value in ["abc", "zz00", "é0"] {
+                assert!(
+                    matches!(
+                        decrypt(&with(&secret, field, value.to_string()), "correct horse"),
+                        Err(Error::Corrupted)
+                    ),
+                    "{field} {value} should be rejected"
+                );
+            }
+        }
+        // a nonce of the wrong length
+        let short = with(&secret, "nonce", nonce[2..].to_string());
+        assert!(matches!(
+            decrypt(&short, "correct horse"),
+            Err(Error::Corrupted)
+        ));
+        // a modified or truncated ciphertext fails authentication
+        let mut bytes = from_hex(ciphertext).unwrap();
+        bytes[0] ^= 1;
+        let modified = with(&secret, "ciphertext", to_hex(&bytes));
+        assert!(matches!(
+            decrypt(&modified, "correct horse"),
+            Err(Error::DecryptionFailed)
+        ));
+        let truncated = with(&secret, "ciphertext", ciphertext[..8].to_string());
+        assert!(matches!(
+            decrypt(&truncated, "correct horse"),
+            Err(Error::DecryptionFailed)
+        ));
+        let keyring = StoredSecret::Keyring {
+            account: "default:testuser".to_string(),
+        };
+        assert!(matches!(
+            decrypt(&keyring, "correct horse"),
+            Err(Error::Corrupted)
+        ));
+    }
+}
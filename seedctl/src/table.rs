@@ -1,16 +1,31 @@
 use std::sync::Arc;
 
+use crate::config::Profile;
 use anyhow::Result;
 use libseed::{
+    conservation::{Listing, Permit},
+    dedupe::Candidate,
     filter::Cmp,
-    project::{allocation, Allocation, Project},
-    sample::{self, Certainty, Sample},
-    source::Source,
-    taxonomy::{Germination, NativeStatus, Rank, Taxon},
-    user::User,
+    forecast::{Trend, YieldForecast},
+    loadable::Loadable,
+    mailqueue::{MailStatus, QueuedMail},
+    notes::NoteEntry,
+    project::{allocation, template::NoteTemplate, Allocation, Project},
+    quality::QualityTest,
+    report::Report,
+    sample::{self, Certainty, Purchase, Sample, SampleFlag},
+    scope::UserScope,
+    sitematch::SiteMatch,
+    source::{HabitatType, LightCondition, NearbySource, SoilMoisture, Source},
+    stats::CollectionYear,
+    taxonomy::{Germination, InvasiveStatus, NativeStatus, Rank, SeedWeight, Taxon},
+    timezone::TimeZone,
+    trip::{Trip, TripSource, TripTaxon},
+    user::{User, UserToken},
 };
 use sqlx::{Pool, Sqlite};
 use tabled::{Table, Tabled};
+use time::{macros::format_description, OffsetDateTime};
 
 pub trait SeedctlTable {
     fn styled(&mut self) -> &mut Self;
@@ -93,10 +108,39 @@ fn table_display_germination(germ: &Option<Vec<Germination>>) -> String {
         .unwrap_or_default()
 }
 
+fn table_display_purchase(purchase: &Option<Purchase>) -> String {
+    let Some(p) = purchase else {
+        return "Collected".to_string();
+    };
+    let mut lines = vec![p.vendor.clone()];
+    if let Some(lot) = &p.lot {
+        lines.push(format!("Lot: {lot}"));
+    }
+    if let Some(date) = p.date {
+        lines.push(format!("Date: {date}"));
+    }
+    if let Some(price) = p.price {
+        lines.push(format!("Price: {price:.2}"));
+    }
+    if let Some(origin) = &p.certified_origin {
+        lines.push(format!("Certified origin: {origin}"));
+    }
+    lines.join("\n")
+}
+
+/// Each project that a sample is allocated to, followed by the project notes about the sample
 fn table_display_allocations(allocations: &[Allocation]) -> String {
     let s = allocations
         .iter()
-        .map(|a| format!("{} ({})", a.project.name, a.project.id))
+        .map(|a| {
+            let mut lines = vec![format!("{} ({})", a.project.name, a.project.id)];
+            lines.extend(
+                a.notes
+                    .iter()
+                    .map(|n| format!("  {} {:?}: {}", n.date, n.kind, n.summary)),
+            );
+            lines.join("\n")
+        })
         .collect::<Vec<String>>()
         .join("\n");
     match s.is_empty() {
@@ -115,8 +159,14 @@ pub struct SampleRowDetails {
     source: String,
     #[tabled(rename = "Collection Date")]
     date: String,
+    #[tabled(display_with = "table_display_purchase")]
+    purchase: Option<Purchase>,
     #[tabled(display_with = "table_display_option")]
-    quantity: Option<i64>,
+    trip: Option<String>,
+    #[tabled(display_with = "table_display_option")]
+    quantity: Option<String>,
+    #[tabled(display_with = "table_display_option", rename = "Estimated Weight")]
+    weight: Option<String>,
     certainty: Certainty,
     #[tabled(
         display_with = "table_display_germination",
@@ -130,24 +180,54 @@ pub struct SampleRowDetails {
 }
 
 impl SampleRowDetails {
-    pub async fn new(sample: &mut Sample, pool: &Pool<Sqlite>) -> Result<Self> {
+    pub async fn new(sample: &mut Sample, user: &User, pool: &Pool<Sqlite>) -> Result<Self> {
+        let fmt = user.number_format();
         let taxon = sample.taxon.load_mut(pool).await?;
         taxon.load_germination_info(pool).await?;
-        let src = sample.source.object()?;
-        let allocations = Allocation::load_all(
-            Some(Arc::new(allocation::Filter::SampleId(sample.id))),
-            None,
-            pool,
-        )
-        .await?;
+        taxon.load_seed_weight(pool).await?;
+        // the source of a sample is loaded without its coordinates
+        let mut src = Source::load(sample.source.id(), pool).await?;
+        src.reveal_for(user.id, pool).await?;
+        let mut allocations = UserScope::new(user.id)
+            .allocations(
+                Some(Arc::new(allocation::Filter::SampleId(sample.id))),
+                None,
+                pool,
+            )
+            .await?;
+        for allocation in allocations.iter_mut() {
+            allocation.load_notes(pool).await?;
+        }
+        let mut source = format!("{} ({})", src.name, src.id);
+        if let Some((latitude, longitude)) = src.visible_coordinates() {
+            source.push('\n');
+            source.push_str(&fmt.coordinates(latitude, longitude, 5));
+            if src.is_generalized() {
+                source.push_str(" (generalized)");
+            }
+        }
+        let trip = match sample.trip {
+            Some(id) => {
+                let trip = Trip::load(id, pool).await?;
+                Some(format!("{} ({})", trip.name, trip.id))
+            }
+            None => None,
+        };
 
         Ok(Self {
             id: sample.id,
             taxon: format!("{} ({})", taxon.complete_name, taxon.id),
             common_names: taxon.vernaculars.clone(),
-            source: format!("{} ({})", src.name, src.id),
+            source,
             date: datestring(sample.month, sample.year),
-            quantity: sample.quantity,
+            purchase: sample.purchase.clone(),
+            trip,
+            quantity: sample.quantity.map(|n| fmt.integer(n)),
+            weight: taxon
+                .seed_weight
+                .as_ref()
+                .zip(sample.quantity)
+                .map(|(w, n)| fmt.weight(w.estimated_weight(n))),
             certainty: sample.certainty.clone(),
             germination: taxon.germination.clone(),
             notes: sample.notes.as_ref().cloned(),
@@ -246,6 +326,13 @@ pub struct SourceRowFull {
     longitude: Option<f64>,
     #[tabled(display_with = "table_display_option")]
     description: Option<String>,
+    #[tabled(display_with = "table_display_option")]
+    habitat: Option<HabitatType>,
+    #[tabled(display_with = "table_display_option")]
+    moisture: Option<SoilMoisture>,
+    #[tabled(display_with = "table_display_option")]
+    light: Option<LightCondition>,
+    sensitive: bool,
 }
 
 impl SourceRowFull {
@@ -253,9 +340,13 @@ impl SourceRowFull {
         Self {
             id: source.id,
             name: source.name.clone(),
-            latitude: source.latitude,
-            longitude: source.longitude,
+            latitude: source.visible_coordinates().map(|(lat, _)| lat),
+            longitude: source.visible_coordinates().map(|(_, lon)| lon),
             description: source.description.clone(),
+            habitat: source.habitat,
+            moisture: source.moisture,
+            light: source.light,
+            sensitive: source.sensitive,
         }
     }
 }
@@ -276,6 +367,31 @@ impl SourceRow {
     }
 }
 
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct NearbySourceRow {
+    id: i64,
+    name: String,
+    #[tabled(rename = "Distance (km)")]
+    distance: String,
+    #[tabled(display_with = "format_string_vec")]
+    samples: Vec<String>,
+}
+
+impl NearbySourceRow {
+    pub fn new(nearby: &NearbySource, samples: &[Sample]) -> Result<Self> {
+        Ok(Self {
+            id: nearby.source.id,
+            name: nearby.source.name.clone(),
+            distance: format!("{:.1}", nearby.distance_km),
+            samples: samples
+                .iter()
+                .map(|s| Ok(format!("{}: {}", s.id, s.taxon.object()?.complete_name)))
+                .collect::<Result<_>>()?,
+        })
+    }
+}
+
 fn format_string_vec(names: &[String]) -> String {
     names.join(",\n")
 }
@@ -314,11 +430,15 @@ pub struct TaxonRowDetails {
     rank: Rank,
     #[tabled(display_with = "table_display_option", rename = "MN Status")]
     mn_status: Option<NativeStatus>,
+    #[tabled(display_with = "table_display_option", rename = "MN Invasive")]
+    mn_invasive: Option<InvasiveStatus>,
     #[tabled(
         display_with = "table_display_germination",
         rename = "Germination Codes"
     )]
     germination: Option<Vec<Germination>>,
+    #[tabled(display_with = "table_display_option", rename = "Seeds Per Gram")]
+    seeds_per_gram: Option<f64>,
     #[tabled(display_with = "table_display_samples")]
     samples: Vec<Sample>,
 }
@@ -341,14 +461,16 @@ fn table_display_samples(samples: &[Sample]) -> String {
 }
 
 impl TaxonRowDetails {
-    pub async fn new(taxon: &mut Taxon, pool: &Pool<Sqlite>) -> Result<Self> {
+    pub async fn new(taxon: &mut Taxon, user: &User, pool: &Pool<Sqlite>) -> Result<Self> {
         taxon.load_germination_info(pool).await?;
-        let mut samples = Sample::load_all(
-            Some(sample::Filter::TaxonId(Cmp::Equal, taxon.id).into()),
-            None,
-            pool,
-        )
-        .await?;
+        taxon.load_seed_weight(pool).await?;
+        let mut samples = UserScope::new(user.id)
+            .samples(
+                Some(sample::Filter::TaxonId(Cmp::Equal, taxon.id).into()),
+                None,
+                pool,
+            )
+            .await?;
         for ref mut s in &mut samples {
             s.source.load(pool).await?;
         }
@@ -359,18 +481,42 @@ impl TaxonRowDetails {
             name: taxon.complete_name.clone(),
             common_names: taxon.vernaculars.clone(),
             mn_status: taxon.native_status.clone(),
+            mn_invasive: taxon.invasive_status,
             germination: taxon.germination.clone(),
+            seeds_per_gram: taxon.seed_weight.as_ref().map(|w| w.seeds_per_gram),
             samples,
         })
     }
 }
 
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct ProfileRow {
+    #[tabled(rename = "")]
+    current: &'static str,
+    name: String,
+    username: String,
+    database: String,
+}
+
+impl ProfileRow {
+    pub fn new(name: &str, profile: &Profile, current: bool) -> Self {
+        Self {
+            current: if current { "*" } else { "" },
+            name: name.to_string(),
+            username: profile.username.clone(),
+            database: profile.database.to_string_lossy().to_string(),
+        }
+    }
+}
+
 #[derive(Tabled)]
 #[tabled(rename_all = "PascalCase")]
 pub struct UserRow {
     id: i64,
     username: String,
     email: String,
+    admin: bool,
 }
 
 impl UserRow {
@@ -379,6 +525,106 @@ impl UserRow {
             id: user.id,
             username: user.username.clone(),
             email: user.email.clone(),
+            admin: user.admin,
+        }
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct TokenRow {
+    id: i64,
+    name: String,
+    created: String,
+    #[tabled(rename = "Last Used")]
+    last_used: String,
+}
+
+/// Format a timestamp from the database as a local date in the given time zone
+fn format_date(date: Option<OffsetDateTime>, tz: &TimeZone) -> Option<String> {
+    date.map(|d| tz.to_local(d).date().to_string())
+}
+
+/// Format a timestamp from the database as a local time in the given time zone
+fn format_timestamp(date: Option<OffsetDateTime>, tz: &TimeZone) -> String {
+    date.and_then(|d| {
+        tz.to_local(d)
+            .format(format_description!(
+                "[year]-[month]-[day] [hour]:[minute]:[second] [offset_hour sign:mandatory]:[offset_minute]"
+            ))
+            .ok()
+    })
+    .unwrap_or_default()
+}
+
+impl TokenRow {
+    pub fn new(token: &UserToken, tz: &TimeZone) -> Self {
+        Self {
+            id: token.id,
+            name: token.name.clone(),
+            created: format_date(token.created, tz).unwrap_or_default(),
+            last_used: format_date(token.last_used, tz).unwrap_or_else(|| "never".to_string()),
+        }
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct MailRow {
+    id: i64,
+    recipient: String,
+    subject: String,
+    status: MailStatus,
+    attempts: i64,
+    created: String,
+}
+
+impl MailRow {
+    pub fn new(mail: &QueuedMail, tz: &TimeZone) -> Self {
+        Self {
+            id: mail.id,
+            recipient: mail.recipient.clone(),
+            subject: mail.subject.clone(),
+            status: mail.status,
+            attempts: mail.attempts,
+            created: format_timestamp(mail.created, tz),
+        }
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct MailRowFull {
+    id: i64,
+    sender: String,
+    recipient: String,
+    subject: String,
+    status: MailStatus,
+    attempts: i64,
+    created: String,
+    #[tabled(rename = "Next Attempt")]
+    next_attempt: String,
+    sent: String,
+    #[tabled(rename = "Last Error", display_with = "table_display_option")]
+    last_error: Option<String>,
+}
+
+impl MailRowFull {
+    pub fn new(mail: &QueuedMail, tz: &TimeZone) -> Self {
+        Self {
+            id: mail.id,
+            sender: mail.sender.clone(),
+            recipient: mail.recipient.clone(),
+            subject: mail.subject.clone(),
+            status: mail.status,
+            attempts: mail.attempts,
+            created: format_timestamp(mail.created, tz),
+            next_attempt: match mail.status {
+                MailStatus::Pending => format_timestamp(mail.next_attempt, tz),
+                _ => "".to_string(),
+            },
+            sent: format_timestamp(mail.sent, tz),
+            last_error: mail.last_error.clone(),
         }
     }
 }
@@ -404,3 +650,341 @@ impl GerminationRow {
         }
     }
 }
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct SeedWeightRow {
+    id: i64,
+    taxon: String,
+    #[tabled(rename = "Seeds Per Gram")]
+    seeds_per_gram: f64,
+    #[tabled(display_with = "table_display_option")]
+    source: Option<String>,
+}
+
+impl SeedWeightRow {
+    pub async fn new(weight: &SeedWeight, pool: &Pool<Sqlite>) -> Result<Self> {
+        let taxon = Taxon::load(weight.tsn, pool).await?;
+        Ok(Self {
+            id: weight.tsn,
+            taxon: taxon.complete_name,
+            seeds_per_gram: weight.seeds_per_gram,
+            source: weight.source.clone(),
+        })
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct ForecastRow {
+    taxon: String,
+    source: String,
+    history: String,
+    expected: String,
+    trend: Trend,
+}
+
+impl ForecastRow {
+    pub fn new(forecast: &YieldForecast, years: &CollectionYear) -> Self {
+        Self {
+            taxon: forecast.taxon_name.clone(),
+            source: forecast.source_name.clone(),
+            history: forecast
+                .history
+                .iter()
+                .map(|(year, quantity)| format!("{}: {quantity}", years.label(*year)))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            expected: format!("{:.0}", forecast.expected),
+            trend: forecast.trend,
+        }
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct DuplicateSourceRow {
+    #[tabled(rename = "Source")]
+    first: String,
+    #[tabled(rename = "Possible duplicate")]
+    second: String,
+    #[tabled(rename = "Name similarity")]
+    similarity: String,
+    #[tabled(rename = "Distance (km)")]
+    distance: String,
+}
+
+impl DuplicateSourceRow {
+    pub fn new(candidate: &Candidate) -> Self {
+        Self {
+            first: format!("{}: {}", candidate.first.id, candidate.first.name),
+            second: format!("{}: {}", candidate.second.id, candidate.second.name),
+            similarity: format!("{:.0}%", candidate.name_similarity * 100.0),
+            distance: candidate
+                .distance_km
+                .map(|d| format!("{d:.2}"))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct SiteMatchRow {
+    score: String,
+    id: i64,
+    taxon: String,
+    source: String,
+    #[tabled(display_with = "format_string_vec")]
+    conditions: Vec<String>,
+    #[tabled(rename = "Distance (km)")]
+    distance: String,
+}
+
+impl SiteMatchRow {
+    pub fn new(m: &SiteMatch) -> Result<Self> {
+        Ok(Self {
+            score: format!("{:.0}%", m.score * 100.0),
+            id: m.sample.id,
+            taxon: m.sample.taxon.object()?.complete_name.clone(),
+            source: m.source.name.clone(),
+            conditions: [
+                m.source.habitat.map(|v| v.to_string()),
+                m.source.moisture.map(|v| v.to_string()),
+                m.source.light.map(|v| v.to_string()),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            distance: m.distance_km.map(|d| format!("{d:.1}")).unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct ListingRow {
+    id: i64,
+    taxon: String,
+    region: String,
+    status: String,
+}
+
+impl ListingRow {
+    pub async fn new(listing: &Listing, pool: &Pool<Sqlite>) -> Result<Self> {
+        let taxon = Taxon::load(listing.tsn, pool).await?;
+        Ok(Self {
+            id: listing.tsn,
+            taxon: taxon.complete_name,
+            region: listing.region.clone(),
+            status: listing.status.to_string(),
+        })
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct PermitRow {
+    id: i64,
+    taxon: String,
+    region: String,
+    number: String,
+    #[tabled(display_with = "table_display_option")]
+    expires: Option<String>,
+    #[tabled(display_with = "table_display_option")]
+    notes: Option<String>,
+}
+
+impl PermitRow {
+    pub async fn new(permit: &Permit, pool: &Pool<Sqlite>) -> Result<Self> {
+        let taxon = Taxon::load(permit.tsn, pool).await?;
+        Ok(Self {
+            id: permit.id,
+            taxon: taxon.complete_name,
+            region: permit.region.clone(),
+            number: permit.number.clone(),
+            expires: permit.expires.map(|d| d.to_string()),
+            notes: permit.notes.clone(),
+        })
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct QualityTestRow {
+    id: i64,
+    tested: String,
+    #[tabled(display_with = "table_display_option")]
+    fill: Option<String>,
+    #[tabled(display_with = "table_display_option")]
+    purity: Option<String>,
+    #[tabled(display_with = "table_display_option")]
+    tester: Option<String>,
+}
+
+impl QualityTestRow {
+    pub fn new(test: &QualityTest) -> Self {
+        Self {
+            id: test.id,
+            tested: test.tested.to_string(),
+            fill: test.fill.map(|fill| match test.method {
+                Some(method) => format!("{fill}% ({method})"),
+                None => format!("{fill}%"),
+            }),
+            purity: test.purity.map(|purity| format!("{purity}%")),
+            tester: test.tester.clone(),
+        }
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct SampleFlagRow {
+    #[tabled(rename = "Sample")]
+    sampleid: i64,
+    taxon: String,
+    source: String,
+    reason: String,
+    flagged: String,
+}
+
+impl SampleFlagRow {
+    pub fn new(sample: &Sample, flag: &SampleFlag, tz: &TimeZone) -> Result<Self> {
+        Ok(Self {
+            sampleid: sample.id,
+            taxon: sample.taxon.object()?.complete_name.clone(),
+            source: sample.source.object()?.name.clone(),
+            reason: flag.reason.clone(),
+            flagged: format_date(flag.created, tz).unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct TripRow {
+    id: i64,
+    name: String,
+    date: String,
+    participants: String,
+}
+
+impl TripRow {
+    pub fn new(trip: &Trip) -> Self {
+        Self {
+            id: trip.id,
+            name: trip.name.clone(),
+            date: trip.date.to_string(),
+            participants: trip.participants.join(", "),
+        }
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct NoteRow {
+    date: String,
+    #[tabled(rename = "Type")]
+    kind: String,
+    sample: i64,
+    taxon: String,
+    project: String,
+    summary: String,
+}
+
+impl NoteRow {
+    pub fn new(note: &NoteEntry) -> Self {
+        Self {
+            date: note.date.map(|d| d.to_string()).unwrap_or_default(),
+            kind: note
+                .kind
+                .map(|k| format!("{k:?}"))
+                .unwrap_or_else(|| "Sample notes".to_string()),
+            sample: note.sampleid,
+            taxon: note.taxon.clone(),
+            project: note
+                .project
+                .as_ref()
+                .map(|p| p.name.clone())
+                .unwrap_or_default(),
+            summary: note.summary.clone(),
+        }
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct NoteTemplateRow {
+    id: i64,
+    name: String,
+    #[tabled(rename = "Type")]
+    kind: String,
+    summary: String,
+}
+
+impl NoteTemplateRow {
+    pub fn new(template: &NoteTemplate) -> Self {
+        Self {
+            id: template.id,
+            name: template.name.clone(),
+            kind: format!("{:?}", template.kind),
+            summary: template.summary.clone(),
+        }
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct ReportRow {
+    id: i64,
+    name: String,
+    format: String,
+    filter: String,
+}
+
+impl ReportRow {
+    pub fn new(report: &Report) -> Self {
+        Self {
+            id: report.id,
+            name: report.name.clone(),
+            format: report.format.to_string(),
+            filter: report.filter.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct TripTaxonRow {
+    id: i64,
+    taxon: String,
+    samples: i64,
+}
+
+impl TripTaxonRow {
+    pub fn new(taxon: &TripTaxon) -> Self {
+        Self {
+            id: taxon.tsn,
+            taxon: taxon.complete_name.clone(),
+            samples: taxon.nsamples,
+        }
+    }
+}
+
+#[derive(Tabled)]
+#[tabled(rename_all = "PascalCase")]
+pub struct TripSourceRow {
+    id: i64,
+    source: String,
+    samples: i64,
+}
+
+impl TripSourceRow {
+    pub fn new(source: &TripSource) -> Self {
+        Self {
+            id: source.id,
+            source: source.name.clone(),
+            samples: source.nsamples,
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use crate::{auth::SqliteUser, error, state::AppState};
+use axum::{extract::State, routing::post, Json, Router};
+use libseed::{deletion::DeleteImpact, organization::Permission, sample::Sample, source::Source};
+use serde::Deserialize;
+use uuid::Uuid;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/", post(delete_impact))
+}
+
+/// The objects that are about to be deleted
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Selection {
+    #[serde(default)]
+    samples: Vec<Uuid>,
+    /// the samples of these sources are assumed to be deleted with them
+    #[serde(default)]
+    sources: Vec<Uuid>,
+}
+
+/// Summarize what would be removed when the selected samples and sources are deleted. Nothing is
+/// deleted.
+async fn delete_impact(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Json(selection): Json<Selection>,
+) -> Result<Json<DeleteImpact>, error::Error> {
+    let mut sampleids = Vec::new();
+    for uuid in selection.samples {
+        let sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+        user.require(&sample, Permission::Manage, &state.dbpool)
+            .await?;
+        sampleids.push(sample.id);
+    }
+    let mut sourceids = Vec::new();
+    for uuid in selection.sources {
+        let src = Source::load_uuid(uuid, &state.dbpool).await?;
+        user.require(&src, Permission::Manage, &state.dbpool)
+            .await?;
+        sourceids.push(src.id);
+    }
+    Ok(Json(
+        DeleteImpact::load(&sampleids, &sourceids, &state.dbpool).await?,
+    ))
+}
@@ -0,0 +1,62 @@
+use crate::{auth::SqliteUser, error, state::AppState};
+use axum::{
+    extract::{Path, Query, State},
+    routing::get,
+    Json, Router,
+};
+use libseed::{
+    empty_string_as_none,
+    filter::{Cmp, CompoundFilter, Op, SortSpecs},
+    taxonomy::{Germination, GerminationFilter, GerminationSort},
+};
+use serde::Deserialize;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_codes))
+        .route("/:id", get(show_code))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct GerminationParams {
+    /// only codes that contain this text
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    code: Option<String>,
+    /// only codes that apply to the taxon with this id
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    taxon: Option<i64>,
+    /// only codes that apply to the taxon of one of the user's samples
+    #[serde(default)]
+    used_by_my_samples: bool,
+    /// one or more sort keys, e.g. `-taxa,code`
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    sort: Option<SortSpecs<GerminationSort>>,
+}
+
+async fn list_codes(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Query(params): Query<GerminationParams>,
+) -> Result<Json<Vec<Germination>>, error::Error> {
+    let mut fbuilder = CompoundFilter::builder(Op::And);
+    if let Some(code) = params.code {
+        fbuilder = fbuilder.push(GerminationFilter::Code(Cmp::Like, code));
+    }
+    if let Some(tsn) = params.taxon {
+        fbuilder = fbuilder.push(GerminationFilter::Taxon(tsn));
+    }
+    if params.used_by_my_samples {
+        fbuilder = fbuilder.push(GerminationFilter::UsedBySamples(user.id));
+    }
+    Ok(Json(
+        Germination::load_all(Some(fbuilder.build()), params.sort, &state.dbpool).await?,
+    ))
+}
+
+async fn show_code(
+    _user: SqliteUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Json<Germination>, error::Error> {
+    Ok(Json(Germination::load(id, &state.dbpool).await?))
+}
@@ -0,0 +1,549 @@
+//! A GraphQL schema as an alternative to the REST api, for clients that want to choose the data
+//! that they fetch in a single request. Lists are paginated, and their filter arguments are
+//! translated to the filters of [libseed], so only the requested page is loaded.
+use crate::{auth::SqliteUser, state::AppState};
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Enum, InputObject, Object, OutputType, Result,
+    Schema, SimpleObject, ID,
+};
+use axum::{extract::State, routing::post, Json, Router};
+use libseed::{
+    filter::{Cmp, CompoundFilter, DynFilterPart, LimitSpec, Op, SortSpecs},
+    loadable::Loadable,
+    organization::Permission,
+    project::{self, allocation, Allocation, Project},
+    sample::{self, Sample},
+    source::{self, Source},
+    taxonomy::{self, Rank, Taxon},
+};
+use std::{str::FromStr, sync::OnceLock};
+use uuid::Uuid;
+
+/// The number of items in a page if the query doesn't ask for a different number
+const DEFAULT_PAGE_SIZE: i32 = 50;
+/// The largest number of items that a single page can contain
+const MAX_PAGE_SIZE: i32 = 500;
+/// The deepest nesting of fields that a query can select, which stops clients from following the
+/// references between objects indefinitely, e.g. from a source to its samples and back
+const MAX_QUERY_DEPTH: usize = 10;
+/// The largest number of fields that a query can resolve, counting the fields of every item of a
+/// page, so a query can't multiply the size of nested lists
+const MAX_QUERY_COMPLEXITY: usize = 10_000;
+
+type SeedSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+fn schema() -> &'static SeedSchema {
+    static SCHEMA: OnceLock<SeedSchema> = OnceLock::new();
+    SCHEMA.get_or_init(|| {
+        Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .limit_depth(MAX_QUERY_DEPTH)
+            .limit_complexity(MAX_QUERY_COMPLEXITY)
+            .finish()
+    })
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/", post(execute))
+}
+
+async fn execute(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Json(request): Json<async_graphql::Request>,
+) -> Json<async_graphql::Response> {
+    Json(schema().execute(request.data(state).data(user)).await)
+}
+
+/// The application state and the user that the query is executed for
+fn context<'a>(ctx: &Context<'a>) -> Result<(&'a AppState, &'a SqliteUser)> {
+    Ok((ctx.data::<AppState>()?, ctx.data::<SqliteUser>()?))
+}
+
+fn parse_uuid(id: &ID) -> Result<Uuid> {
+    Uuid::parse_str(id).map_err(|_| "Invalid id".into())
+}
+
+/// Turn the pagination arguments of a list into the limit of its query
+fn limit(first: Option<i32>, offset: Option<i32>) -> Result<LimitSpec> {
+    let first = first.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&first) {
+        return Err(format!("'first' must be between 1 and {MAX_PAGE_SIZE}").into());
+    }
+    match offset.unwrap_or(0) {
+        offset if offset < 0 => Err("'offset' can't be negative".into()),
+        offset => Ok(LimitSpec(first, Some(offset))),
+    }
+}
+
+/// The complexity of a page of a list, which resolves the selected fields for every item of the
+/// page
+fn page_complexity(first: Option<i32>, child_complexity: usize) -> usize {
+    let size = first.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    usize::try_from(size).unwrap_or(1) * child_complexity
+}
+
+/// A single page of a list, along with the number of items in the whole list
+#[derive(SimpleObject)]
+#[graphql(concrete(name = "SamplePage", params(SampleObject)))]
+#[graphql(concrete(name = "SourcePage", params(SourceObject)))]
+#[graphql(concrete(name = "ProjectPage", params(ProjectObject)))]
+#[graphql(concrete(name = "AllocationPage", params(AllocationObject)))]
+#[graphql(concrete(name = "TaxonPage", params(TaxonObject)))]
+struct Page<T: OutputType> {
+    total_count: i64,
+    nodes: Vec<T>,
+}
+
+/// The conditions that the samples of a list have to match
+#[derive(InputObject, Default)]
+struct SampleFilter {
+    /// samples of the given taxon or of any taxon below it
+    taxon: Option<i64>,
+    /// samples with a scientific or common name that contains the given text
+    taxon_name: Option<String>,
+    source: Option<i64>,
+    family: Option<String>,
+    /// purchased (`true`) or collected (`false`) samples
+    purchased: Option<bool>,
+    /// samples whose quantity is below the user's threshold for them
+    low_stock: Option<bool>,
+    /// samples whose quantity has not been recorded
+    quantity_missing: Option<bool>,
+}
+
+impl SampleFilter {
+    fn build(self, userid: i64) -> DynFilterPart {
+        let mut builder = CompoundFilter::builder(Op::And);
+        if let Some(tsn) = self.taxon {
+            builder = builder.push(sample::Filter::AncestorTsn(tsn));
+        }
+        if let Some(name) = self.taxon_name {
+            builder = builder.push(sample::Filter::TaxonNameLike(name));
+        }
+        if let Some(srcid) = self.source {
+            builder = builder.push(sample::Filter::SourceId(Cmp::Equal, srcid));
+        }
+        if let Some(family) = self.family {
+            builder = builder.push(sample::Filter::Family(family));
+        }
+        if let Some(purchased) = self.purchased {
+            builder = builder.push(sample::Filter::Purchased(purchased));
+        }
+        if self.low_stock == Some(true) {
+            builder = builder.push(sample::Filter::LowStock(userid));
+        }
+        if self.quantity_missing == Some(true) {
+            builder = builder.push(sample::Filter::QuantityMissing);
+        }
+        builder.build()
+    }
+}
+
+async fn sample_page(
+    ctx: &Context<'_>,
+    filter: DynFilterPart,
+    sort: Option<String>,
+    first: Option<i32>,
+    offset: Option<i32>,
+) -> Result<Page<SampleObject>> {
+    let (state, user) = context(ctx)?;
+    let sort = sort
+        .map(|s| SortSpecs::<sample::Sort>::from_str(&s))
+        .transpose()?;
+    let (nodes, total_count) = user
+        .scope()
+        .sample_page(Some(filter), sort, limit(first, offset)?, &state.dbpool)
+        .await?;
+    Ok(Page {
+        total_count,
+        nodes: nodes.into_iter().map(SampleObject).collect(),
+    })
+}
+
+/// The conditions that the sources of a list have to match
+#[derive(InputObject, Default)]
+struct SourceFilter {
+    /// sources with a name that contains the given text
+    name: Option<String>,
+    /// sources whose coordinates have not been recorded
+    location_missing: Option<bool>,
+}
+
+/// The conditions that the taxa of a list have to match
+#[derive(InputObject, Default)]
+struct TaxonFilter {
+    /// taxa with a scientific name that contains the given text
+    name: Option<String>,
+    /// taxa with a common name that contains the given text
+    common_name: Option<String>,
+    /// the rank of the taxa, e.g. `species`
+    rank: Option<String>,
+    /// the direct children of the given taxon
+    parent: Option<i64>,
+    /// taxa that are (`true`) or aren't (`false`) native to Minnesota
+    minnesota: Option<bool>,
+}
+
+/// The stage that an allocated sample has reached
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+#[graphql(name = "AllocationStatus", remote = "project::AllocationStatus")]
+enum StatusValue {
+    Allocated,
+    Sown,
+    Germinated,
+    Planted,
+    Established,
+    Failed,
+}
+
+struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The sample with the given id, if the user can view it
+    async fn sample(&self, ctx: &Context<'_>, id: ID) -> Result<SampleObject> {
+        let (state, user) = context(ctx)?;
+        let sample = Sample::load_uuid(parse_uuid(&id)?, &state.dbpool).await?;
+        user.require(&sample, Permission::View, &state.dbpool)
+            .await?;
+        Ok(SampleObject(sample))
+    }
+
+    /// The samples that the user has access to, sorted taxonomically unless a sort order such as
+    /// `-date,name` is given
+    #[graphql(complexity = "page_complexity(first, child_complexity)")]
+    async fn samples(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<SampleFilter>,
+        sort: Option<String>,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> Result<Page<SampleObject>> {
+        let (_, user) = context(ctx)?;
+        let filter = filter.unwrap_or_default().build(user.id);
+        sample_page(ctx, filter, sort, first, offset).await
+    }
+
+    /// The source with the given id, if the user can view it
+    async fn source(&self, ctx: &Context<'_>, id: ID) -> Result<SourceObject> {
+        let (state, user) = context(ctx)?;
+        let source = Source::load_uuid(parse_uuid(&id)?, &state.dbpool).await?;
+        user.require(&source, Permission::View, &state.dbpool)
+            .await?;
+        SourceObject::new(source, user, state).await
+    }
+
+    /// The sources that the user has access to, sorted by name
+    #[graphql(complexity = "page_complexity(first, child_complexity)")]
+    async fn sources(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<SourceFilter>,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> Result<Page<SourceObject>> {
+        let (state, user) = context(ctx)?;
+        let filter = filter.unwrap_or_default();
+        let mut builder = CompoundFilter::builder(Op::And);
+        if let Some(name) = filter.name {
+            builder = builder.push(source::Filter::Name(Cmp::Like, name));
+        }
+        if filter.location_missing == Some(true) {
+            builder = builder.push(source::Filter::LocationMissing);
+        }
+        let (sources, total_count) = user
+            .scope()
+            .source_page(Some(builder.build()), limit(first, offset)?, &state.dbpool)
+            .await?;
+        let mut nodes = Vec::new();
+        for source in sources {
+            nodes.push(SourceObject::new(source, user, state).await?);
+        }
+        Ok(Page { total_count, nodes })
+    }
+
+    /// The project with the given id, if the user can view it
+    async fn project(&self, ctx: &Context<'_>, id: ID) -> Result<ProjectObject> {
+        let (state, user) = context(ctx)?;
+        let project = Project::load_uuid(parse_uuid(&id)?, &state.dbpool).await?;
+        user.require(&project, Permission::View, &state.dbpool)
+            .await?;
+        Ok(ProjectObject(project))
+    }
+
+    /// The projects that the user has access to, sorted by name
+    #[graphql(complexity = "page_complexity(first, child_complexity)")]
+    async fn projects(
+        &self,
+        ctx: &Context<'_>,
+        name: Option<String>,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> Result<Page<ProjectObject>> {
+        let (state, user) = context(ctx)?;
+        let filter = name.map(|name| project::Filter::Name(Cmp::Like, name).into());
+        let (nodes, total_count) = user
+            .scope()
+            .project_page(filter, limit(first, offset)?, &state.dbpool)
+            .await?;
+        Ok(Page {
+            total_count,
+            nodes: nodes.into_iter().map(ProjectObject).collect(),
+        })
+    }
+
+    /// The taxon with the given ITIS taxonomic serial number
+    async fn taxon(&self, ctx: &Context<'_>, id: i64) -> Result<TaxonObject> {
+        let (state, _) = context(ctx)?;
+        Ok(TaxonObject(Taxon::load(id, &state.dbpool).await?))
+    }
+
+    /// The accepted plant taxa, sorted taxonomically
+    #[graphql(complexity = "page_complexity(first, child_complexity)")]
+    async fn taxa(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<TaxonFilter>,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> Result<Page<TaxonObject>> {
+        let (state, _) = context(ctx)?;
+        let filter = filter.unwrap_or_default();
+        let mut builder = CompoundFilter::builder(Op::And);
+        if let Some(name) = filter.name {
+            builder = builder.push(taxonomy::Filter::CompleteName(format!("%{name}%")));
+        }
+        if let Some(name) = filter.common_name {
+            builder = builder.push(taxonomy::Filter::Vernacular(name));
+        }
+        if let Some(rank) = filter.rank {
+            let rank = Rank::from_str(&rank).map_err(|_| format!("Invalid rank '{rank}'"))?;
+            builder = builder.push(taxonomy::Filter::Rank(rank));
+        }
+        if let Some(parent) = filter.parent {
+            builder = builder.push(taxonomy::Filter::ParentId(parent));
+        }
+        if let Some(minnesota) = filter.minnesota {
+            builder = builder.push(taxonomy::Filter::Minnesota(minnesota));
+        }
+        let filter = builder.build();
+        let nodes = Taxon::load_all(
+            Some(filter.clone()),
+            Some(limit(first, offset)?),
+            &state.dbpool,
+        )
+        .await?;
+        Ok(Page {
+            total_count: Taxon::count(Some(filter), &state.dbpool).await?,
+            nodes: nodes.into_iter().map(TaxonObject).collect(),
+        })
+    }
+}
+
+struct SampleObject(Sample);
+
+#[Object(name = "Sample")]
+impl SampleObject {
+    async fn id(&self) -> ID {
+        ID(self.0.uuid.to_string())
+    }
+
+    async fn taxon(&self, ctx: &Context<'_>) -> Result<TaxonObject> {
+        let (state, _) = context(ctx)?;
+        Ok(TaxonObject(
+            Taxon::load(self.0.taxon.id(), &state.dbpool).await?,
+        ))
+    }
+
+    async fn source(&self, ctx: &Context<'_>) -> Result<SourceObject> {
+        let (state, user) = context(ctx)?;
+        let source = Source::load(self.0.source.id(), &state.dbpool).await?;
+        SourceObject::new(source, user, state).await
+    }
+
+    async fn quantity(&self) -> Option<i64> {
+        self.0.quantity
+    }
+
+    async fn month(&self) -> Option<u32> {
+        self.0.month
+    }
+
+    async fn year(&self) -> Option<u32> {
+        self.0.year
+    }
+
+    async fn notes(&self) -> Option<&str> {
+        self.0.notes.as_deref()
+    }
+
+    async fn certainty(&self) -> String {
+        self.0.certainty.to_string()
+    }
+
+    async fn vendor(&self) -> Option<&str> {
+        self.0.purchase.as_ref().map(|p| p.vendor.as_str())
+    }
+
+    async fn version(&self) -> i64 {
+        self.0.version
+    }
+}
+
+struct SourceObject(Source);
+
+impl SourceObject {
+    /// Wrap a source, revealing its exact location only to the users who may see it
+    async fn new(mut source: Source, user: &SqliteUser, state: &AppState) -> Result<Self> {
+        source.reveal_for(user.id, &state.dbpool).await?;
+        Ok(Self(source))
+    }
+}
+
+#[Object(name = "Source")]
+impl SourceObject {
+    async fn id(&self) -> ID {
+        ID(self.0.uuid.to_string())
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn description(&self) -> Option<&str> {
+        self.0.description.as_deref()
+    }
+
+    /// the latitude, which is generalized for sensitive sources unless the user can edit them
+    async fn latitude(&self) -> Option<f64> {
+        self.0.visible_coordinates().map(|(latitude, _)| latitude)
+    }
+
+    /// the longitude, which is generalized for sensitive sources unless the user can edit them
+    async fn longitude(&self) -> Option<f64> {
+        self.0.visible_coordinates().map(|(_, longitude)| longitude)
+    }
+
+    async fn version(&self) -> i64 {
+        self.0.version
+    }
+
+    /// the samples of this source that the user has access to
+    #[graphql(complexity = "page_complexity(first, child_complexity)")]
+    async fn samples(
+        &self,
+        ctx: &Context<'_>,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> Result<Page<SampleObject>> {
+        let (_, user) = context(ctx)?;
+        let filter = SampleFilter {
+            source: Some(self.0.id),
+            ..Default::default()
+        }
+        .build(user.id);
+        sample_page(ctx, filter, None, first, offset).await
+    }
+}
+
+struct ProjectObject(Project);
+
+#[Object(name = "Project")]
+impl ProjectObject {
+    async fn id(&self) -> ID {
+        ID(self.0.uuid.to_string())
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn description(&self) -> Option<&str> {
+        self.0.description.as_deref()
+    }
+
+    async fn version(&self) -> i64 {
+        self.0.version
+    }
+
+    /// the samples that are allocated to this project, sorted taxonomically
+    #[graphql(complexity = "page_complexity(first, child_complexity)")]
+    async fn allocations(
+        &self,
+        ctx: &Context<'_>,
+        status: Option<StatusValue>,
+        first: Option<i32>,
+        offset: Option<i32>,
+    ) -> Result<Page<AllocationObject>> {
+        let (state, user) = context(ctx)?;
+        let mut builder =
+            CompoundFilter::builder(Op::And).push(allocation::Filter::ProjectId(self.0.id));
+        if let Some(status) = status {
+            builder = builder.push(allocation::Filter::Status(status.into()));
+        }
+        let (nodes, total_count) = user
+            .scope()
+            .allocation_page(
+                Some(builder.build()),
+                None,
+                limit(first, offset)?,
+                &state.dbpool,
+            )
+            .await?;
+        Ok(Page {
+            total_count,
+            nodes: nodes.into_iter().map(AllocationObject).collect(),
+        })
+    }
+}
+
+struct AllocationObject(Allocation);
+
+#[Object(name = "Allocation")]
+impl AllocationObject {
+    async fn id(&self) -> ID {
+        ID(self.0.uuid.to_string())
+    }
+
+    async fn status(&self) -> StatusValue {
+        self.0.status.into()
+    }
+
+    async fn sample(&self, ctx: &Context<'_>) -> Result<SampleObject> {
+        let (state, _) = context(ctx)?;
+        Ok(SampleObject(
+            Sample::load(self.0.sample.id, &state.dbpool).await?,
+        ))
+    }
+}
+
+struct TaxonObject(Taxon);
+
+#[Object(name = "Taxon")]
+impl TaxonObject {
+    /// the ITIS taxonomic serial number
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn rank(&self) -> String {
+        self.0.rank.to_string()
+    }
+
+    async fn complete_name(&self) -> &str {
+        &self.0.complete_name
+    }
+
+    async fn common_names(&self) -> &[String] {
+        &self.0.vernaculars
+    }
+
+    async fn parent(&self, ctx: &Context<'_>) -> Result<Option<TaxonObject>> {
+        let (state, _) = context(ctx)?;
+        match self.0.parentid {
+            Some(id) => Ok(Some(TaxonObject(Taxon::load(id, &state.dbpool).await?))),
+            None => Ok(None),
+        }
+    }
+}
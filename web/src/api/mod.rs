@@ -0,0 +1,28 @@
+use crate::state::AppState;
+use axum::Router;
+use serde::Serialize;
+
+mod deletion;
+mod germination;
+pub mod graphql;
+mod project;
+mod sample;
+mod stats;
+#[cfg(test)]
+mod tests;
+
+/// The body of an error response from the JSON API
+#[derive(Serialize)]
+pub struct ApiError {
+    pub status: u16,
+    pub error: String,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .nest("/stats/", stats::router())
+        .nest("/sample/", sample::router())
+        .nest("/project/", project::router())
+        .nest("/delete-impact", deletion::router())
+        .nest("/germination/", germination::router())
+}
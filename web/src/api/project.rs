@@ -0,0 +1,112 @@
+use crate::{auth::SqliteUser, error, state::AppState};
+use axum::{
+    extract::{rejection::QueryRejection, Path, Query, State},
+    routing::{get, put},
+    Json, Router,
+};
+use libseed::{
+    filter::{CompoundFilter, Op},
+    organization::Permission,
+    project::{
+        allocation,
+        status::{AllocationStatus, Period, StatusEvent, StatusProgress},
+        Allocation, Project,
+    },
+};
+use serde::Deserialize;
+use time::Date;
+use uuid::Uuid;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/:id/progress", get(progress))
+        .route("/:id/sample/:alloc/events", get(events))
+        .route("/:id/sample/:alloc/status", put(update_status))
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProgressParams {
+    #[serde(default)]
+    period: Period,
+    /// only count the status changes on or after this date
+    since: Option<Date>,
+    /// only count the status changes on or before this date
+    until: Option<Date>,
+}
+
+/// The number of the project's allocations that reached each status, per period
+async fn progress(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    query: Result<Query<ProgressParams>, QueryRejection>,
+) -> Result<Json<Vec<StatusProgress>>, error::Error> {
+    let Query(params) = query.map_err(error::Error::UnprocessableEntityQueryRejection)?;
+    let project = Project::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&project, Permission::View, &state.dbpool)
+        .await?;
+    Ok(Json(
+        StatusProgress::load_project(
+            project.id,
+            params.period,
+            params.since,
+            params.until,
+            &state.dbpool,
+        )
+        .await?,
+    ))
+}
+
+/// Load an allocation of the given project that the user has access to
+async fn load_allocation(
+    user: &SqliteUser,
+    projectid: Uuid,
+    allocid: Uuid,
+    state: &AppState,
+) -> Result<Allocation, error::Error> {
+    Ok(user
+        .scope()
+        .allocation(
+            CompoundFilter::builder(Op::And)
+                .push(allocation::Filter::Uuid(allocid))
+                .push(allocation::Filter::ProjectUuid(projectid))
+                .build(),
+            &state.dbpool,
+        )
+        .await?)
+}
+
+/// The status changes of an allocation, most recent changes first
+async fn events(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path((projectid, allocid)): Path<(Uuid, Uuid)>,
+) -> Result<Json<Vec<StatusEvent>>, error::Error> {
+    let allocation = load_allocation(&user, projectid, allocid, &state).await?;
+    Ok(Json(
+        StatusEvent::load_allocation(allocation.id, &state.dbpool).await?,
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatusParams {
+    status: AllocationStatus,
+}
+
+/// Change the status of an allocation, returning the updated allocation
+async fn update_status(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path((projectid, allocid)): Path<(Uuid, Uuid)>,
+    Json(params): Json<StatusParams>,
+) -> Result<Json<Allocation>, error::Error> {
+    let mut allocation = load_allocation(&user, projectid, allocid, &state).await?;
+    user.require(&allocation.project, Permission::Edit, &state.dbpool)
+        .await?;
+    allocation
+        .set_status(params.status, Some(user.id), &state.dbpool)
+        .await?;
+    Ok(Json(allocation))
+}
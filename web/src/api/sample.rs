@@ -0,0 +1,122 @@
+use crate::{auth::SqliteUser, error, state::AppState};
+use axum::{
+    extract::{Path, State},
+    routing::get,
+    Json, Router,
+};
+use libseed::{
+    history,
+    loadable::{Loadable, PartialUpdate},
+    organization::Permission,
+    sample::{Certainty, Sample, SampleField},
+    source::Source,
+};
+use serde::{Deserialize, Deserializer};
+use uuid::Uuid;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/:id", get(show_sample).patch(update_sample))
+}
+
+/// Distinguish a field that is missing from the request (`None`) from a field that is explicitly
+/// set to `null` (`Some(None)`)
+fn present<'de, D, T>(de: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(de).map(Some)
+}
+
+/// The fields of a sample to modify. Fields that are missing are left unchanged, and optional
+/// fields that are `null` are cleared.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SamplePatch {
+    /// the version of the sample that the changes are based on. The update fails if the sample
+    /// was modified since then. This is required, but it is parsed as an `Option` so that a
+    /// missing version can be reported as such.
+    version: Option<i64>,
+    taxon: Option<i64>,
+    source: Option<i64>,
+    #[serde(default, deserialize_with = "present")]
+    quantity: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "present")]
+    notes: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    month: Option<Option<u32>>,
+    #[serde(default, deserialize_with = "present")]
+    year: Option<Option<u32>>,
+    certainty: Option<Certainty>,
+}
+
+impl SamplePatch {
+    fn fields(&self, sample: &Sample) -> Vec<SampleField> {
+        let mut fields = Vec::new();
+        if let Some(taxon) = self.taxon {
+            fields.push(SampleField::Taxon(taxon));
+        }
+        if let Some(source) = self.source {
+            fields.push(SampleField::Source(source));
+        }
+        if let Some(quantity) = self.quantity {
+            fields.push(SampleField::Quantity(quantity));
+        }
+        if let Some(notes) = &self.notes {
+            fields.push(SampleField::Notes(notes.clone()));
+        }
+        // the month and year are saved together so that they are validated together
+        if self.month.is_some() || self.year.is_some() {
+            fields.push(SampleField::Date {
+                month: self.month.unwrap_or(sample.month),
+                year: self.year.unwrap_or(sample.year),
+            });
+        }
+        if let Some(certainty) = &self.certainty {
+            fields.push(SampleField::Certainty(certainty.clone()));
+        }
+        fields
+    }
+}
+
+async fn show_sample(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+) -> Result<Json<Sample>, error::Error> {
+    let sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&sample, Permission::View, &state.dbpool)
+        .await?;
+    Ok(Json(sample))
+}
+
+/// Modify only the fields of a sample that are given in the request, returning the updated sample.
+/// The request has to include the version of the sample that the changes are based on, so that
+/// changes made by someone else in the meantime aren't overwritten.
+async fn update_sample(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    Json(patch): Json<SamplePatch>,
+) -> Result<Json<Sample>, error::Error> {
+    let Some(version) = patch.version else {
+        return Err(error::Error::PreconditionRequired(
+            "The version of the sample that is modified is required".to_string(),
+        ));
+    };
+    let mut sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&sample, Permission::Edit, &state.dbpool)
+        .await?;
+    if let Some(srcid) = patch.source {
+        let source = Source::load(srcid, &state.dbpool).await?;
+        user.require(&source, Permission::View, &state.dbpool)
+            .await?;
+    }
+    sample.version = version;
+    let before = sample.clone();
+    sample
+        .update_fields(&patch.fields(&sample), &state.dbpool)
+        .await?;
+    history::record(&before, &sample, Some(user.id), &state.dbpool).await?;
+    Ok(Json(Sample::load(sample.id, &state.dbpool).await?))
+}
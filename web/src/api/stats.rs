@@ -0,0 +1,105 @@
+use crate::{auth::SqliteUser, error, state::AppState};
+use axum::{extract::State, routing::get, Json, Router};
+use libseed::{
+    preferences::Preferences,
+    stats::{self, CollectionYear, GroupCount, ProjectStatus, VendorSummary},
+};
+use serde::Serialize;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(all_stats))
+        .route("/families", get(families))
+        .route("/years", get(years))
+        .route("/sources", get(sources))
+        .route("/origins", get(origins))
+        .route("/vendors", get(vendors))
+        .route("/projects", get(projects))
+}
+
+/// The collection year that the user's per-year statistics are grouped by
+async fn collection_year(
+    user: &SqliteUser,
+    state: &AppState,
+) -> Result<CollectionYear, error::Error> {
+    Ok(Preferences::load(user.id, &state.dbpool)
+        .await?
+        .collection_year()?)
+}
+
+#[derive(Serialize)]
+struct AllStats {
+    families: Vec<GroupCount>,
+    years: Vec<GroupCount>,
+    sources: Vec<GroupCount>,
+    origins: Vec<GroupCount>,
+    projects: Vec<ProjectStatus>,
+}
+
+async fn all_stats(
+    user: SqliteUser,
+    State(state): State<AppState>,
+) -> Result<Json<AllStats>, error::Error> {
+    let years = collection_year(&user, &state).await?;
+    Ok(Json(AllStats {
+        families: stats::samples_per_family(user.id, &state.dbpool).await?,
+        years: stats::samples_per_year(user.id, &years, &state.dbpool).await?,
+        sources: stats::samples_per_source(user.id, &state.dbpool).await?,
+        origins: stats::samples_per_origin(user.id, &state.dbpool).await?,
+        projects: stats::project_status(user.id, &state.dbpool).await?,
+    }))
+}
+
+async fn families(
+    user: SqliteUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<GroupCount>>, error::Error> {
+    Ok(Json(
+        stats::samples_per_family(user.id, &state.dbpool).await?,
+    ))
+}
+
+async fn years(
+    user: SqliteUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<GroupCount>>, error::Error> {
+    let years = collection_year(&user, &state).await?;
+    Ok(Json(
+        stats::samples_per_year(user.id, &years, &state.dbpool).await?,
+    ))
+}
+
+async fn sources(
+    user: SqliteUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<GroupCount>>, error::Error> {
+    Ok(Json(
+        stats::samples_per_source(user.id, &state.dbpool).await?,
+    ))
+}
+
+async fn origins(
+    user: SqliteUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<GroupCount>>, error::Error> {
+    Ok(Json(
+        stats::samples_per_origin(user.id, &state.dbpool).await?,
+    ))
+}
+
+async fn vendors(
+    user: SqliteUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<VendorSummary>>, error::Error> {
+    let years = collection_year(&user, &state).await?;
+    Ok(Json(
+        stats::vendor_summary(user.id, &years, &state.dbpool).await?,
+    ))
+}
+
+async fn projects(
+    user: SqliteUser,
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ProjectStatus>>, error::Error> {
+    Ok(Json(stats::project_status(user.id, &state.dbpool).await?))
+}
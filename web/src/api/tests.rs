@@ -0,0 +1,785 @@
+use crate::{html::tests::login, test_app, API_PREFIX, GRAPHQL_PATH};
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use http_body_util::BodyExt;
+use libseed::{
+    deletion::DeleteImpact,
+    loadable::Loadable,
+    project::{
+        status::{Period, StatusEvent, StatusProgress},
+        Allocation, AllocationStatus,
+    },
+    sample::Sample,
+    stats::GroupCount,
+    taxonomy::Germination,
+    user::User,
+};
+use sqlx::{Pool, Sqlite};
+use test_log::test;
+use tower::Service;
+use uuid::Uuid;
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_stats(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool).await.expect("failed to create test app");
+
+    // the api requires a logged in user
+    let req = Request::builder()
+        .uri(format!("{API_PREFIX}stats/years"))
+        .method("GET")
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    for path in [
+        "", "families", "years", "sources", "origins", "vendors", "projects",
+    ] {
+        let req = Request::builder()
+            .uri(format!("{API_PREFIX}stats/{path}"))
+            .method("GET")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request");
+        let response = app
+            .as_service()
+            .call(req)
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK, "path {path}");
+    }
+
+    let req = Request::builder()
+        .uri(format!("{API_PREFIX}stats/years"))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let years: Vec<GroupCount> = serde_json::from_slice(&body).expect("Failed to parse json");
+    let total: i64 = years.iter().map(|y| y.count).sum();
+    assert_eq!(total, 3);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_token_auth(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let user = User::load(1, &pool).await.expect("Failed to load user");
+    let (_, token) = user
+        .create_token("test", &pool)
+        .await
+        .expect("Failed to create token");
+
+    let request = |auth: String| {
+        Request::builder()
+            .uri(format!("{API_PREFIX}stats/years"))
+            .method("GET")
+            .header("Authorization", auth)
+            .body(Body::empty())
+            .expect("Failed to build request")
+    };
+    let response = app
+        .as_service()
+        .call(request(format!("Bearer {token}")))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .as_service()
+        .call(request("Bearer sct_1_0123456789abcdef".to_string()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_patch_sample(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let before = Sample::load(2, &pool).await.expect("Failed to load sample");
+    let other = Sample::load(4, &pool).await.expect("Failed to load sample");
+    let mut patch = |uuid: Uuid, body: serde_json::Value| {
+        let req = Request::builder()
+            .uri(format!("{API_PREFIX}sample/{uuid}"))
+            .method("PATCH")
+            .header("Cookie", cookie.clone())
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .expect("Failed to build request");
+        app.as_service().call(req)
+    };
+
+    let response = patch(
+        before.uuid,
+        serde_json::json!({"version": before.version, "quantity": 80, "notes": null}),
+    )
+    .await
+    .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let returned: serde_json::Value = serde_json::from_slice(&body).expect("Failed to parse json");
+    assert_eq!(returned["quantity"], 80);
+    let after = Sample::load(2, &pool).await.expect("Failed to load sample");
+    assert_eq!(after.quantity, Some(80));
+    assert_eq!(after.notes, None);
+    // fields that were not part of the request are unchanged
+    assert_eq!(after.month, before.month);
+    assert_eq!(after.year, before.year);
+    assert_eq!(after.source.id(), before.source.id());
+    assert_eq!(after.version, before.version + 1);
+
+    // only the year is changed, the month is kept
+    let response = patch(
+        before.uuid,
+        serde_json::json!({"version": after.version, "year": 2021}),
+    )
+    .await
+    .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let after = Sample::load(2, &pool).await.expect("Failed to load sample");
+    assert_eq!((after.month, after.year), (before.month, Some(2021)));
+
+    for (body, status) in [
+        // stale version
+        (
+            serde_json::json!({"version": before.version, "quantity": 1}),
+            StatusCode::CONFLICT,
+        ),
+        // missing version
+        (
+            serde_json::json!({"quantity": 1}),
+            StatusCode::PRECONDITION_REQUIRED,
+        ),
+        // invalid value
+        (
+            serde_json::json!({"version": after.version, "quantity": -5}),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ),
+        // nothing to update
+        (
+            serde_json::json!({"version": after.version}),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ),
+    ] {
+        let response = patch(before.uuid, body.clone())
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), status, "{body}");
+    }
+
+    // samples of other users can't be modified
+    let response = patch(
+        other.uuid,
+        serde_json::json!({"version": other.version, "quantity": 1}),
+    )
+    .await
+    .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let after = Sample::load(2, &pool).await.expect("Failed to load sample");
+    assert_eq!(after.quantity, Some(80));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_sample_version(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let uuid = Sample::load(2, &pool)
+        .await
+        .expect("Failed to load sample")
+        .uuid;
+    let mut send = |method: &str, body: Option<serde_json::Value>| {
+        let req = Request::builder()
+            .uri(format!("{API_PREFIX}sample/{uuid}"))
+            .method(method)
+            .header("Cookie", cookie.clone())
+            .header("Content-Type", "application/json")
+            .body(body.map(|b| Body::from(b.to_string())).unwrap_or_default())
+            .expect("Failed to build request");
+        app.as_service().call(req)
+    };
+    async fn json(response: axum::response::Response) -> serde_json::Value {
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        serde_json::from_slice(&body).expect("Failed to parse json")
+    }
+
+    // clients read the version along with the sample and send it back with their changes
+    let loaded = json(send("GET", None).await.expect("Failed to execute request")).await;
+    let version = loaded["version"].as_i64().expect("No version in payload");
+    let updated = json(
+        send(
+            "PATCH",
+            Some(serde_json::json!({"version": version, "quantity": 5})),
+        )
+        .await
+        .expect("Failed to execute request"),
+    )
+    .await;
+    assert_eq!(updated["version"], version + 1);
+
+    // a client that still has the old version can't overwrite the change
+    let response = send(
+        "PATCH",
+        Some(serde_json::json!({"version": version, "quantity": 7})),
+    )
+    .await
+    .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let sample = Sample::load(2, &pool).await.expect("Failed to load sample");
+    assert_eq!(sample.quantity, Some(5));
+    assert_eq!(sample.version, version + 1);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_delete_impact(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let own = Sample::load(2, &pool).await.expect("Failed to load sample");
+    let other = Sample::load(4, &pool).await.expect("Failed to load sample");
+    let mut impact = |body: serde_json::Value| {
+        let req = Request::builder()
+            .uri(format!("{API_PREFIX}delete-impact"))
+            .method("POST")
+            .header("Cookie", cookie.clone())
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .expect("Failed to build request");
+        app.as_service().call(req)
+    };
+
+    let response = impact(serde_json::json!({"samples": [own.uuid]}))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let returned: DeleteImpact = serde_json::from_slice(&body).expect("Failed to parse json");
+    let expected = DeleteImpact::load(&[own.id], &[], &pool)
+        .await
+        .expect("Failed to load impact");
+    assert_eq!(returned, expected);
+    assert_eq!(returned.samples, 1);
+    // nothing was deleted
+    assert!(Sample::load(own.id, &pool).await.is_ok());
+
+    for (body, status) in [
+        (
+            serde_json::json!({"samples": [other.uuid]}),
+            StatusCode::UNAUTHORIZED,
+        ),
+        (
+            serde_json::json!({"sources": [Uuid::new_v4()]}),
+            StatusCode::NOT_FOUND,
+        ),
+        (
+            serde_json::json!({"projects": []}),
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ),
+    ] {
+        let response = impact(body.clone())
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), status, "{body}");
+    }
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_allocation_status(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let alloc = Allocation::load(1, &pool)
+        .await
+        .expect("Failed to load allocation");
+    let project = alloc.project.uuid;
+    let mut request = |method: &str, path: String, body: Option<serde_json::Value>| {
+        let req = Request::builder()
+            .uri(format!("{API_PREFIX}project/{path}"))
+            .method(method)
+            .header("Cookie", cookie.clone())
+            .header("Content-Type", "application/json")
+            .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+            .expect("Failed to build request");
+        app.as_service().call(req)
+    };
+    async fn json(response: axum::response::Response) -> serde_json::Value {
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        serde_json::from_slice(&body).expect("Failed to parse json")
+    }
+
+    for status in ["Sown", "Germinated"] {
+        let response = request(
+            "PUT",
+            format!("{project}/sample/{}/status", alloc.uuid),
+            Some(serde_json::json!({ "status": status })),
+        )
+        .await
+        .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json(response).await["status"], status);
+    }
+    let response = request(
+        "PUT",
+        format!("{project}/sample/{}/status", alloc.uuid),
+        Some(serde_json::json!({ "status": "Harvested" })),
+    )
+    .await
+    .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = request(
+        "GET",
+        format!("{project}/sample/{}/events", alloc.uuid),
+        None,
+    )
+    .await
+    .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let events: Vec<StatusEvent> =
+        serde_json::from_value(json(response).await).expect("Failed to parse events");
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].to, AllocationStatus::Germinated);
+    assert_eq!(events[1].from, Some(AllocationStatus::Allocated));
+
+    for (query, count) in [
+        ("", 2),
+        ("?period=month", 2),
+        ("?period=day&since=2000-01-01", 2),
+        ("?until=2000-01-01", 0),
+    ] {
+        let response = request("GET", format!("{project}/progress{query}"), None)
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK, "{query}");
+        let progress: Vec<StatusProgress> =
+            serde_json::from_value(json(response).await).expect("Failed to parse progress");
+        assert_eq!(progress.len(), count, "{query}");
+    }
+    let response = request("GET", format!("{project}/progress?period=decade"), None)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    // the progress of other users' projects is private
+    let other = Allocation::load(4, &pool)
+        .await
+        .expect("Failed to load allocation");
+    let response = request("GET", format!("{}/progress", other.project.uuid), None)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_client(pool: Pool<Sqlite>) {
+    let app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind listener");
+    let addr = listener.local_addr().expect("Failed to get address");
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    let user = User::load(1, &pool).await.expect("Failed to load user");
+    let (_, token) = user
+        .create_token("test", &pool)
+        .await
+        .expect("Failed to create token");
+    let client = seedclient::Client::new(&format!("http://{addr}/"), &token)
+        .expect("Failed to create client");
+
+    let stats = client.stats().await.expect("Failed to load stats");
+    assert_eq!(stats.years.iter().map(|y| y.count).sum::<i64>(), 3);
+    assert_eq!(
+        client.years().await.expect("Failed to load years"),
+        stats.years
+    );
+    assert_eq!(
+        client.projects().await.expect("Failed to load projects"),
+        stats.projects
+    );
+
+    let sample = Sample::load(2, &pool).await.expect("Failed to load sample");
+    assert_eq!(
+        client
+            .sample(sample.uuid)
+            .await
+            .expect("Failed to load sample"),
+        sample
+    );
+    let updated = client
+        .update_sample(
+            sample.uuid,
+            &seedclient::SamplePatch {
+                quantity: Some(Some(80)),
+                notes: Some(None),
+                ..seedclient::SamplePatch::new(sample.version)
+            },
+        )
+        .await
+        .expect("Failed to update sample");
+    assert_eq!(updated.quantity, Some(80));
+    assert_eq!(updated.notes, None);
+    assert_eq!(updated.year, sample.year);
+    match client
+        .update_sample(
+            sample.uuid,
+            &seedclient::SamplePatch {
+                quantity: Some(Some(1)),
+                ..seedclient::SamplePatch::new(sample.version)
+            },
+        )
+        .await
+    {
+        Err(seedclient::Error::Api { status, .. }) => assert_eq!(status, 409),
+        other => panic!("Unexpected result {other:?}"),
+    }
+
+    let alloc = Allocation::load(1, &pool)
+        .await
+        .expect("Failed to load allocation");
+    let project = alloc.project.uuid;
+    let changed = client
+        .set_allocation_status(project, alloc.uuid, AllocationStatus::Sown)
+        .await
+        .expect("Failed to set status");
+    assert_eq!(changed.status, AllocationStatus::Sown);
+    let events = client
+        .allocation_events(project, alloc.uuid)
+        .await
+        .expect("Failed to load events");
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].to, AllocationStatus::Sown);
+    let progress = client
+        .project_progress(project, Period::Month, None, None)
+        .await
+        .expect("Failed to load progress");
+    assert_eq!(progress.len(), 1);
+    assert_eq!(progress[0].total, 1);
+
+    // the errors of the server are reported with their status
+    match client.sample(Uuid::new_v4()).await {
+        Err(seedclient::Error::Api { status, .. }) => assert_eq!(status, 404),
+        other => panic!("Unexpected result {other:?}"),
+    }
+    let other = Allocation::load(4, &pool)
+        .await
+        .expect("Failed to load allocation");
+    match client
+        .project_progress(other.project.uuid, Period::Week, None, None)
+        .await
+    {
+        Err(seedclient::Error::Api { status, .. }) => assert_eq!(status, 401),
+        other => panic!("Unexpected result {other:?}"),
+    }
+    let anonymous = seedclient::Client::new(&format!("http://{addr}"), "sct_1_0123456789abcdef")
+        .expect("Failed to create client");
+    assert!(anonymous.stats().await.is_err());
+    assert!(seedclient::Client::new("example.com", &token).is_err());
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_graphql(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let query = |cookie: Option<&str>, query: &str| {
+        let mut req = Request::builder()
+            .uri(GRAPHQL_PATH)
+            .method("POST")
+            .header("Content-Type", "application/json");
+        if let Some(cookie) = cookie {
+            req = req.header("Cookie", cookie);
+        }
+        req.body(Body::from(
+            serde_json::json!({ "query": query }).to_string(),
+        ))
+        .expect("Failed to build request")
+    };
+    async fn json(response: axum::response::Response) -> serde_json::Value {
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        serde_json::from_slice(&body).expect("Failed to parse json")
+    }
+
+    let response = app
+        .as_service()
+        .call(query(None, "{ samples { totalCount } }"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let mut run = |q: &str| app.as_service().call(query(Some(&cookie), q));
+
+    let result = json(
+        run(r#"{ samples(first: 2, sort: "-id") {
+            totalCount nodes { id quantity taxon { id } source { name } }
+        } }"#)
+        .await
+        .expect("Failed to execute request"),
+    )
+    .await;
+    assert_eq!(result["errors"], serde_json::Value::Null, "{result}");
+    let page = &result["data"]["samples"];
+    assert_eq!(page["totalCount"], 3);
+    let nodes = page["nodes"].as_array().expect("nodes is not a list");
+    assert_eq!(nodes.len(), 2);
+    let sample = Sample::load(3, &pool).await.expect("Failed to load sample");
+    assert_eq!(nodes[0]["id"], sample.uuid.to_string());
+    assert_eq!(nodes[0]["taxon"]["id"], sample.taxon.id());
+
+    // filters are applied before the list is paginated
+    let result = json(
+        run("{ samples(filter: {source: 1, quantityMissing: true}, offset: 1) { totalCount nodes { id } } }")
+            .await
+            .expect("Failed to execute request"),
+    )
+    .await;
+    assert_eq!(result["data"]["samples"]["totalCount"], 2, "{result}");
+    assert_eq!(
+        result["data"]["samples"]["nodes"].as_array().map(Vec::len),
+        Some(1)
+    );
+
+    // the samples of other users are not visible
+    let other = Sample::load(4, &pool).await.expect("Failed to load sample");
+    let result = json(
+        run(&format!(
+            r#"{{ sample(id: "{}") {{ quantity }} }}"#,
+            other.uuid
+        ))
+        .await
+        .expect("Failed to execute request"),
+    )
+    .await;
+    assert_eq!(result["data"], serde_json::Value::Null);
+    assert!(result["errors"][0]["message"].is_string(), "{result}");
+
+    let alloc = Allocation::load(1, &pool)
+        .await
+        .expect("Failed to load allocation");
+    let result = json(
+        run(&format!(
+            r#"{{ project(id: "{}") {{ name version
+                all: allocations {{ totalCount }}
+                sown: allocations(status: SOWN) {{ totalCount }}
+            }} }}"#,
+            alloc.project.uuid
+        ))
+        .await
+        .expect("Failed to execute request"),
+    )
+    .await;
+    assert_eq!(result["errors"], serde_json::Value::Null, "{result}");
+    assert_eq!(result["data"]["project"]["name"], alloc.project.name);
+    assert_eq!(result["data"]["project"]["version"], alloc.project.version);
+    assert!(result["data"]["project"]["all"]["totalCount"].as_i64() > Some(0));
+    assert_eq!(result["data"]["project"]["sown"]["totalCount"], 0);
+
+    let result = json(
+        run(r#"{ taxa(filter: {name: "Elymus", rank: "species"}, first: 1) { totalCount nodes { completeName parent { rank } } } }"#)
+            .await
+            .expect("Failed to execute request"),
+    )
+    .await;
+    assert_eq!(result["errors"], serde_json::Value::Null, "{result}");
+    assert!(result["data"]["taxa"]["totalCount"].as_i64() > Some(0));
+    assert_eq!(
+        result["data"]["taxa"]["nodes"][0]["parent"]["rank"],
+        "Genus"
+    );
+
+    for invalid in [
+        "{ samples(first: 0) { totalCount } }",
+        "{ samples(offset: -1) { totalCount } }",
+        r#"{ samples(sort: "bogus") { totalCount } }"#,
+        r#"{ taxa(filter: {rank: "bogus"}) { totalCount } }"#,
+    ] {
+        let result = json(run(invalid).await.expect("Failed to execute request")).await;
+        assert!(result["errors"][0]["message"].is_string(), "{invalid}");
+    }
+
+    // queries that follow references too deep or multiply nested lists are rejected before they
+    // are executed
+    let deep = format!(
+        "{{ taxon(id: 1) {{ {} rank {} }} }}",
+        "parent {".repeat(12),
+        "}".repeat(12)
+    );
+    let result = json(run(&deep).await.expect("Failed to execute request")).await;
+    assert_eq!(result["data"], serde_json::Value::Null);
+    assert_eq!(
+        result["errors"][0]["message"], "Query is nested too deep.",
+        "{result}"
+    );
+    let result = json(
+        run("{ sources(first: 500) { nodes { samples(first: 500) { nodes { id } } } } }")
+            .await
+            .expect("Failed to execute request"),
+    )
+    .await;
+    assert_eq!(result["data"], serde_json::Value::Null);
+    assert_eq!(
+        result["errors"][0]["message"], "Query is too complex.",
+        "{result}"
+    );
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_germination_codes(pool: Pool<Sqlite>) {
+    sqlx::query(
+        r#"INSERT INTO sc_germination_codes (germid, code, summary) VALUES
+            (1, 'C(60)', 'Cold moist stratification'), (2, 'D', 'Dry storage'),
+            (3, 'A', 'Sow fresh');
+        INSERT INTO sc_taxon_germination (tsn, germid) VALUES
+            (40683, 1), (43254, 1), (43254, 2), (40677, 3)"#,
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to insert germination codes");
+    let mut app = test_app(pool).await.expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let mut get = |path: &str| {
+        let req = Request::builder()
+            .uri(format!("{API_PREFIX}germination/{path}"))
+            .method("GET")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request");
+        app.as_service().call(req)
+    };
+
+    for (query, expected) in [
+        ("", vec!["A", "C(60)", "D"]),
+        ("?sort=-taxa,code", vec!["C(60)", "A", "D"]),
+        ("?code=c(", vec!["C(60)"]),
+        ("?taxon=43254&sort=-code", vec!["D", "C(60)"]),
+        ("?used_by_my_samples=true", vec!["C(60)", "D"]),
+        ("?used_by_my_samples=true&code=&taxon=40683", vec!["C(60)"]),
+    ] {
+        let response = get(query).await.expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK, "{query}");
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        let codes: Vec<Germination> = serde_json::from_slice(&body).expect("Failed to parse json");
+        let codes: Vec<&str> = codes.iter().map(|g| g.code.as_str()).collect();
+        assert_eq!(codes, expected, "{query}");
+    }
+
+    let response = get("2").await.expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = get("99").await.expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = get("?sort=bogus").await.expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,268 @@
+//! Fingerprinting of static assets. Every file in the static directory can also be requested under
+//! a name that contains a hash of its contents (e.g. `base.3f2a9c1b0e.css`). Since that name
+//! changes whenever the file does, browsers are allowed to cache it forever.
+//!
+//! A site can replace bundled files with its own by putting a file with the same name in an
+//! override directory. The fingerprint is then computed from the file that is actually served.
+use crate::{state::AppState, static_url};
+use axum::{
+    extract::{Request, State},
+    http::{header::CACHE_CONTROL, HeaderValue, Uri},
+    middleware::Next,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{PoisonError, RwLock, RwLockReadGuard},
+};
+use tracing::warn;
+
+/// The number of hex digits of the content hash that are included in the file name
+const HASH_LENGTH: usize = 10;
+
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+#[derive(Debug, Default)]
+struct Names {
+    /// the fingerprinted name of each file, relative to the static directory
+    fingerprinted: HashMap<String, String>,
+    /// the original name of each fingerprinted name
+    originals: HashMap<String, String>,
+}
+
+impl Names {
+    fn insert(&mut self, path: String, hash: &str) {
+        let hashed = fingerprint_name(&path, hash);
+        self.originals.insert(hashed.clone(), path.clone());
+        self.fingerprinted.insert(path, hashed);
+    }
+}
+
+#[derive(Debug)]
+pub struct StaticAssets {
+    dir: PathBuf,
+    overrides: Option<PathBuf>,
+    names: RwLock<Names>,
+}
+
+/// Hash the files in `dir` and its subdirectories, keyed by their path relative to `dir`
+fn hash_files(dir: &Path, hashes: &mut HashMap<String, String>) {
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let entries = match std::fs::read_dir(&current) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(?current, "Unable to read static asset directory: {e}");
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            let (Ok(contents), Ok(relative)) = (std::fs::read(&path), path.strip_prefix(dir))
+            else {
+                continue;
+            };
+            let Some(relative) = relative.to_str() else {
+                continue;
+            };
+            hashes.insert(
+                relative.replace('\\', "/"),
+                hex::encode(Sha256::digest(&contents)),
+            );
+        }
+    }
+}
+
+/// Insert the hash before the extension of the file name, e.g. `fonts/icons.woff2` becomes
+/// `fonts/icons.<hash>.woff2`
+fn fingerprint_name(path: &str, hash: &str) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{dir}/"), name),
+        None => (String::new(), path),
+    };
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{dir}{stem}.{hash}.{ext}"),
+        _ => format!("{dir}{name}.{hash}"),
+    }
+}
+
+impl StaticAssets {
+    /// Hash all of the files in the given directory and its subdirectories, and those in the
+    /// override directory, which replace the files with the same name in `dir`
+    pub fn load(dir: &Path, overrides: Option<&Path>) -> Self {
+        let assets = Self {
+            dir: dir.to_path_buf(),
+            overrides: overrides.map(Path::to_path_buf),
+            names: Default::default(),
+        };
+        assets.reload();
+        assets
+    }
+
+    /// Hash the files again, e.g. after they were modified
+    pub fn reload(&self) {
+        let mut hashes = HashMap::new();
+        hash_files(&self.dir, &mut hashes);
+        if let Some(overrides) = self.overrides.as_ref().filter(|dir| dir.is_dir()) {
+            hash_files(overrides, &mut hashes);
+        }
+        let mut names = Names::default();
+        for (path, hash) in hashes {
+            names.insert(path, &hash[..HASH_LENGTH]);
+        }
+        *self.names.write().unwrap_or_else(PoisonError::into_inner) = names;
+    }
+
+    fn names(&self) -> RwLockReadGuard<'_, Names> {
+        self.names.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The url of a static file, using its fingerprinted name if it is known
+    pub fn url(&self, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+        static_url(
+            self.names()
+                .fingerprinted
+                .get(path)
+                .map_or(path, String::as_str),
+        )
+    }
+
+    /// The original name of the file with the given fingerprinted name
+    fn resolve(&self, hashed: &str) -> Option<String> {
+        self.names().originals.get(hashed).cloned()
+    }
+}
+
+/// Serve requests for fingerprinted file names with the original file and headers that allow the
+/// file to be cached indefinitely. Other requests are passed on unchanged.
+pub async fn serve_fingerprinted(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let original = state
+        .assets
+        .resolve(request.uri().path().trim_start_matches('/'))
+        .and_then(|original| {
+            let query = request
+                .uri()
+                .query()
+                .map(|q| format!("?{q}"))
+                .unwrap_or_default();
+            format!("/{original}{query}").parse::<Uri>().ok()
+        });
+    let Some(uri) = original else {
+        return next.run(request).await;
+    };
+    *request.uri_mut() = uri;
+    let mut response = next.run(request).await;
+    if response.status().is_success() {
+        response
+            .headers_mut()
+            .insert(CACHE_CONTROL, HeaderValue::from_static(IMMUTABLE));
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_app;
+    use axum::{
+        body::Body,
+        http::{self, StatusCode},
+    };
+    use sqlx::{Pool, Sqlite};
+    use tower::Service;
+
+    #[test]
+    fn test_fingerprint_name() {
+        assert_eq!(fingerprint_name("base.css", "abc"), "base.abc.css");
+        assert_eq!(
+            fingerprint_name("fonts/icons.woff2", "abc"),
+            "fonts/icons.abc.woff2"
+        );
+        assert_eq!(fingerprint_name("LICENSE", "abc"), "LICENSE.abc");
+        assert_eq!(fingerprint_name(".hidden", "abc"), ".hidden.abc");
+    }
+
+    #[sqlx::test(migrations = "../db/migrations/")]
+    async fn test_serve_fingerprinted(pool: Pool<Sqlite>) {
+        let mut app = test_app(pool).await.expect("failed to create test app");
+        let assets = StaticAssets::load(Path::new("./static"), None);
+        let url = assets.url("base.css");
+        assert_ne!(url, static_url("base.css"));
+
+        for (url, cached) in [(url.as_str(), true), ("/static/base.css", false)] {
+            let req = http::Request::builder()
+                .uri(url)
+                .body(Body::empty())
+                .expect("Failed to build request");
+            let response = app
+                .as_service()
+                .call(req)
+                .await
+                .expect("Failed to execute request");
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response
+                    .headers()
+                    .get(CACHE_CONTROL)
+                    .is_some_and(|v| v == IMMUTABLE),
+                cached
+            );
+        }
+    }
+
+    #[sqlx::test(migrations = "../db/migrations/")]
+    async fn test_serve_overrides(pool: Pool<Sqlite>) {
+        use http_body_util::BodyExt;
+        use std::sync::Arc;
+
+        let dir = std::env::temp_dir().join(format!("seedweb-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("static")).expect("Failed to create override dir");
+        std::fs::write(dir.join("static/base.css"), "body { color: green; }")
+            .expect("Failed to write override");
+        let mut state = crate::state::SharedState::test(pool);
+        state.config.overrides = Some(dir.clone());
+        state.assets = Arc::new(StaticAssets::load(
+            Path::new("./static"),
+            state.config.override_dir("static").as_deref(),
+        ));
+        let bundled = StaticAssets::load(Path::new("./static"), None);
+        let url = state.assets.url("base.css");
+        assert_ne!(url, bundled.url("base.css"));
+        // files that aren't overridden keep their name
+        assert_eq!(state.assets.url("print.css"), bundled.url("print.css"));
+
+        let mut app = crate::app(Arc::new(state))
+            .await
+            .expect("failed to create app");
+        for (url, overridden) in [
+            (url.as_str(), true),
+            ("/static/base.css", true),
+            ("/static/print.css", false),
+        ] {
+            let req = http::Request::builder()
+                .uri(url)
+                .body(Body::empty())
+                .expect("Failed to build request");
+            let response = app
+                .as_service()
+                .call(req)
+                .await
+                .expect("Failed to execute request");
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body == "body { color: green; }", overridden, "{url}");
+        }
+        std::fs::remove_dir_all(&dir).expect("Failed to remove override dir");
+    }
+}
@@ -1,38 +1,118 @@
-use crate::error::{self, Error};
+use crate::{
+    app_url,
+    error::{self, Error},
+    state::AppState,
+};
 use anyhow::anyhow;
-use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{header::AUTHORIZATION, request::Parts, Method},
+    middleware::Next,
+    response::Response,
+};
 use axum_login::{AuthUser, AuthnBackend, UserId};
 use libseed::{
+    audit::AuditEntry,
     empty_string_as_none,
+    loadable::Loadable,
+    organization::{self, OrgRole, Organization, Owned, Permission},
+    scope::UserScope,
     user::{User, UserStatus},
 };
-use rand::{
-    distributions::{Alphanumeric, DistString},
-    rngs::OsRng,
-};
 use serde::{Deserialize, Serialize};
-use sqlx::{Pool, Sqlite, SqlitePool};
+use sqlx::SqlitePool;
 use std::ops::{Deref, DerefMut};
-use tracing::debug;
+use tower_sessions::Session;
+use tracing::{info, warn};
 
+/// The session key that holds the id of the user that an administrator is impersonating
+const IMPERSONATION_KEY: &str = "impersonating";
+
+/// A user of the web app. While an administrator is impersonating another user, this is the
+/// impersonated user and `impersonator` is the administrator. Both are serialized so that
+/// templates can show who is really logged in.
 #[derive(Debug, Clone, Serialize)]
-pub struct SqliteUser(User);
+pub struct SqliteUser {
+    #[serde(flatten)]
+    user: User,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    impersonator: Option<User>,
+}
+
+impl From<User> for SqliteUser {
+    fn from(user: User) -> Self {
+        Self {
+            user,
+            impersonator: None,
+        }
+    }
+}
 
 impl SqliteUser {
-    pub async fn new_verification_code(&self, pool: &Pool<Sqlite>) -> Result<String, error::Error> {
-        let key = Alphanumeric.sample_string(&mut OsRng, 24);
-        debug!(key, "Generated a new verification code");
-        sqlx::query!(
-            r#"UPDATE sc_user_verification SET uvexpiration=0 WHERE userid=?;
-            INSERT into sc_user_verification (userid, uvkey, uvexpiration) VALUES(?, ?, ?)"#,
-            self.id,
-            self.id,
-            key,
-            (4 * 60 * 60)
-        )
-        .execute(pool)
-        .await?;
-        Ok(key)
+    /// The objects that the user has access to. Handlers list objects through the scope so that
+    /// they can't accidentally list the objects of other users.
+    pub fn scope(&self) -> UserScope {
+        UserScope::new(self.id)
+    }
+
+    /// Fail with [Error::Unauthorized] unless the user has the given permission for the object
+    pub async fn require<T: Owned + ?Sized>(
+        &self,
+        object: &T,
+        permission: Permission,
+        pool: &SqlitePool,
+    ) -> Result<(), Error> {
+        if organization::has_permission(object, self.id, permission, pool).await? {
+            return Ok(());
+        }
+        let action = match permission {
+            Permission::View => "view",
+            Permission::Edit => "modify",
+            Permission::Manage => "delete",
+        };
+        Err(Error::Unauthorized(format!(
+            "No permission to {action} this object"
+        )))
+    }
+
+    /// The organizations that the user can add objects to
+    pub async fn writable_orgs(&self, pool: &SqlitePool) -> Result<Vec<Organization>, Error> {
+        Ok(Organization::load_all_user(self.id, pool)
+            .await?
+            .into_iter()
+            .filter(|(_, role)| role.permits(Permission::Edit))
+            .map(|(org, _)| org)
+            .collect())
+    }
+
+    /// Fail with [Error::Unauthorized] unless the user is allowed to move the object to the given
+    /// organization. Only those that could delete an object may change its owner.
+    pub async fn require_move<T: Owned + ?Sized>(
+        &self,
+        object: &T,
+        orgid: Option<i64>,
+        pool: &SqlitePool,
+    ) -> Result<(), Error> {
+        if orgid == object.organization() {
+            return Ok(());
+        }
+        self.require(object, Permission::Manage, pool).await?;
+        self.require_org(orgid, pool).await
+    }
+
+    /// Fail with [Error::Unauthorized] unless the user is allowed to give ownership of an object to
+    /// the given organization
+    pub async fn require_org(&self, orgid: Option<i64>, pool: &SqlitePool) -> Result<(), Error> {
+        match orgid {
+            None => Ok(()),
+            Some(orgid) => match Organization::role(orgid, self.id, pool).await? {
+                Some(role) if role >= OrgRole::Member => Ok(()),
+                _ => Err(Error::Unauthorized(format!(
+                    "No permission to add objects to organization {orgid}"
+                ))),
+            },
+        }
     }
 }
 
@@ -40,13 +120,13 @@ impl Deref for SqliteUser {
     type Target = User;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.user
     }
 }
 
 impl DerefMut for SqliteUser {
     fn deref_mut(&mut self) -> &mut User {
-        &mut self.0
+        &mut self.user
     }
 }
 
@@ -100,7 +180,7 @@ impl AuthnBackend for SqliteAuthBackend {
     async fn get_user(&self, username: &UserId<Self>) -> Result<Option<Self::User>, Self::Error> {
         User::load_by_username(username, &self.db)
             .await
-            .map(|o| o.map(SqliteUser))
+            .map(|o| o.map(SqliteUser::from))
             .map_err(|e| e.into())
     }
 }
@@ -111,7 +191,7 @@ impl SqliteAuthBackend {
         username: String,
         email: String,
         password: String,
-    ) -> Result<(), error::Error> {
+    ) -> Result<User, error::Error> {
         let password_hash = User::hash_password(&password)?;
         let mut user = User::new(
             username,
@@ -123,12 +203,20 @@ impl SqliteAuthBackend {
             None,
         );
         user.insert(&self.db).await?;
-        Ok(())
+        Ok(user)
     }
 
     pub fn new(db: SqlitePool) -> Self {
         Self { db }
     }
+
+    /// Look up the user that owns the given API token
+    pub async fn authenticate_token(&self, token: &str) -> Result<Option<SqliteUser>, Error> {
+        User::load_by_token(token, &self.db)
+            .await
+            .map(|o| o.map(SqliteUser::from))
+            .map_err(|e| e.into())
+    }
 }
 
 pub type AuthSession = axum_login::AuthSession<SqliteAuthBackend>;
@@ -144,7 +232,151 @@ where
         let auth = AuthSession::from_request_parts(parts, _state)
             .await
             .map_err(|e| anyhow!(e.1))?;
-        auth.user
-            .ok_or_else(|| Error::Unauthorized("No logged in user".to_string()))
+        if let Some(user) = auth.user {
+            let session = Session::from_request_parts(parts, _state)
+                .await
+                .map_err(|e| anyhow!(e.1))?;
+            return impersonated_user(user, &session, &auth.backend.db).await;
+        }
+        // clients that don't use sessions (e.g. scripts using the API) can authenticate with an
+        // API token instead
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match token {
+            Some(token) => auth
+                .backend
+                .authenticate_token(token.trim())
+                .await?
+                .ok_or_else(|| Error::Unauthorized("Invalid API token".to_string())),
+            None => Err(Error::Unauthorized("No logged in user".to_string())),
+        }
+    }
+}
+
+/// Resolve the user that requests of the logged in user act as. That is the logged in user
+/// themselves unless they are an administrator who is impersonating somebody else.
+async fn impersonated_user(
+    user: SqliteUser,
+    session: &Session,
+    pool: &SqlitePool,
+) -> Result<SqliteUser, Error> {
+    let Some(targetid) = session
+        .get::<i64>(IMPERSONATION_KEY)
+        .await
+        .map_err(|e| anyhow!(e))?
+    else {
+        return Ok(user);
+    };
+    if !user.admin {
+        // the user lost their admin rights since they started impersonating
+        warn!(
+            user.username,
+            "Ignoring impersonation by a non-administrator"
+        );
+        session
+            .remove::<i64>(IMPERSONATION_KEY)
+            .await
+            .map_err(|e| anyhow!(e))?;
+        return Ok(user);
+    }
+    let target = User::load(targetid, pool).await?;
+    Ok(SqliteUser {
+        user: target,
+        impersonator: Some(user.user),
+    })
+}
+
+/// Start acting as the given user. Only administrators may impersonate other users and the start
+/// of the impersonation is recorded in the audit trail.
+pub async fn start_impersonation(
+    admin: &User,
+    target: &User,
+    session: &Session,
+    pool: &SqlitePool,
+) -> Result<(), Error> {
+    if !admin.admin {
+        return Err(Error::Unauthorized(
+            "Only administrators can impersonate other users".to_string(),
+        ));
+    }
+    if admin.id == target.id {
+        return Err(
+            libseed::Error::InvalidValue("You can't impersonate yourself".to_string()).into(),
+        );
+    }
+    AuditEntry::record(admin.id, Some(target.id), "impersonate", None, pool).await?;
+    session
+        .insert(IMPERSONATION_KEY, target.id)
+        .await
+        .map_err(|e| anyhow!(e))?;
+    info!(
+        admin = admin.username,
+        target = target.username,
+        "Started impersonating a user"
+    );
+    Ok(())
+}
+
+/// Switch back to the administrator's own account. The end of the impersonation is recorded in the
+/// audit trail.
+pub async fn stop_impersonation(
+    admin: &User,
+    session: &Session,
+    pool: &SqlitePool,
+) -> Result<(), Error> {
+    let Some(targetid) = session
+        .remove::<i64>(IMPERSONATION_KEY)
+        .await
+        .map_err(|e| anyhow!(e))?
+    else {
+        return Ok(());
+    };
+    AuditEntry::record(admin.id, Some(targetid), "stop-impersonating", None, pool).await?;
+    info!(admin = admin.username, "Stopped impersonating a user");
+    Ok(())
+}
+
+/// Record every request of an impersonating administrator that could modify data in the audit
+/// trail, along with both identities and the resulting status code
+pub async fn audit_impersonation(
+    State(state): State<AppState>,
+    auth: AuthSession,
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Response {
+    // starting and stopping the impersonation is recorded by the handlers themselves
+    let safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) || request
+        .uri()
+        .path()
+        .starts_with(&app_url("/auth/impersonate"));
+    let admin = auth.user.filter(|user| user.admin);
+    let target = match (&admin, safe) {
+        (Some(_), false) => session.get::<i64>(IMPERSONATION_KEY).await.ok().flatten(),
+        _ => None,
+    };
+    let (Some(admin), Some(targetid)) = (admin, target) else {
+        return next.run(request).await;
+    };
+    let action = format!("{} {}", request.method(), request.uri().path());
+    let response = next.run(request).await;
+    let details = format!("status {}", response.status().as_u16());
+    if let Err(e) = AuditEntry::record(
+        admin.id,
+        Some(targetid),
+        &action,
+        Some(&details),
+        &state.dbpool,
+    )
+    .await
+    {
+        warn!(?e, action, "Failed to record an action in the audit trail");
     }
+    response
 }
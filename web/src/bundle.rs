@@ -0,0 +1,500 @@
+//! Export bundles of a project: a zip file with everything that is needed to work with a project
+//! outside of the app. A bundle contains
+//!
+//! - `allocations.csv`: every sample in the project along with its latest note
+//! - `species.csv`: the species in the project, with the number of samples and total quantity
+//! - `germination-plan.csv`: the propagation plan of the project
+//! - `labels.pdf`: a sheet of packet labels for all of the samples, sized for 1" x 2⅝" labels
+//! - `field-sheets.pdf`: data sheets for recording collections in the field, see
+//!   [`field_sheets_pdf`]
+//! - `report.html`: a self-contained summary of the project that can be opened in a browser
+//!
+//! Bundles are generated in the background by [`jobs`](crate::jobs), since it can take a while for
+//! a large project.
+use crate::{app_url, format_id_number, state::AppState};
+use anyhow::{anyhow, Context, Result};
+use axum_template::TemplateEngine;
+use libseed::{
+    loadable::Loadable,
+    project::{bundle::Bundle, propagation, propagation::PlanItem, Project},
+    sample::{Certainty, Sample},
+};
+use minijinja::context;
+use printpdf::{
+    BuiltinFont, IndirectFontRef, Line, Mm, PdfDocument, PdfLayerReference, Point, Rect,
+};
+use qrcode::QrCode;
+use std::io::{Cursor, Write};
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+/// The layout of the label sheet: US letter paper with 3 columns of 10 labels each
+const PAGE_WIDTH: f32 = 215.9;
+const PAGE_HEIGHT: f32 = 279.4;
+const LABEL_COLUMNS: usize = 3;
+const LABEL_ROWS: usize = 10;
+const LABEL_WIDTH: f32 = 69.85;
+const LABEL_HEIGHT: f32 = 25.4;
+const LABEL_MARGIN_LEFT: f32 = 4.8;
+const LABEL_MARGIN_TOP: f32 = 12.7;
+/// The longest line that fits on a label in the body font size
+const LABEL_LINE_CHARS: usize = 40;
+
+/// The layout of the field data sheets: a header and a number of entries on each page, on the
+/// same paper as the labels
+const SHEET_MARGIN: f32 = 15.0;
+const SHEET_HEADER_HEIGHT: f32 = 20.0;
+const SHEET_ENTRY_HEIGHT: f32 = 47.0;
+const SHEET_ENTRIES: usize = 5;
+/// The size of the QR code of an entry, which links to the page where it is recorded
+const SHEET_QR_SIZE: f32 = 32.0;
+
+/// Generate the zip file for the given bundle
+pub async fn build(state: &AppState, bundle: &Bundle) -> Result<Vec<u8>> {
+    let mut project = Project::load(bundle.projectid, &state.dbpool).await?;
+    project.load_samples(None, None, &state.dbpool).await?;
+    for alloc in project.allocations.iter_mut() {
+        alloc.load_notes(&state.dbpool).await?;
+    }
+    let plan = propagation::load(project.id, None, &state.dbpool).await?;
+
+    let report = state
+        .tmpl
+        .render(
+            "bundle/report.html",
+            context!(project => project, plan => plan),
+        )
+        .with_context(|| "Failed to render the project report")?;
+    let samples: Vec<&Sample> = project.allocations.iter().map(|a| &a.sample).collect();
+    let files = [
+        ("allocations.csv", allocations_csv(&project)?),
+        ("species.csv", species_csv(&plan)?),
+        ("germination-plan.csv", propagation_csv(&plan)?),
+        ("labels.pdf", labels_pdf(&project.name, &samples)?),
+        (
+            "field-sheets.pdf",
+            field_sheets_pdf(state, &project, false)?,
+        ),
+        ("report.html", report.into_bytes()),
+    ];
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let dir = format_id_number(project.id, Some("P"), None);
+    for (name, data) in files {
+        zip.start_file(format!("{dir}/{name}"), SimpleFileOptions::default())?;
+        zip.write_all(&data)?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+/// The name of the zip file of a bundle of the given project when it is downloaded
+pub fn filename(project: &Project) -> String {
+    format!(
+        "{}-bundle.zip",
+        format_id_number(project.id, Some("P"), None)
+    )
+}
+
+fn allocations_csv(project: &Project) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record([
+        "Sample",
+        "Taxon",
+        "Common Names",
+        "Source",
+        "Month",
+        "Year",
+        "Quantity",
+        "Grade",
+        "Latest Activity",
+        "Activity Type",
+        "Activity Summary",
+    ])?;
+    for alloc in &project.allocations {
+        let sample = &alloc.sample;
+        let taxon = sample.taxon.object()?;
+        let latest = alloc.notes.iter().max_by_key(|n| (n.date, n.id));
+        writer.write_record([
+            format_id_number(sample.id, Some("S"), None),
+            taxon.complete_name.clone(),
+            taxon.vernaculars.join(", "),
+            sample.source.object()?.name.clone(),
+            sample.month.map(|m| m.to_string()).unwrap_or_default(),
+            sample.year.map(|y| y.to_string()).unwrap_or_default(),
+            sample.quantity.map(|q| q.to_string()).unwrap_or_default(),
+            sample
+                .grade
+                .as_ref()
+                .map(|g| g.label.clone())
+                .unwrap_or_default(),
+            latest.map(|n| n.date.to_string()).unwrap_or_default(),
+            latest.map(|n| format!("{:?}", n.kind)).unwrap_or_default(),
+            latest.map(|n| n.summary.clone()).unwrap_or_default(),
+        ])?;
+    }
+    writer.into_inner().map_err(|e| anyhow!("{e}"))
+}
+
+fn species_csv(plan: &[PlanItem]) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record(["Taxon", "Common Names", "Samples", "Quantity"])?;
+    for item in plan {
+        writer.write_record([
+            item.taxon.complete_name.clone(),
+            item.taxon.vernaculars.join(", "),
+            item.samples.len().to_string(),
+            item.quantity.map(|q| q.to_string()).unwrap_or_default(),
+        ])?;
+    }
+    writer.into_inner().map_err(|e| anyhow!("{e}"))
+}
+
+/// The propagation plan as a CSV file, in the order of the given plan
+pub fn propagation_csv(plan: &[PlanItem]) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer.write_record([
+        "Taxon",
+        "Common Names",
+        "Samples",
+        "Quantity",
+        "Usable Quantity",
+        "Germination Codes",
+        "Stratification Days",
+        "Treatments",
+    ])?;
+    for item in plan {
+        writer.write_record([
+            item.taxon.complete_name.clone(),
+            item.taxon.vernaculars.join(", "),
+            item.samples
+                .iter()
+                .map(|(id, _)| format_id_number(*id, Some("S"), None))
+                .collect::<Vec<_>>()
+                .join(" "),
+            item.quantity.map(|q| q.to_string()).unwrap_or_default(),
+            item.usable_quantity
+                .map(|q| q.to_string())
+                .unwrap_or_default(),
+            item.germination
+                .iter()
+                .map(|g| g.code.clone())
+                .collect::<Vec<_>>()
+                .join(" "),
+            item.stratification_days
+                .map(|d| d.to_string())
+                .unwrap_or_default(),
+            item.germination
+                .iter()
+                .filter_map(|g| g.summary.clone())
+                .collect::<Vec<_>>()
+                .join("; "),
+        ])?;
+    }
+    writer.into_inner().map_err(|e| anyhow!("{e}"))
+}
+
+/// Shorten a line of text so that it fits on a label
+fn fit(text: &str) -> String {
+    if text.chars().count() <= LABEL_LINE_CHARS {
+        return text.to_string();
+    }
+    let mut s: String = text.chars().take(LABEL_LINE_CHARS - 3).collect();
+    s.push_str("...");
+    s
+}
+
+/// The lines of text of the packet label of a sample, matching the printable label sheet
+fn label_lines(sample: &Sample) -> Result<Vec<String>> {
+    let taxon = sample.taxon.object()?;
+    let mut lines = vec![match sample.certainty {
+        Certainty::Uncertain => format!("{} (?)", taxon.complete_name),
+        _ => taxon.complete_name.clone(),
+    }];
+    if let Some(name) = taxon.vernaculars.first() {
+        lines.push(name.clone());
+    }
+    lines.push(match &sample.purchase {
+        Some(purchase) => match &purchase.lot {
+            Some(lot) => format!("{}, lot {lot}", purchase.vendor),
+            None => purchase.vendor.clone(),
+        },
+        None => sample.source.object()?.name.clone(),
+    });
+    let mut date = match (sample.month, sample.year) {
+        (Some(month), Some(year)) => format!("{month}/{year}"),
+        (None, Some(year)) => year.to_string(),
+        _ => String::new(),
+    };
+    if let Some(quantity) = sample.quantity {
+        if !date.is_empty() {
+            date.push_str(" - ");
+        }
+        date.push_str(&format!("qty {quantity}"));
+    }
+    if let Some(grade) = &sample.grade {
+        if !date.is_empty() {
+            date.push_str(" - ");
+        }
+        date.push_str(&format!("grade {}", grade.label));
+    }
+    lines.push(date);
+    Ok(lines.iter().map(|l| fit(l)).collect())
+}
+
+fn draw_label(
+    layer: &PdfLayerReference,
+    index: usize,
+    sample: &Sample,
+    font: &IndirectFontRef,
+    bold: &IndirectFontRef,
+) -> Result<()> {
+    let (column, row) = (index % LABEL_COLUMNS, index / LABEL_COLUMNS);
+    let x = LABEL_MARGIN_LEFT + column as f32 * LABEL_WIDTH + 3.0;
+    let mut y = PAGE_HEIGHT - LABEL_MARGIN_TOP - row as f32 * LABEL_HEIGHT - 5.0;
+    layer.use_text(
+        format_id_number(sample.id, Some("S"), None),
+        10.0,
+        Mm(x),
+        Mm(y),
+        bold,
+    );
+    for line in label_lines(sample)? {
+        y -= 4.2;
+        layer.use_text(line, 8.0, Mm(x), Mm(y), font);
+    }
+    Ok(())
+}
+
+/// A PDF with a packet label for each of the given samples
+fn labels_pdf(title: &str, samples: &[&Sample]) -> Result<Vec<u8>> {
+    let (doc, page, layer) = PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Labels");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+    let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+    let per_page = LABEL_COLUMNS * LABEL_ROWS;
+    let mut layer = doc.get_page(page).get_layer(layer);
+    for (i, sample) in samples.iter().enumerate() {
+        if i > 0 && i % per_page == 0 {
+            let (page, l) = doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Labels");
+            layer = doc.get_page(page).get_layer(l);
+        }
+        draw_label(&layer, i % per_page, sample, &font, &bold)?;
+    }
+    Ok(doc.save_to_bytes()?)
+}
+
+/// The name of the PDF file of the field data sheets of the given project when it is downloaded
+pub fn field_sheets_filename(project: &Project) -> String {
+    format!(
+        "{}-field-sheets.pdf",
+        format_id_number(project.id, Some("P"), None)
+    )
+}
+
+/// An entry of a field data sheet: what is already known about a collection, and the page where
+/// the collection is recorded
+struct SheetEntry {
+    title: String,
+    lines: Vec<String>,
+    url: String,
+}
+
+/// Draw a line for writing on, from `x1` to `x2` at the height `y`
+fn draw_blank(layer: &PdfLayerReference, x1: f32, x2: f32, y: f32) {
+    layer.add_line(Line {
+        points: vec![
+            (Point::new(Mm(x1), Mm(y)), false),
+            (Point::new(Mm(x2), Mm(y)), false),
+        ],
+        is_closed: false,
+    });
+}
+
+/// Draw a QR code with the given text with its lower left corner at `x`, `y`
+fn draw_qr(layer: &PdfLayerReference, text: &str, x: f32, y: f32) -> Result<()> {
+    let code = QrCode::new(text.as_bytes()).map_err(|e| anyhow!("Failed to encode {text}: {e}"))?;
+    let width = code.width();
+    let module = SHEET_QR_SIZE / width as f32;
+    for (i, color) in code.to_colors().into_iter().enumerate() {
+        if color == qrcode::Color::Light {
+            continue;
+        }
+        let (column, row) = ((i % width) as f32, (i / width) as f32);
+        let left = x + column * module;
+        let top = y + SHEET_QR_SIZE - row * module;
+        layer.add_rect(Rect::new(
+            Mm(left),
+            Mm(top - module),
+            Mm(left + module),
+            Mm(top),
+        ));
+    }
+    Ok(())
+}
+
+fn draw_sheet_entry(
+    layer: &PdfLayerReference,
+    index: usize,
+    entry: &SheetEntry,
+    font: &IndirectFontRef,
+    bold: &IndirectFontRef,
+) -> Result<()> {
+    let top = PAGE_HEIGHT - SHEET_MARGIN - SHEET_HEADER_HEIGHT - index as f32 * SHEET_ENTRY_HEIGHT;
+    let (left, right) = (SHEET_MARGIN, PAGE_WIDTH - SHEET_MARGIN);
+    let text_right = right - SHEET_QR_SIZE - 5.0;
+    layer.set_outline_thickness(0.8);
+    draw_blank(layer, left, right, top);
+    layer.set_outline_thickness(0.3);
+    draw_qr(
+        layer,
+        &entry.url,
+        right - SHEET_QR_SIZE,
+        top - SHEET_QR_SIZE - 4.0,
+    )?;
+
+    let mut y = top - 6.0;
+    layer.use_text(entry.title.clone(), 11.0, Mm(left), Mm(y), bold);
+    for line in &entry.lines {
+        y -= 5.0;
+        layer.use_text(line.clone(), 9.0, Mm(left), Mm(y), font);
+        if line.ends_with(':') {
+            draw_blank(layer, left + 20.0, text_right, y - 0.5);
+        }
+    }
+    y -= 7.0;
+    layer.use_text("Date:", 9.0, Mm(left), Mm(y), font);
+    draw_blank(layer, left + 20.0, left + 60.0, y - 0.5);
+    layer.use_text("Quantity:", 9.0, Mm(left + 65.0), Mm(y), font);
+    draw_blank(layer, left + 82.0, text_right, y - 0.5);
+    y -= 7.0;
+    layer.use_text("Notes:", 9.0, Mm(left), Mm(y), font);
+    draw_blank(layer, left + 20.0, text_right, y - 0.5);
+    y -= 7.0;
+    draw_blank(layer, left, text_right, y - 0.5);
+    Ok(())
+}
+
+/// A PDF of data sheets for collecting the samples of a project in the field. Each entry of a
+/// sheet has blanks for the date, quantity and notes of a collection, and a QR code that opens
+/// the page where it is recorded. The entries are filled in with the taxon and source of each
+/// sample that is allocated to the project, or left blank for collections that aren't planned
+/// yet, which are recorded as new samples.
+pub fn field_sheets_pdf(state: &AppState, project: &Project, blank: bool) -> Result<Vec<u8>> {
+    let absolute = |path: &str| state.config.absolute_url(&app_url(path));
+    let entries = if blank {
+        (0..SHEET_ENTRIES)
+            .map(|_| SheetEntry {
+                title: "New collection".to_string(),
+                lines: vec!["Taxon:".to_string(), "Source:".to_string()],
+                url: absolute("/sample/new"),
+            })
+            .collect()
+    } else {
+        project
+            .allocations
+            .iter()
+            .map(|alloc| {
+                let sample = &alloc.sample;
+                let taxon = sample.taxon.object()?;
+                let mut lines = Vec::new();
+                if let Some(name) = taxon.vernaculars.first() {
+                    lines.push(name.clone());
+                }
+                lines.push(format!("Source: {}", sample.source.object()?.name));
+                Ok(SheetEntry {
+                    title: format!(
+                        "{}  {}",
+                        format_id_number(sample.id, Some("S"), None),
+                        taxon.complete_name
+                    ),
+                    lines,
+                    url: absolute(&format!("/project/{}/sample/{}", project.uuid, alloc.uuid)),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?
+    };
+
+    let (doc, page, layer) =
+        PdfDocument::new(&project.name, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Field data");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+    let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+    let mut layer = doc.get_page(page).get_layer(layer);
+    let pages = entries.len().div_ceil(SHEET_ENTRIES).max(1);
+    for n in 0..pages {
+        if n > 0 {
+            let (page, l) = doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Field data");
+            layer = doc.get_page(page).get_layer(l);
+        }
+        let y = PAGE_HEIGHT - SHEET_MARGIN - 6.0;
+        layer.use_text(project.name.clone(), 14.0, Mm(SHEET_MARGIN), Mm(y), &bold);
+        layer.use_text(
+            format!(
+                "Field data sheet {} of {pages} - scan the code of an entry to record it",
+                n + 1
+            ),
+            8.0,
+            Mm(SHEET_MARGIN),
+            Mm(y - 6.0),
+            &font,
+        );
+        for (i, entry) in entries
+            .iter()
+            .skip(n * SHEET_ENTRIES)
+            .take(SHEET_ENTRIES)
+            .enumerate()
+        {
+            draw_sheet_entry(&layer, i, entry, &font, &bold)?;
+        }
+    }
+    Ok(doc.save_to_bytes()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SharedState;
+    use std::{io::Read, sync::Arc};
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn test_build(pool: sqlx::Pool<sqlx::Sqlite>) {
+        let state: AppState = Arc::new(SharedState::test(pool.clone()));
+        let mut bundle = Bundle::new(1, 1);
+        bundle.insert(&pool).await.unwrap();
+        let data = build(&state, &bundle)
+            .await
+            .expect("Failed to build bundle");
+
+        let mut zip = zip::ZipArchive::new(Cursor::new(data)).expect("Not a zip file");
+        let mut names: Vec<&str> = zip.file_names().collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "P0001/allocations.csv",
+                "P0001/field-sheets.pdf",
+                "P0001/germination-plan.csv",
+                "P0001/labels.pdf",
+                "P0001/report.html",
+                "P0001/species.csv",
+            ]
+        );
+        let project = Project::load(1, &pool).await.unwrap();
+        let mut report = String::new();
+        zip.by_name("P0001/report.html")
+            .unwrap()
+            .read_to_string(&mut report)
+            .unwrap();
+        assert!(report.contains(&project.name));
+        let mut pdf = Vec::new();
+        zip.by_name("P0001/labels.pdf")
+            .unwrap()
+            .read_to_end(&mut pdf)
+            .unwrap();
+        assert!(pdf.starts_with(b"%PDF"));
+    }
+}
@@ -1,6 +1,235 @@
+//! Database connections and instrumentation of the queries made while handling a request
+use crate::{state::AppState, QueryLogConfig};
 use anyhow::Result;
-use sqlx::SqlitePool;
+use axum::{extract::State, http::HeaderValue, middleware::Next, response::Response};
+use sqlx::{sqlite::SqliteConnectOptions, ConnectOptions, SqlitePool};
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tracing::{field::Visit, span, Instrument, Subscriber};
+use tracing_subscriber::{
+    layer::Context,
+    registry::{LookupSpan, Registry},
+    Layer,
+};
 
-pub async fn pool(db: String) -> Result<SqlitePool> {
-    Ok(SqlitePool::connect(&format!("sqlite://{}", db)).await?)
+/// The tracing target of the per-request span that database queries are counted in
+pub const QUERY_STATS_TARGET: &str = "seedweb::query_stats";
+
+/// The tracing target that sqlx uses for logging statements
+const SQLX_QUERY_TARGET: &str = "sqlx::query";
+
+pub async fn pool(db: String, config: &QueryLogConfig) -> Result<SqlitePool> {
+    let mut options = SqliteConnectOptions::from_str(&format!("sqlite://{}", db))?;
+    if let Some(ms) = config.slow_query_ms {
+        options = options.log_slow_statements(log::LevelFilter::Warn, Duration::from_millis(ms));
+    }
+    Ok(SqlitePool::connect_with(options).await?)
+}
+
+/// The number of database queries made while handling a request and their total execution time
+#[derive(Debug, Default)]
+pub struct QueryStats {
+    count: AtomicU64,
+    micros: AtomicU64,
+}
+
+impl QueryStats {
+    fn record(&self, elapsed: Duration) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_micros(self.micros.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Default)]
+struct ElapsedVisitor(Option<f64>);
+
+impl Visit for ElapsedVisitor {
+    fn record_f64(&mut self, field: &tracing::field::Field, value: f64) {
+        if field.name() == "elapsed_secs" {
+            self.0 = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, _field: &tracing::field::Field, _value: &dyn std::fmt::Debug) {}
+}
+
+/// A tracing layer that adds up the statements logged by sqlx to the [QueryStats] of the request
+/// they were made for. sqlx executes statements on a separate thread, but it enters the span of
+/// the caller, so the request can be found by looking for the enclosing request span.
+///
+/// sqlx only logs statements if a subscriber is interested in them, so this layer must be
+/// registered with a filter that enables the `sqlx::query` target at the debug level.
+pub struct QueryStatsLayer;
+
+impl<S> Layer<S> for QueryStatsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        if event.metadata().target() != SQLX_QUERY_TARGET {
+            return;
+        }
+        let Some(scope) = ctx.event_scope(event) else {
+            return;
+        };
+        for span in scope {
+            if let Some(stats) = span.extensions().get::<Arc<QueryStats>>() {
+                let mut visitor = ElapsedVisitor::default();
+                event.record(&mut visitor);
+                stats.record(Duration::from_secs_f64(visitor.0.unwrap_or_default()));
+                break;
+            }
+        }
+    }
+}
+
+/// Attach the query statistics to a span so that [QueryStatsLayer] can find them
+fn attach_stats(span: &span::Span, stats: &Arc<QueryStats>) {
+    span.with_subscriber(|(id, dispatch)| {
+        if let Some(span) = dispatch
+            .downcast_ref::<Registry>()
+            .and_then(|registry| registry.span(id))
+        {
+            span.extensions_mut().insert(stats.clone());
+        }
+    });
+}
+
+/// Middleware that counts the database queries made while handling a request. The result is
+/// logged, and optionally added to the response as a `Server-Timing` header so that it shows up
+/// in the browser's developer tools.
+pub async fn track_queries(
+    State(state): State<AppState>,
+    request: axum::extract::Request,
+    next: Next,
+) -> Response {
+    let stats = Arc::new(QueryStats::default());
+    let span = tracing::info_span!(target: QUERY_STATS_TARGET, "db_queries");
+    attach_stats(&span, &stats);
+    let mut response = next.run(request).instrument(span.clone()).await;
+
+    let elapsed = stats.elapsed();
+    span.in_scope(|| {
+        tracing::debug!(
+            target: QUERY_STATS_TARGET,
+            queries = stats.count(),
+            elapsed_ms = elapsed.as_secs_f64() * 1000.0,
+            "database queries for request"
+        )
+    });
+    if state.config.query_log.timing_header {
+        let value = format!(
+            "db;dur={:.3};desc=\"{} queries\"",
+            elapsed.as_secs_f64() * 1000.0,
+            stats.count()
+        );
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response.headers_mut().insert("Server-Timing", value);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        app_url,
+        html::tests::{login, sample_path},
+        state::SharedState,
+    };
+    use axum::{body::Body, http::Request, Router};
+    use sqlx::{Pool, Sqlite};
+    use test_log::test;
+    use tower::Service;
+    use tracing::Level;
+    use tracing_subscriber::{filter::Targets, layer::SubscriberExt};
+
+    /// Route the statements that sqlx logs to [QueryStatsLayer] like `main()` does. The statements
+    /// are logged on sqlx's worker thread, so this has to be the global subscriber.
+    fn init_query_stats() {
+        let _ = tracing::subscriber::set_global_default(
+            tracing_subscriber::registry().with(
+                QueryStatsLayer.with_filter(
+                    Targets::new()
+                        .with_target(SQLX_QUERY_TARGET, Level::DEBUG)
+                        .with_target(QUERY_STATS_TARGET, Level::INFO),
+                ),
+            ),
+        );
+    }
+
+    /// The number of queries reported in the `Server-Timing` header of the response to a GET
+    /// request for `uri`, if the header is present
+    async fn timed_queries(app: &mut Router, uri: &str, cookie: &str) -> Option<u64> {
+        let request = Request::builder()
+            .uri(uri)
+            .header("Cookie", cookie)
+            .body(Body::empty())
+            .unwrap();
+        let response = app.as_service().call(request).await.unwrap();
+        assert!(response.status().is_success() || response.status().is_redirection());
+        let header = response.headers().get("Server-Timing")?.to_str().unwrap();
+        let (dur, desc) = header
+            .strip_prefix("db;dur=")
+            .and_then(|rest| rest.split_once(";desc=\""))
+            .unwrap_or_else(|| panic!("unexpected header {header}"));
+        assert!(dur.parse::<f64>().unwrap() >= 0.0);
+        desc.strip_suffix(" queries\"").map(|n| n.parse().unwrap())
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn test_timing_header(pool: Pool<Sqlite>) {
+        init_query_stats();
+        let mut shared = SharedState::test(pool.clone());
+        shared.config.query_log.timing_header = true;
+        let mut app = crate::app(Arc::new(shared))
+            .await
+            .expect("failed to create test app");
+        let cookie = login(&mut app).await.expect("Failed to log in");
+        let path = app_url(&sample_path(1, &pool).await);
+        let list = app_url("/sample/list");
+
+        // the queries of each request are counted separately. The session may or may not be
+        // saved again, so the counts of two identical requests can differ slightly.
+        let count = timed_queries(&mut app, &path, &cookie).await.unwrap();
+        assert!(count > 0);
+        let again = timed_queries(&mut app, &path, &cookie).await.unwrap();
+        assert!(
+            again > 0 && again < 2 * count,
+            "{again} after {count} queries"
+        );
+        assert!(timed_queries(&mut app, &list, &cookie).await.unwrap() > 0);
+        // requests that don't touch the database don't make any queries
+        assert_eq!(timed_queries(&mut app, "/favicon.ico", "").await, Some(0));
+
+        // the header is only added if it is enabled in the configuration
+        let mut app = crate::app(Arc::new(SharedState::test(pool.clone())))
+            .await
+            .expect("failed to create test app");
+        let cookie = login(&mut app).await.expect("Failed to log in");
+        assert_eq!(timed_queries(&mut app, &path, &cookie).await, None);
+        assert_eq!(timed_queries(&mut app, &list, &cookie).await, None);
+    }
 }
@@ -0,0 +1,61 @@
+//! The public demo mode. Anybody can log in to the guest account to try out the site, so the
+//! account is read-only and its data is regularly replaced with an example dataset.
+use crate::{
+    app_url, auth::AuthSession, error::Error, html::error_alert_response, state::AppState,
+};
+use anyhow::Result;
+use axum::{
+    extract::{Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use libseed::demo;
+use tracing::info;
+
+const READ_ONLY_MESSAGE: &str = "The demo account is read-only. Changes can't be saved.";
+
+/// Whether the logged in user is the guest account of the demo mode
+pub fn is_guest(state: &AppState, auth: &AuthSession) -> bool {
+    match (&state.config.demo, &auth.user) {
+        (Some(config), Some(user)) => user.username == config.username,
+        _ => false,
+    }
+}
+
+/// Reject every request of the guest account that could modify data. Logging in and out is still
+/// allowed.
+pub async fn read_only(
+    State(state): State<AppState>,
+    auth: AuthSession,
+    request: Request,
+    next: Next,
+) -> Response {
+    let safe = matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    if safe || !is_guest(&state, &auth) || request.uri().path().starts_with(&app_url("/auth/")) {
+        return next.run(request).await;
+    }
+    if request.headers().get("HX-Request").is_some() {
+        error_alert_response(&state, StatusCode::FORBIDDEN, READ_ONLY_MESSAGE.to_string())
+            .into_response()
+    } else {
+        Error::Forbidden(READ_ONLY_MESSAGE.to_string()).into_response()
+    }
+}
+
+/// Create the guest account if necessary and replace its data with the example dataset
+pub async fn reset(state: &AppState) -> Result<()> {
+    let Some(config) = &state.config.demo else {
+        return Ok(());
+    };
+    let user = demo::ensure_user(&config.username, &state.dbpool).await?;
+    let n = demo::reset(&user, &state.dbpool).await?;
+    info!(
+        user.username,
+        "Reset the demo account with {n} example samples"
+    );
+    Ok(())
+}
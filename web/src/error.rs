@@ -14,6 +14,10 @@ pub enum Error {
     Other(#[from] anyhow::Error),
     #[error("Redirect to another url")]
     Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    PreconditionRequired(String),
     #[error("Library error")]
     Libseed(#[from] libseed::Error),
     #[error("Not Found")]
@@ -23,6 +27,15 @@ pub enum Error {
 }
 
 impl Error {
+    /// Whether this error was caused by trying to save an object that was modified in the database
+    /// after it was loaded
+    pub fn is_version_conflict(&self) -> bool {
+        matches!(
+            self,
+            Error::Libseed(libseed::Error::DatabaseVersionConflict(_))
+        )
+    }
+
     pub fn to_client_status(&self) -> (StatusCode, String) {
         match self {
             Error::Database(_) => (
@@ -33,9 +46,21 @@ impl Error {
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Unknown error".to_string(),
             ),
+            Error::Libseed(libseed::Error::DatabaseVersionConflict(_)) => (
+                StatusCode::CONFLICT,
+                "The object was modified by someone else".to_string(),
+            ),
+            Error::Libseed(libseed::Error::InvalidValue(msg)) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, msg.clone())
+            }
+            Error::Libseed(libseed::Error::DatabaseRowNotFound(_)) => {
+                (StatusCode::NOT_FOUND, "Not found".to_string())
+            }
             // FIXME: make this more specific
             Error::Libseed(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Library error".to_string()),
             Error::Unauthorized(_) => (StatusCode::UNAUTHORIZED, "Not authorized".to_string()),
+            Error::Forbidden(msg) => (StatusCode::FORBIDDEN, msg.clone()),
+            Error::PreconditionRequired(msg) => (StatusCode::PRECONDITION_REQUIRED, msg.clone()),
             Error::NotFound(_) => (StatusCode::NOT_FOUND, "Page not found".to_string()),
             Error::UnprocessableEntityQueryRejection(_) => (
                 StatusCode::UNPROCESSABLE_ENTITY,
@@ -0,0 +1,264 @@
+use crate::{
+    app_url, auth::SqliteUser, error, html::error_alert_response, itis, jobs, state::AppState,
+    TemplateKey,
+};
+use anyhow::anyhow;
+use axum::{
+    extract::{multipart::Field, DefaultBodyLimit, Multipart, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use axum_template::RenderHtml;
+use libseed::taxonomy::{
+    staged::{StagedRelease, StagedStatus},
+    upgrade::{TaxonChange, UpgradeReport, UpgradeTree},
+};
+use minijinja::context;
+use tokio::io::AsyncWriteExt;
+
+/// The largest ITIS release that can be uploaded. The zip file that ITIS publishes is a few
+/// hundred megabytes.
+const MAX_RELEASE_SIZE: usize = 2 * 1024 * 1024 * 1024;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/taxonomy", get(list_upgrades))
+        .route("/taxonomy/:id", get(show_upgrade))
+        .route(
+            "/taxonomy/release",
+            post(stage_release).layer(DefaultBodyLimit::max(MAX_RELEASE_SIZE)),
+        )
+        .route(
+            "/taxonomy/release/:id",
+            get(show_release)
+                .post(apply_release)
+                .delete(discard_release),
+        )
+        .route("/taxonomy/release/:id/progress", get(show_release_progress))
+}
+
+fn require_admin(user: &SqliteUser) -> Result<(), error::Error> {
+    match user.admin {
+        true => Ok(()),
+        false => Err(error::Error::Unauthorized(
+            "Only administrators can view taxonomy upgrades".to_string(),
+        )),
+    }
+}
+
+async fn list_upgrades(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    require_admin(&user)?;
+    let upgrades = UpgradeReport::load_all(&state.dbpool).await?;
+    let releases = StagedRelease::load_all(&state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 upgrades => upgrades,
+                 releases => releases,
+                 itis_url => itis::ITIS_DOWNLOAD_URL),
+    ))
+}
+
+async fn show_upgrade(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, error::Error> {
+    require_admin(&user)?;
+    let upgrade = UpgradeReport::load(id, &state.dbpool)
+        .await
+        .map_err(|_| error::Error::NotFound(format!("Taxonomy upgrade {id} not found")))?;
+    let changes = upgrade.changes(&state.dbpool).await?;
+    let tree = UpgradeTree::new(&changes);
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, upgrade => upgrade, tree => tree),
+    ))
+}
+
+/// Store an uploaded ITIS release in the file that it is fetched into
+async fn receive_release(mut field: Field<'_>, staged: &StagedRelease) -> anyhow::Result<u64> {
+    tokio::fs::create_dir_all(itis::release_dir()).await?;
+    let mut file = tokio::fs::File::create(itis::archive_path(staged)).await?;
+    let mut size = 0;
+    while let Some(bytes) = field.chunk().await? {
+        file.write_all(&bytes).await?;
+        size += bytes.len() as u64;
+    }
+    file.flush().await?;
+    Ok(size)
+}
+
+/// Stage an ITIS release that is either uploaded or downloaded from a URL, and start checking it
+/// in the background
+async fn stage_release(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<Response, error::Error> {
+    require_admin(&user)?;
+    let read_error = |e| anyhow!("Failed to read the upload: {e}");
+    let mut url = None;
+    let mut upload = None;
+    while let Some(field) = multipart.next_field().await.map_err(read_error)? {
+        match field.name() {
+            Some("url") => url = Some(field.text().await.map_err(read_error)?),
+            Some("file") if upload.is_none() => {
+                let filename = field.file_name().unwrap_or_default().trim().to_string();
+                // the file input is sent without a name when no file was chosen
+                if filename.is_empty() {
+                    continue;
+                }
+                let staged = StagedRelease::create(user.id, &filename, &state.dbpool).await?;
+                let received = receive_release(field, &staged).await;
+                if !matches!(received, Ok(size) if size > 0) {
+                    itis::remove_files(&staged);
+                    staged.delete(&state.dbpool).await?;
+                    let message = match received {
+                        Err(e) => format!("Failed to receive the upload: {e:#}"),
+                        Ok(_) => "The uploaded file is empty".to_string(),
+                    };
+                    return Ok(error_alert_response(
+                        &state,
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        message,
+                    )
+                    .into_response());
+                }
+                upload = Some(staged);
+            }
+            _ => (),
+        }
+    }
+    let (staged, uploaded) = match upload {
+        Some(staged) => (staged, true),
+        None => {
+            let url = url
+                .map(|u| u.trim().to_string())
+                .filter(|u| !u.is_empty())
+                .unwrap_or_else(|| itis::ITIS_DOWNLOAD_URL.to_string());
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Ok(error_alert_response(
+                    &state,
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "The release can only be downloaded from an http or https URL".to_string(),
+                )
+                .into_response());
+            }
+            let staged = StagedRelease::create(user.id, &url, &state.dbpool).await?;
+            (staged, false)
+        }
+    };
+    let url = app_url(&format!("/admin/taxonomy/release/{}", staged.id));
+    jobs::prepare_release(state.clone(), staged, uploaded);
+    Ok([("HX-Redirect", url)].into_response())
+}
+
+async fn load_release(id: i64, state: &AppState) -> Result<StagedRelease, error::Error> {
+    StagedRelease::load(id, &state.dbpool)
+        .await
+        .map_err(|_| error::Error::NotFound(format!("ITIS release {id} not found")))
+}
+
+/// Show the progress of a staged release, or the changes that it would make once they can be
+/// reviewed
+async fn show_release(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, error::Error> {
+    require_admin(&user)?;
+    let release = load_release(id, &state).await?;
+    let (tree, summary) = match release.status {
+        StagedStatus::Ready => {
+            let changes = release.changes(&state.dbpool).await?;
+            let count = |f: fn(&TaxonChange) -> bool| changes.iter().filter(|c| f(c)).count();
+            let summary = context!(added => count(TaxonChange::is_added),
+                                   removed => count(TaxonChange::is_removed),
+                                   renamed => count(TaxonChange::is_renamed),
+                                   moved => count(TaxonChange::is_moved));
+            (Some(UpgradeTree::new(&changes)), Some(summary))
+        }
+        _ => (None, None),
+    };
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, release => release, tree => tree, summary => summary),
+    ))
+}
+
+/// The progress of a staged release while a background job is working on it. Once the job is
+/// done, the client is sent to the changes or the recorded upgrade instead.
+async fn show_release_progress(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Response, error::Error> {
+    require_admin(&user)?;
+    let release = load_release(id, &state).await?;
+    let redirect = match (release.status, release.upgradeid) {
+        (StagedStatus::Ready, _) => Some(format!("/admin/taxonomy/release/{id}")),
+        (StagedStatus::Applied, Some(upgradeid)) => Some(format!("/admin/taxonomy/{upgradeid}")),
+        _ => None,
+    };
+    if let Some(redirect) = redirect {
+        return Ok([("HX-Redirect", app_url(&redirect))].into_response());
+    }
+    Ok(RenderHtml(key, state.tmpl.clone(), context!(release => release)).into_response())
+}
+
+/// Upgrade the taxonomy to a reviewed release in the background
+async fn apply_release(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Response, error::Error> {
+    require_admin(&user)?;
+    let mut release = load_release(id, &state).await?;
+    match release.start_applying(&state.dbpool).await {
+        Ok(()) => (),
+        Err(libseed::Error::InvalidValue(message)) => {
+            return Ok(error_alert_response(&state, StatusCode::CONFLICT, message).into_response())
+        }
+        Err(e) => return Err(e.into()),
+    }
+    jobs::apply_release(state.clone(), release);
+    Ok([(
+        "HX-Redirect",
+        app_url(&format!("/admin/taxonomy/release/{id}")),
+    )]
+    .into_response())
+}
+
+/// Remove a staged release that is no longer needed, along with its files
+async fn discard_release(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<Response, error::Error> {
+    require_admin(&user)?;
+    let release = load_release(id, &state).await?;
+    if release.status.is_running() {
+        return Ok(error_alert_response(
+            &state,
+            StatusCode::CONFLICT,
+            "The release cannot be discarded while it is being processed".to_string(),
+        )
+        .into_response());
+    }
+    itis::remove_files(&release);
+    release.delete(&state.dbpool).await?;
+    Ok([("HX-Redirect", app_url("/admin/taxonomy"))].into_response())
+}
@@ -1,35 +1,47 @@
-use super::error_alert_response;
+use super::{error_alert_response, project::load_project};
 use crate::{
     app_url,
     auth::SqliteUser,
     error::{self, Error},
+    format_id_number,
     state::AppState,
     Message, MessageType, TemplateKey,
 };
 use anyhow::anyhow;
 use axum::{
-    extract::{rejection::FormRejection, Path, State},
+    extract::{rejection::FormRejection, Path, Query, State},
     http::StatusCode,
     response::IntoResponse,
-    routing::{delete, get},
+    routing::{delete, get, post, put},
     Form, Router,
 };
 use axum_template::RenderHtml;
 use libseed::{
+    cultivation::CultivationNotes,
     empty_string_as_none,
     filter::{CompoundFilter, Op},
     loadable::Loadable,
-    project::{self, allocation, Allocation, Note, NoteType, Project},
+    organization::Permission,
+    project::{
+        allocation,
+        planting::{Planting, SURVIVAL_CHECKS},
+        status::{AllocationStatus, StatusEvent},
+        template::NoteTemplate,
+        Allocation, Note, NoteType,
+    },
+    reminder::Reminder,
+    taxonomy::names::DisplayName,
 };
 use minijinja::context;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
 use strum::IntoEnumIterator;
 use tracing::error;
+use uuid::Uuid;
 
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/:alloc", get(show_allocation).delete(remove_allocation))
+        .route("/:alloc/status", put(update_status))
         .route("/:alloc/note/:noteid", delete(delete_note))
         .route(
             "/:alloc/note/:noteid/edit",
@@ -39,26 +51,28 @@ pub fn router() -> Router<AppState> {
             "/:alloc/note/new",
             get(show_add_allocation_note).post(add_allocation_note),
         )
+        .route("/:alloc/planting", post(add_planting))
+        .route("/:alloc/planting/:planting", delete(delete_planting))
+        .route("/:alloc/planting/:planting/check", post(add_survival_check))
 }
 
 async fn show_allocation(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
-    Path((projectid, allocid)): Path<(i64, i64)>,
+    Path((projectid, allocid)): Path<(Uuid, Uuid)>,
 ) -> Result<impl IntoResponse, error::Error> {
     // make sure that this is our sample
-    let mut allocation = Allocation::load_one(
-        Some(
+    let mut allocation = user
+        .scope()
+        .allocation(
             CompoundFilter::builder(Op::And)
-                .push(allocation::Filter::Id(allocid))
-                .push(allocation::Filter::UserId(user.id))
-                .push(allocation::Filter::ProjectId(projectid))
+                .push(allocation::Filter::Uuid(allocid))
+                .push(allocation::Filter::ProjectUuid(projectid))
                 .build(),
-        ),
-        &state.dbpool,
-    )
-    .await?;
+            &state.dbpool,
+        )
+        .await?;
 
     allocation.load_notes(&state.dbpool).await?;
     allocation
@@ -67,15 +81,61 @@ async fn show_allocation(
         .object_mut()?
         .load_germination_info(&state.dbpool)
         .await?;
+    let cultivation =
+        CultivationNotes::load_taxon(allocation.sample.taxon.id(), user.id, &state.dbpool).await?;
+    let taxon_names = DisplayName::load_map(user.id, &state.dbpool).await?;
+    let status_events = StatusEvent::load_allocation(allocation.id, &state.dbpool).await?;
+    let statuses: Vec<AllocationStatus> = AllocationStatus::iter().collect();
+    let plantings = Planting::load_allocation(allocation.id, &state.dbpool).await?;
     Ok(RenderHtml(
         key,
         state.tmpl.clone(),
         context!(user => user,
-                 allocation => allocation),
+                 allocation => allocation,
+                 plantings => plantings,
+                 survival_checks => SURVIVAL_CHECKS,
+                 cultivation => cultivation,
+                 statuses => statuses,
+                 status_events => status_events,
+                 taxon_names => taxon_names),
     )
     .into_response())
 }
 
+#[derive(Deserialize)]
+struct StatusParams {
+    status: AllocationStatus,
+}
+
+async fn update_status(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path((projectid, allocid)): Path<(Uuid, Uuid)>,
+    Form(params): Form<StatusParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut allocation = user
+        .scope()
+        .allocation(
+            CompoundFilter::builder(Op::And)
+                .push(allocation::Filter::Uuid(allocid))
+                .push(allocation::Filter::ProjectUuid(projectid))
+                .build(),
+            &state.dbpool,
+        )
+        .await
+        .map_err(|_| {
+            Error::NotFound(format!(
+                "Allocation {allocid} not found for project {projectid}"
+            ))
+        })?;
+    load_project(&user, projectid, Permission::Edit, &state).await?;
+    allocation
+        .set_status(params.status, Some(user.id), &state.dbpool)
+        .await?;
+    let url = app_url(&format!("/project/{projectid}/sample/{allocid}"));
+    Ok([("HX-Redirect", url)].into_response())
+}
+
 #[derive(Deserialize, Serialize)]
 struct NoteParams {
     summary: String,
@@ -83,12 +143,15 @@ struct NoteParams {
     notetype: NoteType,
     #[serde(deserialize_with = "empty_string_as_none")]
     details: Option<String>,
+    /// the length of a cold stratification that was started with the note, in days
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    stratification: Option<u32>,
 }
 
 async fn add_allocation_note(
     user: SqliteUser,
     State(state): State<AppState>,
-    Path((projectid, allocid)): Path<(i64, i64)>,
+    Path((projectid, allocid)): Path<(Uuid, Uuid)>,
     form: Result<Form<NoteParams>, FormRejection>,
 ) -> impl IntoResponse {
     let params = match form {
@@ -100,23 +163,22 @@ async fn add_allocation_note(
     };
 
     // just querying to make sure that this is our sample
-    let _alloc = match Allocation::load_one(
-        Some(
+    let alloc = match user
+        .scope()
+        .allocation(
             CompoundFilter::builder(Op::And)
-                .push(allocation::Filter::Id(allocid))
-                .push(allocation::Filter::UserId(user.id))
-                .push(allocation::Filter::ProjectId(projectid))
+                .push(allocation::Filter::Uuid(allocid))
+                .push(allocation::Filter::ProjectUuid(projectid))
                 .build(),
-        ),
-        &state.dbpool,
-    )
-    .await
+            &state.dbpool,
+        )
+        .await
     {
         Ok(alloc) => alloc,
         Err(e) => {
             error!("Failed to fetch allocation: {}", e);
             match e {
-                sqlx::Error::RowNotFound => {
+                libseed::Error::DatabaseRowNotFound(_) => {
                     return error_alert_response(
                         &state,
                         StatusCode::NOT_FOUND,
@@ -136,6 +198,10 @@ async fn add_allocation_note(
         }
     };
 
+    if let Err(e) = load_project(&user, projectid, Permission::Edit, &state).await {
+        return e.into_response();
+    }
+
     if params.summary.is_empty() {
         return error_alert_response(
             &state,
@@ -145,15 +211,35 @@ async fn add_allocation_note(
         .into_response();
     }
 
+    if params.stratification == Some(0) {
+        return error_alert_response(
+            &state,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Stratification must last at least one day".to_string(),
+        )
+        .into_response();
+    }
+
     let note = Note::new(
-        allocid,
+        alloc.id,
         params.date,
         params.notetype,
         params.summary.clone(),
         params.details.as_ref().cloned(),
     );
     match note.insert(&state.dbpool).await {
-        Ok(_) => {
+        Ok(note) => {
+            if let Some(days) = params.stratification {
+                if let Err(e) = schedule_stratification(&user, &alloc, &note, days, &state).await {
+                    error!("Failed to schedule stratification reminder: {}", e);
+                    return error_alert_response(
+                        &state,
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        format!("The note was saved, but no reminder could be scheduled: {e}"),
+                    )
+                    .into_response();
+                }
+            }
             let url = app_url(&format!("/project/{}/sample/{}", projectid, allocid));
             [("HX-Redirect", url)].into_response()
         }
@@ -169,30 +255,69 @@ async fn add_allocation_note(
     }
 }
 
+/// Remind the user when the cold stratification that was started with the given note is complete
+async fn schedule_stratification(
+    user: &SqliteUser,
+    alloc: &Allocation,
+    note: &Note,
+    days: u32,
+    state: &AppState,
+) -> Result<(), Error> {
+    let title = format!(
+        "The {days}-day cold stratification of {} {} in {} is complete",
+        format_id_number(alloc.sample.id, Some("S"), None),
+        alloc.sample.taxon.object()?.complete_name,
+        alloc.project.name
+    );
+    let mut reminder = Reminder::stratification(user.id, note, days, title)?;
+    reminder.insert(&state.dbpool).await?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct NewNoteParams {
+    /// the note template of the project to pre-fill the note from
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    template: Option<i64>,
+}
+
 async fn show_add_allocation_note(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
-    Path((projectid, allocid)): Path<(i64, i64)>,
+    Path((projectid, allocid)): Path<(Uuid, Uuid)>,
+    Query(params): Query<NewNoteParams>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let allocation = Allocation::load_one(
-        Some(
+    let allocation = user
+        .scope()
+        .allocation(
             CompoundFilter::builder(Op::And)
-                .push(allocation::Filter::Id(allocid))
-                .push(allocation::Filter::UserId(user.id))
-                .push(allocation::Filter::ProjectId(projectid))
+                .push(allocation::Filter::Uuid(allocid))
+                .push(allocation::Filter::ProjectUuid(projectid))
                 .build(),
+            &state.dbpool,
+        )
+        .await?;
+    let project = load_project(&user, projectid, Permission::Edit, &state).await?;
+    let templates = NoteTemplate::load_project(project.id, &state.dbpool).await?;
+    let template = match params.template {
+        Some(id) => Some(
+            templates
+                .iter()
+                .find(|t| t.id == id)
+                .ok_or_else(|| Error::NotFound("That template does not exist".to_string()))?,
         ),
-        &state.dbpool,
-    )
-    .await?;
+        None => None,
+    };
     let note_types: Vec<NoteType> = NoteType::iter().collect();
     Ok(RenderHtml(
         key,
         state.tmpl.clone(),
         context!(user => user,
                  note_types => note_types,
-                 allocation => allocation),
+                 allocation => allocation,
+                 templates => templates,
+                 template => template),
     )
     .into_response())
 }
@@ -200,19 +325,12 @@ async fn show_add_allocation_note(
 async fn remove_allocation(
     user: SqliteUser,
     State(state): State<AppState>,
-    Path((id, psid)): Path<(i64, i64)>,
+    Path((projectid, allocid)): Path<(Uuid, Uuid)>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let mut projects =
-        Project::load_all(Some(Arc::new(project::Filter::Id(id))), &state.dbpool).await?;
-    let Some(c) = projects.pop() else {
-        return Err(Error::NotFound("That project does not exist".to_string()));
-    };
-    if c.userid != user.id {
-        return Err(Error::NotFound("That project does not exist".to_string()));
-    }
-    sqlx::query!(
-        "DELETE FROM sc_project_samples AS PS WHERE PS.psid=? AND PS.projectid IN (SELECT P.projectid FROM sc_projects AS P WHERE P.userid=?)",
-        psid, user.id)
+    let project = load_project(&user, projectid, Permission::Edit, &state).await?;
+    sqlx::query("DELETE FROM sc_project_samples WHERE psuuid=? AND projectid=?")
+        .bind(allocid.to_string())
+        .bind(project.id)
         .execute(&state.dbpool)
         .await?;
     Ok(())
@@ -222,19 +340,15 @@ async fn delete_note(
     user: SqliteUser,
     TemplateKey(_key): TemplateKey,
     State(state): State<AppState>,
-    Path((projectid, allocid, noteid)): Path<(i64, i64, i64)>,
+    Path((projectid, allocid, noteid)): Path<(Uuid, Uuid, Uuid)>,
 ) -> Result<impl IntoResponse, error::Error> {
     // make sure this is a note the user can delete
-    let mut note = Note::load(noteid, &state.dbpool).await?;
+    let mut note = Note::load_uuid(noteid, &state.dbpool).await?;
     let allocation = Allocation::load(note.psid, &state.dbpool).await?;
-    if note.psid != allocid || allocation.project.id != projectid {
+    if allocation.uuid != allocid || allocation.project.uuid != projectid {
         return Err(Into::into(anyhow!("Bad request")));
     }
-    if allocation.sample.user.id() != user.id {
-        return Err(Error::Unauthorized(
-            "No permission to delete this note".to_string(),
-        ));
-    }
+    load_project(&user, projectid, Permission::Edit, &state).await?;
 
     note.delete(&state.dbpool).await?;
     Ok(())
@@ -244,19 +358,15 @@ async fn show_edit_note(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
-    Path((projectid, allocid, noteid)): Path<(i64, i64, i64)>,
+    Path((projectid, allocid, noteid)): Path<(Uuid, Uuid, Uuid)>,
 ) -> Result<impl IntoResponse, error::Error> {
     // make sure this is a note the user can edit
-    let note = Note::load(noteid, &state.dbpool).await?;
+    let note = Note::load_uuid(noteid, &state.dbpool).await?;
     let allocation = Allocation::load(note.psid, &state.dbpool).await?;
-    if note.psid != allocid || allocation.project.id != projectid {
+    if allocation.uuid != allocid || allocation.project.uuid != projectid {
         return Err(Into::into(anyhow!("Bad request")));
     }
-    if allocation.sample.user.id() != user.id {
-        return Err(Error::Unauthorized(
-            "No permission to delete this note".to_string(),
-        ));
-    }
+    load_project(&user, projectid, Permission::Edit, &state).await?;
 
     let note_types: Vec<NoteType> = NoteType::iter().collect();
     Ok(RenderHtml(
@@ -274,20 +384,16 @@ async fn modify_note(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
-    Path((projectid, allocid, noteid)): Path<(i64, i64, i64)>,
+    Path((projectid, allocid, noteid)): Path<(Uuid, Uuid, Uuid)>,
     Form(params): Form<NoteParams>,
 ) -> Result<impl IntoResponse, error::Error> {
     // make sure this is a note the user can edit
-    let mut note = Note::load(noteid, &state.dbpool).await?;
+    let mut note = Note::load_uuid(noteid, &state.dbpool).await?;
     let allocation = Allocation::load(note.psid, &state.dbpool).await?;
-    if note.psid != allocid || allocation.project.id != projectid {
+    if allocation.uuid != allocid || allocation.project.uuid != projectid {
         return Err(Into::into(anyhow!("Bad request")));
     }
-    if allocation.sample.user.id() != user.id {
-        return Err(Error::Unauthorized(
-            "No permission to delete this note".to_string(),
-        ));
-    }
+    load_project(&user, projectid, Permission::Edit, &state).await?;
 
     note.date = params.date;
     note.summary = params.summary;
@@ -317,3 +423,174 @@ async fn modify_note(
         }
     }
 }
+
+/// Load an allocation of the given project that the user is allowed to edit
+async fn load_editable_allocation(
+    user: &SqliteUser,
+    projectid: Uuid,
+    allocid: Uuid,
+    state: &AppState,
+) -> Result<Allocation, Error> {
+    let allocation = user
+        .scope()
+        .allocation(
+            CompoundFilter::builder(Op::And)
+                .push(allocation::Filter::Uuid(allocid))
+                .push(allocation::Filter::ProjectUuid(projectid))
+                .build(),
+            &state.dbpool,
+        )
+        .await
+        .map_err(|_| {
+            Error::NotFound(format!(
+                "Allocation {allocid} not found for project {projectid}"
+            ))
+        })?;
+    load_project(user, projectid, Permission::Edit, state).await?;
+    Ok(allocation)
+}
+
+/// Load a planting of the given allocation
+async fn load_planting(
+    allocation: &Allocation,
+    id: i64,
+    state: &AppState,
+) -> Result<Planting, Error> {
+    let not_found = || Error::NotFound("That planting does not exist".to_string());
+    let planting = Planting::load(id, &state.dbpool)
+        .await
+        .map_err(|_| not_found())?;
+    if planting.psid != allocation.id {
+        return Err(not_found());
+    }
+    Ok(planting)
+}
+
+/// Render the plantings of the allocation along with a message about the change that was made.
+/// Invalid values are shown to the user so that they can correct them.
+async fn render_plantings(
+    user: SqliteUser,
+    key: String,
+    state: AppState,
+    allocation: Allocation,
+    result: libseed::Result<String>,
+) -> Result<impl IntoResponse, Error> {
+    let message = match result {
+        Ok(msg) => Message {
+            r#type: MessageType::Success,
+            msg,
+        },
+        Err(libseed::Error::InvalidValue(msg)) => Message {
+            r#type: MessageType::Error,
+            msg,
+        },
+        Err(e) => return Err(e.into()),
+    };
+    let plantings = Planting::load_allocation(allocation.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 allocation => allocation,
+                 plantings => plantings,
+                 survival_checks => SURVIVAL_CHECKS,
+                 message => message),
+    ))
+}
+
+#[derive(Deserialize)]
+struct PlantingParams {
+    date: time::Date,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    plugs: Option<i64>,
+    planted: i64,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    location: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    notes: Option<String>,
+    /// whether to schedule reminders for the survival checks
+    #[serde(default)]
+    remind: bool,
+}
+
+async fn add_planting(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path((projectid, allocid)): Path<(Uuid, Uuid)>,
+    Form(params): Form<PlantingParams>,
+) -> Result<impl IntoResponse, Error> {
+    let allocation = load_editable_allocation(&user, projectid, allocid, &state).await?;
+    let mut planting = Planting::new(
+        allocation.id,
+        params.date,
+        params.plugs,
+        params.planted,
+        params.location,
+        params.notes,
+    );
+    let mut result = planting
+        .insert(&state.dbpool)
+        .await
+        .map(|_| format!("Recorded the planting of {} plants", planting.planted));
+    if result.is_ok() && params.remind {
+        let label = format!(
+            "{} {} in {}",
+            format_id_number(allocation.sample.id, Some("S"), None),
+            allocation.sample.taxon.object()?.complete_name,
+            allocation.project.name
+        );
+        result = planting
+            .schedule_checks(user.id, allocation.project.id, &label, &state.dbpool)
+            .await
+            .map(|reminders| {
+                format!(
+                    "Recorded the planting of {} plants and scheduled {} survival check reminders",
+                    planting.planted,
+                    reminders.len()
+                )
+            });
+    }
+    render_plantings(user, key, state, allocation, result).await
+}
+
+async fn delete_planting(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path((projectid, allocid, id)): Path<(Uuid, Uuid, i64)>,
+) -> Result<impl IntoResponse, Error> {
+    let allocation = load_editable_allocation(&user, projectid, allocid, &state).await?;
+    let planting = load_planting(&allocation, id, &state).await?;
+    planting.delete(&state.dbpool).await?;
+    let result = Ok("Removed the planting".to_string());
+    render_plantings(user, key, state, allocation, result).await
+}
+
+#[derive(Deserialize)]
+struct SurvivalCheckParams {
+    date: time::Date,
+    months: i64,
+    alive: i64,
+}
+
+async fn add_survival_check(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path((projectid, allocid, id)): Path<(Uuid, Uuid, i64)>,
+    Form(params): Form<SurvivalCheckParams>,
+) -> Result<impl IntoResponse, Error> {
+    let allocation = load_editable_allocation(&user, projectid, allocid, &state).await?;
+    let mut planting = load_planting(&allocation, id, &state).await?;
+    let result = planting
+        .add_check(params.date, params.months, params.alive, &state.dbpool)
+        .await
+        .map(|check| {
+            format!(
+                "Recorded {} of {} plants alive after {} months",
+                check.alive, planting.planted, check.months
+            )
+        });
+    render_plantings(user, key, state, allocation, result).await
+}
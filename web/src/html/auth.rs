@@ -1,11 +1,12 @@
 use super::error_alert_response;
 use crate::{
     app_url,
-    auth::{AuthSession, Credentials},
-    error,
+    auth::{self as webauth, AuthSession, Credentials, SqliteUser},
+    error, mail,
     state::AppState,
     TemplateKey,
 };
+use anyhow::anyhow;
 use axum::{
     extract::{Path, Query, State},
     http::StatusCode,
@@ -15,6 +16,7 @@ use axum::{
 };
 use axum_template::RenderHtml;
 use libseed::{
+    audit::AuditEntry,
     empty_string_as_none,
     loadable::Loadable,
     user::{User, UserStatus},
@@ -23,13 +25,21 @@ use minijinja::context;
 use serde::{Deserialize, Serialize};
 use sqlx::{Pool, Sqlite};
 use time::{macros::format_description, Duration, OffsetDateTime, PrimitiveDateTime};
+use tower_sessions::Session;
 use tracing::{debug, error};
 
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/login", get(show_login).post(do_login))
         .route("/logout", post(logout))
+        .route("/register", get(show_register).post(register_user))
+        .route("/demo", post(demo_login))
         .route("/verify/:key", get(show_verification).post(verify_user))
+        .route(
+            "/impersonate",
+            get(show_impersonation).post(start_impersonation),
+        )
+        .route("/impersonate/stop", post(stop_impersonation))
 }
 
 #[derive(Clone, Deserialize)]
@@ -37,26 +47,80 @@ pub struct RegisterParams {
     pub username: String,
     pub email: String,
     pub password: String,
-    #[serde(deserialize_with = "empty_string_as_none")]
+    #[serde(default, deserialize_with = "empty_string_as_none")]
     pub next: Option<String>,
 }
 
-#[allow(dead_code)]
 async fn register_user(
     auth: AuthSession,
+    State(state): State<AppState>,
     Form(params): Form<RegisterParams>,
 ) -> Result<impl IntoResponse, error::Error> {
-    auth.backend
-        .register(params.username, params.email, params.password)
-        .await
+    require_registration(&state)?;
+    let username = params.username.trim();
+    let email = params.email.trim();
+    if username.is_empty() || email.is_empty() || params.password.is_empty() {
+        return Ok(error_alert_response(
+            &state,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Username, email address and password are required".to_string(),
+        )
+        .into_response());
+    }
+    if let Err(msg) = state.config.registration.check_email(email) {
+        return Ok(
+            error_alert_response(&state, StatusCode::UNPROCESSABLE_ENTITY, msg).into_response(),
+        );
+    }
+    if User::load_by_username(username, &state.dbpool)
+        .await?
+        .is_some()
+    {
+        return Ok(error_alert_response(
+            &state,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("The username '{username}' is already taken"),
+        )
+        .into_response());
+    }
+    let user = auth
+        .backend
+        .register(username.to_string(), email.to_string(), params.password)
+        .await?;
+    mail::send_verification(&state, &user, false).await?;
+
+    // the new user logs in once they have verified their email address
+    let mut login = app_url("/auth/login");
+    if let Some(next) = params.next {
+        login.push('?');
+        login.push_str(&serde_urlencoded::to_string([("next", next)]).map_err(|e| anyhow!(e))?);
+    }
+    Ok([("HX-Redirect", login)].into_response())
 }
 
-#[allow(dead_code)]
 async fn show_register(
     TemplateKey(key): TemplateKey,
+    auth: AuthSession,
     State(state): State<AppState>,
+    Query(NextUrl { next }): Query<NextUrl>,
 ) -> Result<impl IntoResponse, error::Error> {
-    Ok(RenderHtml(key, state.tmpl.clone(), ()))
+    require_registration(&state)?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => auth.user, next => next),
+    ))
+}
+
+/// Visitors can only create their own accounts if the site enables registration
+fn require_registration(state: &AppState) -> Result<(), error::Error> {
+    if state.config.registration.enabled {
+        Ok(())
+    } else {
+        Err(error::Error::NotFound(
+            "Registration is not enabled".to_string(),
+        ))
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -97,6 +161,15 @@ async fn do_login(
 ) -> impl IntoResponse {
     match auth.authenticate(creds.clone()).await {
         Ok(authenticated) => match authenticated {
+            Some(user) if user.status != UserStatus::Verified => {
+                debug!("Rejecting login from unverified user '{}'", user.username);
+                error_alert_response(
+                    &state,
+                    StatusCode::FORBIDDEN,
+                    "Please verify your email address before logging in. Follow the link in the verification email that was sent to you.".to_string(),
+                )
+                .into_response()
+            }
             Some(user) => match auth.login(&user).await {
                 Ok(()) => (
                     [(
@@ -123,6 +196,26 @@ async fn do_login(
     }
 }
 
+/// Log in to the guest account of the demo mode without a password
+async fn demo_login(
+    mut auth: AuthSession,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let Some(config) = &state.config.demo else {
+        return Err(error::Error::NotFound(
+            "The demo is not enabled".to_string(),
+        ));
+    };
+    let user = User::load_by_username(&config.username, &state.dbpool)
+        .await?
+        .ok_or_else(|| error::Error::NotFound("The demo account does not exist".to_string()))?;
+    let user: SqliteUser = user.into();
+    auth.login(&user)
+        .await
+        .map_err(|e| anyhow!("Failed to log in to the demo account: {e}"))?;
+    Ok([("HX-Redirect", app_url("/"))])
+}
+
 async fn logout(mut auth: AuthSession) -> impl IntoResponse {
     match auth.logout().await {
         Ok(_) => Redirect::to("login").into_response(),
@@ -130,6 +223,69 @@ async fn logout(mut auth: AuthSession) -> impl IntoResponse {
     }
 }
 
+/// The administrator who is really logged in, regardless of whether they are impersonating another
+/// user at the moment
+fn require_admin(auth: &AuthSession) -> Result<&SqliteUser, error::Error> {
+    auth.user.as_ref().filter(|user| user.admin).ok_or_else(|| {
+        error::Error::Unauthorized("Only administrators can impersonate other users".to_string())
+    })
+}
+
+/// The number of audit trail entries shown on the impersonation page
+const AUDIT_ENTRIES: i64 = 50;
+
+async fn show_impersonation(
+    user: SqliteUser,
+    auth: AuthSession,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let admin = require_admin(&auth)?;
+    let users: Vec<User> = User::load_all(&state.dbpool)
+        .await?
+        .into_iter()
+        .filter(|u| u.id != admin.id)
+        .collect();
+    let entries = AuditEntry::load_recent(None, AUDIT_ENTRIES, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, users => users, audit_entries => entries),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct ImpersonationParams {
+    userid: i64,
+}
+
+async fn start_impersonation(
+    auth: AuthSession,
+    session: Session,
+    State(state): State<AppState>,
+    Form(params): Form<ImpersonationParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let admin = require_admin(&auth)?;
+    let target = User::load(params.userid, &state.dbpool)
+        .await
+        .map_err(|_| error::Error::NotFound(format!("User {} not found", params.userid)))?;
+    webauth::start_impersonation(admin, &target, &session, &state.dbpool).await?;
+    Ok(Redirect::to(&app_url("/")))
+}
+
+async fn stop_impersonation(
+    auth: AuthSession,
+    session: Session,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let admin = auth
+        .user
+        .as_ref()
+        .ok_or_else(|| error::Error::Unauthorized("No logged in user".to_string()))?;
+    webauth::stop_impersonation(admin, &session, &state.dbpool).await?;
+    Ok(Redirect::to(&app_url("/auth/impersonate")))
+}
+
 #[derive(Serialize, PartialEq, Debug)]
 enum VerifyStatus {
     VerificationCodeExpired,
@@ -271,6 +427,12 @@ mod test {
         let yesterday = format_sqlite_datetime(&yesterday).expect("unable to format timestamp");
         let now = OffsetDateTime::now_utc();
         let now = format_sqlite_datetime(&now).expect("unable to format timestamp");
+        sqlx::query("UPDATE sc_users SET userstatus=? WHERE userid=?")
+            .bind(UserStatus::Unverified)
+            .bind(USERID1)
+            .execute(&pool)
+            .await
+            .expect("Failed to reset user status");
         sqlx::query!(
             r#"INSERT INTO sc_user_verification
                 (uvid, userid, uvkey, uvrequested, uvexpiration, uvconfirmed)
@@ -0,0 +1,24 @@
+//! A page that scores the data quality of the user's collection and links to the objects that need
+//! to be fixed
+use crate::{auth::SqliteUser, error::Error, state::AppState, TemplateKey};
+use axum::{extract::State, response::IntoResponse};
+use axum_template::RenderHtml;
+use libseed::dataquality::{QualityReport, INACTIVE_DAYS};
+use minijinja::context;
+
+pub async fn show_quality(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    let today = user.time_zone().now().date();
+    let report = QualityReport::load(user.id, today, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 score => report.score(),
+                 report => report,
+                 inactive_days => INACTIVE_DAYS),
+    ))
+}
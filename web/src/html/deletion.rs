@@ -0,0 +1,53 @@
+//! Helpers for deleting several objects that were selected in a list at once
+use libseed::deletion::DeleteResult;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// The uuids of the objects that were selected with the checkboxes of the given name, in the
+/// order that they were selected in. Invalid values are ignored.
+pub fn selected_uuids(params: &[(String, String)], name: &str) -> Vec<Uuid> {
+    let mut uuids = Vec::new();
+    for (key, value) in params {
+        if key != name {
+            continue;
+        }
+        if let Ok(uuid) = Uuid::parse_str(value) {
+            if !uuids.contains(&uuid) {
+                uuids.push(uuid);
+            }
+        }
+    }
+    uuids
+}
+
+/// An object that was selected for deletion, along with the outcome of deleting it
+#[derive(Serialize)]
+pub struct BulkItem<T: Serialize> {
+    pub uuid: Uuid,
+    /// the object, unless it could not be loaded
+    pub object: Option<T>,
+    pub deleted: bool,
+    /// why the object was not deleted
+    pub reason: Option<String>,
+}
+
+impl<T: Serialize> BulkItem<T> {
+    /// An object that is not deleted, e.g. because the user isn't allowed to delete it
+    pub fn skipped(uuid: Uuid, object: Option<T>, reason: impl Into<String>) -> Self {
+        Self {
+            uuid,
+            object,
+            deleted: false,
+            reason: Some(reason.into()),
+        }
+    }
+
+    pub fn from_result(uuid: Uuid, object: T, result: DeleteResult) -> Self {
+        Self {
+            uuid,
+            object: Some(object),
+            deleted: result.deleted,
+            reason: result.reason,
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! The user's quality grading scale for samples. The scale is edited on its own page, and each
+//! change returns the updated scale so that it can be swapped into the page.
+use crate::{auth::SqliteUser, error::Error, state::AppState, Message, MessageType, TemplateKey};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::{get, post, put},
+    Form, Router,
+};
+use axum_template::RenderHtml;
+use libseed::{empty_string_as_none, grade::QualityGrade};
+use minijinja::context;
+use serde::Deserialize;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/grades", get(show_grades).post(add_grade))
+        .route("/grades/default", post(add_default_scale))
+        .route("/grades/:id", put(update_grade).delete(delete_grade))
+}
+
+/// Load a grade of the given user's scale
+async fn load_grade(user: &SqliteUser, id: i64, state: &AppState) -> Result<QualityGrade, Error> {
+    let not_found = || Error::NotFound("That grade does not exist".to_string());
+    let grade = QualityGrade::load(id, &state.dbpool)
+        .await
+        .map_err(|_| not_found())?;
+    if grade.userid != user.id {
+        return Err(not_found());
+    }
+    Ok(grade)
+}
+
+/// Render the user's scale along with a message about the change that was made to it
+async fn render_scale(
+    user: SqliteUser,
+    key: String,
+    state: AppState,
+    message: Message,
+) -> Result<impl IntoResponse, Error> {
+    let grades = QualityGrade::load_scale(user.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 grades => grades,
+                 message => message),
+    ))
+}
+
+/// The message for the result of a change to the scale. Invalid values are shown to the user so
+/// that they can correct them.
+fn result_message(result: libseed::Result<()>, success: &str) -> Result<Message, Error> {
+    match result {
+        Ok(()) => Ok(Message {
+            r#type: MessageType::Success,
+            msg: success.to_string(),
+        }),
+        Err(libseed::Error::InvalidValue(msg)) | Err(libseed::Error::InvalidOperation(msg)) => {
+            Ok(Message {
+                r#type: MessageType::Error,
+                msg,
+            })
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn show_grades(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    let grades = QualityGrade::load_scale(user.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 grades => grades),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct GradeParams {
+    label: String,
+    rank: i64,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    criteria: Option<String>,
+}
+
+async fn add_grade(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Form(params): Form<GradeParams>,
+) -> Result<impl IntoResponse, Error> {
+    let mut grade = QualityGrade::new(user.id, params.label, params.rank, params.criteria);
+    let result = grade.insert(&state.dbpool).await;
+    let message = result_message(result, &format!("Added grade '{}'", grade.label))?;
+    render_scale(user, key, state, message).await
+}
+
+async fn add_default_scale(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    let result = QualityGrade::create_default_scale(user.id, &state.dbpool)
+        .await
+        .map(|_| ());
+    let message = result_message(result, "Added the default grading scale")?;
+    render_scale(user, key, state, message).await
+}
+
+async fn update_grade(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(params): Form<GradeParams>,
+) -> Result<impl IntoResponse, Error> {
+    let mut grade = load_grade(&user, id, &state).await?;
+    grade.label = params.label;
+    grade.rank = params.rank;
+    grade.criteria = params.criteria;
+    let result = grade.update(&state.dbpool).await;
+    let message = result_message(result, &format!("Updated grade '{}'", grade.label))?;
+    render_scale(user, key, state, message).await
+}
+
+async fn delete_grade(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, Error> {
+    let grade = load_grade(&user, id, &state).await?;
+    grade.delete(&state.dbpool).await?;
+    let message = result_message(Ok(()), &format!("Removed grade '{}'", grade.label))?;
+    render_scale(user, key, state, message).await
+}
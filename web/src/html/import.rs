@@ -0,0 +1,417 @@
+//! Importing samples from a CSV file. The file is uploaded first, then the user maps its columns to
+//! sample fields while previewing how the rows would be imported, and finally the import runs in
+//! the background while its progress is shown. Large files are uploaded in chunks by the upload
+//! page: it starts an upload with `POST /sample/import/new`, sends each chunk of at most
+//! [CHUNK_SIZE] bytes to `PUT /sample/import/:id/chunk/:n` and completes the upload with
+//! `POST /sample/import/:id/finish`. Without javascript, the file is uploaded in a single request
+//! and stored in chunks while it is received.
+use super::error_alert_response;
+use crate::{
+    app_url, auth::SqliteUser, error::Error, jobs, state::AppState, Message, MessageType,
+    TemplateKey,
+};
+use anyhow::anyhow;
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, Multipart, Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post, put},
+    Form, Router,
+};
+use axum_template::RenderHtml;
+use libseed::import::{
+    ColumnMapping, ImportStatus, SampleField, SampleImport, CHUNK_SIZE, MAX_UPLOAD_SIZE,
+};
+use minijinja::context;
+use serde::Deserialize;
+use std::{collections::HashMap, str::FromStr};
+
+/// The number of rows that are shown in the preview of an import
+const PREVIEW_ROWS: usize = 20;
+
+/// The number of example values that are shown for each column when mapping the columns
+const EXAMPLE_VALUES: usize = 3;
+
+/// The number of rows that are searched for example values of the columns
+const EXAMPLE_ROWS: usize = 100;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/import",
+            get(show_imports)
+                .post(upload_file)
+                // without javascript, the whole file is uploaded in a single request
+                .layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route("/import/new", post(start_upload))
+        .route("/import/:id", get(show_import).post(start_import))
+        .route("/import/:id/chunk/:index", put(upload_chunk))
+        .route("/import/:id/finish", post(finish_upload))
+        .route("/import/:id/preview", get(preview_import))
+        .route("/import/:id/progress", get(show_progress))
+        .route("/import/:id/errors", get(download_errors))
+}
+
+/// Load an import of the given user
+async fn load_import(user: &SqliteUser, id: i64, state: &AppState) -> Result<SampleImport, Error> {
+    let not_found = || Error::NotFound("That import does not exist".to_string());
+    let import = SampleImport::load(id, &state.dbpool)
+        .await
+        .map_err(|_| not_found())?;
+    if import.userid != user.id {
+        return Err(not_found());
+    }
+    Ok(import)
+}
+
+/// Build the column mapping from the submitted form, which has a `column-N` field with the name of
+/// a sample field for each column that should be imported
+async fn mapping_from_params(
+    import: &SampleImport,
+    params: &HashMap<String, String>,
+    state: &AppState,
+) -> Result<ColumnMapping, Error> {
+    let mut mapping = ColumnMapping::default();
+    for (i, header) in import.headers(&state.dbpool).await?.into_iter().enumerate() {
+        match params.get(&format!("column-{i}")).map(String::as_str) {
+            None | Some("") => (),
+            Some(value) => {
+                let field = SampleField::from_str(value)
+                    .map_err(|_| anyhow!("Invalid field '{value}' for column '{header}'"))?;
+                mapping.columns.insert(header, field);
+            }
+        }
+    }
+    Ok(mapping)
+}
+
+async fn show_imports(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    let imports = SampleImport::load_all_user(user.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, imports => imports, chunk_size => CHUNK_SIZE),
+    ))
+}
+
+/// Show the problems with an upload that the user can fix in an alert
+fn upload_problem(state: &AppState, error: libseed::Error) -> Result<Response, Error> {
+    match error {
+        libseed::Error::InvalidValue(message) => {
+            Ok(
+                error_alert_response(state, StatusCode::UNPROCESSABLE_ENTITY, message)
+                    .into_response(),
+            )
+        }
+        libseed::Error::InvalidOperation(message) => {
+            Ok(error_alert_response(state, StatusCode::CONFLICT, message).into_response())
+        }
+        e => Err(e.into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct NewUpload {
+    filename: String,
+}
+
+/// Start uploading a file in chunks. The location of the new import is returned in the
+/// `Location` header.
+async fn start_upload(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Form(params): Form<NewUpload>,
+) -> Result<impl IntoResponse, Error> {
+    let import = SampleImport::create(user.id, params.filename, &state.dbpool).await?;
+    Ok((
+        StatusCode::CREATED,
+        [(
+            header::LOCATION,
+            app_url(&format!("/sample/import/{}", import.id)),
+        )],
+    ))
+}
+
+/// Store a single chunk of a file that is being uploaded
+async fn upload_chunk(
+    user: SqliteUser,
+    Path((id, index)): Path<(i64, i64)>,
+    State(state): State<AppState>,
+    body: Bytes,
+) -> Result<Response, Error> {
+    let import = load_import(&user, id, &state).await?;
+    match import.add_chunk(index, &body, &state.dbpool).await {
+        Ok(()) => Ok(StatusCode::NO_CONTENT.into_response()),
+        Err(e) => upload_problem(&state, e),
+    }
+}
+
+/// Complete a chunked upload. If the file is not a valid CSV file, the import is removed again.
+async fn finish_upload(
+    user: SqliteUser,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<Response, Error> {
+    let mut import = load_import(&user, id, &state).await?;
+    match import.finish_upload(&state.dbpool).await {
+        Ok(()) => Ok(StatusCode::NO_CONTENT.into_response()),
+        Err(e) => {
+            if import.status == ImportStatus::Uploading {
+                import.delete(&state.dbpool).await?;
+            }
+            upload_problem(&state, e)
+        }
+    }
+}
+
+/// Store the uploaded file and continue with mapping its columns
+async fn upload_file(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, Error> {
+    let read_error = |e| anyhow!("Failed to read the upload: {e}");
+    let mut upload = None;
+    while let Some(mut field) = multipart.next_field().await.map_err(read_error)? {
+        if field.name() != Some("file") || upload.is_some() {
+            continue;
+        }
+        let filename = field.file_name().unwrap_or("samples.csv").to_string();
+        let import = SampleImport::create(user.id, filename, &state.dbpool).await?;
+        // store the file in chunks while it is received instead of reading all of it first
+        let mut buffer = Vec::new();
+        let mut index = 0;
+        while let Some(bytes) = field.chunk().await.map_err(read_error)? {
+            buffer.extend_from_slice(&bytes);
+            while buffer.len() >= CHUNK_SIZE {
+                let rest = buffer.split_off(CHUNK_SIZE);
+                import.add_chunk(index, &buffer, &state.dbpool).await?;
+                buffer = rest;
+                index += 1;
+            }
+        }
+        if !buffer.is_empty() {
+            import.add_chunk(index, &buffer, &state.dbpool).await?;
+            index += 1;
+        }
+        upload = Some((import, index));
+    }
+    let res = match upload {
+        Some((import, 0)) => {
+            import.delete(&state.dbpool).await?;
+            Err("The file is empty".to_string())
+        }
+        Some((mut import, _)) => match import.finish_upload(&state.dbpool).await {
+            Ok(()) => Ok(import),
+            Err(libseed::Error::InvalidValue(message)) => {
+                import.delete(&state.dbpool).await?;
+                Err(message)
+            }
+            Err(e) => return Err(e.into()),
+        },
+        None => Err("No file was uploaded".to_string()),
+    };
+    match res {
+        Ok(import) => {
+            Ok(Redirect::to(&app_url(&format!("/sample/import/{}", import.id))).into_response())
+        }
+        Err(message) => {
+            let imports = SampleImport::load_all_user(user.id, &state.dbpool).await?;
+            Ok((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                RenderHtml(
+                    "sample_import.html",
+                    state.tmpl.clone(),
+                    context!(user => user,
+                    imports => imports,
+                    chunk_size => CHUNK_SIZE,
+                    message => Message {
+                        r#type: MessageType::Error,
+                        msg: message,
+                    }),
+                ),
+            )
+                .into_response())
+        }
+    }
+}
+
+/// Map the columns of an import that hasn't been started yet, or show the progress of an import
+async fn show_import(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    let import = load_import(&user, id, &state).await?;
+    if import.status != ImportStatus::Uploaded {
+        return Ok(RenderHtml(
+            key,
+            state.tmpl.clone(),
+            context!(user => user, import => import),
+        ));
+    }
+    let mut reader = import.reader(&state.dbpool).await?;
+    let mut rows = Vec::new();
+    while rows.len() < EXAMPLE_ROWS {
+        match reader.next_row().await? {
+            Some((_, values)) => rows.push(values),
+            None => break,
+        }
+    }
+    let headers = reader.headers;
+    let columns: Vec<_> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            let examples: Vec<&str> = rows
+                .iter()
+                .filter_map(|values| values.get(i).map(String::as_str))
+                .filter(|v| !v.is_empty())
+                .take(EXAMPLE_VALUES)
+                .collect();
+            context!(name => header,
+                     guess => SampleField::guess(header),
+                     examples => examples)
+        })
+        .collect();
+    let fields: Vec<_> = SampleField::ALL
+        .iter()
+        .map(|f| context!(value => f, label => f.to_string()))
+        .collect();
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 import => import,
+                 columns => columns,
+                 fields => fields),
+    ))
+}
+
+/// Show how the first rows of the file would be imported with the mapping from the form
+async fn preview_import(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<impl IntoResponse, Error> {
+    let import = load_import(&user, id, &state).await?;
+    let mapping = mapping_from_params(&import, &params, &state).await?;
+    let (preview, problem) = match mapping.validate() {
+        Ok(()) => (
+            import
+                .preview(&mapping, PREVIEW_ROWS, &state.dbpool)
+                .await?,
+            None,
+        ),
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(import => import,
+                 preview => preview,
+                 problem => problem),
+    ))
+}
+
+/// Start importing the file in the background with the mapping from the form
+async fn start_import(
+    user: SqliteUser,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Form(params): Form<HashMap<String, String>>,
+) -> Result<impl IntoResponse, Error> {
+    let mut import = load_import(&user, id, &state).await?;
+    let mapping = mapping_from_params(&import, &params, &state).await?;
+    if let Err(e) = mapping.validate() {
+        return Ok(
+            error_alert_response(&state, StatusCode::UNPROCESSABLE_ENTITY, e.to_string())
+                .into_response(),
+        );
+    }
+    let problem = match import.status {
+        ImportStatus::Uploaded => None,
+        ImportStatus::Uploading => Some("The upload of the file is not finished yet"),
+        _ => Some("The import has already been started"),
+    };
+    if let Some(problem) = problem {
+        return Ok(
+            error_alert_response(&state, StatusCode::CONFLICT, problem.to_string()).into_response(),
+        );
+    }
+    import.start(&state.dbpool).await?;
+    jobs::run_import(state.clone(), import, mapping);
+    Ok([("HX-Redirect", app_url(&format!("/sample/import/{id}")))].into_response())
+}
+
+async fn show_progress(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    let import = load_import(&user, id, &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(import => import),
+    ))
+}
+
+/// The rows that could not be imported as a CSV file, with the reason and the original values of
+/// each row, so that they can be fixed and imported again
+async fn download_errors(
+    user: SqliteUser,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    let import = load_import(&user, id, &state).await?;
+    let errors = import.errors(&state.dbpool).await?;
+    let mut reader = import.reader(&state.dbpool).await?;
+    let mut writer = csv::Writer::from_writer(vec![]);
+    let mut header = vec!["Line".to_string(), "Error".to_string()];
+    header.extend(reader.headers.iter().cloned());
+    writer
+        .write_record(&header)
+        .map_err(|e| anyhow!("Failed to write the error report: {e}"))?;
+    // the errors are ordered by line, so the file only needs to be read once
+    for error in errors {
+        let mut values = Vec::new();
+        while let Some((line, row)) = reader.next_row().await? {
+            if line == error.line {
+                values = row;
+                break;
+            }
+        }
+        let mut record = vec![error.line.to_string(), error.message];
+        record.extend(values);
+        writer
+            .write_record(&record)
+            .map_err(|e| anyhow!("Failed to write the error report: {e}"))?;
+    }
+    let data = writer.into_inner().map_err(|e| anyhow!("{e}"))?;
+    let stem = import
+        .filename
+        .strip_suffix(".csv")
+        .unwrap_or(&import.filename)
+        .replace('"', "");
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{stem}-errors.csv\""),
+            ),
+        ],
+        data,
+    ))
+}
@@ -1,4 +1,9 @@
-use crate::{auth::AuthSession, error, state::AppState, Message, MessageType, TemplateKey};
+use crate::{
+    auth::{AuthSession, SqliteUser},
+    error,
+    state::AppState,
+    Message, MessageType, TemplateKey,
+};
 use axum::{
     extract::{OriginalUri, Request, State},
     http::StatusCode,
@@ -8,17 +13,31 @@ use axum::{
     Router,
 };
 use axum_template::RenderHtml;
+use libseed::{reminder::Reminder, stock::LowStock};
 use minijinja::context;
 
+mod admin;
 mod allocation;
 mod auth;
+mod dataquality;
+mod deletion;
+mod grade;
+mod import;
 mod info;
+mod notes;
+mod notetemplate;
+mod org;
+mod palette;
 mod project;
+mod reminder;
+mod report;
 mod sample;
 mod source;
+mod stock;
 mod taxonomy;
 #[cfg(test)]
-mod tests;
+pub(crate) mod tests;
+mod trip;
 mod user;
 
 pub fn error_alert_response(
@@ -65,12 +84,21 @@ async fn login_required(
 
 pub fn router(state: AppState) -> Router<AppState> {
     Router::new()
+        .nest("/admin/", admin::router())
         .nest("/info/", info::router())
+        .nest("/org/", org::router())
         .nest("/project/", project::router())
+        .nest("/reminder/", reminder::router())
+        .nest("/report/", report::router())
         .nest("/sample/", sample::router())
         .nest("/source/", source::router())
         .nest("/taxonomy/", taxonomy::router())
+        .nest("/trip/", trip::router())
         .nest("/user/", user::router())
+        .route("/notes", get(notes::list_notes))
+        .route("/dataquality", get(dataquality::show_quality))
+        .route("/stock", get(stock::show_low_stock))
+        .route("/palette", get(palette::palette))
         /* Anything above here is only available to logged-in users */
         .route_layer(middleware::from_fn_with_state(state, login_required))
         .route("/", get(root))
@@ -78,14 +106,27 @@ pub fn router(state: AppState) -> Router<AppState> {
 }
 
 async fn root(
-    auth: AuthSession,
+    user: Option<SqliteUser>,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, error::Error> {
     tracing::info!("root");
+    let (mut due, mut upcoming) = (Vec::new(), Vec::new());
+    let mut low_stock = 0;
+    if let Some(user) = &user {
+        let today = user.time_zone().now().date();
+        (due, upcoming) = Reminder::load_active(user.id, &state.dbpool)
+            .await?
+            .into_iter()
+            .partition(|r| r.is_due(today));
+        low_stock = LowStock::load_user(user.id, &state.dbpool).await?.len();
+    }
     Ok(RenderHtml(
         key,
         state.tmpl.clone(),
-        context!(user => auth.user),
+        context!(user => user,
+                 low_stock => low_stock,
+                 due_reminders => due,
+                 upcoming_reminders => upcoming),
     ))
 }
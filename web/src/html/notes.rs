@@ -0,0 +1,99 @@
+//! A page that lists the notes of all of the user's samples and projects together, filtered by
+//! type, date, text, project or taxon.
+use crate::{auth::SqliteUser, error::Error, state::AppState, TemplateKey};
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    response::IntoResponse,
+};
+use axum_template::RenderHtml;
+use libseed::{
+    empty_string_as_none,
+    filter::{CompoundFilter, Op},
+    notes::{self, NoteSource},
+    parse_date,
+    project::NoteType,
+    stats,
+};
+use minijinja::context;
+use serde::Deserialize;
+use strum::IntoEnumIterator;
+use tracing::debug;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct NoteListParams {
+    /// text to search for in the summary and details of the notes
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    filter: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    source: Option<NoteSource>,
+    #[serde(default, deserialize_with = "empty_string_as_none", rename = "type")]
+    kind: Option<NoteType>,
+    /// the first date of the range of notes to show, as `YYYY-MM-DD`
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    from: Option<String>,
+    /// the last date of the range of notes to show, as `YYYY-MM-DD`
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    to: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    project: Option<i64>,
+    /// only show notes about samples of this taxon or of any taxon below it
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    taxon: Option<i64>,
+}
+
+pub async fn list_notes(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    query: Option<Query<NoteListParams>>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, Error> {
+    debug!("query params: {:?}", query);
+    let params = query.map(|q| q.0).unwrap_or_default();
+    let mut fbuilder = CompoundFilter::builder(Op::And);
+    if let Some(text) = params.filter.as_ref() {
+        fbuilder = fbuilder.push(notes::Filter::TextLike(text.clone()));
+    }
+    if let Some(source) = params.source {
+        fbuilder = fbuilder.push(notes::Filter::Source(source));
+    }
+    if let Some(kind) = params.kind {
+        fbuilder = fbuilder.push(notes::Filter::Type(kind));
+    }
+    if let Some(from) = params.from.as_ref() {
+        fbuilder = fbuilder.push(notes::Filter::DateFrom(parse_date(from)?));
+    }
+    if let Some(to) = params.to.as_ref() {
+        fbuilder = fbuilder.push(notes::Filter::DateTo(parse_date(to)?));
+    }
+    if let Some(project) = params.project {
+        fbuilder = fbuilder.push(notes::Filter::ProjectId(project));
+    }
+    if let Some(taxon) = params.taxon {
+        fbuilder = fbuilder.push(notes::Filter::AncestorTsn(taxon));
+    }
+    let notes = user
+        .scope()
+        .notes(Some(fbuilder.build()), None, &state.dbpool)
+        .await?;
+    let projects = user.scope().projects(None, &state.dbpool).await?;
+    let families = stats::samples_per_family(user.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 notes => notes,
+                 projects => projects,
+                 families => families,
+                 note_types => NoteType::iter().collect::<Vec<_>>(),
+                 filter => params.filter,
+                 source => params.source,
+                 kind => params.kind,
+                 from => params.from,
+                 to => params.to,
+                 project => params.project,
+                 taxon => params.taxon,
+                 filteronly => headers.get("HX-Request").is_some()),
+    ))
+}
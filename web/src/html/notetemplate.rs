@@ -0,0 +1,161 @@
+//! The note templates of a project. The templates are edited on their own tab of the project, and
+//! each change returns the updated list so that it can be swapped into the page.
+use super::project::load_project;
+use crate::{auth::SqliteUser, error::Error, state::AppState, Message, MessageType, TemplateKey};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::{get, put},
+    Form, Router,
+};
+use axum_template::RenderHtml;
+use libseed::{
+    empty_string_as_none,
+    organization::Permission,
+    project::{template::NoteTemplate, NoteType, Project},
+};
+use minijinja::context;
+use serde::Deserialize;
+use strum::IntoEnumIterator;
+use uuid::Uuid;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/:id/templates", get(show_templates).post(add_template))
+        .route(
+            "/:id/templates/:template",
+            put(update_template).delete(delete_template),
+        )
+}
+
+/// Load a template of the given project
+async fn load_template(
+    project: &Project,
+    id: i64,
+    state: &AppState,
+) -> Result<NoteTemplate, Error> {
+    let not_found = || Error::NotFound("That template does not exist".to_string());
+    let template = NoteTemplate::load(id, &state.dbpool)
+        .await
+        .map_err(|_| not_found())?;
+    if template.projectid != project.id {
+        return Err(not_found());
+    }
+    Ok(template)
+}
+
+/// Render the templates of the project along with a message about the change that was made
+async fn render_templates(
+    user: SqliteUser,
+    key: String,
+    state: AppState,
+    project: Project,
+    message: Message,
+) -> Result<impl IntoResponse, Error> {
+    let templates = NoteTemplate::load_project(project.id, &state.dbpool).await?;
+    let note_types: Vec<NoteType> = NoteType::iter().collect();
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 project => project,
+                 templates => templates,
+                 note_types => note_types,
+                 message => message),
+    ))
+}
+
+/// The message for the result of a change to the templates. Invalid values are shown to the user
+/// so that they can correct them.
+fn result_message(result: libseed::Result<()>, success: &str) -> Result<Message, Error> {
+    match result {
+        Ok(()) => Ok(Message {
+            r#type: MessageType::Success,
+            msg: success.to_string(),
+        }),
+        Err(libseed::Error::InvalidValue(msg)) => Ok(Message {
+            r#type: MessageType::Error,
+            msg,
+        }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn show_templates(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+) -> Result<impl IntoResponse, Error> {
+    let project = load_project(&user, uuid, Permission::View, &state).await?;
+    let templates = NoteTemplate::load_project(project.id, &state.dbpool).await?;
+    let note_types: Vec<NoteType> = NoteType::iter().collect();
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 project => project,
+                 templates => templates,
+                 note_types => note_types),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct TemplateParams {
+    name: String,
+    notetype: NoteType,
+    summary: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    details: Option<String>,
+}
+
+async fn add_template(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    Form(params): Form<TemplateParams>,
+) -> Result<impl IntoResponse, Error> {
+    let project = load_project(&user, uuid, Permission::Edit, &state).await?;
+    let mut template = NoteTemplate::new(
+        project.id,
+        params.name,
+        params.notetype,
+        params.summary,
+        params.details,
+    );
+    let result = template.insert(&state.dbpool).await;
+    let message = result_message(result, &format!("Added template '{}'", template.name))?;
+    render_templates(user, key, state, project, message).await
+}
+
+async fn update_template(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path((uuid, id)): Path<(Uuid, i64)>,
+    Form(params): Form<TemplateParams>,
+) -> Result<impl IntoResponse, Error> {
+    let project = load_project(&user, uuid, Permission::Edit, &state).await?;
+    let mut template = load_template(&project, id, &state).await?;
+    template.name = params.name;
+    template.kind = params.notetype;
+    template.summary = params.summary;
+    template.details = params.details;
+    let result = template.update(&state.dbpool).await;
+    let message = result_message(result, &format!("Updated template '{}'", template.name))?;
+    render_templates(user, key, state, project, message).await
+}
+
+async fn delete_template(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path((uuid, id)): Path<(Uuid, i64)>,
+) -> Result<impl IntoResponse, Error> {
+    let project = load_project(&user, uuid, Permission::Edit, &state).await?;
+    let template = load_template(&project, id, &state).await?;
+    template.delete(&state.dbpool).await?;
+    let message = result_message(Ok(()), &format!("Removed template '{}'", template.name))?;
+    render_templates(user, key, state, project, message).await
+}
@@ -0,0 +1,254 @@
+use super::error_alert_response;
+use crate::{
+    app_url,
+    auth::SqliteUser,
+    error::{self, Error},
+    state::AppState,
+    Message, MessageType, TemplateKey,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post, put},
+    Form, Router,
+};
+use axum_template::RenderHtml;
+use libseed::{
+    empty_string_as_none,
+    loadable::Loadable,
+    organization::{OrgRole, Organization},
+    user::User,
+};
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use tracing::{debug, warn};
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/list", get(list_orgs))
+        .route("/new", get(show_new_org).post(insert_org))
+        .route("/:id", get(show_org).put(update_org).delete(delete_org))
+        .route("/:id/members", post(add_member))
+        .route(
+            "/:id/members/:userid",
+            put(update_member).delete(remove_member),
+        )
+}
+
+/// Load the organization with the given id, making sure that the user is a member with at least
+/// the given role
+async fn load_org(
+    user: &SqliteUser,
+    id: i64,
+    minrole: OrgRole,
+    state: &AppState,
+) -> Result<(Organization, OrgRole), Error> {
+    let role = Organization::role(id, user.id, &state.dbpool)
+        .await?
+        .ok_or_else(|| Error::NotFound("That organization does not exist".to_string()))?;
+    if role < minrole {
+        return Err(Error::Unauthorized(
+            "No permission to manage this organization".to_string(),
+        ));
+    }
+    Ok((Organization::load(id, &state.dbpool).await?, role))
+}
+
+async fn list_orgs(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let orgs = Organization::load_all_user(user.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 orgs => orgs),
+    )
+    .into_response())
+}
+
+async fn show_new_org(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    Ok(RenderHtml(key, state.tmpl.clone(), context!(user => user)).into_response())
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct OrgParams {
+    name: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    description: Option<String>,
+}
+
+async fn insert_org(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Form(params): Form<OrgParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut org = Organization::new(params.name.clone(), params.description.clone());
+    match org.insert(user.id, &state.dbpool).await {
+        Err(e) => {
+            warn!("Failed to insert organization: {e:?}");
+            Ok(error_alert_response(
+                &state,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Failed to save organization: {e}"),
+            )
+            .into_response())
+        }
+        Ok(()) => {
+            debug!(org.id, "successfully inserted organization");
+            Ok((
+                [("HX-Redirect", app_url(&format!("/org/{}", org.id)))],
+                RenderHtml(
+                    "_ALERT.html",
+                    state.tmpl.clone(),
+                    context!(message => Message {
+                        r#type: MessageType::Success,
+                        msg: format!("Added new organization {}", org.name),
+                    }),
+                ),
+            )
+                .into_response())
+        }
+    }
+}
+
+async fn show_org(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, error::Error> {
+    let (org, role) = load_org(&user, id, OrgRole::Viewer, &state).await?;
+    let members = org.members(&state.dbpool).await?;
+    let roles: Vec<OrgRole> = OrgRole::iter().collect();
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 org => org,
+                 role => role,
+                 roles => roles,
+                 members => members),
+    )
+    .into_response())
+}
+
+async fn update_org(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(params): Form<OrgParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let (mut org, _) = load_org(&user, id, OrgRole::Admin, &state).await?;
+    org.name = params.name;
+    org.description = params.description;
+    match org.update(&state.dbpool).await {
+        Err(e) => Ok(error_alert_response(
+            &state,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Failed to update organization: {e}"),
+        )
+        .into_response()),
+        Ok(_) => Ok([("HX-Redirect", app_url(&format!("/org/{id}")))].into_response()),
+    }
+}
+
+async fn delete_org(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, error::Error> {
+    let (mut org, _) = load_org(&user, id, OrgRole::Admin, &state).await?;
+    // objects that belonged to the organization are returned to their owners by the database
+    org.delete(&state.dbpool).await?;
+    Ok([("HX-Redirect", app_url("/org/list"))])
+}
+
+#[derive(Debug, Deserialize)]
+struct MemberParams {
+    username: String,
+    role: OrgRole,
+}
+
+async fn add_member(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(params): Form<MemberParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let (org, _) = load_org(&user, id, OrgRole::Admin, &state).await?;
+    let Some(member) = User::load_by_username(params.username.trim(), &state.dbpool).await? else {
+        return Ok(error_alert_response(
+            &state,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("No user named '{}'", params.username.trim()),
+        )
+        .into_response());
+    };
+    match org.set_member(member.id, params.role, &state.dbpool).await {
+        Err(e) => Ok(error_alert_response(
+            &state,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Failed to add member: {e}"),
+        )
+        .into_response()),
+        Ok(_) => Ok([("HX-Redirect", app_url(&format!("/org/{id}")))].into_response()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RoleParams {
+    role: OrgRole,
+}
+
+async fn update_member(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path((id, userid)): Path<(i64, i64)>,
+    Form(params): Form<RoleParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let (org, _) = load_org(&user, id, OrgRole::Admin, &state).await?;
+    match org.set_member(userid, params.role, &state.dbpool).await {
+        Err(e) => Ok(error_alert_response(
+            &state,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Failed to change role: {e}"),
+        )
+        .into_response()),
+        Ok(_) => Ok([("HX-Redirect", app_url(&format!("/org/{id}")))].into_response()),
+    }
+}
+
+async fn remove_member(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path((id, userid)): Path<(i64, i64)>,
+) -> Result<impl IntoResponse, error::Error> {
+    // members may always leave an organization, but only admins can remove others
+    let minrole = match userid == user.id {
+        true => OrgRole::Viewer,
+        false => OrgRole::Admin,
+    };
+    let (org, _) = load_org(&user, id, minrole, &state).await?;
+    if let Err(e) = org.remove_member(userid, &state.dbpool).await {
+        return Ok(error_alert_response(
+            &state,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Failed to remove member: {e}"),
+        )
+        .into_response());
+    }
+    let url = match userid == user.id {
+        true => app_url("/org/list"),
+        false => app_url(&format!("/org/{id}")),
+    };
+    Ok([("HX-Redirect", url)].into_response())
+}
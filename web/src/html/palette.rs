@@ -0,0 +1,123 @@
+//! The command palette: a keyboard-driven popup that can jump to any object or perform a common
+//! action. It is opened with Ctrl+K from any page.
+use crate::{auth::SqliteUser, error::Error, format_id_number, state::AppState, TemplateKey};
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+};
+use axum_template::RenderHtml;
+use libseed::{
+    filter::{SortOrder, SortSpec},
+    project::allocation,
+    search::{self, ResultKind},
+};
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+
+/// The maximum number of search results shown in the palette
+const MAX_RESULTS: usize = 15;
+
+#[derive(Deserialize)]
+pub struct PaletteParams {
+    #[serde(default)]
+    q: String,
+}
+
+#[derive(Serialize)]
+struct PaletteAction {
+    label: String,
+    /// the path of the page within the app
+    url: String,
+    icon: &'static str,
+}
+
+#[derive(Serialize)]
+struct PaletteItem {
+    kind: ResultKind,
+    title: String,
+    subtitle: Option<String>,
+    url: String,
+}
+
+async fn quick_actions(user: &SqliteUser, state: &AppState) -> Result<Vec<PaletteAction>, Error> {
+    let mut actions = vec![
+        PaletteAction {
+            label: "New sample".to_string(),
+            url: "/sample/new".to_string(),
+            icon: "plus-square",
+        },
+        PaletteAction {
+            label: "New project".to_string(),
+            url: "/project/new".to_string(),
+            icon: "plus-square",
+        },
+        PaletteAction {
+            label: "New source".to_string(),
+            url: "/source/new".to_string(),
+            icon: "plus-square",
+        },
+    ];
+    // offer to continue the project journal of whichever sample was worked on most recently
+    let recent = user
+        .scope()
+        .allocations(
+            None,
+            Some(SortSpec::new(allocation::SortField::Activity, SortOrder::Descending).into()),
+            &state.dbpool,
+        )
+        .await?;
+    if let Some(alloc) = recent.first() {
+        actions.push(PaletteAction {
+            label: format!(
+                "New note for {} in {}",
+                format_id_number(alloc.sample.id, Some("S"), None),
+                alloc.project.name
+            ),
+            url: format!(
+                "/project/{}/sample/{}/note/new",
+                alloc.project.uuid, alloc.uuid
+            ),
+            icon: "journal-plus",
+        });
+    }
+    Ok(actions)
+}
+
+pub async fn palette(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Query(params): Query<PaletteParams>,
+) -> Result<impl IntoResponse, Error> {
+    let query = params.q.trim();
+    let actions: Vec<PaletteAction> = quick_actions(&user, &state)
+        .await?
+        .into_iter()
+        .filter(|a| {
+            query.is_empty()
+                || query
+                    .split_whitespace()
+                    .all(|w| a.label.to_lowercase().contains(&w.to_lowercase()))
+        })
+        .collect();
+    let results: Vec<PaletteItem> = search::search(user.id, query, MAX_RESULTS, &state.dbpool)
+        .await?
+        .into_iter()
+        .map(|r| PaletteItem {
+            url: match (r.kind, r.uuid) {
+                (ResultKind::Sample, Some(uuid)) => format!("/sample/{uuid}"),
+                (ResultKind::Project, Some(uuid)) => format!("/project/{uuid}"),
+                (ResultKind::Source, Some(uuid)) => format!("/source/{uuid}"),
+                _ => format!("/taxonomy/{}", r.id),
+            },
+            kind: r.kind,
+            title: r.title,
+            subtitle: r.subtitle,
+        })
+        .collect();
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(actions => actions, results => results, query => query),
+    ))
+}
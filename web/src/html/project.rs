@@ -1,36 +1,48 @@
 use crate::{
     app_url,
     auth::SqliteUser,
+    bundle,
     error::{self, Error},
-    format_id_number,
+    format_id_number, jobs,
     state::AppState,
     Message, MessageType, TemplateKey,
 };
 use anyhow::anyhow;
 use axum::{
     extract::{rejection::QueryRejection, Path, Query, State},
-    http::{HeaderMap, StatusCode},
-    response::IntoResponse,
-    routing::get,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect},
+    routing::{get, post},
     Form, Router,
 };
 use axum_template::RenderHtml;
 use libseed::{
+    bulk::BulkResult,
+    dataquality::Issue,
     empty_string_as_none,
-    filter::{Cmp, CompoundFilter, Op, SortOrder, SortSpec},
-    loadable::{ExternalRef, Loadable},
+    filter::{Cmp, CompoundFilter, Op, SortOrder, SortSpec, SortSpecs},
+    loadable::Loadable,
+    organization::Permission,
     project::{
         self,
         allocation::{self, SortField},
+        bundle::{Bundle, BundleStatus},
+        planting::Establishment,
+        propagation::{self, PlanItem, PlanSortField},
+        timeline::{Timeline, TimelineEvent, TimelineRow},
         Project,
     },
+    reminder::{Reminder, ReminderTarget},
     sample::{self, Sample},
+    stats,
+    taxonomy::names::DisplayName,
 };
 use minijinja::context;
 use serde::{Deserialize, Serialize};
-use sqlx::{sqlite::SqliteQueryResult, Row};
+use sqlx::sqlite::SqliteQueryResult;
 use std::sync::Arc;
 use tracing::{debug, trace, warn};
+use uuid::Uuid;
 
 use super::error_alert_response;
 
@@ -43,8 +55,21 @@ pub fn router() -> Router<AppState> {
             get(show_project).put(modify_project).delete(delete_project),
         )
         .route("/:id/edit", get(show_project))
+        .route(
+            "/:id/delete",
+            get(confirm_delete_project).post(delete_project_confirmed),
+        )
+        .route("/:id/print", get(print_project))
+        .route("/:id/propagation", get(show_propagation_plan))
+        .route("/:id/propagation/csv", get(export_propagation_plan))
+        .route("/:id/timeline", get(show_timeline))
+        .route("/:id/fieldsheets", get(download_field_sheets))
+        .route("/:id/bundle", post(request_bundle))
+        .route("/:id/bundle/:bundle", get(show_bundle))
+        .route("/:id/bundle/:bundle/download", get(download_bundle))
         .route("/:id/add", get(show_add_sample).post(add_sample))
         .nest("/:id/sample/", super::allocation::router())
+        .merge(super::notetemplate::router())
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -53,6 +78,20 @@ struct ProjectListParams {
     filter: Option<String>,
 }
 
+/// Load the project with the given uuid, making sure that the user has the given permission for it
+pub(crate) async fn load_project(
+    user: &SqliteUser,
+    uuid: Uuid,
+    permission: Permission,
+    state: &AppState,
+) -> Result<Project, Error> {
+    let project = Project::load_uuid(uuid, &state.dbpool)
+        .await
+        .map_err(|_| Error::NotFound("That project does not exist".to_string()))?;
+    user.require(&project, permission, &state.dbpool).await?;
+    Ok(project)
+}
+
 async fn list_projects(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
@@ -61,7 +100,6 @@ async fn list_projects(
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, error::Error> {
     trace!(?params, "Listing projects");
-    let mut fbuilder = CompoundFilter::builder(Op::And).push(project::Filter::User(user.id));
     let namefilter = params.and_then(|Query(p)| p.filter).map(|filterstring| {
         debug!(?filterstring, "Got project filter");
         CompoundFilter::builder(Op::Or)
@@ -72,10 +110,7 @@ async fn list_projects(
             ))
             .build()
     });
-    if let Some(namefilter) = namefilter {
-        fbuilder = fbuilder.push(namefilter);
-    }
-    let projects = Project::load_all(Some(fbuilder.build()), &state.dbpool).await?;
+    let projects = user.scope().projects(namefilter, &state.dbpool).await?;
     Ok(RenderHtml(
         key,
         state.tmpl.clone(),
@@ -91,7 +126,13 @@ async fn show_new_project(
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, error::Error> {
-    Ok(RenderHtml(key, state.tmpl.clone(), context!(user => user)).into_response())
+    let orgs = user.writable_orgs(&state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, orgs => orgs),
+    )
+    .into_response())
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -99,19 +140,25 @@ struct ProjectParams {
     name: String,
     #[serde(default, deserialize_with = "empty_string_as_none")]
     description: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    org: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    version: Option<i64>,
 }
 
 async fn do_insert(
     user: SqliteUser,
     params: &ProjectParams,
     state: &AppState,
-) -> Result<SqliteQueryResult, error::Error> {
+) -> Result<Project, error::Error> {
     let mut project = Project::new(
         params.name.clone(),
         params.description.as_ref().cloned(),
         user.id,
     );
-    project.insert(&state.dbpool).await.map_err(|e| e.into())
+    project.orgid = params.org;
+    project.insert(&state.dbpool).await?;
+    Ok(project)
 }
 
 async fn insert_project(
@@ -127,6 +174,7 @@ async fn insert_project(
         )
         .into_response());
     }
+    user.require_org(params.org, &state.dbpool).await?;
     match do_insert(user, &params, &state).await {
         Err(e) => {
             warn!("Failed to insert project: {e:?}");
@@ -137,10 +185,10 @@ async fn insert_project(
             )
             .into_response())
         }
-        Ok(result) => {
-            let id = result.last_insert_rowid();
+        Ok(project) => {
+            let id = project.id;
             debug!(id, "successfully inserted project");
-            let projecturl = app_url(&format!("/project/{}", id));
+            let projecturl = app_url(&format!("/project/{}", project.uuid));
 
             Ok((
                 [("HX-Redirect", projecturl)],
@@ -165,62 +213,449 @@ async fn insert_project(
 
 #[derive(Deserialize, Serialize)]
 struct ShowProjectQueryParams {
-    sort: Option<SortField>,
+    /// one or more sort keys, e.g. `taxon,-date`
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    sort: Option<SortSpecs<SortField>>,
+    /// the direction of the first sort key, as chosen in the sort menu
     dir: Option<SortOrder>,
+    /// a secondary sort key, as chosen in the sort menu
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    then: Option<SortField>,
+    thendir: Option<SortOrder>,
     filter: Option<String>,
+    /// only show allocations with this data quality issue
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    issue: Option<Issue>,
     _limit: Option<i32>,
     _offset: Option<i32>,
 }
 
+impl ShowProjectQueryParams {
+    /// The sort keys of the list, combining the `sort` parameter with the fields of the sort menu
+    fn sort_specs(&self) -> Option<SortSpecs<SortField>> {
+        let mut keys = self.sort.as_ref()?.keys().iter().cloned();
+        let mut first = keys.next()?;
+        if let Some(dir) = &self.dir {
+            first.order = dir.clone();
+        }
+        let mut specs = keys.fold(SortSpecs::new(first), SortSpecs::then);
+        if let Some(field) = &self.then {
+            specs = specs.then(SortSpec::new(
+                field.clone(),
+                self.thendir.clone().unwrap_or(SortOrder::Ascending),
+            ));
+        }
+        Some(specs)
+    }
+}
+
+/// Load a project accessible to `user` along with the allocated samples that match the given query
+async fn load_project_samples(
+    user: &SqliteUser,
+    uuid: Uuid,
+    params: &ShowProjectQueryParams,
+    state: &AppState,
+) -> Result<Project, Error> {
+    let mut projects = user
+        .scope()
+        .projects(Some(project::Filter::Uuid(uuid).into()), &state.dbpool)
+        .await?;
+    let Some(mut project) = projects.pop() else {
+        return Err(Error::NotFound("That project does not exist".to_string()));
+    };
+
+    let sort = params.sort_specs();
+    let mut fbuilder = CompoundFilter::builder(Op::And);
+    if let Some(fragment) = params.filter.as_ref().filter(|f| !f.trim().is_empty()) {
+        fbuilder = fbuilder.push(
+            CompoundFilter::builder(Op::Or)
+                .push(allocation::Filter::TaxonNameLike(fragment.clone()))
+                .push(allocation::Filter::SourceName(Cmp::Like, fragment.clone()))
+                .push(allocation::Filter::Notes(Cmp::Like, fragment.clone()))
+                .build(),
+        );
+    }
+    if let Some(issue) = params.issue {
+        let today = user.time_zone().now().date();
+        let filter = issue.allocation_filter(today).ok_or_else(|| {
+            Error::NotFound(format!(
+                "'{issue}' is not a data quality issue of allocations"
+            ))
+        })?;
+        fbuilder = fbuilder.push(filter);
+    }
+    project
+        .load_samples(Some(fbuilder.build()), sort, &state.dbpool)
+        .await?;
+    Ok(project)
+}
+
 async fn show_project(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
-    Path(id): Path<i64>,
+    Path(uuid): Path<Uuid>,
     State(state): State<AppState>,
     query: Result<Query<ShowProjectQueryParams>, QueryRejection>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, Error> {
     let Query(params) = query.map_err(Error::UnprocessableEntityQueryRejection)?;
-    let fb = CompoundFilter::builder(Op::And)
-        .push(project::Filter::Id(id))
-        .push(project::Filter::User(user.id));
+    let project = load_project_samples(&user, uuid, &params, &state).await?;
+    let orgs = user.writable_orgs(&state.dbpool).await?;
+    let reminders =
+        Reminder::load_target(user.id, ReminderTarget::Project(project.id), &state.dbpool).await?;
+    let taxon_names = DisplayName::load_map(user.id, &state.dbpool).await?;
+    let grades = stats::project_grades(project.id, &state.dbpool).await?;
+    let establishment = Establishment::load_project(project.id, &state.dbpool).await?;
 
-    let mut projects = Project::load_all(Some(fb.build()), &state.dbpool).await?;
-    let Some(mut project) = projects.pop() else {
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 project => project,
+                 taxon_names => taxon_names,
+                 grades => grades,
+                 establishment => establishment,
+                 orgs => orgs,
+                 reminders => reminders,
+                 sort => params.sort_specs().map(|sort| sort.keys().to_vec()),
+                 issue_description => params.issue.map(|issue| issue.description()),
+                 query => params,
+                 filteronly => headers.get("HX-Request").is_some()),
+    )
+    .into_response())
+}
+
+/// A condensed version of the project page that is intended to be printed out and taken into the
+/// field. It accepts the same query parameters as the project page so that the printout matches
+/// what the user is currently looking at.
+async fn print_project(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(uuid): Path<Uuid>,
+    State(state): State<AppState>,
+    query: Result<Query<ShowProjectQueryParams>, QueryRejection>,
+) -> Result<impl IntoResponse, Error> {
+    let Query(params) = query.map_err(Error::UnprocessableEntityQueryRejection)?;
+    let mut project = load_project_samples(&user, uuid, &params, &state).await?;
+    // the project page only shows the latest note, but the printout includes the full history
+    for alloc in project.allocations.iter_mut() {
+        alloc.load_notes(&state.dbpool).await?;
+    }
+    let taxon_names = DisplayName::load_map(user.id, &state.dbpool).await?;
+
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 project => project,
+                 taxon_names => taxon_names,
+                 query => params),
+    )
+    .into_response())
+}
+
+#[derive(Deserialize, Serialize)]
+struct PropagationQueryParams {
+    sort: Option<PlanSortField>,
+    dir: Option<SortOrder>,
+}
+
+/// Load a project accessible to `user` along with its propagation plan
+async fn load_propagation_plan(
+    user: &SqliteUser,
+    uuid: Uuid,
+    params: &PropagationQueryParams,
+    state: &AppState,
+) -> Result<(Project, Vec<PlanItem>), Error> {
+    let mut projects = user
+        .scope()
+        .projects(Some(project::Filter::Uuid(uuid).into()), &state.dbpool)
+        .await?;
+    let Some(project) = projects.pop() else {
         return Err(Error::NotFound("That project does not exist".to_string()));
     };
-
-    let sort = params.sort.as_ref().cloned().map(|field| {
+    let sort = params.sort.map(|field| {
         SortSpec::new(
             field,
             params.dir.as_ref().cloned().unwrap_or(SortOrder::Ascending),
         )
     });
-    let sample_filter = match params.filter {
-        Some(ref fragment) if !fragment.trim().is_empty() => Some(
-            CompoundFilter::builder(Op::Or)
-                .push(allocation::Filter::TaxonNameLike(fragment.clone()))
-                .push(allocation::Filter::SourceName(Cmp::Like, fragment.clone()))
-                .push(allocation::Filter::Notes(Cmp::Like, fragment.clone()))
-                .build(),
-        ),
-        _ => None,
-    };
-    project
-        .load_samples(sample_filter, sort, &state.dbpool)
-        .await?;
+    let plan = propagation::load(project.id, sort, &state.dbpool).await?;
+    Ok((project, plan))
+}
 
+async fn show_propagation_plan(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(uuid): Path<Uuid>,
+    State(state): State<AppState>,
+    query: Result<Query<PropagationQueryParams>, QueryRejection>,
+) -> Result<impl IntoResponse, Error> {
+    let Query(params) = query.map_err(Error::UnprocessableEntityQueryRejection)?;
+    let (project, plan) = load_propagation_plan(&user, uuid, &params, &state).await?;
     Ok(RenderHtml(
         key,
         state.tmpl.clone(),
         context!(user => user,
                  project => project,
-                 query => params,
-                 filteronly => headers.get("HX-Request").is_some()),
+                 plan => plan,
+                 query => params),
     )
     .into_response())
 }
 
+/// The propagation plan as a CSV file, in the same order as it is shown on the page
+async fn export_propagation_plan(
+    user: SqliteUser,
+    Path(uuid): Path<Uuid>,
+    State(state): State<AppState>,
+    query: Result<Query<PropagationQueryParams>, QueryRejection>,
+) -> Result<impl IntoResponse, Error> {
+    let Query(params) = query.map_err(Error::UnprocessableEntityQueryRejection)?;
+    let (project, plan) = load_propagation_plan(&user, uuid, &params, &state).await?;
+    let data = bundle::propagation_csv(&plan)?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{}-propagation.csv\"",
+                    format_id_number(project.id, Some("P"), None)
+                ),
+            ),
+        ],
+        data,
+    ))
+}
+
+#[derive(Deserialize)]
+struct FieldSheetParams {
+    #[serde(default)]
+    blank: bool,
+}
+
+/// The field data sheets of the project as a PDF, filled in with the samples of the project unless
+/// blank sheets are requested
+async fn download_field_sheets(
+    user: SqliteUser,
+    Path(uuid): Path<Uuid>,
+    State(state): State<AppState>,
+    Query(params): Query<FieldSheetParams>,
+) -> Result<impl IntoResponse, Error> {
+    let mut project = load_project(&user, uuid, Permission::View, &state).await?;
+    if !params.blank {
+        project.load_samples(None, None, &state.dbpool).await?;
+    }
+    let data = bundle::field_sheets_pdf(&state, &project, params.blank)?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/pdf".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{}\"",
+                    bundle::field_sheets_filename(&project)
+                ),
+            ),
+        ],
+        data,
+    ))
+}
+
+/// An event of the project timeline along with where it is drawn, as percentages of the width of
+/// the timeline
+#[derive(Serialize)]
+struct ChartEvent<'a> {
+    #[serde(flatten)]
+    event: &'a TimelineEvent,
+    left: f64,
+    width: f64,
+}
+
+#[derive(Serialize)]
+struct ChartRow<'a> {
+    row: &'a TimelineRow,
+    events: Vec<ChartEvent<'a>>,
+}
+
+/// The horizontal layout of the project timeline. It starts at the beginning of the month of the
+/// first event and ends at the end of the month of the last one.
+struct ChartScale {
+    start: time::Date,
+    days: f64,
+}
+
+impl ChartScale {
+    fn new(first: time::Date, last: time::Date) -> Self {
+        let start = first.replace_day(1).unwrap_or(first);
+        let end = match last.month() {
+            time::Month::December => {
+                time::Date::from_calendar_date(last.year() + 1, time::Month::January, 1)
+            }
+            month => time::Date::from_calendar_date(last.year(), month.next(), 1),
+        }
+        .unwrap_or(last);
+        Self {
+            start,
+            days: (end - start).whole_days().max(1) as f64,
+        }
+    }
+
+    /// The position of the start of the given day
+    fn position(&self, date: time::Date) -> f64 {
+        (date - self.start).whole_days() as f64 * 100.0 / self.days
+    }
+
+    fn event<'a>(&self, event: &'a TimelineEvent) -> ChartEvent<'a> {
+        let left = self.position(event.start);
+        ChartEvent {
+            event,
+            left,
+            width: self.position(event.last_day() + time::Duration::DAY) - left,
+        }
+    }
+
+    /// The first day of each month of the timeline, with its position and name
+    fn months(&self) -> Vec<(f64, String)> {
+        let mut months = Vec::new();
+        let mut date = self.start;
+        while self.position(date) < 100.0 {
+            months.push((
+                self.position(date),
+                format!("{} {}", date.month(), date.year()),
+            ));
+            date = date
+                .replace_day(date.month().length(date.year()))
+                .unwrap_or(date)
+                + time::Duration::DAY;
+        }
+        months
+    }
+}
+
+/// The key dates of the project on a horizontal timeline
+async fn show_timeline(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(uuid): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    let mut projects = user
+        .scope()
+        .projects(Some(project::Filter::Uuid(uuid).into()), &state.dbpool)
+        .await?;
+    let Some(project) = projects.pop() else {
+        return Err(Error::NotFound("That project does not exist".to_string()));
+    };
+    let timeline = Timeline::load(project.id, &state.dbpool).await?;
+    let (rows, months, today) = match timeline.span() {
+        Some((first, last)) => {
+            let scale = ChartScale::new(first, last);
+            let rows: Vec<ChartRow> = timeline
+                .rows
+                .iter()
+                .map(|row| ChartRow {
+                    row,
+                    events: row.events.iter().map(|e| scale.event(e)).collect(),
+                })
+                .collect();
+            let today = scale.position(time::OffsetDateTime::now_utc().date());
+            (
+                rows,
+                scale.months(),
+                (0.0..100.0).contains(&today).then_some(today),
+            )
+        }
+        None => (Vec::new(), Vec::new(), None),
+    };
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 project => project,
+                 rows => rows,
+                 months => months,
+                 today => today),
+    ))
+}
+
+/// Start generating an export bundle of the project in the background. The response polls the
+/// status of the bundle until it can be downloaded.
+async fn request_bundle(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(uuid): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    let project = Project::load_uuid(uuid, &state.dbpool)
+        .await
+        .map_err(|_| Error::NotFound("That project does not exist".to_string()))?;
+    user.require(&project, Permission::View, &state.dbpool)
+        .await?;
+    let mut bundle = Bundle::new(project.id, user.id);
+    bundle.insert(&state.dbpool).await?;
+    jobs::build_bundle(state.clone(), bundle.clone());
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(project => project, bundle => bundle),
+    ))
+}
+
+/// Load a bundle of the given project that was requested by `user`
+async fn load_bundle(
+    user: &SqliteUser,
+    projectid: Uuid,
+    bundleid: Uuid,
+    state: &AppState,
+) -> Result<(Project, Bundle), Error> {
+    let not_found = || Error::NotFound("That bundle does not exist".to_string());
+    let bundle = Bundle::load_uuid(bundleid, &state.dbpool)
+        .await
+        .map_err(|_| not_found())?;
+    let project = Project::load(bundle.projectid, &state.dbpool).await?;
+    if project.uuid != projectid || bundle.userid != user.id {
+        return Err(not_found());
+    }
+    Ok((project, bundle))
+}
+
+async fn show_bundle(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path((uuid, bundleid)): Path<(Uuid, Uuid)>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    let (project, bundle) = load_bundle(&user, uuid, bundleid, &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(project => project, bundle => bundle),
+    ))
+}
+
+async fn download_bundle(
+    user: SqliteUser,
+    Path((uuid, bundleid)): Path<(Uuid, Uuid)>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    let (project, bundle) = load_bundle(&user, uuid, bundleid, &state).await?;
+    let (BundleStatus::Ready, Some(data)) = (bundle.status, bundle.data) else {
+        return Err(Error::NotFound("That bundle is not ready yet".to_string()));
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", bundle::filename(&project)),
+            ),
+        ],
+        data,
+    ))
+}
+
 async fn do_update(
     id: i64,
     params: &ProjectParams,
@@ -230,26 +665,46 @@ async fn do_update(
         return Err(anyhow!("No name specified").into());
     }
     let mut project = Project::load(id, &state.dbpool).await?;
+    project.orgid = params.org;
     project.name.clone_from(&params.name);
     project.description.clone_from(&params.description);
+    if let Some(version) = params.version {
+        project.version = version;
+    }
     project.update(&state.dbpool).await.map_err(|e| e.into())
 }
 
 async fn modify_project(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
-    Path(id): Path<i64>,
+    Path(uuid): Path<Uuid>,
     State(state): State<AppState>,
     Form(params): Form<ProjectParams>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let fb = CompoundFilter::builder(Op::And)
-        .push(project::Filter::Id(id))
-        .push(project::Filter::User(user.id));
-    let projects = Project::load_all(Some(fb.build()), &state.dbpool).await?;
-    if projects.is_empty() {
+    let projects = user
+        .scope()
+        .projects(Some(project::Filter::Uuid(uuid).into()), &state.dbpool)
+        .await?;
+    let Some(project) = projects.first() else {
         return Ok(StatusCode::NOT_FOUND.into_response());
     };
-    let (request, message, headers) = match do_update(id, &params, &state).await {
+    user.require(project, Permission::Edit, &state.dbpool)
+        .await?;
+    user.require_move(project, params.org, &state.dbpool)
+        .await?;
+    let id = project.id;
+    let orgs = user.writable_orgs(&state.dbpool).await?;
+    let res = do_update(id, &params, &state).await;
+    let conflict = res.as_ref().is_err_and(|e| e.is_version_conflict());
+    let (request, message, headers) = match res {
+        Err(_) if conflict => (
+            Some(&params),
+            Message {
+                r#type: MessageType::Warning,
+                msg: "This project was modified by someone else while you were editing it. Review the saved values and submit again to replace them with your changes.".to_string(),
+            },
+            None,
+        ),
         Err(e) => (
             Some(&params),
             Message {
@@ -264,7 +719,7 @@ async fn modify_project(
                 r#type: MessageType::Success,
                 msg: "Successfully updated project".to_string(),
             },
-            Some([("HX-Redirect", app_url(&format!("/project/{id}")))]),
+            Some([("HX-Redirect", app_url(&format!("/project/{uuid}")))]),
         ),
     };
     let project = Project::load(id, &state.dbpool).await?;
@@ -274,8 +729,10 @@ async fn modify_project(
             key,
             state.tmpl.clone(),
             context!(project => project,
+             orgs => orgs,
              message => message,
              request => request,
+             conflict => conflict,
             ),
         ),
     )
@@ -285,21 +742,23 @@ async fn modify_project(
 async fn delete_project(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
-    Path(id): Path<i64>,
+    Path(uuid): Path<Uuid>,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let mut project = Project::load(id, &state.dbpool)
+    let mut project = Project::load_uuid(uuid, &state.dbpool)
         .await
         .map_err(|_| Error::NotFound("That project does not exist".to_string()))?;
-    if project.userid != user.id {
+    let id = project.id;
+    if let Err(e) = user
+        .require(&project, Permission::Manage, &state.dbpool)
+        .await
+    {
         warn!(
             user.id,
             ?project,
-            "User tried to delete project they don't own"
+            "User tried to delete project they don't manage"
         );
-        return Err(Error::Unauthorized(
-            "No permission to delete this project".to_string(),
-        ));
+        return Err(e);
     }
 
     let errmsg = match project.delete(&state.dbpool).await {
@@ -331,12 +790,68 @@ async fn delete_project(
     .into_response())
 }
 
+/// Ask to confirm deleting a project, for browsers without javascript
+async fn confirm_delete_project(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(uuid): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let project = Project::load_uuid(uuid, &state.dbpool)
+        .await
+        .map_err(|_| Error::NotFound("That project does not exist".to_string()))?;
+    user.require(&project, Permission::Manage, &state.dbpool)
+        .await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, project => project),
+    ))
+}
+
+/// Delete a project after the deletion was confirmed without javascript
+async fn delete_project_confirmed(
+    user: SqliteUser,
+    Path(uuid): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut project = Project::load_uuid(uuid, &state.dbpool)
+        .await
+        .map_err(|_| Error::NotFound("That project does not exist".to_string()))?;
+    user.require(&project, Permission::Manage, &state.dbpool)
+        .await?;
+    match project.delete(&state.dbpool).await {
+        Ok(_) => Ok(Redirect::to(&app_url("/project/list")).into_response()),
+        Err(e) => {
+            warn!(?e, "Failed to delete project");
+            let project = Project::load_uuid(uuid, &state.dbpool).await?;
+            Ok((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                RenderHtml(
+                    "project_@id_delete.html",
+                    state.tmpl.clone(),
+                    context!(user => user,
+                    project => project,
+                    message => Message {
+                        r#type: MessageType::Error,
+                        msg: format!("Failed to delete project: {e}"),
+                    }),
+                ),
+            )
+                .into_response())
+        }
+    }
+}
+
 async fn add_sample_prep(
     user: &SqliteUser,
-    id: i64,
+    uuid: Uuid,
     state: &AppState,
 ) -> Result<(Project, Vec<Sample>), error::Error> {
-    let project = Project::load(id, &state.dbpool).await?;
+    let project = Project::load_uuid(uuid, &state.dbpool).await?;
+    let id = project.id;
+    user.require(&project, Permission::Edit, &state.dbpool)
+        .await?;
 
     let ids_in_project = sqlx::query!(
         "SELECT PS.sampleid from sc_project_samples PS WHERE PS.projectid=?",
@@ -352,13 +867,14 @@ async fn add_sample_prep(
      * [ query ids first ], then
      *  'WHERE NOT IN (1, 2, 3, 4, 5...)'
      */
-    let samples = Sample::load_all_user(
-        user.id,
-        Some(Arc::new(sample::Filter::IdNotIn(ids))),
-        None,
-        &state.dbpool,
-    )
-    .await?;
+    let samples = user
+        .scope()
+        .samples(
+            Some(Arc::new(sample::Filter::IdNotIn(ids))),
+            None,
+            &state.dbpool,
+        )
+        .await?;
     Ok((project, samples))
 }
 
@@ -366,9 +882,9 @@ async fn show_add_sample(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
-    Path(id): Path<i64>,
+    Path(uuid): Path<Uuid>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let (project, samples) = add_sample_prep(&user, id, &state).await?;
+    let (project, samples) = add_sample_prep(&user, uuid, &state).await?;
     Ok(RenderHtml(
         key,
         state.tmpl.clone(),
@@ -383,7 +899,8 @@ async fn add_sample(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
-    Path(id): Path<i64>,
+    Path(uuid): Path<Uuid>,
+    headers: HeaderMap,
     Form(params): Form<Vec<(String, String)>>,
 ) -> Result<impl IntoResponse, error::Error> {
     let toadd: Vec<i64> = params
@@ -393,71 +910,59 @@ async fn add_sample(
             _ => None,
         })
         .collect();
-    let res = sqlx::query!("SELECT userid FROM sc_projects WHERE projectid=?", id)
-        .fetch_one(&state.dbpool)
-        .await?;
-    if res.userid != user.id {
+    let mut project = Project::load_uuid(uuid, &state.dbpool).await?;
+    if user
+        .require(&project, Permission::Edit, &state.dbpool)
+        .await
+        .is_err()
+    {
         return Ok(StatusCode::UNAUTHORIZED.into_response());
     }
-    let mut qb =
-        sqlx::QueryBuilder::new("SELECT sampleid, userid FROM sc_samples WHERE sampleid IN (");
-    let mut sep = qb.separated(", ");
-    for id in toadd {
-        sep.push_bind(id);
+    let mut idfilter = CompoundFilter::builder(Op::Or);
+    for id in &toadd {
+        idfilter = idfilter.push(sample::Filter::Id(Cmp::Equal, *id));
     }
-    qb.push(")");
-    let res = qb.build().fetch_all(&state.dbpool).await?;
-    let valid_samples = res.iter().filter_map(|row| {
-        let userid: Option<i64> = row.try_get("userid").ok()?;
-        let userid = userid?;
-        let id: i64 = row.try_get("sampleid").ok()?;
-        if userid == user.id {
-            Some(id)
-        } else {
-            warn!(
-                "dropping sample {} which is not owned by user {}",
-                id, user.id
-            );
-            None
-        }
-    });
-
-    let mut project = Project::load(id, &state.dbpool).await?;
-    let mut n_inserted = 0;
-    let mut messages = Vec::new();
-    for sample in valid_samples {
-        match project
-            .allocate_sample(ExternalRef::Stub(sample), &state.dbpool)
-            .await
-        {
-            Err(e) => messages.push(Message {
-                r#type: MessageType::Error,
-                msg: format!(
-                    "Failed to add sample {}: {}",
-                    format_id_number(sample, Some("S"), None),
-                    e
-                ),
-            }),
-            Ok(res) => n_inserted += res.rows_affected(),
+    let valid_samples: Vec<i64> = match toadd.is_empty() {
+        true => Vec::new(),
+        false => user
+            .scope()
+            .samples(Some(idfilter.build()), None, &state.dbpool)
+            .await?
+            .iter()
+            .map(|s| s.id)
+            .collect(),
+    };
+    let allocated = project
+        .allocate_samples(&valid_samples, &state.dbpool)
+        .await;
+    // report the samples that the user can't access along with the ones that failed to be added,
+    // in the order that they were submitted
+    let mut result = BulkResult::new();
+    for id in toadd {
+        match allocated.items.iter().find(|r| r.item == id) {
+            Some(item) => result.items.push(item.clone()),
+            None => {
+                warn!(
+                    "not adding sample {} which is not accessible to user {}",
+                    id, user.id
+                );
+                result.fail(id, "You don't have access to this sample");
+            }
         }
     }
 
-    if n_inserted > 0 {
-        messages.insert(
-            0,
-            Message {
-                r#type: MessageType::Success,
-                msg: format!("Assigned {n_inserted} samples to this project"),
-            },
-        );
-    }
-
-    let (project, samples) = add_sample_prep(&user, id, &state).await?;
+    let (project, samples) = add_sample_prep(&user, uuid, &state).await?;
+    // without javascript, the form was submitted normally and the whole page is shown again
+    let key = match headers.get("HX-Request") {
+        Some(_) => key,
+        None => "project_@id_add.html".to_string(),
+    };
     Ok(RenderHtml(
         key,
         state.tmpl.clone(),
-        context!(project => project,
-                 messages => messages,
+        context!(user => user,
+                 project => project,
+                 result => result,
                  samples => samples),
     )
     .into_response())
@@ -0,0 +1,116 @@
+use crate::{auth::SqliteUser, error::Error, state::AppState, Message, MessageType, TemplateKey};
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::post,
+    Form, Router,
+};
+use axum_template::RenderHtml;
+use libseed::{
+    organization::Permission,
+    parse_date,
+    project::Project,
+    reminder::{Reminder, ReminderTarget},
+    sample::Sample,
+    source::Source,
+};
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/new", post(add_reminder))
+        .route("/:id/dismiss", post(dismiss_reminder))
+}
+
+/// The kind of object that a new reminder is about
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum TargetKind {
+    Sample,
+    Project,
+    Source,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReminderParams {
+    target: TargetKind,
+    uuid: Uuid,
+    title: String,
+    due: String,
+}
+
+/// Find the object that a reminder is about, making sure that the user can see it
+async fn load_target(
+    user: &SqliteUser,
+    kind: TargetKind,
+    uuid: Uuid,
+    state: &AppState,
+) -> Result<ReminderTarget, Error> {
+    Ok(match kind {
+        TargetKind::Sample => {
+            let sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+            user.require(&sample, Permission::View, &state.dbpool)
+                .await?;
+            ReminderTarget::Sample(sample.id)
+        }
+        TargetKind::Project => {
+            let project = Project::load_uuid(uuid, &state.dbpool).await?;
+            user.require(&project, Permission::View, &state.dbpool)
+                .await?;
+            ReminderTarget::Project(project.id)
+        }
+        TargetKind::Source => {
+            let source = Source::load_uuid(uuid, &state.dbpool).await?;
+            user.require(&source, Permission::View, &state.dbpool)
+                .await?;
+            ReminderTarget::Source(source.id)
+        }
+    })
+}
+
+async fn add_reminder(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Form(params): Form<ReminderParams>,
+) -> Result<impl IntoResponse, Error> {
+    let target = load_target(&user, params.target, params.uuid, &state).await?;
+    let res = match parse_date(&params.due) {
+        Ok(due) => {
+            let mut reminder = Reminder::about(user.id, target, due, params.title.clone());
+            reminder.insert(&state.dbpool).await
+        }
+        Err(e) => Err(e),
+    };
+    let message = res.err().map(|e| Message {
+        r#type: MessageType::Error,
+        msg: format!("Failed to add reminder: {e}"),
+    });
+    let reminders = Reminder::load_target(user.id, target, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(reminders => reminders,
+                 target => params.target,
+                 uuid => params.uuid,
+                 message => message),
+    ))
+}
+
+async fn dismiss_reminder(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, Error> {
+    let mut reminder = Reminder::load(id, &state.dbpool)
+        .await
+        .map_err(|_| Error::NotFound(format!("Reminder {id} not found")))?;
+    if reminder.userid != user.id {
+        return Err(Error::NotFound(format!("Reminder {id} not found")));
+    }
+    reminder.dismiss(&state.dbpool).await?;
+    // the reminder is simply removed from the page
+    Ok(())
+}
@@ -0,0 +1,244 @@
+use crate::{
+    app_url,
+    auth::SqliteUser,
+    error::{self, Error},
+    state::AppState,
+    Message, MessageType, TemplateKey,
+};
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Form, Router,
+};
+use axum_template::RenderHtml;
+use libseed::{
+    empty_string_as_none,
+    loadable::Loadable,
+    report::{Report, ReportFormat},
+};
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use tracing::{debug, warn};
+
+use super::error_alert_response;
+
+/// The content security policy of rendered HTML reports. Reports are written by users, so they are
+/// sandboxed and can't run scripts or load anything from other sites.
+const REPORT_CSP: &str = "sandbox; default-src 'none'; style-src 'unsafe-inline'; img-src data:";
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/list", get(list_reports))
+        .route("/new", get(show_new_report).post(insert_report))
+        .route(
+            "/:id",
+            get(show_report).put(modify_report).delete(delete_report),
+        )
+        .route("/:id/run", get(run_report))
+}
+
+async fn list_reports(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let reports = user.scope().reports(None, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, reports => reports),
+    )
+    .into_response())
+}
+
+async fn show_new_report(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let formats: Vec<ReportFormat> = ReportFormat::iter().collect();
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, formats => formats),
+    )
+    .into_response())
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ReportParams {
+    name: String,
+    #[serde(default)]
+    format: ReportFormat,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    filter: Option<String>,
+    template: String,
+}
+
+impl ReportParams {
+    /// Apply the submitted values to the given report
+    fn apply(&self, report: &mut Report) {
+        report.name.clone_from(&self.name);
+        report.format = self.format;
+        report.filter.clone_from(&self.filter);
+        report.template.clone_from(&self.template);
+    }
+}
+
+async fn insert_report(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Form(params): Form<ReportParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut report = Report::new(
+        String::new(),
+        ReportFormat::default(),
+        None,
+        String::new(),
+        user.id,
+    );
+    params.apply(&mut report);
+    match report.insert(&state.dbpool).await {
+        Err(e) => {
+            warn!("Failed to insert report: {e:?}");
+            Ok(error_alert_response(
+                &state,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Failed to save report: {e}"),
+            )
+            .into_response())
+        }
+        Ok(_) => {
+            debug!(id = report.id, "successfully inserted report");
+            Ok((
+                [("HX-Redirect", app_url(&format!("/report/{}", report.id)))],
+                RenderHtml(
+                    "_ALERT.html",
+                    state.tmpl.clone(),
+                    context!(message => Message {
+                        r#type: MessageType::Success,
+                        msg: format!("Added new report {}", report.name),
+                    }),
+                ),
+            )
+                .into_response())
+        }
+    }
+}
+
+/// Load a report, making sure that it belongs to `user`. Reports can't be shared with other users.
+async fn load_report(user: &SqliteUser, id: i64, state: &AppState) -> Result<Report, Error> {
+    match Report::load(id, &state.dbpool).await {
+        Ok(report) if report.userid == user.id => Ok(report),
+        _ => Err(Error::NotFound("That report does not exist".to_string())),
+    }
+}
+
+async fn show_report(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let report = load_report(&user, id, &state).await?;
+    let formats: Vec<ReportFormat> = ReportFormat::iter().collect();
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, report => report, formats => formats),
+    )
+    .into_response())
+}
+
+async fn modify_report(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Form(params): Form<ReportParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut report = load_report(&user, id, &state).await?;
+    params.apply(&mut report);
+    let (request, message) = match report.update(&state.dbpool).await {
+        Err(e) => (
+            Some(&params),
+            Message {
+                r#type: MessageType::Error,
+                msg: e.to_string(),
+            },
+        ),
+        Ok(_) => (
+            None,
+            Message {
+                r#type: MessageType::Success,
+                msg: "Successfully updated report".to_string(),
+            },
+        ),
+    };
+    let report = Report::load(id, &state.dbpool).await?;
+    let formats: Vec<ReportFormat> = ReportFormat::iter().collect();
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(report => report,
+                 formats => formats,
+                 message => message,
+                 request => request),
+    )
+    .into_response())
+}
+
+async fn delete_report(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut report = load_report(&user, id, &state).await?;
+    report.delete(&state.dbpool).await?;
+    debug!(id, "Successfully deleted report");
+    Ok((
+        [("HX-Redirect", app_url("/report/list"))],
+        RenderHtml(key, state.tmpl.clone(), context!(deleted => true, id => id)),
+    )
+        .into_response())
+}
+
+/// Render a report. HTML reports are shown in the browser in a sandbox, CSV reports are downloaded.
+async fn run_report(
+    user: SqliteUser,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let report = load_report(&user, id, &state).await?;
+    let output = match report.render(&state.dbpool).await {
+        Ok(output) => output,
+        Err(e) => {
+            warn!(id, "Failed to render report: {e}");
+            return Ok((StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response());
+        }
+    };
+    let disposition = match report.format {
+        ReportFormat::Html => "inline".to_string(),
+        ReportFormat::Csv => format!(
+            "attachment; filename=\"report-{}.{}\"",
+            report.id,
+            report.format.extension()
+        ),
+    };
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                report.format.content_type().to_string(),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::CONTENT_SECURITY_POLICY, REPORT_CSP.to_string()),
+        ],
+        output,
+    )
+        .into_response())
+}
@@ -7,42 +7,183 @@ use crate::{
 };
 use anyhow::anyhow;
 use axum::{
-    extract::{Path, Query, State},
-    http::HeaderMap,
-    response::IntoResponse,
-    routing::get,
+    extract::{DefaultBodyLimit, Multipart, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect},
+    routing::{delete, get, post, put},
     Form, Router,
 };
 use axum_template::RenderHtml;
 use libseed::{
+    conservation::{self, Listing, PermitPolicy},
+    dataquality::Issue,
+    deletion::{self, DeleteImpact},
     empty_string_as_none,
-    filter::{Cmp, CompoundFilter, Op},
-    loadable::{ExternalRef, Loadable},
-    project::{allocation, Allocation},
-    sample::{self, Certainty, Sample},
-    source::Source,
+    filter::{Cmp, CompoundFilter, Op, SortSpecs},
+    forecast,
+    grade::QualityGrade,
+    history::{self, Change},
+    loadable::{ExternalRef, Loadable, PartialUpdate},
+    monitoring,
+    organization::Permission,
+    photo::{Photo, MAX_PHOTO_SIZE},
+    preferences::Preferences,
+    project::allocation,
+    quality::{FillMethod, QualityTest},
+    reminder::{Reminder, ReminderTarget},
+    sample::{self, Certainty, Purchase, Sample, SampleField, SampleFlag},
+    sitematch::PlantingSite,
+    source::{HabitatType, LightCondition, SoilMoisture},
+    stats::{self, Snapshot},
+    stock::{Threshold, ThresholdTarget},
+    taxonomy::{self, names::DisplayName, Taxon},
+    trip::Trip,
 };
 use minijinja::context;
 use serde::{Deserialize, Serialize};
 use sqlx::sqlite::SqliteQueryResult;
-use std::sync::Arc;
+use std::{str::FromStr, sync::Arc};
+use strum::IntoEnumIterator;
+use time::Duration;
 use tracing::debug;
+use uuid::Uuid;
+
+use super::{
+    deletion::{selected_uuids, BulkItem},
+    error_alert_response,
+};
 
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/list", get(list_samples))
+        .route("/delete", get(confirm_bulk_delete).post(bulk_delete))
         .route("/new", get(new_sample).post(insert_sample))
         .route(
             "/:id",
             get(show_sample).put(update_sample).delete(delete_sample),
         )
         .route("/:id/edit", get(show_sample))
+        .route(
+            "/:id/delete",
+            get(confirm_delete_sample).post(delete_sample_confirmed),
+        )
+        .route(
+            "/:id/inline/:field",
+            get(show_inline_field).patch(update_inline_field),
+        )
+        .route("/:id/inline/:field/edit", get(edit_inline_field))
+        .route("/:id/threshold", put(update_threshold))
+        .route("/:id/grade", put(update_grade))
+        .route("/:id/history", get(show_history))
+        .route("/:id/history/:change/revert", post(revert_change))
+        .route("/:id/flag", post(flag_sample))
+        .route("/:id/flag/:flagid", delete(unflag_sample))
+        .route("/:id/quality", post(add_quality_test))
+        .route("/:id/quality/:testid", delete(delete_quality_test))
+        .route(
+            "/:id/photos",
+            post(add_photo).layer(DefaultBodyLimit::max(MAX_PHOTO_SIZE + 64 * 1024)),
+        )
+        .route("/:id/photos/:photoid", get(show_photo).delete(delete_photo))
+        .route("/flagged", get(list_flagged))
+        .route("/calendar", get(show_calendar))
+        .route("/warnings", get(show_taxon_warnings))
+        .route("/match", get(match_site))
+        .route("/forecast", get(show_forecast))
+        .route("/forecast/csv", get(export_forecast))
+        .route("/vendors", get(show_vendors))
+        .route("/growth", get(show_growth))
+        .route("/growth/csv", get(export_growth))
+        .route("/labels", get(show_labels).post(mark_labels_printed))
+        .merge(super::import::router())
+        .merge(super::grade::router())
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum SampleGrouping {
+    Taxon,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum SampleOrigin {
+    Collected,
+    Purchased,
+}
+
+impl std::str::FromStr for SampleOrigin {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "collected" => Ok(Self::Collected),
+            "purchased" => Ok(Self::Purchased),
+            _ => Err(anyhow!("Unknown sample origin '{s}'")),
+        }
+    }
+}
+
+/// The grade that the sample list is filtered by: either a grade of the user's scale (by id) or
+/// `none` for samples without a grade
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GradeSelection {
+    Ungraded,
+    Grade(i64),
+}
+
+impl FromStr for GradeSelection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::Ungraded),
+            _ => s
+                .parse()
+                .map(Self::Grade)
+                .map_err(|_| anyhow!("Unknown grade '{s}'")),
+        }
+    }
+}
+
+impl std::fmt::Display for GradeSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ungraded => write!(f, "none"),
+            Self::Grade(id) => write!(f, "{id}"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
 struct SampleListParams {
-    #[serde(deserialize_with = "empty_string_as_none")]
+    #[serde(default, deserialize_with = "empty_string_as_none")]
     filter: Option<String>,
+    #[serde(default)]
+    group: Option<SampleGrouping>,
+    /// only show samples of this taxon. Used to expand a group in the grouped view
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    taxon: Option<i64>,
+    /// only show samples of taxa in the family with this id
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    family: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    origin: Option<SampleOrigin>,
+    /// only show samples collected at sources with these conditions
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    habitat: Option<HabitatType>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    moisture: Option<SoilMoisture>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    light: Option<LightCondition>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    grade: Option<GradeSelection>,
+    /// one or more sort keys, e.g. `name,-date`
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    sort: Option<SortSpecs<sample::Sort>>,
+    /// only show samples with this data quality issue
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    issue: Option<Issue>,
 }
 
 async fn list_samples(
@@ -51,78 +192,258 @@ async fn list_samples(
     State(state): State<AppState>,
     query: Option<Query<SampleListParams>>,
     headers: HeaderMap,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, error::Error> {
     debug!("query params: {:?}", query);
-    let filter = query.and_then(|params| {
-        params.filter.as_ref().map(|f| {
+    let params = query.map(|q| q.0).unwrap_or_default();
+    let mut fbuilder = CompoundFilter::builder(Op::And);
+    if let Some(f) = params.filter.as_ref() {
+        fbuilder = fbuilder.push(
             CompoundFilter::builder(Op::Or)
                 .push(sample::Filter::TaxonNameLike(f.clone()))
                 .push(sample::Filter::Notes(Cmp::Like, f.clone()))
                 .push(sample::Filter::SourceNameLike(f.clone()))
-                .build()
-        })
-    });
-    match Sample::load_all_user(user.id, filter, None, &state.dbpool).await {
-        Ok(samples) => RenderHtml(
-            key,
-            state.tmpl.clone(),
-            context!(user => user,
-                     samples => samples,
-                     filteronly => headers.get("HX-Request").is_some()),
-        )
-        .into_response(),
-        Err(e) => error::Error::from(e).into_response(),
+                .build(),
+        );
+    }
+    if let Some(taxon) = params.taxon {
+        fbuilder = fbuilder.push(sample::Filter::TaxonId(Cmp::Equal, taxon));
+    }
+    if let Some(family) = params.family {
+        fbuilder = fbuilder.push(sample::Filter::AncestorTsn(family));
+    }
+    if let Some(origin) = params.origin {
+        fbuilder = fbuilder.push(sample::Filter::Purchased(origin == SampleOrigin::Purchased));
     }
+    if let Some(habitat) = params.habitat {
+        fbuilder = fbuilder.push(sample::Filter::SourceHabitat(habitat));
+    }
+    if let Some(moisture) = params.moisture {
+        fbuilder = fbuilder.push(sample::Filter::SourceMoisture(moisture));
+    }
+    if let Some(light) = params.light {
+        fbuilder = fbuilder.push(sample::Filter::SourceLight(light));
+    }
+    if let Some(grade) = params.grade {
+        fbuilder = fbuilder.push(sample::Filter::Grade(match grade {
+            GradeSelection::Ungraded => None,
+            GradeSelection::Grade(id) => Some(id),
+        }));
+    }
+    if let Some(issue) = params.issue {
+        let filter = issue.sample_filter().ok_or_else(|| {
+            Error::NotFound(format!("'{issue}' is not a data quality issue of samples"))
+        })?;
+        fbuilder = fbuilder.push(filter);
+    }
+    let filter = Some(fbuilder.build());
+    let sort = params.sort.as_ref().map(ToString::to_string);
+    let (samples, groups) = match params.group {
+        Some(SampleGrouping::Taxon) => (
+            Vec::new(),
+            Sample::load_grouped_by_taxon(user.id, filter, &state.dbpool).await?,
+        ),
+        None => (
+            user.scope()
+                .samples(filter, params.sort, &state.dbpool)
+                .await?,
+            Vec::new(),
+        ),
+    };
+    let families = stats::samples_per_family(user.id, &state.dbpool).await?;
+    let taxon_names = DisplayName::load_map(user.id, &state.dbpool).await?;
+    let grades = QualityGrade::load_scale(user.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 samples => samples,
+                 groups => groups,
+                 taxon_names => taxon_names,
+                 families => families,
+                 family => params.family,
+                 origin => params.origin,
+                 habitat => params.habitat,
+                 moisture => params.moisture,
+                 light => params.light,
+                 grades => grades,
+                 grade => params.grade.map(|g| g.to_string()),
+                 sort => sort,
+                 group => params.group,
+                 filter => params.filter,
+                 issue => params.issue,
+                 issue_description => params.issue.map(|issue| issue.description()),
+                 taxon => params.taxon,
+                 filteronly => headers.get("HX-Request").is_some()),
+    ))
 }
 
 async fn show_sample(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
-    Path(id): Path<i64>,
+    Path(uuid): Path<Uuid>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let mut sample = Sample::load(id, &state.dbpool).await?;
-    sample
-        .taxon
-        .object_mut()?
-        .load_germination_info(&state.dbpool)
+    let mut sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&sample, Permission::View, &state.dbpool)
         .await?;
+    let id = sample.id;
+    let taxon = sample.taxon.object_mut()?;
+    taxon.load_germination_info(&state.dbpool).await?;
+    taxon.load_seed_weight(&state.dbpool).await?;
 
     // needed for edit form
-    let sources = Source::load_all_user(user.id, &state.dbpool).await?;
+    let sources = user.scope().sources(None, &state.dbpool).await?;
+    let trips = user.scope().trips(None, &state.dbpool).await?;
+    let orgs = user.writable_orgs(&state.dbpool).await?;
 
-    let mut allocations = Allocation::load_all(
-        Some(Arc::new(allocation::Filter::SampleId(id))),
-        None,
-        &state.dbpool,
-    )
-    .await?;
+    let mut allocations = user
+        .scope()
+        .allocations(
+            Some(Arc::new(allocation::Filter::SampleId(id))),
+            None,
+            &state.dbpool,
+        )
+        .await?;
     for alloc in allocations.iter_mut() {
         alloc.load_notes(&state.dbpool).await?;
     }
 
+    let flags = sample.load_flags(&state.dbpool).await?;
+    let quality_tests = QualityTest::load_sample(id, &state.dbpool).await?;
+    let photos = Photo::load_sample(id, &state.dbpool).await?;
+    let listings = Listing::load_taxon(sample.taxon.id(), &state.dbpool).await?;
+    let reminders =
+        Reminder::load_target(user.id, ReminderTarget::Sample(id), &state.dbpool).await?;
+    let taxon_names = DisplayName::load_map(user.id, &state.dbpool).await?;
+    let threshold = Threshold::load(user.id, ThresholdTarget::Sample(id), &state.dbpool).await?;
+    let taxon_threshold = Threshold::load(
+        user.id,
+        ThresholdTarget::Taxon(sample.taxon.id()),
+        &state.dbpool,
+    )
+    .await?;
+    let grades = QualityGrade::load_scale(sample.user.id(), &state.dbpool).await?;
+
     Ok(RenderHtml(
         key,
         state.tmpl.clone(),
         context!(user => user,
                  sample => sample,
+                 taxon_names => taxon_names,
+                 threshold => threshold,
+                 taxon_threshold => taxon_threshold,
                  sources => sources,
-                 allocations => allocations),
+                 trips => trips,
+                 orgs => orgs,
+                 allocations => allocations,
+                 flags => flags,
+                 quality_tests => quality_tests,
+                 photos => photos,
+                 grades => grades,
+                 fill_methods => FillMethod::iter().collect::<Vec<_>>(),
+                 listings => listings,
+                 reminders => reminders,
+                 flag_reasons => SampleFlag::COMMON_REASONS),
     )
     .into_response())
 }
 
+#[derive(Deserialize)]
+struct ThresholdParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    quantity: Option<i64>,
+}
+
+/// Set the user's minimum quantity of a sample, below which it is shown as low on stock
+async fn update_threshold(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    Form(params): Form<ThresholdParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&sample, Permission::View, &state.dbpool)
+        .await?;
+    let target = ThresholdTarget::Sample(sample.id);
+    let (threshold, message) =
+        match Threshold::save(user.id, target, params.quantity, &state.dbpool).await {
+            Ok(threshold) => (
+                threshold,
+                Message {
+                    r#type: MessageType::Success,
+                    msg: "Saved the minimum quantity".to_string(),
+                },
+            ),
+            Err(libseed::Error::InvalidValue(msg)) => (
+                Threshold::load(user.id, target, &state.dbpool).await?,
+                Message {
+                    r#type: MessageType::Error,
+                    msg,
+                },
+            ),
+            Err(e) => return Err(e.into()),
+        };
+    let taxon_threshold = Threshold::load(
+        user.id,
+        ThresholdTarget::Taxon(sample.taxon.id()),
+        &state.dbpool,
+    )
+    .await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 sample => sample,
+                 threshold => threshold,
+                 taxon_threshold => taxon_threshold,
+                 message => message),
+    ))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct NewSampleQuery {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    trip: Option<i64>,
+}
+
 async fn new_sample(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
+    query: Option<Query<NewSampleQuery>>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let sources = Source::load_all_user(user.id, &state.dbpool).await?;
+    let query = query.map(|q| q.0).unwrap_or_default();
+    let sources = user.scope().sources(None, &state.dbpool).await?;
+    let trips = user.scope().trips(None, &state.dbpool).await?;
+    let orgs = user.writable_orgs(&state.dbpool).await?;
+    let prefs = Preferences::load(user.id, &state.dbpool).await?;
+    let (month, year) = prefs.default_date(&user.time_zone());
+    let defaults = SampleParams {
+        taxon: None,
+        source: prefs.default_source,
+        month,
+        year,
+        quantity: None,
+        notes: None,
+        uncertain: Some(prefs.default_certainty == Certainty::Uncertain),
+        org: None,
+        version: None,
+        vendor: None,
+        lot: None,
+        purchase_date: None,
+        price: None,
+        certified_origin: None,
+        trip: query.trip,
+    };
     Ok(RenderHtml(
         key,
         state.tmpl.clone(),
         context!(user => user,
-                 sources => sources),
+                 sources => sources,
+                 trips => trips,
+                 orgs => orgs,
+                 request => defaults),
     )
     .into_response())
 }
@@ -142,6 +463,44 @@ struct SampleParams {
     #[serde(deserialize_with = "empty_string_as_none")]
     notes: Option<String>,
     uncertain: Option<bool>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    org: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    version: Option<i64>,
+    /// the vendor that the sample was bought from. The other purchase details are ignored for
+    /// samples without a vendor.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    vendor: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    lot: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    purchase_date: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    price: Option<f64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    certified_origin: Option<String>,
+    /// the trip that the sample was collected on
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    trip: Option<i64>,
+}
+
+impl SampleParams {
+    fn purchase(&self) -> Result<Option<Purchase>, error::Error> {
+        let Some(vendor) = self.vendor.clone() else {
+            return Ok(None);
+        };
+        Ok(Some(Purchase {
+            vendor,
+            lot: self.lot.clone(),
+            date: self
+                .purchase_date
+                .as_deref()
+                .map(Purchase::parse_date)
+                .transpose()?,
+            price: self.price,
+            certified_origin: self.certified_origin.clone(),
+        }))
+    }
 }
 
 async fn do_insert(
@@ -164,21 +523,83 @@ async fn do_insert(
         params.notes.clone(),
         certainty,
     );
+    sample.orgid = params.org;
+    sample.purchase = params.purchase()?;
+    check_trip(user, params.trip, state).await?;
+    sample.trip = params.trip;
     sample.insert(&state.dbpool).await.map_err(|e| e.into())
 }
 
+/// Make sure that the user may record samples as collected on the given trip
+async fn check_trip(
+    user: &SqliteUser,
+    trip: Option<i64>,
+    state: &AppState,
+) -> Result<(), error::Error> {
+    if let Some(id) = trip {
+        let trip = Trip::load(id, &state.dbpool)
+            .await
+            .map_err(|_| Error::NotFound("That trip does not exist".to_string()))?;
+        user.require(&trip, Permission::Edit, &state.dbpool).await?;
+    }
+    Ok(())
+}
+
+/// Check whether the user's permit policy allows adding a new sample of the given taxon
+async fn check_permit(
+    user: &SqliteUser,
+    tsn: i64,
+    state: &AppState,
+) -> Result<libseed::Result<()>, error::Error> {
+    let prefs = Preferences::load(user.id, &state.dbpool).await?;
+    if prefs.permit_policy == PermitPolicy::Ignore {
+        return Ok(Ok(()));
+    }
+    let unpermitted = conservation::unpermitted_listings(
+        user.id,
+        tsn,
+        prefs.region.as_deref(),
+        user.time_zone().now().date(),
+        &state.dbpool,
+    )
+    .await?;
+    Ok(prefs.permit_policy.check(&unpermitted))
+}
+
 async fn insert_sample(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
     Form(params): Form<SampleParams>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let sources = Source::load_all_user(user.id, &state.dbpool).await?;
+    let sources = user.scope().sources(None, &state.dbpool).await?;
+    let trips = user.scope().trips(None, &state.dbpool).await?;
+    let orgs = user.writable_orgs(&state.dbpool).await?;
+    user.require_org(params.org, &state.dbpool).await?;
+    if let Some(tsn) = params.taxon {
+        if let Err(e) = check_permit(&user, tsn, &state).await? {
+            return Ok(RenderHtml(
+                key,
+                state.tmpl.clone(),
+                context!(sources => sources,
+                         trips => trips,
+                         orgs => orgs,
+                         message => Message {
+                             r#type: MessageType::Error,
+                             msg: format!("Failed to save sample: {e}. Add the permit to your profile first."),
+                         },
+                         request => params),
+            )
+            .into_response());
+        }
+    }
     match do_insert(&user, &params, &state).await {
         Err(e) => Ok(RenderHtml(
             key,
             state.tmpl.clone(),
             context!(sources => sources,
+                         trips => trips,
+                         orgs => orgs,
                          message => Message {
                              r#type: MessageType::Error,
                              msg: format!("Failed to save sample: {}", e),
@@ -190,13 +611,15 @@ async fn insert_sample(
             let id = result.last_insert_rowid();
             let sample = Sample::load(id, &state.dbpool).await?;
 
-            let sampleurl = app_url(&format!("/sample/{}", sample.id));
+            let sampleurl = app_url(&format!("/sample/{}", sample.uuid));
             Ok((
                 [("HX-Redirect", sampleurl)],
                 RenderHtml(
                     key,
                     state.tmpl.clone(),
                     context!(sources => sources,
+                    trips => trips,
+                    orgs => orgs,
                     message => Message {
                         r#type: MessageType::Success,
                         msg: format!(
@@ -213,6 +636,7 @@ async fn insert_sample(
 }
 
 async fn do_update(
+    user: &SqliteUser,
     id: i64,
     params: &SampleParams,
     state: &AppState,
@@ -222,6 +646,8 @@ async fn do_update(
         _ => Certainty::Certain,
     };
     let mut sample = Sample::load(id, &state.dbpool).await?;
+    let before = sample.clone();
+    sample.orgid = params.org;
     sample.taxon = ExternalRef::Stub(params.taxon.ok_or_else(|| anyhow!("No taxon specified"))?);
     sample.source = ExternalRef::Stub(
         params
@@ -233,18 +659,46 @@ async fn do_update(
     sample.quantity = params.quantity;
     sample.notes = params.notes.as_ref().cloned();
     sample.certainty = certainty;
-    sample.update(&state.dbpool).await.map_err(|e| e.into())
+    sample.purchase = params.purchase()?;
+    if sample.trip != params.trip {
+        check_trip(user, params.trip, state).await?;
+        sample.trip = params.trip;
+    }
+    if let Some(version) = params.version {
+        sample.version = version;
+    }
+    let res = sample.update(&state.dbpool).await?;
+    history::record(&before, &sample, Some(user.id), &state.dbpool).await?;
+    Ok(res)
 }
 
 async fn update_sample(
     user: SqliteUser,
-    Path(id): Path<i64>,
+    Path(uuid): Path<Uuid>,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
     Form(params): Form<SampleParams>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let sources = Source::load_all_user(user.id, &state.dbpool).await?;
-    let (request, message, headers) = match do_update(id, &params, &state).await {
+    let sources = user.scope().sources(None, &state.dbpool).await?;
+    let trips = user.scope().trips(None, &state.dbpool).await?;
+    let orgs = user.writable_orgs(&state.dbpool).await?;
+    let sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+    let id = sample.id;
+    user.require(&sample, Permission::Edit, &state.dbpool)
+        .await?;
+    user.require_move(&sample, params.org, &state.dbpool)
+        .await?;
+    let res = do_update(&user, id, &params, &state).await;
+    let conflict = res.as_ref().is_err_and(|e| e.is_version_conflict());
+    let (request, message, headers) = match res {
+        Err(_) if conflict => (
+            Some(params),
+            Message {
+                r#type: MessageType::Warning,
+                msg: "This sample was modified by someone else while you were editing it. Review the saved values and submit again to replace them with your changes.".to_string(),
+            },
+            None,
+        ),
         Err(e) => (
             Some(params),
             Message {
@@ -259,7 +713,7 @@ async fn update_sample(
                 r#type: MessageType::Success,
                 msg: format!("Updated sample {}", id),
             },
-            Some([("HX-Redirect", app_url(&format!("/sample/{id}")))]),
+            Some([("HX-Redirect", app_url(&format!("/sample/{uuid}")))]),
         ),
     };
 
@@ -271,34 +725,279 @@ async fn update_sample(
             key,
             state.tmpl.clone(),
             context!(sources => sources,
+                     trips => trips,
+                     orgs => orgs,
                      sample => sample,
                      message => message,
-                     request => request),
+                     request => request,
+                     conflict => conflict),
         ),
     )
         .into_response())
 }
 
-async fn delete_sample(
+async fn show_history(
     user: SqliteUser,
-    Path(id): Path<i64>,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let mut sample = Sample::load(id, &state.dbpool).await?;
-    if sample.user.id() != user.id {
-        return Err(Error::Unauthorized(
-            "No permission to delete sample".to_string(),
-        ));
+    let sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&sample, Permission::View, &state.dbpool)
+        .await?;
+    let history = Change::load_sample(sample.id, &state.dbpool).await?;
+    let editable = user
+        .require(&sample, Permission::Edit, &state.dbpool)
+        .await
+        .is_ok();
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, sample => sample, history => history, editable => editable),
+    ))
+}
+
+/// Set a field of a sample back to the value it had before the given change
+async fn revert_change(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Path((uuid, changeid)): Path<(Uuid, i64)>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&sample, Permission::Edit, &state.dbpool)
+        .await?;
+    let change = Change::load(changeid, &state.dbpool)
+        .await
+        .ok()
+        .filter(|c| c.sampleid == sample.id)
+        .ok_or_else(|| Error::NotFound("That change does not exist".to_string()))?;
+    match change.revert(&mut sample, user.id, &state.dbpool).await {
+        Ok(()) => Ok([("HX-Redirect", app_url(&format!("/sample/{uuid}")))].into_response()),
+        Err(e) => Ok(error_alert_response(
+            &state,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!("Failed to revert the change: {e}"),
+        )
+        .into_response()),
+    }
+}
+
+/// The fields of a sample that can be edited in place on the sample page
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum InlineField {
+    Quantity,
+    Notes,
+    Date,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct InlineParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    version: Option<i64>,
+    quantity: Option<String>,
+    notes: Option<String>,
+    month: Option<String>,
+    year: Option<String>,
+}
+
+/// Parse a number entered in an inline editor. An empty value means that the field is cleared.
+fn parse_inline<T: FromStr>(value: &Option<String>, name: &str) -> Result<Option<T>, String> {
+    match value.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(v) => v
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("'{v}' is not a valid {name}")),
+    }
+}
+
+impl InlineParams {
+    fn value(&self, field: InlineField) -> Result<SampleField, String> {
+        Ok(match field {
+            InlineField::Quantity => {
+                SampleField::Quantity(parse_inline(&self.quantity, "quantity")?)
+            }
+            InlineField::Notes => SampleField::Notes(self.notes.clone()),
+            InlineField::Date => SampleField::Date {
+                month: parse_inline(&self.month, "month")?,
+                year: parse_inline(&self.year, "year")?,
+            },
+        })
+    }
+}
+
+/// Load a sample for showing one of its fields, including the seed weight of its taxon that is
+/// used to estimate the weight of the quantity
+async fn load_inline_sample(
+    user: &SqliteUser,
+    uuid: Uuid,
+    permission: Permission,
+    state: &AppState,
+) -> Result<Sample, error::Error> {
+    let mut sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&sample, permission, &state.dbpool).await?;
+    sample
+        .taxon
+        .object_mut()?
+        .load_seed_weight(&state.dbpool)
+        .await?;
+    Ok(sample)
+}
+
+async fn show_inline_field(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path((uuid, field)): Path<(Uuid, InlineField)>,
+) -> Result<impl IntoResponse, error::Error> {
+    let sample = load_inline_sample(&user, uuid, Permission::View, &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(sample => sample, field => field),
+    ))
+}
+
+async fn edit_inline_field(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path((uuid, field)): Path<(Uuid, InlineField)>,
+) -> Result<impl IntoResponse, error::Error> {
+    let sample = load_inline_sample(&user, uuid, Permission::Edit, &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(sample => sample, field => field),
+    ))
+}
+
+/// Save a single field of a sample from an inline editor. On success, the field is shown with its
+/// new value, otherwise the editor is shown again along with the error.
+async fn update_inline_field(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path((uuid, field)): Path<(Uuid, InlineField)>,
+    Form(params): Form<InlineParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut sample = load_inline_sample(&user, uuid, Permission::Edit, &state).await?;
+    if let Some(version) = params.version {
+        sample.version = version;
+    }
+    let before = sample.clone();
+    let res = match params.value(field) {
+        Ok(value) => sample
+            .update_field(value, &state.dbpool)
+            .await
+            .map_err(|e| match e {
+                libseed::Error::DatabaseVersionConflict(_) => {
+                    "This sample was modified by someone else in the meantime. Reload the page to see the saved values.".to_string()
+                }
+                e => format!("Failed to save sample: {e}"),
+            }),
+        Err(msg) => Err(msg),
+    };
+    if res.is_ok() {
+        history::record(&before, &sample, Some(user.id), &state.dbpool).await?;
+    }
+    let (message, request) = match res {
+        Ok(_) => (None, None),
+        Err(msg) => {
+            // show the current version so that the user can try again
+            sample = load_inline_sample(&user, uuid, Permission::Edit, &state).await?;
+            (
+                Some(Message {
+                    r#type: MessageType::Error,
+                    msg,
+                }),
+                Some(params),
+            )
+        }
+    };
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(sample => sample,
+                 field => field,
+                 editing => message.is_some(),
+                 message => message,
+                 request => request),
+    ))
+}
+
+/// Ask to confirm deleting a sample, for browsers without javascript
+async fn confirm_delete_sample(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(uuid): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&sample, Permission::Manage, &state.dbpool)
+        .await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, sample => sample),
+    ))
+}
+
+/// Delete a sample after the deletion was confirmed without javascript
+async fn delete_sample_confirmed(
+    user: SqliteUser,
+    Path(uuid): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&sample, Permission::Manage, &state.dbpool)
+        .await?;
+    let id = sample.id;
+    match sample.delete(&state.dbpool).await {
+        Ok(_) => Ok(Redirect::to(&app_url("/sample/list")).into_response()),
+        Err(e) => {
+            let sample = Sample::load(id, &state.dbpool).await?;
+            Ok((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                RenderHtml(
+                    "sample_@id_delete.html",
+                    state.tmpl.clone(),
+                    context!(user => user,
+                    sample => sample,
+                    message => Message {
+                        r#type: MessageType::Error,
+                        msg: format!("Error deleting sample: {e}"),
+                    }),
+                ),
+            )
+                .into_response())
+        }
     }
+}
+
+async fn delete_sample(
+    user: SqliteUser,
+    Path(uuid): Path<Uuid>,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&sample, Permission::Manage, &state.dbpool)
+        .await?;
+    let id = sample.id;
     match sample.delete(&state.dbpool).await {
         Err(e) => {
-            let sources = Source::load_all_user(user.id, &state.dbpool).await?;
+            let sources = user.scope().sources(None, &state.dbpool).await?;
+            let trips = user.scope().trips(None, &state.dbpool).await?;
+            let orgs = user.writable_orgs(&state.dbpool).await?;
             let sample = Sample::load(id, &state.dbpool).await?;
             Ok(RenderHtml(
                 key,
                 state.tmpl.clone(),
                 context!(sources => sources,
+                trips => trips,
+                orgs => orgs,
                 sample => sample,
                 message => Message {
                     r#type: MessageType::Error,
@@ -322,3 +1021,843 @@ async fn delete_sample(
             .into_response()),
     }
 }
+
+/// The samples that were selected in the list and that the user may delete, along with the
+/// selected samples that are skipped
+async fn selected_samples(
+    user: &SqliteUser,
+    params: &[(String, String)],
+    state: &AppState,
+) -> Result<(Vec<Sample>, Vec<BulkItem<Sample>>), Error> {
+    let mut samples = Vec::new();
+    let mut skipped = Vec::new();
+    for uuid in selected_uuids(params, "sample") {
+        match Sample::load_uuid(uuid, &state.dbpool).await {
+            Ok(sample) => match user
+                .require(&sample, Permission::Manage, &state.dbpool)
+                .await
+            {
+                Ok(()) => samples.push(sample),
+                Err(Error::Unauthorized(reason)) => {
+                    skipped.push(BulkItem::skipped(uuid, Some(sample), reason))
+                }
+                Err(e) => return Err(e),
+            },
+            Err(libseed::Error::DatabaseRowNotFound(_)) => {
+                skipped.push(BulkItem::skipped(uuid, None, "The sample does not exist"))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok((samples, skipped))
+}
+
+/// Summarize what would be removed along with the samples that were selected in the list, and ask
+/// to confirm deleting them
+async fn confirm_bulk_delete(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<impl IntoResponse, error::Error> {
+    let (samples, skipped) = selected_samples(&user, &params, &state).await?;
+    let ids: Vec<i64> = samples.iter().map(|s| s.id).collect();
+    let impact = DeleteImpact::load(&ids, &[], &state.dbpool).await?;
+    let taxon_names = DisplayName::load_map(user.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 samples => samples,
+                 skipped => skipped,
+                 impact => impact,
+                 taxon_names => taxon_names),
+    ))
+}
+
+/// Delete the selected samples in a single transaction and report the outcome for each of them
+async fn bulk_delete(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Form(params): Form<Vec<(String, String)>>,
+) -> Result<impl IntoResponse, error::Error> {
+    let (samples, skipped) = selected_samples(&user, &params, &state).await?;
+    let ids: Vec<i64> = samples.iter().map(|s| s.id).collect();
+    let results = deletion::delete_samples(&ids, &state.dbpool).await?;
+    let mut items: Vec<BulkItem<Sample>> = samples
+        .into_iter()
+        .zip(results)
+        .map(|(sample, result)| BulkItem::from_result(sample.uuid, sample, result))
+        .collect();
+    items.extend(skipped);
+    let ndeleted = items.iter().filter(|item| item.deleted).count();
+    debug!("Deleted {ndeleted} samples for user {}", user.id);
+    let taxon_names = DisplayName::load_map(user.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 items => items,
+                 ndeleted => ndeleted,
+                 taxon_names => taxon_names),
+    ))
+}
+
+async fn load_own_sample(user: &SqliteUser, uuid: Uuid, state: &AppState) -> Result<Sample, Error> {
+    let sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&sample, Permission::Edit, &state.dbpool)
+        .await?;
+    Ok(sample)
+}
+
+#[derive(Debug, Deserialize)]
+struct FlagParams {
+    reason: String,
+}
+
+async fn flag_sample(
+    user: SqliteUser,
+    Path(uuid): Path<Uuid>,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Form(params): Form<FlagParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let sample = load_own_sample(&user, uuid, &state).await?;
+    let message = match sample.flag(&params.reason, &state.dbpool).await {
+        Ok(_) => None,
+        Err(e) => Some(Message {
+            r#type: MessageType::Error,
+            msg: format!("Failed to flag sample: {}", e),
+        }),
+    };
+    let flags = sample.load_flags(&state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(sample => sample,
+                 flags => flags,
+                 message => message),
+    ))
+}
+
+async fn unflag_sample(
+    user: SqliteUser,
+    Path((uuid, flagid)): Path<(Uuid, i64)>,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let sample = load_own_sample(&user, uuid, &state).await?;
+    let flag = SampleFlag::load(flagid, &state.dbpool).await?;
+    if flag.sampleid != sample.id {
+        return Err(Error::NotFound(format!(
+            "Sample {} does not have flag {flagid}",
+            sample.id
+        )));
+    }
+    flag.delete(&state.dbpool).await?;
+    let flags = sample.load_flags(&state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(sample => sample,
+                 flags => flags),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct QualityTestParams {
+    date: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    fill: Option<f64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    method: Option<FillMethod>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    purity: Option<f64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    tester: Option<String>,
+}
+
+async fn add_quality_test(
+    user: SqliteUser,
+    Path(uuid): Path<Uuid>,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Form(params): Form<QualityTestParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let sample = load_own_sample(&user, uuid, &state).await?;
+    let result = match libseed::parse_date(&params.date) {
+        Ok(date) => {
+            let mut test = QualityTest::new(sample.id, date);
+            test.fill = params.fill;
+            test.method = params.method;
+            test.purity = params.purity;
+            test.tester = params.tester;
+            test.insert(&state.dbpool).await.map(|_| ())
+        }
+        Err(e) => Err(e),
+    };
+    let message = result.err().map(|e| Message {
+        r#type: MessageType::Error,
+        msg: format!("Failed to add quality test: {}", e),
+    });
+    let quality_tests = QualityTest::load_sample(sample.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(sample => sample,
+                 quality_tests => quality_tests,
+                 message => message),
+    ))
+}
+
+async fn delete_quality_test(
+    user: SqliteUser,
+    Path((uuid, testid)): Path<(Uuid, i64)>,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let sample = load_own_sample(&user, uuid, &state).await?;
+    let test = QualityTest::load(testid, &state.dbpool).await?;
+    if test.sampleid != sample.id {
+        return Err(Error::NotFound(format!(
+            "Sample {} does not have quality test {testid}",
+            sample.id
+        )));
+    }
+    test.delete(&state.dbpool).await?;
+    let quality_tests = QualityTest::load_sample(sample.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(sample => sample,
+                 quality_tests => quality_tests),
+    ))
+}
+
+/// Add a photo of a sample. The form has a `photo` file field and an optional `caption`.
+async fn add_photo(
+    user: SqliteUser,
+    Path(uuid): Path<Uuid>,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, error::Error> {
+    let sample = load_own_sample(&user, uuid, &state).await?;
+    let read_error = |e| anyhow!("Failed to read the upload: {e}");
+    let mut upload = None;
+    let mut caption = None;
+    while let Some(field) = multipart.next_field().await.map_err(read_error)? {
+        match field.name() {
+            Some("photo") => {
+                let content_type = field.content_type().unwrap_or_default().to_string();
+                let data = field.bytes().await.map_err(read_error)?;
+                upload = Some((content_type, data.to_vec()));
+            }
+            Some("caption") => caption = Some(field.text().await.map_err(read_error)?),
+            _ => {}
+        }
+    }
+    let result = match upload {
+        Some((content_type, data)) => Photo::new(sample.id, content_type, data, caption)
+            .insert(&state.dbpool)
+            .await
+            .map(|_| ()),
+        None => Err(libseed::Error::InvalidValue(
+            "No photo was uploaded".to_string(),
+        )),
+    };
+    let message = result.err().map(|e| Message {
+        r#type: MessageType::Error,
+        msg: format!("Failed to add photo: {}", e),
+    });
+    let photos = Photo::load_sample(sample.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(sample => sample,
+                 photos => photos,
+                 message => message),
+    ))
+}
+
+/// Load a photo of the given sample
+async fn load_sample_photo(
+    sample: &Sample,
+    photoid: i64,
+    state: &AppState,
+) -> Result<Photo, error::Error> {
+    let photo = Photo::load(photoid, &state.dbpool).await?;
+    if photo.sampleid != sample.id {
+        return Err(Error::NotFound(format!(
+            "Sample {} does not have photo {photoid}",
+            sample.id
+        )));
+    }
+    Ok(photo)
+}
+
+/// The image of a photo, as it was uploaded
+async fn show_photo(
+    user: SqliteUser,
+    Path((uuid, photoid)): Path<(Uuid, i64)>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let sample = Sample::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&sample, Permission::View, &state.dbpool)
+        .await?;
+    let photo = load_sample_photo(&sample, photoid, &state).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, photo.content_type),
+            // never let the browser treat the data as anything but the image it was checked to be
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (header::CONTENT_DISPOSITION, "inline".to_string()),
+            (header::CACHE_CONTROL, "private, max-age=86400".to_string()),
+        ],
+        photo.data.unwrap_or_default(),
+    ))
+}
+
+async fn delete_photo(
+    user: SqliteUser,
+    Path((uuid, photoid)): Path<(Uuid, i64)>,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let sample = load_own_sample(&user, uuid, &state).await?;
+    load_sample_photo(&sample, photoid, &state)
+        .await?
+        .delete(&state.dbpool)
+        .await?;
+    let photos = Photo::load_sample(sample.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(sample => sample,
+                 photos => photos),
+    ))
+}
+
+#[derive(Deserialize)]
+struct GradeParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    grade: Option<i64>,
+}
+
+/// Give the sample a grade of its owner's grading scale, or remove its grade
+async fn update_grade(
+    user: SqliteUser,
+    Path(uuid): Path<Uuid>,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Form(params): Form<GradeParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut sample = load_own_sample(&user, uuid, &state).await?;
+    let grades = QualityGrade::load_scale(sample.user.id(), &state.dbpool).await?;
+    let grade = match params.grade {
+        Some(id) => Some(
+            grades
+                .iter()
+                .find(|g| g.id == id)
+                .ok_or_else(|| Error::NotFound(format!("Grade {id} does not exist")))?,
+        ),
+        None => None,
+    };
+    sample.set_grade(grade, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(sample => sample,
+        grades => grades,
+        message => Message {
+            r#type: MessageType::Success,
+            msg: "Saved the grade".to_string(),
+        }),
+    ))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FlaggedParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    reason: Option<String>,
+}
+
+async fn list_flagged(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    query: Option<Query<FlaggedParams>>,
+) -> Result<impl IntoResponse, error::Error> {
+    let params = query.map(|q| q.0).unwrap_or_default();
+    let samples = user
+        .scope()
+        .samples(
+            Some(sample::Filter::Flagged(params.reason.clone()).into()),
+            None,
+            &state.dbpool,
+        )
+        .await?;
+    let flags = SampleFlag::load_all_user(user.id, &state.dbpool).await?;
+    let reasons = SampleFlag::reasons(user.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 samples => samples,
+                 flags => flags,
+                 reasons => reasons,
+                 reason => params.reason),
+    ))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct CalendarParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    source: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct CalendarMonth {
+    name: String,
+    weeks: usize,
+}
+
+/// How many weeks ahead the visits to monitored sources are listed on the calendar
+const CALENDAR_VISIT_WEEKS: i64 = 8;
+
+/// A calendar showing which taxa are typically ready for collection in each week of the year, to
+/// help with planning field trips. It also shows the phenophases that were observed on visits to
+/// monitored sources, and the visits that are coming up.
+async fn show_calendar(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    query: Option<Query<CalendarParams>>,
+) -> Result<impl IntoResponse, error::Error> {
+    let params = query.map(|q| q.0).unwrap_or_default();
+    let sources = user.scope().sources(None, &state.dbpool).await?;
+    let calendar = stats::collection_calendar(user.id, params.source, &state.dbpool).await?;
+    let months: Vec<CalendarMonth> = stats::calendar_months()
+        .into_iter()
+        .map(|(month, weeks)| CalendarMonth {
+            name: month.to_string(),
+            weeks,
+        })
+        .collect();
+    let today = user.time_zone().now().date();
+    let current_week = stats::calendar_week(today);
+    let phenology = monitoring::phenology_calendar(user.id, params.source, &state.dbpool).await?;
+    let visits = monitoring::upcoming_visits(
+        user.id,
+        params.source,
+        today,
+        today + Duration::weeks(CALENDAR_VISIT_WEEKS),
+        &state.dbpool,
+    )
+    .await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 sources => sources,
+                 source => params.source,
+                 calendar => calendar,
+                 phenology => phenology,
+                 visits => visits,
+                 months => months,
+                 current_week => current_week),
+    ))
+}
+
+/// How much seed was bought from each vendor per year
+async fn show_vendors(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let prefs = Preferences::load(user.id, &state.dbpool).await?;
+    let vendors = stats::vendor_summary(user.id, &prefs.collection_year()?, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 vendors => vendors,
+                 year_start => prefs.year_start_month),
+    ))
+}
+
+#[derive(Deserialize)]
+struct TaxonWarningParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    taxon: Option<String>,
+}
+
+/// Warnings about the taxon that is being entered in the sample form, e.g. because it is listed
+/// as invasive in the user's region
+async fn show_taxon_warnings(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Query(params): Query<TaxonWarningParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    // the taxon input may contain a partial name while the user is still typing
+    let taxon = match params.taxon.and_then(|t| t.parse::<i64>().ok()) {
+        Some(tsn) => Taxon::load(tsn, &state.dbpool).await.ok(),
+        None => None,
+    };
+    let listings = match taxon {
+        Some(ref t) => Listing::load_taxon(t.id, &state.dbpool).await?,
+        None => Vec::new(),
+    };
+    let prefs = Preferences::load(user.id, &state.dbpool).await?;
+    let invasive = taxon.filter(|t| t.invasive_in(prefs.region.as_deref()).is_some());
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(invasive => invasive, listings => listings),
+    ))
+}
+
+/// The number of recommendations that are shown for a planting site
+const SITE_MATCH_LIMIT: usize = 50;
+
+/// The steps of the site matching wizard
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum MatchStep {
+    #[default]
+    Location,
+    Conditions,
+    Results,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct MatchParams {
+    #[serde(default)]
+    step: MatchStep,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    latitude: Option<f64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    longitude: Option<f64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    habitat: Option<HabitatType>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    moisture: Option<SoilMoisture>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    light: Option<LightCondition>,
+}
+
+/// A wizard that asks for the location and the conditions of a planting site and then recommends
+/// the samples whose sources match the site best
+async fn match_site(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Query(params): Query<MatchParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let (mut matches, mut message) = (Vec::new(), None);
+    let mut step = params.step;
+    if step == MatchStep::Results {
+        let site = PlantingSite {
+            latitude: params.latitude,
+            longitude: params.longitude,
+            habitat: params.habitat,
+            moisture: params.moisture,
+            light: params.light,
+        };
+        match site
+            .recommend(user.id, SITE_MATCH_LIMIT, &state.dbpool)
+            .await
+        {
+            Ok(m) => matches = m,
+            Err(e @ libseed::Error::InvalidValue(_)) => {
+                message = Some(Message {
+                    r#type: MessageType::Error,
+                    msg: format!("Unable to match the site: {e}"),
+                });
+                step = MatchStep::Conditions;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 request => params,
+                 step => step,
+                 matches => matches,
+                 message => message),
+    ))
+}
+
+#[derive(Deserialize)]
+struct ForecastParams {
+    #[serde(default)]
+    include_invasive: bool,
+}
+
+/// Whether invasive taxa should be left out of the user's reports
+fn include_invasive(requested: bool, prefs: &Preferences) -> bool {
+    requested || !taxonomy::status_applies_to(prefs.region.as_deref())
+}
+
+/// A report estimating the yield that can be expected from each source in the coming season
+async fn show_forecast(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Query(params): Query<ForecastParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let prefs = Preferences::load(user.id, &state.dbpool).await?;
+    let forecasts = forecast::load(
+        user.id,
+        &prefs.collection_year()?,
+        include_invasive(params.include_invasive, &prefs),
+        &state.dbpool,
+    )
+    .await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 forecasts => forecasts,
+                 include_invasive => params.include_invasive,
+                 year_start => prefs.year_start_month),
+    ))
+}
+
+/// The yield forecast as a CSV file
+async fn export_forecast(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Query(params): Query<ForecastParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let prefs = Preferences::load(user.id, &state.dbpool).await?;
+    let years = prefs.collection_year()?;
+    let forecasts = forecast::load(
+        user.id,
+        &years,
+        include_invasive(params.include_invasive, &prefs),
+        &state.dbpool,
+    )
+    .await?;
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer
+        .write_record([
+            "Taxon",
+            "Source",
+            "Years Collected",
+            "First Year",
+            "Last Year",
+            "Last Quantity",
+            "Expected Quantity",
+            "Trend",
+        ])
+        .map_err(anyhow::Error::from)?;
+    for f in forecasts {
+        writer
+            .write_record([
+                f.taxon_name.clone(),
+                f.source_name.clone(),
+                f.history.len().to_string(),
+                f.first_year().map(|y| years.label(y)).unwrap_or_default(),
+                f.last_year().map(|y| years.label(y)).unwrap_or_default(),
+                f.history
+                    .last()
+                    .map(|(_, q)| q.to_string())
+                    .unwrap_or_default(),
+                format!("{:.0}", f.expected),
+                f.trend.to_string(),
+            ])
+            .map_err(anyhow::Error::from)?;
+    }
+    let data = writer.into_inner().map_err(|e| anyhow!("{e}"))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"yield-forecast.csv\"",
+            ),
+        ],
+        data,
+    ))
+}
+
+/// The size of the charts on the collection growth page, in SVG user units
+const CHART_WIDTH: f64 = 400.0;
+const CHART_HEIGHT: f64 = 100.0;
+
+/// A single statistic of the user's collection over time, drawn as a line chart
+#[derive(Debug, Serialize)]
+struct GrowthChart {
+    label: &'static str,
+    latest: i64,
+    max: i64,
+    /// the `x,y` coordinates of the line for an SVG polyline
+    points: String,
+}
+
+impl GrowthChart {
+    fn new(label: &'static str, history: &[Snapshot], value: fn(&Snapshot) -> i64) -> Self {
+        let max = history.iter().map(value).max().unwrap_or_default();
+        let (first, last) = match (history.first(), history.last()) {
+            (Some(first), Some(last)) => (first.date, last.date),
+            _ => {
+                return Self {
+                    label,
+                    latest: 0,
+                    max,
+                    points: String::new(),
+                }
+            }
+        };
+        let days = (last - first).whole_days().max(1) as f64;
+        let y = |s: &Snapshot| match max {
+            0 => CHART_HEIGHT,
+            _ => CHART_HEIGHT - value(s) as f64 * CHART_HEIGHT / max as f64,
+        };
+        let mut points: Vec<String> = history
+            .iter()
+            .map(|s| {
+                let x = (s.date - first).whole_days() as f64 * CHART_WIDTH / days;
+                format!("{x:.1},{:.1}", y(s))
+            })
+            .collect();
+        // a single snapshot is drawn as a flat line across the chart
+        if let [only] = history {
+            points.push(format!("{CHART_WIDTH:.1},{:.1}", y(only)));
+        }
+        Self {
+            label,
+            latest: history.last().map(value).unwrap_or_default(),
+            max,
+            points: points.join(" "),
+        }
+    }
+}
+
+/// Charts of how the user's collection grew over time, from the periodically recorded snapshots of
+/// its statistics
+async fn show_growth(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let history = stats::snapshot_history(user.id, &state.dbpool).await?;
+    let charts = [
+        GrowthChart::new("Samples", &history, |s| s.nsamples),
+        GrowthChart::new("Taxa", &history, |s| s.ntaxa),
+        GrowthChart::new("Sources", &history, |s| s.nsources),
+        GrowthChart::new("Total quantity", &history, |s| s.quantity),
+    ];
+    let snapshots: Vec<_> = history
+        .iter()
+        .rev()
+        .map(|s| {
+            context!(date => s.date.to_string(),
+                     nsamples => s.nsamples,
+                     ntaxa => s.ntaxa,
+                     nsources => s.nsources,
+                     quantity => s.quantity)
+        })
+        .collect();
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 charts => charts,
+                 snapshots => snapshots,
+                 width => CHART_WIDTH,
+                 height => CHART_HEIGHT),
+    ))
+}
+
+/// The recorded snapshots of the user's collection statistics as a CSV file
+async fn export_growth(
+    user: SqliteUser,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let history = stats::snapshot_history(user.id, &state.dbpool).await?;
+    let mut writer = csv::Writer::from_writer(vec![]);
+    writer
+        .write_record(["Date", "Samples", "Taxa", "Sources", "Quantity"])
+        .map_err(anyhow::Error::from)?;
+    for s in history {
+        writer
+            .write_record([
+                s.date.to_string(),
+                s.nsamples.to_string(),
+                s.ntaxa.to_string(),
+                s.nsources.to_string(),
+                s.quantity.to_string(),
+            ])
+            .map_err(anyhow::Error::from)?;
+    }
+    let data = writer.into_inner().map_err(|e| anyhow!("{e}"))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"collection-growth.csv\"",
+            ),
+        ],
+        data,
+    ))
+}
+
+/// A printable sheet with the packet labels of all samples that were added or modified since their
+/// label was last printed
+async fn show_labels(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let samples = user
+        .scope()
+        .samples(
+            Some(sample::Filter::LabelPending.into()),
+            Some(sample::Sort::Id.into()),
+            &state.dbpool,
+        )
+        .await?;
+    let taxon_names = DisplayName::load_map(user.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, samples => samples, taxon_names => taxon_names),
+    ))
+}
+
+async fn mark_labels_printed(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Form(params): Form<Vec<(String, String)>>,
+) -> Result<impl IntoResponse, error::Error> {
+    // only mark the labels that were on the sheet, in case more samples were queued since then
+    let printed: Vec<i64> = params
+        .iter()
+        .filter_map(|(name, value)| match name.as_str() {
+            "sample" => value.parse::<i64>().ok(),
+            _ => None,
+        })
+        .collect();
+    let ids: Vec<i64> = user
+        .scope()
+        .samples(
+            Some(sample::Filter::LabelPending.into()),
+            None,
+            &state.dbpool,
+        )
+        .await?
+        .iter()
+        .map(|s| s.id)
+        .filter(|id| printed.contains(id))
+        .collect();
+    let n = Sample::mark_labels_printed(&ids, &state.dbpool).await?;
+    debug!("Marked {n} labels as printed for user {}", user.id);
+    Ok(Redirect::to(&app_url("/sample/labels")))
+}
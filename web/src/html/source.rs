@@ -2,25 +2,35 @@ use crate::{app_url, auth::SqliteUser, Message, MessageType, TemplateKey};
 use anyhow::{anyhow, Context};
 use axum::{
     extract::{Path, Query, State},
-    http::HeaderMap,
-    response::IntoResponse,
-    routing::get,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect},
+    routing::{delete, get, post, put},
     Form, Router,
 };
 use axum_template::RenderHtml;
 use libseed::{
+    dataquality::Issue,
+    dedupe,
+    deletion::{self, DeleteImpact},
     empty_string_as_none,
     filter::{Cmp, CompoundFilter, Op},
     loadable::Loadable,
+    monitoring::{Phenophase, Schedule, Visit},
+    organization::{self, Permission},
+    parse_date,
+    reminder::{Reminder, ReminderTarget},
     sample::{Filter, Sample},
-    source,
-    source::Source,
+    source::{self, HabitatType, LightCondition, NearbySource, OnDelete, SoilMoisture, Source},
+    taxonomy::Taxon,
 };
 use minijinja::context;
 use serde::{Deserialize, Serialize};
 use sqlx::sqlite::SqliteQueryResult;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
+use time::Duration;
+use uuid::Uuid;
 
+use super::deletion::{selected_uuids, BulkItem};
 use crate::{error, state::AppState};
 
 pub fn router() -> Router<AppState> {
@@ -32,13 +42,33 @@ pub fn router() -> Router<AppState> {
             get(show_source).put(update_source).delete(delete_source),
         )
         .route("/:id/edit", get(show_source))
+        .route("/:id/monitoring", put(update_schedule))
+        .route("/:id/visits", post(add_visit))
+        .route("/:id/visits/:visitid", delete(delete_visit))
+        .route(
+            "/:id/delete",
+            get(confirm_delete_source).post(delete_source_confirmed),
+        )
         .route("/list", get(list_sources))
+        .route("/delete", get(confirm_bulk_delete).post(bulk_delete))
         .route("/list/options", get(list_sources))
+        .route("/near", get(find_nearby_sources))
+        .route("/dedupe", get(list_duplicates))
+        .route("/dedupe/merge", get(preview_merge).post(merge_sources))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 struct SourceListParams {
     filter: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    habitat: Option<HabitatType>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    moisture: Option<SoilMoisture>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    light: Option<LightCondition>,
+    /// only show sources with this data quality issue
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    issue: Option<Issue>,
 }
 
 async fn list_sources(
@@ -48,59 +78,367 @@ async fn list_sources(
     Query(params): Query<SourceListParams>,
     headers: HeaderMap,
 ) -> Result<impl IntoResponse, error::Error> {
-    let mut fbuilder = CompoundFilter::builder(Op::And).push(source::Filter::UserId(user.id));
+    let mut fbuilder = CompoundFilter::builder(Op::And);
 
-    if let Some(filterstring) = params.filter {
+    if let Some(filterstring) = &params.filter {
         let subfilter = CompoundFilter::builder(Op::Or)
             .push(source::Filter::Name(Cmp::Like, filterstring.clone()))
             .push(source::Filter::Description(Cmp::Like, filterstring.clone()))
             .build();
         fbuilder = fbuilder.push(subfilter);
     }
-    let sources = Source::load_all(Some(fbuilder.build()), &state.dbpool).await?;
+    if let Some(habitat) = params.habitat {
+        fbuilder = fbuilder.push(source::Filter::Habitat(habitat));
+    }
+    if let Some(moisture) = params.moisture {
+        fbuilder = fbuilder.push(source::Filter::Moisture(moisture));
+    }
+    if let Some(light) = params.light {
+        fbuilder = fbuilder.push(source::Filter::Light(light));
+    }
+    if let Some(issue) = params.issue {
+        let filter = issue.source_filter().ok_or_else(|| {
+            error::Error::NotFound(format!("'{issue}' is not a data quality issue of sources"))
+        })?;
+        fbuilder = fbuilder.push(filter);
+    }
+    let sources = user
+        .scope()
+        .sources(Some(fbuilder.build()), &state.dbpool)
+        .await?;
     Ok(RenderHtml(
         key,
         state.tmpl.clone(),
         context!(user => user,
                  sources => sources,
+                 issue_description => params.issue.map(|issue| issue.description()),
+                 params => params,
                  filteronly => headers.get("HX-Request").is_some()),
     )
     .into_response())
 }
 
+/// The default search radius in kilometers
+const DEFAULT_RADIUS_KM: f64 = 10.0;
+
+#[derive(Deserialize, Serialize)]
+struct NearbyParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    latitude: Option<f64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    longitude: Option<f64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    radius: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct NearbyResult {
+    #[serde(flatten)]
+    nearby: NearbySource,
+    samples: Vec<Sample>,
+}
+
+/// Search for the sources within a radius of a location, together with the user's samples from
+/// each of them
+async fn find_nearby_sources(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Query(params): Query<NearbyParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let radius = params.radius.unwrap_or(DEFAULT_RADIUS_KM);
+    let (mut results, mut message, mut map_viewer) = (Vec::new(), None, None);
+    if let (Some(latitude), Some(longitude)) = (params.latitude, params.longitude) {
+        match user
+            .scope()
+            .sources_near(latitude, longitude, radius, None, &state.dbpool)
+            .await
+        {
+            Ok(nearby) => {
+                for mut n in nearby {
+                    n.reveal_for(user.id, latitude, longitude, &state.dbpool)
+                        .await?;
+                    let samples = user
+                        .scope()
+                        .samples(
+                            Some(Arc::new(Filter::SourceId(Cmp::Equal, n.source.id))),
+                            None,
+                            &state.dbpool,
+                        )
+                        .await?;
+                    results.push(NearbyResult { nearby: n, samples });
+                }
+                // zoom out far enough to show the whole search radius
+                let zoom = (14.0 - radius.log2()).clamp(2.0, 16.0) as f32;
+                map_viewer = Some(source::map_viewer_uri(latitude, longitude, zoom));
+            }
+            Err(e @ libseed::Error::InvalidValue(_)) => {
+                message = Some(Message {
+                    r#type: MessageType::Error,
+                    msg: format!("Unable to search for sources: {e}"),
+                })
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 request => params,
+                 radius => radius,
+                 results => results,
+                 map_viewer => map_viewer,
+                 message => message),
+    ))
+}
+
 async fn add_source(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, error::Error> {
-    Ok(RenderHtml(key, state.tmpl.clone(), context!(user => user)).into_response())
+    let orgs = user.writable_orgs(&state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, orgs => orgs),
+    )
+    .into_response())
 }
 
 async fn show_source(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
-    Path(id): Path<i64>,
+    Path(uuid): Path<Uuid>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let src = Source::load(id, &state.dbpool).await?;
-    let samples = Sample::load_all_user(
-        user.id,
-        Some(Arc::new(Filter::SourceId(Cmp::Equal, id))),
-        None,
-        &state.dbpool,
-    )
-    .await?;
+    let mut src = Source::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&src, Permission::View, &state.dbpool).await?;
+    src.reveal_for(user.id, &state.dbpool).await?;
+    let id = src.id;
+    let orgs = user.writable_orgs(&state.dbpool).await?;
+    let samples = user
+        .scope()
+        .samples(
+            Some(Arc::new(Filter::SourceId(Cmp::Equal, id))),
+            None,
+            &state.dbpool,
+        )
+        .await?;
+    let reminders =
+        Reminder::load_target(user.id, ReminderTarget::Source(id), &state.dbpool).await?;
+    // only the users who look after a source monitor it
+    let monitoring =
+        match organization::has_permission(&src, user.id, Permission::Edit, &state.dbpool).await? {
+            true => Some(Monitoring::load(&user, &src, &samples, &state).await?),
+            false => None,
+        };
     Ok(RenderHtml(
         key,
         state.tmpl.clone(),
         context!(user => user,
                  source => src,
+                 orgs => orgs,
                  map_viewer => src.map_viewer_uri(12.0),
-                 samples => samples),
+                 samples => samples,
+                 reminders => reminders,
+                 monitoring => monitoring),
     )
     .into_response())
 }
 
+/// How many weeks ahead the upcoming visits to a monitored source are listed
+const MONITORING_WEEKS: i64 = 12;
+
+/// The monitoring schedule of a source and the visits that the user made to it
+#[derive(Serialize)]
+struct Monitoring {
+    schedule: Option<Schedule>,
+    /// the dates of the upcoming visits, and of today so that overdue visits can be highlighted
+    upcoming: Vec<String>,
+    today: String,
+    visits: Vec<Visit>,
+    /// the taxa that were collected at the source, whose populations can be observed on a visit
+    taxa: Vec<Taxon>,
+}
+
+impl Monitoring {
+    async fn load(
+        user: &SqliteUser,
+        src: &Source,
+        samples: &[Sample],
+        state: &AppState,
+    ) -> Result<Self, error::Error> {
+        let schedule = Schedule::load(user.id, src.id, &state.dbpool).await?;
+        let today = user.time_zone().now().date();
+        let upcoming = schedule
+            .as_ref()
+            .map(|s| s.upcoming(today, today + Duration::weeks(MONITORING_WEEKS)))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|d| d.to_string())
+            .collect();
+        let visits = Visit::load_source(user.id, src.id, &state.dbpool).await?;
+        let mut taxa: Vec<Taxon> = Vec::new();
+        for sample in samples {
+            if let Ok(taxon) = sample.taxon.object() {
+                if !taxa.iter().any(|t| t.id == taxon.id) {
+                    taxa.push(taxon.clone());
+                }
+            }
+        }
+        taxa.sort_by(|a, b| a.complete_name.cmp(&b.complete_name));
+        Ok(Self {
+            schedule,
+            upcoming,
+            today: today.to_string(),
+            visits,
+            taxa,
+        })
+    }
+}
+
+/// Load a source that the user looks after, i.e. that they are allowed to modify
+async fn load_monitored_source(
+    user: &SqliteUser,
+    uuid: Uuid,
+    state: &AppState,
+) -> Result<Source, error::Error> {
+    let src = Source::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&src, Permission::Edit, &state.dbpool).await?;
+    Ok(src)
+}
+
+/// Render the monitoring section of a source's page, e.g. after the schedule was changed
+async fn render_monitoring(
+    user: SqliteUser,
+    key: String,
+    src: Source,
+    message: Option<Message>,
+    state: &AppState,
+) -> Result<impl IntoResponse, error::Error> {
+    let samples = user
+        .scope()
+        .samples(
+            Some(Arc::new(Filter::SourceId(Cmp::Equal, src.id))),
+            None,
+            &state.dbpool,
+        )
+        .await?;
+    let monitoring = Monitoring::load(&user, &src, &samples, state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 source => src,
+                 monitoring => monitoring,
+                 message => message),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct ScheduleParams {
+    /// the number of weeks between visits. No interval stops monitoring the source.
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    interval: Option<i64>,
+    season_start: u8,
+    season_end: u8,
+}
+
+async fn update_schedule(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    Form(params): Form<ScheduleParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let src = load_monitored_source(&user, uuid, &state).await?;
+    let message = match Schedule::save(
+        user.id,
+        src.id,
+        params.interval,
+        (params.season_start, params.season_end),
+        &state.dbpool,
+    )
+    .await
+    {
+        Ok(schedule) => Message {
+            r#type: MessageType::Success,
+            msg: match schedule {
+                Some(_) => "Saved the monitoring schedule".to_string(),
+                None => "Stopped monitoring this source".to_string(),
+            },
+        },
+        Err(libseed::Error::InvalidValue(msg)) => Message {
+            r#type: MessageType::Error,
+            msg,
+        },
+        Err(e) => return Err(e.into()),
+    };
+    render_monitoring(user, key, src, Some(message), &state).await
+}
+
+#[derive(Debug, Deserialize)]
+struct VisitParams {
+    date: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    taxon: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    population: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    phenophase: Option<Phenophase>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    notes: Option<String>,
+}
+
+/// Record the outcome of a visit to a source
+async fn add_visit(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    Form(params): Form<VisitParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let src = load_monitored_source(&user, uuid, &state).await?;
+    let res = match parse_date(&params.date) {
+        Ok(date) => {
+            let mut visit = Visit::new(user.id, src.id, date);
+            visit.tsn = params.taxon;
+            visit.population = params.population;
+            visit.phenophase = params.phenophase;
+            visit.notes = params.notes;
+            visit.insert(&state.dbpool).await
+        }
+        Err(e) => Err(e),
+    };
+    let message = match res {
+        Ok(()) => None,
+        Err(libseed::Error::InvalidValue(msg)) => Some(Message {
+            r#type: MessageType::Error,
+            msg: format!("Failed to record the visit: {msg}"),
+        }),
+        Err(e) => return Err(e.into()),
+    };
+    render_monitoring(user, key, src, message, &state).await
+}
+
+async fn delete_visit(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path((uuid, visitid)): Path<(Uuid, i64)>,
+) -> Result<impl IntoResponse, error::Error> {
+    let src = load_monitored_source(&user, uuid, &state).await?;
+    let visit = Visit::load(visitid, &state.dbpool)
+        .await
+        .ok()
+        .filter(|v| v.userid == user.id && v.source == src.id)
+        .ok_or_else(|| error::Error::NotFound(format!("Visit {visitid} not found")))?;
+    visit.delete(&state.dbpool).await?;
+    render_monitoring(user, key, src, None, &state).await
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 struct SourceParams {
     #[serde(deserialize_with = "empty_string_as_none")]
@@ -112,6 +450,18 @@ struct SourceParams {
     #[serde(deserialize_with = "empty_string_as_none")]
     longitude: Option<f64>,
     modal: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    org: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    version: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    habitat: Option<HabitatType>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    moisture: Option<SoilMoisture>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    light: Option<LightCondition>,
+    #[serde(default)]
+    sensitive: bool,
 }
 
 async fn do_update(
@@ -120,6 +470,7 @@ async fn do_update(
     state: &AppState,
 ) -> Result<SqliteQueryResult, error::Error> {
     let mut src = Source::load(id, &state.dbpool).await?;
+    src.orgid = params.org;
     src.name = params
         .name
         .as_ref()
@@ -128,6 +479,13 @@ async fn do_update(
     src.description = params.description.as_ref().cloned();
     src.latitude = params.latitude;
     src.longitude = params.longitude;
+    src.habitat = params.habitat;
+    src.moisture = params.moisture;
+    src.light = params.light;
+    src.sensitive = params.sensitive;
+    if let Some(version) = params.version {
+        src.version = version;
+    }
 
     src.update(&state.dbpool).await.map_err(|e| e.into())
 }
@@ -136,14 +494,25 @@ async fn update_source(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
-    Path(id): Path<i64>,
+    Path(uuid): Path<Uuid>,
     Form(params): Form<SourceParams>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let src = Source::load(id, &state.dbpool).await?;
-    if src.userid != user.id {
-        return Err(error::Error::Unauthorized("Not yours".to_string()));
-    }
-    let (request, message, headers) = match do_update(id, &params, &state).await {
+    let src = Source::load_uuid(uuid, &state.dbpool).await?;
+    let id = src.id;
+    user.require(&src, Permission::Edit, &state.dbpool).await?;
+    user.require_move(&src, params.org, &state.dbpool).await?;
+    let orgs = user.writable_orgs(&state.dbpool).await?;
+    let res = do_update(id, &params, &state).await;
+    let conflict = res.as_ref().is_err_and(|e| e.is_version_conflict());
+    let (request, message, headers) = match res {
+        Err(_) if conflict => (
+            Some(&params),
+            Message {
+                r#type: MessageType::Warning,
+                msg: "This source was modified by someone else while you were editing it. Review the saved values and submit again to replace them with your changes.".to_string(),
+            },
+            None,
+        ),
         Err(e) => (
             Some(&params),
             Message {
@@ -158,17 +527,19 @@ async fn update_source(
                 r#type: MessageType::Success,
                 msg: "Successfully updated source".to_string(),
             },
-            Some([("HX-Redirect", app_url(&format!("/source/{id}")))]),
+            Some([("HX-Redirect", app_url(&format!("/source/{uuid}")))]),
         ),
     };
-    let samples = Sample::load_all_user(
-        user.id,
-        Some(Arc::new(Filter::SourceId(Cmp::Equal, id))),
-        None,
-        &state.dbpool,
-    )
-    .await?;
-    let src = Source::load(id, &state.dbpool).await?;
+    let samples = user
+        .scope()
+        .samples(
+            Some(Arc::new(Filter::SourceId(Cmp::Equal, id))),
+            None,
+            &state.dbpool,
+        )
+        .await?;
+    let mut src = Source::load(id, &state.dbpool).await?;
+    src.reveal_for(user.id, &state.dbpool).await?;
 
     Ok((
         headers,
@@ -176,8 +547,10 @@ async fn update_source(
             key,
             state.tmpl.clone(),
             context!(source => src,
+             orgs => orgs,
              message => message,
              request => request,
+             conflict => conflict,
              samples => samples
             ),
         )
@@ -189,7 +562,7 @@ async fn do_insert(
     user: &SqliteUser,
     params: &SourceParams,
     state: &AppState,
-) -> Result<SqliteQueryResult, error::Error> {
+) -> Result<Source, error::Error> {
     let mut source = Source::new(
         params
             .name
@@ -201,7 +574,13 @@ async fn do_insert(
         params.longitude,
         user.id,
     );
-    source.insert(&state.dbpool).await.map_err(|e| e.into())
+    source.orgid = params.org;
+    source.habitat = params.habitat;
+    source.moisture = params.moisture;
+    source.light = params.light;
+    source.sensitive = params.sensitive;
+    source.insert(&state.dbpool).await?;
+    Ok(source)
 }
 
 async fn new_source(
@@ -213,6 +592,8 @@ async fn new_source(
     let message;
     let mut request: Option<&SourceParams> = None;
     let mut headers = HeaderMap::new();
+    let orgs = user.writable_orgs(&state.dbpool).await?;
+    user.require_org(params.org, &state.dbpool).await?;
     match do_insert(&user, &params, &state).await {
         Err(e) => {
             message = Some(Message {
@@ -221,12 +602,11 @@ async fn new_source(
             });
             request = Some(&params)
         }
-        Ok(result) => {
-            let newid = result.last_insert_rowid();
-            let url = app_url(&format!("/source/{newid}"));
+        Ok(source) => {
+            let url = app_url(&format!("/source/{}", source.uuid));
             message = Some(Message {
                 r#type: MessageType::Success,
-                msg: format!("Successfully added source {newid}"),
+                msg: format!("Successfully added source {}", source.id),
             });
             if params.modal.is_some() {
                 headers.append(
@@ -250,24 +630,365 @@ async fn new_source(
             state.tmpl.clone(),
             context!(message => message,
             request => request,
+            orgs => orgs,
             ),
         ),
     )
         .into_response())
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum SampleAction {
+    Reassign,
+    Delete,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteParams {
+    /// what to do with the samples of the source. If not specified, a source that still has
+    /// samples is not deleted and the user is asked instead.
+    #[serde(default)]
+    samples: Option<SampleAction>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    reassign: Option<i64>,
+}
+
+/// Decide what happens to the samples of a source that is deleted, making sure that the user is
+/// allowed to change them
+async fn sample_action(
+    user: &SqliteUser,
+    id: i64,
+    params: &DeleteParams,
+    state: &AppState,
+) -> Result<OnDelete, error::Error> {
+    Ok(match (params.samples, params.reassign) {
+        (None, _) => OnDelete::Restrict,
+        (Some(SampleAction::Reassign), Some(newid)) => {
+            let target = Source::load(newid, &state.dbpool).await?;
+            user.require(&target, Permission::Edit, &state.dbpool)
+                .await?;
+            OnDelete::Reassign(newid)
+        }
+        (Some(SampleAction::Reassign), None) => {
+            return Err(anyhow!("No source specified for the samples").into())
+        }
+        (Some(SampleAction::Delete), _) => {
+            let samples = Sample::load_source(id, &state.dbpool).await?;
+            for sample in &samples {
+                user.require(sample, Permission::Manage, &state.dbpool)
+                    .await?;
+            }
+            OnDelete::Cascade
+        }
+    })
+}
+
+/// The sources of the user that the samples of a deleted source can be moved to
+async fn other_sources(
+    user: &SqliteUser,
+    id: i64,
+    state: &AppState,
+) -> Result<Vec<Source>, error::Error> {
+    Ok(user
+        .scope()
+        .sources(None, &state.dbpool)
+        .await?
+        .into_iter()
+        .filter(|s| s.id != id)
+        .collect())
+}
+
 async fn delete_source(
     user: SqliteUser,
-    Path(id): Path<i64>,
+    TemplateKey(key): TemplateKey,
+    Path(uuid): Path<Uuid>,
     State(state): State<AppState>,
+    Query(params): Query<DeleteParams>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let src = Source::load(id, &state.dbpool).await?;
-    if src.userid != user.id {
-        return Err(error::Error::Unauthorized("Not yours".to_string()));
+    let mut src = Source::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&src, Permission::Manage, &state.dbpool)
+        .await?;
+    let id = src.id;
+    let on_delete = sample_action(&user, id, &params, &state).await?;
+    match src.delete_with(on_delete, &state.dbpool).await {
+        Ok(_) => Ok([("HX-redirect", app_url("/source/list"))].into_response()),
+        Err(libseed::Error::InvalidOperationObjectInUse(nsamples)) => {
+            let sources = other_sources(&user, id, &state).await?;
+            Ok(RenderHtml(
+                key,
+                state.tmpl.clone(),
+                context!(source => src,
+                         nsamples => nsamples,
+                         sources => sources),
+            )
+            .into_response())
+        }
+        Err(e) => Err(e.into()),
     }
-    sqlx::query("DELETE FROM sc_sources WHERE srcid=?")
-        .bind(id)
-        .execute(&state.dbpool)
+}
+
+/// Ask to confirm deleting a source, for browsers without javascript. If the source is still in
+/// use, the user is also asked what should happen to its samples.
+async fn confirm_delete_source(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(uuid): Path<Uuid>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let src = Source::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&src, Permission::Manage, &state.dbpool)
+        .await?;
+    let nsamples = src.count_samples(&state.dbpool).await?;
+    let sources = other_sources(&user, src.id, &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 source => src,
+                 nsamples => nsamples,
+                 sources => sources),
+    ))
+}
+
+/// Delete a source after the deletion was confirmed without javascript
+async fn delete_source_confirmed(
+    user: SqliteUser,
+    Path(uuid): Path<Uuid>,
+    State(state): State<AppState>,
+    Form(params): Form<DeleteParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut src = Source::load_uuid(uuid, &state.dbpool).await?;
+    user.require(&src, Permission::Manage, &state.dbpool)
         .await?;
-    Ok([("HX-redirect", app_url("/source/list"))])
+    let id = src.id;
+    let on_delete = sample_action(&user, id, &params, &state).await?;
+    let message = match src.delete_with(on_delete, &state.dbpool).await {
+        Ok(_) => return Ok(Redirect::to(&app_url("/source/list")).into_response()),
+        Err(libseed::Error::InvalidOperationObjectInUse(_)) => {
+            "The source is still in use, choose what should happen to its samples".to_string()
+        }
+        Err(e) => format!("Error deleting source: {e}"),
+    };
+    let src = Source::load(id, &state.dbpool).await?;
+    let nsamples = src.count_samples(&state.dbpool).await?;
+    let sources = other_sources(&user, id, &state).await?;
+    Ok((
+        StatusCode::UNPROCESSABLE_ENTITY,
+        RenderHtml(
+            "source_@id_delete.html",
+            state.tmpl.clone(),
+            context!(user => user,
+            source => src,
+            nsamples => nsamples,
+            sources => sources,
+            message => Message {
+                r#type: MessageType::Error,
+                msg: message,
+            }),
+        ),
+    )
+        .into_response())
+}
+
+/// The sources that were selected in the list and that the user may delete, along with the
+/// selected sources that are skipped
+async fn selected_sources(
+    user: &SqliteUser,
+    params: &[(String, String)],
+    state: &AppState,
+) -> Result<(Vec<Source>, Vec<BulkItem<Source>>), error::Error> {
+    let mut sources = Vec::new();
+    let mut skipped = Vec::new();
+    for uuid in selected_uuids(params, "source") {
+        match Source::load_uuid(uuid, &state.dbpool).await {
+            Ok(src) => match user.require(&src, Permission::Manage, &state.dbpool).await {
+                Ok(()) => sources.push(src),
+                Err(error::Error::Unauthorized(reason)) => {
+                    skipped.push(BulkItem::skipped(uuid, Some(src), reason))
+                }
+                Err(e) => return Err(e),
+            },
+            Err(libseed::Error::DatabaseRowNotFound(_)) => {
+                skipped.push(BulkItem::skipped(uuid, None, "The source does not exist"))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok((sources, skipped))
+}
+
+/// Summarize what would be removed along with the sources that were selected in the list,
+/// including their samples, and ask to confirm deleting them
+async fn confirm_bulk_delete(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<impl IntoResponse, error::Error> {
+    let (sources, skipped) = selected_sources(&user, &params, &state).await?;
+    let ids: Vec<i64> = sources.iter().map(|s| s.id).collect();
+    let impact = DeleteImpact::load(&[], &ids, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 sources => sources,
+                 skipped => skipped,
+                 impact => impact),
+    ))
+}
+
+/// Delete the selected sources in a single transaction and report the outcome for each of them.
+/// The sources that still have samples are only deleted if their samples are deleted with them.
+async fn bulk_delete(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Form(params): Form<Vec<(String, String)>>,
+) -> Result<impl IntoResponse, error::Error> {
+    let (sources, skipped) = selected_sources(&user, &params, &state).await?;
+    let with_samples = params
+        .iter()
+        .any(|(name, value)| name == "samples" && value == "delete");
+    if with_samples {
+        // the user also needs to be allowed to delete the samples of the sources
+        for src in &sources {
+            sample_action(
+                &user,
+                src.id,
+                &DeleteParams {
+                    samples: Some(SampleAction::Delete),
+                    reassign: None,
+                },
+                &state,
+            )
+            .await?;
+        }
+    }
+    let ids: Vec<i64> = sources.iter().map(|s| s.id).collect();
+    let results = deletion::delete_sources(&ids, with_samples, &state.dbpool).await?;
+    let mut items: Vec<BulkItem<Source>> = sources
+        .into_iter()
+        .zip(results)
+        .map(|(src, result)| BulkItem::from_result(src.uuid, src, result))
+        .collect();
+    items.extend(skipped);
+    let ndeleted = items.iter().filter(|item| item.deleted).count();
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, items => items, ndeleted => ndeleted),
+    ))
+}
+
+/// List the pairs of sources that may be duplicates of each other. Only pairs where the user is
+/// allowed to delete both sources are shown, since merging a source deletes it.
+async fn list_duplicates(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut manageable = HashMap::new();
+    let mut candidates = Vec::new();
+    for mut candidate in user.scope().duplicate_sources(&state.dbpool).await? {
+        let mut allowed = true;
+        for src in [&candidate.first, &candidate.second] {
+            let permitted = match manageable.get(&src.id) {
+                Some(permitted) => *permitted,
+                None => {
+                    let permitted = organization::has_permission(
+                        src,
+                        user.id,
+                        Permission::Manage,
+                        &state.dbpool,
+                    )
+                    .await?;
+                    manageable.insert(src.id, permitted);
+                    permitted
+                }
+            };
+            allowed &= permitted;
+        }
+        if allowed {
+            candidate.first.reveal_for(user.id, &state.dbpool).await?;
+            candidate.second.reveal_for(user.id, &state.dbpool).await?;
+            candidates.push(candidate);
+        }
+    }
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 candidates => candidates),
+    )
+    .into_response())
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct MergeParams {
+    /// the source that is kept
+    keep: i64,
+    /// a comma-separated list of the sources that are merged into the kept source
+    remove: String,
+}
+
+impl MergeParams {
+    fn remove_ids(&self) -> Result<Vec<i64>, error::Error> {
+        self.remove
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(|id| {
+                id.parse()
+                    .map_err(|_| anyhow!("Invalid source id '{id}'").into())
+            })
+            .collect()
+    }
+
+    /// Fail unless the user may change the kept source and delete the merged sources
+    async fn require(&self, user: &SqliteUser, state: &AppState) -> Result<Vec<i64>, error::Error> {
+        let remove = self.remove_ids()?;
+        let keep = Source::load(self.keep, &state.dbpool).await?;
+        user.require(&keep, Permission::Edit, &state.dbpool).await?;
+        for id in &remove {
+            let src = Source::load(*id, &state.dbpool).await?;
+            user.require(&src, Permission::Manage, &state.dbpool)
+                .await?;
+        }
+        Ok(remove)
+    }
+}
+
+async fn preview_merge(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Query(params): Query<MergeParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let remove = params.require(&user, &state).await?;
+    let mut preview = dedupe::preview_merge(params.keep, &remove, &state.dbpool).await?;
+    preview.keep.reveal_for(user.id, &state.dbpool).await?;
+    for src in preview.remove.iter_mut() {
+        src.reveal_for(user.id, &state.dbpool).await?;
+    }
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 preview => preview,
+                 request => params),
+    )
+    .into_response())
+}
+
+async fn merge_sources(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Form(params): Form<MergeParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let remove = params.require(&user, &state).await?;
+    dedupe::merge(params.keep, &remove, &state.dbpool).await?;
+    let keep = Source::load(params.keep, &state.dbpool).await?;
+    Ok([("HX-Redirect", app_url(&format!("/source/{}", keep.uuid)))].into_response())
 }
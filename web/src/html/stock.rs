@@ -0,0 +1,22 @@
+//! A report of the samples whose quantity has fallen below the minimum that the user wants to keep
+use crate::{auth::SqliteUser, error::Error, state::AppState, TemplateKey};
+use axum::{extract::State, response::IntoResponse};
+use axum_template::RenderHtml;
+use libseed::{stock::LowStock, taxonomy::names::DisplayName};
+use minijinja::context;
+
+pub async fn show_low_stock(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    let items = LowStock::load_user(user.id, &state.dbpool).await?;
+    let taxon_names = DisplayName::load_map(user.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 items => items,
+                 taxon_names => taxon_names),
+    ))
+}
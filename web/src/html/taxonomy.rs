@@ -1,24 +1,27 @@
-use crate::{auth::SqliteUser, error, state::AppState, TemplateKey};
+use crate::{app_url, auth::SqliteUser, error, state::AppState, Message, MessageType, TemplateKey};
 use axum::{
-    extract::{Path, Query, Request, State},
-    response::IntoResponse,
-    routing::get,
+    extract::{Path, Query, State},
+    response::{IntoResponse, Redirect},
+    routing::{get, put},
     Form, Router,
 };
 use axum_template::RenderHtml;
 use libseed::loadable::Loadable;
 use libseed::{
+    conservation::Listing,
+    cultivation::{CultivationNotes, Scope},
     empty_string_as_none,
     filter::{Cmp, CompoundFilter, LimitSpec, Op},
+    organization::{Organization, Permission},
+    photo,
     sample::{self, Sample},
-    taxonomy::{self, any_filter, Germination, Rank, Taxon},
+    stock::{Threshold, ThresholdTarget},
+    taxonomy::{self, names::DisplayName, Germination, Rank, Taxon, TaxonCursor},
 };
 use minijinja::context;
-use serde::Deserialize;
-use sqlx::Row;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use strum::IntoEnumIterator;
-use tracing::debug;
 
 pub fn router() -> Router<AppState> {
     Router::new()
@@ -26,65 +29,46 @@ pub fn router() -> Router<AppState> {
         .route("/list", get(list_taxa))
         .route("/:id", get(show_taxon))
         .route("/:id/samples", get(show_all_children))
+        .route("/:id/notes", get(show_notes).put(update_notes))
+        .route("/:id/notes/edit", get(edit_notes))
+        .route("/:id/names", get(show_names).put(update_names))
+        .route("/:id/names/edit", get(edit_names))
+        .route("/:id/threshold", put(update_threshold))
+        .route("/names", get(list_names))
         .route("/datalist", get(datalist))
         .route("/search", get(search))
         .route("/editgerm", get(editgerm).post(addgerm))
 }
 
+#[derive(Deserialize)]
+struct RootParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    rank: Option<Rank>,
+}
+
 async fn root(
     user: SqliteUser,
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
+    Query(params): Query<RootParams>,
 ) -> Result<impl IntoResponse, error::Error> {
     let ranks: Vec<Rank> = Rank::iter().collect();
     Ok(RenderHtml(
         key,
         state.tmpl.clone(),
-        context!(user => user, ranks => ranks),
+        context!(user => user, ranks => ranks, selected_rank => params.rank),
     ))
 }
 
-const PAGE_SIZE: i32 = 100;
 #[derive(Deserialize)]
 struct ListParams {
     rank: Option<Rank>,
-    page: Option<i32>,
 }
 
-async fn list_taxa(
-    user: SqliteUser,
-    TemplateKey(key): TemplateKey,
-    State(state): State<AppState>,
-    Query(params): Query<ListParams>,
-    req: Request,
-) -> Result<impl IntoResponse, error::Error> {
-    let rank = match params.rank {
-        Some(r) => r,
-        None => Rank::Species,
-    };
-    let pg = params.page.unwrap_or(1);
-    let row = taxonomy::count_query(Some(taxonomy::Filter::Rank(rank.clone()).into()))
-        .build()
-        .fetch_one(&state.dbpool)
-        .await?;
-    let count = row.try_get::<i32, _>("count")?;
-    let total_pages = (count + PAGE_SIZE - 1) / PAGE_SIZE;
-    let taxa: Vec<Taxon> = Taxon::load_all(
-        Some(taxonomy::Filter::Rank(rank).into()),
-        Some(LimitSpec(PAGE_SIZE, Some(PAGE_SIZE * (pg - 1)))),
-        &state.dbpool,
-    )
-    .await?;
-    debug!("req={:?}", req);
-    Ok(RenderHtml(
-        key,
-        state.tmpl.clone(),
-        context!(user => user,
-                 taxa => taxa,
-                 page => pg,
-                 total_pages => total_pages,
-                 request_uri => req.uri().to_string()),
-    ))
+/// Browsing the taxa of a rank is now done on the find page, which loads more taxa while scrolling
+async fn list_taxa(Query(params): Query<ListParams>) -> impl IntoResponse {
+    let rank = params.rank.unwrap_or(Rank::Species);
+    Redirect::to(&app_url(&format!("/taxonomy/?rank={rank}")))
 }
 
 async fn show_all_children(
@@ -150,11 +134,12 @@ async fn show_all_children(
             INNER JOIN sc_sources L on L.srcid=S.srcid
             INNER JOIN sc_users U on U.userid=S.userid
             LEFT JOIN mntaxa M on CTE.tsn=M.tsn 
-            WHERE S.userid=?
+            WHERE (S.userid=? OR S.sampleorgid IN (SELECT orgid FROM sc_org_members WHERE userid=?))
             ORDER BY seq"#,
     )
     .bind(id)
     .bind(user.id)
+    .bind(user.id)
     .fetch_all(&state.dbpool)
     .await?;
     Ok(RenderHtml(
@@ -174,27 +159,301 @@ async fn show_taxon(
     let mut taxon = Taxon::load(id, &state.dbpool).await?;
     let hierarchy = taxon.fetch_hierarchy(&state.dbpool).await?;
     let children = taxon.fetch_children(&state.dbpool).await?;
-    let samples = Sample::load_all_user(
-        user.id,
-        Some(Arc::new(sample::Filter::TaxonId(Cmp::Equal, id))),
-        None,
-        &state.dbpool,
-    )
-    .await?;
+    let samples = user
+        .scope()
+        .samples(
+            Some(Arc::new(sample::Filter::TaxonId(Cmp::Equal, id))),
+            None,
+            &state.dbpool,
+        )
+        .await?;
     taxon.load_germination_info(&state.dbpool).await?;
+    taxon.load_seed_weight(&state.dbpool).await?;
+    let listings = Listing::load_taxon(id, &state.dbpool).await?;
+    let notes = CultivationNotes::load_taxon(id, user.id, &state.dbpool).await?;
+    let scopes = edit_scopes(&user, "Personal notes", &state).await?;
+    let names = DisplayName::load_taxon(id, user.id, &state.dbpool).await?;
+    let name_scopes = edit_scopes(&user, "Personal name", &state).await?;
+    let taxon_names = DisplayName::load_map(user.id, &state.dbpool).await?;
+    let threshold = Threshold::load(user.id, ThresholdTarget::Taxon(id), &state.dbpool).await?;
+    let photos = photo::load_gallery(id, user.id, &state.dbpool).await?;
 
     Ok(RenderHtml(
         key,
         state.tmpl.clone(),
         context!(user => user,
                  taxon => taxon,
+                 threshold => threshold,
+                 listings => listings,
+                 notes => notes,
+                 scopes => scopes,
+                 names => names,
+                 name_scopes => name_scopes,
+                 taxon_names => taxon_names,
                  parents => hierarchy,
                  children => children,
-                 samples => samples),
+                 samples => samples,
+                 photos => photos),
     )
     .into_response())
 }
 
+/// A set of cultivation notes or a display name that the user can edit
+#[derive(Serialize)]
+struct EditScope {
+    org: Option<i64>,
+    name: String,
+}
+
+/// The user's personal scope (labeled with `personal`) and the scope of each organization whose
+/// objects they can edit
+async fn edit_scopes(
+    user: &SqliteUser,
+    personal: &str,
+    state: &AppState,
+) -> Result<Vec<EditScope>, error::Error> {
+    let mut scopes = vec![EditScope {
+        org: None,
+        name: personal.to_string(),
+    }];
+    for (org, role) in Organization::load_all_user(user.id, &state.dbpool).await? {
+        if role.permits(Permission::Edit) {
+            scopes.push(EditScope {
+                org: Some(org.id),
+                name: org.name,
+            });
+        }
+    }
+    Ok(scopes)
+}
+
+async fn show_notes(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, error::Error> {
+    let taxon = Taxon::load(id, &state.dbpool).await?;
+    let notes = CultivationNotes::load_taxon(id, user.id, &state.dbpool).await?;
+    let scopes = edit_scopes(&user, "Personal notes", &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, taxon => taxon, notes => notes, scopes => scopes),
+    ))
+}
+
+#[derive(Deserialize)]
+struct NoteScopeParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    org: Option<i64>,
+}
+
+/// The scope of the notes or name that the user wants to edit, making sure that they are allowed
+/// to
+async fn load_scope(
+    user: &SqliteUser,
+    org: Option<i64>,
+    state: &AppState,
+) -> Result<(Scope, Option<Organization>), error::Error> {
+    match org {
+        None => Ok((Scope::Personal(user.id), None)),
+        Some(orgid) => {
+            let allowed = Organization::role(orgid, user.id, &state.dbpool)
+                .await?
+                .is_some_and(|role| role.permits(Permission::Edit));
+            if !allowed {
+                return Err(error::Error::Unauthorized(
+                    "No permission to edit the taxa of this organization".to_string(),
+                ));
+            }
+            let org = Organization::load(orgid, &state.dbpool).await?;
+            Ok((Scope::Organization(orgid), Some(org)))
+        }
+    }
+}
+
+async fn edit_notes(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(params): Query<NoteScopeParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let taxon = Taxon::load(id, &state.dbpool).await?;
+    let (scope, org) = load_scope(&user, params.org, &state).await?;
+    let notes = CultivationNotes::load_scope(id, scope, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, taxon => taxon, org => org, notes => notes),
+    ))
+}
+
+#[derive(Deserialize)]
+struct NotesParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    org: Option<i64>,
+    text: String,
+}
+
+async fn update_notes(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(params): Form<NotesParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let taxon = Taxon::load(id, &state.dbpool).await?;
+    let (scope, _) = load_scope(&user, params.org, &state).await?;
+    CultivationNotes::save(id, scope, &params.text, user.id, &state.dbpool).await?;
+    let notes = CultivationNotes::load_taxon(id, user.id, &state.dbpool).await?;
+    let scopes = edit_scopes(&user, "Personal notes", &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+        taxon => taxon,
+        notes => notes,
+        scopes => scopes,
+        message => Message {
+            r#type: MessageType::Success,
+            msg: "Saved the cultivation notes".to_string(),
+        }),
+    ))
+}
+
+/// All display names that the user can see, so that they can be managed in one place
+async fn list_names(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let names = DisplayName::load_all_user(user.id, &state.dbpool).await?;
+    let mut taxa = Vec::new();
+    for name in &names {
+        if !taxa.iter().any(|t: &Taxon| t.id == name.tsn) {
+            taxa.push(Taxon::load(name.tsn, &state.dbpool).await?);
+        }
+    }
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, taxa => taxa, names => names),
+    ))
+}
+
+async fn show_names(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, error::Error> {
+    let taxon = Taxon::load(id, &state.dbpool).await?;
+    let names = DisplayName::load_taxon(id, user.id, &state.dbpool).await?;
+    let scopes = edit_scopes(&user, "Personal name", &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, taxon => taxon, names => names, scopes => scopes),
+    ))
+}
+
+async fn edit_names(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(params): Query<NoteScopeParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let taxon = Taxon::load(id, &state.dbpool).await?;
+    let (scope, org) = load_scope(&user, params.org, &state).await?;
+    let name = DisplayName::load_scope(id, scope, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, taxon => taxon, org => org, name => name),
+    ))
+}
+
+#[derive(Deserialize)]
+struct NameParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    org: Option<i64>,
+    name: String,
+}
+
+async fn update_names(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(params): Form<NameParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let taxon = Taxon::load(id, &state.dbpool).await?;
+    let (scope, _) = load_scope(&user, params.org, &state).await?;
+    DisplayName::save(id, scope, &params.name, user.id, &state.dbpool).await?;
+    let names = DisplayName::load_taxon(id, user.id, &state.dbpool).await?;
+    let scopes = edit_scopes(&user, "Personal name", &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+        taxon => taxon,
+        names => names,
+        scopes => scopes,
+        message => Message {
+            r#type: MessageType::Success,
+            msg: "Saved the display name".to_string(),
+        }),
+    ))
+}
+
+#[derive(Deserialize)]
+struct ThresholdParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    quantity: Option<i64>,
+}
+
+/// Set the user's minimum quantity of each sample of a taxon, below which the samples are shown as
+/// low on stock
+async fn update_threshold(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(params): Form<ThresholdParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let taxon = Taxon::load(id, &state.dbpool).await?;
+    let target = ThresholdTarget::Taxon(id);
+    let (threshold, message) =
+        match Threshold::save(user.id, target, params.quantity, &state.dbpool).await {
+            Ok(threshold) => (
+                threshold,
+                Message {
+                    r#type: MessageType::Success,
+                    msg: "Saved the minimum quantity".to_string(),
+                },
+            ),
+            Err(libseed::Error::InvalidValue(msg)) => (
+                Threshold::load(user.id, target, &state.dbpool).await?,
+                Message {
+                    r#type: MessageType::Error,
+                    msg,
+                },
+            ),
+            Err(e) => return Err(e.into()),
+        };
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 taxon => taxon,
+                 threshold => threshold,
+                 message => message),
+    ))
+}
+
 #[derive(Deserialize)]
 struct DatalistParams {
     taxon: String,
@@ -205,57 +464,72 @@ async fn datalist(
     State(state): State<AppState>,
     Query(DatalistParams { taxon }): Query<DatalistParams>,
 ) -> Result<impl IntoResponse, error::Error> {
-    quickfind(key, &state, taxon, None, None).await
+    quickfind(key, &state, taxon).await
 }
 
+/// The number of taxa that are loaded at a time while scrolling through the search results. It is
+/// even so that the alternating row colors continue across pages.
+const SEARCH_PAGE_SIZE: i32 = 100;
+
 #[derive(Deserialize)]
 struct SearchParams {
+    #[serde(default)]
     taxon: String,
-    #[serde(deserialize_with = "empty_string_as_none")]
+    #[serde(default, deserialize_with = "empty_string_as_none")]
     rank: Option<Rank>,
     minnesota: Option<bool>,
+    /// continue the results after this cursor rather than starting from the beginning
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    after: Option<TaxonCursor>,
 }
 
+/// Find taxa by name, rank or region. The results are loaded a page at a time in taxonomic order
+/// as the user scrolls, and a query without a name browses all taxa. Since the best matches for a
+/// name can't be sorted first across pages, they are sorted first within each page.
 async fn search(
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
-    Query(SearchParams {
-        taxon,
-        rank,
-        minnesota,
-    }): Query<SearchParams>,
+    Query(params): Query<SearchParams>,
 ) -> Result<impl IntoResponse, error::Error> {
-    quickfind(key, &state, taxon, rank, minnesota).await
+    let mut filter = CompoundFilter::builder(Op::And);
+    if let Some(query) = taxonomy::quickfind(params.taxon.clone()) {
+        filter = filter.push(query);
+    }
+    if let Some(rank) = params.rank {
+        filter = filter.push(taxonomy::Filter::Rank(rank));
+    }
+    if Some(true) == params.minnesota {
+        filter = filter.push(taxonomy::Filter::Minnesota(true));
+    }
+    let mut page = Taxon::load_page(
+        Some(filter.build()),
+        params.after,
+        SEARCH_PAGE_SIZE,
+        &state.dbpool,
+    )
+    .await?;
+    taxonomy::rank_quickfind_results(&params.taxon, &mut page.taxa);
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(taxa => page.taxa,
+                 next => page.next.map(|c| c.to_string()),
+                 after => params.after.is_some()),
+    ))
 }
 
 async fn quickfind(
     key: String,
     state: &AppState,
     taxon: String,
-    rank: Option<Rank>,
-    minnesota: Option<bool>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let taxa: Vec<Taxon> = match taxon.is_empty() {
-        true => Vec::new(),
-        false => {
-            let parts = taxon.split(' ');
-            let mut filter = CompoundFilter::builder(Op::And);
-            for part in parts {
-                filter = filter.push(any_filter(part));
-            }
-            if let Some(rank) = rank {
-                filter = filter.push(taxonomy::Filter::Rank(rank));
-            }
-            if Some(true) == minnesota {
-                filter = filter.push(taxonomy::Filter::Minnesota(true));
-            }
-            /* FIXME: pagination for /search endpoing? */
-            Taxon::load_all(
-                Some(filter.build()),
-                Some(LimitSpec(200, None)),
-                &state.dbpool,
-            )
-            .await?
+    let taxa: Vec<Taxon> = match taxonomy::quickfind(taxon.clone()) {
+        None => Vec::new(),
+        Some(query) => {
+            let mut taxa =
+                Taxon::load_all(Some(query), Some(LimitSpec(200, None)), &state.dbpool).await?;
+            taxonomy::rank_quickfind_results(&taxon, &mut taxa);
+            taxa
         }
     };
     Ok(RenderHtml(key, state.tmpl.clone(), context!(taxa => taxa)))
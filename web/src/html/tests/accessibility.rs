@@ -0,0 +1,249 @@
+//! An automated check of the main pages for common accessibility problems: form controls without
+//! a label, images without alternative text, links and buttons without an accessible name, and
+//! references to elements that don't exist.
+use super::*;
+use axum::http::header::CONTENT_TYPE;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashSet;
+use test_log::test;
+
+/// Types of `<input>` elements that are not labelled by a `<label>`
+const UNLABELLED_INPUTS: [&str; 5] = ["hidden", "submit", "button", "reset", "image"];
+
+fn select<'a>(document: &'a Html, selector: &str) -> Vec<ElementRef<'a>> {
+    let selector = Selector::parse(selector).expect("Invalid selector");
+    document.select(&selector).collect()
+}
+
+/// Whether the element has an accessible name that doesn't come from its content
+fn has_aria_name(element: &ElementRef) -> bool {
+    ["aria-label", "aria-labelledby", "title"]
+        .iter()
+        .any(|attr| {
+            element
+                .value()
+                .attr(attr)
+                .is_some_and(|v| !v.trim().is_empty())
+        })
+}
+
+/// A short description of an element for the failure messages
+fn describe(element: &ElementRef) -> String {
+    let attrs: Vec<String> = element
+        .value()
+        .attrs()
+        .filter(|(name, _)| ["id", "name", "type", "class", "href"].contains(name))
+        .map(|(name, value)| format!("{name}=\"{value}\""))
+        .collect();
+    format!("<{} {}>", element.value().name(), attrs.join(" "))
+}
+
+/// Check the html of a page and return the problems that were found
+fn check_page(html: &str) -> Vec<String> {
+    let document = Html::parse_document(html);
+    let mut problems = Vec::new();
+
+    if select(&document, "html[lang]").is_empty() {
+        problems.push("the language of the page is not set".to_string());
+    }
+    if select(&document, "main").len() != 1 {
+        problems.push("the page doesn't have exactly one <main> landmark".to_string());
+    }
+    if select(&document, "[aria-live]").is_empty() {
+        problems.push("the page has no live region for messages".to_string());
+    }
+
+    let mut ids = HashSet::new();
+    for element in select(&document, "[id]") {
+        let id = element.value().id().unwrap_or_default();
+        if !ids.insert(id.to_string()) {
+            problems.push(format!("the id '{id}' is used more than once"));
+        }
+    }
+    let labelled: HashSet<&str> = select(&document, "label[for]")
+        .iter()
+        .filter_map(|label| label.value().attr("for"))
+        .collect();
+    for control in select(&document, "input, select, textarea") {
+        if control
+            .value()
+            .attr("type")
+            .is_some_and(|t| UNLABELLED_INPUTS.contains(&t))
+        {
+            continue;
+        }
+        let in_label = control
+            .ancestors()
+            .filter_map(ElementRef::wrap)
+            .any(|e| e.value().name() == "label");
+        let for_label = control.value().id().is_some_and(|id| labelled.contains(id));
+        if !(in_label || for_label || has_aria_name(&control)) {
+            problems.push(format!("{} has no label", describe(&control)));
+        }
+    }
+    for image in select(&document, "img:not([alt])") {
+        problems.push(format!("{} has no alternative text", describe(&image)));
+    }
+    for element in select(&document, "a[href], button") {
+        let text: String = element.text().collect();
+        if text.trim().is_empty() && !has_aria_name(&element) {
+            problems.push(format!("{} has no accessible name", describe(&element)));
+        }
+    }
+    for attr in ["aria-describedby", "aria-labelledby", "aria-controls"] {
+        for element in select(&document, &format!("[{attr}]")) {
+            for id in element
+                .value()
+                .attr(attr)
+                .unwrap_or_default()
+                .split_whitespace()
+            {
+                if !ids.contains(id) {
+                    problems.push(format!(
+                        "{} refers to the missing element '{id}' in {attr}",
+                        describe(&element)
+                    ));
+                }
+            }
+        }
+    }
+    problems
+}
+
+#[test]
+fn test_check_page() {
+    let good = r#"<!DOCTYPE html><html lang="en"><body><main>
+        <div aria-live="polite"></div>
+        <label for="name">Name</label><input id="name" name="name">
+        <label>Notes <textarea name="notes"></textarea></label>
+        <input type="hidden" name="csrf">
+        <input type="search" name="q" aria-label="Search" aria-describedby="help">
+        <p id="help">Search by name</p>
+        <button aria-label="Delete"><i class="bi-trash"></i></button>
+        <img src="x.png" alt="">
+        </main></body></html>"#;
+    assert_eq!(check_page(good), Vec::<String>::new());
+
+    let bad = r#"<!DOCTYPE html><html><body>
+        <input id="name" name="name"><select id="name"></select>
+        <a href="/"><i class="bi-house"></i></a>
+        <img src="x.png">
+        <input name="q" aria-describedby="missing">
+        </body></html>"#;
+    let problems = check_page(bad);
+    assert_eq!(problems.len(), 10, "{problems:#?}");
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_main_pages_accessibility(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+
+    let mut pages = vec![("/auth/login".to_string(), None), ("/".to_string(), None)];
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let sample = sample_path(1, &pool).await;
+    let source = source_path(1, &pool).await;
+    let project = project_path(1, &pool).await;
+    for path in [
+        "/".to_string(),
+        "/sample/list".to_string(),
+        "/sample/new".to_string(),
+        "/sample/import".to_string(),
+        sample.clone(),
+        format!("{sample}/edit"),
+        format!("{sample}/delete"),
+        "/source/list".to_string(),
+        "/source/new".to_string(),
+        source.clone(),
+        format!("{source}/edit"),
+        "/project/list".to_string(),
+        "/project/new".to_string(),
+        project.clone(),
+        format!("{project}/edit"),
+        format!("{project}/add"),
+        "/taxonomy/".to_string(),
+        "/taxonomy/40683".to_string(),
+        "/trip/list".to_string(),
+        "/notes".to_string(),
+        "/dataquality".to_string(),
+        "/taxonomy/names".to_string(),
+        "/stock".to_string(),
+        "/report/list".to_string(),
+        "/org/list".to_string(),
+        "/user/me".to_string(),
+    ] {
+        pages.push((path, Some(cookie.clone())));
+    }
+
+    let mut problems = Vec::new();
+    for (path, cookie) in pages {
+        let mut req = Request::builder().uri(app_url(&path)).method("GET");
+        if let Some(cookie) = cookie {
+            req = req.header("Cookie", cookie);
+        }
+        let req = req.body(Body::empty()).expect("Failed to build request");
+        let response = app
+            .as_service()
+            .call(req)
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK, "{path}");
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        let html = String::from_utf8(bytes.to_vec()).expect("Body is not utf8");
+        problems.extend(
+            check_page(&html)
+                .into_iter()
+                .map(|problem| format!("{path}: {problem}")),
+        );
+    }
+
+    // a form that is shown again with an error refers to the error message
+    let req = Request::builder()
+        .uri(app_url(&format!("{source}/delete")))
+        .method("POST")
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = String::from_utf8(bytes.to_vec()).expect("Body is not utf8");
+    let document = Html::parse_document(&html);
+    assert_eq!(
+        select(
+            &document,
+            "form[aria-describedby=delete-confirmation-message]"
+        )
+        .len(),
+        1
+    );
+    assert_eq!(select(&document, "[role=alert][data-sc-focus]").len(), 1);
+    problems.extend(
+        check_page(&html)
+            .into_iter()
+            .map(|problem| format!("{source}/delete: {problem}")),
+    );
+    assert!(problems.is_empty(), "{problems:#?}");
+}
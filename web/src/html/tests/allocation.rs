@@ -1,11 +1,30 @@
 use super::*;
 use crate::app_url;
 use crate::test_app;
+use axum::body::Body;
 use axum::http::StatusCode;
 use axum::http::{header::CONTENT_TYPE, Request};
+use http_body_util::BodyExt;
+use libseed::{
+    project::{planting::Planting, template::NoteTemplate, Allocation, AllocationStatus},
+    reminder::{Reminder, ReminderTarget},
+};
 use sqlx::{Pool, Sqlite};
 use test_log::test;
 use tower::Service;
+use uuid::Uuid;
+
+/// The path for adding a note to the allocation with the given id in the given project
+async fn new_note_path(projectid: i64, allocid: i64, pool: &Pool<Sqlite>) -> String {
+    let alloc = Allocation::load(allocid, pool)
+        .await
+        .expect("Failed to load allocation");
+    format!(
+        "{}/sample/{}/note/new",
+        project_path(projectid, pool).await,
+        alloc.uuid
+    )
+}
 
 #[test(sqlx::test(
     migrations = "../db/migrations/",
@@ -15,9 +34,12 @@ use tower::Service;
     )
 ))]
 async fn test_new_note(pool: Pool<Sqlite>) {
-    let mut app = test_app(pool).await.expect("failed to create test app");
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let url = new_note_path(1, 1, &pool).await;
 
-    let params = serde_urlencoded::to_string(&[
+    let params = serde_urlencoded::to_string([
         ("notetype", "Planting"),
         ("date", "2023-01-01"),
         ("summary", "This is a summary"),
@@ -26,7 +48,7 @@ async fn test_new_note(pool: Pool<Sqlite>) {
     .expect("failed to serialize form");
     // make sure we can't add a note without logging in
     let req = Request::builder()
-        .uri(app_url("/project/1/sample/1/note/new"))
+        .uri(app_url(&url))
         .method("POST")
         .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
         .body(params.clone())
@@ -43,7 +65,7 @@ async fn test_new_note(pool: Pool<Sqlite>) {
 
     // then try to add a note
     let req = Request::builder()
-        .uri(app_url("/project/1/sample/1/note/new"))
+        .uri(app_url(&url))
         .method("POST")
         .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
         .header("Cookie", cookie.clone())
@@ -58,7 +80,11 @@ async fn test_new_note(pool: Pool<Sqlite>) {
 
     // try to add a note to a sample that doesn't exist
     let req = Request::builder()
-        .uri(app_url("/project/1/sample/99/note/new"))
+        .uri(app_url(&format!(
+            "{}/sample/{}/note/new",
+            project_path(1, &pool).await,
+            Uuid::new_v4()
+        )))
         .method("POST")
         .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
         .header("Cookie", cookie.clone())
@@ -73,7 +99,7 @@ async fn test_new_note(pool: Pool<Sqlite>) {
 
     // this url specifies a sample for a different user that is not in this project
     let req = Request::builder()
-        .uri(app_url("/project/1/sample/4/note/new"))
+        .uri(app_url(&new_note_path(1, 4, &pool).await))
         .method("POST")
         .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
         .header("Cookie", cookie.clone())
@@ -89,7 +115,7 @@ async fn test_new_note(pool: Pool<Sqlite>) {
     // trying to add a note to a sample that is owned by a different user and also in a
     // different project owned by that user
     let req = Request::builder()
-        .uri(app_url("/project/3/sample/4/note/new"))
+        .uri(app_url(&new_note_path(3, 4, &pool).await))
         .method("POST")
         .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
         .header("Cookie", cookie.clone())
@@ -104,7 +130,7 @@ async fn test_new_note(pool: Pool<Sqlite>) {
 
     // validate form fields
     // missing summary
-    let missing_summary = serde_urlencoded::to_string(&[
+    let missing_summary = serde_urlencoded::to_string([
         ("notetype", "Planting"),
         ("date", "2023-01-01"),
         ("summary", ""),
@@ -112,7 +138,7 @@ async fn test_new_note(pool: Pool<Sqlite>) {
     ])
     .expect("failed to serialize form");
     let req = Request::builder()
-        .uri(app_url("/project/1/sample/1/note/new"))
+        .uri(app_url(&url))
         .method("POST")
         .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
         .header("Cookie", cookie.clone())
@@ -126,7 +152,7 @@ async fn test_new_note(pool: Pool<Sqlite>) {
     assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
 
     // validate note type
-    let missing_type = serde_urlencoded::to_string(&[
+    let missing_type = serde_urlencoded::to_string([
         ("notetype", ""),
         ("date", "2023-01-01"),
         ("summary", "summary"),
@@ -134,7 +160,7 @@ async fn test_new_note(pool: Pool<Sqlite>) {
     ])
     .expect("failed to serialize form");
     let req = Request::builder()
-        .uri(app_url("/project/1/sample/1/note/new"))
+        .uri(app_url(&url))
         .method("POST")
         .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
         .header("Cookie", cookie.clone())
@@ -148,7 +174,7 @@ async fn test_new_note(pool: Pool<Sqlite>) {
     assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
 
     // missing date
-    let missing_date = serde_urlencoded::to_string(&[
+    let missing_date = serde_urlencoded::to_string([
         ("notetype", "Planting"),
         ("date", ""),
         ("summary", "summary"),
@@ -156,7 +182,7 @@ async fn test_new_note(pool: Pool<Sqlite>) {
     ])
     .expect("failed to serialize form");
     let req = Request::builder()
-        .uri(app_url("/project/1/sample/1/note/new"))
+        .uri(app_url(&url))
         .method("POST")
         .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
         .header("Cookie", cookie.clone())
@@ -169,3 +195,390 @@ async fn test_new_note(pool: Pool<Sqlite>) {
         .expect("Failed to execute request");
     assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
 }
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_stratification_reminder(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let params = serde_urlencoded::to_string([
+        ("notetype", "Preparation"),
+        ("date", "2023-01-01"),
+        ("summary", "Started cold stratification"),
+        ("details", ""),
+        ("stratification", "30"),
+    ])
+    .expect("failed to serialize form");
+    let req = Request::builder()
+        .uri(app_url(&new_note_path(1, 1, &pool).await))
+        .method("POST")
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header("Cookie", cookie.clone())
+        .body(params)
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let reminders = Reminder::load_active(1, &pool).await.unwrap();
+    assert_eq!(reminders.len(), 1);
+    assert_eq!(reminders[0].due, time::macros::date!(2023 - 01 - 31));
+
+    // the reminder is due, so it is shown on the home page
+    let req = Request::builder()
+        .uri(app_url("/"))
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = std::str::from_utf8(&body).unwrap();
+    assert!(body.contains(&reminders[0].title));
+
+    let req = Request::builder()
+        .uri(app_url(&format!("/reminder/{}/dismiss", reminders[0].id)))
+        .method("POST")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(Reminder::load_active(1, &pool).await.unwrap().is_empty());
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_allocation_status(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let alloc = Allocation::load(1, &pool)
+        .await
+        .expect("Failed to load allocation");
+    let url = format!("{}/sample/{}", project_path(1, &pool).await, alloc.uuid);
+
+    let req = Request::builder()
+        .uri(app_url(&format!("{url}/status")))
+        .method("PUT")
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header("Cookie", cookie.clone())
+        .body("status=Sown".to_string())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key("HX-Redirect"));
+    let alloc = Allocation::load(1, &pool)
+        .await
+        .expect("Failed to load allocation");
+    assert_eq!(alloc.status, AllocationStatus::Sown);
+
+    // the change is shown in the history of the allocation
+    let req = Request::builder()
+        .uri(app_url(&url))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(String::new())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let body = String::from_utf8_lossy(&body);
+    assert!(body.contains("status-history"));
+    assert!(body.contains("testuser"));
+
+    // the status of other users' allocations can't be changed
+    let other = Allocation::load(4, &pool)
+        .await
+        .expect("Failed to load allocation");
+    let req = Request::builder()
+        .uri(app_url(&format!(
+            "{}/sample/{}/status",
+            project_path(3, &pool).await,
+            other.uuid
+        )))
+        .method("PUT")
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header("Cookie", cookie.clone())
+        .body("status=Sown".to_string())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        Allocation::load(4, &pool)
+            .await
+            .expect("Failed to load allocation")
+            .status,
+        AllocationStatus::Allocated
+    );
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_note_templates(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let send = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let templates_url = format!("{}/templates", project_path(1, &pool).await);
+    let params = serde_urlencoded::to_string([
+        ("name", "Weekly check"),
+        ("notetype", "Germination"),
+        ("summary", "Germination check"),
+        ("details", "- [ ] moisture\n- [ ] mold"),
+    ])
+    .expect("failed to serialize form");
+    let response = app
+        .as_service()
+        .call(send("POST", &templates_url, params.clone()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // a second template with the same name is rejected with a message
+    let response = app
+        .as_service()
+        .call(send("POST", &templates_url, params))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(html.contains("already has a template"));
+    let templates = NoteTemplate::load_project(1, &pool).await.unwrap();
+    assert_eq!(templates.len(), 1);
+
+    // the template pre-fills the form for a new note
+    let url = format!(
+        "{}?template={}",
+        new_note_path(1, 1, &pool).await,
+        templates[0].id
+    );
+    let response = app
+        .as_service()
+        .call(send("GET", &url, String::new()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(html.contains(r#"value="Germination check""#));
+    assert!(html.contains("- [ ] moisture"));
+
+    // templates of other projects can't be used or modified
+    let other_url = format!(
+        "{}?template={}",
+        new_note_path(3, 4, &pool).await,
+        templates[0].id
+    );
+    let response = app
+        .as_service()
+        .call(send("GET", &other_url, String::new()))
+        .await
+        .unwrap();
+    assert_ne!(response.status(), StatusCode::OK);
+    let response = app
+        .as_service()
+        .call(send(
+            "DELETE",
+            &format!(
+                "{}/templates/{}",
+                project_path(2, &pool).await,
+                templates[0].id
+            ),
+            String::new(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app
+        .as_service()
+        .call(send(
+            "DELETE",
+            &format!("{templates_url}/{}", templates[0].id),
+            String::new(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(NoteTemplate::load_project(1, &pool)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_plantings(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let send = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let alloc = Allocation::load(1, &pool).await.unwrap();
+    let url = format!(
+        "{}/sample/{}/planting",
+        project_path(1, &pool).await,
+        alloc.uuid
+    );
+
+    // more plants can't be planted than plugs were produced
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            &url,
+            "date=2024-05-01&plugs=10&planted=20&location=&notes=".to_string(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(html.contains("can&#x27;t be planted") || html.contains("can't be planted"));
+    assert!(Planting::load_allocation(alloc.id, &pool)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            &url,
+            "date=2024-05-01&plugs=40&planted=30&location=North+meadow&notes=&remind=true"
+                .to_string(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let plantings = Planting::load_allocation(alloc.id, &pool).await.unwrap();
+    assert_eq!(plantings.len(), 1);
+    assert_eq!(plantings[0].location.as_deref(), Some("North meadow"));
+    let reminders = Reminder::load_target(1, ReminderTarget::Project(1), &pool)
+        .await
+        .unwrap();
+    assert_eq!(reminders.len(), 2);
+
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            &format!("{url}/{}/check", plantings[0].id),
+            "date=2024-06-01&months=1&alive=24".to_string(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let planting = Planting::load(plantings[0].id, &pool).await.unwrap();
+    assert_eq!(planting.check(1).unwrap().alive, 24);
+
+    // the establishment rate is shown on the project page
+    let response = app
+        .as_service()
+        .call(send("GET", &project_path(1, &pool).await, String::new()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(html.contains("1-month survival: 24 of 30 (80%)"));
+
+    // plantings of other allocations can't be modified through this one
+    let other = Allocation::load(2, &pool).await.unwrap();
+    let other_url = format!(
+        "{}/sample/{}/planting/{}",
+        project_path(1, &pool).await,
+        other.uuid,
+        planting.id
+    );
+    let response = app
+        .as_service()
+        .call(send("DELETE", &other_url, String::new()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app
+        .as_service()
+        .call(send(
+            "DELETE",
+            &format!("{url}/{}", planting.id),
+            String::new(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(Planting::load_allocation(alloc.id, &pool)
+        .await
+        .unwrap()
+        .is_empty());
+}
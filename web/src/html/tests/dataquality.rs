@@ -0,0 +1,62 @@
+use super::*;
+use test_log::test;
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_data_quality(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let mut get = |uri: String| {
+        let req = Request::builder()
+            .uri(app_url(&uri))
+            .method("GET")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request");
+        let response = app.as_service().call(req);
+        async move {
+            let response = response.await.expect("Failed to execute request");
+            let status = response.status();
+            let bytes = response
+                .into_body()
+                .collect()
+                .await
+                .expect("Failed to read body")
+                .to_bytes();
+            (status, String::from_utf8_lossy(&bytes).to_string())
+        }
+    };
+
+    let (status, html) = get("/dataquality".to_string()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("quality-score"));
+    assert!(html.contains("list?issue=missing-quantity"));
+
+    // the link lists only the samples with the issue
+    let (status, html) = get("/sample/list?issue=missing-quantity".to_string()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(html.matches("sample-item").count(), 2);
+    assert!(html.contains("issue-notice"));
+    let (status, _) = get("/sample/list?issue=missing-location".to_string()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    // none of the fixture sources are missing coordinates
+    let (status, html) = get("/source/list?issue=missing-location".to_string()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("issue-notice"));
+    assert!(!html.contains("Test source 1"));
+
+    // none of the allocations have recent notes
+    let project = project_path(1, &pool).await;
+    let (status, html) = get(format!("{project}?issue=inactive-allocation")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("issue-notice"));
+}
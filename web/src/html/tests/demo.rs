@@ -0,0 +1,133 @@
+use super::*;
+use crate::{app, state::SharedState, DemoConfig};
+use libseed::{sample::Sample, user::User};
+use sqlx::{Pool, Sqlite};
+use std::sync::Arc;
+use test_log::test;
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(path = "../../../../db/fixtures", scripts("users", "taxa"))
+))]
+async fn test_demo(pool: Pool<Sqlite>) {
+    let mut shared = SharedState::test(pool.clone());
+    shared.config.demo = Some(DemoConfig::default());
+    let state = Arc::new(shared);
+    crate::demo::reset(&state)
+        .await
+        .expect("Failed to reset the demo account");
+    let mut app = app(state.clone()).await.expect("failed to create test app");
+
+    let request = Request::builder()
+        .uri(app_url("/auth/demo"))
+        .method("POST")
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app.as_service().call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("HX-Redirect").unwrap(),
+        &app_url("/")
+    );
+    let cookie = response
+        .headers()
+        .get("set-cookie")
+        .expect("no set-cookie header")
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let send = |method: &str, uri: &str, body: String, htmx: bool| {
+        let mut builder = Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone());
+        if htmx {
+            builder = builder.header("HX-Request", "true");
+        }
+        builder.body(body).expect("Failed to build request")
+    };
+
+    // the example data can be browsed
+    let response = app
+        .as_service()
+        .call(send("GET", "/sample/list", String::new(), false))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // ...but not modified
+    let demo = User::load_by_username("demo", &pool)
+        .await
+        .unwrap()
+        .unwrap();
+    let samples = Sample::load_all_user(demo.id, None, None, &pool)
+        .await
+        .unwrap();
+    assert!(!samples.is_empty());
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            "/source/new",
+            "name=Blocked&description=&latitude=&longitude=".to_string(),
+            false,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app
+        .as_service()
+        .call(send(
+            "DELETE",
+            &format!("/sample/{}", samples[0].uuid),
+            String::new(),
+            true,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        Sample::load_all_user(demo.id, None, None, &pool)
+            .await
+            .unwrap()
+            .len(),
+        samples.len()
+    );
+
+    // logging out is still possible
+    let response = app
+        .as_service()
+        .call(send("POST", "/auth/logout", String::new(), false))
+        .await
+        .unwrap();
+    assert!(response.status().is_redirection());
+
+    // other accounts are not affected
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let request = Request::builder()
+        .uri(app_url("/source/new"))
+        .method("POST")
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header("Cookie", cookie)
+        .body("name=Allowed&description=&latitude=&longitude=".to_string())
+        .unwrap();
+    let response = app.as_service().call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(path = "../../../../db/fixtures", scripts("users"))
+))]
+async fn test_demo_disabled(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool).await.expect("failed to create test app");
+    let request = Request::builder()
+        .uri(app_url("/auth/demo"))
+        .method("POST")
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app.as_service().call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -5,13 +5,27 @@ use axum::{
     Router,
 };
 use http_body_util::BodyExt;
+use libseed::{loadable::Loadable, project::Project, sample::Sample, source::Source};
 use sqlx::{Pool, Sqlite};
 use test_log::test;
 use tower::Service;
 
+mod accessibility;
 mod allocation;
+mod dataquality;
+mod demo;
+mod notes;
+mod org;
+mod palette;
 mod project;
+mod reminder;
+mod report;
 mod sample;
+mod source;
+mod stock;
+mod taxonomy;
+mod trip;
+mod user;
 
 /// usage:
 /// let (_parts, body) = response.into_parts();
@@ -39,8 +53,28 @@ where
     }
 }
 
+/// The path of the page of the sample with the given id
+pub(crate) async fn sample_path(id: i64, pool: &Pool<Sqlite>) -> String {
+    let sample = Sample::load(id, pool).await.expect("Failed to load sample");
+    format!("/sample/{}", sample.uuid)
+}
+
+/// The path of the page of the source with the given id
+pub(crate) async fn source_path(id: i64, pool: &Pool<Sqlite>) -> String {
+    let source = Source::load(id, pool).await.expect("Failed to load source");
+    format!("/source/{}", source.uuid)
+}
+
+/// The path of the page of the project with the given id
+pub(crate) async fn project_path(id: i64, pool: &Pool<Sqlite>) -> String {
+    let project = Project::load(id, pool)
+        .await
+        .expect("Failed to load project");
+    format!("/project/{}", project.uuid)
+}
+
 /// logs the user into the app and returns a cookie value that can be used in subsequent requests
-async fn login(app: &mut Router) -> Result<String> {
+pub(crate) async fn login(app: &mut Router) -> Result<String> {
     let creds = serde_urlencoded::to_string(Credentials {
         username: "testuser".to_string(),
         password: "topsecret123".to_string(),
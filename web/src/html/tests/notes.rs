@@ -0,0 +1,73 @@
+use super::*;
+use scraper::{Html, Selector};
+use test_log::test;
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "csnotes")
+    )
+))]
+async fn test_list_notes(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let mut summaries = |query: &str| {
+        let req = Request::builder()
+            .uri(app_url(&format!("/notes{query}")))
+            .method("GET")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request");
+        let response = app.as_service().call(req);
+        async move {
+            let response = response.await.expect("Failed to execute request");
+            let status = response.status();
+            let bytes = response
+                .into_body()
+                .collect()
+                .await
+                .expect("Failed to read body")
+                .to_bytes();
+            let document = Html::parse_document(&String::from_utf8_lossy(&bytes));
+            let selector = Selector::parse(".note-row h6 .flex-grow-1").unwrap();
+            let summaries: Vec<String> = document
+                .select(&selector)
+                .map(|e| e.text().collect::<String>().trim().to_string())
+                .collect();
+            (status, summaries)
+        }
+    };
+
+    assert_eq!(
+        summaries("").await,
+        (
+            StatusCode::OK,
+            vec![
+                "summary 3".to_string(),
+                "summary 1".to_string(),
+                "summary 2".to_string(),
+                "These are some notes".to_string(),
+            ]
+        )
+    );
+    assert_eq!(
+        summaries("?type=Planting&from=2024-01-13").await.1,
+        ["summary 1"]
+    );
+    assert_eq!(
+        summaries("?source=sample&filter=&to=").await.1,
+        ["These are some notes"]
+    );
+    assert_eq!(
+        summaries("?filter=details&project=1").await.1,
+        ["summary 3", "summary 1"]
+    );
+    assert_eq!(
+        summaries("?from=yesterday").await.0,
+        StatusCode::UNPROCESSABLE_ENTITY
+    );
+}
@@ -0,0 +1,149 @@
+use super::*;
+use libseed::{
+    loadable::Loadable,
+    organization::{OrgRole, Organization},
+    sample::Sample,
+};
+use test_log::test;
+
+async fn send(app: &mut Router, method: &str, uri: &str, cookie: &str, body: String) -> StatusCode {
+    let req = Request::builder()
+        .uri(app_url(uri))
+        .method(method)
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header("Cookie", cookie)
+        .body(body)
+        .expect("Failed to build request");
+    app.as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request")
+        .status()
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_shared_sample(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let update = serde_urlencoded::to_string([
+        ("taxon", "43254"),
+        ("source", "1"),
+        ("month", ""),
+        ("year", ""),
+        ("quantity", ""),
+        ("notes", "shared"),
+        ("org", ""),
+    ])
+    .unwrap();
+
+    // sample 4 belongs to another user
+    let url = sample_path(4, &pool).await;
+    assert_eq!(
+        send(&mut app, "GET", &url, &cookie, String::new()).await,
+        StatusCode::UNAUTHORIZED
+    );
+
+    let mut org = Organization::new("Seed Library".to_string(), None);
+    org.insert(2, &pool).await.unwrap();
+    org.set_member(1, OrgRole::Viewer, &pool).await.unwrap();
+    let mut sample = Sample::load(4, &pool).await.unwrap();
+    sample.orgid = Some(org.id);
+    sample.update(&pool).await.unwrap();
+
+    // viewers can see the organization's samples, but not modify them
+    assert_eq!(
+        send(&mut app, "GET", &url, &cookie, String::new()).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send(&mut app, "PUT", &url, &cookie, update.clone()).await,
+        StatusCode::UNAUTHORIZED
+    );
+
+    // members can modify them, but not delete them or take them out of the organization
+    org.set_member(1, OrgRole::Member, &pool).await.unwrap();
+    let keep_org = update.replace("org=", &format!("org={}", org.id));
+    assert_eq!(
+        send(&mut app, "PUT", &url, &cookie, keep_org).await,
+        StatusCode::OK
+    );
+    let sample = Sample::load(4, &pool).await.unwrap();
+    assert_eq!(sample.notes.as_deref(), Some("shared"));
+    assert_eq!(sample.orgid, Some(org.id));
+    assert_eq!(
+        send(&mut app, "PUT", &url, &cookie, update).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(Sample::load(4, &pool).await.unwrap().orgid, Some(org.id));
+    assert_eq!(
+        send(&mut app, "DELETE", &url, &cookie, String::new()).await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(path = "../../../../db/fixtures", scripts("users"))
+))]
+async fn test_manage_org(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let params = serde_urlencoded::to_string([("name", "Seed Library"), ("description", "")])
+        .expect("Failed to serialize params");
+    assert_eq!(
+        send(&mut app, "POST", "/org/new", &cookie, params).await,
+        StatusCode::OK
+    );
+    let orgs = Organization::load_all_user(1, &pool).await.unwrap();
+    assert_eq!(orgs.len(), 1);
+    let (org, role) = &orgs[0];
+    assert_eq!(*role, OrgRole::Admin);
+
+    let uri = format!("/org/{}", org.id);
+    assert_eq!(
+        send(&mut app, "GET", &uri, &cookie, String::new()).await,
+        StatusCode::OK
+    );
+    let params = serde_urlencoded::to_string([("username", "test.user2"), ("role", "viewer")])
+        .expect("Failed to serialize params");
+    assert_eq!(
+        send(&mut app, "POST", &format!("{uri}/members"), &cookie, params).await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        Organization::role(org.id, 2, &pool).await.unwrap(),
+        Some(OrgRole::Viewer)
+    );
+
+    // the only admin can't leave the organization
+    assert_ne!(
+        send(
+            &mut app,
+            "DELETE",
+            &format!("{uri}/members/1"),
+            &cookie,
+            String::new()
+        )
+        .await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        send(&mut app, "DELETE", &uri, &cookie, String::new()).await,
+        StatusCode::OK
+    );
+    assert!(Organization::load_all_user(1, &pool)
+        .await
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,65 @@
+use super::*;
+use test_log::test;
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_palette(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    async fn palette(app: &mut Router, cookie: &str, query: &str) -> String {
+        let req = Request::builder()
+            .uri(app_url(&format!(
+                "/palette?{}",
+                serde_urlencoded::to_string([("q", query)]).unwrap()
+            )))
+            .method("GET")
+            .header("Cookie", cookie)
+            .body(Body::empty())
+            .expect("Failed to build request");
+        let response = app
+            .as_service()
+            .call(req)
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8(bytes.to_vec()).expect("Body is not utf8")
+    }
+
+    // links are html-escaped in the output
+    let link = |path: &str| app_url(path).replace('/', "&#x2f;");
+
+    // without a query, only the quick actions are shown
+    let html = palette(&mut app, &cookie, "").await;
+    assert!(html.contains("New sample"));
+    assert!(html.contains(&link("/sample/new")));
+    assert!(html.contains("New note for S0001"));
+    assert!(!html.contains("Results"));
+
+    let html = palette(&mut app, &cookie, "elymus").await;
+    assert!(!html.contains("New sample"));
+    assert!(html.contains(&link("/taxonomy/40677")));
+    assert!(html.contains(&link(&sample_path(2, &pool).await)));
+    // samples of other users are not included
+    assert!(!html.contains(&format!("{}\"", link(&sample_path(4, &pool).await))));
+
+    let html = palette(&mut app, &cookie, "new sam").await;
+    assert!(html.contains("New sample"));
+    assert!(!html.contains("New project"));
+
+    let html = palette(&mut app, &cookie, "zzzz").await;
+    assert!(html.contains("No results"));
+}
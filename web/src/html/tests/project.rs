@@ -85,7 +85,7 @@ async fn test_new_project(pool: Pool<Sqlite>) {
 
     // well-formed form data, but not expected format
     let missing_name =
-        serde_urlencoded::to_string(&[("foo", "bar")]).expect("failed to serialize form");
+        serde_urlencoded::to_string([("foo", "bar")]).expect("failed to serialize form");
     let req = Request::builder()
         .uri(app_url("/project/new"))
         .method("POST")
@@ -101,7 +101,7 @@ async fn test_new_project(pool: Pool<Sqlite>) {
     assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
 
     // only name
-    let form = serde_urlencoded::to_string(&[("name", "project name #1")])
+    let form = serde_urlencoded::to_string([("name", "project name #1")])
         .expect("failed to serialize form");
     let req = Request::builder()
         .uri(app_url("/project/new"))
@@ -118,7 +118,7 @@ async fn test_new_project(pool: Pool<Sqlite>) {
     assert_eq!(response.status(), StatusCode::OK);
 
     // empty name
-    let form = serde_urlencoded::to_string(&[("name", "")]).expect("failed to serialize form");
+    let form = serde_urlencoded::to_string([("name", "")]).expect("failed to serialize form");
     let req = Request::builder()
         .uri(app_url("/project/new"))
         .method("POST")
@@ -134,7 +134,7 @@ async fn test_new_project(pool: Pool<Sqlite>) {
     assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
 
     // name + empty description
-    let form = serde_urlencoded::to_string(&[("name", "project name #2"), ("description", "")])
+    let form = serde_urlencoded::to_string([("name", "project name #2"), ("description", "")])
         .expect("failed to serialize form");
     let req = Request::builder()
         .uri(app_url("/project/new"))
@@ -152,7 +152,7 @@ async fn test_new_project(pool: Pool<Sqlite>) {
     assert!(response.headers().get("HX-Redirect").is_some());
 
     // name + description
-    let form = serde_urlencoded::to_string(&[
+    let form = serde_urlencoded::to_string([
         ("name", "project name #3"),
         ("description", "This is a description of the project"),
     ])
@@ -172,3 +172,563 @@ async fn test_new_project(pool: Pool<Sqlite>) {
     assert_eq!(response.status(), StatusCode::OK);
     assert!(response.headers().get("HX-Redirect").is_some());
 }
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "csnotes")
+    )
+))]
+async fn test_print_project(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    // first log in:
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let req = Request::builder()
+        .uri(app_url(&format!("{}/print", project_path(1, &pool).await)))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    // the full note history is included, not just the latest note
+    assert!(html.contains("summary 1"));
+    assert!(html.contains("summary 2"));
+    assert!(html.contains("summary 3"));
+    // no navigation chrome
+    assert!(!html.contains("navbar"));
+
+    // projects that don't exist can't be printed
+    let req = Request::builder()
+        .uri(app_url(&format!("/project/{}/print", uuid::Uuid::new_v4())))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_propagation_plan(pool: Pool<Sqlite>) {
+    sqlx::query(
+        r#"INSERT INTO sc_germination_codes (germid, code, summary) VALUES (1, 'C(60)', 'Cold moist stratify for 60 days');
+        INSERT INTO sc_taxon_germination (tsn, germid) VALUES (40683, 1);"#,
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to insert germination codes");
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    // first log in:
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let req = Request::builder()
+        .uri(app_url(&format!(
+            "{}/propagation?sort=qty&dir=desc",
+            project_path(1, &pool).await
+        )))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    assert!(html.contains("60 days"));
+
+    let req = Request::builder()
+        .uri(app_url(&format!(
+            "{}/propagation/csv",
+            project_path(1, &pool).await
+        )))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).map(|v| v.as_bytes()),
+        Some(&b"text/csv"[..])
+    );
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let csv = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    // the species that needs the longest stratification is listed first
+    assert!(lines[1].starts_with("Elymus canadensis,"));
+    assert!(lines[1].contains("S0002 S0003"));
+    assert!(lines[1].contains(",C(60),60,"));
+
+    // projects that belong to other users are not accessible
+    let req = Request::builder()
+        .uri(app_url(&format!(
+            "{}/propagation/csv",
+            project_path(3, &pool).await
+        )))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_project_timeline(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    // first log in:
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let url = app_url(&format!("{}/timeline", project_path(1, &pool).await));
+    let get_timeline = || {
+        Request::builder()
+            .uri(&url)
+            .method("GET")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request")
+    };
+
+    let response = app
+        .as_service()
+        .call(get_timeline())
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    assert!(html.contains("Nothing has been scheduled"));
+
+    sqlx::query(
+        r#"INSERT INTO sc_germination_codes (germid, code) VALUES (1, 'C(60)');
+        INSERT INTO sc_taxon_germination (tsn, germid) VALUES (40683, 1);
+        INSERT INTO sc_project_notes (psid, notedate, notetype, notesummary)
+            VALUES (2, '2024-01-10', 1, 'Started stratification');"#,
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to insert notes");
+    let response = app
+        .as_service()
+        .call(get_timeline())
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    assert!(html.contains("timeline-event note"));
+    assert!(html.contains("timeline-event stratification"));
+    assert!(html.contains("timeline-event sowingwindow"));
+    // the timeline runs from the start of January to the end of March
+    assert!(html.contains("January 2024"));
+    assert!(html.contains("March 2024"));
+    assert!(!html.contains("April 2024"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_field_sheets(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    for query in ["", "?blank=true"] {
+        let req = Request::builder()
+            .uri(app_url(&format!(
+                "{}/fieldsheets{query}",
+                project_path(1, &pool).await
+            )))
+            .method("GET")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request");
+        let response = app
+            .as_service()
+            .call(req)
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).map(|v| v.as_bytes()),
+            Some(&b"application/pdf"[..])
+        );
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        assert!(bytes.starts_with(b"%PDF"));
+    }
+
+    // projects that belong to other users are not accessible
+    let req = Request::builder()
+        .uri(app_url(&format!(
+            "{}/fieldsheets",
+            project_path(3, &pool).await
+        )))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_project_bundle(pool: Pool<Sqlite>) {
+    use libseed::project::bundle::{Bundle, BundleStatus};
+
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let path = project_path(1, &pool).await;
+
+    let req = Request::builder()
+        .uri(app_url(&format!("{path}/bundle")))
+        .method("POST")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // the bundle is generated in the background
+    let uuid: String = sqlx::query_scalar("SELECT bundleuuid FROM sc_project_bundles")
+        .fetch_one(&pool)
+        .await
+        .expect("No bundle was requested");
+    let uuid = uuid::Uuid::parse_str(&uuid).unwrap();
+    let mut bundle = Bundle::load_uuid(uuid, &pool).await.unwrap();
+    for _ in 0..100 {
+        if bundle.status != BundleStatus::Pending {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        bundle = Bundle::load_uuid(uuid, &pool).await.unwrap();
+    }
+    assert_eq!(bundle.status, BundleStatus::Ready);
+
+    let req = Request::builder()
+        .uri(app_url(&format!("{path}/bundle/{uuid}")))
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let html = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    // the status links to the download once the bundle is ready
+    assert!(html.contains("Download"));
+
+    let req = Request::builder()
+        .uri(app_url(&format!("{path}/bundle/{uuid}/download")))
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        "application/zip"
+    );
+
+    // a bundle can only be downloaded through the project it belongs to
+    let req = Request::builder()
+        .uri(app_url(&format!(
+            "{}/bundle/{uuid}/download",
+            project_path(2, &pool).await
+        )))
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_project_without_javascript(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let url = project_path(2, &pool).await;
+    let post = |uri: String, body: &str| {
+        Request::builder()
+            .uri(app_url(&uri))
+            .method("POST")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body.to_string())
+            .expect("Failed to build request")
+    };
+
+    // without htmx, adding samples renders the whole page instead of a fragment
+    let response = app
+        .as_service()
+        .call(post(format!("{url}/add"), "sample=1"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = String::from_utf8(bytes.to_vec()).expect("Body is not utf8");
+    assert!(html.contains("<html"));
+    assert!(html.contains("Assigned 1 samples"));
+    let n: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sc_project_samples WHERE projectid=2")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(n, 1);
+
+    // samples that can't be added don't prevent the others from being added, and each of them is
+    // reported with the reason
+    let response = app
+        .as_service()
+        .call(post(format!("{url}/add"), "sample=1&sample=4&sample=2"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = String::from_utf8(bytes.to_vec()).expect("Body is not utf8");
+    assert!(html.contains("Assigned 1 samples"));
+    assert!(html.contains("2 samples could not be added"));
+    assert!(html.contains("The sample is already part of this project"));
+    assert!(html.contains("You don&#x27;t have access to this sample"));
+    let n: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sc_project_samples WHERE projectid=2")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(n, 2);
+
+    // a project without samples can be deleted from the confirmation page
+    let mut empty = Project::new("empty project".to_string(), None, 1);
+    empty.insert(&pool).await.unwrap();
+    let response = app
+        .as_service()
+        .call(post(
+            format!("{}/delete", project_path(empty.id, &pool).await),
+            "",
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        response.headers().get("location").unwrap(),
+        &app_url("/project/list")
+    );
+    assert!(Project::load(empty.id, &pool).await.is_err());
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_sort_project_samples(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let project = project_path(1, &pool).await;
+
+    // the sort parameter can list several fields, and the sort menu adds a secondary field
+    for (query, order) in [
+        ("sort=src,-id", ["S0003", "S0001", "S0002"]),
+        (
+            "sort=src&dir=asc&then=id&thendir=desc",
+            ["S0003", "S0001", "S0002"],
+        ),
+        ("sort=date&dir=desc&then=", ["S0003", "S0002", "S0001"]),
+    ] {
+        let req = Request::builder()
+            .uri(app_url(&format!("{project}?{query}")))
+            .method("GET")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request");
+        let response = app
+            .as_service()
+            .call(req)
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK, "{query}");
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        let html = std::str::from_utf8(&bytes).expect("Body is not utf8");
+        let positions: Vec<usize> = order
+            .iter()
+            .map(|id| html.find(id).expect("Sample is not listed"))
+            .collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]), "{query}");
+    }
+
+    // the sort menu shows the current sort keys
+    let req = Request::builder()
+        .uri(app_url(&format!("{project}?sort=qty,-date")))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    let document = scraper::Html::parse_document(html);
+    let selected = |select: &str| {
+        let selector =
+            scraper::Selector::parse(&format!("select[name={select}] option[selected]")).unwrap();
+        document
+            .select(&selector)
+            .map(|option| option.value().attr("value").unwrap_or_default().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(selected("sort"), ["qty"]);
+    assert_eq!(selected("dir"), ["asc"]);
+    assert_eq!(selected("then"), ["date"]);
+    assert_eq!(selected("thendir"), ["desc"]);
+
+    // unknown fields are rejected
+    let req = Request::builder()
+        .uri(app_url(&format!("{project}?sort=bogus")))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
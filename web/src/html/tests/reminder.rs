@@ -0,0 +1,105 @@
+use super::*;
+use libseed::reminder::{Reminder, ReminderTarget};
+use test_log::test;
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_add_reminders(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let sample = Sample::load(1, &pool).await.unwrap();
+    let other = Sample::load(4, &pool).await.unwrap();
+    let source = Source::load(1, &pool).await.unwrap();
+
+    let mut post = |body: String| {
+        let req = Request::builder()
+            .uri(app_url("/reminder/new"))
+            .method("POST")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request");
+        let response = app.as_service().call(req);
+        async move {
+            let response = response.await.expect("Failed to execute request");
+            let status = response.status();
+            let bytes = response
+                .into_body()
+                .collect()
+                .await
+                .expect("Failed to read body")
+                .to_bytes();
+            (status, String::from_utf8_lossy(&bytes).into_owned())
+        }
+    };
+
+    let (status, body) = post(format!(
+        "target=sample&uuid={}&title=Re-test+viability&due=2000-03-01",
+        sample.uuid
+    ))
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("Re-test viability"), "{body}");
+    let (status, _) = post(format!(
+        "target=source&uuid={}&title=Return+in+August&due=9999-08-01",
+        source.uuid
+    ))
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let reminders = Reminder::load_active(1, &pool).await.unwrap();
+    assert_eq!(
+        reminders.iter().map(|r| r.target).collect::<Vec<_>>(),
+        [
+            Some(ReminderTarget::Sample(1)),
+            Some(ReminderTarget::Source(1))
+        ]
+    );
+
+    // an invalid date is reported in the list
+    let (status, body) = post(format!(
+        "target=sample&uuid={}&title=Sow&due=March",
+        sample.uuid
+    ))
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("Failed to add reminder"), "{body}");
+    assert_eq!(Reminder::load_active(1, &pool).await.unwrap().len(), 2);
+
+    // the sample of another user can't be used
+    let (status, _) = post(format!(
+        "target=sample&uuid={}&title=Sow&due=2000-03-01",
+        other.uuid
+    ))
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // the due reminder links to its sample on the home page, and the reminders are listed on the
+    // pages of their objects
+    for (path, text) in [
+        ("/".to_string(), sample.uuid.to_string()),
+        (sample_path(1, &pool).await, "Re-test viability".to_string()),
+        (source_path(1, &pool).await, "Return in August".to_string()),
+    ] {
+        let req = Request::builder()
+            .uri(app_url(&path))
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request");
+        let response = app
+            .as_service()
+            .call(req)
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(&text), "{path}: {text}");
+    }
+}
@@ -0,0 +1,152 @@
+use super::*;
+use crate::test_app;
+use axum::http::header;
+use libseed::report::{self, Report, ReportFormat};
+use sqlx::{Pool, Sqlite};
+use test_log::test;
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_reports(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let send = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let text = |response: axum::response::Response| async {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+
+    let template = "{% for s in samples %}{{ s.id | idfmt('S') }},{{ s.taxon.complete_name | csv }}\n{% endfor %}";
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            "/report/new",
+            serde_urlencoded::to_string([
+                ("name", "Sample list"),
+                ("format", "csv"),
+                ("filter", ""),
+                ("template", template),
+            ])
+            .unwrap(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert!(response.headers().get("HX-Redirect").is_some());
+    let reports = Report::load_all(Some(report::Filter::User(1).into()), &pool)
+        .await
+        .expect("Failed to load reports");
+    assert_eq!(reports.len(), 1);
+    let id = reports[0].id;
+    assert_eq!(reports[0].format, ReportFormat::Csv);
+
+    // an invalid template is rejected
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            "/report/new",
+            "name=Broken&format=html&template=%7B%25+for+%25%7D".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = app
+        .as_service()
+        .call(send("GET", &format!("/report/{id}/run"), String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).unwrap(),
+        ReportFormat::Csv.content_type()
+    );
+    assert!(response
+        .headers()
+        .get(header::CONTENT_DISPOSITION)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let samples = Sample::load_all_user(1, None, None, &pool).await.unwrap();
+    assert_eq!(text(response).await.lines().count(), samples.len());
+
+    // html reports are sandboxed
+    let response = app
+        .as_service()
+        .call(send(
+            "PUT",
+            &format!("/report/{id}"),
+            "name=Sample+list&format=html&template=%3Cp%3E%7B%7B+samples+%7C+length+%7D%7D%3C%2Fp%3E"
+                .to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .as_service()
+        .call(send("GET", &format!("/report/{id}/run"), String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert!(response
+        .headers()
+        .get(header::CONTENT_SECURITY_POLICY)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("sandbox"));
+    assert_eq!(text(response).await, format!("<p>{}</p>", samples.len()));
+
+    // other users' reports are not accessible
+    let mut other = Report::new(
+        "Other".to_string(),
+        ReportFormat::Html,
+        None,
+        "secret".to_string(),
+        2,
+    );
+    other.insert(&pool).await.unwrap();
+    for (method, uri) in [
+        ("GET", format!("/report/{}", other.id)),
+        ("GET", format!("/report/{}/run", other.id)),
+        ("DELETE", format!("/report/{}", other.id)),
+    ] {
+        let response = app
+            .as_service()
+            .call(send(method, &uri, String::new()))
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    let response = app
+        .as_service()
+        .call(send("DELETE", &format!("/report/{id}"), String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(Report::load(id, &pool).await.is_err());
+    assert!(Report::load(other.id, &pool).await.is_ok());
+}
@@ -1,4 +1,17 @@
 use super::*;
+use libseed::{
+    conservation::{Listing, ListingStatus, Permit},
+    format::{NumberStyle, WeightUnit},
+    grade::QualityGrade,
+    history::Change,
+    loadable::Loadable,
+    photo::Photo,
+    preferences::Preferences,
+    sample::Sample,
+    stats,
+    timezone::TimeZone,
+    user::User,
+};
 use test_log::test;
 
 #[test(sqlx::test(
@@ -29,3 +42,1997 @@ async fn test_filter_samples(pool: Pool<Sqlite>) {
         .expect("Failed to execute request");
     assert_eq!(response.status(), StatusCode::OK);
 }
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_update_sample_conflict(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+
+    // first log in:
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    // submit an edit based on version 1 of the sample
+    let url = sample_path(1, &pool).await;
+    let update = |notes: &str| {
+        let params = serde_urlencoded::to_string([
+            ("taxon", "43254"),
+            ("source", "1"),
+            ("month", ""),
+            ("year", ""),
+            ("quantity", ""),
+            ("notes", notes),
+            ("version", "1"),
+        ])
+        .expect("Failed to serialize params");
+        Request::builder()
+            .uri(app_url(&url))
+            .method("PUT")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(params)
+            .expect("Failed to build request")
+    };
+    let response = app
+        .as_service()
+        .call(update("first edit"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("HX-Redirect").is_some());
+
+    // a second edit based on the same (now stale) version should not overwrite the first
+    let response = app
+        .as_service()
+        .call(update("second edit"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("HX-Redirect").is_none());
+
+    let notes: Option<String> = sqlx::query_scalar("SELECT notes FROM sc_samples WHERE sampleid=1")
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to query sample");
+    assert_eq!(notes.as_deref(), Some("first edit"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_group_samples(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool).await.expect("failed to create test app");
+
+    // first log in:
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let get = |uri: &str| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method("GET")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request")
+    };
+    let body = |response: axum::response::Response| async move {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8(bytes.to_vec()).expect("Body is not utf8")
+    };
+
+    // one row per taxon, with a count of the samples
+    let response = app
+        .as_service()
+        .call(get("/sample/list?group=taxon"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert_eq!(html.matches("sample-group").count(), 2);
+    assert!(html.contains("2 samples"));
+    // the filter bar lists the families of the user's samples
+    assert!(html.contains("Poaceae (2)"));
+    assert!(html.contains("Iridaceae (1)"));
+
+    // expanding a group shows only the samples of that taxon
+    let response = app
+        .as_service()
+        .call(get("/sample/list?taxon=40683&filter="))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert_eq!(html.matches("sample-item").count(), 2);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_sample_weight_estimate(pool: Pool<Sqlite>) {
+    libseed::taxonomy::SeedWeight {
+        tsn: 40683,
+        seeds_per_gram: 40.0,
+        source: None,
+    }
+    .save(&pool)
+    .await
+    .expect("Failed to save seed weight");
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let path = sample_path(2, &pool).await;
+    let page = |app: &mut Router| {
+        let req = Request::builder()
+            .uri(app_url(&path))
+            .method("GET")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request");
+        let call = app.as_service().call(req);
+        async move {
+            let response = call.await.expect("Failed to execute request");
+            assert_eq!(response.status(), StatusCode::OK);
+            let bytes = response
+                .into_body()
+                .collect()
+                .await
+                .expect("Failed to read body")
+                .to_bytes();
+            String::from_utf8(bytes.to_vec()).expect("Body is not utf8")
+        }
+    };
+    // 100 seeds at 40 seeds per gram
+    let html = page(&mut app).await;
+    assert!(html.contains("(≈ 2.50 g)"));
+
+    // weights are shown in the unit and number format that the user prefers
+    let mut user = User::load(1, &pool).await.unwrap();
+    user.number_style = NumberStyle::Comma;
+    user.weight_unit = WeightUnit::Ounces;
+    user.update(&pool).await.unwrap();
+    let html = page(&mut app).await;
+    assert!(html.contains("(≈ 0,09 oz)"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_flag_samples(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let request = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let body = |response: axum::response::Response| async move {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8(bytes.to_vec()).expect("Body is not utf8")
+    };
+
+    let response = app
+        .as_service()
+        .call(request(
+            "POST",
+            &format!("{}/flag", sample_path(1, &pool).await),
+            "reason=Needs+ID+confirmation".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert_eq!(html.matches("sample-flag\"").count(), 1);
+    assert!(html.contains("Needs ID confirmation"));
+
+    // samples of other users can't be flagged
+    let response = app
+        .as_service()
+        .call(request(
+            "POST",
+            &format!("{}/flag", sample_path(4, &pool).await),
+            "reason=Needs+cleaning".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // the review queue only lists the flagged sample
+    let response = app
+        .as_service()
+        .call(request("GET", "/sample/flagged", String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert_eq!(html.matches("sample-item").count(), 1);
+    assert!(html.contains("Sisyrinchium campestre"));
+
+    let flagid: i64 = sqlx::query_scalar("SELECT flagid FROM sc_sample_flags WHERE sampleid=1")
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to query flag");
+    let response = app
+        .as_service()
+        .call(request(
+            "DELETE",
+            &format!("{}/flag/{flagid}", sample_path(1, &pool).await),
+            String::new(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert!(!html.contains("sample-flag\""));
+
+    let response = app
+        .as_service()
+        .call(request("GET", "/sample/flagged", String::new()))
+        .await
+        .expect("Failed to execute request");
+    let html = body(response).await;
+    assert!(html.contains("No samples are flagged for review"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_quality_tests(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let request = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let body = |response: axum::response::Response| async move {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8(bytes.to_vec()).expect("Body is not utf8")
+    };
+    let url = format!("{}/quality", sample_path(2, &pool).await);
+
+    let response = app
+        .as_service()
+        .call(request(
+            "POST",
+            &url,
+            "date=2024-02-01&fill=80&method=x-ray&purity=50&tester=Seed+lab".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert_eq!(html.matches("quality-test\"").count(), 1);
+    assert!(html.contains("Seed lab"));
+    // the sample has a quantity of 100
+    assert!(html.contains("Usable quantity: about 40"));
+
+    // invalid measurements are reported and not saved
+    let response = app
+        .as_service()
+        .call(request(
+            "POST",
+            &url,
+            "date=2024-03-01&fill=150&method=&purity=&tester=".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert!(html.contains("Failed to add quality test"));
+    assert_eq!(html.matches("quality-test\"").count(), 1);
+
+    // samples of other users can't be tested
+    let response = app
+        .as_service()
+        .call(request(
+            "POST",
+            &format!("{}/quality", sample_path(4, &pool).await),
+            "date=2024-02-01&fill=80".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let testid: i64 = sqlx::query_scalar("SELECT qtestid FROM sc_quality_tests WHERE sampleid=2")
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to query quality test");
+    let response = app
+        .as_service()
+        .call(request("DELETE", &format!("{url}/{testid}"), String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert!(html.contains("Not tested"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_new_sample_defaults(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let params = serde_urlencoded::to_string([
+        ("source", "2"),
+        ("uncertain", "true"),
+        ("currentdate", "true"),
+    ])
+    .expect("Failed to serialize params");
+    let req = Request::builder()
+        .uri(app_url("/user/me/preferences"))
+        .method("PUT")
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header("Cookie", cookie.clone())
+        .body(Body::from(params))
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let prefs = libseed::preferences::Preferences::load(1, &pool)
+        .await
+        .expect("Failed to load preferences");
+    assert_eq!(prefs.default_source, Some(2));
+    assert!(prefs.default_date_current);
+
+    let req = Request::builder()
+        .uri(app_url("/sample/new"))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = String::from_utf8(bytes.to_vec()).expect("Body is not utf8");
+    let uncertain = html
+        .split("id=\"SampleUncertaintyInput\"")
+        .nth(1)
+        .and_then(|s| s.split('>').next())
+        .expect("Missing uncertainty checkbox");
+    assert!(uncertain.contains("checked"));
+    let (_, year) = prefs.default_date(&TimeZone::utc());
+    assert!(html.contains(&format!("value=\"{}\"", year.unwrap())));
+    let source = html
+        .split("<option value=\"2\"")
+        .nth(1)
+        .and_then(|s| s.split('>').next())
+        .expect("Missing source option");
+    assert!(source.contains("selected"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_collection_calendar(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool).await.expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    for (uri, taxa) in [("/sample/calendar", 2), ("/sample/calendar?source=2", 1)] {
+        let req = Request::builder()
+            .uri(app_url(uri))
+            .method("GET")
+            .header("Cookie", &cookie)
+            .body(Body::empty())
+            .expect("Failed to build request");
+        let response = app
+            .as_service()
+            .call(req)
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        let html = String::from_utf8(bytes.to_vec()).expect("Body is not utf8");
+        let rows = html
+            .split("<tbody>")
+            .nth(1)
+            .and_then(|s| s.split("</tbody>").next())
+            .expect("Missing calendar");
+        assert_eq!(rows.matches("<tr>").count(), taxa);
+    }
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_yield_forecast(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let req = Request::builder()
+        .uri(app_url("/sample/forecast"))
+        .method("GET")
+        .header("Cookie", &cookie)
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let req = Request::builder()
+        .uri(app_url("/sample/forecast/csv"))
+        .method("GET")
+        .header("Cookie", &cookie)
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).map(|v| v.as_bytes()),
+        Some(&b"text/csv"[..])
+    );
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let csv = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    let lines: Vec<&str> = csv.lines().collect();
+    // only sample 2 has both a year and a quantity
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].starts_with("Elymus canadensis,"));
+    assert!(lines[1].ends_with(",1,2023,2023,100,100,Unknown"));
+
+    // with a collection year that starts in october, sample 2 belongs to 2023/24
+    let mut prefs = Preferences::load(1, &pool)
+        .await
+        .expect("Failed to load preferences");
+    prefs.year_start_month = 10;
+    prefs.save(&pool).await.expect("Failed to save preferences");
+    let req = Request::builder()
+        .uri(app_url("/sample/forecast/csv"))
+        .method("GET")
+        .header("Cookie", &cookie)
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let csv = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    assert!(csv.contains(",1,2023/24,2023/24,100,100,Unknown"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_collection_growth(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let mut get = |uri: &str| {
+        let req = Request::builder()
+            .uri(app_url(uri))
+            .method("GET")
+            .header("Cookie", &cookie)
+            .body(Body::empty())
+            .expect("Failed to build request");
+        app.as_service().call(req)
+    };
+
+    let response = get("/sample/growth")
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let body = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    assert!(!body.contains("growth-charts"));
+
+    let start = time::macros::date!(2024 - 03 - 01);
+    stats::record_snapshots(start, 1, &pool).await.unwrap();
+    sqlx::query("UPDATE sc_samples SET quantity=250 WHERE sampleid=1")
+        .execute(&pool)
+        .await
+        .unwrap();
+    stats::record_snapshots(start + time::Duration::days(1), 1, &pool)
+        .await
+        .unwrap();
+
+    let response = get("/sample/growth")
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let body = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    assert!(body.contains("growth-charts"));
+    // the quantity grew from 100 to 350, which is the top of the chart
+    assert!(body.contains(r#"points="0.0,71.4 400.0,0.0""#));
+
+    let response = get("/sample/growth/csv")
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).map(|v| v.as_bytes()),
+        Some(&b"text/csv"[..])
+    );
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let csv = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    assert_eq!(
+        csv.lines().collect::<Vec<_>>(),
+        vec![
+            "Date,Samples,Taxa,Sources,Quantity",
+            "2024-03-01,3,2,2,100",
+            "2024-03-02,3,2,2,350"
+        ]
+    );
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_match_site(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    sqlx::query("UPDATE sc_sources SET srchabitat=1 WHERE srcid=2")
+        .execute(&pool)
+        .await
+        .expect("Failed to update source");
+    let mut get = |query: &str| {
+        let req = Request::builder()
+            .uri(app_url(&format!("/sample/match{query}")))
+            .method("GET")
+            .header("Cookie", &cookie)
+            .body(Body::empty())
+            .expect("Failed to build request");
+        app.as_service().call(req)
+    };
+    let text = |response: axum::response::Response| async {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+
+    let response = get("").await.expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(text(response).await.contains("site-location"));
+    let response = get("?step=conditions&latitude=40.123&longitude=-90.123")
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = text(response).await;
+    assert!(html.contains("site-conditions"));
+    assert!(html.contains("value=\"40.123\""));
+
+    let response = get("?step=results&latitude=40.123&longitude=-90.123&habitat=prairie")
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = text(response).await;
+    assert!(html.contains("site-matches"));
+    // the prairie source is ranked above the nearby source without a habitat
+    let prairie = html.find("Test source 2").expect("Source 2 is not listed");
+    let nearby = html.find("Test source 1").expect("Source 1 is not listed");
+    assert!(prairie < nearby);
+    assert!(html.contains("0.0 km"));
+
+    // without any attributes of the site, the user is asked for them again
+    let response = get("?step=results")
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = text(response).await;
+    assert!(html.contains("site-conditions"));
+    assert!(!html.contains("site-matches"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_purchased_sample(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let params = |date: &str| {
+        serde_urlencoded::to_string([
+            ("taxon", "43254"),
+            ("source", "1"),
+            ("month", ""),
+            ("year", "2024"),
+            ("quantity", "250"),
+            ("notes", ""),
+            ("vendor", "Prairie Moon"),
+            ("lot", "L-77"),
+            ("purchase_date", date),
+            ("price", "7.25"),
+            ("certified_origin", "Dane County, WI"),
+        ])
+        .expect("Failed to serialize params")
+    };
+    let send = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+
+    // an invalid purchase date is rejected
+    let response = app
+        .as_service()
+        .call(send("POST", "/sample/new", params("2024-13-01")))
+        .await
+        .expect("Failed to execute request");
+    assert!(response.headers().get("HX-Redirect").is_none());
+
+    let response = app
+        .as_service()
+        .call(send("POST", "/sample/new", params("2024-03-01")))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("HX-Redirect").is_some());
+    let id: i64 = sqlx::query_scalar("SELECT MAX(sampleid) FROM sc_samples")
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to query samples");
+    let sample = Sample::load(id, &pool)
+        .await
+        .expect("Failed to load sample");
+    let purchase = sample.purchase.expect("Sample is not purchased");
+    assert_eq!(purchase.vendor, "Prairie Moon");
+    assert_eq!(purchase.lot.as_deref(), Some("L-77"));
+    assert_eq!(
+        purchase.date.map(|d| d.to_string()).as_deref(),
+        Some("2024-03-01")
+    );
+    assert_eq!(purchase.price, Some(7.25));
+
+    for uri in [
+        format!("/sample/{}", sample.uuid),
+        "/sample/list?origin=purchased".to_string(),
+        "/sample/vendors".to_string(),
+    ] {
+        let response = app
+            .as_service()
+            .call(send("GET", &uri, String::new()))
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        let body = std::str::from_utf8(&bytes).expect("Body is not utf8");
+        assert!(body.contains("Prairie Moon"), "{uri}");
+    }
+
+    let response = app
+        .as_service()
+        .call(send("GET", "/sample/list?origin=collected", String::new()))
+        .await
+        .expect("Failed to execute request");
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let body = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    assert!(!body.contains(&format!(">S{id:04}<")));
+    assert!(body.contains(">S0001<"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_label_queue(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let send = |method: &str, body: String| {
+        Request::builder()
+            .uri(app_url("/sample/labels"))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let labels = |response: axum::response::Response| async {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+
+    let response = app
+        .as_service()
+        .call(send("GET", String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(labels(response)
+        .await
+        .contains("There are no labels waiting to be printed"));
+
+    // editing samples queues their labels
+    for id in [1, 3, 4] {
+        let mut sample = Sample::load(id, &pool)
+            .await
+            .expect("Failed to load sample");
+        sample.quantity = Some(10);
+        sample.update(&pool).await.expect("Failed to update sample");
+    }
+    let response = app
+        .as_service()
+        .call(send("GET", String::new()))
+        .await
+        .expect("Failed to execute request");
+    let body = labels(response).await;
+    assert!(body.contains("S0001"));
+    assert!(body.contains("S0003"));
+
+    // only the labels that were submitted are marked as printed, and samples of other users are
+    // ignored
+    let response = app
+        .as_service()
+        .call(send("POST", "sample=1&sample=4".to_string()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let pending: Vec<i64> =
+        sqlx::query_scalar("SELECT sampleid FROM sc_label_queue WHERE labelprinted IS NULL")
+            .fetch_all(&pool)
+            .await
+            .expect("Failed to query label queue");
+    assert_eq!(pending, [3, 4]);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_inline_edit(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let send = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let text = |response: axum::response::Response| async {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    let url = sample_path(1, &pool).await;
+
+    let response = app
+        .as_service()
+        .call(send(
+            "GET",
+            &format!("{url}/inline/quantity/edit"),
+            String::new(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = text(response).await;
+    assert!(body.contains("hx-patch"));
+    assert!(body.contains(r#"name="quantity""#));
+
+    let version = Sample::load(1, &pool)
+        .await
+        .expect("Failed to load sample")
+        .version;
+    let response = app
+        .as_service()
+        .call(send(
+            "PATCH",
+            &format!("{url}/inline/quantity"),
+            format!("version={version}&quantity=345"),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = text(response).await;
+    assert!(body.contains("345"));
+    assert!(!body.contains("hx-patch"));
+    let sample = Sample::load(1, &pool).await.expect("Failed to load sample");
+    assert_eq!(sample.quantity, Some(345));
+
+    // invalid values are shown in the editor along with the error
+    for (field, body, error) in [
+        (
+            "quantity",
+            "quantity=lots",
+            "&#x27;lots&#x27; is not a valid quantity",
+        ),
+        ("quantity", "quantity=-3", "the quantity cannot be negative"),
+        ("date", "month=13&year=2023", "13 is not a valid month"),
+        ("date", "month=4&year=", "a month requires a year as well"),
+    ] {
+        let response = app
+            .as_service()
+            .call(send(
+                "PATCH",
+                &format!("{url}/inline/{field}"),
+                format!("version={}&{body}", sample.version),
+            ))
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = text(response).await;
+        assert!(body.contains("hx-patch"), "{field}: {body}");
+        assert!(body.contains(error), "{field}: {body}");
+    }
+
+    // a stale version is reported as a conflict
+    let response = app
+        .as_service()
+        .call(send(
+            "PATCH",
+            &format!("{url}/inline/notes"),
+            format!("version={version}&notes=stale"),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert!(text(response).await.contains("modified by someone else"));
+
+    let response = app
+        .as_service()
+        .call(send(
+            "PATCH",
+            &format!("{url}/inline/date"),
+            format!("version={}&month=6&year=2020", sample.version),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert!(text(response).await.contains("6/2020"));
+    let sample = Sample::load(1, &pool).await.expect("Failed to load sample");
+    assert_eq!((sample.month, sample.year), (Some(6), Some(2020)));
+
+    // samples of other users can't be edited
+    let response = app
+        .as_service()
+        .call(send(
+            "PATCH",
+            &format!("{}/inline/quantity", sample_path(4, &pool).await),
+            "quantity=1".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_ne!(response.status(), StatusCode::OK);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_conservation_permits(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    Listing::replace_region("MN", &[(43254, ListingStatus::Threatened)], &pool)
+        .await
+        .expect("Failed to save listings");
+
+    let send = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let text = |response: axum::response::Response| async {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    let new_sample = || "taxon=43254&source=1&month=&year=2024&quantity=&notes=".to_string();
+
+    // listed taxa are flagged
+    for uri in ["/taxonomy/43254".to_string(), sample_path(1, &pool).await] {
+        let response = app
+            .as_service()
+            .call(send("GET", &uri, String::new()))
+            .await
+            .expect("Failed to execute request");
+        assert!(
+            text(response).await.contains("conservation-warning"),
+            "{uri}"
+        );
+    }
+
+    // by default, a listed taxon can't be added without a permit
+    let response = app
+        .as_service()
+        .call(send("POST", "/sample/new", new_sample()))
+        .await
+        .expect("Failed to execute request");
+    assert!(response.headers().get("HX-Redirect").is_none());
+    assert!(text(response)
+        .await
+        .contains("requires a collecting permit"));
+
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            "/user/me/permits",
+            "taxon=43254&region=mn&number=SP-1234&expires=&notes=".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert!(text(response).await.contains("SP-1234"));
+    let permits = Permit::load_all_user(1, &pool)
+        .await
+        .expect("Failed to load permits");
+    assert_eq!(permits.len(), 1);
+    assert_eq!(permits[0].region, "MN");
+
+    let response = app
+        .as_service()
+        .call(send("POST", "/sample/new", new_sample()))
+        .await
+        .expect("Failed to execute request");
+    assert!(response.headers().get("HX-Redirect").is_some());
+
+    // permits of other users can't be removed
+    let mut other = Permit::new(2, 43254, "MN", "OTHER-1".to_string());
+    other.insert(&pool).await.expect("Failed to insert permit");
+    let response = app
+        .as_service()
+        .call(send(
+            "DELETE",
+            &format!("/user/me/permits/{}", other.id),
+            String::new(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_ne!(response.status(), StatusCode::OK);
+
+    let response = app
+        .as_service()
+        .call(send(
+            "DELETE",
+            &format!("/user/me/permits/{}", permits[0].id),
+            String::new(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(Permit::load_all_user(1, &pool)
+        .await
+        .expect("Failed to load permits")
+        .is_empty());
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_invasive_warnings(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    sqlx::query(
+        "INSERT INTO mntaxa (tsn, native_status, invasive_status) VALUES (40683, 'I', 'RN')",
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to insert invasive status");
+
+    let mut get = |uri: &str| {
+        let req = Request::builder()
+            .uri(app_url(uri))
+            .method("GET")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request");
+        app.as_service().call(req)
+    };
+    let text = |response: axum::response::Response| async {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+
+    let response = get("/sample/warnings?taxon=40683")
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(text(response).await.contains("invasive-warning"));
+    for taxon in ["43254", "Elym", ""] {
+        let response = get(&format!("/sample/warnings?taxon={taxon}"))
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(
+            !text(response).await.contains("invasive-warning"),
+            "{taxon}"
+        );
+    }
+
+    // the forecast only includes invasive taxa on request
+    let response = get("/sample/forecast")
+        .await
+        .expect("Failed to execute request");
+    assert!(!text(response).await.contains("Elymus canadensis"));
+    let response = get("/sample/forecast?include_invasive=true")
+        .await
+        .expect("Failed to execute request");
+    assert!(text(response).await.contains("Elymus canadensis"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_sample_history(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let send = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let text = |response: axum::response::Response| async {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    let url = sample_path(1, &pool).await;
+    let original = Sample::load(1, &pool).await.expect("Failed to load sample");
+
+    let response = app
+        .as_service()
+        .call(send(
+            "PATCH",
+            &format!("{url}/inline/quantity"),
+            format!("version={}&quantity=345", original.version),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .as_service()
+        .call(send("GET", &format!("{url}/history"), String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = text(response).await;
+    assert!(body.contains("Quantity"));
+    assert!(body.contains("345"));
+    assert!(body.contains("testuser"));
+    assert!(body.contains("Revert"));
+
+    let history = Change::load_sample(1, &pool).await.unwrap();
+    assert_eq!(history.len(), 1);
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            &format!("{url}/history/{}/revert", history[0].id),
+            String::new(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert!(response.headers().get("HX-Redirect").is_some());
+    let sample = Sample::load(1, &pool).await.expect("Failed to load sample");
+    assert_eq!(sample.quantity, original.quantity);
+    assert_eq!(Change::load_sample(1, &pool).await.unwrap().len(), 2);
+
+    // changes can only be reverted on the sample that they belong to
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            &format!(
+                "{}/history/{}/revert",
+                sample_path(2, &pool).await,
+                history[0].id
+            ),
+            String::new(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // the history of other users' samples isn't visible
+    let response = app
+        .as_service()
+        .call(send(
+            "GET",
+            &format!("{}/history", sample_path(4, &pool).await),
+            String::new(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_ne!(response.status(), StatusCode::OK);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_import_samples(pool: Pool<Sqlite>) {
+    use libseed::import::{ImportStatus, SampleImport};
+
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let get = |uri: &str| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method("GET")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request")
+    };
+    let body = |response: axum::response::Response| async move {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8(bytes.to_vec()).expect("Body is not utf8")
+    };
+
+    let csv = "Species,Site,Year,Remarks\n40683,Test source 1,2023,Dry\nElymus nonexistens,Test source 1,2023,\n40683,Roadside,2022,\n";
+    let upload = format!(
+        "--BOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"seeds.csv\"\r\nContent-Type: text/csv\r\n\r\n{csv}\r\n--BOUNDARY--\r\n"
+    );
+    let response = app
+        .as_service()
+        .call(
+            Request::builder()
+                .uri(app_url("/sample/import"))
+                .method("POST")
+                .header(CONTENT_TYPE, "multipart/form-data; boundary=BOUNDARY")
+                .header("Cookie", cookie.clone())
+                .body(Body::from(upload))
+                .expect("Failed to build request"),
+        )
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let imports = SampleImport::load_all_user(1, &pool).await.unwrap();
+    assert_eq!(imports.len(), 1);
+    let import = &imports[0];
+    assert_eq!(import.total, 3);
+    let url = format!("/sample/import/{}", import.id);
+    assert_eq!(
+        response.headers().get("location").unwrap(),
+        app_url(&url).as_str()
+    );
+
+    // the columns with a recognized name are mapped already
+    let response = app.as_service().call(get(&url)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert!(html.contains("import-columns"));
+    assert!(html.contains("value=\"taxon\" selected"));
+    assert!(html.contains("value=\"notes\" selected"));
+
+    let response = app
+        .as_service()
+        .call(get(&format!("{url}/preview?column-0=taxon&column-2=year")))
+        .await
+        .unwrap();
+    let html = body(response).await;
+    assert!(html.contains("No column is mapped to the &#x27;Source&#x27; field"));
+    let response = app
+        .as_service()
+        .call(get(&format!(
+            "{url}/preview?column-0=taxon&column-1=source&column-2=year"
+        )))
+        .await
+        .unwrap();
+    let html = body(response).await;
+    assert_eq!(html.matches("table-danger").count(), 1);
+    assert!(html.contains("Elymus nonexistens"));
+    assert!(html.contains("new</span>"));
+
+    let response = app
+        .as_service()
+        .call(
+            Request::builder()
+                .uri(app_url(&url))
+                .method("POST")
+                .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .header("Cookie", cookie.clone())
+                .body(Body::from("column-0=taxon&column-1=source&column-2=year"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key("HX-Redirect"));
+    let mut import = SampleImport::load(import.id, &pool).await.unwrap();
+    for _ in 0..50 {
+        if import.status != ImportStatus::Running {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        import = SampleImport::load(import.id, &pool).await.unwrap();
+    }
+    assert_eq!(import.status, ImportStatus::Finished);
+    assert_eq!(import.added, 2);
+
+    let response = app
+        .as_service()
+        .call(get(&format!("{url}/progress")))
+        .await
+        .unwrap();
+    let html = body(response).await;
+    assert!(html.contains("Imported 2 samples from seeds.csv"));
+    assert!(html.contains("1 row could not be imported"));
+    let response = app
+        .as_service()
+        .call(get(&format!("{url}/errors")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = body(response).await;
+    assert!(report.starts_with("Line,Error,Species,Site,Year,Remarks\n3,"));
+    assert!(report.contains("Elymus nonexistens"));
+
+    // it can't be started again
+    let response = app
+        .as_service()
+        .call(
+            Request::builder()
+                .uri(app_url(&url))
+                .method("POST")
+                .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .header("Cookie", cookie.clone())
+                .body(Body::from("column-0=taxon&column-1=source"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // the imports of other users can't be seen
+    let other = SampleImport::create(2, "other.csv".to_string(), &pool)
+        .await
+        .unwrap();
+    let response = app
+        .as_service()
+        .call(get(&format!("/sample/import/{}", other.id)))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_import_samples_in_chunks(pool: Pool<Sqlite>) {
+    use libseed::import::{ImportStatus, SampleImport};
+
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let request = |method: &str, uri: &str, body: Body| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+
+    let response = app
+        .as_service()
+        .call(request(
+            "POST",
+            "/sample/import/new",
+            Body::from("filename=big.csv"),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response
+        .headers()
+        .get("location")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    let import = &SampleImport::load_all_user(1, &pool).await.unwrap()[0];
+    assert_eq!(import.status, ImportStatus::Uploading);
+    let url = format!("/sample/import/{}", import.id);
+    assert_eq!(location, app_url(&url));
+
+    // the chunks are split in the middle of a row
+    let csv = "Species,Site\n40683,Test source 1\n40683,Roadside\n";
+    for (n, chunk) in [&csv[..20], &csv[20..]].into_iter().enumerate() {
+        let response = app
+            .as_service()
+            .call(request(
+                "PUT",
+                &format!("{url}/chunk/{n}"),
+                Body::from(chunk.to_string()),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+    // chunks can't be skipped
+    let response = app
+        .as_service()
+        .call(request("PUT", &format!("{url}/chunk/5"), Body::from("x")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    // and the import can't be started before the upload is finished
+    let response = app
+        .as_service()
+        .call(request(
+            "POST",
+            &url,
+            Body::from("column-0=taxon&column-1=source"),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = app
+        .as_service()
+        .call(request("POST", &format!("{url}/finish"), Body::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let import = SampleImport::load(import.id, &pool).await.unwrap();
+    assert_eq!(import.status, ImportStatus::Uploaded);
+    assert_eq!(import.total, 2);
+    let response = app
+        .as_service()
+        .call(request("GET", &url, Body::empty()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    let html = String::from_utf8(bytes.to_vec()).unwrap();
+    assert!(html.contains("Roadside"));
+    assert!(html.contains("Import 2 rows"));
+
+    // a file without a header row is rejected and removed again
+    let response = app
+        .as_service()
+        .call(request(
+            "POST",
+            "/sample/import/new",
+            Body::from("filename=empty.csv"),
+        ))
+        .await
+        .unwrap();
+    let location = response
+        .headers()
+        .get("location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    let empty = location.rsplit('/').next().unwrap().parse().unwrap();
+    let response = app
+        .as_service()
+        .call(request(
+            "POST",
+            &format!("/sample/import/{empty}/finish"),
+            Body::empty(),
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(SampleImport::load(empty, &pool).await.is_err());
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_delete_sample_without_javascript(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let url = format!("{}/delete", sample_path(1, &pool).await);
+
+    // a plain link leads to a confirmation page with a regular form
+    let req = Request::builder()
+        .uri(app_url(&url))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = String::from_utf8(bytes.to_vec()).expect("Body is not utf8");
+    assert!(html.contains("id=\"delete-confirmation\""));
+    assert!(Sample::load(1, &pool).await.is_ok());
+
+    let req = Request::builder()
+        .uri(app_url(&url))
+        .method("POST")
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        response.headers().get("location").unwrap(),
+        &app_url("/sample/list")
+    );
+    assert!(Sample::load(1, &pool).await.is_err());
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_bulk_delete_samples(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let own = Sample::load(2, &pool).await.expect("Failed to load sample");
+    let other = Sample::load(4, &pool).await.expect("Failed to load sample");
+    let params = serde_urlencoded::to_string([
+        ("sample", own.uuid.to_string()),
+        ("sample", other.uuid.to_string()),
+    ])
+    .expect("Failed to serialize params");
+    let body = |response: axum::response::Response| async move {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8(bytes.to_vec()).expect("Body is not utf8")
+    };
+
+    // the confirmation page summarizes what will be removed without deleting anything
+    let req = Request::builder()
+        .uri(app_url(&format!("/sample/delete?{params}")))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert!(html.contains("id=\"delete-impact\""));
+    assert!(html.contains(&format!("value=\"{}\"", own.uuid)));
+    // the sample of the other user is listed as skipped
+    assert_eq!(html.matches("bulk-skipped").count(), 2);
+    assert!(Sample::load(own.id, &pool).await.is_ok());
+
+    let req = Request::builder()
+        .uri(app_url("/sample/delete"))
+        .method("POST")
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header("Cookie", cookie.clone())
+        .body(Body::from(params))
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert!(html.contains("Deleted 1 of 2 samples"));
+    assert_eq!(html.matches("bulk-deleted").count(), 1);
+    assert_eq!(html.matches("bulk-skipped").count(), 1);
+    assert!(Sample::load(own.id, &pool).await.is_err());
+    assert!(Sample::load(other.id, &pool).await.is_ok());
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_quality_grades(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let request = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let body = |response: axum::response::Response| async move {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8(bytes.to_vec()).expect("Body is not utf8")
+    };
+
+    let response = app
+        .as_service()
+        .call(request("POST", "/sample/grades/default", String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body(response).await.matches("quality-grade\"").count(), 3);
+
+    // a label that is already used is reported to the user
+    let response = app
+        .as_service()
+        .call(request(
+            "POST",
+            "/sample/grades",
+            "label=A&rank=4&criteria=".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert!(html.contains("The scale already has a grade &#x27;A&#x27;"));
+    assert_eq!(html.matches("quality-grade\"").count(), 3);
+
+    let scale = QualityGrade::load_scale(1, &pool)
+        .await
+        .expect("Failed to load scale");
+    let response = app
+        .as_service()
+        .call(request(
+            "PUT",
+            &format!("{}/grade", sample_path(1, &pool).await),
+            format!("grade={}", scale[0].id),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        Sample::load(1, &pool).await.unwrap().grade.as_ref(),
+        Some(&scale[0])
+    );
+
+    // the list can be filtered by grade
+    let response = app
+        .as_service()
+        .call(request(
+            "GET",
+            &format!("/sample/list?grade={}&sort=grade", scale[0].id),
+            String::new(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body(response).await.matches("sample-item").count(), 1);
+
+    // grades of other users can't be edited
+    let mut other = QualityGrade::new(2, "X".to_string(), 1, None);
+    other.insert(&pool).await.expect("Failed to insert grade");
+    let response = app
+        .as_service()
+        .call(request(
+            "DELETE",
+            &format!("/sample/grades/{}", other.id),
+            String::new(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // removing a grade leaves its samples ungraded
+    let response = app
+        .as_service()
+        .call(request(
+            "DELETE",
+            &format!("/sample/grades/{}", scale[0].id),
+            String::new(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(Sample::load(1, &pool).await.unwrap().grade, None);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_sample_photos(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let path = sample_path(2, &pool).await;
+    let request = |method: &str, uri: &str| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header("Cookie", cookie.clone())
+    };
+    const JPEG: &[u8] = b"\xff\xd8\xff\xe0 a jpeg";
+    let upload = |content_type: &str, data: &[u8]| {
+        let mut body = format!(
+            "--BOUNDARY\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"seeds\"\r\nContent-Type: {content_type}\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n--BOUNDARY\r\nContent-Disposition: form-data; name=\"caption\"\r\n\r\nCleaned seeds\r\n--BOUNDARY--\r\n");
+        Body::from(body)
+    };
+    let body = |response: axum::response::Response| async move {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8(bytes.to_vec()).expect("Body is not utf8")
+    };
+
+    let response = app
+        .as_service()
+        .call(
+            request("POST", &format!("{path}/photos"))
+                .header(CONTENT_TYPE, "multipart/form-data; boundary=BOUNDARY")
+                .body(upload("application/pdf", b"%PDF-1.4"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body(response)
+        .await
+        .contains("Photos must be JPEG, PNG, GIF or WebP images"));
+    assert!(Photo::load_sample(2, &pool).await.unwrap().is_empty());
+
+    // the content has to be an image of the declared type, not just anything labelled as one
+    for (content_type, data) in [
+        ("image/png", &b"<html><script>alert(1)</script></html>"[..]),
+        ("image/png", JPEG),
+    ] {
+        let response = app
+            .as_service()
+            .call(
+                request("POST", &format!("{path}/photos"))
+                    .header(CONTENT_TYPE, "multipart/form-data; boundary=BOUNDARY")
+                    .body(upload(content_type, data))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body(response)
+            .await
+            .contains("The file is not a valid image of the given type"));
+        assert!(Photo::load_sample(2, &pool).await.unwrap().is_empty());
+    }
+
+    let response = app
+        .as_service()
+        .call(
+            request("POST", &format!("{path}/photos"))
+                .header(CONTENT_TYPE, "multipart/form-data; boundary=BOUNDARY")
+                .body(upload("image/jpeg", JPEG))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let photos = Photo::load_sample(2, &pool).await.unwrap();
+    assert_eq!(photos.len(), 1);
+    let url = format!("{path}/photos/{}", photos[0].id);
+    // urls are escaped in the html
+    let link = app_url(&url).replace('/', "&#x2f;");
+    assert!(body(response).await.contains(&link));
+
+    let response = app
+        .as_service()
+        .call(request("GET", &url).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(CONTENT_TYPE).map(|v| v.as_bytes()),
+        Some(&b"image/jpeg"[..])
+    );
+    assert_eq!(
+        response
+            .headers()
+            .get("X-Content-Type-Options")
+            .map(|v| v.as_bytes()),
+        Some(&b"nosniff"[..])
+    );
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    assert_eq!(&bytes[..], JPEG);
+
+    // the photo is shown in the gallery of the sample's taxon, captioned with the sample's details
+    let response = app
+        .as_service()
+        .call(
+            request("GET", "/taxonomy/40683")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert!(html.contains("taxon-gallery"));
+    assert!(html.contains("Cleaned seeds"));
+    assert!(html.contains(&link));
+    let response = app
+        .as_service()
+        .call(
+            request("GET", "/taxonomy/43254")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert!(!body(response).await.contains("taxon-gallery"));
+
+    // photos of other samples can't be reached through this sample
+    let response = app
+        .as_service()
+        .call(
+            request(
+                "GET",
+                &format!("{}/photos/{}", sample_path(3, &pool).await, photos[0].id),
+            )
+            .body(Body::empty())
+            .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app
+        .as_service()
+        .call(request("DELETE", &url).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body(response).await.contains("No photos"));
+    assert!(Photo::load_sample(2, &pool).await.unwrap().is_empty());
+}
@@ -0,0 +1,622 @@
+use super::*;
+use axum::http::header::CONTENT_TYPE;
+use libseed::{
+    loadable::Loadable,
+    monitoring::{Schedule, Visit},
+    organization::{OrgRole, Organization},
+    source::{HabitatType, SoilMoisture, Source},
+};
+use test_log::test;
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "assigned-samples")
+    )
+))]
+async fn test_delete_source_with_samples(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let url = source_path(1, &pool).await;
+    let delete = |query: &str| {
+        Request::builder()
+            .uri(app_url(&format!("{url}{query}")))
+            .method("DELETE")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request")
+    };
+
+    // the user is asked what to do with the samples first
+    let response = app
+        .as_service()
+        .call(delete(""))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("HX-Redirect").is_none());
+    assert!(Source::load(1, &pool).await.is_ok());
+
+    let response = app
+        .as_service()
+        .call(delete("?samples=reassign&reassign=2"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("HX-Redirect").is_some());
+    assert!(Source::load(1, &pool).await.is_err());
+    let src = Source::load(2, &pool).await.unwrap();
+    assert_eq!(src.count_samples(&pool).await.unwrap(), 3);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "assigned-samples")
+    )
+))]
+async fn test_delete_source_without_javascript(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let url = format!("{}/delete", source_path(1, &pool).await);
+    let confirm = |body: &str| {
+        Request::builder()
+            .uri(app_url(&url))
+            .method("POST")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body.to_string())
+            .expect("Failed to build request")
+    };
+
+    // the form has to say what happens to the samples of the source
+    let response = app
+        .as_service()
+        .call(confirm(""))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(Source::load(1, &pool).await.is_ok());
+
+    let response = app
+        .as_service()
+        .call(confirm("samples=reassign&reassign=2"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert!(Source::load(1, &pool).await.is_err());
+    let src = Source::load(2, &pool).await.unwrap();
+    assert_eq!(src.count_samples(&pool).await.unwrap(), 3);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_nearby_sources(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool).await.expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let mut get = |query: &str| {
+        let req = Request::builder()
+            .uri(app_url(&format!("/source/near{query}")))
+            .method("GET")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request");
+        app.as_service().call(req)
+    };
+    let text = |response: axum::response::Response| async {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+
+    // without a location, only the search form is shown
+    let response = get("").await.expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!text(response).await.contains("nearby-sources"));
+
+    let response = get("?latitude=40.15&longitude=-90.123&radius=5")
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = text(response).await;
+    assert!(html.contains("Test source 1"));
+    assert!(!html.contains("Test source 2"));
+    assert!(html.contains("3.0 km"));
+    // only the user's own samples are listed
+    assert!(html.contains("S0001:"));
+    assert!(html.contains("S0003:"));
+    assert!(!html.contains("S0004:"));
+
+    let response = get("?latitude=95&longitude=-90.123")
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(text(response).await.contains("invalid latitude"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_source_habitat(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let url = source_path(1, &pool).await;
+    let send = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .header("HX-Request", "true")
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let text = |response: axum::response::Response| async {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+
+    let response = app
+        .as_service()
+        .call(send(
+            "PUT",
+            &url,
+            "name=Test+source+1&description=&latitude=&longitude=&habitat=prairie&moisture=dry-mesic&light=".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let src = Source::load(1, &pool).await.unwrap();
+    assert_eq!(src.habitat, Some(HabitatType::Prairie));
+    assert_eq!(src.moisture, Some(SoilMoisture::DryMesic));
+    assert_eq!(src.light, None);
+
+    // an unknown value is rejected
+    let response = app
+        .as_service()
+        .call(send(
+            "PUT",
+            &url,
+            "name=Test+source+1&description=&latitude=&longitude=&habitat=desert".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = app
+        .as_service()
+        .call(send("GET", "/source/list?habitat=prairie", String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = text(response).await;
+    assert!(html.contains("Test source 1"));
+    assert!(!html.contains("Test source 2"));
+    assert!(html.contains("Dry mesic"));
+    let response = app
+        .as_service()
+        .call(send("GET", "/source/list?moisture=wet", String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert!(!text(response).await.contains("Test source 1"));
+
+    // samples can be filtered by the conditions of their source
+    let response = app
+        .as_service()
+        .call(send(
+            "GET",
+            "/sample/list?habitat=prairie&moisture=dry-mesic",
+            String::new(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = text(response).await;
+    assert!(html.contains("S0001"));
+    assert!(!html.contains("S0004"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_sensitive_source(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    // source 1 belongs to another user and is shared with an organization that the test user can
+    // only view
+    let mut org = Organization::new("Rare Plant Survey".to_string(), None);
+    org.insert(2, &pool).await.unwrap();
+    org.set_member(1, OrgRole::Viewer, &pool).await.unwrap();
+    sqlx::query("UPDATE sc_sources SET userid=2, srcorgid=?, srcsensitive=1 WHERE srcid=1")
+        .bind(org.id)
+        .execute(&pool)
+        .await
+        .expect("Failed to update source");
+    let url = source_path(1, &pool).await;
+    let mut get = |uri: String| {
+        let req = Request::builder()
+            .uri(app_url(&uri))
+            .method("GET")
+            .header("Cookie", cookie.clone())
+            .body(Body::empty())
+            .expect("Failed to build request");
+        app.as_service().call(req)
+    };
+    let text = |response: axum::response::Response| async {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+
+    let response = get(url.clone()).await.expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = text(response).await;
+    assert!(html.contains("location of this source is protected"));
+    assert!(html.contains("40.1"));
+    assert!(!html.contains("40.123"));
+    assert!(!html.contains("90.123"));
+
+    // the distance to a nearby sensitive source doesn't reveal its location either
+    let response = get("/source/near?latitude=40.123&longitude=-90.123&radius=20".to_string())
+        .await
+        .expect("Failed to execute request");
+    let html = text(response).await;
+    assert!(html.contains("Test source 1"));
+    assert!(!html.contains(">0.0 km"));
+
+    // members that can edit the source see the exact location
+    org.set_member(1, OrgRole::Member, &pool).await.unwrap();
+    let response = get(url).await.expect("Failed to execute request");
+    let html = text(response).await;
+    assert!(!html.contains("location of this source is protected"));
+    assert!(html.contains("40.123"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_merge_duplicate_sources(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let send = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .header("HX-Request", "true")
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let text = |response: axum::response::Response| async {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    let mut west = Source::new(
+        "Test source 1 west".to_string(),
+        None,
+        Some(40.124),
+        Some(-90.124),
+        1,
+    );
+    west.insert(&pool).await.unwrap();
+    sqlx::query("UPDATE sc_samples SET srcid=? WHERE sampleid=1")
+        .bind(west.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    // a possible duplicate that belongs to somebody else
+    let mut other = Source::new(
+        "Test source 1 north".to_string(),
+        None,
+        Some(40.125),
+        Some(-90.125),
+        2,
+    );
+    other.insert(&pool).await.unwrap();
+
+    let response = app
+        .as_service()
+        .call(send("GET", "/source/dedupe", String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = text(response).await;
+    assert!(html.contains("Test source 1 west"));
+    assert!(!html.contains("Test source 1 north"));
+
+    let response = app
+        .as_service()
+        .call(send(
+            "GET",
+            &format!("/source/dedupe/merge?keep=1&remove={}", west.id),
+            String::new(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(text(response)
+        .await
+        .contains("1 sample will be moved to Test source 1"));
+
+    // sources of other users can't be merged
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            "/source/dedupe/merge",
+            format!("keep=1&remove={}", other.id),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_ne!(response.status(), StatusCode::OK);
+    assert!(Source::load(other.id, &pool).await.is_ok());
+
+    let before = Source::load(1, &pool)
+        .await
+        .unwrap()
+        .count_samples(&pool)
+        .await
+        .unwrap();
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            "/source/dedupe/merge",
+            format!("keep=1&remove={}", west.id),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get("HX-Redirect").is_some());
+    assert!(Source::load(west.id, &pool).await.is_err());
+    let src = Source::load(1, &pool).await.unwrap();
+    assert_eq!(src.count_samples(&pool).await.unwrap(), before + 1);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "assigned-samples")
+    )
+))]
+async fn test_bulk_delete_sources(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let first = Source::load(1, &pool).await.expect("Failed to load source");
+    let second = Source::load(2, &pool).await.expect("Failed to load source");
+    let nsamples = first.count_samples(&pool).await.unwrap();
+    assert!(nsamples > 0);
+    let delete = |samples: Option<&str>| {
+        let mut params = vec![("source", first.uuid.to_string())];
+        if let Some(samples) = samples {
+            params.push(("samples", samples.to_string()));
+        }
+        let params = serde_urlencoded::to_string(params).expect("Failed to serialize params");
+        Request::builder()
+            .uri(app_url("/source/delete"))
+            .method("POST")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(Body::from(params))
+            .expect("Failed to build request")
+    };
+
+    let req = Request::builder()
+        .uri(app_url(&format!(
+            "/source/delete?source={}&source={}",
+            first.uuid, second.uuid
+        )))
+        .method("GET")
+        .header("Cookie", cookie.clone())
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = String::from_utf8(bytes.to_vec()).expect("Body is not utf8");
+    assert!(html.contains("id=\"delete-impact\""));
+    assert!(html.contains("name=\"samples\" value=\"delete\""));
+
+    // a source that still has samples is skipped
+    let response = app
+        .as_service()
+        .call(delete(None))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(Source::load(1, &pool).await.is_ok());
+
+    // unless its samples are deleted with it
+    let response = app
+        .as_service()
+        .call(delete(Some("delete")))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(Source::load(1, &pool).await.is_err());
+    assert!(Source::load(2, &pool).await.is_ok());
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_source_monitoring(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let url = source_path(1, &pool).await;
+    let request = |method: &str, uri: &str, params: &str| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(Body::from(params.to_string()))
+            .expect("Failed to build request")
+    };
+    let body = |response: axum::response::Response| async move {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8(bytes.to_vec()).expect("Body is not utf8")
+    };
+
+    let response = app
+        .as_service()
+        .call(request(
+            "PUT",
+            &format!("{url}/monitoring"),
+            "interval=0&season_start=1&season_end=12",
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(Schedule::load(1, 1, &pool).await.unwrap().is_none());
+
+    // a source that is in season all year is due for a visit right away
+    let response = app
+        .as_service()
+        .call(request(
+            "PUT",
+            &format!("{url}/monitoring"),
+            "interval=2&season_start=1&season_end=12",
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body(response).await.contains("id=\"upcoming-visits\""));
+    assert_eq!(
+        Schedule::load(1, 1, &pool)
+            .await
+            .unwrap()
+            .map(|s| s.interval_weeks),
+        Some(2)
+    );
+
+    let response = app
+        .as_service()
+        .call(request(
+            "POST",
+            &format!("{url}/visits"),
+            "date=2024-07-22&taxon=40683&population=150&phenophase=seed-ripe&notes=",
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert!(html.contains("id=\"source-visits\""));
+    assert!(html.contains("Seed ripe"));
+    let visits = Visit::load_source(1, 1, &pool).await.unwrap();
+    assert_eq!(visits.len(), 1);
+    assert_eq!(visits[0].population, Some(150));
+
+    // the observation shows up in the phenology calendar
+    let response = app
+        .as_service()
+        .call(request("GET", "/sample/calendar", ""))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body(response).await;
+    assert!(html.contains("id=\"phenology-calendar\""));
+    assert!(html.contains("phenophase-seed-ripe"));
+    assert!(html.contains("id=\"calendar-visits\""));
+
+    let response = app
+        .as_service()
+        .call(request(
+            "DELETE",
+            &format!("{url}/visits/{}", visits[0].id),
+            "",
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(Visit::load_source(1, 1, &pool).await.unwrap().is_empty());
+
+    // only the users who look after a source can monitor it
+    let srcid = sqlx::query("INSERT INTO sc_sources (srcname, userid) VALUES ('Other', 2)")
+        .execute(&pool)
+        .await
+        .unwrap()
+        .last_insert_rowid();
+    let other = source_path(srcid, &pool).await;
+    let response = app
+        .as_service()
+        .call(request(
+            "PUT",
+            &format!("{other}/monitoring"),
+            "interval=2&season_start=1&season_end=12",
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
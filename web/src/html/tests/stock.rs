@@ -0,0 +1,112 @@
+use super::*;
+use libseed::stock::{Threshold, ThresholdTarget};
+use test_log::test;
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_low_stock(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let mut send = |method: &str, uri: String, body: String| {
+        let req = Request::builder()
+            .uri(app_url(&uri))
+            .method(method)
+            .header("Cookie", cookie.clone())
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+            .expect("Failed to build request");
+        let response = app.as_service().call(req);
+        async move {
+            let response = response.await.expect("Failed to execute request");
+            let status = response.status();
+            let bytes = response
+                .into_body()
+                .collect()
+                .await
+                .expect("Failed to read body")
+                .to_bytes();
+            (status, String::from_utf8_lossy(&bytes).to_string())
+        }
+    };
+
+    let (status, html) = send("GET", "/".to_string(), String::new()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!html.contains("low-stock-alert"));
+
+    // sample 2 has a quantity of 100
+    let sample = sample_path(2, &pool).await;
+    let (status, html) = send("GET", sample.clone(), String::new()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("stock-threshold"));
+    let (status, html) = send(
+        "PUT",
+        format!("{sample}/threshold"),
+        "quantity=-5".to_string(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("can&#x27;t be negative"));
+    let (status, _) = send(
+        "PUT",
+        format!("{sample}/threshold"),
+        "quantity=150".to_string(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        Threshold::load(1, ThresholdTarget::Sample(2), &pool)
+            .await
+            .unwrap()
+            .map(|t| t.quantity),
+        Some(150)
+    );
+
+    let (_, html) = send("GET", "/".to_string(), String::new()).await;
+    assert!(html.contains("low-stock-alert"));
+    assert!(html.contains("1 sample is low on stock"));
+    let (status, html) = send("GET", "/stock".to_string(), String::new()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(html.matches("low-stock-row").count(), 1);
+    assert!(html.contains("100 / 150"));
+
+    // an empty quantity removes the threshold, but the taxon threshold still applies
+    send(
+        "PUT",
+        format!("{sample}/threshold"),
+        "quantity=".to_string(),
+    )
+    .await;
+    let (status, _) = send(
+        "PUT",
+        "/taxonomy/40683/threshold".to_string(),
+        "quantity=200".to_string(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        Threshold::load(1, ThresholdTarget::Sample(2), &pool)
+            .await
+            .unwrap(),
+        None
+    );
+    let (_, html) = send("GET", "/stock".to_string(), String::new()).await;
+    assert!(html.contains("100 / 200"));
+    assert!(html.contains("(taxon)"));
+
+    // the thresholds of other users' samples can't be set
+    let (status, _) = send(
+        "PUT",
+        format!("{}/threshold", sample_path(4, &pool).await),
+        "quantity=10".to_string(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
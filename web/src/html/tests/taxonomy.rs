@@ -0,0 +1,500 @@
+use super::*;
+use libseed::{
+    cultivation::CultivationNotes,
+    loadable::Loadable,
+    organization::{OrgRole, Organization},
+    project::Allocation,
+    taxonomy::{
+        names::DisplayName,
+        staged::{StagedRelease, StagedStatus},
+        upgrade::{self, UpgradeReport},
+    },
+    user::User,
+};
+use test_log::test;
+
+async fn send(
+    app: &mut Router,
+    cookie: &str,
+    method: &str,
+    uri: &str,
+    body: String,
+) -> (StatusCode, String) {
+    let req = Request::builder()
+        .uri(app_url(uri))
+        .method(method)
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header("Cookie", cookie)
+        .body(body)
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    let status = response.status();
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    (
+        status,
+        String::from_utf8(bytes.to_vec()).expect("Body is not utf8"),
+    )
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_cultivation_notes(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let alloc = Allocation::load(1, &pool).await.unwrap();
+    let tsn = alloc.sample.taxon.id();
+
+    let params =
+        serde_urlencoded::to_string([("org", ""), ("text", "Sow **outdoors** in fall")]).unwrap();
+    let (status, html) = send(
+        &mut app,
+        &cookie,
+        "PUT",
+        &format!("/taxonomy/{tsn}/notes"),
+        params,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("<strong>outdoors</strong>"));
+
+    // the notes are shown on the taxon page and on the pages of allocated samples of the taxon
+    let (status, html) = send(
+        &mut app,
+        &cookie,
+        "GET",
+        &format!("/taxonomy/{tsn}"),
+        String::new(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("<strong>outdoors</strong>"));
+    let (status, html) = send(
+        &mut app,
+        &cookie,
+        "GET",
+        &format!(
+            "{}/sample/{}",
+            project_path(alloc.project.id, &pool).await,
+            alloc.uuid
+        ),
+        String::new(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("<strong>outdoors</strong>"));
+
+    // the notes of an organization can only be changed by its members
+    let mut org = Organization::new("Prairie Group".to_string(), None);
+    org.insert(2, &pool).await.unwrap();
+    let params = serde_urlencoded::to_string([
+        ("org", org.id.to_string().as_str()),
+        ("text", "Shared notes"),
+    ])
+    .unwrap();
+    let uri = format!("/taxonomy/{tsn}/notes");
+    let (status, _) = send(&mut app, &cookie, "PUT", &uri, params.clone()).await;
+    assert_ne!(status, StatusCode::OK);
+    let (status, _) = send(
+        &mut app,
+        &cookie,
+        "GET",
+        &format!("{uri}/edit?org={}", org.id),
+        String::new(),
+    )
+    .await;
+    assert_ne!(status, StatusCode::OK);
+    org.set_member(1, OrgRole::Member, &pool).await.unwrap();
+    let (status, html) = send(
+        &mut app,
+        &cookie,
+        "GET",
+        &format!("{uri}/edit?org={}", org.id),
+        String::new(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("Prairie Group"));
+    let (status, _) = send(&mut app, &cookie, "PUT", &uri, params).await;
+    assert_eq!(status, StatusCode::OK);
+    let notes = CultivationNotes::load_taxon(tsn, 1, &pool).await.unwrap();
+    assert_eq!(
+        notes.iter().map(|n| n.text.as_str()).collect::<Vec<_>>(),
+        vec!["Shared notes", "Sow **outdoors** in fall"]
+    );
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples", "projects")
+    )
+))]
+async fn test_display_names(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let alloc = Allocation::load(1, &pool).await.unwrap();
+    let tsn = alloc.sample.taxon.id();
+    let sample_uri = format!("/sample/{}", alloc.sample.uuid);
+
+    let params = serde_urlencoded::to_string([("org", ""), ("name", "Our local rye")]).unwrap();
+    let (status, html) = send(
+        &mut app,
+        &cookie,
+        "PUT",
+        &format!("/taxonomy/{tsn}/names"),
+        params,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("Our local rye"));
+    assert_eq!(
+        DisplayName::load_map(1, &pool).await.unwrap().get(&tsn),
+        Some(&"Our local rye".to_string())
+    );
+
+    // the name is shown instead of the ITIS name, which is still visible on the sample page
+    let (status, html) = send(&mut app, &cookie, "GET", &sample_uri, String::new()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("Our local rye"));
+    let (status, html) = send(&mut app, &cookie, "GET", "/taxonomy/names", String::new()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("Our local rye"));
+
+    // the names of an organization can only be changed by its members
+    let mut org = Organization::new("Prairie Group".to_string(), None);
+    org.insert(2, &pool).await.unwrap();
+    let params =
+        serde_urlencoded::to_string([("org", org.id.to_string().as_str()), ("name", "Shared rye")])
+            .unwrap();
+    let uri = format!("/taxonomy/{tsn}/names");
+    let (status, _) = send(&mut app, &cookie, "PUT", &uri, params.clone()).await;
+    assert_ne!(status, StatusCode::OK);
+    org.set_member(1, OrgRole::Member, &pool).await.unwrap();
+    let (status, html) = send(
+        &mut app,
+        &cookie,
+        "GET",
+        &format!("{uri}/edit?org={}", org.id),
+        String::new(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("Prairie Group"));
+    let (status, _) = send(&mut app, &cookie, "PUT", &uri, params).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // removing the personal name falls back to the name of the organization
+    let params = serde_urlencoded::to_string([("org", ""), ("name", "")]).unwrap();
+    let (status, _) = send(&mut app, &cookie, "PUT", &uri, params).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, html) = send(&mut app, &cookie, "GET", &sample_uri, String::new()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("Shared rye"));
+    assert!(!html.contains("Our local rye"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(path = "../../../../db/fixtures", scripts("users", "taxa"))
+))]
+async fn test_taxonomy_upgrade(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    upgrade::snapshot(&pool).await.unwrap();
+    sqlx::query("UPDATE taxonomic_units SET unit_name1='Leymus' WHERE tsn IN (40677, 40683)")
+        .execute(&pool)
+        .await
+        .unwrap();
+    libseed::taxonomy::refresh_complete_names(&pool)
+        .await
+        .unwrap();
+    let report = UpgradeReport::record(Some("ITIS 2024"), &pool)
+        .await
+        .unwrap();
+    let path = format!("/admin/taxonomy/{}", report.id);
+
+    // only administrators can see the upgrade reports
+    let (status, _) = send(&mut app, &cookie, "GET", &path, String::new()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let mut admin = User::load(1, &pool).await.unwrap();
+    admin.admin = true;
+    admin.update(&pool).await.unwrap();
+
+    let (status, html) = send(&mut app, &cookie, "GET", "/admin/taxonomy", String::new()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("ITIS 2024"));
+
+    let (status, html) = send(&mut app, &cookie, "GET", &path, String::new()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("Elymus canadensis"));
+    assert!(html.contains("Leymus canadensis"));
+    assert!(html.contains("renamed"));
+    assert!(html.contains("Poaceae"));
+
+    let (status, _) = send(
+        &mut app,
+        &cookie,
+        "GET",
+        "/admin/taxonomy/999",
+        String::new(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+/// Wait until the background job that works on a staged release is done
+async fn wait_for_release(id: i64, pool: &Pool<Sqlite>) -> StagedRelease {
+    for _ in 0..100 {
+        let release = StagedRelease::load(id, pool).await.unwrap();
+        if !release.status.is_running() {
+            return release;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("The staged release was not processed in time");
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(path = "../../../../db/fixtures", scripts("users", "taxa"))
+))]
+async fn test_stage_release(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    // a release that renames a genus, made from a copy of the current taxonomy
+    let path = std::env::temp_dir().join(format!("seedweb-release-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    sqlx::query("VACUUM INTO ?")
+        .bind(path.to_string_lossy().to_string())
+        .execute(&pool)
+        .await
+        .unwrap();
+    let release = sqlx::SqlitePool::connect(&format!("sqlite://{}", path.display()))
+        .await
+        .unwrap();
+    sqlx::query("UPDATE taxonomic_units SET unit_name1='Leymus' WHERE tsn=40677")
+        .execute(&release)
+        .await
+        .unwrap();
+    release.close().await;
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let upload = |name: &str, filename: &str, data: &[u8]| {
+        let mut body = format!(
+            "--BOUNDARY\r\nContent-Disposition: form-data; name=\"{name}\"; filename=\"{filename}\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n--BOUNDARY--\r\n");
+        Request::builder()
+            .uri(app_url("/admin/taxonomy/release"))
+            .method("POST")
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=BOUNDARY")
+            .header("Cookie", cookie.clone())
+            .body(Body::from(body))
+            .expect("Failed to build request")
+    };
+
+    // only administrators can stage releases
+    let response = app
+        .as_service()
+        .call(upload("file", "ITIS.sqlite", &data))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let mut admin = User::load(1, &pool).await.unwrap();
+    admin.admin = true;
+    admin.update(&pool).await.unwrap();
+
+    let response = app
+        .as_service()
+        .call(upload("url", "", b"ftp://example.com/itis.zip"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let response = app
+        .as_service()
+        .call(upload("file", "ITIS.sqlite", b""))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(StagedRelease::load_all(&pool).await.unwrap().is_empty());
+
+    let response = app
+        .as_service()
+        .call(upload("file", "ITIS.sqlite", &data))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let staged = StagedRelease::load_all(&pool).await.unwrap().remove(0);
+    let uri = format!("/admin/taxonomy/release/{}", staged.id);
+    assert_eq!(
+        response.headers().get("HX-Redirect").unwrap(),
+        app_url(&uri).as_str()
+    );
+    let staged = wait_for_release(staged.id, &pool).await;
+    assert_eq!(staged.status, StagedStatus::Ready);
+    assert_eq!(staged.nchanges, 1);
+
+    // the changes can be reviewed before the taxonomy is upgraded
+    let (status, html) = send(&mut app, &cookie, "GET", &uri, String::new()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(html.contains("Leymus"));
+    assert!(html.contains("renamed"));
+    assert!(html.contains("Upgrade the taxonomy"));
+    let name = || {
+        sqlx::query_scalar::<_, String>("SELECT complete_name FROM taxonomic_units WHERE tsn=40677")
+            .fetch_one(&pool)
+    };
+    assert_eq!(name().await.unwrap(), "Elymus");
+
+    let (status, _) = send(&mut app, &cookie, "POST", &uri, String::new()).await;
+    assert_eq!(status, StatusCode::OK);
+    let staged = wait_for_release(staged.id, &pool).await;
+    assert_eq!(staged.status, StagedStatus::Applied);
+    assert_eq!(name().await.unwrap(), "Leymus");
+    let report = UpgradeReport::load(staged.upgradeid.unwrap(), &pool)
+        .await
+        .unwrap();
+    assert_eq!(report.nchanges, 1);
+    // an applied release can't be applied again
+    let (status, _) = send(&mut app, &cookie, "POST", &uri, String::new()).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    // once the job is done, the progress sends the client to the recorded upgrade
+    let response = app
+        .as_service()
+        .call(
+            Request::builder()
+                .uri(app_url(&format!("{uri}/progress")))
+                .header("Cookie", cookie.clone())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers().get("HX-Redirect").unwrap(),
+        app_url(&format!("/admin/taxonomy/{}", report.id)).as_str()
+    );
+
+    let (status, _) = send(&mut app, &cookie, "DELETE", &uri, String::new()).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&mut app, &cookie, "GET", &uri, String::new()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(path = "../../../../db/fixtures", scripts("users", "taxa"))
+))]
+async fn test_search_pages(pool: Pool<Sqlite>) {
+    sqlx::query(
+        r#"WITH RECURSIVE N(i) AS (SELECT 1 UNION ALL SELECT i+1 FROM N WHERE i < 150)
+        INSERT INTO taxonomic_units (tsn, parent_tsn, unit_name1, unit_name2, name_usage,
+            credibility_rtng, initial_time_stamp, kingdom_id, rank_id, update_date,
+            complete_name, phylo_sort_seq)
+        SELECT 900000+i, 40677, 'Elymus', 'testspecies' || i, 'accepted', 'TWG standards met',
+            '2024-01-01', 3, 220, '2024-01-01', 'Elymus testspecies' || i, 50000+i FROM N"#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let (status, body) = send(
+        &mut app,
+        &cookie,
+        "GET",
+        "/taxonomy/search?taxon=&rank=Species",
+        String::new(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.matches(r#"class="scientific""#).count(), 100);
+    assert!(body.contains("Loading more taxa"));
+    let after = body
+        .split_once(r#""after": ""#)
+        .and_then(|(_, rest)| rest.split_once('"'))
+        .map(|(cursor, _)| cursor.to_string())
+        .expect("No cursor for the next page");
+
+    // the next page continues where the first one ended and is appended to the same list. The
+    // fixture has two more species.
+    let (status, body) = send(
+        &mut app,
+        &cookie,
+        "GET",
+        &format!("/taxonomy/search?taxon=&rank=Species&after={after}"),
+        String::new(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.matches(r#"class="scientific""#).count(), 52);
+    assert!(!body.contains("Loading more taxa"));
+    assert!(!body.contains("<ul"));
+    assert!(body.contains("Elymus testspecies150"));
+
+    // a name narrows the results down to a single page
+    let (status, body) = send(
+        &mut app,
+        &cookie,
+        "GET",
+        "/taxonomy/search?taxon=E.+testspecies12&rank=",
+        String::new(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.matches(r#"class="scientific""#).count(), 11);
+    assert!(!body.contains("Loading more taxa"));
+
+    let (status, _) = send(
+        &mut app,
+        &cookie,
+        "GET",
+        "/taxonomy/search?taxon=&after=bogus",
+        String::new(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = send(
+        &mut app,
+        &cookie,
+        "GET",
+        "/taxonomy/?rank=Genus",
+        String::new(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains(r#"<option value="Genus" selected>"#));
+}
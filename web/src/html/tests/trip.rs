@@ -0,0 +1,135 @@
+use super::*;
+use crate::test_app;
+use libseed::{
+    loadable::Loadable,
+    sample::Sample,
+    trip::{self, Trip},
+};
+use sqlx::{Pool, Sqlite};
+use test_log::test;
+use time::macros::date;
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(
+        path = "../../../../db/fixtures",
+        scripts("users", "sources", "taxa", "samples")
+    )
+))]
+async fn test_trips(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+
+    let send = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let text = |response: axum::response::Response| async {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            "/trip/new",
+            "name=Prairie+walk&date=2024-09-15&participants=Sam%0D%0AAlex&notes=".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert!(response.headers().get("HX-Redirect").is_some());
+    let trips = Trip::load_all(Some(trip::Filter::User(1).into()), &pool)
+        .await
+        .expect("Failed to load trips");
+    assert_eq!(trips.len(), 1);
+    let id = trips[0].id;
+    assert_eq!(trips[0].participants, ["Alex", "Sam"]);
+
+    // an invalid date is rejected
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            "/trip/new",
+            "name=Bog&date=September&participants=".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            &format!("/trip/{id}/targets"),
+            "taxon=40683&source=1".to_string(),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(text(response).await.contains("0 of 1 collected"));
+
+    // samples entered for the trip count towards its targets
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            "/sample/new",
+            format!("taxon=40683&source=1&month=9&year=2024&quantity=&notes=&trip={id}"),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert!(response.headers().get("HX-Redirect").is_some());
+    let response = app
+        .as_service()
+        .call(send("GET", &format!("/trip/{id}"), String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(text(response).await.contains("1 of 1 collected"));
+
+    // trips of other users can't be viewed or used for samples
+    let mut other = Trip::new("Other".to_string(), date!(2024 - 09 - 15), 2);
+    other.insert(&pool).await.expect("Failed to insert trip");
+    let response = app
+        .as_service()
+        .call(send("GET", &format!("/trip/{}", other.id), String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = app
+        .as_service()
+        .call(send(
+            "PUT",
+            &sample_path(1, &pool).await,
+            format!(
+                "taxon=43254&source=1&month=&year=&quantity=&notes=&trip={}",
+                other.id
+            ),
+        ))
+        .await
+        .expect("Failed to execute request");
+    assert!(response.headers().get("HX-Redirect").is_none());
+    assert_eq!(Sample::load(1, &pool).await.unwrap().trip, None);
+
+    let response = app
+        .as_service()
+        .call(send("DELETE", &format!("/trip/{id}"), String::new()))
+        .await
+        .expect("Failed to execute request");
+    assert!(response.headers().get("HX-Redirect").is_some());
+    assert!(Trip::load(id, &pool).await.is_err());
+}
@@ -0,0 +1,493 @@
+use super::*;
+use crate::{state::SharedState, MailInConfig, RegistrationConfig};
+use libseed::{
+    audit::AuditEntry,
+    loadable::Loadable,
+    mailin::MailInKey,
+    scope::UserScope,
+    user::{User, UserStatus},
+};
+use std::sync::Arc;
+use test_log::test;
+
+async fn update_profile(app: &mut Router, cookie: &str, timezone: &str) -> StatusCode {
+    let params = serde_urlencoded::to_string([
+        ("email", "test@domain.com"),
+        ("displayname", ""),
+        ("profile", ""),
+        ("timezone", timezone),
+    ])
+    .expect("Failed to serialize params");
+    let req = Request::builder()
+        .uri(app_url("/user/me"))
+        .method("PUT")
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .header("Cookie", cookie)
+        .body(params)
+        .expect("Failed to build request");
+    app.as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request")
+        .status()
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(path = "../../../../db/fixtures", scripts("users"))
+))]
+async fn test_user_timezone(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    sqlx::query("UPDATE sc_users SET usersince='2024-01-01 23:30:00' WHERE userid=1")
+        .execute(&pool)
+        .await
+        .expect("Failed to set registration date");
+
+    assert_ne!(
+        update_profile(&mut app, &cookie, "Not/AZone").await,
+        StatusCode::OK
+    );
+    assert_eq!(User::load(1, &pool).await.unwrap().timezone, None);
+    assert_eq!(
+        update_profile(&mut app, &cookie, "Europe/Berlin").await,
+        StatusCode::OK
+    );
+    assert_eq!(
+        User::load(1, &pool).await.unwrap().timezone.as_deref(),
+        Some("Europe/Berlin")
+    );
+
+    // the registration date is stored in UTC but displayed in the user's time zone
+    let req = Request::builder()
+        .uri(app_url("/user/me"))
+        .method("GET")
+        .header("Cookie", &cookie)
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = String::from_utf8(bytes.to_vec()).expect("Body is not utf8");
+    assert!(html.contains("2024-01-02"));
+}
+
+async fn send_mailin(app: &mut Router, cookie: &str, method: &str) -> (StatusCode, String) {
+    let req = Request::builder()
+        .uri(app_url("/user/me/mailin"))
+        .method(method)
+        .header("Cookie", cookie)
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    let status = response.status();
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    (
+        status,
+        String::from_utf8(bytes.to_vec()).expect("Body is not utf8"),
+    )
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(path = "../../../../db/fixtures", scripts("users"))
+))]
+async fn test_mailin_key(pool: Pool<Sqlite>) {
+    // notes by email are disabled unless the inbox is configured
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    assert_eq!(
+        send_mailin(&mut app, &cookie, "POST").await.0,
+        StatusCode::NOT_FOUND
+    );
+
+    let mut state = SharedState::test(pool.clone());
+    state.config.mail_in = Some(MailInConfig {
+        address: "notes@example.com".to_string(),
+        authserv_id: "mx.example.com".to_string(),
+        host: "pop.example.com".to_string(),
+        port: 995,
+        username: "notes".to_string(),
+        passwordfile: String::new(),
+        password: String::new(),
+        poll_minutes: 5,
+    });
+    let mut app = crate::app(Arc::new(state))
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let (status, html) = send_mailin(&mut app, &cookie, "POST").await;
+    assert_eq!(status, StatusCode::OK);
+    let key = MailInKey::load(1, &pool)
+        .await
+        .unwrap()
+        .expect("No key was generated");
+    assert!(html.contains(&format!("notes+{}@example.com", key.key)));
+
+    let (status, html) = send_mailin(&mut app, &cookie, "DELETE").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!html.contains(&key.key));
+    assert_eq!(MailInKey::load(1, &pool).await.unwrap(), None);
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(path = "../../../../db/fixtures", scripts("users"))
+))]
+async fn test_restricted_email(pool: Pool<Sqlite>) {
+    let mut state = SharedState::test(pool.clone());
+    state.config.registration = RegistrationConfig {
+        allowed_domains: vec!["example.org".to_string()],
+        ..Default::default()
+    };
+    let mut app = crate::app(Arc::new(state))
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let change_email = |email: &str| {
+        let params =
+            serde_urlencoded::to_string([("email", email), ("displayname", ""), ("profile", "")])
+                .expect("Failed to serialize params");
+        Request::builder()
+            .uri(app_url("/user/me"))
+            .method("PUT")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(params)
+            .expect("Failed to build request")
+    };
+
+    // users that registered before the restriction can keep their address
+    let response = app
+        .as_service()
+        .call(change_email("test@domain.com"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .as_service()
+        .call(change_email("test@example.com"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    assert!(html.contains("Only email addresses at example.org"));
+    assert_eq!(User::load(1, &pool).await.unwrap().email, "test@domain.com");
+
+    let response = app
+        .as_service()
+        .call(change_email("test@example.org"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        User::load(1, &pool).await.unwrap().email,
+        "test@example.org"
+    );
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(path = "../../../../db/fixtures", scripts("users"))
+))]
+async fn test_restricted_registration(pool: Pool<Sqlite>) {
+    let mut state = SharedState::test(pool.clone());
+    state.config.registration = RegistrationConfig {
+        enabled: true,
+        allowed_domains: vec!["example.org".to_string()],
+        allowed_emails: Vec::new(),
+    };
+    let mut app = crate::app(Arc::new(state))
+        .await
+        .expect("failed to create test app");
+    let register = |username: &str, email: &str| {
+        let params = serde_urlencoded::to_string([
+            ("username", username),
+            ("email", email),
+            ("password", "topsecret123"),
+            ("next", "/project/list"),
+        ])
+        .expect("Failed to serialize params");
+        Request::builder()
+            .uri(app_url("/auth/register"))
+            .method("POST")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(params)
+            .expect("Failed to build request")
+    };
+
+    let response = app
+        .as_service()
+        .call(register("newuser", "newuser@example.com"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    assert!(html.contains("Only email addresses at example.org"));
+    assert_eq!(
+        User::load_by_username("newuser", &pool).await.unwrap(),
+        None
+    );
+
+    // usernames can't be registered twice
+    let response = app
+        .as_service()
+        .call(register("testuser", "testuser@example.org"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = app
+        .as_service()
+        .call(register("newuser", "NewUser@Example.org"))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let redirect = response
+        .headers()
+        .get("HX-Redirect")
+        .expect("No redirect after registering")
+        .to_str()
+        .unwrap();
+    assert!(redirect.starts_with(&app_url("/auth/login?")));
+    assert!(redirect.contains("next=%2Fproject%2Flist"));
+    let user = User::load_by_username("newuser", &pool)
+        .await
+        .unwrap()
+        .expect("User was not registered");
+    assert_eq!(user.email, "NewUser@Example.org");
+    assert_eq!(user.status, UserStatus::Unverified);
+
+    // the new user can't log in until they have verified their address
+    let creds = serde_urlencoded::to_string([
+        ("username", "newuser"),
+        ("password", "topsecret123"),
+        ("next", ""),
+    ])
+    .expect("Failed to serialize params");
+    let req = Request::builder()
+        .uri(app_url("/auth/login"))
+        .method("POST")
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(creds)
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(response.headers().get("set-cookie").is_none());
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    assert!(html.contains("Please verify your email address"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(path = "../../../../db/fixtures", scripts("users"))
+))]
+async fn test_registration_disabled(pool: Pool<Sqlite>) {
+    // registration is disabled unless the configuration enables it
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let req = Request::builder()
+        .uri(app_url("/auth/register"))
+        .method("GET")
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let params = serde_urlencoded::to_string([
+        ("username", "newuser"),
+        ("email", "newuser@example.org"),
+        ("password", "topsecret123"),
+    ])
+    .expect("Failed to serialize params");
+    let req = Request::builder()
+        .uri(app_url("/auth/register"))
+        .method("POST")
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(params)
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        User::load_by_username("newuser", &pool).await.unwrap(),
+        None
+    );
+
+    // and the login page doesn't link to it
+    let req = Request::builder()
+        .uri(app_url("/auth/login"))
+        .method("GET")
+        .body(Body::empty())
+        .expect("Failed to build request");
+    let response = app
+        .as_service()
+        .call(req)
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let bytes = response
+        .into_body()
+        .collect()
+        .await
+        .expect("Failed to read body")
+        .to_bytes();
+    let html = std::str::from_utf8(&bytes).expect("Body is not utf8");
+    assert!(html.contains("Log in"));
+    assert!(!html.contains("register"));
+}
+
+#[test(sqlx::test(
+    migrations = "../db/migrations/",
+    fixtures(path = "../../../../db/fixtures", scripts("users"))
+))]
+async fn test_impersonation(pool: Pool<Sqlite>) {
+    let mut app = test_app(pool.clone())
+        .await
+        .expect("failed to create test app");
+    let cookie = login(&mut app).await.expect("Failed to log in");
+    let send = |method: &str, uri: &str, body: String| {
+        Request::builder()
+            .uri(app_url(uri))
+            .method(method)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header("Cookie", cookie.clone())
+            .body(body)
+            .expect("Failed to build request")
+    };
+    let page = |response: axum::response::Response| async move {
+        let bytes = response
+            .into_body()
+            .collect()
+            .await
+            .expect("Failed to read body")
+            .to_bytes();
+        String::from_utf8(bytes.to_vec()).expect("Body is not utf8")
+    };
+
+    // only administrators can impersonate other users
+    let response = app
+        .as_service()
+        .call(send("POST", "/auth/impersonate", "userid=2".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let mut admin = User::load(1, &pool).await.unwrap();
+    admin.admin = true;
+    admin.update(&pool).await.unwrap();
+
+    let response = app
+        .as_service()
+        .call(send("GET", "/auth/impersonate", String::new()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .as_service()
+        .call(send("POST", "/auth/impersonate", "userid=2".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    // the site is shown as the impersonated user with a banner, and changes are made as them
+    let response = app
+        .as_service()
+        .call(send("GET", "/", String::new()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = page(response).await;
+    assert!(html.contains("are acting as"));
+    assert!(html.contains("test.user2"));
+    let response = app
+        .as_service()
+        .call(send(
+            "POST",
+            "/source/new",
+            "name=Impersonated&description=&latitude=&longitude=".to_string(),
+        ))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+    let sources = UserScope::new(2).sources(None, &pool).await.unwrap();
+    assert!(sources.iter().any(|s| s.name == "Impersonated"));
+
+    // switching back is immediate
+    let response = app
+        .as_service()
+        .call(send("POST", "/auth/impersonate/stop", String::new()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let response = app
+        .as_service()
+        .call(send("GET", "/", String::new()))
+        .await
+        .unwrap();
+    let html = page(response).await;
+    assert!(!html.contains("are acting as"));
+
+    // everything was recorded with both identities
+    let entries = AuditEntry::load_recent(None, 10, &pool).await.unwrap();
+    let actions: Vec<&str> = entries.iter().map(|e| e.action.as_str()).collect();
+    assert_eq!(actions.len(), 3);
+    assert_eq!(actions[0], "stop-impersonating");
+    assert!(actions[1].starts_with("POST ") && actions[1].ends_with("/source/new"));
+    assert_eq!(actions[2], "impersonate");
+    assert!(entries
+        .iter()
+        .all(|e| e.actorid == Some(1) && e.impersonatedid == Some(2)));
+}
@@ -0,0 +1,322 @@
+use crate::{
+    app_url,
+    auth::SqliteUser,
+    error::{self, Error},
+    state::AppState,
+    Message, MessageType, TemplateKey,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{delete, get, post},
+    Form, Router,
+};
+use axum_template::RenderHtml;
+use libseed::{
+    empty_string_as_none, loadable::Loadable, organization::Permission, parse_date, source::Source,
+    trip::Trip,
+};
+use minijinja::context;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::error_alert_response;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/list", get(list_trips))
+        .route("/new", get(show_new_trip).post(insert_trip))
+        .route("/:id", get(show_trip).put(modify_trip).delete(delete_trip))
+        .route("/:id/edit", get(show_trip))
+        .route("/:id/targets", post(add_target))
+        .route("/:id/targets/taxon/:tsn", delete(remove_target_taxon))
+        .route("/:id/targets/source/:srcid", delete(remove_target_source))
+}
+
+async fn list_trips(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let trips = user.scope().trips(None, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, trips => trips),
+    )
+    .into_response())
+}
+
+async fn show_new_trip(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    Ok(RenderHtml(key, state.tmpl.clone(), context!(user => user)).into_response())
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct TripParams {
+    name: String,
+    date: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    notes: Option<String>,
+    /// the names of the participants, one per line
+    #[serde(default)]
+    participants: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    version: Option<i64>,
+}
+
+impl TripParams {
+    /// Apply the submitted values to the given trip
+    fn apply(&self, trip: &mut Trip) -> Result<(), error::Error> {
+        trip.name.clone_from(&self.name);
+        trip.date = parse_date(&self.date)?;
+        trip.notes.clone_from(&self.notes);
+        trip.participants = self.participants.lines().map(|p| p.to_string()).collect();
+        if let Some(version) = self.version {
+            trip.version = version;
+        }
+        Ok(())
+    }
+}
+
+async fn insert_trip(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Form(params): Form<TripParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut trip = Trip::new(String::new(), time::Date::MIN, user.id);
+    let res = match params.apply(&mut trip) {
+        Ok(_) => trip.insert(&state.dbpool).await.map_err(|e| e.into()),
+        Err(e) => Err(e),
+    };
+    match res {
+        Err(e) => {
+            warn!("Failed to insert trip: {e:?}");
+            Ok(error_alert_response(
+                &state,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Failed to save trip: {e}"),
+            )
+            .into_response())
+        }
+        Ok(_) => {
+            debug!(trip.id, "successfully inserted trip");
+            Ok((
+                [("HX-Redirect", app_url(&format!("/trip/{}", trip.id)))],
+                RenderHtml(
+                    "_ALERT.html",
+                    state.tmpl.clone(),
+                    context!(message => Message {
+                        r#type: MessageType::Success,
+                        msg: format!("Added new trip {}: {} to the database", trip.id, trip.name),
+                    }),
+                ),
+            )
+                .into_response())
+        }
+    }
+}
+
+/// Load a trip and make sure that `user` has the given permission for it
+async fn load_trip(
+    user: &SqliteUser,
+    id: i64,
+    permission: Permission,
+    state: &AppState,
+) -> Result<Trip, error::Error> {
+    let trip = Trip::load(id, &state.dbpool)
+        .await
+        .map_err(|_| Error::NotFound("That trip does not exist".to_string()))?;
+    user.require(&trip, permission, &state.dbpool).await?;
+    Ok(trip)
+}
+
+async fn show_trip(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let trip = load_trip(&user, id, Permission::View, &state).await?;
+    let summary = trip.summary(&state.dbpool).await?;
+    let sources = user.scope().sources(None, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 trip => trip,
+                 summary => summary,
+                 sources => sources),
+    )
+    .into_response())
+}
+
+async fn modify_trip(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Form(params): Form<TripParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut trip = load_trip(&user, id, Permission::Edit, &state).await?;
+    let res = match params.apply(&mut trip) {
+        Ok(_) => trip.update(&state.dbpool).await.map_err(|e| e.into()),
+        Err(e) => Err(e),
+    };
+    let conflict = res.as_ref().is_err_and(|e| e.is_version_conflict());
+    let (request, message, headers) = match res {
+        Err(_) if conflict => (
+            Some(&params),
+            Message {
+                r#type: MessageType::Warning,
+                msg: "This trip was modified while you were editing it. Review the saved values and submit again to replace them with your changes.".to_string(),
+            },
+            None,
+        ),
+        Err(e) => (
+            Some(&params),
+            Message {
+                r#type: MessageType::Error,
+                msg: e.to_string(),
+            },
+            None,
+        ),
+        Ok(_) => (
+            None,
+            Message {
+                r#type: MessageType::Success,
+                msg: "Successfully updated trip".to_string(),
+            },
+            Some([("HX-Redirect", app_url(&format!("/trip/{id}")))]),
+        ),
+    };
+    let trip = Trip::load(id, &state.dbpool).await?;
+    Ok((
+        headers,
+        RenderHtml(
+            key,
+            state.tmpl.clone(),
+            context!(trip => trip,
+                     message => message,
+                     request => request,
+                     conflict => conflict),
+        ),
+    )
+        .into_response())
+}
+
+async fn delete_trip(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let mut trip = load_trip(&user, id, Permission::Manage, &state).await?;
+    match trip.delete(&state.dbpool).await {
+        Ok(_) => {
+            debug!(id, "Successfully deleted trip");
+            Ok((
+                [("HX-Redirect", app_url("/trip/list"))],
+                RenderHtml(key, state.tmpl.clone(), context!(deleted => true, id => id)),
+            )
+                .into_response())
+        }
+        Err(e) => {
+            warn!(?e, "Failed to delete trip");
+            Ok(RenderHtml(
+                key,
+                state.tmpl.clone(),
+                context!(trip => trip,
+                message => Message {
+                    r#type: MessageType::Error,
+                    msg: format!("Failed to delete trip: {e}"),
+                }),
+            )
+            .into_response())
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct TargetParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    taxon: Option<i64>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    source: Option<i64>,
+}
+
+/// Render the targets of a trip after they were modified
+async fn render_targets(
+    key: String,
+    user: &SqliteUser,
+    trip: Trip,
+    message: Option<Message>,
+    state: &AppState,
+) -> Result<axum::response::Response, error::Error> {
+    let summary = trip.summary(&state.dbpool).await?;
+    let sources = user.scope().sources(None, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(trip => trip,
+                 summary => summary,
+                 sources => sources,
+                 message => message),
+    )
+    .into_response())
+}
+
+async fn add_target(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Form(params): Form<TargetParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    let trip = load_trip(&user, id, Permission::Edit, &state).await?;
+    if params.taxon.is_none() && params.source.is_none() {
+        let message = Message {
+            r#type: MessageType::Error,
+            msg: "Choose a taxon or a source to add to the trip".to_string(),
+        };
+        return render_targets(key, &user, trip, Some(message), &state).await;
+    }
+    if let Some(tsn) = params.taxon {
+        trip.add_target_taxon(tsn, &state.dbpool).await?;
+    }
+    if let Some(srcid) = params.source {
+        let source = Source::load(srcid, &state.dbpool).await?;
+        user.require(&source, Permission::View, &state.dbpool)
+            .await?;
+        trip.add_target_source(srcid, &state.dbpool).await?;
+    }
+    render_targets(key, &user, trip, None, &state).await
+}
+
+async fn remove_target_taxon(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path((id, tsn)): Path<(i64, i64)>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let trip = load_trip(&user, id, Permission::Edit, &state).await?;
+    trip.remove_target_taxon(tsn, &state.dbpool).await?;
+    render_targets(key, &user, trip, None, &state).await
+}
+
+async fn remove_target_source(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    Path((id, srcuuid)): Path<(i64, Uuid)>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, error::Error> {
+    let trip = load_trip(&user, id, Permission::Edit, &state).await?;
+    let source = Source::load_uuid(srcuuid, &state.dbpool).await?;
+    trip.remove_target_source(source.id, &state.dbpool).await?;
+    render_targets(key, &user, trip, None, &state).await
+}
@@ -1,37 +1,53 @@
+use super::error_alert_response;
 use crate::{
     app_url,
     auth::SqliteUser,
     error::{self, Error},
+    mail,
     state::AppState,
     Message, MessageType, TemplateKey,
 };
-use anyhow::{anyhow, Context};
+use anyhow::anyhow;
 use axum::{
-    extract::State,
+    extract::{Path, State},
+    http::StatusCode,
     response::IntoResponse,
-    routing::{get, post},
+    routing::{delete, get, post, put},
     Form, Router,
 };
-use axum_template::{RenderHtml, TemplateEngine};
-use lettre::{
-    message::{header::ContentType, Mailbox},
-    AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
-};
+use axum_template::RenderHtml;
 use libseed::{
+    conservation::{Permit, PermitPolicy},
+    empty_string_as_none,
+    format::{NumberStyle, WeightUnit},
+    loadable::Loadable,
+    mailin::MailInKey,
+    organization::Permission,
+    preferences::Preferences,
     project::{self, Project},
-    sample::{self, Sample},
+    sample::{self, Certainty, Sample},
     source::{self, Source},
+    taxonomy::Taxon,
+    timezone::{self, TimeZone},
     user::UserStatus,
 };
 use minijinja::context;
 use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
 use tracing::warn;
 
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/me", get(show_profile).put(update_profile))
         .route("/me/edit", get(show_edit_profile))
+        .route("/me/preferences", put(update_preferences))
         .route("/me/reverify", post(resend_verification))
+        .route("/me/permits", get(show_permits).post(add_permit))
+        .route("/me/permits/:id", delete(remove_permit))
+        .route(
+            "/me/mailin",
+            post(generate_mailin_key).delete(remove_mailin_key),
+        )
 }
 
 #[derive(Serialize)]
@@ -66,7 +82,77 @@ async fn show_edit_profile(
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, Error> {
-    Ok(RenderHtml(key, state.tmpl.clone(), context!(user => user)))
+    let sources = user.scope().sources(None, &state.dbpool).await?;
+    let prefs = Preferences::load(user.id, &state.dbpool).await?;
+    let mailin_address = mailin_address(&user, &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+                 sources => sources,
+                 prefs => prefs,
+                 timezones => TimeZone::names(),
+                 number_styles => NumberStyle::iter()
+                    .map(|style| context!(value => style, example => style.example()))
+                    .collect::<Vec<_>>(),
+                 mailin_enabled => state.config.mail_in.is_some(),
+                 mailin_address => mailin_address),
+    ))
+}
+
+/// The address that the user can send notes to, if the environment accepts notes by email and
+/// the user has generated a mail-in key
+async fn mailin_address(user: &SqliteUser, state: &AppState) -> Result<Option<String>, Error> {
+    let Some(config) = &state.config.mail_in else {
+        return Ok(None);
+    };
+    Ok(MailInKey::load(user.id, &state.dbpool)
+        .await?
+        .and_then(|key| key.address(&config.address)))
+}
+
+#[derive(Deserialize)]
+struct PreferencesParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    source: Option<i64>,
+    uncertain: Option<bool>,
+    currentdate: Option<bool>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    yearstart: Option<u8>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    region: Option<String>,
+    #[serde(default)]
+    permitpolicy: PermitPolicy,
+    reminders: Option<bool>,
+    lowstock: Option<bool>,
+}
+
+async fn update_preferences(
+    user: SqliteUser,
+    State(state): State<AppState>,
+    Form(params): Form<PreferencesParams>,
+) -> Result<impl IntoResponse, error::Error> {
+    if let Some(srcid) = params.source {
+        let source = Source::load(srcid, &state.dbpool).await?;
+        user.require(&source, Permission::View, &state.dbpool)
+            .await?;
+    }
+    let prefs = Preferences {
+        userid: user.id,
+        default_source: params.source,
+        default_certainty: match params.uncertain {
+            Some(true) => Certainty::Uncertain,
+            _ => Certainty::Certain,
+        },
+        default_date_current: params.currentdate.unwrap_or(false),
+        year_start_month: params.yearstart.unwrap_or(1),
+        region: params.region,
+        permit_policy: params.permitpolicy,
+        email_reminders: params.reminders.unwrap_or(false),
+        email_low_stock: params.lowstock.unwrap_or(false),
+    };
+    prefs.save(&state.dbpool).await?;
+    Ok([("HX-Redirect", app_url("/user/me"))])
 }
 
 #[derive(Deserialize)]
@@ -74,6 +160,10 @@ struct ProfileParams {
     email: String,
     displayname: String,
     profile: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    timezone: Option<String>,
+    numberstyle: Option<NumberStyle>,
+    weightunit: Option<WeightUnit>,
 }
 
 async fn update_profile(
@@ -87,6 +177,11 @@ async fn update_profile(
     }
     let newemail = params.email.trim();
     if newemail != user.email.trim() {
+        if let Err(msg) = state.config.registration.check_email(newemail) {
+            return Ok(
+                error_alert_response(&state, StatusCode::UNPROCESSABLE_ENTITY, msg).into_response(),
+            );
+        }
         user.status = UserStatus::Unverified;
         user.email = newemail.to_string();
         need_reverify = true;
@@ -99,71 +194,23 @@ async fn update_profile(
         "" => None,
         s => Some(s.to_string()),
     };
+    user.timezone = match params.timezone {
+        Some(tz) if tz != timezone::UTC => Some(TimeZone::load(&tz)?.name().to_string()),
+        _ => None,
+    };
+    if let Some(style) = params.numberstyle {
+        user.number_style = style;
+    }
+    if let Some(unit) = params.weightunit {
+        user.weight_unit = unit;
+    }
     user.update(&state.dbpool).await?;
 
     if need_reverify {
-        send_verification(user, &state).await?;
+        mail::send_verification(&state, &user, false).await?;
     }
 
-    Ok([("HX-Redirect", app_url("/user/me"))])
-}
-
-async fn send_verification(user: SqliteUser, state: &AppState) -> Result<(), error::Error> {
-    let uvkey = user.new_verification_code(&state.dbpool).await?;
-    // FIXME: figure out how to do the host/port stuff properly. Right now this will send a link to
-    // host 0.0.0.0 if that's what we configured the server to listen on...
-    let mut verification_url = "https://".to_string();
-    verification_url.push_str(&state.config.listen.host);
-    if state.config.listen.https_port != 443 {
-        verification_url.push_str(&format!(":{}", state.config.listen.https_port));
-    }
-    verification_url.push_str(&app_url(&format!("/auth/verify/{uvkey}")));
-    let emailbody = state
-        .tmpl
-        .render(
-            "verification-email",
-            context!(user => user,
-                     verification_url => verification_url),
-        )
-        .with_context(|| "Failed to render email")?;
-    let email = lettre::Message::builder()
-        .from(
-            "NOBODY <jonathon@quotidian.org>"
-                .parse()
-                .with_context(|| "failed to parse sender address")?,
-        )
-        .to(Mailbox::new(
-            user.display_name.clone(),
-            user.email
-                .parse()
-                .with_context(|| "Failed to parse recipient address")?,
-        ))
-        .subject("Verify your email address")
-        .header(ContentType::TEXT_PLAIN)
-        .body(emailbody)
-        .with_context(|| "Failed to create email message")?;
-    match state.config.mail_transport {
-        crate::MailTransport::File(ref path) => AsyncFileTransport::<Tokio1Executor>::new(path)
-            .send(email)
-            .await
-            .map_err(anyhow::Error::from)
-            .map(|_| ()),
-        crate::MailTransport::LocalSmtp => {
-            AsyncSmtpTransport::<Tokio1Executor>::unencrypted_localhost()
-                .send(email)
-                .await
-                .map_err(anyhow::Error::from)
-                .map(|_| ())
-        }
-        crate::MailTransport::Smtp(ref cfg) => cfg
-            .build()?
-            .send(email)
-            .await
-            .map_err(anyhow::Error::from)
-            .map(|_| ()),
-    }
-    .with_context(|| "Failed to send email")
-    .map_err(|e| e.into())
+    Ok([("HX-Redirect", app_url("/user/me"))].into_response())
 }
 
 async fn resend_verification(
@@ -171,7 +218,7 @@ async fn resend_verification(
     TemplateKey(key): TemplateKey,
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, error::Error> {
-    let message = match send_verification(user, &state).await {
+    let message = match mail::send_verification(&state, &user, false).await {
         Ok(_) => Message {
             r#type: MessageType::Success,
             msg: "Sent verification email".to_string(),
@@ -191,3 +238,153 @@ async fn resend_verification(
         context!(message => message),
     ))
 }
+
+#[derive(Serialize)]
+struct PermitInfo {
+    permit: Permit,
+    taxon: Taxon,
+    expired: bool,
+}
+
+async fn load_permits(user: &SqliteUser, state: &AppState) -> Result<Vec<PermitInfo>, Error> {
+    let today = user.time_zone().now().date();
+    let mut permits = Vec::new();
+    for permit in Permit::load_all_user(user.id, &state.dbpool).await? {
+        permits.push(PermitInfo {
+            taxon: Taxon::load(permit.tsn, &state.dbpool).await?,
+            expired: permit.expires.is_some_and(|d| d < today),
+            permit,
+        });
+    }
+    Ok(permits)
+}
+
+/// The permits that allow the user to collect taxa that are listed as endangered, threatened or
+/// of special concern
+async fn show_permits(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    let permits = load_permits(&user, &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, permits => permits),
+    ))
+}
+
+#[derive(Deserialize)]
+struct PermitParams {
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    taxon: Option<i64>,
+    region: String,
+    number: String,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    expires: Option<String>,
+    #[serde(default, deserialize_with = "empty_string_as_none")]
+    notes: Option<String>,
+}
+
+async fn do_add_permit(
+    user: &SqliteUser,
+    params: &PermitParams,
+    state: &AppState,
+) -> libseed::Result<Permit> {
+    let tsn = params
+        .taxon
+        .ok_or_else(|| libseed::Error::InvalidValue("no taxon was specified".to_string()))?;
+    let taxon = Taxon::load(tsn, &state.dbpool).await?;
+    let mut permit = Permit::new(user.id, taxon.id, &params.region, params.number.clone());
+    permit.expires = params
+        .expires
+        .as_deref()
+        .map(libseed::parse_date)
+        .transpose()?;
+    permit.notes = params.notes.clone();
+    permit.insert(&state.dbpool).await?;
+    Ok(permit)
+}
+
+async fn add_permit(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Form(params): Form<PermitParams>,
+) -> Result<impl IntoResponse, Error> {
+    let message = match do_add_permit(&user, &params, &state).await {
+        Ok(permit) => Message {
+            r#type: MessageType::Success,
+            msg: format!("Added permit {}", permit.number),
+        },
+        Err(e) => Message {
+            r#type: MessageType::Error,
+            msg: format!("Failed to add permit: {e}"),
+        },
+    };
+    let permits = load_permits(&user, &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(permits => permits, message => message),
+    ))
+}
+
+async fn remove_permit(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Result<impl IntoResponse, Error> {
+    let permit = Permit::load(id, &state.dbpool).await?;
+    if permit.userid != user.id {
+        return Err(Error::Unauthorized(
+            "No permission to delete this permit".to_string(),
+        ));
+    }
+    permit.delete(&state.dbpool).await?;
+    let permits = load_permits(&user, &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(permits => permits),
+    ))
+}
+
+/// Generate a new address for adding notes by email, which replaces the user's previous address
+async fn generate_mailin_key(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    if state.config.mail_in.is_none() {
+        return Err(Error::NotFound(
+            "Notes by email are not enabled on this site".to_string(),
+        ));
+    }
+    MailInKey::generate(user.id, &state.dbpool).await?;
+    let address = mailin_address(&user, &state).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user,
+        address => address,
+        message => Message {
+            r#type: MessageType::Success,
+            msg: "Generated a new address for notes by email".to_string(),
+        }),
+    ))
+}
+
+async fn remove_mailin_key(
+    user: SqliteUser,
+    TemplateKey(key): TemplateKey,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, Error> {
+    MailInKey::remove(user.id, &state.dbpool).await?;
+    Ok(RenderHtml(
+        key,
+        state.tmpl.clone(),
+        context!(user => user, address => None::<String>),
+    ))
+}
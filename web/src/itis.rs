@@ -0,0 +1,131 @@
+//! Fetching ITIS releases that administrators stage to upgrade the taxonomy. A release is either
+//! uploaded or downloaded from ITIS, and the SQLite database is extracted from the zip file that
+//! ITIS publishes. The files are kept in a temporary directory until the release has been
+//! applied or discarded.
+use crate::state::AppState;
+use anyhow::{anyhow, Context, Result};
+use libseed::taxonomy::staged::StagedRelease;
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+use tokio::{io::AsyncWriteExt, task::spawn_blocking};
+
+/// The SQLite release of the ITIS database, published at https://www.itis.gov/downloads/
+pub const ITIS_DOWNLOAD_URL: &str = "https://www.itis.gov/downloads/itisSqlite.zip";
+
+/// How much has to be downloaded before the recorded progress is updated again
+const PROGRESS_BYTES: u64 = 8 * 1024 * 1024;
+
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+/// The directory that fetched releases are kept in
+pub fn release_dir() -> PathBuf {
+    std::env::temp_dir().join("seedweb-itis")
+}
+
+/// The file that the upload or download of a staged release is stored in before the database is
+/// extracted from it
+pub fn archive_path(staged: &StagedRelease) -> PathBuf {
+    release_dir().join(format!("release-{}.download", staged.id))
+}
+
+fn database_path(staged: &StagedRelease) -> PathBuf {
+    release_dir().join(format!("release-{}.sqlite", staged.id))
+}
+
+/// Remove the files of a staged release that is no longer needed
+pub fn remove_files(staged: &StagedRelease) {
+    for path in [archive_path(staged), database_path(staged)] {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Fetch the release, check it and record the changes that it would make. If `uploaded` is
+/// false, the release is downloaded from its source URL first.
+pub async fn prepare(state: &AppState, staged: &mut StagedRelease, uploaded: bool) -> Result<()> {
+    tokio::fs::create_dir_all(release_dir()).await?;
+    let archive = archive_path(staged);
+    if !uploaded {
+        let url = staged.source.clone();
+        download(state, staged, &url, &archive).await?;
+    }
+    staged
+        .set_progress("Extracting the ITIS database", &state.dbpool)
+        .await?;
+    let dest = database_path(staged);
+    let path = dest.clone();
+    spawn_blocking(move || extract(&archive, &path)).await??;
+    let _ = tokio::fs::remove_file(archive_path(staged)).await;
+    staged.check(&dest, &state.dbpool).await?;
+    Ok(())
+}
+
+async fn download(
+    state: &AppState,
+    staged: &mut StagedRelease,
+    url: &str,
+    path: &Path,
+) -> Result<()> {
+    staged
+        .set_progress(&format!("Downloading {url}"), &state.dbpool)
+        .await?;
+    let mut response = reqwest::get(url)
+        .await
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to download {url}"))?;
+    let total = response.content_length();
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut received = 0;
+    let mut reported = 0;
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .with_context(|| format!("Failed to download {url}"))?
+    {
+        file.write_all(&chunk).await?;
+        received += chunk.len() as u64;
+        if received - reported >= PROGRESS_BYTES {
+            reported = received;
+            let progress = match total {
+                Some(total) => format!(
+                    "Downloaded {:.1} of {:.1} MiB",
+                    received as f64 / MIB,
+                    total as f64 / MIB
+                ),
+                None => format!("Downloaded {:.1} MiB", received as f64 / MIB),
+            };
+            staged.set_progress(&progress, &state.dbpool).await?;
+        }
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// Extract the SQLite database from an ITIS zip file. The SQLite database can also be uploaded
+/// directly, in which case it is used as is.
+fn extract(archive: &Path, dest: &Path) -> Result<()> {
+    let mut file = File::open(archive).context("Failed to open the ITIS release")?;
+    let mut magic = [0; SQLITE_MAGIC.len()];
+    if file.read_exact(&mut magic).is_ok() && magic == SQLITE_MAGIC {
+        drop(file);
+        std::fs::rename(archive, dest)?;
+        return Ok(());
+    }
+    let mut archive = zip::ZipArchive::new(file).context("The ITIS release is not a zip file")?;
+    let name = archive
+        .file_names()
+        .find(|name| name.ends_with(".sqlite"))
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("The zip file does not contain an ITIS SQLite database"))?;
+    let mut entry = archive.by_name(&name)?;
+    let mut out =
+        File::create(dest).with_context(|| format!("Failed to create {}", dest.display()))?;
+    io::copy(&mut entry, &mut out)?;
+    Ok(())
+}
@@ -0,0 +1,407 @@
+//! Background jobs that run periodically for as long as the server is running
+use crate::{bundle, demo, itis, mail, mailin, state::AppState};
+use anyhow::Result;
+use libseed::{
+    import::{ColumnMapping, SampleImport},
+    loadable::Loadable,
+    project::bundle::Bundle,
+    reminder::Reminder,
+    stats,
+    stock::{self, LowStock},
+    taxonomy::staged::StagedRelease,
+    user::{verification, User},
+};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How often the mail queue is checked for messages that are due to be retried
+const MAIL_QUEUE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often the verification policy is applied to unverified users
+const VERIFICATION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often reminders that have become due are sent to the users that want them by email
+const REMINDER_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often users that want low stock alerts by email are checked for new samples that are low
+/// on stock
+const LOW_STOCK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often project bundles that have expired are removed
+const BUNDLE_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often uploads of sample imports that were never finished are removed
+const UPLOAD_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often users are checked for collection statistics that are due to be recorded. The
+/// interval between the snapshots of a user is configured for the environment.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long a generated project bundle can be downloaded before it is removed
+const BUNDLE_EXPIRY: time::Duration = time::Duration::days(1);
+
+/// How long the chunks of an upload are kept if the upload is never finished
+const UPLOAD_EXPIRY: time::Duration = time::Duration::days(1);
+
+/// Start all background jobs
+pub fn spawn(state: AppState) {
+    let s = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(MAIL_QUEUE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match mail::process_queue(&s).await {
+                Ok(0) => (),
+                Ok(n) => info!("Sent {n} queued emails"),
+                Err(e) => warn!("Failed to process the mail queue: {e:#}"),
+            }
+        }
+    });
+    if let Some(config) = &state.config.demo {
+        let s = state.clone();
+        // the first tick happens right away, so the demo data is also reset on startup
+        let period = Duration::from_secs(u64::from(config.reset_hours.max(1)) * 60 * 60);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(e) = demo::reset(&s).await {
+                    warn!("Failed to reset the demo account: {e:#}");
+                }
+            }
+        });
+    }
+    if let Some(config) = &state.config.mail_in {
+        let s = state.clone();
+        let period = Duration::from_secs(u64::from(config.poll_minutes.max(1)) * 60);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let Some(config) = &s.config.mail_in else {
+                    break;
+                };
+                match mailin::poll(&s, config).await {
+                    Ok(0) => (),
+                    Ok(n) => info!("Added {n} notes from the mail-in inbox"),
+                    Err(e) => warn!("Failed to check the mail-in inbox: {e:#}"),
+                }
+            }
+        });
+    }
+    let s = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REMINDER_INTERVAL);
+        loop {
+            interval.tick().await;
+            match send_reminders(&s).await {
+                Ok(0) => (),
+                Ok(n) => info!("Sent {n} reminders"),
+                Err(e) => warn!("Failed to send reminders: {e:#}"),
+            }
+        }
+    });
+    let s = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(LOW_STOCK_INTERVAL);
+        loop {
+            interval.tick().await;
+            match send_low_stock_alerts(&s).await {
+                Ok(0) => (),
+                Ok(n) => info!("Sent {n} low stock alerts"),
+                Err(e) => warn!("Failed to send low stock alerts: {e:#}"),
+            }
+        }
+    });
+    let s = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(BUNDLE_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match Bundle::delete_expired(BUNDLE_EXPIRY, &s.dbpool).await {
+                Ok(0) => (),
+                Ok(n) => info!("Removed {n} expired project bundles"),
+                Err(e) => warn!("Failed to remove expired project bundles: {e:#}"),
+            }
+        }
+    });
+    let s = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPLOAD_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            match SampleImport::delete_abandoned(UPLOAD_EXPIRY, &s.dbpool).await {
+                Ok(0) => (),
+                Ok(n) => info!("Removed {n} abandoned sample import uploads"),
+                Err(e) => warn!("Failed to remove abandoned sample import uploads: {e:#}"),
+            }
+        }
+    });
+    let s = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+        loop {
+            interval.tick().await;
+            let today = time::OffsetDateTime::now_utc().date();
+            let days = s.config.stats_snapshots.days();
+            match stats::record_snapshots(today, days, &s.dbpool).await {
+                Ok(0) => (),
+                Ok(n) => info!("Recorded {n} collection statistics snapshots"),
+                Err(e) => warn!("Failed to record collection statistics: {e:#}"),
+            }
+        }
+    });
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(VERIFICATION_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = apply_verification_policy(&state).await {
+                warn!("Failed to apply the verification policy: {e:#}");
+            }
+        }
+    });
+}
+
+/// Send reminders to users that haven't verified their email address and remove the accounts
+/// that have been unverified for too long, as configured for the environment
+pub async fn apply_verification_policy(state: &AppState) -> Result<()> {
+    let config = &state.config.verification;
+    if let Some(days) = config.reminder_days {
+        let users = verification::users_due_for_reminder(
+            time::Duration::days(days.into()),
+            config.max_reminders,
+            &state.dbpool,
+        )
+        .await?;
+        for user in users {
+            match mail::send_verification(state, &user, true).await {
+                Ok(()) => info!(user.username, "Sent verification reminder"),
+                Err(e) => warn!(user.username, "Failed to send verification reminder: {e:#}"),
+            }
+        }
+    }
+    if let Some(days) = config.expire_days {
+        let users =
+            verification::expire_unverified_users(time::Duration::days(days.into()), &state.dbpool)
+                .await?;
+        for user in users {
+            info!(user.username, user.email, "Removed unverified account");
+        }
+    }
+    Ok(())
+}
+
+/// Generate the given project bundle in the background. The outcome is recorded in the bundle, so
+/// that it can be downloaded when it is ready.
+pub fn build_bundle(state: AppState, mut bundle: Bundle) {
+    tokio::spawn(async move {
+        let res = match bundle::build(&state, &bundle).await {
+            Ok(data) => bundle.complete(data, &state.dbpool).await,
+            Err(e) => {
+                warn!(bundle.id, "Failed to build project bundle: {e:#}");
+                bundle.fail(&format!("{e:#}"), &state.dbpool).await
+            }
+        };
+        if let Err(e) = res {
+            warn!(bundle.id, "Failed to save project bundle: {e:#}");
+        }
+    });
+}
+
+/// Run the given sample import in the background. The import records its own progress, so that it
+/// can be shown while it runs.
+pub fn run_import(state: AppState, mut import: SampleImport, mapping: ColumnMapping) {
+    tokio::spawn(async move {
+        if let Err(e) = import.run(&mapping, &state.dbpool).await {
+            warn!(import.id, "Failed to import samples: {e:#}");
+            if let Err(e) = import.fail(&format!("{e:#}"), &state.dbpool).await {
+                warn!(import.id, "Failed to save the sample import: {e:#}");
+            }
+        }
+    });
+}
+
+/// Fetch and check the given staged ITIS release in the background, so that its changes can be
+/// reviewed. The release records its own progress. If `uploaded` is false, it is downloaded from
+/// its source URL first.
+pub fn prepare_release(state: AppState, mut staged: StagedRelease, uploaded: bool) {
+    tokio::spawn(async move {
+        if let Err(e) = itis::prepare(&state, &mut staged, uploaded).await {
+            warn!(staged.id, "Failed to prepare ITIS release: {e:#}");
+            itis::remove_files(&staged);
+            if let Err(e) = staged.fail(&format!("{e:#}"), &state.dbpool).await {
+                warn!(staged.id, "Failed to save the ITIS release: {e:#}");
+            }
+        }
+    });
+}
+
+/// Upgrade the taxonomy to the given reviewed ITIS release in the background. The files of the
+/// release are removed once it has been applied.
+pub fn apply_release(state: AppState, mut staged: StagedRelease) {
+    tokio::spawn(async move {
+        match staged.apply(&state.dbpool).await {
+            Ok(report) => info!(
+                staged.id,
+                "Upgraded the taxonomy, {} taxa changed", report.nchanges
+            ),
+            Err(e) => {
+                warn!(staged.id, "Failed to apply ITIS release: {e:#}");
+                if let Err(e) = staged.fail(&format!("{e:#}"), &state.dbpool).await {
+                    warn!(staged.id, "Failed to save the ITIS release: {e:#}");
+                }
+            }
+        }
+        itis::remove_files(&staged);
+    });
+}
+
+/// Email all reminders that have become due to the users that enabled email reminders. Returns
+/// the number of reminders that were sent.
+pub async fn send_reminders(state: &AppState) -> Result<usize> {
+    let mut sent = 0;
+    let today = time::OffsetDateTime::now_utc().date();
+    for mut reminder in Reminder::load_unsent(today, &state.dbpool).await? {
+        let user = User::load(reminder.userid, &state.dbpool).await?;
+        match mail::send_reminder(state, &user, &reminder).await {
+            Ok(()) => {
+                reminder.mark_emailed(&state.dbpool).await?;
+                sent += 1;
+            }
+            Err(e) => warn!(user.username, "Failed to send reminder: {e:#}"),
+        }
+    }
+    Ok(sent)
+}
+
+/// Email the users that enabled low stock alerts about the samples that have fallen below their
+/// minimum quantity since they were last alerted. Returns the number of alerts that were sent.
+pub async fn send_low_stock_alerts(state: &AppState) -> Result<usize> {
+    let mut sent = 0;
+    for userid in stock::users_with_email_alerts(&state.dbpool).await? {
+        let items = LowStock::load_unalerted(userid, &state.dbpool).await?;
+        if items.is_empty() {
+            continue;
+        }
+        let user = User::load(userid, &state.dbpool).await?;
+        match mail::send_low_stock(state, &user, &items).await {
+            Ok(()) => {
+                LowStock::mark_alerted(userid, &items, &state.dbpool).await?;
+                sent += 1;
+            }
+            Err(e) => warn!(user.username, "Failed to send low stock alert: {e:#}"),
+        }
+    }
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SharedState;
+    use libseed::{
+        mailqueue::QueuedMail,
+        preferences::Preferences,
+        stock::{Threshold, ThresholdTarget},
+        user::UserStatus,
+    };
+    use std::sync::Arc;
+    use test_log::test;
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users"))
+    ))]
+    async fn test_verification_policy(pool: sqlx::Pool<sqlx::Sqlite>) {
+        let mut shared = SharedState::test(pool.clone());
+        shared.config.verification.expire_days = Some(30);
+        let state: AppState = Arc::new(shared);
+
+        // user 1 registered long ago without verifying and has never been sent a reminder
+        sqlx::query(
+            "UPDATE sc_users SET usersince=datetime('now', '-10 days'), userstatus=? WHERE userid=1",
+        )
+        .bind(UserStatus::Unverified)
+        .execute(&pool)
+        .await
+        .unwrap();
+        apply_verification_policy(&state)
+            .await
+            .expect("Failed to apply verification policy");
+        let mails = QueuedMail::load_all(None, &pool).await.unwrap();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].recipient, "test@domain.com");
+        assert_eq!(mails[0].subject, "Reminder: verify your email address");
+        // the reminder isn't sent again right away
+        apply_verification_policy(&state).await.unwrap();
+        assert_eq!(QueuedMail::load_all(None, &pool).await.unwrap().len(), 1);
+        assert_eq!(
+            User::load(1, &pool).await.unwrap().status,
+            UserStatus::Unverified
+        );
+
+        sqlx::query("UPDATE sc_users SET usersince=datetime('now', '-40 days') WHERE userid=1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        apply_verification_policy(&state).await.unwrap();
+        assert!(User::load(1, &pool).await.is_err());
+        assert!(User::load(2, &pool).await.is_ok());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(path = "../../db/fixtures", scripts("users"))
+    ))]
+    async fn test_send_reminders(pool: sqlx::Pool<sqlx::Sqlite>) {
+        let state: AppState = Arc::new(SharedState::test(pool.clone()));
+        let yesterday = time::OffsetDateTime::now_utc()
+            .date()
+            .previous_day()
+            .unwrap();
+        let mut reminder = Reminder::new(1, yesterday, "Sow the seeds".to_string());
+        reminder.insert(&pool).await.unwrap();
+        let mut later = Reminder::new(1, yesterday.replace_year(9999).unwrap(), "Later".into());
+        later.insert(&pool).await.unwrap();
+
+        // nothing is sent until the user enables email reminders
+        assert_eq!(send_reminders(&state).await.unwrap(), 0);
+        let mut prefs = Preferences::load(1, &pool).await.unwrap();
+        prefs.email_reminders = true;
+        prefs.save(&pool).await.unwrap();
+        assert_eq!(send_reminders(&state).await.unwrap(), 1);
+        let mails = QueuedMail::load_all(None, &pool).await.unwrap();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].subject, "Reminder: Sow the seeds");
+        // each reminder is only sent once
+        assert_eq!(send_reminders(&state).await.unwrap(), 0);
+        assert!(Reminder::load(reminder.id, &pool).await.unwrap().emailed);
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples")
+        )
+    ))]
+    async fn test_send_low_stock_alerts(pool: sqlx::Pool<sqlx::Sqlite>) {
+        let state: AppState = Arc::new(SharedState::test(pool.clone()));
+        // sample 2 has a quantity of 100
+        Threshold::save(1, ThresholdTarget::Sample(2), Some(150), &pool)
+            .await
+            .unwrap();
+
+        // nothing is sent until the user enables low stock alerts
+        assert_eq!(send_low_stock_alerts(&state).await.unwrap(), 0);
+        let mut prefs = Preferences::load(1, &pool).await.unwrap();
+        prefs.email_low_stock = true;
+        prefs.save(&pool).await.unwrap();
+        assert_eq!(send_low_stock_alerts(&state).await.unwrap(), 1);
+        let mails = QueuedMail::load_all(None, &pool).await.unwrap();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].subject, "1 sample is low on stock");
+        // each sample is only alerted about once
+        assert_eq!(send_low_stock_alerts(&state).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,359 @@
+//! Rendering and sending of emails. Each email is rendered from a pair of templates in the
+//! `mail/` template directory: `<name>.txt` for the plain-text part, which is required, and
+//! `<name>.html` for an optional HTML part. When both exist, a multipart message is sent so that
+//! mail clients can choose which version to display. All templates have access to the branding of
+//! the current environment as `site`.
+//!
+//! Outgoing emails are not sent directly but are added to the mail queue in the database first.
+//! Delivery is attempted right away, and messages that couldn't be delivered are retried by a
+//! background task with an increasing delay between attempts.
+use crate::{app_url, state::AppState, EnvConfig};
+use anyhow::{anyhow, Context, Result};
+use axum_template::TemplateEngine;
+use lettre::{
+    address::Envelope,
+    message::{Mailbox, MultiPart, SinglePart},
+    AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+};
+use libseed::{
+    mailqueue::QueuedMail,
+    reminder::Reminder,
+    stock::LowStock,
+    user::{verification, User},
+};
+use minijinja::{context, ErrorKind, Value};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+/// The maximum number of messages that are sent in a single run of the queue
+const QUEUE_BATCH_SIZE: i64 = 20;
+
+/// The branding variables that are available to every mail template
+#[derive(Serialize, Debug)]
+pub struct Branding {
+    pub name: String,
+    /// The url of the site, without a trailing slash
+    pub base_url: String,
+    /// The url of the web app itself
+    pub app_url: String,
+    pub footer: Option<String>,
+}
+
+impl Branding {
+    fn new(config: &EnvConfig) -> Self {
+        let base_url = config.base_url();
+        Self {
+            name: config.mail.site_name.clone(),
+            app_url: config.absolute_url(&app_url("/")),
+            base_url,
+            footer: config.mail.footer.clone(),
+        }
+    }
+}
+
+/// Render a mail template, returning `None` if the template doesn't exist
+fn render_optional(state: &AppState, key: &str, ctx: &Value) -> Result<Option<String>> {
+    match state.tmpl.render(key, ctx) {
+        Ok(s) => Ok(Some(s)),
+        Err(e) if e.kind() == ErrorKind::TemplateNotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to render mail template {key}")),
+    }
+}
+
+/// Build an email from the `mail/<template>` templates
+pub fn build_message(
+    state: &AppState,
+    to: Mailbox,
+    subject: &str,
+    template: &str,
+    ctx: Value,
+) -> Result<lettre::Message> {
+    let ctx = context!(site => Branding::new(&state.config), ..ctx);
+    let text = render_optional(state, &format!("mail/{template}.txt"), &ctx)?
+        .with_context(|| format!("Missing plain text mail template for '{template}'"))?;
+    let html = render_optional(state, &format!("mail/{template}.html"), &ctx)?;
+    let builder = lettre::Message::builder()
+        .from(
+            state
+                .config
+                .mail
+                .sender
+                .parse()
+                .with_context(|| "failed to parse sender address")?,
+        )
+        .to(to)
+        .subject(subject);
+    match html {
+        Some(html) => builder.multipart(MultiPart::alternative_plain_html(text, html)),
+        None => builder.singlepart(SinglePart::plain(text)),
+    }
+    .with_context(|| "Failed to create email message")
+}
+
+/// Hand a formatted message to the configured mail transport
+async fn deliver(state: &AppState, envelope: &Envelope, message: &[u8]) -> Result<()> {
+    match state.config.mail_transport {
+        crate::MailTransport::File(ref path) => AsyncFileTransport::<Tokio1Executor>::new(path)
+            .send_raw(envelope, message)
+            .await
+            .map_err(anyhow::Error::from)
+            .map(|_| ()),
+        crate::MailTransport::LocalSmtp => {
+            AsyncSmtpTransport::<Tokio1Executor>::unencrypted_localhost()
+                .send_raw(envelope, message)
+                .await
+                .map_err(anyhow::Error::from)
+                .map(|_| ())
+        }
+        crate::MailTransport::Smtp(ref cfg) => cfg
+            .build()?
+            .send_raw(envelope, message)
+            .await
+            .map_err(anyhow::Error::from)
+            .map(|_| ()),
+    }
+    .with_context(|| "Failed to send email")
+}
+
+/// Attempt to deliver a queued message and record the outcome in the queue
+async fn deliver_queued(state: &AppState, mail: &mut QueuedMail) -> Result<()> {
+    let envelope = Envelope::new(
+        Some(
+            mail.sender
+                .parse()
+                .with_context(|| "Failed to parse sender address")?,
+        ),
+        vec![mail
+            .recipient
+            .parse()
+            .with_context(|| "Failed to parse recipient address")?],
+    )?;
+    match deliver(state, &envelope, &mail.message).await {
+        Ok(()) => {
+            mail.mark_sent(&state.dbpool).await?;
+            debug!(mail.id, "Sent queued email");
+            Ok(())
+        }
+        Err(e) => {
+            mail.mark_failed(&format!("{e:#}"), &state.dbpool).await?;
+            warn!(mail.id, mail.attempts, ?mail.status, "Failed to send queued email: {e:#}");
+            Err(e)
+        }
+    }
+}
+
+/// Render an email from the `mail/<template>` templates, add it to the mail queue and attempt to
+/// deliver it right away. If delivery fails, the message stays in the queue and is retried later,
+/// so this only returns an error if the message could not be queued.
+pub async fn send(
+    state: &AppState,
+    to: Mailbox,
+    subject: &str,
+    template: &str,
+    ctx: Value,
+) -> Result<()> {
+    let email = build_message(state, to, subject, template, ctx)?;
+    let envelope = email.envelope();
+    let mut mail = QueuedMail::new(
+        envelope
+            .from()
+            .ok_or_else(|| anyhow!("Email has no sender"))?
+            .to_string(),
+        envelope
+            .to()
+            .first()
+            .ok_or_else(|| anyhow!("Email has no recipient"))?
+            .to_string(),
+        subject.to_string(),
+        email.formatted(),
+    );
+    mail.insert(&state.dbpool)
+        .await
+        .with_context(|| "Failed to queue email")?;
+    // a delivery failure has already been recorded and will be retried
+    let _ = deliver_queued(state, &mut mail).await;
+    Ok(())
+}
+
+/// Attempt to deliver all queued messages that are due. Returns the number of messages that were
+/// sent successfully.
+pub async fn process_queue(state: &AppState) -> Result<usize> {
+    let mut sent = 0;
+    for mut mail in QueuedMail::load_due(QUEUE_BATCH_SIZE, &state.dbpool).await? {
+        if deliver_queued(state, &mut mail).await.is_ok() {
+            sent += 1;
+        }
+    }
+    Ok(sent)
+}
+
+/// Send a new verification code to the user. A reminder uses a different template that explains
+/// why the user is receiving the email again.
+pub async fn send_verification(state: &AppState, user: &User, reminder: bool) -> Result<()> {
+    let uvkey = verification::new_code(user.id, &state.dbpool).await?;
+    let verification_url = state
+        .config
+        .absolute_url(&app_url(&format!("/auth/verify/{uvkey}")));
+    let to = Mailbox::new(
+        user.display_name.clone(),
+        user.email
+            .parse()
+            .with_context(|| "Failed to parse recipient address")?,
+    );
+    let (subject, template) = match reminder {
+        true => (
+            "Reminder: verify your email address",
+            "verification-reminder",
+        ),
+        false => ("Verify your email address", "verification"),
+    };
+    send(
+        state,
+        to,
+        subject,
+        template,
+        context!(user => user, verification_url => verification_url),
+    )
+    .await
+}
+
+/// Send a reminder that has become due to its user
+pub async fn send_reminder(state: &AppState, user: &User, reminder: &Reminder) -> Result<()> {
+    let url = match (reminder.project_uuid, reminder.allocation_uuid) {
+        (Some(project), Some(allocation)) => {
+            app_url(&format!("/project/{project}/sample/{allocation}"))
+        }
+        (Some(project), None) => app_url(&format!("/project/{project}")),
+        _ => match (reminder.sample_uuid, reminder.source_uuid) {
+            (Some(sample), _) => app_url(&format!("/sample/{sample}")),
+            (_, Some(source)) => app_url(&format!("/source/{source}")),
+            _ => app_url("/"),
+        },
+    };
+    let to = Mailbox::new(
+        user.display_name.clone(),
+        user.email
+            .parse()
+            .with_context(|| "Failed to parse recipient address")?,
+    );
+    send(
+        state,
+        to,
+        &format!("Reminder: {}", reminder.title),
+        "reminder",
+        context!(user => user,
+                 reminder => reminder,
+                 url => state.config.absolute_url(&url)),
+    )
+    .await
+}
+
+/// Alert a user that the given samples have fallen below their minimum quantity
+pub async fn send_low_stock(state: &AppState, user: &User, items: &[LowStock]) -> Result<()> {
+    let to = Mailbox::new(
+        user.display_name.clone(),
+        user.email
+            .parse()
+            .with_context(|| "Failed to parse recipient address")?,
+    );
+    let subject = match items.len() {
+        1 => "1 sample is low on stock".to_string(),
+        n => format!("{n} samples are low on stock"),
+    };
+    send(
+        state,
+        to,
+        &subject,
+        "low-stock",
+        context!(user => user,
+                 items => items,
+                 url => state.config.absolute_url(&app_url("/stock"))),
+    )
+    .await
+}
+
+/// Send a test email to the given address to check the mail configuration and templates of an
+/// environment. The message bypasses the mail queue so that any error is reported immediately.
+pub async fn send_test(state: &AppState, address: &str) -> Result<()> {
+    let to = Mailbox::new(
+        None,
+        address
+            .parse()
+            .with_context(|| "Failed to parse recipient address")?,
+    );
+    let email = build_message(state, to, "Test email", "test", context!())?;
+    deliver(state, email.envelope(), &email.formatted()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SharedState;
+    use libseed::mailqueue::MailStatus;
+    use std::sync::Arc;
+    use test_log::test;
+
+    #[test(sqlx::test(migrations = "../db/migrations/"))]
+    async fn test_build_message(pool: sqlx::Pool<sqlx::Sqlite>) {
+        let state: AppState = Arc::new(SharedState::test(pool));
+        let to = Mailbox::new(None, "someone@example.com".parse().unwrap());
+        let message = build_message(
+            &state,
+            to,
+            "Verify your email address",
+            "verification",
+            context!(verification_url => "https://example.com/verify/abc"),
+        )
+        .expect("Failed to build message");
+        let formatted = String::from_utf8(message.formatted()).expect("Message is not utf8");
+        assert!(formatted.contains("multipart/alternative"));
+        assert!(formatted.contains("text/plain"));
+        assert!(formatted.contains("text/html"));
+        assert!(formatted.contains("https://example.com/verify/abc"));
+        assert!(formatted.contains(&state.config.mail.site_name));
+
+        // the html part is optional
+        let to = Mailbox::new(None, "someone@example.com".parse().unwrap());
+        let message = build_message(&state, to, "Test email", "test", context!())
+            .expect("Failed to build message");
+        let formatted = String::from_utf8(message.formatted()).expect("Message is not utf8");
+        assert!(!formatted.contains("multipart/alternative"));
+        assert!(formatted.contains("text/plain"));
+
+        let to = Mailbox::new(None, "someone@example.com".parse().unwrap());
+        assert!(build_message(&state, to, "Nothing", "no-such-template", context!()).is_err());
+    }
+
+    #[test(sqlx::test(migrations = "../db/migrations/"))]
+    async fn test_mail_queue(pool: sqlx::Pool<sqlx::Sqlite>) {
+        let maildir =
+            std::env::temp_dir().join(format!("seedweb-mail-test-{}", std::process::id()));
+        let mut shared = SharedState::test(pool.clone());
+        // the directory doesn't exist yet, so delivery fails
+        shared.config.mail_transport =
+            crate::MailTransport::File(maildir.to_string_lossy().into_owned());
+        let state: AppState = Arc::new(shared);
+
+        let to = Mailbox::new(None, "someone@example.com".parse().unwrap());
+        send(&state, to, "Test email", "test", context!())
+            .await
+            .expect("Failed to queue email");
+        let queued = QueuedMail::load_all(None, &pool).await.unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].status, MailStatus::Pending);
+        assert_eq!(queued[0].attempts, 1);
+        assert_eq!(queued[0].recipient, "someone@example.com");
+        assert!(queued[0].last_error.is_some());
+        // not due again until the retry delay has passed
+        assert_eq!(process_queue(&state).await.unwrap(), 0);
+
+        std::fs::create_dir_all(&maildir).unwrap();
+        let mut mail = queued[0].clone();
+        mail.resend(&pool).await.unwrap();
+        assert_eq!(process_queue(&state).await.unwrap(), 1);
+        let mail = QueuedMail::load(mail.id, &pool).await.unwrap();
+        assert_eq!(mail.status, MailStatus::Sent);
+        assert_eq!(std::fs::read_dir(&maildir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&maildir).unwrap();
+    }
+}
@@ -0,0 +1,716 @@
+//! Notes by email. The mail-in inbox of the environment is checked periodically with POP3 (see
+//! [`jobs`](crate::jobs)), and each message that was sent to the plus-address of a user's mail-in
+//! key is added as a note by [`libseed::mailin::add_note`]. Every message is removed from the
+//! inbox once it has been processed, whether or not a note could be added.
+//!
+//! A message is only accepted if it is sent from the verified email address of the user that the
+//! key belongs to, and the mail server of the inbox confirmed that the sender address isn't forged,
+//! so knowing the key alone is not enough to add notes. The sender is told whether
+//! the note was added by a reply to the same (verified) address, so that forged messages never
+//! cause mail to be sent to anybody else.
+use crate::{app_url, mail, state::AppState, MailInConfig};
+use anyhow::{bail, Context, Result};
+use lettre::message::Mailbox;
+use libseed::{
+    loadable::Loadable,
+    mailin::{self, Delivery, MailInKey},
+    user::{User, UserStatus},
+};
+use mail_parser::{Message, MessageParser};
+use minijinja::context;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_native_tls::{native_tls, TlsConnector};
+use tracing::{debug, info, warn};
+
+/// What happened to a message from the inbox
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// the note was added and the sender was notified
+    Added,
+    /// the note could not be added and the sender was told why
+    Rejected(String),
+    /// the message was not a valid note from a known user, so it was dropped without a reply
+    Ignored(String),
+}
+
+/// A minimal POP3 client, which implements just enough of RFC 1939 to download and delete all
+/// messages in a mailbox
+struct Pop3<S> {
+    stream: BufReader<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Pop3<S> {
+    /// Start a session on a connected stream by reading the greeting of the server
+    async fn start(stream: S) -> Result<Self> {
+        let mut client = Self {
+            stream: BufReader::new(stream),
+        };
+        client.response().await?;
+        Ok(client)
+    }
+
+    async fn read_line(&mut self) -> Result<Vec<u8>> {
+        let mut line = Vec::new();
+        if self.stream.read_until(b'\n', &mut line).await? == 0 {
+            bail!("The POP3 server closed the connection");
+        }
+        Ok(line)
+    }
+
+    /// Read a status line, returning the text after `+OK`
+    async fn response(&mut self) -> Result<String> {
+        let line = self.read_line().await?;
+        let line = String::from_utf8_lossy(&line);
+        match line.trim_end().strip_prefix("+OK") {
+            Some(text) => Ok(text.trim().to_string()),
+            None => bail!("The POP3 server returned an error: {}", line.trim_end()),
+        }
+    }
+
+    /// Read the data of a multi-line response up to the terminating `.` line, undoing the
+    /// byte-stuffing of lines that start with a `.`
+    async fn data(&mut self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            if line == b".\r\n" || line == b".\n" {
+                return Ok(data);
+            }
+            match line.strip_prefix(b".") {
+                Some(rest) => data.extend_from_slice(rest),
+                None => data.extend_from_slice(&line),
+            }
+        }
+    }
+
+    async fn command(&mut self, command: &str) -> Result<String> {
+        let stream = self.stream.get_mut();
+        stream.write_all(command.as_bytes()).await?;
+        stream.write_all(b"\r\n").await?;
+        stream.flush().await?;
+        self.response().await
+    }
+
+    async fn login(&mut self, username: &str, password: &str) -> Result<()> {
+        self.command(&format!("USER {username}")).await?;
+        self.command(&format!("PASS {password}"))
+            .await
+            .with_context(|| format!("Failed to log in to the mail-in inbox as {username}"))?;
+        Ok(())
+    }
+
+    /// The numbers of all messages in the mailbox
+    async fn list(&mut self) -> Result<Vec<u32>> {
+        self.command("LIST").await?;
+        let data = self.data().await?;
+        String::from_utf8_lossy(&data)
+            .lines()
+            .filter_map(|line| line.split_whitespace().next())
+            .map(|n| {
+                n.parse()
+                    .with_context(|| format!("Invalid message number '{n}'"))
+            })
+            .collect()
+    }
+
+    async fn retrieve(&mut self, n: u32) -> Result<Vec<u8>> {
+        self.command(&format!("RETR {n}")).await?;
+        self.data().await
+    }
+
+    /// Mark a message for deletion. Messages are only removed once the session is ended with
+    /// [`Self::quit`].
+    async fn delete(&mut self, n: u32) -> Result<()> {
+        self.command(&format!("DELE {n}")).await.map(|_| ())
+    }
+
+    async fn quit(mut self) -> Result<()> {
+        self.command("QUIT").await.map(|_| ())
+    }
+}
+
+/// Check the mail-in inbox and add the notes of all messages in it. Returns the number of notes
+/// that were added.
+pub async fn poll(state: &AppState, config: &MailInConfig) -> Result<usize> {
+    let tcp = TcpStream::connect((config.host.as_str(), config.port))
+        .await
+        .with_context(|| format!("Failed to connect to {}:{}", config.host, config.port))?;
+    let tls = TlsConnector::from(native_tls::TlsConnector::new()?)
+        .connect(&config.host, tcp)
+        .await
+        .with_context(|| format!("Failed to start a TLS session with {}", config.host))?;
+    let mut client = Pop3::start(tls).await?;
+    client.login(&config.username, &config.password).await?;
+    let added = process_mailbox(state, config, &mut client).await;
+    // messages that were processed are only deleted if the session ends properly
+    client.quit().await?;
+    added
+}
+
+/// Process every message in the mailbox, deleting the ones that were processed. Messages that
+/// couldn't be processed because of an internal error are left in the mailbox, so that they are
+/// tried again the next time.
+async fn process_mailbox<S: AsyncRead + AsyncWrite + Unpin>(
+    state: &AppState,
+    config: &MailInConfig,
+    client: &mut Pop3<S>,
+) -> Result<usize> {
+    let mut added = 0;
+    for n in client.list().await? {
+        let raw = client.retrieve(n).await?;
+        match process_message(state, config, &raw).await {
+            Ok(outcome) => {
+                match outcome {
+                    Outcome::Added => added += 1,
+                    Outcome::Rejected(reason) => info!("Rejected mail-in note: {reason}"),
+                    Outcome::Ignored(reason) => info!("Ignored mail-in message: {reason}"),
+                }
+                client.delete(n).await?;
+            }
+            Err(e) => warn!("Failed to process mail-in message, will retry: {e:#}"),
+        }
+    }
+    Ok(added)
+}
+
+/// The values of all headers with the given name, in the order they appear in the message
+fn raw_headers<'a>(message: &'a Message, name: &'a str) -> impl Iterator<Item = &'a str> {
+    message
+        .headers_raw()
+        .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+/// Whether the mail server of the inbox, identified by its authserv-id, found that the message
+/// really comes from the sender. The topmost `Authentication-Results` header is the one added by
+/// the server that delivered the message to the inbox, so it is the only one that can be trusted.
+/// The message is authentic if it passed DMARC, or if it passed DKIM or SPF for the domain of the
+/// sender. Messages without such a result are never accepted, so a misconfigured mail server can't
+/// let forged messages through.
+fn is_authenticated(message: &Message, sender: &str, authserv_id: &str) -> bool {
+    let Some(results) = raw_headers(message, "Authentication-Results").next() else {
+        return false;
+    };
+    let Some((_, domain)) = sender.rsplit_once('@') else {
+        return false;
+    };
+    let results = strip_comments(results);
+    let mut parts = results.split(';');
+    // the authserv-id can be followed by a version number
+    let id = parts.next().and_then(|id| id.split_whitespace().next());
+    if !id.is_some_and(|id| id.eq_ignore_ascii_case(authserv_id)) {
+        return false;
+    }
+    parts.any(|resinfo| {
+        let mut tokens = resinfo.split_whitespace();
+        let Some((method, result)) = tokens.next().and_then(|t| t.split_once('=')) else {
+            return false;
+        };
+        if !result.eq_ignore_ascii_case("pass") {
+            return false;
+        }
+        let property = |name: &str| {
+            tokens
+                .clone()
+                .filter_map(|t| t.split_once('='))
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.trim_matches('"'))
+        };
+        match method.to_ascii_lowercase().as_str() {
+            "dmarc" => property("header.from").is_none_or(|from| aligned(from, domain)),
+            "dkim" => property("header.d").is_some_and(|d| aligned(d, domain)),
+            "spf" => property("smtp.mailfrom")
+                .map(|from| from.rsplit_once('@').map_or(from, |(_, d)| d))
+                .is_some_and(|d| aligned(d, domain)),
+            _ => false,
+        }
+    })
+}
+
+/// Remove the comments in parentheses from a header value
+fn strip_comments(value: &str) -> String {
+    let mut depth = 0usize;
+    value
+        .chars()
+        .filter(|&c| {
+            match c {
+                '(' => depth += 1,
+                ')' if depth > 0 => {
+                    depth -= 1;
+                    return false;
+                }
+                _ => (),
+            }
+            depth == 0
+        })
+        .collect()
+}
+
+/// Whether a domain that was authenticated is the domain of the sender, or one of them is a
+/// subdomain of the other, like the relaxed alignment of DMARC
+fn aligned(authenticated: &str, sender: &str) -> bool {
+    let authenticated = authenticated.trim_end_matches('.').to_ascii_lowercase();
+    let sender = sender.trim_end_matches('.').to_ascii_lowercase();
+    !authenticated.is_empty()
+        && (authenticated == sender
+            || sender.ends_with(&format!(".{authenticated}"))
+            || authenticated.ends_with(&format!(".{sender}")))
+}
+
+/// Add the note of a single raw message. An error is only returned if the message could not be
+/// processed because of an internal error; messages that can't be accepted result in
+/// [`Outcome::Rejected`] or [`Outcome::Ignored`].
+pub async fn process_message(
+    state: &AppState,
+    config: &MailInConfig,
+    raw: &[u8],
+) -> Result<Outcome> {
+    let Some(message) = MessageParser::default().parse(raw) else {
+        return Ok(Outcome::Ignored("not a valid email message".to_string()));
+    };
+    // never answer automatic replies such as vacation messages or bounces
+    if raw_headers(&message, "Auto-Submitted").any(|v| !v.eq_ignore_ascii_case("no")) {
+        return Ok(Outcome::Ignored(
+            "automatically submitted message".to_string(),
+        ));
+    }
+    let Some(sender) = message
+        .from()
+        .and_then(|from| from.first())
+        .and_then(|addr| addr.address())
+    else {
+        return Ok(Outcome::Ignored("message has no sender".to_string()));
+    };
+
+    let recipients: Vec<&str> = [message.to(), message.cc()]
+        .into_iter()
+        .flatten()
+        .flat_map(|addresses| addresses.iter())
+        .filter_map(|addr| addr.address())
+        .chain(raw_headers(&message, "Delivered-To"))
+        .chain(raw_headers(&message, "X-Original-To"))
+        .collect();
+    let Some(key) = recipients
+        .iter()
+        .find_map(|address| mailin::key_from_address(address, &config.address))
+    else {
+        return Ok(Outcome::Ignored(format!(
+            "message from {sender} was not sent to a mail-in address"
+        )));
+    };
+    let Some(key) = MailInKey::find(key, &state.dbpool).await? else {
+        return Ok(Outcome::Ignored(format!(
+            "message from {sender} was sent to an unknown mail-in key"
+        )));
+    };
+    let user = User::load(key.userid, &state.dbpool).await?;
+    if user.status != UserStatus::Verified
+        || !user.email.eq_ignore_ascii_case(sender)
+        || !is_authenticated(&message, sender, &config.authserv_id)
+    {
+        return Ok(Outcome::Ignored(format!(
+            "message from {sender} does not come from the owner of the mail-in key, {}",
+            user.username
+        )));
+    }
+
+    let subject = message.subject().unwrap_or_default();
+    let body = message.body_text(0).unwrap_or_default();
+    let date = user.time_zone().now().date();
+    debug!(user.username, subject, "Adding mail-in note");
+    let outcome = match mailin::add_note(user.id, subject, &body, date, &state.dbpool).await {
+        Ok(delivery) => {
+            let url = match &delivery {
+                Delivery::Allocation(allocation, _) => app_url(&format!(
+                    "/project/{}/sample/{}",
+                    allocation.project.uuid, allocation.uuid
+                )),
+                Delivery::Sample(sample) => app_url(&format!("/sample/{}", sample.uuid)),
+            };
+            send_reply(
+                state,
+                &user,
+                subject,
+                "mailin-added",
+                context!(url => state.config.absolute_url(&url)),
+            )
+            .await;
+            Outcome::Added
+        }
+        Err(libseed::Error::InvalidValue(reason)) => {
+            send_reply(
+                state,
+                &user,
+                subject,
+                "mailin-rejected",
+                context!(reason => reason),
+            )
+            .await;
+            Outcome::Rejected(reason)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    Ok(outcome)
+}
+
+/// Tell the user what happened to their note. The note has already been processed at this point,
+/// so a reply that can't be sent is only logged.
+async fn send_reply(
+    state: &AppState,
+    user: &User,
+    subject: &str,
+    template: &str,
+    ctx: minijinja::Value,
+) {
+    let res = match user.email.parse() {
+        Ok(address) => {
+            mail::send(
+                state,
+                Mailbox::new(user.display_name.clone(), address),
+                &format!("Re: {subject}"),
+                template,
+                context!(user => user, subject => subject, ..ctx),
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = res {
+        warn!(user.username, "Failed to reply to mail-in note: {e:#}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SharedState;
+    use libseed::{
+        mailqueue::QueuedMail,
+        project::{allocation, Allocation},
+    };
+    use std::sync::Arc;
+    use test_log::test;
+    use tokio::io::DuplexStream;
+
+    const INBOX: &str = "notes@example.com";
+    const AUTHSERV_ID: &str = "mx.example.com";
+
+    async fn verify(pool: &sqlx::Pool<sqlx::Sqlite>) {
+        sqlx::query("UPDATE sc_users SET userstatus=? WHERE userid=1")
+            .bind(UserStatus::Verified)
+            .execute(pool)
+            .await
+            .unwrap();
+    }
+
+    fn config() -> MailInConfig {
+        MailInConfig {
+            address: INBOX.to_string(),
+            authserv_id: AUTHSERV_ID.to_string(),
+            host: "pop.example.com".to_string(),
+            port: 995,
+            username: "notes".to_string(),
+            passwordfile: String::new(),
+            password: "secret".to_string(),
+            poll_minutes: 5,
+        }
+    }
+
+    fn message(from: &str, to: &str, subject: &str, extra_headers: &str, body: &str) -> Vec<u8> {
+        format!(
+            "From: {from}\r\nTo: {to}\r\nSubject: {subject}\r\n{extra_headers}Content-Type: text/plain\r\n\r\n{body}\r\n"
+        )
+        .into_bytes()
+    }
+
+    /// A message that the mail server of the inbox found to come from the sender's domain
+    fn authenticated(
+        from: &str,
+        to: &str,
+        subject: &str,
+        extra_headers: &str,
+        body: &str,
+    ) -> Vec<u8> {
+        let domain = from.rsplit_once('@').map_or(from, |(_, domain)| domain);
+        let results = format!(
+            "Authentication-Results: {AUTHSERV_ID}; dkim=pass header.d={domain}; dmarc=pass header.from={domain}\r\n"
+        );
+        message(from, to, subject, &(results + extra_headers), body)
+    }
+
+    /// A POP3 server that serves the given messages and returns the numbers of the messages that
+    /// were deleted when the session ends
+    async fn fake_server(stream: DuplexStream, messages: Vec<Vec<u8>>) -> Vec<u32> {
+        let (read, mut write) = tokio::io::split(stream);
+        let mut lines = BufReader::new(read).lines();
+        let mut deleted = Vec::new();
+        write.write_all(b"+OK ready\r\n").await.unwrap();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let (command, arg) = line.split_once(' ').unwrap_or((&line, ""));
+            let response = match command {
+                "USER" => "+OK\r\n".to_string(),
+                "PASS" if arg == "secret" => "+OK logged in\r\n".to_string(),
+                "PASS" => "-ERR invalid password\r\n".to_string(),
+                "LIST" => {
+                    let mut r = format!("+OK {} messages\r\n", messages.len());
+                    for (i, m) in messages.iter().enumerate() {
+                        r.push_str(&format!("{} {}\r\n", i + 1, m.len()));
+                    }
+                    r + ".\r\n"
+                }
+                "RETR" => {
+                    let data =
+                        String::from_utf8(messages[arg.parse::<usize>().unwrap() - 1].clone())
+                            .unwrap();
+                    let stuffed: String = data
+                        .lines()
+                        .map(|l| match l.starts_with('.') {
+                            true => format!(".{l}\r\n"),
+                            false => format!("{l}\r\n"),
+                        })
+                        .collect();
+                    format!("+OK\r\n{stuffed}.\r\n")
+                }
+                "DELE" => {
+                    deleted.push(arg.parse().unwrap());
+                    "+OK\r\n".to_string()
+                }
+                "QUIT" => {
+                    write.write_all(b"+OK bye\r\n").await.unwrap();
+                    break;
+                }
+                _ => "-ERR unknown command\r\n".to_string(),
+            };
+            write.write_all(response.as_bytes()).await.unwrap();
+        }
+        deleted
+    }
+
+    #[test(tokio::test)]
+    async fn test_pop3() {
+        let (client, server) = tokio::io::duplex(4096);
+        let raw = b"Subject: test\r\n\r\n.leading dot\r\nbody\r\n".to_vec();
+        let server = tokio::spawn(fake_server(server, vec![raw.clone(), raw.clone()]));
+        let mut pop3 = Pop3::start(client).await.unwrap();
+        assert!(pop3.login("notes", "wrong").await.is_err());
+        pop3.login("notes", "secret").await.unwrap();
+        assert_eq!(pop3.list().await.unwrap(), vec![1, 2]);
+        assert_eq!(pop3.retrieve(2).await.unwrap(), raw);
+        pop3.delete(2).await.unwrap();
+        pop3.quit().await.unwrap();
+        assert_eq!(server.await.unwrap(), vec![2]);
+
+        // the connection is closed unexpectedly
+        let (client, mut server) = tokio::io::duplex(64);
+        server.write_all(b"+OK ready\r\n").await.unwrap();
+        let mut pop3 = Pop3::start(client).await.unwrap();
+        drop(server);
+        assert!(pop3.list().await.is_err());
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn test_process_message(pool: sqlx::Pool<sqlx::Sqlite>) {
+        let state: AppState = Arc::new(SharedState::test(pool.clone()));
+        let config = config();
+        let user = User::load(1, &pool).await.unwrap();
+        let key = MailInKey::generate(user.id, &pool).await.unwrap();
+        let address = key.address(INBOX).unwrap();
+        // notes are only accepted from verified addresses
+        sqlx::query("UPDATE sc_users SET userstatus=? WHERE userid=1")
+            .bind(UserStatus::Unverified)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let raw = authenticated(&user.email, &address, "S0001 Sowed", "", "In flat 3");
+        assert!(matches!(
+            process_message(&state, &config, &raw).await.unwrap(),
+            Outcome::Ignored(_)
+        ));
+        verify(&pool).await;
+        let notes = |pool: sqlx::Pool<sqlx::Sqlite>| async move {
+            let mut alloc =
+                Allocation::load_one(Some(allocation::Filter::SampleId(1).into()), &pool)
+                    .await
+                    .unwrap();
+            alloc.load_notes(&pool).await.unwrap();
+            alloc.notes
+        };
+        let before = notes(pool.clone()).await.len();
+
+        let outcome = process_message(&state, &config, &raw).await.unwrap();
+        assert_eq!(outcome, Outcome::Added);
+        let after = notes(pool.clone()).await;
+        assert_eq!(after.len(), before + 1);
+        assert!(after
+            .iter()
+            .any(|n| n.summary == "Sowed" && n.details.as_deref() == Some("In flat 3")));
+        let replies = QueuedMail::load_all(None, &pool).await.unwrap();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].recipient, user.email);
+        assert_eq!(replies[0].subject, "Re: S0001 Sowed");
+
+        // problems with the note are reported to the sender
+        let outcome = process_message(
+            &state,
+            &config,
+            &authenticated(&user.email, &address, "Sowed", "", ""),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(outcome, Outcome::Rejected(_)));
+        assert_eq!(QueuedMail::load_all(None, &pool).await.unwrap().len(), 2);
+
+        // forged senders, unknown keys and automatic messages are dropped without a reply
+        for raw in [
+            authenticated("someone@example.org", &address, "S0001 Sowed", "", ""),
+            authenticated(
+                &user.email,
+                "notes+0000000000@example.com",
+                "S0001 Sowed",
+                "",
+                "",
+            ),
+            authenticated(&user.email, INBOX, "S0001 Sowed", "", ""),
+            authenticated(
+                &user.email,
+                &address,
+                "S0001 Sowed",
+                "Auto-Submitted: auto-replied\r\n",
+                "",
+            ),
+            message(
+                &user.email,
+                &address,
+                "S0001 Sowed",
+                "Authentication-Results: mx.example.com; spf=fail; dmarc=fail header.from=domain.com\r\n",
+                "",
+            ),
+            // the mail server didn't authenticate the message at all
+            message(&user.email, &address, "S0001 Sowed", "", ""),
+            // the results were added by another server, e.g. by the sender
+            message(
+                &user.email,
+                &address,
+                "S0001 Sowed",
+                "Authentication-Results: mx.attacker.example; dmarc=pass header.from=domain.com\r\n",
+                "",
+            ),
+        ] {
+            assert!(matches!(
+                process_message(&state, &config, &raw).await.unwrap(),
+                Outcome::Ignored(_)
+            ));
+        }
+        assert_eq!(notes(pool.clone()).await.len(), before + 1);
+        assert_eq!(QueuedMail::load_all(None, &pool).await.unwrap().len(), 2);
+
+        // the key can also be found in the envelope recipient
+        let raw = authenticated(
+            &user.email,
+            "undisclosed-recipients:;",
+            "S0001 Watered",
+            &format!("Delivered-To: {address}\r\n"),
+            "",
+        );
+        assert_eq!(
+            process_message(&state, &config, &raw).await.unwrap(),
+            Outcome::Added
+        );
+    }
+
+    #[test(sqlx::test(
+        migrations = "../db/migrations/",
+        fixtures(
+            path = "../../db/fixtures",
+            scripts("users", "sources", "taxa", "samples", "projects")
+        )
+    ))]
+    async fn test_process_mailbox(pool: sqlx::Pool<sqlx::Sqlite>) {
+        let state: AppState = Arc::new(SharedState::test(pool.clone()));
+        let config = config();
+        let user = User::load(1, &pool).await.unwrap();
+        verify(&pool).await;
+        let address = MailInKey::generate(user.id, &pool)
+            .await
+            .unwrap()
+            .address(INBOX)
+            .unwrap();
+        let (client, server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(fake_server(
+            server,
+            vec![
+                authenticated(&user.email, &address, "S0001 Sowed", "", ""),
+                message("spam@example.org", INBOX, "Buy now", "", ""),
+            ],
+        ));
+        let mut pop3 = Pop3::start(client).await.unwrap();
+        pop3.login("notes", "secret").await.unwrap();
+        assert_eq!(
+            process_mailbox(&state, &config, &mut pop3).await.unwrap(),
+            1
+        );
+        pop3.quit().await.unwrap();
+        // both messages were processed, so both are removed from the inbox
+        assert_eq!(server.await.unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_authentication() {
+        let authenticated = |results: &str| {
+            let raw = message(
+                "test@domain.com",
+                INBOX,
+                "S0001 Sowed",
+                &format!("Authentication-Results: {results}\r\n"),
+                "",
+            );
+            let message = MessageParser::default().parse(&raw).unwrap();
+            is_authenticated(&message, "test@domain.com", AUTHSERV_ID)
+        };
+        assert!(authenticated(
+            "mx.example.com; dmarc=pass header.from=domain.com"
+        ));
+        assert!(authenticated(
+            "MX.example.com 1; spf=none (no record); dmarc=pass (p=reject) header.from=domain.com"
+        ));
+        // DKIM and SPF only count for the domain of the sender or a subdomain of it
+        assert!(authenticated(
+            "mx.example.com; dkim=pass (2048-bit key) header.d=mail.domain.com header.s=s1"
+        ));
+        assert!(authenticated(
+            "mx.example.com; spf=pass smtp.mailfrom=bounces@domain.com"
+        ));
+        assert!(!authenticated(
+            "mx.example.com; dkim=pass header.d=attacker.example; spf=pass smtp.mailfrom=attacker.example"
+        ));
+        assert!(!authenticated(
+            "mx.example.com; dkim=pass header.d=notdomain.com"
+        ));
+        assert!(!authenticated(
+            "mx.example.com; dmarc=pass header.from=attacker.example"
+        ));
+        assert!(!authenticated(
+            "mx.example.com; dmarc=fail header.from=domain.com"
+        ));
+        assert!(!authenticated("mx.example.com; none"));
+        // a passing result in a comment doesn't count
+        assert!(!authenticated(
+            "mx.example.com; dkim=fail (dmarc=pass) header.d=domain.com"
+        ));
+        assert!(!authenticated(
+            "mx.example.com.attacker.example; dmarc=pass"
+        ));
+        assert!(!authenticated(
+            "(mx.example.com) attacker.example; dmarc=pass"
+        ));
+    }
+}
@@ -1,11 +1,11 @@
-use crate::error::Error;
+use crate::{assets::StaticAssets, error::Error, templates::Templates};
 use anyhow::{anyhow, Context, Result};
 use auth::AuthSession;
 use axum::{
     async_trait,
     extract::{rejection::MatchedPathRejection, FromRequestParts, Host, MatchedPath, State},
     handler::HandlerWithoutStateExt,
-    http::{request::Parts, HeaderMap, HeaderValue, Method, Request, StatusCode, Uri},
+    http::{request::Parts, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, Uri},
     middleware::{self, Next},
     response::{IntoResponse, Redirect, Response},
     routing::get,
@@ -16,33 +16,83 @@ use axum_login::{
     AuthManagerLayerBuilder,
 };
 use axum_server::tls_rustls::RustlsConfig;
-use axum_template::{engine::Engine, RenderHtml};
+use axum_template::RenderHtml;
 use clap::Parser;
 use lettre::{transport::smtp::authentication::Credentials, AsyncSmtpTransport, Tokio1Executor};
-use minijinja::{context, Environment, ErrorKind};
+use libseed::{
+    format::NumberFormat,
+    monitoring::Phenophase,
+    source::{HabitatType, LightCondition, SoilMoisture},
+    stats::CollectionYear,
+    timezone::TimeZone,
+};
+use minijinja::{context, Environment, ErrorKind, Value};
 use serde::{Deserialize, Serialize};
 use state::{AppState, SharedState};
-use std::{collections::HashMap, net::SocketAddr, path::PathBuf, sync::Arc};
-use time::Duration;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+use strum::IntoEnumIterator;
+use time::{
+    format_description::well_known::{Iso8601, Rfc3339},
+    macros::format_description,
+    Date, Duration, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset,
+};
 use tower::ServiceBuilder;
 use tower_http::{
+    cors::{AllowOrigin, CorsLayer},
     request_id::{MakeRequestId, RequestId},
     services::ServeDir,
     trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
     ServiceBuilderExt,
 };
 use tower_sessions_sqlx_store::SqliteStore;
-use tracing::{debug, info, trace};
-use tracing_subscriber::filter::EnvFilter;
+use tracing::{debug, info, trace, Level};
+use tracing_subscriber::{
+    filter::{EnvFilter, Targets},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    Layer,
+};
 use uuid::Uuid;
 
+mod api;
+mod assets;
 mod auth;
+mod bundle;
 mod db;
+mod demo;
 mod error;
 mod html;
+mod itis;
+mod jobs;
+mod mail;
+mod mailin;
 mod state;
+mod templates;
 
 const APP_PREFIX: &str = "/app/";
+const API_PREFIX: &str = "/api/v1/";
+const GRAPHQL_PATH: &str = "/api/graphql";
+
+/// The path that the site is served below, e.g. `/seeds`, or an empty string if it is served at
+/// the root of its domain. It is taken from the environment's base url at startup.
+static MOUNT_PATH: OnceLock<String> = OnceLock::new();
+
+fn mount_path() -> &'static str {
+    MOUNT_PATH.get().map(String::as_str).unwrap_or_default()
+}
+
+fn api_prefix() -> String {
+    format!("{}{API_PREFIX}", mount_path())
+}
+
+fn graphql_path() -> String {
+    format!("{}{GRAPHQL_PATH}", mount_path())
+}
 
 #[derive(Serialize)]
 pub enum MessageType {
@@ -75,7 +125,7 @@ where
             .await?
             // Cargo doesn't allow `:` as a file name
             .as_str()
-            .trim_start_matches(APP_PREFIX)
+            .trim_start_matches(&app_url(""))
             .replace(':', "@")
             .replace('/', "_");
 
@@ -103,10 +153,21 @@ pub struct Cli {
         help = "shows all valid values for the --env option"
     )]
     pub list_envs: bool,
+    #[arg(
+        long,
+        value_name = "ADDRESS",
+        help = "sends a test email to the given address using the environment's mail settings, then exits"
+    )]
+    pub send_test_email: Option<String>,
 }
 
 pub fn app_url(value: &str) -> String {
-    [APP_PREFIX, value.trim_start_matches('/')].join("")
+    [mount_path(), APP_PREFIX, value.trim_start_matches('/')].join("")
+}
+
+/// The url of a file in the static asset directory
+pub fn static_url(value: &str) -> String {
+    [mount_path(), "/static/", value.trim_start_matches('/')].join("")
 }
 
 pub fn markdown(value: Option<&str>) -> minijinja::Value {
@@ -142,6 +203,13 @@ pub fn append_query_param(
     Ok(format!("?{querystring}"))
 }
 
+/// The name of a collection year for a user whose collection year starts in the given month
+pub fn collection_year_label(year: u32, start_month: Option<u8>) -> String {
+    CollectionYear::starting_in(start_month.unwrap_or(1))
+        .unwrap_or_default()
+        .label(year)
+}
+
 pub fn truncate_text(mut s: String, chars: Option<usize>) -> String {
     let chars = chars.unwrap_or(100);
     if s.len() > chars {
@@ -158,6 +226,96 @@ pub fn format_id_number(id: i64, prefix: Option<&str>, width: Option<usize>) ->
     format!("{}{:0>width$}", prefix, id, width = width)
 }
 
+/// Convert a UTC timestamp to the time zone of the logged-in user, so that it can be passed on to
+/// the `dateformat` or `datetimeformat` filters. Accepts the same values as those filters: a
+/// timestamp string, a unix timestamp (e.g. from `now()`) or a serialized `OffsetDateTime`.
+pub fn localtime(
+    state: &minijinja::State,
+    value: minijinja::Value,
+) -> Result<String, minijinja::Error> {
+    let invalid = || minijinja::Error::new(ErrorKind::InvalidOperation, "not a valid timestamp");
+    let datetime = if let Some(s) = value.as_str() {
+        OffsetDateTime::parse(s, &Iso8601::PARSING)
+            .or_else(|_| PrimitiveDateTime::parse(s, &Iso8601::PARSING).map(|dt| dt.assume_utc()))
+            .or_else(|_| {
+                PrimitiveDateTime::parse(
+                    s,
+                    format_description!("[year]-[month]-[day] [hour]:[minute]:[second]"),
+                )
+                .map(|dt| dt.assume_utc())
+            })
+            .map_err(|_| invalid())?
+    } else if let Ok(secs) = f64::try_from(value.clone()) {
+        OffsetDateTime::from_unix_timestamp_nanos((secs * 1e9) as i128).map_err(|_| invalid())?
+    } else {
+        // (year, ordinal, hour, minute, second, nanosecond, offset hours, minutes, seconds)
+        let items = value
+            .try_iter()?
+            .map(i64::try_from)
+            .collect::<Result<Vec<i64>, _>>()?;
+        let [year, ordinal, hour, minute, second, nano, oh, om, os] = items[..] else {
+            return Err(invalid());
+        };
+        let date = Date::from_ordinal_date(year as i32, ordinal as u16).map_err(|_| invalid())?;
+        let time = Time::from_hms_nano(hour as u8, minute as u8, second as u8, nano as u32)
+            .map_err(|_| invalid())?;
+        let offset = UtcOffset::from_hms(oh as i8, om as i8, os as i8).map_err(|_| invalid())?;
+        PrimitiveDateTime::new(date, time).assume_offset(offset)
+    };
+    let tzname = state
+        .lookup("user")
+        .and_then(|user| user.get_attr("timezone").ok())
+        .and_then(|tz| tz.as_str().map(str::to_string));
+    TimeZone::load_or_utc(tzname.as_deref())
+        .to_local(datetime)
+        .format(&Rfc3339)
+        .map_err(|_| invalid())
+}
+
+/// The number format of the logged-in user, or the default format if nobody is logged in
+fn user_number_format(state: &minijinja::State) -> NumberFormat {
+    let preference = |name: &str| {
+        state
+            .lookup("user")
+            .and_then(|user| user.get_attr(name).ok())
+            .and_then(|value| value.as_str().map(str::to_string))
+    };
+    NumberFormat::new(
+        preference("number_style")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_default(),
+        preference("weight_unit")
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_default(),
+    )
+}
+
+/// Format a number in the number format of the logged-in user, rounded to `decimals` decimals
+/// (none by default)
+pub fn format_number(state: &minijinja::State, value: f64, decimals: Option<usize>) -> String {
+    user_number_format(state).number(value, decimals.unwrap_or(0))
+}
+
+/// Format a weight in grams in the number format and weight unit of the logged-in user
+pub fn format_weight(state: &minijinja::State, grams: f64) -> String {
+    user_number_format(state).weight(grams)
+}
+
+/// The name of a taxon to show to the logged-in user. If the template was given the user's
+/// `taxon_names` (see [`DisplayName::load_map`](libseed::taxonomy::names::DisplayName::load_map)),
+/// this is their display name for the taxon. Otherwise it is the ITIS name of the taxon.
+pub fn taxon_name(state: &minijinja::State, taxon: Value) -> Result<Value, minijinja::Error> {
+    let id = taxon.get_attr("id")?;
+    let name = state
+        .lookup("taxon_names")
+        .and_then(|names| names.get_item(&id).ok())
+        .filter(|name| !name.is_undefined() && !name.is_none());
+    match name {
+        Some(name) => Ok(name),
+        None => taxon.get_attr("complete_name"),
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 struct Ports {
     http: u16,
@@ -223,14 +381,361 @@ struct ListenConfig {
     https_port: u16,
 }
 
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+struct QueryLogConfig {
+    /// statements that take longer than this many milliseconds are logged as warnings. If this is
+    /// not specified, sqlx's default threshold of one second is used.
+    slow_query_ms: Option<u64>,
+    /// add a `Server-Timing` header with the number of database queries and their total
+    /// execution time to each response. Useful in development environments.
+    #[serde(default)]
+    timing_header: bool,
+}
+
+/// Settings for the emails sent by the site. The branding settings are available to all mail
+/// templates.
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(default)]
+struct MailConfig {
+    sender: String,
+    site_name: String,
+    /// deprecated, use [EnvConfig::base_url] instead
+    base_url: Option<String>,
+    footer: Option<String>,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            sender: "NOBODY <jonathon@quotidian.org>".to_string(),
+            site_name: "SeedCollection".to_string(),
+            base_url: None,
+            footer: None,
+        }
+    }
+}
+
+/// The policy for users that haven't verified their email address yet
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(default)]
+struct VerificationConfig {
+    /// the number of days after which an unverified user is sent a new verification email, or
+    /// `None` to never send reminders
+    reminder_days: Option<u32>,
+    /// the maximum number of reminders that are sent to a user
+    max_reminders: u32,
+    /// the number of days after registration after which the accounts of unverified users are
+    /// removed, or `None` to keep them forever. Accounts that contain any data are always kept.
+    expire_days: Option<u32>,
+}
+
+impl Default for VerificationConfig {
+    fn default() -> Self {
+        Self {
+            reminder_days: Some(3),
+            max_reminders: 2,
+            expire_days: None,
+        }
+    }
+}
+
+/// Controls whether visitors can create their own accounts, and restricts the email addresses that
+/// users can register with or change their address to, for sites that are only meant for the
+/// members of an organization. Any address is accepted if neither list is given.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(default)]
+struct RegistrationConfig {
+    /// whether the registration page is available. Accounts can only be created with
+    /// `seedctl users add` otherwise.
+    enabled: bool,
+    /// addresses at these domains are accepted, e.g. `example.org`
+    allowed_domains: Vec<String>,
+    /// these addresses are accepted regardless of their domain
+    allowed_emails: Vec<String>,
+}
+
+impl RegistrationConfig {
+    /// Check whether the given email address may be used on this site. The error is a message
+    /// that can be shown to the user.
+    fn check_email(&self, email: &str) -> std::result::Result<(), String> {
+        if self.allowed_domains.is_empty() && self.allowed_emails.is_empty() {
+            return Ok(());
+        }
+        let email = email.trim();
+        if self
+            .allowed_emails
+            .iter()
+            .any(|allowed| allowed.trim().eq_ignore_ascii_case(email))
+        {
+            return Ok(());
+        }
+        let domain = email.rsplit_once('@').map(|(_, domain)| domain);
+        if domain.is_some_and(|domain| {
+            self.allowed_domains
+                .iter()
+                .any(|allowed| allowed.trim().eq_ignore_ascii_case(domain))
+        }) {
+            return Ok(());
+        }
+        Err(match self.allowed_domains.as_slice() {
+            [] => "This email address is not allowed on this site. Please contact the site administrator to request access.".to_string(),
+            domains => format!(
+                "Only email addresses at {} can be used on this site",
+                domains.join(", ")
+            ),
+        })
+    }
+}
+
+/// How often a snapshot of the statistics of each user's collection is recorded
+#[derive(Debug, Deserialize, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+enum SnapshotInterval {
+    #[default]
+    Daily,
+    Weekly,
+}
+
+impl SnapshotInterval {
+    fn days(self) -> u32 {
+        match self {
+            Self::Daily => 1,
+            Self::Weekly => 7,
+        }
+    }
+}
+
+/// A public demo mode with a read-only guest account that anybody can log in to
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+#[serde(default)]
+struct DemoConfig {
+    /// the username of the guest account. The account is created if it doesn't exist yet.
+    username: String,
+    /// how often the guest account's data is replaced with the example dataset, in hours
+    reset_hours: u32,
+}
+
+impl Default for DemoConfig {
+    fn default() -> Self {
+        Self {
+            username: "demo".to_string(),
+            reset_hours: 24,
+        }
+    }
+}
+
+/// An inbox that users can send notes to by email, see [mailin]. The inbox is checked with POP3
+/// over TLS and every message is deleted once it has been processed.
+#[derive(Deserialize, PartialEq)]
+struct MailInConfig {
+    /// the address of the inbox, e.g. `notes@example.com`. Each user sends their notes to a
+    /// plus-address of the inbox that contains their personal key, e.g.
+    /// `notes+0123456789abcdef0123@example.com`, so the mail server has to deliver plus-addresses
+    /// to the inbox.
+    address: String,
+    /// the authserv-id that the mail server which delivers to the inbox uses in the
+    /// `Authentication-Results` headers that it adds, usually its host name. Messages are only
+    /// accepted if that server found that they really come from the sender.
+    authserv_id: String,
+    host: String,
+    #[serde(default = "MailInConfig::default_port")]
+    port: u16,
+    username: String,
+    passwordfile: String,
+    #[serde(skip)]
+    password: String,
+    /// how often the inbox is checked for new messages, in minutes
+    #[serde(default = "MailInConfig::default_poll_minutes")]
+    poll_minutes: u32,
+}
+
+impl MailInConfig {
+    fn default_port() -> u16 {
+        995
+    }
+
+    fn default_poll_minutes() -> u32 {
+        5
+    }
+}
+
+impl std::fmt::Debug for MailInConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MailInConfig")
+            .field("address", &self.address)
+            .field("authserv_id", &self.authserv_id)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .field("passwordfile", &self.passwordfile)
+            .field("poll_minutes", &self.poll_minutes)
+            .finish()
+    }
+}
+
+/// Cross-origin resource sharing for the JSON API, so that it can be used by web applications that
+/// are served from other origins. It only applies to the routes below [API_PREFIX] and to
+/// [GRAPHQL_PATH].
+#[derive(Debug, Deserialize, PartialEq, Clone)]
+struct CorsConfig {
+    /// the origins that may use the api, e.g. `https://app.example.com`, or `*` for any origin
+    allowed_origins: Vec<String>,
+    #[serde(default = "CorsConfig::default_methods")]
+    allowed_methods: Vec<String>,
+    #[serde(default = "CorsConfig::default_headers")]
+    allowed_headers: Vec<String>,
+    /// allow requests that include the session cookie. Not allowed together with `*` origins.
+    #[serde(default)]
+    allow_credentials: bool,
+    /// how long browsers may cache the response to a preflight request, in seconds
+    #[serde(default)]
+    max_age_secs: Option<u64>,
+}
+
+impl CorsConfig {
+    fn default_methods() -> Vec<String> {
+        ["GET", "POST", "PUT", "DELETE"]
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    fn default_headers() -> Vec<String> {
+        vec!["content-type".to_string()]
+    }
+
+    /// Build the layer that adds the CORS headers to the responses of the api
+    fn layer(&self) -> Result<CorsLayer> {
+        let any_origin = self.allowed_origins.iter().any(|o| o == "*");
+        if any_origin && self.allow_credentials {
+            return Err(anyhow!(
+                "CORS credentials can't be allowed for any origin, the origins must be listed"
+            ));
+        }
+        let origins = match any_origin {
+            true => AllowOrigin::any(),
+            false => AllowOrigin::list(
+                self.allowed_origins
+                    .iter()
+                    .map(|o| {
+                        HeaderValue::from_str(o.trim_end_matches('/'))
+                            .with_context(|| format!("Invalid CORS origin '{o}'"))
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+        };
+        let methods = self
+            .allowed_methods
+            .iter()
+            .map(|m| {
+                Method::from_bytes(m.to_uppercase().as_bytes())
+                    .with_context(|| format!("Invalid CORS method '{m}'"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let headers = self
+            .allowed_headers
+            .iter()
+            .map(|h| {
+                HeaderName::from_bytes(h.as_bytes())
+                    .with_context(|| format!("Invalid CORS header '{h}'"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut layer = CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .allow_credentials(self.allow_credentials);
+        if let Some(secs) = self.max_age_secs {
+            layer = layer.max_age(std::time::Duration::from_secs(secs));
+        }
+        Ok(layer)
+    }
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 struct EnvConfig {
     listen: ListenConfig,
+    /// the url that the site is reachable at, e.g. `https://seeds.example.com`. If the url has a
+    /// path (e.g. `https://example.org/seeds/`), the site is served below that path. If not
+    /// specified, it is derived from the listen address.
+    #[serde(default)]
+    base_url: Option<String>,
     database: String,
     mail_transport: MailTransport,
+    #[serde(default)]
+    query_log: QueryLogConfig,
+    #[serde(default)]
+    mail: MailConfig,
+    #[serde(default)]
+    verification: VerificationConfig,
+    /// restrict the email addresses that can be used, see [RegistrationConfig]
+    #[serde(default)]
+    registration: RegistrationConfig,
+    /// reload templates and static files when they change and show detailed template errors.
+    /// Only meant for developing templates.
+    #[serde(default)]
+    dev_mode: bool,
+    /// enable the demo mode, see [DemoConfig]
+    #[serde(default)]
+    demo: Option<DemoConfig>,
+    /// accept notes by email, see [MailInConfig]
+    #[serde(default)]
+    mail_in: Option<MailInConfig>,
+    /// allow other origins to use the JSON api, see [CorsConfig]
+    #[serde(default)]
+    cors: Option<CorsConfig>,
+    /// a directory with `templates` and `static` subdirectories. Files in them are used instead of
+    /// the bundled files with the same name, so that a site can be customized without modifying
+    /// the installed files.
+    #[serde(default)]
+    overrides: Option<PathBuf>,
+    /// the names of the built-in plugins to enable, see [libseed::plugin]
+    #[serde(default)]
+    plugins: Vec<String>,
+    /// how often the statistics of each collection are recorded to show its growth over time
+    #[serde(default)]
+    stats_snapshots: SnapshotInterval,
 }
 
 impl EnvConfig {
+    /// The url that the site is reachable at, without a trailing slash
+    fn base_url(&self) -> String {
+        match self.base_url.as_ref().or(self.mail.base_url.as_ref()) {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => {
+                // This will produce a link to host 0.0.0.0 if that's what the server is configured
+                // to listen on, so `base_url` should be configured for production environments
+                let mut url = format!("https://{}", self.listen.host);
+                if self.listen.https_port != 443 {
+                    url.push_str(&format!(":{}", self.listen.https_port));
+                }
+                url
+            }
+        }
+    }
+
+    /// The path that the site is served below, without a trailing slash
+    fn mount_path(&self) -> String {
+        self.base_url()
+            .parse::<Uri>()
+            .map(|uri| uri.path().trim_end_matches('/').to_string())
+            .unwrap_or_default()
+    }
+
+    /// The absolute url of a path on this site, such as one returned by [app_url]
+    fn absolute_url(&self, path: &str) -> String {
+        let base_url = self.base_url();
+        let origin = base_url
+            .strip_suffix(&self.mount_path())
+            .unwrap_or(&base_url);
+        format!("{origin}{path}")
+    }
+
+    /// The subdirectory of the override directory that replaces the given bundled directory
+    fn override_dir(&self, name: &str) -> Option<PathBuf> {
+        self.overrides.as_ref().map(|dir| dir.join(name))
+    }
+
     fn init(&mut self) -> Result<()> {
         if let MailTransport::Smtp(ref mut cfg) = self.mail_transport {
             if let Some(ref mut creds) = cfg.credentials {
@@ -238,6 +743,11 @@ impl EnvConfig {
                     .with_context(|| "Failed to read smtp password to file")?;
             }
         }
+        if let Some(ref mut cfg) = self.mail_in {
+            cfg.password = std::fs::read_to_string(&cfg.passwordfile)
+                .map(|p| p.trim_end().to_string())
+                .with_context(|| "Failed to read mail-in password file")?;
+        }
         Ok(())
     }
 }
@@ -253,21 +763,57 @@ impl MakeRequestId for MakeRequestUuid {
     }
 }
 
-fn template_engine<T>(envname: &str, template_dir: T) -> Engine<minijinja::Environment<'static>>
+fn template_engine<T>(
+    envname: &str,
+    template_dir: T,
+    override_dir: Option<&std::path::Path>,
+    assets: Arc<StaticAssets>,
+    dev_mode: bool,
+    demo: Option<&DemoConfig>,
+    registration_enabled: bool,
+) -> Templates
 where
     T: AsRef<std::path::Path>,
 {
     let mut jinja = Environment::new();
-    jinja.set_loader(minijinja::path_loader(template_dir));
+    jinja.set_loader(templates::loader(template_dir.as_ref(), override_dir));
+    minijinja_contrib::add_to_environment(&mut jinja);
     jinja.add_filter("app_url", app_url);
+    jinja.add_filter("static_url", move |value: &str| assets.url(value));
     jinja.add_filter("append_query_param", append_query_param);
     jinja.add_filter("truncate", truncate_text);
+    jinja.add_filter("collection_year", collection_year_label);
     jinja.add_filter("idfmt", format_id_number);
     jinja.add_filter("markdown", markdown);
+    jinja.add_filter("localtime", localtime);
+    jinja.add_filter("taxon_name", taxon_name);
+    jinja.add_filter("number", format_number);
+    jinja.add_filter("weight", format_weight);
     jinja.add_global("environment", envname);
-    minijinja_contrib::add_to_environment(&mut jinja);
+    // the login page offers a guest login and the guest sees a notice that the data is read-only
+    jinja.add_global("demo_username", demo.map(|d| d.username.clone()));
+    // links to the registration page are only shown if visitors can create their own accounts
+    jinja.add_global("registration_enabled", registration_enabled);
+    // the controlled vocabularies of the habitat attributes of a source, for forms and filters
+    jinja.add_global(
+        "habitat_types",
+        Value::from_serialize(HabitatType::iter().collect::<Vec<_>>()),
+    );
+    jinja.add_global(
+        "soil_moisture_classes",
+        Value::from_serialize(SoilMoisture::iter().collect::<Vec<_>>()),
+    );
+    jinja.add_global(
+        "light_conditions",
+        Value::from_serialize(LightCondition::iter().collect::<Vec<_>>()),
+    );
+    jinja.add_global(
+        "phenophases",
+        Value::from_serialize(Phenophase::iter().collect::<Vec<_>>()),
+    );
+    jinja.set_debug(dev_mode);
 
-    Engine::from(jinja)
+    Templates::new(jinja, dev_mode)
 }
 
 async fn app(shared_state: AppState) -> Result<Router> {
@@ -289,11 +835,42 @@ async fn app(shared_state: AppState) -> Result<Router> {
 
     trace!("Creating routers");
     let static_path = shared_state.datadir.join("static");
-    let app = Router::new()
-        .route("/", get(root))
-        .route("/favicon.ico", get(favicon_redirect))
-        .nest_service("/static", ServeDir::new(static_path))
-        .nest(APP_PREFIX, html::router(shared_state.clone()))
+    let mount = mount_path();
+    let mut app = Router::new()
+        .route(&format!("{mount}/"), get(root))
+        .route(&format!("{mount}/favicon.ico"), get(favicon_redirect))
+        .nest_service(
+            &format!("{mount}/static"),
+            ServiceBuilder::new()
+                .layer(middleware::from_fn_with_state(
+                    shared_state.clone(),
+                    assets::serve_fingerprinted,
+                ))
+                .service(
+                    ServeDir::new(
+                        shared_state
+                            .config
+                            .override_dir("static")
+                            .unwrap_or_else(|| static_path.clone()),
+                    )
+                    .fallback(ServeDir::new(static_path)),
+                ),
+        )
+        .nest(&app_url(""), html::router(shared_state.clone()));
+    app = match shared_state.config.cors {
+        Some(ref cors) => {
+            let layer = cors.layer()?;
+            app.nest(&api_prefix(), api::router().layer(layer.clone()))
+                .nest(&graphql_path(), api::graphql::router().layer(layer))
+        }
+        None => app
+            .nest(&api_prefix(), api::router())
+            .nest(&graphql_path(), api::graphql::router()),
+    };
+    if !mount.is_empty() {
+        app = app.route(mount, get(root));
+    }
+    let app = app
         .layer(
             ServiceBuilder::new()
                 .set_x_request_id(MakeRequestUuid)
@@ -302,11 +879,23 @@ async fn app(shared_state: AppState) -> Result<Router> {
                         .make_span_with(DefaultMakeSpan::new().include_headers(true))
                         .on_response(DefaultOnResponse::new().include_headers(true)),
                 )
+                .layer(middleware::from_fn_with_state(
+                    shared_state.clone(),
+                    db::track_queries,
+                ))
                 .propagate_x_request_id()
                 .layer(auth_layer)
                 .layer(middleware::from_fn_with_state(
                     shared_state.clone(),
                     error_mapper,
+                ))
+                .layer(middleware::from_fn_with_state(
+                    shared_state.clone(),
+                    demo::read_only,
+                ))
+                .layer(middleware::from_fn_with_state(
+                    shared_state.clone(),
+                    auth::audit_impersonation,
                 )),
         )
         .with_state(shared_state);
@@ -348,8 +937,15 @@ fn data_dir() -> PathBuf {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_env("SEEDWEB_LOG"))
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(EnvFilter::from_env("SEEDWEB_LOG")))
+        .with(
+            db::QueryStatsLayer.with_filter(
+                Targets::new()
+                    .with_target("sqlx::query", Level::DEBUG)
+                    .with_target(db::QUERY_STATS_TARGET, Level::INFO),
+            ),
+        )
         .init();
 
     let args = Cli::parse();
@@ -381,7 +977,17 @@ async fn main() -> Result<()> {
     })?;
     // we want to fail early if the config isn't valid or the password can't be read
     env.init()?;
+    libseed::plugin::load_builtin(&env.plugins)?;
     info!(envarg, ?env);
+    // urls are built by free functions (e.g. for templates), so the path needs to be global
+    MOUNT_PATH.get_or_init(|| env.mount_path());
+
+    if let Some(address) = args.send_test_email {
+        let state = Arc::new(SharedState::new(envarg, env, datadir).await?);
+        mail::send_test(&state, &address).await?;
+        println!("Sent a test email to {address}");
+        return Ok(());
+    }
     let listen = env.listen.clone();
 
     let ports = Ports {
@@ -399,7 +1005,9 @@ async fn main() -> Result<()> {
                 "Unable to load TLS key and certificate. See certs/README for more info"
             })?;
 
-    let app = app(Arc::new(SharedState::new(envarg, env, datadir).await?)).await?;
+    let state = Arc::new(SharedState::new(envarg, env, datadir).await?);
+    jobs::spawn(state.clone());
+    let app = app(state).await?;
 
     let addr: SocketAddr = format!("{}:{}", listen.host, listen.https_port).parse()?;
     info!("Listening on https://{}", addr);
@@ -417,6 +1025,8 @@ async fn error_mapper(
     next: Next,
 ) -> Response {
     let is_htmx = headers.get("HX-Request").is_some();
+    let path = request.uri().path();
+    let is_api = path.starts_with(&api_prefix()) || path == graphql_path();
     let response = next.run(request).await;
     if is_htmx {
         // don't print out a fancy error page for HTMX since it will just get inserted inside a
@@ -427,6 +1037,21 @@ async fn error_mapper(
     let server_error = response.extensions().get::<Arc<Error>>();
     let client_status = server_error.map(|se| se.as_ref().to_client_status());
 
+    if is_api {
+        return client_status
+            .map(|(status_code, client_error)| {
+                (
+                    status_code,
+                    axum::Json(api::ApiError {
+                        status: status_code.as_u16(),
+                        error: client_error,
+                    }),
+                )
+                    .into_response()
+            })
+            .unwrap_or(response);
+    }
+
     let error_response = client_status.as_ref().map(|(status_code, client_error)| {
         (
             *status_code,
@@ -486,12 +1111,11 @@ async fn redirect_http_to_https(addr: String, ports: Ports) {
 }
 
 async fn root() -> impl IntoResponse {
-    Redirect::permanent(APP_PREFIX)
+    Redirect::permanent(&app_url("/"))
 }
 
-async fn favicon_redirect(State(state): State<AppState>) -> impl IntoResponse {
-    let path = state.datadir.join("static/favicon.ico");
-    Redirect::permanent(path.to_str().unwrap_or_default())
+async fn favicon_redirect() -> impl IntoResponse {
+    Redirect::permanent(&static_url("favicon.ico"))
 }
 
 #[cfg(test)]
@@ -503,6 +1127,7 @@ async fn test_app(pool: sqlx::Pool<sqlx::Sqlite>) -> Result<Router> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use test_log::test;
 
     #[test]
     fn test_parse_config() {
@@ -515,10 +1140,38 @@ mod test {
     host: "0.0.0.0"
     http_port: 8080
     https_port: 8443
+  query_log:
+    slow_query_ms: 250
+    timing_header: true
+  mail:
+    site_name: "Seeds (dev)"
+    base_url: "https://dev.example.com/"
+  verification:
+    reminder_days: 7
+    expire_days: 30
 prod:
   database: prod-database.sqlite
   mail_transport: !LocalSmtp
   asset_root: "/path/to/assets2"
+  demo:
+    reset_hours: 6
+  mail_in:
+    address: "notes@example.com"
+    authserv_id: "mx.example.com"
+    host: "pop.example.com"
+    username: "notes"
+    passwordfile: "/path/to/password"
+  cors:
+    allowed_origins: ["https://app.example.com"]
+    allow_credentials: true
+    max_age_secs: 3600
+  overrides: "/etc/seedweb/custom"
+  plugins: ["no-future-dates"]
+  stats_snapshots: weekly
+  registration:
+    enabled: true
+    allowed_domains: ["example.org"]
+    allowed_emails: ["volunteer@example.com"]
   listen: *LISTEN"#;
         let configs: HashMap<String, EnvConfig> =
             serde_yaml::from_str(yaml).expect("Failed to parse yaml");
@@ -526,26 +1179,213 @@ prod:
         assert_eq!(
             configs["dev"],
             EnvConfig {
+                base_url: None,
                 database: "dev-database.sqlite".to_string(),
                 mail_transport: MailTransport::File("/tmp/".to_string()),
                 listen: ListenConfig {
                     host: "0.0.0.0".to_string(),
                     http_port: 8080,
                     https_port: 8443,
-                }
+                },
+                query_log: QueryLogConfig {
+                    slow_query_ms: Some(250),
+                    timing_header: true,
+                },
+                mail: MailConfig {
+                    site_name: "Seeds (dev)".to_string(),
+                    base_url: Some("https://dev.example.com/".to_string()),
+                    ..Default::default()
+                },
+                verification: VerificationConfig {
+                    reminder_days: Some(7),
+                    max_reminders: 2,
+                    expire_days: Some(30),
+                },
+                registration: RegistrationConfig::default(),
+                dev_mode: false,
+                demo: None,
+                mail_in: None,
+                cors: None,
+                overrides: None,
+                plugins: Vec::new(),
+                stats_snapshots: SnapshotInterval::Daily,
             }
         );
+        assert_eq!(configs["dev"].base_url(), "https://dev.example.com");
+        assert_eq!(configs["dev"].mount_path(), "");
         assert_eq!(
             configs["prod"],
             EnvConfig {
+                base_url: None,
                 database: "prod-database.sqlite".to_string(),
                 mail_transport: MailTransport::LocalSmtp,
                 listen: ListenConfig {
                     host: "0.0.0.0".to_string(),
                     http_port: 8080,
                     https_port: 8443,
-                }
+                },
+                query_log: QueryLogConfig::default(),
+                mail: MailConfig::default(),
+                verification: VerificationConfig::default(),
+                registration: RegistrationConfig {
+                    enabled: true,
+                    allowed_domains: vec!["example.org".to_string()],
+                    allowed_emails: vec!["volunteer@example.com".to_string()],
+                },
+                dev_mode: false,
+                demo: Some(DemoConfig {
+                    username: "demo".to_string(),
+                    reset_hours: 6,
+                }),
+                mail_in: Some(MailInConfig {
+                    address: "notes@example.com".to_string(),
+                    authserv_id: "mx.example.com".to_string(),
+                    host: "pop.example.com".to_string(),
+                    port: 995,
+                    username: "notes".to_string(),
+                    passwordfile: "/path/to/password".to_string(),
+                    password: String::new(),
+                    poll_minutes: 5,
+                }),
+                cors: Some(CorsConfig {
+                    allowed_origins: vec!["https://app.example.com".to_string()],
+                    allowed_methods: CorsConfig::default_methods(),
+                    allowed_headers: vec!["content-type".to_string()],
+                    allow_credentials: true,
+                    max_age_secs: Some(3600),
+                }),
+                overrides: Some(PathBuf::from("/etc/seedweb/custom")),
+                plugins: vec!["no-future-dates".to_string()],
+                stats_snapshots: SnapshotInterval::Weekly,
             }
         );
+        assert_eq!(configs["prod"].base_url(), "https://0.0.0.0:8443");
+        assert_eq!(
+            configs["prod"].absolute_url("/app/auth/verify/abc"),
+            "https://0.0.0.0:8443/app/auth/verify/abc"
+        );
+    }
+
+    #[test]
+    fn test_registration_config() {
+        let mut config = RegistrationConfig::default();
+        assert!(config.check_email("anybody@example.net").is_ok());
+
+        config.allowed_emails = vec!["Volunteer@example.com".to_string()];
+        assert!(config.check_email("volunteer@example.com").is_ok());
+        assert!(config.check_email("staff@example.org").is_err());
+
+        config.allowed_domains = vec!["example.org".to_string()];
+        assert!(config.check_email(" staff@EXAMPLE.org ").is_ok());
+        assert!(config.check_email("volunteer@example.com").is_ok());
+        assert!(config.check_email("staff@sub.example.org").is_err());
+        assert!(config.check_email("example.org").is_err());
+        assert_eq!(
+            config.check_email("someone@example.net"),
+            Err("Only email addresses at example.org can be used on this site".to_string())
+        );
+    }
+
+    #[test]
+    fn test_cors_config() {
+        let mut cors = CorsConfig {
+            allowed_origins: vec!["*".to_string()],
+            allowed_methods: vec!["get".to_string()],
+            allowed_headers: CorsConfig::default_headers(),
+            allow_credentials: false,
+            max_age_secs: None,
+        };
+        assert!(cors.layer().is_ok());
+        cors.allow_credentials = true;
+        assert!(cors.layer().is_err());
+        cors.allowed_origins = vec!["https://app.example.com/".to_string()];
+        assert!(cors.layer().is_ok());
+        cors.allowed_headers = vec!["bad header".to_string()];
+        assert!(cors.layer().is_err());
+    }
+
+    #[test(sqlx::test(migrations = "../db/migrations/"))]
+    async fn test_cors_api_only(pool: sqlx::Pool<sqlx::Sqlite>) {
+        use axum::body::Body;
+        use tower::Service;
+
+        let mut state = SharedState::test(pool);
+        state.config.cors = Some(CorsConfig {
+            allowed_origins: vec!["https://app.example.com".to_string()],
+            allowed_methods: CorsConfig::default_methods(),
+            allowed_headers: CorsConfig::default_headers(),
+            allow_credentials: false,
+            max_age_secs: None,
+        });
+        let mut app = app(Arc::new(state)).await.expect("failed to create app");
+        let preflight = |uri: String, origin: &str| {
+            Request::builder()
+                .uri(uri)
+                .method("OPTIONS")
+                .header("origin", origin)
+                .header("access-control-request-method", "GET")
+                .body(Body::empty())
+                .expect("Failed to build request")
+        };
+        let allowed_origin = |response: &Response| {
+            response
+                .headers()
+                .get("access-control-allow-origin")
+                .map(|v| v.to_str().unwrap().to_string())
+        };
+
+        let response = app
+            .as_service()
+            .call(preflight(
+                format!("{API_PREFIX}stats/years"),
+                "https://app.example.com",
+            ))
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            allowed_origin(&response).as_deref(),
+            Some("https://app.example.com")
+        );
+
+        // other origins are not allowed
+        let response = app
+            .as_service()
+            .call(preflight(
+                format!("{API_PREFIX}stats/years"),
+                "https://evil.example.com",
+            ))
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(allowed_origin(&response), None);
+
+        // the html pages are not affected
+        let response = app
+            .as_service()
+            .call(preflight(
+                app_url("/sample/list"),
+                "https://app.example.com",
+            ))
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(allowed_origin(&response), None);
+    }
+
+    #[test]
+    fn test_subpath_config() {
+        let yaml = r#"database: database.sqlite
+mail_transport: !LocalSmtp
+base_url: "https://example.org/seeds/"
+listen:
+  host: "0.0.0.0"
+  http_port: 8080
+  https_port: 8443"#;
+        let config: EnvConfig = serde_yaml::from_str(yaml).expect("Failed to parse yaml");
+        assert_eq!(config.base_url(), "https://example.org/seeds");
+        assert_eq!(config.mount_path(), "/seeds");
+        assert_eq!(
+            config.absolute_url("/seeds/app/auth/verify/abc"),
+            "https://example.org/seeds/app/auth/verify/abc"
+        );
     }
 }
@@ -1,25 +1,65 @@
-use crate::{db, template_engine, EnvConfig};
+use crate::{assets::StaticAssets, db, template_engine, templates::Templates, EnvConfig};
 use anyhow::{Context, Result};
-use axum_template::engine::Engine;
 use lettre::{AsyncSmtpTransport, Tokio1Executor};
+use notify::RecommendedWatcher;
 use sqlx::SqlitePool;
 use std::{path::PathBuf, sync::Arc};
-use tracing::{debug, trace};
-
-type TemplateEngine = Engine<minijinja::Environment<'static>>;
+use tracing::{debug, info, trace};
 
 #[derive(Debug)]
 pub struct SharedState {
     pub dbpool: SqlitePool,
-    pub tmpl: TemplateEngine,
+    pub tmpl: Templates,
     pub config: EnvConfig,
     pub datadir: PathBuf,
+    pub assets: Arc<StaticAssets>,
+    /// watches the template and static directories in development mode
+    _watcher: Option<RecommendedWatcher>,
 }
 
 impl SharedState {
     pub async fn new(envname: &str, env: EnvConfig, datadir: PathBuf) -> Result<Self> {
         let tmpl_path = datadir.join("templates");
-        let template = template_engine(envname, &tmpl_path);
+        let static_path = datadir.join("static");
+        let tmpl_overrides = env.override_dir("templates");
+        let static_overrides = env.override_dir("static");
+        if let Some(ref dir) = env.overrides {
+            info!(
+                ?dir,
+                "Using templates and static files from the override directory"
+            );
+        }
+        let assets = Arc::new(StaticAssets::load(
+            &static_path,
+            static_overrides.as_deref(),
+        ));
+        let template = template_engine(
+            envname,
+            &tmpl_path,
+            tmpl_overrides.as_deref(),
+            assets.clone(),
+            env.dev_mode,
+            env.demo.as_ref(),
+            env.registration.enabled,
+        );
+        let watcher = if env.dev_mode {
+            info!("Development mode: reloading templates and static files when they change");
+            let mut dirs = vec![tmpl_path.as_path(), static_path.as_path()];
+            dirs.extend(
+                [&tmpl_overrides, &static_overrides]
+                    .into_iter()
+                    .flatten()
+                    .map(PathBuf::as_path)
+                    .filter(|dir| dir.is_dir()),
+            );
+            Some(
+                template
+                    .watch(assets.clone(), &dirs)
+                    .with_context(|| "Unable to watch the template directories")?,
+            )
+        } else {
+            None
+        };
         trace!("Creating shared app state");
         // do a quick sanity check on the mail transport
         debug!(?env.mail_transport,
@@ -38,23 +78,35 @@ impl SharedState {
         }
         .with_context(|| "Sanity check of mail transport failed")?;
         Ok(Self {
-            dbpool: db::pool(env.database.clone())
+            dbpool: db::pool(env.database.clone(), &env.query_log)
                 .await
                 .with_context(|| format!("Unable to open database {}", &env.database))?,
             tmpl: template,
             config: env,
             datadir,
+            assets,
+            _watcher: watcher,
         })
     }
 
     #[cfg(test)]
     pub fn test(pool: sqlx::Pool<sqlx::Sqlite>) -> Self {
-        let template = template_engine("test", "./templates");
+        let assets = Arc::new(StaticAssets::load(std::path::Path::new("./static"), None));
+        let template = template_engine(
+            "test",
+            "./templates",
+            None,
+            assets.clone(),
+            false,
+            None,
+            false,
+        );
         debug!("Creating test shared app state");
         Self {
             dbpool: pool,
             tmpl: template,
             config: EnvConfig {
+                base_url: None,
                 listen: crate::ListenConfig {
                     host: "127.0.0.1".to_string(),
                     http_port: 8080,
@@ -62,8 +114,21 @@ impl SharedState {
                 },
                 database: "test-database.sqlite".to_string(),
                 mail_transport: crate::MailTransport::File("/tmp/".to_string()),
+                query_log: Default::default(),
+                mail: Default::default(),
+                verification: Default::default(),
+                registration: Default::default(),
+                dev_mode: false,
+                demo: None,
+                mail_in: None,
+                cors: None,
+                overrides: None,
+                plugins: Vec::new(),
+                stats_snapshots: Default::default(),
             },
             datadir: ".".into(),
+            assets,
+            _watcher: None,
         }
     }
 }
@@ -0,0 +1,228 @@
+//! The engine that renders pages and emails. In development mode the template and static
+//! directories are watched, so that changes show up without restarting the server, and template
+//! errors are shown in detail instead of as a terse error message.
+//!
+//! Self-hosted sites can replace individual templates without modifying the bundled ones, see
+//! [loader].
+use crate::assets::StaticAssets;
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use axum_template::TemplateEngine;
+use minijinja::{Environment, ErrorKind, HtmlEscape};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::{
+    path::Path,
+    sync::{Arc, PoisonError, RwLock},
+};
+use tracing::{debug, warn};
+
+/// The prefix of template names that always refer to the bundled template, so that an overriding
+/// template can extend the template it replaces, e.g. `{% extends "bundled/root.html" %}`
+pub const BUNDLED_PREFIX: &str = "bundled/";
+
+/// A template loader that looks for templates in the `overrides` directory before the `bundled`
+/// directory
+pub fn loader(
+    bundled: &Path,
+    overrides: Option<&Path>,
+) -> impl Fn(&str) -> Result<Option<String>, minijinja::Error> + Send + Sync + 'static {
+    let bundled = minijinja::path_loader(bundled.to_path_buf());
+    let overrides = overrides.map(|dir| minijinja::path_loader(dir.to_path_buf()));
+    move |name| {
+        if let Some(name) = name.strip_prefix(BUNDLED_PREFIX) {
+            return bundled(name);
+        }
+        match overrides.as_ref().map(|load| load(name)).transpose()? {
+            Some(Some(source)) => Ok(Some(source)),
+            _ => bundled(name),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Templates {
+    env: Arc<RwLock<Environment<'static>>>,
+    dev_mode: bool,
+}
+
+impl Templates {
+    pub fn new(env: Environment<'static>, dev_mode: bool) -> Self {
+        Self {
+            env: Arc::new(RwLock::new(env)),
+            dev_mode,
+        }
+    }
+
+    /// Forget all templates that were loaded so far, so that they're read from disk again the next
+    /// time they are used
+    pub fn reload(&self) {
+        self.env
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear_templates();
+    }
+
+    /// Reload the templates and static assets whenever something changes in one of the given
+    /// directories. Changes are only watched as long as the returned watcher is kept alive.
+    pub fn watch(
+        &self,
+        assets: Arc<StaticAssets>,
+        dirs: &[&Path],
+    ) -> notify::Result<RecommendedWatcher> {
+        let templates = self.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+                Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                    debug!(?event.paths, "Reloading templates and static assets");
+                    templates.reload();
+                    assets.reload();
+                }
+                Ok(_) => (),
+                Err(e) => warn!("Error while watching for template changes: {e}"),
+            })?;
+        for dir in dirs {
+            watcher.watch(dir, RecursiveMode::Recursive)?;
+        }
+        Ok(watcher)
+    }
+}
+
+impl TemplateEngine for Templates {
+    type Error = TemplateError;
+
+    fn render<S: Serialize>(&self, key: &str, data: S) -> Result<String, Self::Error> {
+        let env = self.env.read().unwrap_or_else(PoisonError::into_inner);
+        env.get_template(key)
+            .and_then(|template| template.render(&data))
+            .map_err(|error| TemplateError {
+                error,
+                verbose: self.dev_mode,
+            })
+    }
+}
+
+/// An error that occurred while rendering a template
+#[derive(thiserror::Error, Debug)]
+#[error("{error}")]
+pub struct TemplateError {
+    error: minijinja::Error,
+    /// whether to show the full details of the error in the response
+    verbose: bool,
+}
+
+impl TemplateError {
+    pub fn kind(&self) -> ErrorKind {
+        self.error.kind()
+    }
+}
+
+impl IntoResponse for TemplateError {
+    fn into_response(self) -> Response {
+        if !self.verbose {
+            return (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response();
+        }
+        let mut details = format!("{:#}", self.error);
+        let mut source = std::error::Error::source(&self.error);
+        while let Some(e) = source {
+            details.push_str(&format!("\n\ncaused by: {e:#}"));
+            source = e.source();
+        }
+        let page = format!(
+            "<!DOCTYPE html><html><head><title>Template error</title></head><body>\
+            <h1>Template error</h1><pre>{}</pre></body></html>",
+            HtmlEscape(&details)
+        );
+        (StatusCode::INTERNAL_SERVER_ERROR, Html(page)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+
+    #[test]
+    fn test_reload() {
+        let dir = std::env::temp_dir().join(format!("seedweb-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("Failed to create template dir");
+        std::fs::write(dir.join("page.html"), "first").expect("Failed to write template");
+        let mut env = Environment::new();
+        env.set_loader(minijinja::path_loader(&dir));
+        let templates = Templates::new(env, true);
+        assert_eq!(templates.render("page.html", ()).unwrap(), "first");
+
+        // loaded templates are cached until they're reloaded
+        std::fs::write(dir.join("page.html"), "second").expect("Failed to write template");
+        assert_eq!(templates.render("page.html", ()).unwrap(), "first");
+        templates.reload();
+        assert_eq!(templates.render("page.html", ()).unwrap(), "second");
+        std::fs::remove_dir_all(&dir).expect("Failed to remove template dir");
+    }
+
+    #[test]
+    fn test_overrides() {
+        let root = std::env::temp_dir().join(format!("seedweb-{}", uuid::Uuid::new_v4()));
+        let (bundled, overrides) = (root.join("bundled"), root.join("overrides"));
+        std::fs::create_dir_all(&bundled).expect("Failed to create template dir");
+        std::fs::create_dir_all(&overrides).expect("Failed to create override dir");
+        std::fs::write(
+            bundled.join("base.html"),
+            "{% block logo %}Seeds{% endblock %}",
+        )
+        .expect("Failed to write template");
+        std::fs::write(bundled.join("page.html"), "{% extends 'base.html' %}")
+            .expect("Failed to write template");
+        std::fs::write(
+            overrides.join("base.html"),
+            "{% extends 'bundled/base.html' %}{% block logo %}My {{ super() }}{% endblock %}",
+        )
+        .expect("Failed to write template");
+        let mut env = Environment::new();
+        env.set_loader(loader(&bundled, Some(&overrides)));
+        let templates = Templates::new(env, false);
+        assert_eq!(templates.render("page.html", ()).unwrap(), "My Seeds");
+        assert_eq!(
+            templates.render("bundled/page.html", ()).unwrap(),
+            "My Seeds"
+        );
+        assert_eq!(templates.render("bundled/base.html", ()).unwrap(), "Seeds");
+
+        // a missing override directory is the same as no overrides
+        let mut env = Environment::new();
+        env.set_loader(loader(&bundled, Some(&root.join("missing"))));
+        let templates = Templates::new(env, false);
+        assert_eq!(templates.render("page.html", ()).unwrap(), "Seeds");
+        std::fs::remove_dir_all(&root).expect("Failed to remove template dir");
+    }
+
+    #[tokio::test]
+    async fn test_error_page() {
+        let mut env = Environment::new();
+        env.set_debug(true);
+        env.add_template_owned("missing.html", "{% include 'nothing.html' %}")
+            .expect("Failed to add template");
+
+        let quiet = Templates::new(env.clone(), false);
+        let error = quiet.render("missing.html", ()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TemplateNotFound);
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert!(!String::from_utf8_lossy(&body).contains("<pre>"));
+
+        let verbose = Templates::new(env, true);
+        let response = verbose
+            .render("missing.html", ())
+            .unwrap_err()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("<h1>Template error</h1>"));
+        // the debug information includes the line of the template that failed
+        assert!(body.contains("{% include"));
+    }
+}